@@ -0,0 +1,199 @@
+//! 加密凭据包
+//!
+//! 迁移主机时用于整体导出/导入凭据：凭据列表序列化为 JSON 后以 AES-256-GCM 加密，
+//! 密钥由口令经 PBKDF2-HMAC-SHA256 派生，盐与随机数每次导出重新生成
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::kiro::model::credentials::KiroCredentials;
+
+/// 当前凭据包格式版本
+const BUNDLE_VERSION: u32 = 1;
+
+/// 密钥派生算法标识
+const KDF: &str = "pbkdf2-sha256";
+
+/// 导出时使用的 PBKDF2 迭代次数
+const EXPORT_ITERATIONS: u32 = 600_000;
+
+/// 导入时允许的最大迭代次数（防止构造的凭据包占满 CPU）
+const MAX_ITERATIONS: u32 = 10_000_000;
+
+/// 盐长度（字节）
+const SALT_LEN: usize = 16;
+
+/// 口令最小长度
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// 加密凭据包
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialBundle {
+    /// 格式版本
+    pub version: u32,
+    /// 密钥派生算法
+    pub kdf: String,
+    /// PBKDF2 迭代次数
+    pub iterations: u32,
+    /// 盐（base64）
+    pub salt: String,
+    /// AES-GCM 随机数（base64）
+    pub nonce: String,
+    /// 密文（base64，含认证标签）
+    pub ciphertext: String,
+    /// 导出时间（RFC3339）
+    pub exported_at: String,
+    /// 凭据数量（明文，便于导入前核对）
+    pub count: usize,
+}
+
+/// 凭据包处理错误
+#[derive(Debug, PartialEq, Eq)]
+pub enum BundleError {
+    /// 格式版本或参数不受支持
+    Unsupported(String),
+    /// 字段解码失败
+    Malformed(String),
+    /// 口令错误或数据被篡改
+    Decrypt,
+}
+
+impl std::fmt::Display for BundleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BundleError::Unsupported(msg) => write!(f, "不支持的凭据包: {}", msg),
+            BundleError::Malformed(msg) => write!(f, "凭据包格式错误: {}", msg),
+            BundleError::Decrypt => write!(f, "解密失败：口令错误或凭据包已损坏"),
+        }
+    }
+}
+
+impl std::error::Error for BundleError {}
+
+/// 加密凭据列表
+pub fn seal(credentials: &[KiroCredentials], passphrase: &str) -> anyhow::Result<CredentialBundle> {
+    seal_with_iterations(credentials, passphrase, EXPORT_ITERATIONS)
+}
+
+fn seal_with_iterations(
+    credentials: &[KiroCredentials],
+    passphrase: &str,
+    iterations: u32,
+) -> anyhow::Result<CredentialBundle> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt, iterations));
+
+    let plaintext = serde_json::to_vec(credentials)?;
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_slice())
+        .map_err(|_| anyhow::anyhow!("加密凭据失败"))?;
+
+    Ok(CredentialBundle {
+        version: BUNDLE_VERSION,
+        kdf: KDF.to_string(),
+        iterations,
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        count: credentials.len(),
+    })
+}
+
+/// 解密凭据包
+pub fn open(
+    bundle: &CredentialBundle,
+    passphrase: &str,
+) -> Result<Vec<KiroCredentials>, BundleError> {
+    if bundle.version != BUNDLE_VERSION {
+        return Err(BundleError::Unsupported(format!("版本 {}", bundle.version)));
+    }
+    if bundle.kdf != KDF {
+        return Err(BundleError::Unsupported(format!(
+            "密钥派生算法 {}",
+            bundle.kdf
+        )));
+    }
+    if bundle.iterations == 0 || bundle.iterations > MAX_ITERATIONS {
+        return Err(BundleError::Unsupported(format!(
+            "迭代次数 {}",
+            bundle.iterations
+        )));
+    }
+
+    let decode = |field: &str, value: &str| {
+        BASE64
+            .decode(value)
+            .map_err(|e| BundleError::Malformed(format!("{}: {}", field, e)))
+    };
+    let salt = decode("salt", &bundle.salt)?;
+    let nonce = decode("nonce", &bundle.nonce)?;
+    let ciphertext = decode("ciphertext", &bundle.ciphertext)?;
+    if nonce.len() != 12 {
+        return Err(BundleError::Malformed(format!(
+            "nonce 长度 {}",
+            nonce.len()
+        )));
+    }
+
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt, bundle.iterations));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| BundleError::Decrypt)?;
+    serde_json::from_slice(&plaintext).map_err(|e| BundleError::Malformed(e.to_string()))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Key<Aes256Gcm> {
+    let mut key = Key::<Aes256Gcm>::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential(refresh_token: &str) -> KiroCredentials {
+        KiroCredentials {
+            id: Some(1),
+            refresh_token: Some(refresh_token.to_string()),
+            auth_method: Some("social".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_seal_and_open_roundtrip() {
+        let credentials = vec![credential("token-a"), credential("token-b")];
+        let bundle = seal_with_iterations(&credentials, "correct horse", 1000).unwrap();
+        assert_eq!(bundle.count, 2);
+        assert!(!bundle.ciphertext.contains("token-a"));
+
+        let opened = open(&bundle, "correct horse").unwrap();
+        assert_eq!(opened, credentials);
+        assert_eq!(open(&bundle, "wrong horse"), Err(BundleError::Decrypt));
+    }
+
+    #[test]
+    fn test_open_rejects_tampered_bundle() {
+        let mut bundle =
+            seal_with_iterations(&[credential("token-a")], "passphrase", 1000).unwrap();
+        let mut ciphertext = BASE64.decode(&bundle.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        bundle.ciphertext = BASE64.encode(ciphertext);
+        assert_eq!(open(&bundle, "passphrase"), Err(BundleError::Decrypt));
+
+        bundle.iterations = 0;
+        assert!(matches!(
+            open(&bundle, "passphrase"),
+            Err(BundleError::Unsupported(_))
+        ));
+    }
+}
@@ -20,6 +20,12 @@ pub enum AdminServiceError {
 
     /// 凭据无效（验证失败）
     InvalidCredential(String),
+
+    /// 客户端 API Key 不存在
+    ApiKeyNotFound { name: String },
+
+    /// 请求参数无效
+    InvalidRequest(String),
 }
 
 impl fmt::Display for AdminServiceError {
@@ -31,6 +37,8 @@ impl fmt::Display for AdminServiceError {
             AdminServiceError::UpstreamError(msg) => write!(f, "上游服务错误: {}", msg),
             AdminServiceError::InternalError(msg) => write!(f, "内部错误: {}", msg),
             AdminServiceError::InvalidCredential(msg) => write!(f, "凭据无效: {}", msg),
+            AdminServiceError::ApiKeyNotFound { name } => write!(f, "API Key 不存在: {}", name),
+            AdminServiceError::InvalidRequest(msg) => write!(f, "请求无效: {}", msg),
         }
     }
 }
@@ -45,6 +53,8 @@ impl AdminServiceError {
             AdminServiceError::UpstreamError(_) => StatusCode::BAD_GATEWAY,
             AdminServiceError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AdminServiceError::InvalidCredential(_) => StatusCode::BAD_REQUEST,
+            AdminServiceError::ApiKeyNotFound { .. } => StatusCode::NOT_FOUND,
+            AdminServiceError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
         }
     }
 
@@ -56,9 +66,12 @@ impl AdminServiceError {
             AdminServiceError::InternalError(_) => {
                 AdminErrorResponse::internal_error(self.to_string())
             }
-            AdminServiceError::InvalidCredential(_) => {
+            AdminServiceError::InvalidCredential(_) | AdminServiceError::InvalidRequest(_) => {
                 AdminErrorResponse::invalid_request(self.to_string())
             }
+            AdminServiceError::ApiKeyNotFound { .. } => {
+                AdminErrorResponse::not_found(self.to_string())
+            }
         }
     }
 }
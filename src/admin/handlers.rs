@@ -1,20 +1,36 @@
 //! Admin API HTTP 处理器
 
+use std::convert::Infallible;
+
 use axum::{
     Json,
-    extract::{Path, State},
-    response::IntoResponse,
+    extract::{Path, Query, State},
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use futures::StreamExt;
+
+use crate::model::config::{LoggingConfig, RateLimitConfig, TokenQuota};
 
 use super::{
     middleware::AdminState,
-    types::{AddCredentialRequest, BatchImportRequest, SetDisabledRequest, SetPriorityRequest, SuccessResponse},
+    types::{
+        AddCredentialRequest, BalancesQuery, BatchImportRequest, CreateApiKeyRequest,
+        CredentialsQuery, ExportCredentialsRequest, FlushCacheRequest, ImportCredentialsRequest,
+        LogStreamQuery, RequestsQuery, SetDisabledRequest, SetPriorityRequest, SetTagsRequest,
+        SuccessResponse, UsageQuery,
+    },
 };
 
 /// GET /api/admin/credentials
-/// 获取所有凭据状态
-pub async fn get_all_credentials(State(state): State<AdminState>) -> impl IntoResponse {
-    let response = state.service.get_all_credentials();
+/// 获取凭据状态（支持按状态、认证方式、标签过滤，排序与分页）
+pub async fn get_all_credentials(
+    State(state): State<AdminState>,
+    Query(query): Query<CredentialsQuery>,
+) -> impl IntoResponse {
+    let response = state.service.get_all_credentials(&query);
     Json(response)
 }
 
@@ -51,6 +67,19 @@ pub async fn set_credential_priority(
     }
 }
 
+/// POST /api/admin/credentials/:id/tags
+/// 设置凭据分组标签
+pub async fn set_credential_tags(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+    Json(payload): Json<SetTagsRequest>,
+) -> impl IntoResponse {
+    match state.service.set_tags(id, payload.tags) {
+        Ok(_) => Json(SuccessResponse::new(format!("凭据 #{} 分组标签已更新", id))).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
 /// POST /api/admin/credentials/:id/reset
 /// 重置失败计数并重新启用
 pub async fn reset_failure_count(
@@ -95,6 +124,30 @@ pub async fn get_credential_balance(
     }
 }
 
+/// GET /api/admin/credentials/:id/stats
+/// 获取指定凭据的累计用量统计
+pub async fn get_credential_stats(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.service.get_credential_stats(id) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/credentials/:id/balance-history
+/// 获取指定凭据的余额历史与消耗速度
+pub async fn get_balance_history(
+    State(state): State<AdminState>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.service.get_balance_history(id) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
 /// POST /api/admin/credentials
 /// 添加新凭据
 pub async fn add_credential(
@@ -130,3 +183,245 @@ pub async fn batch_import_credentials(
         Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
     }
 }
+
+/// GET /api/admin/credentials/balances
+/// 并发查询全部（或过滤后的）凭据余额并汇总
+pub async fn get_credential_balances(
+    State(state): State<AdminState>,
+    Query(query): Query<BalancesQuery>,
+) -> impl IntoResponse {
+    match state.service.get_balances(&query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/credentials/export
+/// 导出全部凭据为口令加密的凭据包
+pub async fn export_credentials(
+    State(state): State<AdminState>,
+    Json(payload): Json<ExportCredentialsRequest>,
+) -> impl IntoResponse {
+    match state.service.export_credentials(payload).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/credentials/import
+/// 从加密凭据包导入凭据
+pub async fn import_credentials(
+    State(state): State<AdminState>,
+    Json(payload): Json<ImportCredentialsRequest>,
+) -> impl IntoResponse {
+    match state.service.import_credentials(payload).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/usage?days=7
+/// 获取最近若干天按模型汇总的用量
+pub async fn get_usage(
+    State(state): State<AdminState>,
+    Query(query): Query<UsageQuery>,
+) -> impl IntoResponse {
+    match state.service.get_usage(query.days) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/requests
+/// 分页查询请求审计记录（支持按客户端 Key、模型、状态码、凭据与时间范围过滤）
+pub async fn get_requests(
+    State(state): State<AdminState>,
+    Query(query): Query<RequestsQuery>,
+) -> impl IntoResponse {
+    match state.service.get_requests(&query) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/cache/flush
+/// 清空缓存（请求体可选，`caches` 为空时清空全部）
+pub async fn flush_caches(
+    State(state): State<AdminState>,
+    payload: Option<Json<FlushCacheRequest>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    match state.service.flush_caches(&payload.caches) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/config
+/// 获取当前生效的运行时策略
+pub async fn get_effective_config(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.get_effective_config())
+}
+
+/// GET /api/admin/status
+/// 获取运行状态（tokenizer、时钟偏差等）
+pub async fn get_runtime_status(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.get_runtime_status())
+}
+
+/// GET /api/admin/stats
+/// 获取性能统计（按模型、凭据的输出吞吐量分位数）
+pub async fn get_stats(State(state): State<AdminState>) -> impl IntoResponse {
+    Json(state.service.get_stats())
+}
+
+/// GET /api/admin/logs/stream
+/// 以 SSE 实时推送日志（`level` 过滤最低级别，`module` 过滤模块路径前缀）
+pub async fn stream_logs(
+    State(state): State<AdminState>,
+    Query(query): Query<LogStreamQuery>,
+) -> impl IntoResponse {
+    match state.service.stream_logs(query) {
+        Ok(logs) => {
+            let events = logs.map(|record| {
+                Ok::<_, Infallible>(
+                    Event::default()
+                        .event("log")
+                        .json_data(record)
+                        .unwrap_or_default(),
+                )
+            });
+            Sse::new(events)
+                .keep_alive(KeepAlive::default())
+                .into_response()
+        }
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// PUT /api/admin/config/logging
+/// 运行时替换按路由的日志级别（`routes` 为空时全部恢复为 RUST_LOG）
+pub async fn set_logging_config(
+    State(state): State<AdminState>,
+    Json(payload): Json<LoggingConfig>,
+) -> impl IntoResponse {
+    match state.service.set_logging_config(&payload) {
+        Ok(_) => Json(SuccessResponse::new("路由日志级别已更新")).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/api-keys
+/// 获取所有客户端 API Key
+pub async fn get_api_keys(State(state): State<AdminState>) -> impl IntoResponse {
+    match state.service.get_api_keys() {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/api-keys
+/// 创建客户端 API Key
+pub async fn create_api_key(
+    State(state): State<AdminState>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> impl IntoResponse {
+    match state.service.create_api_key(payload) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/api-keys/:name/disabled
+/// 设置客户端 API Key 禁用状态
+pub async fn set_api_key_disabled(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Json(payload): Json<SetDisabledRequest>,
+) -> impl IntoResponse {
+    match state.service.set_api_key_disabled(&name, payload.disabled) {
+        Ok(_) => {
+            let action = if payload.disabled { "禁用" } else { "启用" };
+            Json(SuccessResponse::new(format!(
+                "API Key {} 已{}",
+                name, action
+            )))
+            .into_response()
+        }
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/api-keys/:name/rate-limit
+/// 设置客户端 API Key 速率限制（未提供的维度不限制）
+pub async fn set_api_key_rate_limit(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Json(payload): Json<RateLimitConfig>,
+) -> impl IntoResponse {
+    match state.service.set_api_key_rate_limit(&name, payload) {
+        Ok(_) => Json(SuccessResponse::new(format!(
+            "API Key {} 速率限制已更新",
+            name
+        )))
+        .into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/api-keys/:name/quota
+/// 设置客户端 API Key 的 token 预算（未提供的周期不限制）
+pub async fn set_api_key_quota(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Json(payload): Json<TokenQuota>,
+) -> impl IntoResponse {
+    match state.service.set_api_key_quota(&name, payload) {
+        Ok(_) => Json(SuccessResponse::new(format!(
+            "API Key {} token 预算已更新",
+            name
+        )))
+        .into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// GET /api/admin/api-keys/:name/usage
+/// 获取客户端 API Key 的 token 预算与当日、当月用量
+pub async fn get_api_key_usage(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.service.get_api_key_usage(&name) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// POST /api/admin/api-keys/:name/usage/reset
+/// 清零客户端 API Key 当日与当月的 token 用量
+pub async fn reset_api_key_usage(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.service.reset_api_key_usage(&name) {
+        Ok(_) => Json(SuccessResponse::new(format!(
+            "API Key {} token 用量已清零",
+            name
+        )))
+        .into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
+
+/// DELETE /api/admin/api-keys/:name
+/// 删除客户端 API Key
+pub async fn delete_api_key(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.service.delete_api_key(&name) {
+        Ok(_) => Json(SuccessResponse::new(format!("API Key {} 已删除", name))).into_response(),
+        Err(e) => (e.status_code(), Json(e.into_response())).into_response(),
+    }
+}
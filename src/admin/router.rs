@@ -2,14 +2,19 @@
 
 use axum::{
     Router, middleware,
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
 };
 
 use super::{
     handlers::{
-        add_credential, batch_import_credentials, delete_credential, get_all_credentials,
-        get_credential_balance, refresh_credential_token, reset_failure_count,
-        set_credential_disabled, set_credential_priority,
+        add_credential, batch_import_credentials, create_api_key, delete_api_key,
+        delete_credential, export_credentials, flush_caches, get_all_credentials,
+        get_api_key_usage, get_api_keys, get_balance_history, get_credential_balance,
+        get_credential_balances, get_credential_stats, get_effective_config, get_requests,
+        get_runtime_status, get_stats, get_usage, import_credentials, refresh_credential_token,
+        reset_api_key_usage, reset_failure_count, set_api_key_disabled, set_api_key_quota,
+        set_api_key_rate_limit, set_credential_disabled, set_credential_priority,
+        set_credential_tags, set_logging_config, stream_logs,
     },
     middleware::{AdminState, admin_auth_middleware},
 };
@@ -20,12 +25,34 @@ use super::{
 /// - `GET /credentials` - 获取所有凭据状态
 /// - `POST /credentials` - 添加新凭据
 /// - `POST /credentials/batch` - 批量导入凭据
+/// - `GET /credentials/balances` - 并发查询多个凭据余额并汇总
+/// - `POST /credentials/export` - 导出加密凭据包
+/// - `POST /credentials/import` - 导入加密凭据包
 /// - `DELETE /credentials/:id` - 删除凭据
 /// - `POST /credentials/:id/disabled` - 设置凭据禁用状态
 /// - `POST /credentials/:id/priority` - 设置凭据优先级
+/// - `POST /credentials/:id/tags` - 设置凭据分组标签
 /// - `POST /credentials/:id/reset` - 重置失败计数
 /// - `POST /credentials/:id/refresh` - 强制刷新 Token
 /// - `GET /credentials/:id/balance` - 获取凭据余额
+/// - `GET /credentials/:id/balance-history` - 获取凭据余额历史
+/// - `GET /credentials/:id/stats` - 获取凭据累计用量统计
+/// - `GET /api-keys` - 获取所有客户端 API Key
+/// - `POST /api-keys` - 创建客户端 API Key
+/// - `DELETE /api-keys/:name` - 删除客户端 API Key
+/// - `POST /api-keys/:name/disabled` - 设置客户端 API Key 禁用状态
+/// - `POST /api-keys/:name/rate-limit` - 设置客户端 API Key 速率限制
+/// - `POST /api-keys/:name/quota` - 设置客户端 API Key 的 token 预算
+/// - `GET /api-keys/:name/usage` - 获取客户端 API Key 当日与当月的 token 用量
+/// - `POST /api-keys/:name/usage/reset` - 清零客户端 API Key 的 token 用量
+/// - `GET /usage` - 获取按日期、模型汇总的用量
+/// - `GET /requests` - 分页查询请求审计记录
+/// - `POST /cache/flush` - 清空缓存
+/// - `GET /config` - 获取当前生效的运行时策略
+/// - `PUT /config/logging` - 设置按路由的日志级别
+/// - `GET /logs/stream` - 实时日志（SSE）
+/// - `GET /status` - 获取运行状态（tokenizer、时钟偏差）
+/// - `GET /stats` - 获取按模型、凭据的输出吞吐量分位数
 ///
 /// # 认证
 /// 需要 Admin API Key 认证，支持：
@@ -38,12 +65,36 @@ pub fn create_admin_router(state: AdminState) -> Router {
             get(get_all_credentials).post(add_credential),
         )
         .route("/credentials/batch", post(batch_import_credentials))
+        .route("/credentials/balances", get(get_credential_balances))
+        .route("/credentials/export", post(export_credentials))
+        .route("/credentials/import", post(import_credentials))
         .route("/credentials/{id}", delete(delete_credential))
         .route("/credentials/{id}/disabled", post(set_credential_disabled))
         .route("/credentials/{id}/priority", post(set_credential_priority))
+        .route("/credentials/{id}/tags", post(set_credential_tags))
         .route("/credentials/{id}/reset", post(reset_failure_count))
         .route("/credentials/{id}/refresh", post(refresh_credential_token))
         .route("/credentials/{id}/balance", get(get_credential_balance))
+        .route(
+            "/credentials/{id}/balance-history",
+            get(get_balance_history),
+        )
+        .route("/credentials/{id}/stats", get(get_credential_stats))
+        .route("/api-keys", get(get_api_keys).post(create_api_key))
+        .route("/api-keys/{name}", delete(delete_api_key))
+        .route("/api-keys/{name}/disabled", post(set_api_key_disabled))
+        .route("/api-keys/{name}/rate-limit", post(set_api_key_rate_limit))
+        .route("/api-keys/{name}/quota", post(set_api_key_quota))
+        .route("/api-keys/{name}/usage", get(get_api_key_usage))
+        .route("/api-keys/{name}/usage/reset", post(reset_api_key_usage))
+        .route("/usage", get(get_usage))
+        .route("/requests", get(get_requests))
+        .route("/cache/flush", post(flush_caches))
+        .route("/config", get(get_effective_config))
+        .route("/config/logging", put(set_logging_config))
+        .route("/logs/stream", get(stream_logs))
+        .route("/status", get(get_runtime_status))
+        .route("/stats", get(get_stats))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             admin_auth_middleware,
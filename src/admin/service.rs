@@ -1,39 +1,434 @@
 //! Admin API 业务逻辑服务
 
+use std::str::FromStr;
 use std::sync::Arc;
 
-use crate::kiro::model::credentials::KiroCredentials;
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::common::api_keys::ApiKeyRegistry;
+use crate::common::cache::{CacheKind, CacheRegistry};
+use crate::common::metrics;
+use crate::common::telemetry::{self, LogRecord};
+use crate::kiro::circuit_breaker::BreakerState;
+use crate::kiro::clock;
+use crate::kiro::model::credentials::{KiroCredentials, normalize_tags};
 use crate::kiro::token_manager::MultiTokenManager;
+use crate::model::config::{LoggingConfig, RateLimitConfig, TokenQuota};
+use crate::storage::audit_log::AuditLog;
+use crate::storage::balance_history::{self, BalanceHistory};
+use crate::storage::credential_stats::CredentialStats;
+use crate::storage::ledger::UsageLedger;
+use crate::token;
 
+use super::bundle::{self, CredentialBundle};
 use super::error::AdminServiceError;
 use super::types::{
-    AddCredentialRequest, AddCredentialResponse, BalanceResponse, BatchImportRequest,
-    BatchImportResponse, BatchImportResultItem, CredentialStatusItem, CredentialsStatusResponse,
+    AddCredentialRequest, AddCredentialResponse, ApiKeyItem, ApiKeyUsageResponse, ApiKeysResponse,
+    BalanceError, BalanceHistoryResponse, BalanceResponse, BalancesQuery, BalancesResponse,
+    BatchImportRequest, BatchImportResponse, BatchImportResultItem, CreateApiKeyRequest,
+    CreateApiKeyResponse, CredentialSort, CredentialStatsResponse, CredentialStatusFilter,
+    CredentialStatusItem, CredentialsQuery, CredentialsStatusResponse, EffectiveConfigResponse,
+    ExportCredentialsRequest, FlushCacheResponse, ImportCredentialsRequest,
+    ImportCredentialsResponse, LogStreamQuery, RequestsQuery, RequestsResponse,
+    RuntimeStatusResponse, SortOrder, StatsResponse, UsageResponse,
 };
 
+/// 用量查询默认天数
+const DEFAULT_USAGE_DAYS: u32 = 7;
+
+/// 用量查询最大天数
+const MAX_USAGE_DAYS: u32 = 90;
+
+/// 凭据列表分页时默认每页数量
+const DEFAULT_CREDENTIALS_LIMIT: usize = 50;
+
+/// 凭据列表分页时最大每页数量
+const MAX_CREDENTIALS_LIMIT: usize = 500;
+
+/// 批量余额查询默认并发数
+const DEFAULT_BALANCE_CONCURRENCY: usize = 8;
+
+/// 批量余额查询最大并发数
+const MAX_BALANCE_CONCURRENCY: usize = 32;
+
+/// 审计记录查询默认每页条数
+const DEFAULT_REQUESTS_LIMIT: usize = 50;
+
+/// 审计记录查询最大每页条数
+const MAX_REQUESTS_LIMIT: usize = 500;
+
 /// Admin 服务
 ///
 /// 封装所有 Admin API 的业务逻辑
 pub struct AdminService {
     token_manager: Arc<MultiTokenManager>,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    credential_stats: Option<Arc<CredentialStats>>,
+    balance_history: Option<Arc<BalanceHistory>>,
+    audit_log: Option<Arc<AuditLog>>,
+    caches: Arc<CacheRegistry>,
+    api_keys: Option<Arc<ApiKeyRegistry>>,
 }
 
 impl AdminService {
     pub fn new(token_manager: Arc<MultiTokenManager>) -> Self {
-        Self { token_manager }
+        Self {
+            token_manager,
+            usage_ledger: None,
+            credential_stats: None,
+            balance_history: None,
+            audit_log: None,
+            caches: Arc::new(CacheRegistry::new()),
+            api_keys: None,
+        }
+    }
+
+    /// 设置用量账本
+    pub fn with_usage_ledger(mut self, ledger: Arc<UsageLedger>) -> Self {
+        self.usage_ledger = Some(ledger);
+        self
+    }
+
+    /// 设置凭据用量统计
+    pub fn with_credential_stats(mut self, stats: Arc<CredentialStats>) -> Self {
+        self.credential_stats = Some(stats);
+        self
+    }
+
+    /// 设置余额历史
+    pub fn with_balance_history(mut self, history: Arc<BalanceHistory>) -> Self {
+        self.balance_history = Some(history);
+        self
+    }
+
+    /// 设置请求审计日志
+    pub fn with_audit_log(mut self, log: Arc<AuditLog>) -> Self {
+        self.audit_log = Some(log);
+        self
+    }
+
+    /// 设置缓存注册表
+    pub fn with_cache_registry(mut self, caches: Arc<CacheRegistry>) -> Self {
+        self.caches = caches;
+        self
+    }
+
+    /// 设置客户端 API Key 注册表
+    pub fn with_api_keys(mut self, api_keys: Arc<ApiKeyRegistry>) -> Self {
+        self.api_keys = Some(api_keys);
+        self
+    }
+
+    /// 获取当前生效的运行时策略
+    pub fn get_effective_config(&self) -> EffectiveConfigResponse {
+        EffectiveConfigResponse {
+            resilience: self.token_manager.config().resilience.clone(),
+            concurrency: self.token_manager.config().concurrency.clone(),
+            timeouts: self.token_manager.config().timeouts.clone(),
+            scheduling_strategy: self.token_manager.config().scheduling_strategy,
+            logging: LoggingConfig {
+                routes: telemetry::route_levels(),
+                format: telemetry::format(),
+            },
+        }
+    }
+
+    /// 获取运行状态
+    pub fn get_runtime_status(&self) -> RuntimeStatusResponse {
+        RuntimeStatusResponse {
+            tokenizer: token::tokenizer_status(),
+            clock_skew_secs: clock::skew_secs(),
+        }
+    }
+
+    /// 获取性能统计
+    pub fn get_stats(&self) -> StatsResponse {
+        StatsResponse {
+            output_throughput: metrics::OUTPUT_THROUGHPUT.summaries(),
+        }
+    }
+
+    /// 替换按路由的日志级别与日志输出格式
+    pub fn set_logging_config(&self, logging: &LoggingConfig) -> Result<(), AdminServiceError> {
+        telemetry::set_route_levels(&logging.routes)
+            .map_err(|e| AdminServiceError::InvalidRequest(e.to_string()))?;
+        telemetry::set_format(logging.format);
+        tracing::info!("路由日志级别已更新: {:?}", logging.routes);
+        Ok(())
+    }
+
+    /// 订阅实时日志，按最低级别与模块前缀过滤
+    ///
+    /// 推送落后导致日志被丢弃时，插入一条说明丢弃数量的 WARN 记录
+    pub fn stream_logs(
+        &self,
+        query: LogStreamQuery,
+    ) -> Result<impl Stream<Item = LogRecord> + use<>, AdminServiceError> {
+        let level = query
+            .level
+            .as_deref()
+            .map(|level| {
+                tracing::Level::from_str(level).map_err(|_| {
+                    AdminServiceError::InvalidRequest(format!("日志级别无效: {}", level))
+                })
+            })
+            .transpose()?;
+        let module = query.module;
+
+        let logs = telemetry::subscribe_logs();
+        Ok(futures::stream::unfold(logs, move |mut logs| {
+            let module = module.clone();
+            async move {
+                loop {
+                    match logs.recv().await {
+                        Ok(record) if record.matches(level, module.as_deref()) => {
+                            return Some((record, logs));
+                        }
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => {
+                            let record = LogRecord {
+                                timestamp: chrono::Utc::now().to_rfc3339(),
+                                level: tracing::Level::WARN.to_string(),
+                                target: module_path!().to_string(),
+                                message: format!("实时日志推送落后，已丢弃 {} 条日志", skipped),
+                                fields: Default::default(),
+                            };
+                            return Some((record, logs));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        }))
+    }
+
+    /// 清空指定类型的缓存（为空时清空全部）
+    pub fn flush_caches(
+        &self,
+        kinds: &[CacheKind],
+    ) -> Result<FlushCacheResponse, AdminServiceError> {
+        let results = self
+            .caches
+            .flush(kinds)
+            .map_err(|e| AdminServiceError::InternalError(format!("清空缓存失败: {}", e)))?;
+        Ok(FlushCacheResponse { results })
+    }
+
+    /// 获取最近若干天的用量汇总
+    pub fn get_usage(&self, days: Option<u32>) -> Result<UsageResponse, AdminServiceError> {
+        let ledger = self
+            .usage_ledger
+            .as_ref()
+            .ok_or_else(|| AdminServiceError::InternalError("用量账本未启用".to_string()))?;
+        let days = days.unwrap_or(DEFAULT_USAGE_DAYS).clamp(1, MAX_USAGE_DAYS);
+        let entries = ledger
+            .recent_days(days)
+            .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+        Ok(UsageResponse { days, entries })
+    }
+
+    /// 分页查询请求审计记录
+    pub fn get_requests(
+        &self,
+        query: &RequestsQuery,
+    ) -> Result<RequestsResponse, AdminServiceError> {
+        let log = self
+            .audit_log
+            .as_ref()
+            .ok_or_else(|| AdminServiceError::InternalError("请求审计日志未启用".to_string()))?;
+        let offset = query.offset.unwrap_or(0);
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_REQUESTS_LIMIT)
+            .clamp(1, MAX_REQUESTS_LIMIT);
+        let page = log
+            .query(&query.filter(), offset, limit)
+            .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+        Ok(RequestsResponse {
+            total: page.total,
+            offset,
+            limit,
+            records: page.records,
+        })
+    }
+
+    /// 获取指定凭据的累计用量统计
+    pub fn get_credential_stats(
+        &self,
+        id: u64,
+    ) -> Result<CredentialStatsResponse, AdminServiceError> {
+        let stats = self
+            .credential_stats
+            .as_ref()
+            .ok_or_else(|| AdminServiceError::InternalError("凭据统计未启用".to_string()))?;
+        let snapshot = self.token_manager.snapshot();
+        if !snapshot.entries.iter().any(|e| e.id == id) {
+            return Err(AdminServiceError::NotFound { id });
+        }
+        let usage = stats
+            .get(id)
+            .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+        Ok(CredentialStatsResponse { id, usage })
+    }
+
+    /// 获取指定凭据的余额历史
+    pub fn get_balance_history(
+        &self,
+        id: u64,
+    ) -> Result<BalanceHistoryResponse, AdminServiceError> {
+        let history = self
+            .balance_history
+            .as_ref()
+            .ok_or_else(|| AdminServiceError::InternalError("余额历史未启用".to_string()))?;
+        let snapshot = self.token_manager.snapshot();
+        if !snapshot.entries.iter().any(|e| e.id == id) {
+            return Err(AdminServiceError::NotFound { id });
+        }
+        let samples = history
+            .list(id)
+            .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+        Ok(BalanceHistoryResponse {
+            id,
+            burn_rate_per_day: balance_history::burn_rate_per_day(&samples),
+            samples,
+        })
+    }
+
+    /// 获取所有客户端 API Key
+    pub fn get_api_keys(&self) -> Result<ApiKeysResponse, AdminServiceError> {
+        let keys: Vec<ApiKeyItem> = self
+            .api_key_registry()?
+            .snapshot()
+            .into_iter()
+            .map(|entry| ApiKeyItem {
+                name: entry.name,
+                key_preview: entry.key_preview,
+                source: entry.source,
+                disabled: entry.disabled,
+                rate_limit: entry.rate_limit,
+                group: entry.group,
+                priority: entry.priority,
+                quota: entry.quota,
+                mcp_tools: entry.mcp_tools,
+                model_override: entry.model_override,
+                requests: entry.requests,
+            })
+            .collect();
+        Ok(ApiKeysResponse {
+            total: keys.len(),
+            keys,
+        })
+    }
+
+    /// 创建客户端 API Key
+    pub fn create_api_key(
+        &self,
+        req: CreateApiKeyRequest,
+    ) -> Result<CreateApiKeyResponse, AdminServiceError> {
+        let created = self
+            .api_key_registry()?
+            .create(
+                &req.name,
+                req.key,
+                req.rate_limit,
+                req.group,
+                req.priority,
+                req.quota,
+            )
+            .map_err(|e| self.classify_api_key_error(e, &req.name))?;
+        Ok(CreateApiKeyResponse {
+            success: true,
+            message: format!(
+                "API Key {} 创建成功，请妥善保存，之后不再显示完整 Key",
+                created.name
+            ),
+            name: created.name,
+            key: created.key,
+        })
+    }
+
+    /// 设置客户端 API Key 禁用状态
+    pub fn set_api_key_disabled(
+        &self,
+        name: &str,
+        disabled: bool,
+    ) -> Result<(), AdminServiceError> {
+        self.api_key_registry()?
+            .set_disabled(name, disabled)
+            .map_err(|e| self.classify_api_key_error(e, name))
+    }
+
+    /// 设置客户端 API Key 速率限制
+    pub fn set_api_key_rate_limit(
+        &self,
+        name: &str,
+        rate_limit: RateLimitConfig,
+    ) -> Result<(), AdminServiceError> {
+        self.api_key_registry()?
+            .set_rate_limit(name, rate_limit)
+            .map_err(|e| self.classify_api_key_error(e, name))
+    }
+
+    /// 设置客户端 API Key 的 token 预算
+    pub fn set_api_key_quota(
+        &self,
+        name: &str,
+        quota: TokenQuota,
+    ) -> Result<(), AdminServiceError> {
+        self.api_key_registry()?
+            .set_quota(name, quota)
+            .map_err(|e| self.classify_api_key_error(e, name))
+    }
+
+    /// 获取客户端 API Key 的 token 预算与当前周期用量
+    pub fn get_api_key_usage(&self, name: &str) -> Result<ApiKeyUsageResponse, AdminServiceError> {
+        let (quota, usage) = self
+            .api_key_registry()?
+            .usage(name)
+            .map_err(|e| self.classify_api_key_error(e, name))?;
+        Ok(ApiKeyUsageResponse {
+            name: name.to_string(),
+            quota,
+            usage,
+        })
+    }
+
+    /// 清零客户端 API Key 当前周期的 token 用量
+    pub fn reset_api_key_usage(&self, name: &str) -> Result<(), AdminServiceError> {
+        self.api_key_registry()?
+            .reset_usage(name)
+            .map_err(|e| self.classify_api_key_error(e, name))
+    }
+
+    /// 删除客户端 API Key
+    pub fn delete_api_key(&self, name: &str) -> Result<(), AdminServiceError> {
+        self.api_key_registry()?
+            .delete(name)
+            .map_err(|e| self.classify_api_key_error(e, name))
+    }
+
+    fn api_key_registry(&self) -> Result<&ApiKeyRegistry, AdminServiceError> {
+        self.api_keys
+            .as_deref()
+            .ok_or_else(|| AdminServiceError::InternalError("API Key 注册表未启用".to_string()))
     }
 
     /// 获取所有凭据状态
-    pub fn get_all_credentials(&self) -> CredentialsStatusResponse {
+    pub fn get_all_credentials(&self, query: &CredentialsQuery) -> CredentialsStatusResponse {
         let snapshot = self.token_manager.snapshot();
 
-        let mut credentials: Vec<CredentialStatusItem> = snapshot
+        let credentials: Vec<CredentialStatusItem> = snapshot
             .entries
             .into_iter()
             .map(|entry| CredentialStatusItem {
                 id: entry.id,
                 priority: entry.priority,
+                weight: entry.weight,
+                tags: entry.tags,
                 disabled: entry.disabled,
+                disabled_reason: entry.disabled_reason,
+                quota_reset_at: entry.quota_reset_at,
                 failure_count: entry.failure_count,
                 is_current: entry.id == snapshot.current_id,
                 expires_at: entry.expires_at,
@@ -41,16 +436,36 @@ impl AdminService {
                 has_profile_arn: entry.has_profile_arn,
                 active_connections: entry.active_connections,
                 max_concurrent: entry.max_concurrent,
+                degraded_reason: entry.degraded_reason,
+                breaker_state: entry.breaker_state,
             })
             .collect();
 
-        // 按优先级排序（数字越小优先级越高）
-        credentials.sort_by_key(|c| c.priority);
+        let mut credentials = filter_credentials(credentials, query);
+        let matched = credentials.len();
+        let (page, limit) = match (query.page, query.limit) {
+            (None, None) => (None, None),
+            (page, limit) => {
+                let page = page.unwrap_or(1).max(1);
+                let limit = limit
+                    .unwrap_or(DEFAULT_CREDENTIALS_LIMIT)
+                    .clamp(1, MAX_CREDENTIALS_LIMIT);
+                credentials = credentials
+                    .into_iter()
+                    .skip((page - 1).saturating_mul(limit))
+                    .take(limit)
+                    .collect();
+                (Some(page), Some(limit))
+            }
+        };
 
         CredentialsStatusResponse {
             total: snapshot.total,
             available: snapshot.available,
             current_id: snapshot.current_id,
+            matched,
+            page,
+            limit,
             credentials,
         }
     }
@@ -79,6 +494,13 @@ impl AdminService {
             .map_err(|e| self.classify_error(e, id))
     }
 
+    /// 设置凭据分组标签
+    pub fn set_tags(&self, id: u64, tags: Vec<String>) -> Result<(), AdminServiceError> {
+        self.token_manager
+            .set_tags(id, tags)
+            .map_err(|e| self.classify_error(e, id))
+    }
+
     /// 重置失败计数并重新启用
     pub fn reset_and_enable(&self, id: u64) -> Result<(), AdminServiceError> {
         self.token_manager
@@ -122,6 +544,95 @@ impl AdminService {
         })
     }
 
+    /// 并发查询多个凭据的余额并汇总
+    ///
+    /// 单个凭据查询失败计入 `errors`，不影响其他凭据
+    pub async fn get_balances(
+        &self,
+        query: &BalancesQuery,
+    ) -> Result<BalancesResponse, AdminServiceError> {
+        let ids = match &query.ids {
+            Some(ids) => Some(
+                ids.split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(|id| {
+                        id.parse::<u64>().map_err(|_| {
+                            AdminServiceError::InvalidRequest(format!("无效的凭据 ID: {}", id))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+        let filter = CredentialsQuery {
+            status: query.status,
+            auth_method: query.auth_method.clone(),
+            tag: query.tag.clone(),
+            sort: CredentialSort::Id,
+            ..Default::default()
+        };
+        let listing = self.get_all_credentials(&filter).credentials;
+        if let Some(&id) = ids.iter().flatten().find(|id| {
+            !self
+                .token_manager
+                .snapshot()
+                .entries
+                .iter()
+                .any(|e| e.id == **id)
+        }) {
+            return Err(AdminServiceError::NotFound { id });
+        }
+        let targets: Vec<u64> = listing
+            .into_iter()
+            .map(|c| c.id)
+            .filter(|id| ids.as_ref().is_none_or(|ids| ids.contains(id)))
+            .collect();
+
+        let concurrency = query
+            .concurrency
+            .unwrap_or(DEFAULT_BALANCE_CONCURRENCY)
+            .clamp(1, MAX_BALANCE_CONCURRENCY);
+        let mut results: Vec<_> = futures::stream::iter(targets)
+            .map(|id| async move { (id, self.get_balance(id).await) })
+            .buffer_unordered(concurrency)
+            .collect()
+            .await;
+        results.sort_by_key(|(id, _)| *id);
+
+        let total = results.len();
+        let mut balances = Vec::new();
+        let mut errors = Vec::new();
+        for (id, result) in results {
+            match result {
+                Ok(balance) => balances.push(balance),
+                Err(e) => errors.push(BalanceError {
+                    id,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        let current_usage: f64 = balances.iter().map(|b| b.current_usage).sum();
+        let usage_limit: f64 = balances.iter().map(|b| b.usage_limit).sum();
+        let usage_percentage = if usage_limit > 0.0 {
+            (current_usage / usage_limit * 100.0).min(100.0)
+        } else {
+            0.0
+        };
+
+        Ok(BalancesResponse {
+            total,
+            succeeded: balances.len(),
+            failed: errors.len(),
+            current_usage,
+            usage_limit,
+            remaining: balances.iter().map(|b| b.remaining).sum(),
+            usage_percentage,
+            balances,
+            errors,
+        })
+    }
+
     /// 添加新凭据
     pub async fn add_credential(
         &self,
@@ -138,8 +649,15 @@ impl AdminService {
             client_id: req.client_id,
             client_secret: req.client_secret,
             priority: req.priority,
+            weight: req.weight,
+            max_concurrent: req.max_concurrent,
+            tags: normalize_tags(req.tags),
             region: req.region,
+            api_region: req.api_region,
+            endpoint: req.endpoint,
+            failover_regions: req.failover_regions,
             machine_id: req.machine_id,
+            headers: req.headers,
         };
 
         // 调用 token_manager 添加凭据
@@ -163,6 +681,55 @@ impl AdminService {
             .map_err(|e| self.classify_delete_error(e, id))
     }
 
+    /// 导出全部凭据为加密凭据包
+    pub async fn export_credentials(
+        &self,
+        req: ExportCredentialsRequest,
+    ) -> Result<CredentialBundle, AdminServiceError> {
+        check_passphrase(&req.passphrase)?;
+        let credentials = self.token_manager.export_credentials();
+        // 密钥派生耗时较长，避免阻塞 worker
+        let bundle =
+            tokio::task::spawn_blocking(move || bundle::seal(&credentials, &req.passphrase))
+                .await
+                .map_err(|e| AdminServiceError::InternalError(e.to_string()))?
+                .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+        tracing::info!("已导出 {} 个凭据", bundle.count);
+        Ok(bundle)
+    }
+
+    /// 从加密凭据包导入凭据
+    pub async fn import_credentials(
+        &self,
+        req: ImportCredentialsRequest,
+    ) -> Result<ImportCredentialsResponse, AdminServiceError> {
+        check_passphrase(&req.passphrase)?;
+        let credentials =
+            tokio::task::spawn_blocking(move || bundle::open(&req.bundle, &req.passphrase))
+                .await
+                .map_err(|e| AdminServiceError::InternalError(e.to_string()))?
+                .map_err(|e| AdminServiceError::InvalidRequest(e.to_string()))?;
+        let total = credentials.len();
+        let report = self
+            .token_manager
+            .import_credentials(credentials)
+            .map_err(|e| AdminServiceError::InternalError(e.to_string()))?;
+
+        Ok(ImportCredentialsResponse {
+            success: true,
+            message: format!(
+                "导入完成：新增 {} 个，已存在 {} 个，无效 {} 个",
+                report.imported.len(),
+                report.duplicates,
+                report.invalid
+            ),
+            total,
+            credential_ids: report.imported,
+            duplicates: report.duplicates,
+            invalid: report.invalid,
+        })
+    }
+
     /// 批量导入凭据
     pub async fn batch_import_credentials(
         &self,
@@ -343,8 +910,15 @@ impl AdminService {
                 client_id: None,
                 client_secret: None,
                 priority: 0,
+                weight: None,
+                max_concurrent: None,
+                tags: Vec::new(),
                 region: None,
+                api_region: None,
+                endpoint: None,
+                failover_regions: Vec::new(),
                 machine_id: None,
+                headers: Default::default(),
             };
 
             match self.token_manager.add_credential(new_cred).await {
@@ -467,6 +1041,24 @@ impl AdminService {
         }
     }
 
+    /// 分类客户端 API Key 操作错误
+    fn classify_api_key_error(&self, e: anyhow::Error, name: &str) -> AdminServiceError {
+        let msg = e.to_string();
+        if msg.contains("不存在") {
+            AdminServiceError::ApiKeyNotFound {
+                name: name.to_string(),
+            }
+        } else if msg.contains("无效")
+            || msg.contains("已存在")
+            || msg.contains("只读")
+            || msg.contains("已被")
+        {
+            AdminServiceError::InvalidRequest(msg)
+        } else {
+            AdminServiceError::InternalError(msg)
+        }
+    }
+
     /// 分类删除凭据错误
     fn classify_delete_error(&self, e: anyhow::Error, id: u64) -> AdminServiceError {
         let msg = e.to_string();
@@ -479,3 +1071,128 @@ impl AdminService {
         }
     }
 }
+
+/// 校验凭据包口令长度
+fn check_passphrase(passphrase: &str) -> Result<(), AdminServiceError> {
+    if passphrase.chars().count() < bundle::MIN_PASSPHRASE_LEN {
+        return Err(AdminServiceError::InvalidRequest(format!(
+            "口令至少需要 {} 个字符",
+            bundle::MIN_PASSPHRASE_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// 按查询参数过滤并排序凭据列表（同值时按 ID 排序，保证分页稳定）
+fn filter_credentials(
+    credentials: Vec<CredentialStatusItem>,
+    query: &CredentialsQuery,
+) -> Vec<CredentialStatusItem> {
+    let mut credentials: Vec<_> = credentials
+        .into_iter()
+        .filter(|c| match query.status {
+            None => true,
+            Some(CredentialStatusFilter::Enabled) => !c.disabled,
+            Some(CredentialStatusFilter::Disabled) => c.disabled,
+            Some(CredentialStatusFilter::Degraded) => c.degraded_reason.is_some(),
+            Some(CredentialStatusFilter::Tripped) => c.breaker_state != BreakerState::Closed,
+        })
+        .filter(|c| {
+            query.auth_method.as_deref().is_none_or(|method| {
+                c.auth_method
+                    .as_deref()
+                    .is_some_and(|m| m.eq_ignore_ascii_case(method))
+            })
+        })
+        .filter(|c| query.tag.as_ref().is_none_or(|tag| c.tags.contains(tag)))
+        .collect();
+
+    credentials.sort_by(|a, b| {
+        let ordering = match query.sort {
+            CredentialSort::Priority => a.priority.cmp(&b.priority),
+            CredentialSort::Id => a.id.cmp(&b.id),
+            CredentialSort::FailureCount => a.failure_count.cmp(&b.failure_count),
+            CredentialSort::ExpiresAt => a.expires_at.cmp(&b.expires_at),
+            CredentialSort::ActiveConnections => a.active_connections.cmp(&b.active_connections),
+        };
+        let ordering = match query.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        ordering.then(a.id.cmp(&b.id))
+    });
+    credentials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(
+        id: u64,
+        failure_count: u32,
+        disabled: bool,
+        auth_method: &str,
+    ) -> CredentialStatusItem {
+        CredentialStatusItem {
+            id,
+            priority: 0,
+            weight: 1,
+            tags: Vec::new(),
+            disabled,
+            disabled_reason: None,
+            quota_reset_at: None,
+            failure_count,
+            is_current: false,
+            expires_at: None,
+            auth_method: Some(auth_method.to_string()),
+            has_profile_arn: false,
+            active_connections: 0,
+            max_concurrent: 1,
+            degraded_reason: None,
+            breaker_state: BreakerState::Closed,
+        }
+    }
+
+    #[test]
+    fn test_filter_credentials() {
+        let credentials = || {
+            vec![
+                item(1, 0, false, "social"),
+                item(2, 3, true, "idc"),
+                item(3, 5, true, "social"),
+                item(4, 3, false, "social"),
+            ]
+        };
+
+        let query = CredentialsQuery {
+            status: Some(CredentialStatusFilter::Disabled),
+            ..Default::default()
+        };
+        let ids: Vec<_> = filter_credentials(credentials(), &query)
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+
+        let query = CredentialsQuery {
+            auth_method: Some("Social".to_string()),
+            sort: CredentialSort::FailureCount,
+            order: SortOrder::Desc,
+            ..Default::default()
+        };
+        let ids: Vec<_> = filter_credentials(credentials(), &query)
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(ids, vec![3, 4, 1]);
+
+        // 同值时按 ID 排序
+        let query = CredentialsQuery::default();
+        let ids: Vec<_> = filter_credentials(credentials(), &query)
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+    }
+}
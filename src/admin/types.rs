@@ -1,7 +1,25 @@
 //! Admin API 类型定义
 
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+use super::bundle::CredentialBundle;
+use crate::common::api_keys::ApiKeySource;
+use crate::common::cache::{CacheKind, FlushResult};
+use crate::common::metrics::ThroughputSummary;
+use crate::kiro::circuit_breaker::BreakerState;
+use crate::model::config::{
+    ClientPriority, ConcurrencyConfig, LoggingConfig, RateLimitConfig, ResilienceConfig,
+    SchedulingStrategy, TimeoutConfig, TokenQuota,
+};
+use crate::storage::audit_log::{AuditFilter, AuditRecord};
+use crate::storage::balance_history::BalanceSample;
+use crate::storage::credential_stats::CredentialUsage;
+use crate::storage::key_usage::KeyUsageTotals;
+use crate::storage::ledger::DailyUsage;
+use crate::token::TokenizerStatus;
+
 // ============ 凭据状态 ============
 
 /// 所有凭据状态响应
@@ -14,10 +32,76 @@ pub struct CredentialsStatusResponse {
     pub available: usize,
     /// 当前活跃凭据 ID
     pub current_id: u64,
+    /// 符合过滤条件的凭据数量
+    pub matched: usize,
+    /// 当前页码（未分页时省略）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    /// 每页数量（未分页时省略）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
     /// 各凭据状态列表
     pub credentials: Vec<CredentialStatusItem>,
 }
 
+/// 凭据列表查询参数（均可省略，省略时返回全部凭据并按优先级排序）
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsQuery {
+    /// 按状态过滤
+    pub status: Option<CredentialStatusFilter>,
+    /// 按认证方式过滤（`social` / `idc`，不区分大小写）
+    #[serde(alias = "auth_method")]
+    pub auth_method: Option<String>,
+    /// 按分组标签过滤
+    pub tag: Option<String>,
+    /// 排序字段，默认 `priority`
+    #[serde(default)]
+    pub sort: CredentialSort,
+    /// 排序方向，默认升序
+    #[serde(default)]
+    pub order: SortOrder,
+    /// 页码（从 1 开始），指定 `page` 或 `limit` 时分页
+    pub page: Option<usize>,
+    /// 每页数量，默认 50，最大 500
+    pub limit: Option<usize>,
+}
+
+/// 凭据状态过滤条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialStatusFilter {
+    /// 未禁用
+    Enabled,
+    /// 已禁用
+    Disabled,
+    /// 已降级（上游维护/版本过低等）
+    Degraded,
+    /// 熔断器未闭合（open / half_open）
+    Tripped,
+}
+
+/// 凭据列表排序字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialSort {
+    #[default]
+    Priority,
+    Id,
+    FailureCount,
+    ExpiresAt,
+    ActiveConnections,
+}
+
+/// 排序方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
 /// 单个凭据的状态信息
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -26,8 +110,18 @@ pub struct CredentialStatusItem {
     pub id: u64,
     /// 优先级（数字越小优先级越高）
     pub priority: u32,
+    /// 调度权重（`weighted` 策略）
+    pub weight: u32,
+    /// 分组标签
+    pub tags: Vec<String>,
     /// 是否被禁用
     pub disabled: bool,
+    /// 禁用原因：`manual` / `too_many_failures` / `quota_exhausted`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled_reason: Option<&'static str>,
+    /// 额度用尽时的下次重置时间（RFC3339，到达后自动重新启用）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_reset_at: Option<String>,
     /// 连续失败次数
     pub failure_count: u32,
     /// 是否为当前活跃凭据
@@ -40,8 +134,13 @@ pub struct CredentialStatusItem {
     pub has_profile_arn: bool,
     /// 当前活跃连接数
     pub active_connections: u32,
-    /// 最大并发连接数
+    /// 最大并发连接数（自适应调整后的当前值）
     pub max_concurrent: u32,
+    /// 降级原因（上游维护/版本过低等，为空表示健康）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degraded_reason: Option<String>,
+    /// 熔断器状态：`closed` / `open` / `half_open`
+    pub breaker_state: BreakerState,
 }
 
 // ============ 操作请求 ============
@@ -62,6 +161,14 @@ pub struct SetPriorityRequest {
     pub priority: u32,
 }
 
+/// 设置分组标签请求
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetTagsRequest {
+    /// 新的标签列表（整体替换，空列表表示清除）
+    pub tags: Vec<String>,
+}
+
 /// 添加凭据请求
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -83,13 +190,38 @@ pub struct AddCredentialRequest {
     #[serde(default)]
     pub priority: u32,
 
+    /// 调度权重（可选，默认 1，`weighted` 策略生效）
+    pub weight: Option<u32>,
+
+    /// 最大并发连接数（可选，硬上限）
+    pub max_concurrent: Option<u32>,
+
+    /// 分组标签（可选）
+    #[serde(default)]
+    pub tags: Vec<String>,
+
     /// 凭据级 Region 配置（用于 OIDC token 刷新）
     /// 未配置时回退到 config.json 的全局 region
     pub region: Option<String>,
 
+    /// 凭据级 Kiro API Region（可选）
+    /// 未配置时回退到 config.json 的全局 region
+    pub api_region: Option<String>,
+
+    /// 凭据级 Kiro API 地址（可选，配置后优先于 apiRegion）
+    pub endpoint: Option<String>,
+
+    /// 备用 Kiro API Region（可选）
+    #[serde(default)]
+    pub failover_regions: Vec<String>,
+
     /// 凭据级 Machine ID（可选，64 位字符串）
     /// 未配置时回退到 config.json 的 machineId
     pub machine_id: Option<String>,
+
+    /// 凭据级自定义上游请求头（可选）
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
 }
 
 fn default_auth_method() -> String {
@@ -206,6 +338,40 @@ pub struct BatchImportResponse {
     pub results: Vec<BatchImportResultItem>,
 }
 
+// ============ 凭据导出/导入 ============
+
+/// 导出凭据请求
+#[derive(Debug, Deserialize)]
+pub struct ExportCredentialsRequest {
+    /// 加密口令（至少 8 个字符）
+    pub passphrase: String,
+}
+
+/// 导入凭据请求
+#[derive(Debug, Deserialize)]
+pub struct ImportCredentialsRequest {
+    /// 导出时使用的口令
+    pub passphrase: String,
+    /// `POST /credentials/export` 返回的凭据包
+    pub bundle: CredentialBundle,
+}
+
+/// 导入凭据响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCredentialsResponse {
+    pub success: bool,
+    pub message: String,
+    /// 凭据包中的凭据数量
+    pub total: usize,
+    /// 新导入的凭据 ID
+    pub credential_ids: Vec<u64>,
+    /// 已存在而跳过的数量
+    pub duplicates: usize,
+    /// refresh token 无效而跳过的数量
+    pub invalid: usize,
+}
+
 // ============ 余额查询 ============
 
 /// 余额查询响应
@@ -228,6 +394,296 @@ pub struct BalanceResponse {
     pub next_reset_at: Option<f64>,
 }
 
+/// 批量余额查询参数（均可省略，省略时查询全部凭据）
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancesQuery {
+    /// 逗号分隔的凭据 ID 列表
+    pub ids: Option<String>,
+    /// 按状态过滤
+    pub status: Option<CredentialStatusFilter>,
+    /// 按认证方式过滤
+    #[serde(alias = "auth_method")]
+    pub auth_method: Option<String>,
+    /// 按分组标签过滤
+    pub tag: Option<String>,
+    /// 并发查询数，默认 8，最大 32
+    pub concurrency: Option<usize>,
+}
+
+/// 单个凭据余额查询失败
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceError {
+    pub id: u64,
+    pub error: String,
+}
+
+/// 批量余额查询汇总
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancesResponse {
+    /// 查询的凭据数量
+    pub total: usize,
+    /// 查询成功数量
+    pub succeeded: usize,
+    /// 查询失败数量
+    pub failed: usize,
+    /// 成功凭据的已用额度合计
+    pub current_usage: f64,
+    /// 成功凭据的总额度合计
+    pub usage_limit: f64,
+    /// 成功凭据的剩余额度合计
+    pub remaining: f64,
+    /// 整体使用百分比
+    pub usage_percentage: f64,
+    /// 各凭据余额（按 ID 排序）
+    pub balances: Vec<BalanceResponse>,
+    /// 查询失败的凭据
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<BalanceError>,
+}
+
+// ============ 用量统计 ============
+
+/// 实时日志订阅参数
+#[derive(Debug, Deserialize)]
+pub struct LogStreamQuery {
+    /// 最低日志级别（如 `warn` 只推送 WARN 与 ERROR），默认全部
+    pub level: Option<String>,
+    /// 模块路径前缀（如 `kiro_rs::anthropic`），默认全部
+    pub module: Option<String>,
+}
+
+/// 用量查询参数
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// 查询最近多少天（含今天），默认 7，最大 90
+    pub days: Option<u32>,
+}
+
+/// 请求审计记录查询参数
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestsQuery {
+    /// 客户端 API Key 名称
+    pub client_key: Option<String>,
+    pub model: Option<String>,
+    /// HTTP 状态码
+    pub status: Option<u16>,
+    pub credential_id: Option<u64>,
+    /// 起始时间（RFC3339，含）
+    pub since: Option<String>,
+    /// 结束时间（RFC3339，不含）
+    pub until: Option<String>,
+    /// 跳过的记录数，默认 0
+    pub offset: Option<usize>,
+    /// 每页记录数，默认 50，最大 500
+    pub limit: Option<usize>,
+}
+
+impl RequestsQuery {
+    pub fn filter(&self) -> AuditFilter {
+        AuditFilter {
+            client_key: self.client_key.clone(),
+            model: self.model.clone(),
+            status: self.status,
+            credential_id: self.credential_id,
+            since: self.since.clone(),
+            until: self.until.clone(),
+        }
+    }
+}
+
+/// 请求审计记录响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestsResponse {
+    /// 符合条件的记录总数
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// 本页记录（最新的在前）
+    pub records: Vec<AuditRecord>,
+}
+
+/// 用量统计响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageResponse {
+    /// 实际查询的天数
+    pub days: u32,
+    /// 按日期、模型汇总的用量
+    pub entries: Vec<DailyUsage>,
+}
+
+/// 凭据余额历史响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceHistoryResponse {
+    /// 凭据 ID
+    pub id: u64,
+    /// 当前额度周期内的平均每日消耗（采样不足时为空）
+    pub burn_rate_per_day: Option<f64>,
+    /// 余额采样（按时间升序）
+    pub samples: Vec<BalanceSample>,
+}
+
+/// 凭据用量统计响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialStatsResponse {
+    /// 凭据 ID
+    pub id: u64,
+    /// 累计统计
+    #[serde(flatten)]
+    pub usage: CredentialUsage,
+}
+
+// ============ 缓存管理 ============
+
+/// 清空缓存请求
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlushCacheRequest {
+    /// 需要清空的缓存类型，为空时清空全部
+    #[serde(default)]
+    pub caches: Vec<CacheKind>,
+}
+
+/// 清空缓存响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlushCacheResponse {
+    /// 各缓存类型的清空结果
+    pub results: Vec<FlushResult>,
+}
+
+// ============ 客户端 API Key ============
+
+/// 所有客户端 API Key 响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeysResponse {
+    /// Key 总数
+    pub total: usize,
+    /// 各 Key 状态列表
+    pub keys: Vec<ApiKeyItem>,
+}
+
+/// 单个客户端 API Key 的状态（不含完整 Key）
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyItem {
+    /// 名称
+    pub name: String,
+    /// Key 预览（仅保留前几位）
+    pub key_preview: String,
+    /// 来源（config 为只读）
+    pub source: ApiKeySource,
+    /// 是否被禁用
+    pub disabled: bool,
+    /// 速率限制
+    pub rate_limit: RateLimitConfig,
+    /// 绑定的凭据分组
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// 优先级
+    pub priority: ClientPriority,
+    /// token 预算
+    pub quota: TokenQuota,
+    /// 是否允许使用服务端执行的 MCP 工具
+    pub mcp_tools: bool,
+    /// 是否允许使用模型覆盖请求头
+    pub model_override: bool,
+    /// 本次启动以来的请求数
+    pub requests: u64,
+}
+
+/// 创建客户端 API Key 请求
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiKeyRequest {
+    /// 名称（字母、数字、- 或 _）
+    pub name: String,
+    /// 自定义 Key（可选，未提供时自动生成）
+    pub key: Option<String>,
+    /// 速率限制（可选，默认不限制）
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// 绑定的凭据分组（可选）
+    pub group: Option<String>,
+    /// 优先级（可选，默认 normal）
+    #[serde(default)]
+    pub priority: ClientPriority,
+    /// token 预算（可选，默认不限制）
+    #[serde(default)]
+    pub quota: TokenQuota,
+}
+
+/// 客户端 API Key 的 token 预算与当前周期用量
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyUsageResponse {
+    /// 名称
+    pub name: String,
+    /// token 预算
+    pub quota: TokenQuota,
+    /// 当前周期用量
+    #[serde(flatten)]
+    pub usage: KeyUsageTotals,
+}
+
+/// 创建客户端 API Key 响应（完整 Key 仅在此返回一次）
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiKeyResponse {
+    pub success: bool,
+    pub message: String,
+    pub name: String,
+    pub key: String,
+}
+
+// ============ 运行时配置 ============
+
+/// 当前生效的运行时策略
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectiveConfigResponse {
+    /// 重试、退避与熔断策略
+    pub resilience: ResilienceConfig,
+    /// 凭据并发上限策略
+    pub concurrency: ConcurrencyConfig,
+    /// 上游请求超时
+    pub timeouts: TimeoutConfig,
+    /// 凭据调度策略
+    pub scheduling_strategy: SchedulingStrategy,
+    /// 按路由的日志级别
+    pub logging: LoggingConfig,
+}
+
+// ============ 运行状态 ============
+
+/// 运行状态响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeStatusResponse {
+    /// Claude tokenizer 加载状态
+    pub tokenizer: TokenizerStatus,
+    /// 估算的本地时钟与上游的偏差（秒）
+    pub clock_skew_secs: i64,
+}
+
+// ============ 性能统计 ============
+
+/// 性能统计响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsResponse {
+    /// 已完成流式响应的输出吞吐量（tokens/s），按模型与凭据分组
+    pub output_throughput: Vec<ThroughputSummary>,
+}
+
 // ============ 通用响应 ============
 
 /// 操作成功响应
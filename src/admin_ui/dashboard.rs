@@ -0,0 +1,46 @@
+//! 内置控制台
+//!
+//! 手写的单页控制台（`src/admin_ui/dashboard`），无需前端构建即可随二进制发布，
+//! 挂载在 `/admin/ui`：查看凭据状态、余额与活跃连接，启用/禁用凭据并调整顺序
+
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{Response, StatusCode, header},
+    response::IntoResponse,
+};
+use rust_embed::Embed;
+
+/// 嵌入控制台静态文件
+#[derive(Embed)]
+#[folder = "src/admin_ui/dashboard"]
+struct DashboardAsset;
+
+/// 处理控制台首页请求
+pub async fn dashboard_index() -> impl IntoResponse {
+    serve("index.html")
+}
+
+/// 处理控制台静态文件请求
+pub async fn dashboard_file(Path(file): Path<String>) -> impl IntoResponse {
+    serve(&file)
+}
+
+/// 返回嵌入的文件（控制台文件不带内容哈希，一律不缓存）
+fn serve(path: &str) -> Response<Body> {
+    match DashboardAsset::get(path) {
+        Some(content) => Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                header::CONTENT_TYPE,
+                mime_guess::from_path(path).first_or_octet_stream().as_ref(),
+            )
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Body::from(content.data.into_owned()))
+            .expect("Failed to build response"),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found"))
+            .expect("Failed to build response"),
+    }
+}
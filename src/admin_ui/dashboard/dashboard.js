@@ -0,0 +1,200 @@
+// kiro-rs 内置控制台：无需构建，直接调用 /api/admin 接口
+(function () {
+  'use strict';
+
+  // 与 admin-ui 共用同一个本地存储键
+  var KEY_STORAGE = 'adminApiKey';
+  var REFRESH_INTERVAL_MS = 5000;
+
+  var credentials = [];
+  var balances = {};
+  var timer = null;
+
+  function $(id) {
+    return document.getElementById(id);
+  }
+
+  function api(method, path, body) {
+    var headers = { 'x-api-key': localStorage.getItem(KEY_STORAGE) || '' };
+    if (body !== undefined) {
+      headers['Content-Type'] = 'application/json';
+    }
+    return fetch('/api/admin' + path, {
+      method: method,
+      headers: headers,
+      body: body === undefined ? undefined : JSON.stringify(body),
+    }).then(function (res) {
+      return res.json().catch(function () { return {}; }).then(function (data) {
+        if (res.status === 401) {
+          logout();
+        }
+        if (!res.ok) {
+          throw new Error((data.error && data.error.message) || ('HTTP ' + res.status));
+        }
+        return data;
+      });
+    });
+  }
+
+  function el(tag, attrs, children) {
+    var node = document.createElement(tag);
+    Object.keys(attrs || {}).forEach(function (name) {
+      if (name === 'onclick' || name === 'onchange') {
+        node[name] = attrs[name];
+      } else if (attrs[name] !== false && attrs[name] != null) {
+        node.setAttribute(name, attrs[name] === true ? '' : attrs[name]);
+      }
+    });
+    (children || []).forEach(function (child) {
+      node.appendChild(typeof child === 'string' ? document.createTextNode(child) : child);
+    });
+    return node;
+  }
+
+  function showError(message) {
+    $('error').textContent = message || '';
+  }
+
+  function statusBadge(c) {
+    if (c.disabled) {
+      return el('span', { class: 'badge off' }, ['已禁用']);
+    }
+    if (c.breakerState === 'open') {
+      return el('span', { class: 'badge degraded', title: '连续被上游拒绝，冷却后探测恢复' }, ['熔断']);
+    }
+    if (c.breakerState === 'half_open') {
+      return el('span', { class: 'badge degraded' }, ['探测中']);
+    }
+    if (c.degradedReason) {
+      return el('span', { class: 'badge degraded', title: c.degradedReason }, ['降级']);
+    }
+    if (c.isCurrent) {
+      return el('span', { class: 'badge current' }, ['当前']);
+    }
+    return el('span', { class: 'badge ok' }, ['正常']);
+  }
+
+  function balanceCell(c) {
+    var b = balances[c.id];
+    if (!b) {
+      return el('button', { class: 'secondary', onclick: function () { loadBalance(c.id); } }, ['查询']);
+    }
+    if (b.error) {
+      return el('span', { class: 'error', title: b.error }, ['查询失败']);
+    }
+    var text = b.remaining.toFixed(2) + ' / ' + b.usageLimit.toFixed(2);
+    return el('span', { title: b.subscriptionTitle || '' }, [text]);
+  }
+
+  function render(data) {
+    credentials = data.credentials.slice().sort(function (a, b) {
+      return a.priority - b.priority || a.id - b.id;
+    });
+    var active = credentials.reduce(function (sum, c) { return sum + c.activeConnections; }, 0);
+    $('summary').textContent =
+      '凭据 ' + data.available + ' / ' + data.total + ' 可用 · 活跃连接 ' + active;
+
+    var tbody = $('credentials');
+    tbody.textContent = '';
+    credentials.forEach(function (c, index) {
+      tbody.appendChild(el('tr', { class: c.disabled ? 'disabled' : false }, [
+        el('td', {}, [
+          el('button', { class: 'icon', disabled: index === 0, onclick: function () { move(index, -1); } }, ['↑']),
+          ' ',
+          el('button', { class: 'icon', disabled: index === credentials.length - 1, onclick: function () { move(index, 1); } }, ['↓']),
+        ]),
+        el('td', {}, ['#' + c.id]),
+        el('td', {}, [String(c.priority)]),
+        el('td', {}, [statusBadge(c)]),
+        el('td', {}, [c.activeConnections + ' / ' + c.maxConcurrent]),
+        el('td', {}, [String(c.failureCount)]),
+        el('td', {}, [c.authMethod || '-']),
+        el('td', {}, [c.expiresAt ? new Date(c.expiresAt).toLocaleString() : '-']),
+        el('td', {}, [balanceCell(c)]),
+        el('td', {}, [
+          el('input', {
+            type: 'checkbox',
+            checked: !c.disabled,
+            onchange: function (e) { setDisabled(c.id, !e.target.checked); },
+          }),
+        ]),
+      ]));
+    });
+  }
+
+  function refresh() {
+    return api('GET', '/credentials').then(function (data) {
+      showError('');
+      render(data);
+    }).catch(function (e) { showError(e.message); });
+  }
+
+  function loadBalance(id) {
+    return api('GET', '/credentials/' + id + '/balance').then(function (data) {
+      balances[id] = data;
+    }).catch(function (e) {
+      balances[id] = { error: e.message };
+    }).then(refresh);
+  }
+
+  function setDisabled(id, disabled) {
+    api('POST', '/credentials/' + id + '/disabled', { disabled: disabled })
+      .catch(function (e) { showError(e.message); })
+      .then(refresh);
+  }
+
+  // 交换相邻两项后按列表顺序重写优先级（0, 1, 2...），只提交发生变化的凭据
+  function move(index, delta) {
+    var order = credentials.slice();
+    var target = index + delta;
+    var moved = order[index];
+    order[index] = order[target];
+    order[target] = moved;
+
+    var updates = order
+      .map(function (c, priority) { return { id: c.id, priority: priority, old: c.priority }; })
+      .filter(function (u) { return u.priority !== u.old; })
+      .map(function (u) {
+        return api('POST', '/credentials/' + u.id + '/priority', { priority: u.priority });
+      });
+    Promise.all(updates)
+      .catch(function (e) { showError(e.message); })
+      .then(refresh);
+  }
+
+  function login(key) {
+    localStorage.setItem(KEY_STORAGE, key);
+    return api('GET', '/credentials').then(function (data) {
+      $('login').hidden = true;
+      $('app').hidden = false;
+      render(data);
+      clearInterval(timer);
+      timer = setInterval(refresh, REFRESH_INTERVAL_MS);
+    });
+  }
+
+  function logout() {
+    localStorage.removeItem(KEY_STORAGE);
+    clearInterval(timer);
+    $('app').hidden = true;
+    $('login').hidden = false;
+  }
+
+  $('login-form').onsubmit = function (e) {
+    e.preventDefault();
+    login($('login-key').value.trim()).catch(function (err) {
+      $('login-error').textContent = err.message;
+    });
+  };
+  $('logout').onclick = logout;
+  $('refresh-balances').onclick = function () {
+    Promise.all(credentials.map(function (c) { return loadBalance(c.id); }));
+  };
+
+  var saved = localStorage.getItem(KEY_STORAGE);
+  if (saved) {
+    login(saved).catch(logout);
+  } else {
+    logout();
+  }
+})();
@@ -1,7 +1,8 @@
 //! Admin UI 静态文件服务模块
 //!
-//! 使用 rust-embed 嵌入前端构建产物
+//! 使用 rust-embed 嵌入前端构建产物与内置控制台
 
+mod dashboard;
 mod router;
 
 pub use router::create_admin_ui_router;
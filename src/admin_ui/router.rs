@@ -9,15 +9,22 @@ use axum::{
 };
 use rust_embed::Embed;
 
+use super::dashboard::{dashboard_file, dashboard_index};
+
 /// 嵌入前端构建产物
 #[derive(Embed)]
 #[folder = "admin-ui/dist"]
 struct Asset;
 
 /// 创建 Admin UI 路由
+///
+/// `/ui` 为内置控制台（无需构建），其余路径为 admin-ui 前端
 pub fn create_admin_ui_router() -> Router {
     Router::new()
         .route("/", get(index_handler))
+        .route("/ui", get(dashboard_index))
+        .route("/ui/", get(dashboard_index))
+        .route("/ui/{*file}", get(dashboard_file))
         .route("/{*file}", get(static_handler))
 }
 
@@ -0,0 +1,1149 @@
+//! Message Batches API 模拟
+//!
+//! 批处理任务在后台分两个阶段执行：
+//! 1. 预处理：按 CPU 核数并发解析参数、转换请求并估算输入 tokens，不占用上游执行名额；
+//!    转换失败的请求直接记为 `errored`
+//! 2. 执行：每个请求按非流式 `/v1/messages` 处理（凭据由调度策略选择），
+//!    所有批处理任务合计同时执行的请求数不超过 `batches.concurrency`
+//!
+//! 每个请求在独立的任务中预处理与执行，参数错误、转换失败、上游错误乃至 panic 都只影响该请求自身的结果。
+//!
+//! 任务保存在本地目录（`batches.dir`）：元数据为 `<id>.json`（只含状态与计数，执行期间定期保存），
+//! custom_id 列表为 `<id>.ids`；执行期间结果由单个写入任务按完成顺序追加到 `<id>.partial.jsonl`，
+//! 任务结束时按请求顺序整理为 `<id>.jsonl`（每行一个结果），结果接口分块流式返回该文件。
+//! 服务重启时仍在执行的任务会被结束：计数按已写入的结果重建，未完成的请求记为 `expired`
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    Extension,
+    body::{Body, Bytes, to_bytes},
+    extract::{Path as PathParam, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use futures::{StreamExt, stream};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{Semaphore, mpsc};
+use tokio::task::JoinSet;
+
+use crate::common::api_keys::ClientKey;
+use crate::model::config::{BatchConfig, Config};
+use crate::token;
+
+use super::converter::convert_request;
+use super::handlers::post_messages;
+use super::middleware::AppState;
+use super::system_prompt;
+use super::types::{ErrorResponse, MessagesRequest};
+
+/// 批处理 ID 前缀
+const BATCH_ID_PREFIX: &str = "msgbatch_";
+
+/// 单个批处理任务的最大请求数
+const MAX_BATCH_REQUESTS: usize = 10_000;
+
+/// 任务有效期（超过后尚未开始的请求记为 expired）
+const BATCH_TTL_HOURS: i64 = 24;
+
+/// 列表默认返回数量
+const DEFAULT_LIST_LIMIT: usize = 20;
+
+/// 列表最大返回数量
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// 未能获取 CPU 核数时的预处理并发数
+const DEFAULT_PREPARE_CONCURRENCY: usize = 4;
+
+/// 执行期间保存任务计数的最短间隔
+const COUNTS_PERSIST_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// 读取结果文件时每次读取的字节数
+const RESULTS_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 处理状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingStatus {
+    InProgress,
+    Canceling,
+    Ended,
+}
+
+/// 各结果类型的请求数
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestCounts {
+    pub processing: usize,
+    pub succeeded: usize,
+    pub errored: usize,
+    pub canceled: usize,
+    pub expired: usize,
+}
+
+/// 批处理任务（API 响应格式）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageBatch {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub processing_status: ProcessingStatus,
+    pub request_counts: RequestCounts,
+    pub created_at: String,
+    pub expires_at: String,
+    pub ended_at: Option<String>,
+    pub cancel_initiated_at: Option<String>,
+    pub archived_at: Option<String>,
+    pub results_url: Option<String>,
+}
+
+/// 持久化的任务记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BatchRecord {
+    #[serde(flatten)]
+    batch: MessageBatch,
+    /// 创建任务的客户端 API Key 名称（只有同一个 Key 可以访问）
+    owner: String,
+    /// 请求总数（custom_id 另存于 `<id>.ids`）
+    total: usize,
+}
+
+/// 执行期间按完成顺序写入的单个结果
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialResult {
+    /// 请求在任务中的位置
+    index: usize,
+    custom_id: String,
+    result: Value,
+}
+
+/// 单个请求的结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchOutcome {
+    Succeeded(Value),
+    /// Anthropic 错误响应体（`{"type": "error", "error": {...}}`）
+    Errored(Value),
+    Canceled,
+    Expired,
+}
+
+impl BatchOutcome {
+    fn errored(error_type: &str, message: impl Into<String>) -> Self {
+        BatchOutcome::Errored(json!({
+            "type": "error",
+            "error": {"type": error_type, "message": message.into()}
+        }))
+    }
+
+    fn from_json(result: &Value) -> Self {
+        match result["type"].as_str() {
+            Some("succeeded") => BatchOutcome::Succeeded(result["message"].clone()),
+            Some("errored") => BatchOutcome::Errored(result["error"].clone()),
+            Some("canceled") => BatchOutcome::Canceled,
+            _ => BatchOutcome::Expired,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            BatchOutcome::Succeeded(message) => json!({"type": "succeeded", "message": message}),
+            BatchOutcome::Errored(error) => json!({"type": "errored", "error": error}),
+            BatchOutcome::Canceled => json!({"type": "canceled"}),
+            BatchOutcome::Expired => json!({"type": "expired"}),
+        }
+    }
+}
+
+/// 创建批处理任务的请求
+#[derive(Debug, Deserialize)]
+pub struct CreateBatchRequest {
+    pub requests: Vec<BatchRequestItem>,
+}
+
+/// 批处理中的单个请求
+#[derive(Debug, Deserialize)]
+pub struct BatchRequestItem {
+    pub custom_id: String,
+    /// Messages API 请求参数（执行时才解析，参数错误只影响该请求）
+    pub params: Value,
+}
+
+/// 本地批处理任务存储与执行队列
+pub struct BatchStore {
+    dir: PathBuf,
+    records: Mutex<HashMap<String, BatchRecord>>,
+    /// 串行化元数据写入，避免较旧的快照覆盖较新的快照
+    persist_lock: tokio::sync::Mutex<()>,
+    /// 所有任务共享的执行并发上限
+    permits: Arc<Semaphore>,
+}
+
+impl BatchStore {
+    /// 打开（必要时创建）任务目录，并结束上次运行时未完成的任务
+    pub fn open(config: &BatchConfig) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        fs::create_dir_all(&dir)?;
+        let store = Self {
+            dir,
+            records: Mutex::new(HashMap::new()),
+            persist_lock: tokio::sync::Mutex::new(()),
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+        };
+
+        let mut records = HashMap::new();
+        for entry in fs::read_dir(&store.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| serde_json::from_str::<BatchRecord>(&s).map_err(Into::into))
+            {
+                Ok(mut record) => {
+                    if record.batch.processing_status != ProcessingStatus::Ended {
+                        store.expire_unfinished(&mut record)?;
+                    }
+                    records.insert(record.batch.id.clone(), record);
+                }
+                Err(e) => tracing::warn!("读取批处理任务元数据失败 {:?}: {}", path, e),
+            }
+        }
+        *store.records.lock() = records;
+        Ok(store)
+    }
+
+    /// 结束上次运行时未完成的任务：按已写入的结果重新统计，其余请求记为 expired
+    fn expire_unfinished(&self, record: &mut BatchRecord) -> anyhow::Result<()> {
+        let id = record.batch.id.clone();
+        let mut counts = RequestCounts::default();
+        let mut done = vec![false; record.total];
+        for partial in read_partial_results(&partial_path(&self.dir, &id))? {
+            if let Some(slot) = done.get_mut(partial.index).filter(|d| !**d) {
+                *slot = true;
+                count(&mut counts, &BatchOutcome::from_json(&partial.result));
+            }
+        }
+        let custom_ids: Vec<String> = match fs::read_to_string(ids_path(&self.dir, &id)) {
+            Ok(ids) => ids.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let pending: Vec<usize> = (0..done.len()).filter(|&i| !done[i]).collect();
+        tracing::warn!(
+            "批处理任务 {} 在服务重启前未完成，{} 个请求记为 expired",
+            id,
+            pending.len()
+        );
+        let mut file = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(partial_path(&self.dir, &id))?,
+        );
+        for index in pending {
+            let custom_id = custom_ids.get(index).cloned().unwrap_or_default();
+            file.write_all(&partial_line(index, custom_id, &BatchOutcome::Expired)?)?;
+            counts.expired += 1;
+        }
+        file.flush()?;
+        drop(file);
+        record.batch.request_counts = counts;
+        assemble_results(&self.dir, &id, record.total)?;
+        end(&mut record.batch);
+        fs::write(
+            record_path(&self.dir, &id),
+            serde_json::to_string(&*record)?,
+        )?;
+        Ok(())
+    }
+
+    /// 登记新任务（请求由 [`spawn`] 在后台执行）
+    async fn create(&self, owner: &str, custom_ids: Vec<String>) -> anyhow::Result<MessageBatch> {
+        let now = Utc::now();
+        let record = BatchRecord {
+            batch: MessageBatch {
+                id: format!("{}{}", BATCH_ID_PREFIX, uuid::Uuid::new_v4().simple()),
+                object_type: "message_batch".to_string(),
+                processing_status: ProcessingStatus::InProgress,
+                request_counts: RequestCounts {
+                    processing: custom_ids.len(),
+                    ..RequestCounts::default()
+                },
+                created_at: timestamp(now),
+                expires_at: timestamp(now + Duration::hours(BATCH_TTL_HOURS)),
+                ended_at: None,
+                cancel_initiated_at: None,
+                archived_at: None,
+                results_url: None,
+            },
+            owner: owner.to_string(),
+            total: custom_ids.len(),
+        };
+        let id = record.batch.id.clone();
+        // custom_id 只在重启后补写 expired 结果时需要，单独写入一次，不随计数反复保存
+        tokio::fs::write(ids_path(&self.dir, &id), custom_ids.join("\n")).await?;
+        tokio::fs::File::create(partial_path(&self.dir, &id)).await?;
+        let batch = record.batch.clone();
+        self.records.lock().insert(id.clone(), record);
+        self.persist(&id).await?;
+        Ok(batch)
+    }
+
+    /// 获取任务（不存在或属于其他 API Key 时返回 None）
+    pub fn get(&self, owner: &str, id: &str) -> Option<MessageBatch> {
+        self.records
+            .lock()
+            .get(id)
+            .filter(|r| r.owner == owner)
+            .map(|r| r.batch.clone())
+    }
+
+    /// 列出任务（按创建时间倒序）
+    pub fn list(&self, owner: &str) -> Vec<MessageBatch> {
+        let mut batches: Vec<_> = self
+            .records
+            .lock()
+            .values()
+            .filter(|r| r.owner == owner)
+            .map(|r| r.batch.clone())
+            .collect();
+        batches.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        batches
+    }
+
+    /// 取消任务：尚未开始的请求记为 canceled，执行中的请求完成后任务结束
+    pub async fn cancel(&self, owner: &str, id: &str) -> anyhow::Result<Option<MessageBatch>> {
+        let batch = {
+            let mut records = self.records.lock();
+            let Some(record) = records.get_mut(id).filter(|r| r.owner == owner) else {
+                return Ok(None);
+            };
+            if record.batch.processing_status != ProcessingStatus::InProgress {
+                return Ok(Some(record.batch.clone()));
+            }
+            record.batch.processing_status = ProcessingStatus::Canceling;
+            record.batch.cancel_initiated_at = Some(timestamp(Utc::now()));
+            record.batch.clone()
+        };
+        self.persist(id).await?;
+        tracing::info!("批处理任务 {} 已开始取消", id);
+        Ok(Some(batch))
+    }
+
+    /// 任务及其 JSONL 结果文件路径（任务不存在时返回 None；结果文件在任务结束后才存在）
+    pub fn results(&self, owner: &str, id: &str) -> Option<(MessageBatch, PathBuf)> {
+        let batch = self.get(owner, id)?;
+        Some((batch, results_path(&self.dir, id)))
+    }
+
+    /// 请求开始执行前的检查：任务取消中或已过期时直接给出结果
+    fn precheck(&self, id: &str) -> Option<BatchOutcome> {
+        let records = self.records.lock();
+        let record = records.get(id)?;
+        if record.batch.processing_status == ProcessingStatus::Canceling {
+            return Some(BatchOutcome::Canceled);
+        }
+        let expired = DateTime::parse_from_rfc3339(&record.batch.expires_at)
+            .is_ok_and(|expires_at| Utc::now() >= expires_at);
+        expired.then_some(BatchOutcome::Expired)
+    }
+
+    /// 在内存中累计一个请求的结果
+    fn count(&self, id: &str, outcome: &BatchOutcome) {
+        if let Some(record) = self.records.lock().get_mut(id) {
+            let counts = &mut record.batch.request_counts;
+            counts.processing = counts.processing.saturating_sub(1);
+            count(counts, outcome);
+        }
+    }
+
+    /// 所有请求都有结果后按请求顺序整理结果并结束任务
+    async fn finish(&self, id: &str) {
+        let Some(total) = self.records.lock().get(id).map(|r| r.total) else {
+            return;
+        };
+        let (dir, batch_id) = (self.dir.clone(), id.to_string());
+        match tokio::task::spawn_blocking(move || assemble_results(&dir, &batch_id, total)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::error!("整理批处理任务 {} 的结果失败: {}", id, e),
+            Err(e) => tracing::error!("整理批处理任务 {} 的结果失败: {}", id, e),
+        }
+        let Some(counts) = self.records.lock().get_mut(id).map(|record| {
+            end(&mut record.batch);
+            record.batch.request_counts.clone()
+        }) else {
+            return;
+        };
+        if let Err(e) = self.persist(id).await {
+            tracing::error!("保存批处理任务 {} 失败: {}", id, e);
+        }
+        tracing::info!(
+            "批处理任务 {} 已结束 - 成功: {}, 失败: {}, 取消: {}, 过期: {}",
+            id,
+            counts.succeeded,
+            counts.errored,
+            counts.canceled,
+            counts.expired
+        );
+    }
+
+    /// 保存任务元数据（只含状态与计数，大小与请求数无关）
+    async fn persist(&self, id: &str) -> anyhow::Result<()> {
+        let _guard = self.persist_lock.lock().await;
+        let Some(json) = self
+            .records
+            .lock()
+            .get(id)
+            .map(serde_json::to_string)
+            .transpose()?
+        else {
+            return Ok(());
+        };
+        tokio::fs::write(record_path(&self.dir, id), json).await?;
+        Ok(())
+    }
+}
+
+/// 执行期间按完成顺序追加结果，计数在内存中累计并定期保存
+struct ResultWriter {
+    store: Arc<BatchStore>,
+    id: String,
+    file: tokio::fs::File,
+    persisted_at: Instant,
+}
+
+impl ResultWriter {
+    async fn open(store: Arc<BatchStore>, id: String) -> anyhow::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(partial_path(&store.dir, &id))
+            .await?;
+        Ok(Self {
+            store,
+            id,
+            file,
+            persisted_at: Instant::now(),
+        })
+    }
+
+    /// 记录第 `index` 个请求的结果
+    async fn write(&mut self, index: usize, custom_id: String, outcome: &BatchOutcome) {
+        let written = match partial_line(index, custom_id, outcome) {
+            Ok(line) => self.file.write_all(&line).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            tracing::error!("写入批处理任务 {} 的结果失败: {}", self.id, e);
+        }
+        self.store.count(&self.id, outcome);
+        if self.persisted_at.elapsed() >= COUNTS_PERSIST_INTERVAL {
+            self.persisted_at = Instant::now();
+            if let Err(e) = self.store.persist(&self.id).await {
+                tracing::error!("保存批处理任务 {} 失败: {}", self.id, e);
+            }
+        }
+    }
+
+    /// 确保所有结果都已写入文件
+    async fn close(mut self) {
+        if let Err(e) = self.file.flush().await {
+            tracing::error!("写入批处理任务 {} 的结果失败: {}", self.id, e);
+        }
+    }
+}
+
+fn record_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+fn ids_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.ids", id))
+}
+
+fn results_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.jsonl", id))
+}
+
+fn partial_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.partial.jsonl", id))
+}
+
+/// 序列化一行按完成顺序写入的结果
+fn partial_line(
+    index: usize,
+    custom_id: String,
+    outcome: &BatchOutcome,
+) -> anyhow::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(&PartialResult {
+        index,
+        custom_id,
+        result: outcome.to_json(),
+    })?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// 按结果类型计数
+fn count(counts: &mut RequestCounts, outcome: &BatchOutcome) {
+    match outcome {
+        BatchOutcome::Succeeded(_) => counts.succeeded += 1,
+        BatchOutcome::Errored(_) => counts.errored += 1,
+        BatchOutcome::Canceled => counts.canceled += 1,
+        BatchOutcome::Expired => counts.expired += 1,
+    }
+}
+
+/// 读取按完成顺序写入的结果（文件不存在时为空，无法解析的行被跳过）
+fn read_partial_results(path: &Path) -> anyhow::Result<Vec<PartialResult>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut results = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(partial) = serde_json::from_str(&line?) {
+            results.push(partial);
+        }
+    }
+    Ok(results)
+}
+
+/// 将按完成顺序写入的结果整理为按请求顺序排列的 `<id>.jsonl`，完成后删除临时文件
+///
+/// 只在内存中保留每个结果的位置，逐条读取写入，不会一次性载入全部结果
+fn assemble_results(dir: &Path, id: &str, total: usize) -> anyhow::Result<()> {
+    let partial = partial_path(dir, id);
+    let mut offsets: Vec<Option<(u64, usize)>> = vec![None; total];
+    let mut reader = BufReader::new(File::open(&partial)?);
+    let (mut offset, mut line) = (0u64, String::new());
+    loop {
+        line.clear();
+        let len = reader.read_line(&mut line)?;
+        if len == 0 {
+            break;
+        }
+        if let Ok(result) = serde_json::from_str::<PartialResult>(&line)
+            && let Some(slot) = offsets.get_mut(result.index).filter(|s| s.is_none())
+        {
+            *slot = Some((offset, len));
+        }
+        offset += len as u64;
+    }
+
+    let mut file = reader.into_inner();
+    let tmp = results_path(dir, id).with_extension("jsonl.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    let mut buf = Vec::new();
+    for (offset, len) in offsets.into_iter().flatten() {
+        buf.resize(len, 0);
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+        let result: PartialResult = serde_json::from_slice(&buf)?;
+        let line = json!({"custom_id": result.custom_id, "result": result.result});
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    fs::rename(&tmp, results_path(dir, id))?;
+    fs::remove_file(&partial)?;
+    let _ = fs::remove_file(ids_path(dir, id));
+    Ok(())
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// 将任务标记为已结束
+fn end(batch: &mut MessageBatch) {
+    batch.processing_status = ProcessingStatus::Ended;
+    batch.request_counts.processing = 0;
+    batch.ended_at = Some(timestamp(Utc::now()));
+    batch.results_url = Some(format!("/v1/messages/batches/{}/results", batch.id));
+}
+
+/// 在后台执行任务中的所有请求
+fn spawn(
+    store: Arc<BatchStore>,
+    state: AppState,
+    client: Option<Extension<ClientKey>>,
+    id: String,
+    requests: Vec<BatchRequestItem>,
+) {
+    tokio::spawn(async move {
+        let config = state
+            .kiro_provider
+            .as_ref()
+            .map(|p| p.token_manager().config())
+            .unwrap_or_default();
+        let concurrency =
+            std::thread::available_parallelism().map_or(DEFAULT_PREPARE_CONCURRENCY, |n| n.get());
+        // 预处理按请求顺序产出，执行阶段据此依次分配执行名额
+        let mut prepared = stream::iter(requests.into_iter().enumerate())
+            .map(|(index, item)| {
+                let config = config.clone();
+                async move {
+                    let prepared = tokio::spawn(prepare(config, item.params))
+                        .await
+                        .unwrap_or_else(|e| {
+                            tracing::error!("批处理请求 {} 预处理异常: {}", item.custom_id, e);
+                            Err(BatchOutcome::errored(
+                                "api_error",
+                                "Failed to prepare request",
+                            ))
+                        });
+                    (index, item.custom_id, prepared)
+                }
+            })
+            .buffered(concurrency);
+
+        // 结果由单个写入任务按完成顺序追加，执行任务之间互不阻塞
+        let (results, mut rx) = mpsc::unbounded_channel::<(usize, String, BatchOutcome)>();
+        let writer = match ResultWriter::open(store.clone(), id.clone()).await {
+            Ok(mut writer) => tokio::spawn(async move {
+                while let Some((index, custom_id, outcome)) = rx.recv().await {
+                    writer.write(index, custom_id, &outcome).await;
+                }
+                writer.close().await;
+            }),
+            Err(e) => {
+                tracing::error!("打开批处理任务 {} 的结果文件失败: {}", id, e);
+                store.finish(&id).await;
+                return;
+            }
+        };
+
+        let mut tasks = JoinSet::new();
+        let (mut invalid, mut input_tokens) = (0, 0);
+        while let Some((index, custom_id, prepared)) = prepared.next().await {
+            let request = match prepared {
+                Ok((request, tokens)) => {
+                    input_tokens += tokens;
+                    request
+                }
+                Err(outcome) => {
+                    invalid += 1;
+                    let _ = results.send((index, custom_id, outcome));
+                    continue;
+                }
+            };
+            if let Some(outcome) = store.precheck(&id) {
+                let _ = results.send((index, custom_id, outcome));
+                continue;
+            }
+            let Ok(permit) = store.permits.clone().acquire_owned().await else {
+                break;
+            };
+            // 等待执行名额期间任务可能已被取消
+            if let Some(outcome) = store.precheck(&id) {
+                let _ = results.send((index, custom_id, outcome));
+                continue;
+            }
+
+            let (results, state, client) = (results.clone(), state.clone(), client.clone());
+            tasks.spawn(async move {
+                let outcome = tokio::spawn(execute(state, client, request))
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!("批处理请求 {} 执行异常: {}", custom_id, e);
+                        BatchOutcome::errored("api_error", "Failed to execute request")
+                    });
+                drop(permit);
+                let _ = results.send((index, custom_id, outcome));
+            });
+        }
+        tracing::info!(
+            "批处理任务 {} 预处理完成：{} 个请求无效，估算输入 {} tokens",
+            id,
+            invalid,
+            input_tokens
+        );
+        while tasks.join_next().await.is_some() {}
+        drop(results);
+        if let Err(e) = writer.await {
+            tracing::error!("批处理任务 {} 的结果写入任务异常: {}", id, e);
+        }
+        store.finish(&id).await;
+    });
+}
+
+/// 预处理单个请求：解析参数、转换请求并估算输入 tokens
+///
+/// 转换在阻塞线程池上执行；失败的请求直接得到 errored 结果，不占用上游执行名额
+async fn prepare(
+    config: Arc<Config>,
+    mut params: Value,
+) -> Result<(MessagesRequest, u64), BatchOutcome> {
+    if let Some(params) = params.as_object_mut() {
+        params.insert("stream".to_string(), Value::Bool(false));
+    }
+    let request: MessagesRequest = serde_json::from_value(params).map_err(|e| {
+        BatchOutcome::errored("invalid_request_error", format!("Invalid params: {}", e))
+    })?;
+    let request = tokio::task::spawn_blocking(move || {
+        convert_request(
+            &request,
+            None,
+            None,
+            system_prompt::resolve(&config, &request.model, None),
+            &config.synthetic_history,
+        )
+        .map(|_| request)
+    })
+    .await
+    .map_err(|e| BatchOutcome::errored("api_error", format!("Failed to convert request: {}", e)))?
+    .map_err(|e| BatchOutcome::errored("invalid_request_error", e.to_string()))?;
+
+    let tokens = token::count_all_tokens(
+        &request.model,
+        request.system.as_deref(),
+        &request.messages,
+        request.tools.as_deref(),
+    )
+    .await;
+    Ok((request, tokens))
+}
+
+/// 以非流式 `/v1/messages` 请求执行单个已预处理的批处理请求
+async fn execute(
+    state: AppState,
+    client: Option<Extension<ClientKey>>,
+    request: MessagesRequest,
+) -> BatchOutcome {
+    let response = post_messages(State(state), client, HeaderMap::new(), Json(request)).await;
+    let status = response.status();
+    let body = match to_bytes(response.into_body(), usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            return BatchOutcome::errored("api_error", format!("Failed to read response: {}", e));
+        }
+    };
+    match serde_json::from_slice::<Value>(&body) {
+        Ok(message) if status.is_success() => BatchOutcome::Succeeded(message),
+        Ok(error) if error.get("error").is_some() => BatchOutcome::Errored(json!({
+            "type": "error",
+            "error": error["error"]
+        })),
+        _ => BatchOutcome::errored("api_error", String::from_utf8_lossy(&body)),
+    }
+}
+
+/// 校验创建请求，返回所有 custom_id
+fn validate_requests(requests: &[BatchRequestItem]) -> Result<Vec<String>, String> {
+    if requests.is_empty() {
+        return Err("requests: List should have at least 1 item".to_string());
+    }
+    if requests.len() > MAX_BATCH_REQUESTS {
+        return Err(format!(
+            "requests: List should have at most {} items",
+            MAX_BATCH_REQUESTS
+        ));
+    }
+    let mut seen = HashSet::new();
+    for (index, item) in requests.iter().enumerate() {
+        let id = &item.custom_id;
+        if id.is_empty()
+            || id.len() > 64
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "requests.{}.custom_id: must be 1-64 characters of letters, digits, '_' or '-'",
+                index
+            ));
+        }
+        if !seen.insert(id.as_str()) {
+            return Err(format!(
+                "requests.{}.custom_id: duplicate custom_id {}",
+                index, id
+            ));
+        }
+    }
+    Ok(requests.iter().map(|r| r.custom_id.clone()).collect())
+}
+
+/// 列表查询参数
+#[derive(Debug, Deserialize)]
+pub struct ListBatchesQuery {
+    pub limit: Option<usize>,
+    pub after_id: Option<String>,
+}
+
+/// 生成错误响应
+fn error_response(status: StatusCode, error_type: &str, message: impl Into<String>) -> Response {
+    (status, Json(ErrorResponse::new(error_type, message))).into_response()
+}
+
+fn batches_disabled() -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "not_found_error",
+        "Message Batches API is not enabled",
+    )
+}
+
+fn batch_not_found(id: &str) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "not_found_error",
+        format!("Message batch not found: {}", id),
+    )
+}
+
+/// 资源归属的客户端 API Key 名称（未启用客户端 Key 时为空）
+pub(super) fn owner(client: &Option<Extension<ClientKey>>) -> &str {
+    client.as_ref().map(|c| c.name.as_str()).unwrap_or_default()
+}
+
+/// POST /v1/messages/batches
+///
+/// 创建批处理任务，立即返回任务信息，请求在后台执行
+pub async fn create_batch(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    Json(payload): Json<CreateBatchRequest>,
+) -> Response {
+    let Some(store) = state.batch_store.clone() else {
+        return batches_disabled();
+    };
+    let custom_ids = match validate_requests(&payload.requests) {
+        Ok(ids) => ids,
+        Err(message) => {
+            return error_response(StatusCode::BAD_REQUEST, "invalid_request_error", message);
+        }
+    };
+
+    match store.create(owner(&client), custom_ids).await {
+        Ok(batch) => {
+            tracing::info!(
+                "已创建批处理任务 {}，共 {} 个请求",
+                batch.id,
+                payload.requests.len()
+            );
+            spawn(store, state, client, batch.id.clone(), payload.requests);
+            Json(batch).into_response()
+        }
+        Err(e) => {
+            tracing::error!("创建批处理任务失败: {}", e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                format!("创建批处理任务失败: {}", e),
+            )
+        }
+    }
+}
+
+/// GET /v1/messages/batches
+///
+/// 列出当前 API Key 的批处理任务（支持 `limit` 和 `after_id` 分页）
+pub async fn list_batches(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    Query(query): Query<ListBatchesQuery>,
+) -> Response {
+    let Some(store) = state.batch_store.as_ref() else {
+        return batches_disabled();
+    };
+    let batches = store.list(owner(&client));
+
+    let start = query
+        .after_id
+        .as_ref()
+        .and_then(|after| batches.iter().position(|b| &b.id == after).map(|i| i + 1))
+        .unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let page: Vec<_> = batches.iter().skip(start).take(limit).collect();
+    let has_more = start + page.len() < batches.len();
+
+    Json(json!({
+        "data": page,
+        "first_id": page.first().map(|b| &b.id),
+        "last_id": page.last().map(|b| &b.id),
+        "has_more": has_more
+    }))
+    .into_response()
+}
+
+/// GET /v1/messages/batches/{id}
+pub async fn get_batch(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    PathParam(id): PathParam<String>,
+) -> Response {
+    let Some(store) = state.batch_store.as_ref() else {
+        return batches_disabled();
+    };
+    match store.get(owner(&client), &id) {
+        Some(batch) => Json(batch).into_response(),
+        None => batch_not_found(&id),
+    }
+}
+
+/// POST /v1/messages/batches/{id}/cancel
+pub async fn cancel_batch(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    PathParam(id): PathParam<String>,
+) -> Response {
+    let Some(store) = state.batch_store.as_ref() else {
+        return batches_disabled();
+    };
+    match store.cancel(owner(&client), &id).await {
+        Ok(Some(batch)) => Json(batch).into_response(),
+        Ok(None) => batch_not_found(&id),
+        Err(e) => {
+            tracing::error!("取消批处理任务失败: {}", e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                format!("取消批处理任务失败: {}", e),
+            )
+        }
+    }
+}
+
+/// GET /v1/messages/batches/{id}/results
+///
+/// 以流式响应返回 JSONL 格式的结果（任务结束后可用）
+pub async fn get_batch_results(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    PathParam(id): PathParam<String>,
+) -> Response {
+    let Some(store) = state.batch_store.as_ref() else {
+        return batches_disabled();
+    };
+    let Some((batch, path)) = store.results(owner(&client), &id) else {
+        return batch_not_found(&id);
+    };
+    if batch.processing_status != ProcessingStatus::Ended {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            format!(
+                "Message batch {} is still {}",
+                id,
+                json!(batch.processing_status)
+            ),
+        );
+    }
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::error!("读取批处理结果失败: {}", e);
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                format!("读取批处理结果失败: {}", e),
+            );
+        }
+    };
+    // 分块读取文件，不把全部结果载入内存；读取出错后结束响应
+    let chunks = stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = vec![0; RESULTS_CHUNK_SIZE];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), Some(file)))
+            }
+            Err(e) => {
+                tracing::error!("读取批处理结果失败: {}", e);
+                Some((Err(e), None))
+            }
+        }
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-jsonl")],
+        Body::from_stream(chunks),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BatchConfig {
+        let dir = std::env::temp_dir().join(format!("kiro-batches-{}", uuid::Uuid::new_v4()));
+        BatchConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            concurrency: 2,
+        }
+    }
+
+    fn item(custom_id: &str) -> BatchRequestItem {
+        BatchRequestItem {
+            custom_id: custom_id.to_string(),
+            params: json!({}),
+        }
+    }
+
+    #[test]
+    fn test_validate_requests() {
+        assert!(validate_requests(&[]).is_err());
+        assert_eq!(
+            validate_requests(&[item("a"), item("b-1")]).unwrap(),
+            vec!["a", "b-1"]
+        );
+        assert!(
+            validate_requests(&[item("a"), item("a")])
+                .unwrap_err()
+                .contains("duplicate")
+        );
+        assert!(
+            validate_requests(&[item("bad id")])
+                .unwrap_err()
+                .starts_with("requests.0.custom_id")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_cancel_and_owner_isolation() {
+        let store = Arc::new(BatchStore::open(&config()).unwrap());
+        let batch = store
+            .create(
+                "alice",
+                vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            )
+            .await
+            .unwrap();
+        assert!(store.get("bob", &batch.id).is_none());
+        assert_eq!(store.list("alice").len(), 1);
+
+        // 结果按完成顺序记录，整理后按请求顺序输出
+        let mut writer = ResultWriter::open(store.clone(), batch.id.clone())
+            .await
+            .unwrap();
+        writer
+            .write(
+                2,
+                "c".to_string(),
+                &BatchOutcome::errored("api_error", "boom"),
+            )
+            .await;
+        writer
+            .write(
+                0,
+                "a".to_string(),
+                &BatchOutcome::Succeeded(json!({"id": "msg_1"})),
+            )
+            .await;
+        store.cancel("alice", &batch.id).await.unwrap();
+        assert_eq!(store.precheck(&batch.id), Some(BatchOutcome::Canceled));
+        writer
+            .write(1, "b".to_string(), &BatchOutcome::Canceled)
+            .await;
+        writer.close().await;
+        store.finish(&batch.id).await;
+
+        let (batch, path) = store.results("alice", &batch.id).unwrap();
+        let results = fs::read_to_string(path).unwrap();
+        assert_eq!(batch.processing_status, ProcessingStatus::Ended);
+        assert_eq!(
+            batch.request_counts,
+            RequestCounts {
+                processing: 0,
+                succeeded: 1,
+                errored: 1,
+                canceled: 1,
+                expired: 0,
+            }
+        );
+        let lines: Vec<Value> = results
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let ids: Vec<_> = lines
+            .iter()
+            .map(|l| l["custom_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert_eq!(lines[0]["result"]["message"]["id"], "msg_1");
+        assert_eq!(lines[1]["result"]["type"], "canceled");
+        assert_eq!(lines[2]["result"]["error"]["error"]["type"], "api_error");
+    }
+
+    #[tokio::test]
+    async fn test_unfinished_batches_expire_on_reopen() {
+        let config = config();
+        let store = Arc::new(BatchStore::open(&config).unwrap());
+        let batch = store
+            .create("alice", vec!["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        let mut writer = ResultWriter::open(store.clone(), batch.id.clone())
+            .await
+            .unwrap();
+        writer
+            .write(1, "b".to_string(), &BatchOutcome::Succeeded(json!({})))
+            .await;
+        writer.close().await;
+        drop(store);
+
+        let store = BatchStore::open(&config).unwrap();
+        let (batch, path) = store.results("alice", &batch.id).unwrap();
+        let results = fs::read_to_string(path).unwrap();
+        assert_eq!(batch.processing_status, ProcessingStatus::Ended);
+        assert_eq!(batch.request_counts.succeeded, 1);
+        assert_eq!(batch.request_counts.expired, 1);
+        assert!(batch.results_url.is_some());
+        let lines: Vec<Value> = results
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["custom_id"], "a");
+        assert_eq!(lines[0]["result"]["type"], "expired");
+        assert_eq!(lines[1]["result"]["type"], "succeeded");
+    }
+
+    #[tokio::test]
+    async fn test_prepare_isolates_invalid_requests() {
+        let config = Arc::new(Config::default());
+        let error_type = |outcome: Result<(MessagesRequest, u64), BatchOutcome>| match outcome {
+            Err(BatchOutcome::Errored(error)) => error["error"]["type"].clone(),
+            _ => panic!("应返回 errored 结果"),
+        };
+
+        let outcome = prepare(config.clone(), json!({"model": 1})).await;
+        assert_eq!(error_type(outcome), "invalid_request_error");
+
+        // 转换失败（消息列表为空）同样只得到该请求的 errored 结果
+        let outcome = prepare(
+            config.clone(),
+            json!({"model": "claude-sonnet-4-5", "max_tokens": 16, "messages": []}),
+        )
+        .await;
+        assert_eq!(error_type(outcome), "invalid_request_error");
+
+        let (request, tokens) = prepare(
+            config,
+            json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "stream": true,
+                "messages": [{"role": "user", "content": "hello"}]
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(!request.stream);
+        assert!(tokens > 0);
+    }
+}
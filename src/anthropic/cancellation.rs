@@ -0,0 +1,215 @@
+//! 进行中请求的取消
+//!
+//! 每个 `/v1/messages` 请求在处理期间登记在 [`RequestRegistry`] 中，ID 即消息 ID
+//! （客户端可通过 `x-kiro-request-id` 预先指定）。`DELETE /v1/messages/{id}` 取消请求时，
+//! 丢弃正在进行的上游调用或响应流，从而中止上游连接并释放凭据的连接占用
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use bytes::Bytes;
+use futures::{Stream, StreamExt, stream};
+use parking_lot::Mutex;
+use serde_json::json;
+use tokio::sync::watch;
+
+use super::stream::SseEvent;
+use super::types::ErrorResponse;
+
+/// 请求 ID 请求头：请求时可指定 ID，响应中返回实际使用的 ID
+pub const REQUEST_ID_HEADER: &str = "x-kiro-request-id";
+
+/// 客户端指定的请求 ID 最大长度
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// 已取消请求返回的错误类型
+pub const CANCELLED_ERROR_TYPE: &str = "request_cancelled";
+
+struct Registered {
+    /// 发起请求的 API Key 名称，只有同一个 Key 可以取消
+    owner: String,
+    cancel: watch::Sender<bool>,
+}
+
+/// 进行中请求的登记表
+#[derive(Default)]
+pub struct RequestRegistry {
+    requests: Mutex<HashMap<String, Registered>>,
+}
+
+impl RequestRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记请求，ID 已被进行中的请求占用时返回 None
+    pub fn register(self: &Arc<Self>, id: String, owner: &str) -> Option<RequestHandle> {
+        let mut requests = self.requests.lock();
+        if requests.contains_key(&id) {
+            return None;
+        }
+        let (cancel, cancelled) = watch::channel(false);
+        requests.insert(
+            id.clone(),
+            Registered {
+                owner: owner.to_string(),
+                cancel,
+            },
+        );
+        Some(RequestHandle {
+            registry: Arc::clone(self),
+            id,
+            cancelled,
+        })
+    }
+
+    /// 取消请求，请求不存在或不属于该 Key 时返回 false
+    pub fn cancel(&self, id: &str, owner: &str) -> bool {
+        let requests = self.requests.lock();
+        match requests.get(id) {
+            Some(registered) if registered.owner == owner => {
+                registered.cancel.send_replace(true);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 已登记请求的句柄，drop 时自动注销
+pub struct RequestHandle {
+    registry: Arc<RequestRegistry>,
+    id: String,
+    cancelled: watch::Receiver<bool>,
+}
+
+impl RequestHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// 等待请求被取消（未取消时永不返回）
+    pub async fn cancelled(&mut self) {
+        // 发送端随登记项存活，而登记项只会在句柄 drop 时移除
+        if self
+            .cancelled
+            .wait_for(|cancelled| *cancelled)
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for RequestHandle {
+    fn drop(&mut self) {
+        self.registry.requests.lock().remove(&self.id);
+    }
+}
+
+/// 解析客户端指定的请求 ID，格式不合法时返回错误信息
+///
+/// 只允许字母、数字、`-` 与 `_`，长度不超过 128
+pub fn requested_id(headers: &HeaderMap) -> Result<Option<String>, String> {
+    let Some(value) = headers.get(REQUEST_ID_HEADER) else {
+        return Ok(None);
+    };
+    let id = value.to_str().unwrap_or_default().trim();
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(Some(id.to_string()))
+    } else {
+        Err(format!(
+            "{} must be 1-{} characters of [A-Za-z0-9_-]",
+            REQUEST_ID_HEADER, MAX_REQUEST_ID_LEN
+        ))
+    }
+}
+
+/// 请求在返回响应前被取消时的响应（499，与 nginx 的 Client Closed Request 一致）
+pub fn cancelled_response() -> Response {
+    (
+        StatusCode::from_u16(499).unwrap(),
+        Json(ErrorResponse::new(
+            CANCELLED_ERROR_TYPE,
+            "Request was cancelled",
+        )),
+    )
+        .into_response()
+}
+
+/// 使 SSE 流可被取消：取消时输出一个 error 事件后结束，并丢弃原始流
+pub fn cancellable<S>(
+    stream: S,
+    handle: RequestHandle,
+) -> impl Stream<Item = Result<Bytes, Infallible>>
+where
+    S: Stream<Item = Result<Bytes, Infallible>> + Send + 'static,
+{
+    stream::unfold(Some((Box::pin(stream), handle)), |state| async move {
+        let (mut stream, mut handle) = state?;
+        tokio::select! {
+            item = stream.next() => item.map(|item| (item, Some((stream, handle)))),
+            _ = handle.cancelled() => {
+                tracing::info!("请求 {} 已被取消，中止响应流", handle.id());
+                let event = SseEvent::new(
+                    "error",
+                    json!({
+                        "type": "error",
+                        "error": {"type": CANCELLED_ERROR_TYPE, "message": "Request was cancelled"}
+                    }),
+                );
+                Some((Ok(Bytes::from(event.to_sse_string())), None))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_requires_same_owner_and_unregisters_on_drop() {
+        let registry = Arc::new(RequestRegistry::new());
+        let handle = registry.register("msg_1".to_string(), "team-a").unwrap();
+        assert!(registry.register("msg_1".to_string(), "team-a").is_none());
+
+        assert!(!registry.cancel("msg_1", "team-b"));
+        assert!(!registry.cancel("msg_2", "team-a"));
+
+        let chunks = stream::pending::<Result<Bytes, Infallible>>();
+        let mut stream = std::pin::pin!(cancellable(chunks, handle));
+        assert!(registry.cancel("msg_1", "team-a"));
+        let Some(Ok(event)) = stream.next().await else {
+            panic!("取消后应输出 error 事件");
+        };
+        assert!(String::from_utf8_lossy(&event).contains(CANCELLED_ERROR_TYPE));
+        assert!(stream.next().await.is_none());
+
+        // 句柄已随流 drop，ID 可以重新使用
+        assert!(registry.register("msg_1".to_string(), "team-a").is_some());
+    }
+
+    #[test]
+    fn test_requested_id_validation() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(requested_id(&headers), Ok(None));
+
+        headers.insert(REQUEST_ID_HEADER, "job-42_a".parse().unwrap());
+        assert_eq!(requested_id(&headers), Ok(Some("job-42_a".to_string())));
+
+        headers.insert(REQUEST_ID_HEADER, "a/b".parse().unwrap());
+        assert!(requested_id(&headers).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! 部署能力描述
+//!
+//! `GET /v1/capabilities` 以稳定的机器可读格式描述当前部署支持的模型、请求大小上限、
+//! 本地模拟的 beta 功能、被拦截的工具与认证方式，客户端可据此做特性探测，
+//! 而不必对未文档化的代理行为反复试错。字段只增不改，破坏性变更时递增 `schema_version`
+
+use std::sync::Arc;
+
+use axum::{
+    Extension,
+    extract::State,
+    http::HeaderMap,
+    response::{IntoResponse, Json},
+};
+use serde::Serialize;
+
+use crate::common::api_keys::ClientKey;
+use crate::model::config::{Config, RequestValidation, WebSearchMode};
+
+use super::cancellation::REQUEST_ID_HEADER;
+use super::credential_group::GROUP_HEADER;
+use super::event_buffer::TRANSPORT_HEADER;
+use super::handlers::available_models;
+use super::injection::INJECT_HEADER;
+use super::middleware::AppState;
+use super::model_config::get_context_window_size;
+use super::model_override::{self, MODEL_OVERRIDE_HEADER};
+use super::response_cache::CACHE_HEADER;
+use super::router::MAX_BODY_SIZE;
+use super::types::MAX_BUDGET_TOKENS;
+use super::websearch;
+
+/// 能力描述格式版本
+const SCHEMA_VERSION: u32 = 1;
+
+/// 能力描述响应
+#[derive(Debug, Serialize)]
+pub struct CapabilitiesResponse {
+    /// 格式版本
+    pub schema_version: u32,
+    /// 服务信息
+    pub server: ServerInfo,
+    /// 可用模型
+    pub models: Vec<ModelCapability>,
+    /// 请求限制
+    pub limits: Limits,
+    /// 支持的端点
+    pub endpoints: Vec<&'static str>,
+    /// 功能开关
+    pub features: Features,
+    /// 本地模拟的 Anthropic beta 功能
+    pub betas_emulated: Vec<&'static str>,
+    /// 被代理拦截处理的工具
+    pub tools_intercepted: Vec<InterceptedTool>,
+    /// 认证方式
+    pub auth: AuthInfo,
+}
+
+/// 服务信息
+#[derive(Debug, Serialize)]
+pub struct ServerInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+/// 模型能力
+#[derive(Debug, Serialize)]
+pub struct ModelCapability {
+    pub id: String,
+    pub display_name: String,
+    pub max_output_tokens: i32,
+    pub context_window: i32,
+}
+
+/// 请求限制
+#[derive(Debug, Serialize)]
+pub struct Limits {
+    /// 请求体最大字节数
+    pub max_request_body_bytes: usize,
+    /// thinking 预算上限（超出部分按上限处理）
+    pub max_thinking_budget_tokens: i32,
+}
+
+/// 功能开关
+#[derive(Debug, Serialize)]
+pub struct Features {
+    pub streaming: bool,
+    /// thinking（通过提示词标签模拟）
+    pub thinking: bool,
+    pub tools: bool,
+    /// tool_choice（通过提示词提示模拟）
+    pub tool_choice: bool,
+    pub images: bool,
+    /// stop_sequences（本地截断）
+    pub stop_sequences: bool,
+    /// count_tokens（本地估算）
+    pub count_tokens: bool,
+    /// Files API（本地存储）
+    pub files: bool,
+    /// Message Batches API（本地任务队列）
+    pub message_batches: bool,
+    /// OpenAI 兼容的 `/v1/chat/completions`
+    pub openai_chat_completions: bool,
+    /// `Idempotency-Key` 非流式响应缓存
+    pub idempotency_keys: bool,
+    /// 相同非流式请求的响应缓存（`responseCache`）
+    pub response_cache: bool,
+    /// 长轮询传输（`x-kiro-transport: poll`）
+    pub long_polling: bool,
+    /// 取消进行中的请求（`DELETE /v1/messages/{id}`）
+    pub request_cancellation: bool,
+    /// 流式响应在输出内容前中断时换用其他凭据重试（`streamRetries`）
+    pub stream_failover: bool,
+    /// 提示词注入头（`x-kiro-inject`）
+    pub prompt_injection_header: bool,
+    /// 模型覆盖请求头（`x-kiro-model-override`，按本次请求的 Key 判断是否可用）
+    pub model_override_header: bool,
+    /// 重复图片去重
+    pub image_dedupe: bool,
+    /// 超限图片缩小（`imageDownscale`）
+    pub image_downscale: bool,
+    /// 下载 URL 来源的图片（`imageFetch`）
+    pub image_fetch: bool,
+    /// 请求体过大时自动压缩历史（`autoCompactHistory`）
+    pub auto_compact_history: bool,
+    /// 历史过长时由服务端生成摘要（`contextSummary`）
+    pub context_summary: bool,
+    /// Kiro 凭据不可用时转发到备用后端（`fallbackBackends`）
+    pub fallback_backends: bool,
+    /// 请求 schema 校验方式（`requestValidation`）
+    pub request_validation: RequestValidation,
+    /// 自定义请求头名称
+    pub headers: FeatureHeaders,
+}
+
+/// 功能相关的请求头
+#[derive(Debug, Serialize)]
+pub struct FeatureHeaders {
+    pub transport: &'static str,
+    pub inject: &'static str,
+    pub model_override: &'static str,
+    pub idempotency: &'static str,
+    pub cache: &'static str,
+    pub credential_group: &'static str,
+    pub request_id: &'static str,
+}
+
+/// 被拦截的工具
+#[derive(Debug, Serialize)]
+pub struct InterceptedTool {
+    pub name: &'static str,
+    /// 处理方式：`intercept` / `strip` / `reject`
+    pub mode: WebSearchMode,
+}
+
+/// 认证方式
+#[derive(Debug, Serialize)]
+pub struct AuthInfo {
+    /// 支持的凭据传递方式
+    pub methods: Vec<&'static str>,
+}
+
+/// GET /v1/capabilities
+///
+/// 返回当前部署（及当前 API Key，如 WebSearch 覆盖）的能力描述
+pub async fn get_capabilities(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let config = match &state.kiro_provider {
+        Some(provider) => provider.token_manager().config(),
+        None => Arc::new(Config::default()),
+    };
+    Json(build_capabilities(
+        &config,
+        client.as_deref(),
+        &headers,
+        state.file_store.is_some(),
+        state.batch_store.is_some(),
+        state.idempotency.is_some(),
+    ))
+}
+
+/// 构建能力描述
+fn build_capabilities(
+    config: &Config,
+    client: Option<&ClientKey>,
+    headers: &HeaderMap,
+    files: bool,
+    message_batches: bool,
+    idempotency: bool,
+) -> CapabilitiesResponse {
+    let models = available_models(config)
+        .into_iter()
+        .map(|m| ModelCapability {
+            context_window: get_context_window_size(&m.id),
+            max_output_tokens: m.max_tokens,
+            display_name: m.display_name,
+            id: m.id,
+        })
+        .collect();
+
+    let mut endpoints = vec![
+        "GET /v1/models",
+        "GET /v1/models/{id}",
+        "GET /v1/capabilities",
+        "POST /v1/messages",
+        "POST /v1/messages/count_tokens",
+        "DELETE /v1/messages/{id}",
+        "GET /v1/messages/{id}/events",
+        "POST /v1/chat/completions",
+        "POST /v1beta/models/{model}:generateContent",
+        "POST /v1beta/models/{model}:streamGenerateContent",
+        "POST /v1beta/models/{model}:countTokens",
+    ];
+    let mut betas_emulated = vec!["token-counting-2024-11-01", "prompt-caching-2024-07-31"];
+    if files {
+        endpoints.extend(["POST /v1/files", "GET /v1/files", "GET /v1/files/{file_id}"]);
+        betas_emulated.push("files-api-2025-04-14");
+    }
+    if message_batches {
+        endpoints.extend([
+            "POST /v1/messages/batches",
+            "GET /v1/messages/batches",
+            "GET /v1/messages/batches/{batch_id}",
+            "POST /v1/messages/batches/{batch_id}/cancel",
+            "GET /v1/messages/batches/{batch_id}/results",
+        ]);
+        betas_emulated.push("message-batches-2024-09-24");
+    }
+
+    CapabilitiesResponse {
+        schema_version: SCHEMA_VERSION,
+        server: ServerInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        },
+        models,
+        limits: Limits {
+            max_request_body_bytes: MAX_BODY_SIZE,
+            max_thinking_budget_tokens: MAX_BUDGET_TOKENS,
+        },
+        endpoints,
+        features: Features {
+            streaming: true,
+            thinking: true,
+            tools: true,
+            tool_choice: true,
+            images: true,
+            stop_sequences: true,
+            count_tokens: true,
+            files,
+            message_batches,
+            openai_chat_completions: true,
+            idempotency_keys: idempotency,
+            response_cache: config.response_cache.enabled,
+            long_polling: true,
+            request_cancellation: true,
+            stream_failover: config.resilience.stream_retries > 0,
+            prompt_injection_header: config.allow_inject_header,
+            model_override_header: model_override::is_allowed(config, client),
+            image_dedupe: config.image_dedupe,
+            image_downscale: config.image_downscale.enabled,
+            image_fetch: config.image_fetch.enabled,
+            auto_compact_history: config.auto_compact_history,
+            context_summary: config.context_summary.enabled,
+            fallback_backends: !config.fallback_backends.is_empty(),
+            request_validation: config.request_validation,
+            headers: FeatureHeaders {
+                transport: TRANSPORT_HEADER,
+                inject: INJECT_HEADER,
+                model_override: MODEL_OVERRIDE_HEADER,
+                idempotency: "idempotency-key",
+                cache: CACHE_HEADER,
+                credential_group: GROUP_HEADER,
+                request_id: REQUEST_ID_HEADER,
+            },
+        },
+        betas_emulated,
+        tools_intercepted: vec![InterceptedTool {
+            name: "web_search",
+            mode: websearch::resolve_mode(config, headers),
+        }],
+        auth: AuthInfo {
+            methods: vec!["x-api-key", "authorization-bearer"],
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_capabilities_reflect_config() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("trusted-key"));
+        let config = Config {
+            websearch_mode: WebSearchMode::Reject,
+            websearch_key_overrides: [("trusted-key".to_string(), WebSearchMode::Strip)].into(),
+            allow_inject_header: false,
+            ..Config::default()
+        };
+
+        let caps = build_capabilities(&config, None, &headers, false, false, true);
+        assert_eq!(caps.schema_version, SCHEMA_VERSION);
+        assert!(!caps.models.is_empty());
+        assert!(!caps.features.files);
+        assert!(!caps.features.prompt_injection_header);
+        assert!(!caps.features.model_override_header);
+        assert!(!caps.betas_emulated.contains(&"files-api-2025-04-14"));
+        assert_eq!(caps.tools_intercepted[0].mode, WebSearchMode::Strip);
+
+        let json = serde_json::to_value(build_capabilities(
+            &config,
+            None,
+            &HeaderMap::new(),
+            true,
+            true,
+            true,
+        ))
+        .unwrap();
+        assert_eq!(json["tools_intercepted"][0]["mode"], "reject");
+        assert_eq!(json["features"]["files"], true);
+        assert_eq!(json["features"]["message_batches"], true);
+        assert_eq!(json["limits"]["max_request_body_bytes"], MAX_BODY_SIZE);
+    }
+
+    #[test]
+    fn test_models_come_from_config() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "models": [{"id": "claude-sonnet-4-5", "maxTokens": 64000}]
+        }))
+        .unwrap();
+
+        let caps = build_capabilities(&config, None, &HeaderMap::new(), false, false, false);
+        assert_eq!(caps.models.len(), 1);
+        assert_eq!(caps.models[0].id, "claude-sonnet-4-5");
+        assert_eq!(caps.models[0].display_name, "claude-sonnet-4-5");
+        assert_eq!(caps.models[0].max_output_tokens, 64000);
+
+        let caps = build_capabilities(
+            &Config::default(),
+            None,
+            &HeaderMap::new(),
+            false,
+            false,
+            false,
+        );
+        assert_eq!(caps.models.len(), 3);
+    }
+}
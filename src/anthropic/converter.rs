@@ -2,20 +2,28 @@
 //!
 //! 负责将 Anthropic API 请求格式转换为 Kiro API 请求格式
 
+use std::collections::HashSet;
+
 use uuid::Uuid;
 
 use crate::kiro::model::requests::conversation::{
     AssistantMessage, ConversationState, CurrentMessage, HistoryAssistantMessage,
-    HistoryUserMessage, KiroImage, Message, UserInputMessage, UserInputMessageContext, UserMessage,
+    HistoryUserMessage, InferenceConfiguration, KiroImage, Message, UserInputMessage,
+    UserInputMessageContext, UserMessage,
 };
 use crate::kiro::model::requests::tool::{
     InputSchema, Tool, ToolResult, ToolSpecification, ToolUseEntry,
 };
 
+use crate::model::config::{SyntheticTurn, SystemPromptTemplate};
+
+use super::document;
+use super::system_prompt;
+use super::thinking_signature;
 use super::types::{ContentBlock, MessagesRequest, Thinking};
 
 /// 专业助手提示词（用于 Opus 请求增强）
-const PROFESSIONAL_SYSTEM_PROMPT: &str = r#"# 🧠 专业AI助手
+pub(crate) const PROFESSIONAL_SYSTEM_PROMPT: &str = r#"# 🧠 专业AI助手
 
 ## 🎭 角色定义
 AI时代的行业变革顾问 + 角色创造专家
@@ -114,6 +122,10 @@ pub fn map_model(model: &str) -> Option<String> {
 pub struct ConversionResult {
     /// 转换后的 Kiro 请求
     pub conversation_state: ConversationState,
+    /// 从 `metadata.user_id` 提取的会话 ID（用于凭据粘性绑定，随机生成时为 None）
+    pub session_id: Option<String>,
+    /// 历史开头由系统提示词与预置对话组成的消息数（自动压缩历史时保留）
+    pub pinned_history: usize,
 }
 
 /// 转换错误
@@ -121,6 +133,10 @@ pub struct ConversionResult {
 pub enum ConversionError {
     UnsupportedModel(String),
     EmptyMessages,
+    /// tool_choice 指定的工具不在 tools 列表中
+    UnknownToolChoice(String),
+    /// 采样参数超出取值范围
+    InvalidParameter(String),
 }
 
 impl std::fmt::Display for ConversionError {
@@ -128,6 +144,10 @@ impl std::fmt::Display for ConversionError {
         match self {
             ConversionError::UnsupportedModel(model) => write!(f, "模型不支持: {}", model),
             ConversionError::EmptyMessages => write!(f, "消息列表为空"),
+            ConversionError::UnknownToolChoice(name) => {
+                write!(f, "tool_choice 指定的工具不存在: {}", name)
+            }
+            ConversionError::InvalidParameter(msg) => write!(f, "参数无效: {}", msg),
         }
     }
 }
@@ -193,10 +213,26 @@ fn create_placeholder_tool(name: &str) -> Tool {
 }
 
 /// 将 Anthropic 请求转换为 Kiro 请求
-pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, ConversionError> {
-    // 1. 映射模型
-    let model_id = map_model(&req.model)
-        .ok_or_else(|| ConversionError::UnsupportedModel(req.model.clone()))?;
+///
+/// `model_override` 为请求头指定的 Kiro 模型 ID（由 `model_override` 模块解析，跳过模型映射），
+/// `injected_prompt` 为需要注入到系统消息前的提示词（由 `injection` 模块解析），
+/// `system_template` 为按模型匹配的系统提示词模板（由 `system_prompt` 模块解析），
+/// `synthetic_history` 为插入到真实消息之前的预置对话轮次
+#[tracing::instrument(skip_all)]
+pub fn convert_request(
+    req: &MessagesRequest,
+    model_override: Option<&str>,
+    injected_prompt: Option<&str>,
+    system_template: Option<&SystemPromptTemplate>,
+    synthetic_history: &[SyntheticTurn],
+) -> Result<ConversionResult, ConversionError> {
+    // 1. 映射模型（请求头指定的模型 ID 优先）
+    let model_id = match model_override {
+        Some(model_id) => model_id.to_string(),
+        None => map_model(&req.model)
+            .ok_or_else(|| ConversionError::UnsupportedModel(req.model.clone()))?,
+    };
+    let inference_config = inference_configuration(req)?;
 
     // 2. 检查消息列表
     if req.messages.is_empty() {
@@ -205,11 +241,13 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
 
     // 3. 生成会话 ID 和代理 ID
     // 优先从 metadata.user_id 中提取 session UUID 作为 conversationId
-    let conversation_id = req
+    let session_id = req
         .metadata
         .as_ref()
         .and_then(|m| m.user_id.as_ref())
-        .and_then(|user_id| extract_session_id(user_id))
+        .and_then(|user_id| extract_session_id(user_id));
+    let conversation_id = session_id
+        .clone()
         .unwrap_or_else(|| Uuid::new_v4().to_string());
     let agent_continuation_id = Uuid::new_v4().to_string();
 
@@ -220,11 +258,34 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
     let last_message = req.messages.last().unwrap();
     let (text_content, images, tool_results) = process_message_content(&last_message.content)?;
 
-    // 6. 转换工具定义
+    // 6. 转换工具定义，并按 tool_choice 筛选
+    let tool_choice = ToolChoice::parse(req.tool_choice.as_ref());
     let mut tools = convert_tools(&req.tools);
+    match &tool_choice {
+        ToolChoice::None => tools.clear(),
+        ToolChoice::Tool(name) => {
+            if !tools.iter().any(|t| &t.tool_specification.name == name) {
+                return Err(ConversionError::UnknownToolChoice(name.clone()));
+            }
+            // 仅保留强制使用的工具
+            tools.retain(|t| &t.tool_specification.name == name);
+        }
+        ToolChoice::Auto | ToolChoice::Any => {}
+    }
+    let tool_choice_hint = if tools.is_empty() {
+        None
+    } else {
+        tool_choice.hint()
+    };
 
     // 7. 构建历史消息（需要先构建，以便收集历史中使用的工具）
-    let history = build_history(req, &model_id)?;
+    let (history, pinned_history) = build_history(
+        req,
+        &model_id,
+        injected_prompt,
+        system_template,
+        synthetic_history,
+    )?;
 
     // 8. 验证并过滤 tool_use/tool_result 配对
     // 移除孤立的 tool_result（没有对应的 tool_use）
@@ -256,7 +317,12 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
 
     // 11. 构建当前消息
     // 保留文本内容，即使有工具结果也不丢弃用户文本
-    let content = text_content;
+    // Kiro 不支持 tool_choice，强制使用工具时在当前消息末尾追加提示
+    let content = match tool_choice_hint {
+        Some(hint) if text_content.is_empty() => hint,
+        Some(hint) => format!("{}\n\n{}", text_content, hint),
+        None => text_content,
+    };
 
     let mut user_input = UserInputMessage::new(content, &model_id)
         .with_context(context)
@@ -265,6 +331,9 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
     if !images.is_empty() {
         user_input = user_input.with_images(images);
     }
+    if !inference_config.is_empty() {
+        user_input = user_input.with_inference_configuration(inference_config);
+    }
 
     let current_message = CurrentMessage::new(user_input);
 
@@ -276,7 +345,158 @@ pub fn convert_request(req: &MessagesRequest) -> Result<ConversionResult, Conver
         .with_current_message(current_message)
         .with_history(history);
 
-    Ok(ConversionResult { conversation_state })
+    Ok(ConversionResult {
+        conversation_state,
+        session_id,
+        pinned_history,
+    })
+}
+
+/// 自动压缩历史的目标请求体大小（Kiro API 上限约 2MB，预留 profileArn 与省略提示的空间）
+pub const AUTO_COMPACT_MAX_BYTES: usize = 1_900_000;
+
+/// 从最早的真实对话轮次开始成对丢弃历史消息，直到序列化后的对话不超过 `max_bytes`
+///
+/// 开头的 `pinned` 条消息（系统提示词与预置对话）与最后一轮对话始终保留；
+/// 丢弃后失去对应 tool_use 的 tool_result 一并移除，并在保留的第一条用户消息前注明省略的消息数。
+/// 返回丢弃的消息数
+pub fn compact_history(state: &mut ConversationState, pinned: usize, max_bytes: usize) -> usize {
+    let mut body = json_len(state);
+    if body <= max_bytes {
+        return 0;
+    }
+
+    let mut dropped = 0;
+    while body > max_bytes && state.history.len() >= pinned + 4 {
+        // 每条消息在数组中另占一个逗号
+        body -= state
+            .history
+            .drain(pinned..pinned + 2)
+            .map(|m| json_len(&m) + 1)
+            .sum::<usize>();
+        dropped += 2;
+    }
+    if dropped == 0 {
+        return 0;
+    }
+
+    drop_orphan_tool_results(&mut state.history);
+    if let Some(Message::User(first)) = state.history.get_mut(pinned) {
+        let content = &mut first.user_input_message.content;
+        let note = format!(
+            "[{} earlier messages omitted to keep the request size under the upstream limit]",
+            dropped
+        );
+        *content = if content.is_empty() {
+            note
+        } else {
+            format!("{}\n\n{}", note, content)
+        };
+    }
+    dropped
+}
+
+/// 移除历史中找不到对应 tool_use 的 tool_result（如其 tool_use 所在的轮次已被丢弃或替换为摘要）
+pub(crate) fn drop_orphan_tool_results(history: &mut [Message]) {
+    let tool_use_ids: HashSet<String> = history
+        .iter()
+        .filter_map(|m| match m {
+            Message::Assistant(a) => a.assistant_response_message.tool_uses.as_ref(),
+            Message::User(_) => None,
+        })
+        .flatten()
+        .map(|t| t.tool_use_id.clone())
+        .collect();
+    for message in history {
+        if let Message::User(user) = message {
+            user.user_input_message
+                .user_input_message_context
+                .tool_results
+                .retain(|r| tool_use_ids.contains(&r.tool_use_id));
+        }
+    }
+}
+
+/// 序列化后的 JSON 字节数
+fn json_len<T: serde::Serialize>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(0, |v| v.len())
+}
+
+/// 校验采样参数并转换为 Kiro 推理参数
+fn inference_configuration(
+    req: &MessagesRequest,
+) -> Result<InferenceConfiguration, ConversionError> {
+    let in_unit_range = |name: &str, value: Option<f64>| match value {
+        Some(v) if !(0.0..=1.0).contains(&v) => Err(ConversionError::InvalidParameter(format!(
+            "{} 取值范围为 0.0 ~ 1.0，实际为 {}",
+            name, v
+        ))),
+        _ => Ok(value),
+    };
+
+    let temperature = in_unit_range("temperature", req.temperature)?;
+    let top_p = in_unit_range("top_p", req.top_p)?;
+    if let Some(top_k) = req.top_k
+        && top_k < 1
+    {
+        return Err(ConversionError::InvalidParameter(format!(
+            "top_k 必须大于 0，实际为 {}",
+            top_k
+        )));
+    }
+
+    Ok(InferenceConfiguration {
+        temperature,
+        top_p,
+        top_k: req.top_k,
+    })
+}
+
+/// 工具选择策略（Anthropic `tool_choice`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ToolChoice {
+    /// 由模型决定是否调用工具（默认）
+    Auto,
+    /// 必须调用任意工具
+    Any,
+    /// 必须调用指定工具
+    Tool(String),
+    /// 不允许调用工具
+    None,
+}
+
+impl ToolChoice {
+    /// 解析 tool_choice，未设置或无法识别时视为 auto
+    fn parse(value: Option<&serde_json::Value>) -> Self {
+        let Some(value) = value else {
+            return ToolChoice::Auto;
+        };
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("any") => ToolChoice::Any,
+            Some("none") => ToolChoice::None,
+            Some("tool") => value
+                .get("name")
+                .and_then(|n| n.as_str())
+                .map(|name| ToolChoice::Tool(name.to_string()))
+                .unwrap_or(ToolChoice::Any),
+            _ => ToolChoice::Auto,
+        }
+    }
+
+    /// 强制使用工具时追加到当前消息的提示
+    fn hint(&self) -> Option<String> {
+        match self {
+            ToolChoice::Any => Some(
+                "<tool_choice>You must respond by calling one of the available tools.</tool_choice>"
+                    .to_string(),
+            ),
+            ToolChoice::Tool(name) => Some(format!(
+                "<tool_choice>You must respond by calling the `{}` tool.</tool_choice>",
+                name
+            )),
+            ToolChoice::Auto | ToolChoice::None => None,
+        }
+    }
 }
 
 /// 确定聊天触发类型
@@ -299,6 +519,11 @@ fn process_message_content(
         }
         serde_json::Value::Array(arr) => {
             for item in arr {
+                // document 块的来源结构各异，直接按 JSON 处理并内联为文本
+                if item.get("type").and_then(|v| v.as_str()) == Some("document") {
+                    text_parts.push(document::document_text(item));
+                    continue;
+                }
                 if let Ok(block) = serde_json::from_value::<ContentBlock>(item.clone()) {
                     match block.block_type.as_str() {
                         "text" => {
@@ -308,7 +533,10 @@ fn process_message_content(
                         }
                         "image" => {
                             if let Some(source) = block.source {
-                                if let Some(format) = get_image_format(&source.media_type) {
+                                if let Some(url) = source.url {
+                                    // 未下载的 URL 图片（imageFetch 未启用）以文本引用的形式转发
+                                    text_parts.push(format!("[Image: {}]", url));
+                                } else if let Some(format) = get_image_format(&source.media_type) {
                                     images.push(KiroImage::from_base64(format, source.data));
                                 }
                             }
@@ -344,7 +572,7 @@ fn process_message_content(
 }
 
 /// 从 media_type 获取图片格式
-fn get_image_format(media_type: &str) -> Option<String> {
+pub(crate) fn get_image_format(media_type: &str) -> Option<String> {
     match media_type {
         "image/jpeg" => Some("jpeg".to_string()),
         "image/png" => Some("png".to_string()),
@@ -499,54 +727,61 @@ fn has_thinking_tags(content: &str) -> bool {
 }
 
 /// 构建历史消息
-fn build_history(req: &MessagesRequest, model_id: &str) -> Result<Vec<Message>, ConversionError> {
+fn build_history(
+    req: &MessagesRequest,
+    model_id: &str,
+    injected_prompt: Option<&str>,
+    system_template: Option<&SystemPromptTemplate>,
+    synthetic_history: &[SyntheticTurn],
+) -> Result<(Vec<Message>, usize), ConversionError> {
     let mut history = Vec::new();
 
     // 生成thinking前缀（如果需要）
     let thinking_prefix = generate_thinking_prefix(&req.thinking);
 
-    // 检查是否是 Opus 请求（需要注入专业提示词）
-    let is_opus_request = req.model.to_lowercase().contains("opus");
+    // 1. 处理系统消息（先应用按模型匹配的系统提示词模板）
+    let system_content: String = req
+        .system
+        .iter()
+        .flatten()
+        .map(|s| s.text.clone())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let system_content = match system_template {
+        Some(template) => system_prompt::apply(template, &system_content, &req.model),
+        None => system_content,
+    };
 
-    // 1. 处理系统消息
-    if let Some(ref system) = req.system {
-        let system_content: String = system
-            .iter()
-            .map(|s| s.text.clone())
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        if !system_content.is_empty() {
-            // 如果是 Opus 请求，在系统消息前注入专业提示词
-            let enhanced_content = if is_opus_request {
-                format!("{}\n\n---\n\n{}", PROFESSIONAL_SYSTEM_PROMPT, system_content)
-            } else {
-                system_content.clone()
-            };
+    if !system_content.is_empty() {
+        // 在系统消息前注入提示词（如果需要）
+        let enhanced_content = if let Some(prompt) = injected_prompt {
+            format!("{}\n\n---\n\n{}", prompt, system_content)
+        } else {
+            system_content.clone()
+        };
 
-            // 注入thinking标签到系统消息最前面（如果需要且不存在）
-            let final_content = if let Some(ref prefix) = thinking_prefix {
-                if !has_thinking_tags(&enhanced_content) {
-                    format!("{}\n{}", prefix, enhanced_content)
-                } else {
-                    enhanced_content
-                }
+        // 注入thinking标签到系统消息最前面（如果需要且不存在）
+        let final_content = if let Some(ref prefix) = thinking_prefix {
+            if !has_thinking_tags(&enhanced_content) {
+                format!("{}\n{}", prefix, enhanced_content)
             } else {
                 enhanced_content
-            };
+            }
+        } else {
+            enhanced_content
+        };
 
-            // 系统消息作为 user + assistant 配对
-            let user_msg = HistoryUserMessage::new(final_content, model_id);
-            history.push(Message::User(user_msg));
+        // 系统消息作为 user + assistant 配对
+        let user_msg = HistoryUserMessage::new(final_content, model_id);
+        history.push(Message::User(user_msg));
 
-            let assistant_msg = HistoryAssistantMessage::new("I will follow these instructions.");
-            history.push(Message::Assistant(assistant_msg));
-        }
+        let assistant_msg = HistoryAssistantMessage::new("I will follow these instructions.");
+        history.push(Message::Assistant(assistant_msg));
     } else if let Some(ref prefix) = thinking_prefix {
         // 没有系统消息但有thinking配置，插入新的系统消息
-        // 如果是 Opus 请求，也注入专业提示词
-        let content = if is_opus_request {
-            format!("{}\n\n{}", PROFESSIONAL_SYSTEM_PROMPT, prefix)
+        // 如果需要注入提示词，也一并注入
+        let content = if let Some(prompt) = injected_prompt {
+            format!("{}\n\n{}", prompt, prefix)
         } else {
             prefix.clone()
         };
@@ -556,16 +791,32 @@ fn build_history(req: &MessagesRequest, model_id: &str) -> Result<Vec<Message>,
 
         let assistant_msg = HistoryAssistantMessage::new("I will follow these instructions.");
         history.push(Message::Assistant(assistant_msg));
-    } else if is_opus_request {
-        // Opus 请求但没有系统消息和thinking配置，单独注入专业提示词
-        let user_msg = HistoryUserMessage::new(PROFESSIONAL_SYSTEM_PROMPT.to_string(), model_id);
+    } else if let Some(prompt) = injected_prompt {
+        // 没有系统消息和thinking配置，单独注入提示词
+        let user_msg = HistoryUserMessage::new(prompt.to_string(), model_id);
         history.push(Message::User(user_msg));
 
         let assistant_msg = HistoryAssistantMessage::new("I will follow these instructions.");
         history.push(Message::Assistant(assistant_msg));
     }
 
-    // 2. 处理常规消息历史
+    // 2. 插入预置对话轮次（跳过内容为空的轮次，上游不接受空消息）
+    for turn in synthetic_history {
+        if turn.user.trim().is_empty() || turn.assistant.trim().is_empty() {
+            continue;
+        }
+        history.push(Message::User(HistoryUserMessage::new(
+            turn.user.clone(),
+            model_id,
+        )));
+        history.push(Message::Assistant(HistoryAssistantMessage::new(
+            turn.assistant.clone(),
+        )));
+    }
+
+    let pinned = history.len();
+
+    // 3. 处理常规消息历史
     // 最后一条消息作为 currentMessage，不加入历史
     let history_end_index = req.messages.len().saturating_sub(1);
 
@@ -614,7 +865,7 @@ fn build_history(req: &MessagesRequest, model_id: &str) -> Result<Vec<Message>,
         history.push(Message::Assistant(auto_assistant));
     }
 
-    Ok(history)
+    Ok((history, pinned))
 }
 
 /// 合并多个 user 消息
@@ -654,6 +905,25 @@ fn merge_user_messages(
     })
 }
 
+/// redacted_thinking 块在 Kiro 历史中的占位标记
+///
+/// 块内容是只有 Anthropic 能解密的数据，转发给 Kiro 没有意义；保留标记使历史中的
+/// thinking 结构完整，也避免仅包含 redacted_thinking 的消息变成空 content
+const REDACTED_THINKING_MARKER: &str = "[redacted thinking]";
+
+/// 校验客户端回传的 thinking 签名
+///
+/// Kiro 历史只接受纯文本，签名本身无需转发；本服务格式的签名与内容不符说明 thinking
+/// 被客户端意外改动过，仅记录日志，内容照常转发（签名不带密钥，无法防止有意伪造）。
+/// 其他来源的签名（如 Anthropic 签发）无法校验，直接忽略
+fn check_thinking_signature(thinking: &str, signature: Option<&str>) {
+    if let Some(signature) = signature.filter(|s| thinking_signature::is_local(s))
+        && !thinking_signature::verify(thinking, signature)
+    {
+        tracing::warn!("thinking 块签名与内容不匹配，内容可能已被客户端修改");
+    }
+}
+
 /// 转换 assistant 消息
 fn convert_assistant_message(
     msg: &super::types::Message,
@@ -672,9 +942,13 @@ fn convert_assistant_message(
                     match block.block_type.as_str() {
                         "thinking" => {
                             if let Some(thinking) = block.thinking {
+                                check_thinking_signature(&thinking, block.signature.as_deref());
                                 thinking_content.push_str(&thinking);
                             }
                         }
+                        "redacted_thinking" => {
+                            thinking_content.push_str(REDACTED_THINKING_MARKER);
+                        }
                         "text" => {
                             if let Some(text) = block.text {
                                 text_content.push_str(&text);
@@ -776,6 +1050,10 @@ mod tests {
             tool_choice: None,
             thinking: None,
             metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
         assert_eq!(determine_chat_trigger_type(&req), "MANUAL");
     }
@@ -854,9 +1132,13 @@ mod tests {
             tool_choice: None,
             thinking: None,
             metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
-        let result = convert_request(&req).unwrap();
+        let result = convert_request(&req, None, None, None, &[]).unwrap();
 
         // 验证 tools 列表中包含了历史中使用的工具的占位符定义
         let tools = &result
@@ -873,6 +1155,123 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sampling_parameters() {
+        use super::super::types::Message as AnthropicMessage;
+
+        let request = |temperature, top_p, top_k| MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![AnthropicMessage {
+                role: "user".to_string(),
+                content: serde_json::json!("Hello"),
+            }],
+            stream: false,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature,
+            top_p,
+            top_k,
+        };
+        let inference = |req: &MessagesRequest| {
+            convert_request(req, None, None, None, &[])
+                .unwrap()
+                .conversation_state
+                .current_message
+                .user_input_message
+                .inference_configuration
+        };
+
+        assert_eq!(inference(&request(None, None, None)), None);
+
+        let config = inference(&request(Some(0.2), Some(0.9), Some(40))).unwrap();
+        assert_eq!(config.temperature, Some(0.2));
+        assert_eq!(config.top_p, Some(0.9));
+        assert_eq!(config.top_k, Some(40));
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["topP"], 0.9);
+
+        assert!(matches!(
+            convert_request(&request(Some(1.5), None, None), None, None, None, &[]),
+            Err(ConversionError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            convert_request(&request(None, None, Some(0)), None, None, None, &[]),
+            Err(ConversionError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn test_tool_choice_handling() {
+        use super::super::types::{Message as AnthropicMessage, Tool as AnthropicTool};
+
+        let tool = |name: &str| AnthropicTool {
+            tool_type: None,
+            name: name.to_string(),
+            description: format!("{} tool", name),
+            input_schema: Default::default(),
+            max_uses: None,
+            cache_control: None,
+        };
+        let request = |tool_choice: Option<serde_json::Value>| MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![AnthropicMessage {
+                role: "user".to_string(),
+                content: serde_json::json!("What's the weather?"),
+            }],
+            stream: false,
+            system: None,
+            tools: Some(vec![tool("get_weather"), tool("search")]),
+            tool_choice,
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        };
+        let convert = |tool_choice| {
+            let result = convert_request(&request(tool_choice), None, None, None, &[]).unwrap();
+            let input = result.conversation_state.current_message.user_input_message;
+            let names: Vec<_> = input
+                .user_input_message_context
+                .tools
+                .iter()
+                .map(|t| t.tool_specification.name.clone())
+                .collect();
+            (input.content, names)
+        };
+
+        let (content, names) = convert(Some(serde_json::json!({"type": "auto"})));
+        assert_eq!(content, "What's the weather?");
+        assert_eq!(names.len(), 2);
+
+        let (content, names) = convert(Some(serde_json::json!({"type": "none"})));
+        assert_eq!(content, "What's the weather?");
+        assert!(names.is_empty());
+
+        let (content, names) = convert(Some(serde_json::json!({"type": "any"})));
+        assert!(content.contains("calling one of the available tools"));
+        assert_eq!(names.len(), 2);
+
+        let (content, names) = convert(Some(
+            serde_json::json!({"type": "tool", "name": "get_weather"}),
+        ));
+        assert!(content.contains("`get_weather`"));
+        assert_eq!(names, vec!["get_weather".to_string()]);
+
+        let req = request(Some(serde_json::json!({"type": "tool", "name": "missing"})));
+        assert!(matches!(
+            convert_request(&req, None, None, None, &[]),
+            Err(ConversionError::UnknownToolChoice(_))
+        ));
+    }
+
     #[test]
     fn test_extract_session_id_valid() {
         // 测试有效的 user_id 格式
@@ -922,13 +1321,21 @@ mod tests {
                     "user_0dede55c6dcc4a11a30bbb5e7f22e6fdf86cdeba3820019cc27612af4e1243cd_account__session_a0662283-7fd3-4399-a7eb-52b9a717ae88".to_string(),
                 ),
             }),
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
-        let result = convert_request(&req).unwrap();
+        let result = convert_request(&req, None, None, None, &[]).unwrap();
         assert_eq!(
             result.conversation_state.conversation_id,
             "a0662283-7fd3-4399-a7eb-52b9a717ae88"
         );
+        assert_eq!(
+            result.session_id.as_deref(),
+            Some("a0662283-7fd3-4399-a7eb-52b9a717ae88")
+        );
     }
 
     #[test]
@@ -949,9 +1356,14 @@ mod tests {
             tool_choice: None,
             thinking: None,
             metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
-        let result = convert_request(&req).unwrap();
+        let result = convert_request(&req, None, None, None, &[]).unwrap();
+        assert_eq!(result.session_id, None);
         // 验证生成的是有效的 UUID 格式
         assert_eq!(result.conversation_state.conversation_id.len(), 36);
         assert_eq!(
@@ -965,6 +1377,232 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_synthetic_history_before_messages() {
+        use super::super::types::{Message as AnthropicMessage, SystemMessage};
+
+        let req = MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![
+                AnthropicMessage {
+                    role: "user".to_string(),
+                    content: serde_json::json!("first"),
+                },
+                AnthropicMessage {
+                    role: "assistant".to_string(),
+                    content: serde_json::json!("reply"),
+                },
+                AnthropicMessage {
+                    role: "user".to_string(),
+                    content: serde_json::json!("second"),
+                },
+            ],
+            stream: false,
+            system: Some(vec![SystemMessage {
+                text: "system prompt".to_string(),
+                cache_control: None,
+            }]),
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        };
+        let synthetic = [
+            SyntheticTurn {
+                user: "Always call tools with JSON input.".to_string(),
+                assistant: "Understood.".to_string(),
+            },
+            SyntheticTurn {
+                user: "   ".to_string(),
+                assistant: "skipped".to_string(),
+            },
+        ];
+
+        let result = convert_request(&req, None, None, None, &synthetic).unwrap();
+        let contents: Vec<&str> = result
+            .conversation_state
+            .history
+            .iter()
+            .map(|m| match m {
+                Message::User(u) => u.user_input_message.content.as_str(),
+                Message::Assistant(a) => a.assistant_response_message.content.as_str(),
+            })
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                "system prompt",
+                "I will follow these instructions.",
+                "Always call tools with JSON input.",
+                "Understood.",
+                "first",
+                "reply",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_model_override_skips_mapping() {
+        use super::super::types::Message as AnthropicMessage;
+
+        let req = MessagesRequest {
+            model: "gpt-4o".to_string(),
+            max_tokens: 1024,
+            messages: vec![AnthropicMessage {
+                role: "user".to_string(),
+                content: serde_json::json!("hi"),
+            }],
+            stream: false,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        };
+        assert!(matches!(
+            convert_request(&req, None, None, None, &[]),
+            Err(ConversionError::UnsupportedModel(_))
+        ));
+
+        let result = convert_request(&req, Some("claude-haiku-4.5"), None, None, &[]).unwrap();
+        assert_eq!(
+            result
+                .conversation_state
+                .current_message
+                .user_input_message
+                .model_id,
+            "claude-haiku-4.5"
+        );
+    }
+
+    #[test]
+    fn test_system_prompt_template_in_history() {
+        use super::super::types::{Message as AnthropicMessage, SystemMessage};
+
+        let mut req = MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![AnthropicMessage {
+                role: "user".to_string(),
+                content: serde_json::json!("hi"),
+            }],
+            stream: false,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        };
+        let template = SystemPromptTemplate {
+            prepend: Some("You are {model}.".to_string()),
+            append: Some("Answer briefly.".to_string()),
+        };
+        let first_history_content = |req: &MessagesRequest| {
+            let result = convert_request(req, None, None, Some(&template), &[]).unwrap();
+            match &result.conversation_state.history[0] {
+                Message::User(u) => u.user_input_message.content.clone(),
+                Message::Assistant(_) => panic!("expected system message pair"),
+            }
+        };
+
+        // 没有系统消息时，模板单独构成系统消息
+        assert_eq!(
+            first_history_content(&req),
+            "You are claude-sonnet-4.\n\nAnswer briefly."
+        );
+
+        req.system = Some(vec![SystemMessage {
+            text: "system prompt".to_string(),
+            cache_control: None,
+        }]);
+        assert_eq!(
+            first_history_content(&req),
+            "You are claude-sonnet-4.\n\nsystem prompt\n\nAnswer briefly."
+        );
+    }
+
+    #[test]
+    fn test_compact_history_drops_oldest_turns() {
+        use super::super::types::{Message as AnthropicMessage, SystemMessage};
+
+        let message = |role: &str, content: serde_json::Value| AnthropicMessage {
+            role: role.to_string(),
+            content,
+        };
+        let req = MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![
+                message("user", serde_json::json!("a".repeat(10_000))),
+                message(
+                    "assistant",
+                    serde_json::json!([{"type": "tool_use", "id": "t1", "name": "read", "input": {}}]),
+                ),
+                message(
+                    "user",
+                    serde_json::json!([
+                        {"type": "tool_result", "tool_use_id": "t1", "content": "ok"},
+                        {"type": "text", "text": "b".repeat(10_000)}
+                    ]),
+                ),
+                message("assistant", serde_json::json!("done")),
+                message("user", serde_json::json!("c")),
+                message("assistant", serde_json::json!("d")),
+                message("user", serde_json::json!("current")),
+            ],
+            stream: false,
+            system: Some(vec![SystemMessage {
+                text: "system prompt".to_string(),
+                cache_control: None,
+            }]),
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        };
+        let result = convert_request(&req, None, None, None, &[]).unwrap();
+        assert_eq!(result.pinned_history, 2);
+        let mut state = result.conversation_state;
+        let size = json_len(&state);
+
+        // 未超过上限时不做改动
+        assert_eq!(compact_history(&mut state, 2, size), 0);
+        assert_eq!(state.history.len(), 8);
+
+        // 丢弃最早的一轮后，失去 tool_use 的 tool_result 一并移除，系统提示词保留
+        assert_eq!(compact_history(&mut state, 2, size - 5_000), 2);
+        assert_eq!(state.history.len(), 6);
+        let Message::User(first) = &state.history[2] else {
+            panic!("expected user message");
+        };
+        let first = &first.user_input_message;
+        assert!(first.content.starts_with("[2 earlier messages omitted"));
+        assert!(first.user_input_message_context.tool_results.is_empty());
+        assert!(json_len(&state) <= size - 5_000);
+
+        // 最后一轮对话始终保留
+        assert_eq!(compact_history(&mut state, 2, 0), 2);
+        assert_eq!(state.history.len(), 4);
+        assert_eq!(compact_history(&mut state, 2, 0), 0);
+    }
+
     #[test]
     fn test_validate_tool_pairing_orphaned_result() {
         // 测试孤立的 tool_result 被过滤
@@ -1225,4 +1863,76 @@ mod tests {
         assert_eq!(tool_uses.len(), 1);
         assert_eq!(tool_uses[0].tool_use_id, "toolu_02XYZ");
     }
+
+    #[test]
+    fn test_convert_assistant_message_keeps_redacted_thinking_marker() {
+        use super::super::types::Message as AnthropicMessage;
+
+        // 仅包含 redacted_thinking 的消息不能转换为空 content
+        let msg = AnthropicMessage {
+            role: "assistant".to_string(),
+            content: serde_json::json!([
+                {"type": "redacted_thinking", "data": "EmwKAhgBEgy3va3pzix"}
+            ]),
+        };
+        let result = convert_assistant_message(&msg).unwrap();
+        assert_eq!(
+            result.assistant_response_message.content,
+            "<thinking>[redacted thinking]</thinking>"
+        );
+
+        let msg = AnthropicMessage {
+            role: "assistant".to_string(),
+            content: serde_json::json!([
+                {"type": "thinking", "thinking": "Let me check. ", "signature": "sig"},
+                {"type": "redacted_thinking", "data": "EmwKAhgBEgy3va3pzix"},
+                {"type": "text", "text": "Done."}
+            ]),
+        };
+        let result = convert_assistant_message(&msg).unwrap();
+        assert_eq!(
+            result.assistant_response_message.content,
+            "<thinking>Let me check. [redacted thinking]</thinking>\n\nDone."
+        );
+    }
+
+    #[test]
+    fn test_thinking_signature_round_trip() {
+        use super::super::types::Message as AnthropicMessage;
+
+        // 客户端回传流式输出中签发的签名：thinking 块与签名都保留
+        let signature = thinking_signature::sign("Let me check.");
+        let block = serde_json::json!(
+            {"type": "thinking", "thinking": "Let me check.", "signature": signature}
+        );
+        let parsed: ContentBlock = serde_json::from_value(block.clone()).unwrap();
+        assert_eq!(parsed.signature.as_deref(), Some(signature.as_str()));
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), block);
+
+        let msg = AnthropicMessage {
+            role: "assistant".to_string(),
+            content: serde_json::json!([block, {"type": "text", "text": "Done."}]),
+        };
+        let result = convert_assistant_message(&msg).unwrap();
+        assert_eq!(
+            result.assistant_response_message.content,
+            "<thinking>Let me check.</thinking>\n\nDone."
+        );
+    }
+
+    #[test]
+    fn test_process_message_content_inlines_document() {
+        let content = serde_json::json!([
+            {"type": "document", "title": "notes", "source": {"type": "text", "media_type": "text/plain", "data": "Revenue grew."}},
+            {"type": "text", "text": "Summarize"}
+        ]);
+
+        let (text, images, tool_results) = process_message_content(&content).unwrap();
+        assert_eq!(
+            text,
+            "<document title=\"notes\">\nRevenue grew.\n</document>\nSummarize"
+        );
+        assert!(images.is_empty());
+        assert!(tool_results.is_empty());
+    }
 }
@@ -0,0 +1,89 @@
+//! 凭据分组选择
+//!
+//! 凭据可通过 `tags` 打上分组标签（如 `work`、`personal`）。请求使用哪个分组：
+//! - 客户端 Key 配置了 `group` 时固定使用该分组（请求头无法越权切换）
+//! - 否则可通过 `x-kiro-group` 请求头指定
+//! - 都未指定时不限制分组
+
+use axum::http::HeaderMap;
+
+use crate::common::api_keys::ClientKey;
+use crate::kiro::token_manager::MultiTokenManager;
+
+/// 指定凭据分组的请求头
+pub const GROUP_HEADER: &str = "x-kiro-group";
+
+/// 解析本次请求使用的凭据分组
+///
+/// 指定的分组下没有任何凭据时返回 `Err(分组名)`
+pub fn resolve(
+    client: Option<&ClientKey>,
+    headers: &HeaderMap,
+    token_manager: &MultiTokenManager,
+) -> Result<Option<String>, String> {
+    let group = match client.and_then(|c| c.group.clone()) {
+        Some(group) => group,
+        None => match headers
+            .get(GROUP_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+        {
+            Some(group) => group.to_string(),
+            None => return Ok(None),
+        },
+    };
+    if token_manager.has_group(&group) {
+        Ok(Some(group))
+    } else {
+        Err(group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use axum::http::HeaderValue;
+
+    use crate::common::rate_limit::KeyRateLimiter;
+    use crate::kiro::model::credentials::KiroCredentials;
+    use crate::model::config::{Config, RateLimitConfig};
+
+    #[test]
+    fn test_resolve_prefers_client_group() {
+        let creds = ["work", "personal"]
+            .map(|tag| KiroCredentials {
+                tags: vec![tag.to_string()],
+                ..Default::default()
+            })
+            .to_vec();
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+        let client = |group: Option<&str>| ClientKey {
+            name: "team".to_string(),
+            limiter: Arc::new(KeyRateLimiter::new(&RateLimitConfig::default())),
+            group: group.map(str::to_string),
+            priority: Default::default(),
+            quota: Default::default(),
+            mcp_tools: false,
+            model_override: false,
+        };
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(resolve(None, &headers, &manager), Ok(None));
+
+        headers.insert(GROUP_HEADER, HeaderValue::from_static("personal"));
+        assert_eq!(
+            resolve(Some(&client(None)), &headers, &manager),
+            Ok(Some("personal".to_string()))
+        );
+        assert_eq!(
+            resolve(Some(&client(Some("work"))), &headers, &manager),
+            Ok(Some("work".to_string()))
+        );
+
+        headers.insert(GROUP_HEADER, HeaderValue::from_static("idc"));
+        assert_eq!(resolve(None, &headers, &manager), Err("idc".to_string()));
+    }
+}
@@ -0,0 +1,307 @@
+//! document 内容块
+//!
+//! Kiro 上游不支持文档附件，document 块在转换时内联为文本：
+//!
+//! - `text` 来源：直接使用其中的文本
+//! - `content` 来源：拼接其中的文本块
+//! - `base64` PDF：提取未压缩内容流中的文本；压缩流、扫描件等无法提取时，
+//!   内联包含页数与大小的占位说明，避免附件被静默丢弃
+//! - `url` 来源：内联包含 URL 的占位说明
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde_json::Value;
+
+/// 将 document 内容块转换为内联文本（`<document>` 标签包裹）
+pub fn document_text(block: &Value) -> String {
+    let source = block.get("source").unwrap_or(&Value::Null);
+    let str_field =
+        |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+
+    let body = match source.get("type").and_then(Value::as_str) {
+        Some("text") => str_field(source, "data").unwrap_or_default(),
+        Some("content") => match source.get("content") {
+            Some(Value::String(text)) => text.clone(),
+            Some(Value::Array(blocks)) => blocks
+                .iter()
+                .filter_map(|b| b.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        },
+        Some("base64") => {
+            let media_type = str_field(source, "media_type").unwrap_or_default();
+            let data = str_field(source, "data").unwrap_or_default();
+            match BASE64.decode(data.as_bytes()) {
+                Ok(bytes) if media_type == "application/pdf" => pdf_text(&bytes),
+                Ok(bytes) => match String::from_utf8(bytes) {
+                    Ok(text) => text,
+                    Err(e) => placeholder(&format!(
+                        "{} document, {} bytes",
+                        media_type,
+                        e.as_bytes().len()
+                    )),
+                },
+                Err(_) => placeholder(&format!("{} document with invalid base64 data", media_type)),
+            }
+        }
+        Some("url") => placeholder(&format!(
+            "document at {}",
+            str_field(source, "url").unwrap_or_default()
+        )),
+        other => placeholder(&format!(
+            "document with unsupported source type {}",
+            other.unwrap_or("(missing)")
+        )),
+    };
+
+    let mut text = match str_field(block, "title") {
+        Some(title) => format!("<document title=\"{}\">\n", title),
+        None => "<document>\n".to_string(),
+    };
+    if let Some(context) = str_field(block, "context") {
+        text.push_str(&context);
+        text.push_str("\n\n");
+    }
+    text.push_str(&body);
+    text.push_str("\n</document>");
+    text
+}
+
+/// 无法提取内容时的占位说明
+fn placeholder(description: &str) -> String {
+    tracing::info!("document 内容块无法提取文本，使用占位说明: {}", description);
+    format!(
+        "[Attached {}. The proxy could not extract its text, so the content is not available.]",
+        description
+    )
+}
+
+/// 提取 PDF 中未压缩内容流的文本，提取不到时返回占位说明
+fn pdf_text(bytes: &[u8]) -> String {
+    let mut lines = Vec::new();
+    let mut rest = bytes;
+    while let Some(start) = find(rest, b"stream") {
+        // 流字典位于对象开头（`obj`）与 `stream` 关键字之间，带 /Filter 的流已压缩，无法直接读取
+        let dictionary = &rest[rfind(&rest[..start], b"obj").unwrap_or(0)..start];
+        let body_start = start + b"stream".len();
+        let Some(len) = find(&rest[body_start..], b"endstream") else {
+            break;
+        };
+        let body = &rest[body_start..body_start + len];
+        if find(dictionary, b"/Filter").is_none() {
+            lines.extend(content_stream_text(body));
+        }
+        rest = &rest[body_start + len + b"endstream".len()..];
+    }
+
+    let text = lines.join("\n");
+    if text.trim().is_empty() {
+        let pages = count_pages(bytes);
+        placeholder(&format!(
+            "PDF document ({} pages, {} bytes)",
+            pages,
+            bytes.len()
+        ))
+    } else {
+        text
+    }
+}
+
+/// 提取内容流中文本显示操作符（Tj / TJ / ' / "）的字符串
+fn content_stream_text(stream: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut pending = String::new();
+    let mut i = 0;
+    while i < stream.len() {
+        match stream[i] {
+            b'(' => {
+                let (text, end) = literal_string(stream, i + 1);
+                pending.push_str(&text);
+                i = end;
+            }
+            b'%' => {
+                while i < stream.len() && stream[i] != b'\n' && stream[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == b'\'' || c == b'"' || c == b'*' => {
+                let start = i;
+                while i < stream.len()
+                    && (stream[i].is_ascii_alphabetic() || matches!(stream[i], b'*' | b'\'' | b'"'))
+                {
+                    i += 1;
+                }
+                match &stream[start..i] {
+                    b"Tj" | b"TJ" => line.push_str(&std::mem::take(&mut pending)),
+                    b"'" | b"\"" => {
+                        lines.push(std::mem::take(&mut line));
+                        line.push_str(&std::mem::take(&mut pending));
+                    }
+                    b"T*" | b"Td" | b"TD" | b"ET" => {
+                        if !line.is_empty() {
+                            lines.push(std::mem::take(&mut line));
+                        }
+                    }
+                    _ => pending.clear(),
+                }
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// 解析字面量字符串（`(` 之后开始），返回文本与结束位置（`)` 之后）
+fn literal_string(stream: &[u8], mut i: usize) -> (String, usize) {
+    let mut bytes = Vec::new();
+    let mut depth = 0;
+    while i < stream.len() {
+        match stream[i] {
+            b'\\' if i + 1 < stream.len() => {
+                i += 1;
+                match stream[i] {
+                    b'n' => bytes.push(b'\n'),
+                    b'r' => bytes.push(b'\r'),
+                    b't' => bytes.push(b'\t'),
+                    b'b' | b'f' => {}
+                    d @ b'0'..=b'7' => {
+                        let mut value = u32::from(d - b'0');
+                        for _ in 0..2 {
+                            match stream.get(i + 1) {
+                                Some(&d @ b'0'..=b'7') => {
+                                    value = value * 8 + u32::from(d - b'0');
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        bytes.push(value as u8);
+                    }
+                    b'\r' | b'\n' => {}
+                    other => bytes.push(other),
+                }
+            }
+            b'(' => {
+                depth += 1;
+                bytes.push(b'(');
+            }
+            b')' if depth == 0 => return (decode_pdf_string(&bytes), i + 1),
+            b')' => {
+                depth -= 1;
+                bytes.push(b')');
+            }
+            b => bytes.push(b),
+        }
+        i += 1;
+    }
+    (decode_pdf_string(&bytes), i)
+}
+
+/// 解码 PDF 字符串：带 BOM 的 UTF-16BE，否则按 Latin-1 处理
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+/// 统计页数（`/Type /Page` 对象数量，不含 `/Pages`）
+fn count_pages(bytes: &[u8]) -> usize {
+    let mut count = 0;
+    let mut rest = bytes;
+    while let Some(pos) = find(rest, b"/Type") {
+        rest = &rest[pos + b"/Type".len()..];
+        let value = rest.trim_ascii_start();
+        if value.starts_with(b"/Page") && !value.starts_with(b"/Pages") {
+            count += 1;
+        }
+    }
+    count
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SIMPLE_PDF: &[u8] = b"%PDF-1.4
+1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 >> endobj
+3 0 obj << /Type /Page /Parent 2 0 R /Contents 4 0 R >> endobj
+4 0 obj << /Length 60 >>
+stream
+BT /F1 12 Tf 72 720 Td (Hello \\(PDF\\)) Tj T* [(Wor) -20 (ld)] TJ ET
+endstream
+endobj
+%%EOF";
+
+    fn base64_document(media_type: &str, data: &[u8]) -> Value {
+        json!({
+            "type": "document",
+            "title": "report",
+            "source": {"type": "base64", "media_type": media_type, "data": BASE64.encode(data)}
+        })
+    }
+
+    #[test]
+    fn test_extracts_uncompressed_pdf_text() {
+        let text = document_text(&base64_document("application/pdf", SIMPLE_PDF));
+        assert_eq!(
+            text,
+            "<document title=\"report\">\nHello (PDF)\nWorld\n</document>"
+        );
+    }
+
+    #[test]
+    fn test_compressed_pdf_placeholder() {
+        let pdf = b"%PDF-1.7
+3 0 obj << /Type /Page >> endobj
+5 0 obj << /Type /Page >> endobj
+4 0 obj << /Length 3 /Filter /FlateDecode >>
+stream
+xyz
+endstream";
+        let text = document_text(&base64_document("application/pdf", pdf));
+        assert!(text.contains("PDF document (2 pages,"));
+        assert!(text.contains("could not extract"));
+    }
+
+    #[test]
+    fn test_text_and_url_sources() {
+        let text = document_text(&json!({
+            "type": "document",
+            "context": "Quarterly notes",
+            "source": {"type": "text", "media_type": "text/plain", "data": "Revenue grew."}
+        }));
+        assert_eq!(
+            text,
+            "<document>\nQuarterly notes\n\nRevenue grew.\n</document>"
+        );
+
+        let text = document_text(&json!({
+            "type": "document",
+            "source": {"type": "url", "url": "https://example.com/a.pdf"}
+        }));
+        assert!(text.contains("document at https://example.com/a.pdf"));
+    }
+}
@@ -0,0 +1,350 @@
+//! 流式事件缓冲区
+//!
+//! 为无法使用 SSE 的环境（如会改写 server-sent events 的企业代理）提供长轮询回退：
+//! 生成过程在后台运行，事件按顺序缓冲，客户端通过
+//! `GET /v1/messages/{id}/events?cursor=` 拉取增量事件。
+//! 缓冲区归属创建它的客户端 API Key，其他 Key 无法读取或占用同一消息 ID
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::Notify;
+
+/// 选择长轮询传输的请求头（值为 `poll`）
+pub const TRANSPORT_HEADER: &str = "x-kiro-transport";
+
+/// 生成结束后缓冲区的保留时间
+const RETENTION: Duration = Duration::from_secs(10 * 60);
+
+/// 同时保留的缓冲区数量上限
+const MAX_STREAMS: usize = 1024;
+
+/// 长轮询默认等待时间（秒）
+pub const DEFAULT_WAIT_SECS: u64 = 25;
+
+/// 长轮询最大等待时间（秒）
+pub const MAX_WAIT_SECS: u64 = 60;
+
+/// 缓冲的单个事件
+#[derive(Debug, Clone, Serialize)]
+pub struct BufferedEvent {
+    /// 事件类型（与 SSE 的 event 字段一致）
+    pub event: String,
+    /// 事件数据
+    pub data: serde_json::Value,
+}
+
+/// 一次轮询的结果
+#[derive(Debug, Serialize)]
+pub struct PollResult {
+    /// 消息 ID
+    pub id: String,
+    /// 自 cursor 起的新事件
+    pub events: Vec<BufferedEvent>,
+    /// 下一次轮询使用的 cursor
+    pub cursor: usize,
+    /// 生成是否已结束（结束后不会再有新事件）
+    pub done: bool,
+}
+
+/// 创建缓冲区失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateError {
+    /// 消息 ID 已被其他客户端的缓冲区占用
+    Conflict,
+    /// 缓冲区数量已达上限且没有已结束的缓冲可以淘汰
+    Full,
+}
+
+/// 单次生成的事件缓冲
+pub struct BufferedStream {
+    owner: String,
+    events: Mutex<Vec<BufferedEvent>>,
+    done: AtomicBool,
+    finished_at: Mutex<Option<Instant>>,
+    notify: Notify,
+}
+
+impl BufferedStream {
+    fn new(owner: &str) -> Self {
+        Self {
+            owner: owner.to_string(),
+            events: Mutex::new(Vec::new()),
+            done: AtomicBool::new(false),
+            finished_at: Mutex::new(None),
+            notify: Notify::new(),
+        }
+    }
+
+    /// 追加一段 SSE 输出（可能包含多个事件，ping 事件会被忽略）
+    pub fn push_chunk(&self, chunk: &Bytes) {
+        let text = String::from_utf8_lossy(chunk);
+        let parsed = parse_sse_events(&text);
+        if parsed.is_empty() {
+            return;
+        }
+        self.events.lock().extend(parsed);
+        self.notify.notify_waiters();
+    }
+
+    /// 标记生成结束
+    pub fn finish(&self) {
+        self.done.store(true, Ordering::Release);
+        *self.finished_at.lock() = Some(Instant::now());
+        self.notify.notify_waiters();
+    }
+
+    fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.finished_at
+            .lock()
+            .is_some_and(|at| now.duration_since(at) >= RETENTION)
+    }
+
+    fn finished_at(&self) -> Option<Instant> {
+        *self.finished_at.lock()
+    }
+
+    /// 读取 cursor 之后的事件
+    fn read_from(&self, cursor: usize) -> (Vec<BufferedEvent>, usize) {
+        let events = self.events.lock();
+        let start = cursor.min(events.len());
+        (events[start..].to_vec(), events.len())
+    }
+}
+
+/// 事件缓冲区（按消息 ID 索引）
+#[derive(Default)]
+pub struct EventBuffer {
+    streams: Mutex<HashMap<String, Arc<BufferedStream>>>,
+}
+
+impl EventBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 为新的生成创建缓冲，同时清理已过期的缓冲
+    ///
+    /// 同一客户端可以替换自己的旧缓冲；达到数量上限时淘汰最早结束的缓冲，
+    /// 全部仍在生成时拒绝创建
+    pub fn create(&self, owner: &str, id: &str) -> Result<Arc<BufferedStream>, CreateError> {
+        let mut streams = self.streams.lock();
+        let now = Instant::now();
+        streams.retain(|_, s| !s.is_expired(now));
+        match streams.get(id) {
+            Some(existing) if existing.owner != owner => return Err(CreateError::Conflict),
+            Some(_) => {}
+            None if streams.len() >= MAX_STREAMS => {
+                let key = streams
+                    .iter()
+                    .filter_map(|(k, s)| s.finished_at().map(|at| (k, at)))
+                    .min_by_key(|(_, at)| *at)
+                    .map(|(k, _)| k.clone())
+                    .ok_or(CreateError::Full)?;
+                streams.remove(&key);
+            }
+            None => {}
+        }
+        let stream = Arc::new(BufferedStream::new(owner));
+        streams.insert(id.to_string(), stream.clone());
+        Ok(stream)
+    }
+
+    /// 长轮询读取事件
+    ///
+    /// 有新事件或生成已结束时立即返回，否则最多等待 `wait`；
+    /// 消息 ID 不存在（或已过期）、或缓冲区不属于该客户端时返回 None
+    pub async fn poll(
+        &self,
+        owner: &str,
+        id: &str,
+        cursor: usize,
+        wait: Duration,
+    ) -> Option<PollResult> {
+        let stream = self
+            .streams
+            .lock()
+            .get(id)
+            .filter(|s| s.owner == owner)
+            .cloned()?;
+        let deadline = tokio::time::Instant::now() + wait;
+
+        loop {
+            // 先注册通知再检查状态，避免错过检查与等待之间到达的事件
+            let notified = stream.notify.notified();
+            let done = stream.is_done();
+            let (events, next_cursor) = stream.read_from(cursor);
+            if !events.is_empty() || done {
+                return Some(PollResult {
+                    id: id.to_string(),
+                    events,
+                    cursor: next_cursor,
+                    done,
+                });
+            }
+
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return Some(PollResult {
+                    id: id.to_string(),
+                    events: Vec::new(),
+                    cursor: next_cursor,
+                    done: false,
+                });
+            }
+        }
+    }
+}
+
+/// 解析 SSE 文本中的事件（忽略注释行和 ping 事件）
+fn parse_sse_events(text: &str) -> Vec<BufferedEvent> {
+    let mut events = Vec::new();
+    for block in text.split("\n\n") {
+        let mut event = None;
+        let mut data = String::new();
+        for line in block.lines() {
+            if let Some(value) = line.strip_prefix("event:") {
+                event = Some(value.trim().to_string());
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data.push_str(value.trim_start());
+            }
+        }
+        let Some(event) = event else {
+            continue;
+        };
+        if event == "ping" {
+            continue;
+        }
+        let data = serde_json::from_str(&data).unwrap_or(serde_json::Value::String(data));
+        events.push(BufferedEvent { event, data });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sse_events() {
+        let text = ": comment\n\nevent: message_start\ndata: {\"type\":\"message_start\"}\n\nevent: ping\ndata: {\"type\": \"ping\"}\n\nevent: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+        let events = parse_sse_events(text);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, "message_start");
+        assert_eq!(events[0].data["type"], "message_start");
+        assert_eq!(events[1].event, "message_stop");
+    }
+
+    #[tokio::test]
+    async fn test_poll_returns_new_events_and_done() {
+        let buffer = EventBuffer::new();
+        let stream = buffer.create("alice", "msg_1").unwrap();
+        stream.push_chunk(&Bytes::from(
+            "event: message_start\ndata: {\"type\":\"message_start\"}\n\n",
+        ));
+
+        let result = buffer
+            .poll("alice", "msg_1", 0, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(result.cursor, 1);
+        assert!(!result.done);
+
+        // 无新事件时等待超时返回空结果
+        let result = buffer
+            .poll("alice", "msg_1", 1, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert!(result.events.is_empty());
+        assert_eq!(result.cursor, 1);
+
+        stream.finish();
+        let result = buffer
+            .poll("alice", "msg_1", 1, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert!(result.done);
+
+        assert!(
+            buffer
+                .poll("alice", "missing", 0, Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_wakes_on_new_event() {
+        let buffer = Arc::new(EventBuffer::new());
+        let stream = buffer.create("alice", "msg_2").unwrap();
+
+        let poller = {
+            let buffer = buffer.clone();
+            tokio::spawn(async move {
+                buffer
+                    .poll("alice", "msg_2", 0, Duration::from_secs(5))
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stream.push_chunk(&Bytes::from(
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\"}\n\n",
+        ));
+
+        let result = poller.await.unwrap().unwrap();
+        assert_eq!(result.events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_buffer_scoped_to_owner() {
+        let buffer = EventBuffer::new();
+        let stream = buffer.create("alice", "job-1").unwrap();
+        stream.finish();
+
+        assert!(
+            buffer
+                .poll("bob", "job-1", 0, Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+        assert_eq!(
+            buffer.create("bob", "job-1").err(),
+            Some(CreateError::Conflict)
+        );
+        assert!(
+            buffer
+                .poll("alice", "job-1", 0, Duration::from_millis(10))
+                .await
+                .is_some()
+        );
+        assert!(buffer.create("alice", "job-1").is_ok());
+    }
+
+    #[test]
+    fn test_create_at_capacity() {
+        let buffer = EventBuffer::new();
+        for i in 0..MAX_STREAMS {
+            buffer.create("alice", &format!("msg_{}", i)).unwrap();
+        }
+        assert_eq!(
+            buffer.create("alice", "msg_new").err(),
+            Some(CreateError::Full)
+        );
+
+        // 有已结束的缓冲时淘汰它，数量不超过上限
+        buffer.streams.lock()["msg_7"].finish();
+        assert!(buffer.create("alice", "msg_new").is_ok());
+        let streams = buffer.streams.lock();
+        assert_eq!(streams.len(), MAX_STREAMS);
+        assert!(!streams.contains_key("msg_7"));
+    }
+}
@@ -0,0 +1,285 @@
+//! 备用后端
+//!
+//! 所有 Kiro 凭据用尽或被上游限流时，`/v1/messages` 请求按 `fallbackBackends` 的顺序
+//! 转发到备用后端（Anthropic API 或其他 kiro.rs 实例），响应原样返回给客户端：
+//! - 请求模型按各后端的 `models` 映射替换
+//! - 后端返回 429 / 5xx 或连接失败时尝试下一个后端，其他响应直接返回
+//! - 全部失败时返回原始的 Kiro 错误
+//!
+//! 后端列表每次请求时从配置读取，修改后热重载生效
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue},
+    response::Response,
+};
+use reqwest::Client;
+use serde_json::{Value, json};
+
+use super::types::MessagesRequest;
+use crate::http_client::{ProxyConfig, build_client};
+use crate::kiro::error::{CredentialsUnavailable, KiroApiError};
+use crate::model::config::{Config, FallbackBackend};
+
+/// 标记响应头：实际处理请求的备用后端名称
+pub const FALLBACK_HEADER: &str = "x-kiro-fallback";
+
+/// 备用后端请求超时（秒），与上游流式请求一致
+const FALLBACK_TIMEOUT_SECS: u64 = 720;
+
+/// 默认的 anthropic-version
+const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// 原样转发给备用后端的客户端请求头
+const FORWARDED_HEADERS: &[&str] = &["anthropic-version", "anthropic-beta"];
+
+/// 错误响应扩展：该错误由 Kiro 凭据用尽或限流导致，可以转发到备用后端
+#[derive(Debug, Clone, Copy)]
+pub struct KiroExhausted;
+
+/// 判断上游错误是否应转发到备用后端
+pub fn is_exhausted(e: &anyhow::Error) -> bool {
+    if e.downcast_ref::<CredentialsUnavailable>().is_some() {
+        return true;
+    }
+    e.downcast_ref::<KiroApiError>().is_some_and(|err| {
+        err.credentials_exhausted || err.is_overloaded() || err.status.as_u16() == 429
+    })
+}
+
+/// 备用后端转发器
+pub struct FallbackChain {
+    client: Client,
+}
+
+impl FallbackChain {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let client = build_client(
+            ProxyConfig::from_config(config).as_ref(),
+            FALLBACK_TIMEOUT_SECS,
+            config.tls_backend,
+        )?;
+        Ok(Self { client })
+    }
+
+    /// 按顺序转发到备用后端，全部失败（或未配置后端）时返回 None
+    pub async fn forward(
+        &self,
+        backends: &[FallbackBackend],
+        payload: &MessagesRequest,
+        headers: &HeaderMap,
+    ) -> Option<Response> {
+        for backend in backends {
+            let model = backend.map_model(&payload.model);
+            let url = format!("{}/v1/messages", backend.base_url.trim_end_matches('/'));
+            let mut request = self
+                .client
+                .post(&url)
+                .header("x-api-key", &backend.api_key)
+                .header("anthropic-version", DEFAULT_ANTHROPIC_VERSION)
+                .json(&request_body(payload, model));
+            for name in FORWARDED_HEADERS {
+                if let Some(value) = headers.get(*name) {
+                    request = request.header(*name, value);
+                }
+            }
+
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::warn!("备用后端 {} 请求失败: {}", backend.name, e);
+                    continue;
+                }
+            };
+            let status = response.status();
+            if status.as_u16() == 429 || status.is_server_error() {
+                tracing::warn!("备用后端 {} 返回 {}，尝试下一个后端", backend.name, status);
+                continue;
+            }
+            tracing::info!(
+                "Kiro 凭据不可用，请求已转发到备用后端 {}（模型: {}，状态: {}）",
+                backend.name,
+                model,
+                status
+            );
+            return Some(into_response(&backend.name, response));
+        }
+        None
+    }
+}
+
+/// 将后端响应转换为客户端响应（流式响应体逐块转发）
+fn into_response(name: &str, response: reqwest::Response) -> Response {
+    let mut builder = Response::builder().status(response.status());
+    for (key, value) in response.headers() {
+        // 响应体由 axum 重新分块，长度与传输编码相关的头不能沿用
+        if !matches!(
+            key.as_str(),
+            "content-length" | "transfer-encoding" | "connection"
+        ) {
+            builder = builder.header(key, value);
+        }
+    }
+    if let Ok(value) = HeaderValue::from_str(name) {
+        builder = builder.header(FALLBACK_HEADER, value);
+    }
+    builder
+        .body(Body::from_stream(response.bytes_stream()))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
+/// 由已解析的请求重建 Anthropic 格式的请求体
+fn request_body(payload: &MessagesRequest, model: &str) -> Value {
+    let mut body = json!({
+        "model": model,
+        "max_tokens": payload.max_tokens,
+        "messages": payload.messages,
+        "stream": payload.stream,
+    });
+    if let Some(system) = &payload.system {
+        body["system"] = system
+            .iter()
+            .map(|s| {
+                let mut block = json!({"type": "text", "text": s.text});
+                if let Some(cache_control) = &s.cache_control {
+                    block["cache_control"] = json!(cache_control);
+                }
+                block
+            })
+            .collect();
+    }
+    if let Some(tools) = &payload.tools {
+        body["tools"] = tools
+            .iter()
+            .map(|tool| {
+                if tool.is_web_search() {
+                    let mut value = json!({"type": tool.tool_type, "name": tool.name});
+                    if let Some(max_uses) = tool.max_uses {
+                        value["max_uses"] = json!(max_uses);
+                    }
+                    value
+                } else {
+                    let mut value = json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.input_schema,
+                    });
+                    if let Some(cache_control) = &tool.cache_control {
+                        value["cache_control"] = json!(cache_control);
+                    }
+                    value
+                }
+            })
+            .collect();
+    }
+    if let Some(tool_choice) = &payload.tool_choice {
+        body["tool_choice"] = tool_choice.clone();
+    }
+    if let Some(thinking) = &payload.thinking {
+        body["thinking"] = json!({
+            "type": thinking.thinking_type,
+            "budget_tokens": thinking.budget_tokens,
+        });
+    }
+    if let Some(user_id) = payload.metadata.as_ref().and_then(|m| m.user_id.as_ref()) {
+        body["metadata"] = json!({"user_id": user_id});
+    }
+    if let Some(stop_sequences) = &payload.stop_sequences {
+        body["stop_sequences"] = json!(stop_sequences);
+    }
+    for (key, value) in [
+        ("temperature", payload.temperature.map(|v| json!(v))),
+        ("top_p", payload.top_p.map(|v| json!(v))),
+        ("top_k", payload.top_k.map(|v| json!(v))),
+    ] {
+        if let Some(value) = value {
+            body[key] = value;
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, http::StatusCode, routing::post};
+    use std::collections::HashMap;
+
+    fn backend(name: &str, base_url: &str) -> FallbackBackend {
+        FallbackBackend {
+            name: name.to_string(),
+            base_url: base_url.to_string(),
+            api_key: "sk-test".to_string(),
+            models: HashMap::from([("*".to_string(), "claude-sonnet-4-5".to_string())]),
+        }
+    }
+
+    fn payload() -> MessagesRequest {
+        serde_json::from_value(json!({
+            "model": "claude-opus-4-5",
+            "max_tokens": 128,
+            "system": "be brief",
+            "messages": [{"role": "user", "content": "hi"}],
+            "temperature": 0.5
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_request_body_maps_model() {
+        let body = request_body(&payload(), "claude-sonnet-4-5");
+        assert_eq!(body["model"], "claude-sonnet-4-5");
+        assert_eq!(
+            body["system"],
+            json!([{"type": "text", "text": "be brief"}])
+        );
+        assert_eq!(body["temperature"], 0.5);
+        assert!(body.get("tools").is_none() && body.get("top_k").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_forward_skips_failing_backends() {
+        let app = Router::new()
+            .route(
+                "/busy/v1/messages",
+                post(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+            )
+            .route(
+                "/ok/v1/messages",
+                post(|headers: HeaderMap, Json(body): Json<Value>| async move {
+                    assert_eq!(headers["x-api-key"], "sk-test");
+                    Json(json!({"type": "message", "model": body["model"]}))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let chain = FallbackChain {
+            client: Client::new(),
+        };
+        let backends = [
+            backend("down", "http://127.0.0.1:1"),
+            backend("busy", &format!("http://{}/busy", addr)),
+            backend("ok", &format!("http://{}/ok/", addr)),
+        ];
+        let response = chain
+            .forward(&backends, &payload(), &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[FALLBACK_HEADER], "ok");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["model"], "claude-sonnet-4-5");
+
+        assert!(
+            chain
+                .forward(&backends[..2], &payload(), &HeaderMap::new())
+                .await
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,612 @@
+//! Files API 模拟
+//!
+//! 上传的文件保存在本地目录（`filesDir`）：文件内容为 `<id>`，元数据为 `<id>.json`。
+//! 文件只对上传它的客户端 API Key 可见，引用其他 Key 的文件按不存在处理。
+//! 消息中 `source.type == "file"` 的图片 / 文档块在请求转换前解析为内联内容：
+//! 图片转换为 base64 来源，文本类文档转换为文本块（Kiro 不支持二进制文档）
+
+use std::path::{Path, PathBuf};
+
+use axum::{
+    Extension,
+    extract::{Multipart, Path as PathParam, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::fs;
+
+use crate::common::api_keys::ClientKey;
+
+use super::batches::owner;
+use super::middleware::AppState;
+use super::types::{ErrorResponse, MessagesRequest};
+
+/// 文件 ID 前缀
+const FILE_ID_PREFIX: &str = "file_";
+
+/// 列表默认返回数量
+const DEFAULT_LIST_LIMIT: usize = 20;
+
+/// 列表最大返回数量
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// 文件元数据
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size_bytes: u64,
+    pub created_at: String,
+    pub downloadable: bool,
+}
+
+/// 持久化的文件记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileRecord {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    /// 上传文件的客户端 API Key 名称（只有同一个 Key 可以访问）
+    #[serde(default)]
+    owner: String,
+}
+
+/// 本地文件存储
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// 打开（必要时创建）文件目录
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// 保存上传的文件
+    pub async fn upload(
+        &self,
+        owner: &str,
+        filename: &str,
+        mime_type: &str,
+        data: &[u8],
+    ) -> anyhow::Result<FileMetadata> {
+        let record = FileRecord {
+            metadata: FileMetadata {
+                id: format!("{}{}", FILE_ID_PREFIX, uuid::Uuid::new_v4().simple()),
+                object_type: "file".to_string(),
+                filename: filename.to_string(),
+                mime_type: mime_type.to_string(),
+                size_bytes: data.len() as u64,
+                created_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                downloadable: false,
+            },
+            owner: owner.to_string(),
+        };
+        let id = &record.metadata.id;
+        fs::write(self.dir.join(id), data).await?;
+        fs::write(self.metadata_path(id), serde_json::to_string(&record)?).await?;
+        Ok(record.metadata)
+    }
+
+    /// 列出文件（按创建时间倒序）
+    pub async fn list(&self, owner: &str) -> anyhow::Result<Vec<FileMetadata>> {
+        let mut files = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match read_record(&path).await {
+                Ok(record) if record.owner == owner => files.push(record.metadata),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("读取文件元数据失败 {:?}: {}", path, e),
+            }
+        }
+        files.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        Ok(files)
+    }
+
+    /// 获取文件元数据（不存在或属于其他 API Key 时返回 None）
+    pub async fn get(&self, owner: &str, id: &str) -> anyhow::Result<Option<FileMetadata>> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        let record = match read_record(&self.metadata_path(id)).await {
+            Ok(record) => record,
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        Ok((record.owner == owner).then_some(record.metadata))
+    }
+
+    /// 读取文件元数据和内容（不存在或属于其他 API Key 时返回 None）
+    pub async fn read(
+        &self,
+        owner: &str,
+        id: &str,
+    ) -> anyhow::Result<Option<(FileMetadata, Vec<u8>)>> {
+        let Some(metadata) = self.get(owner, id).await? else {
+            return Ok(None);
+        };
+        let data = fs::read(self.dir.join(id)).await?;
+        Ok(Some((metadata, data)))
+    }
+
+    fn metadata_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+async fn read_record(path: &Path) -> anyhow::Result<FileRecord> {
+    let content = fs::read_to_string(path).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// 校验文件 ID（防止路径穿越）
+fn is_valid_id(id: &str) -> bool {
+    id.strip_prefix(FILE_ID_PREFIX)
+        .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// 文件引用解析错误
+#[derive(Debug)]
+pub enum FileRefError {
+    /// 未启用 Files API
+    Disabled,
+    /// 文件不存在
+    NotFound(String),
+    /// 文档不是文本内容，无法转发给上游
+    UnsupportedDocument { id: String, mime_type: String },
+    /// 读取文件失败
+    Io(anyhow::Error),
+}
+
+impl std::fmt::Display for FileRefError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileRefError::Disabled => write!(f, "Files API 未启用，无法引用 file_id"),
+            FileRefError::NotFound(id) => write!(f, "文件不存在: {}", id),
+            FileRefError::UnsupportedDocument { id, mime_type } => {
+                write!(f, "不支持的文档类型: {} ({})", mime_type, id)
+            }
+            FileRefError::Io(e) => write!(f, "读取文件失败: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FileRefError {}
+
+/// 将消息中 `owner` 可访问的 file_id 引用解析为内联内容，返回解析的引用数量
+pub async fn resolve_file_references(
+    store: Option<&FileStore>,
+    owner: &str,
+    req: &mut MessagesRequest,
+) -> Result<usize, FileRefError> {
+    let mut resolved = 0;
+    for message in &mut req.messages {
+        let Value::Array(blocks) = &mut message.content else {
+            continue;
+        };
+        for block in blocks.iter_mut() {
+            let Some(id) = file_reference(block) else {
+                continue;
+            };
+            let store = store.ok_or(FileRefError::Disabled)?;
+            let (metadata, data) = store
+                .read(owner, &id)
+                .await
+                .map_err(FileRefError::Io)?
+                .ok_or_else(|| FileRefError::NotFound(id.clone()))?;
+            *block = inline_block(block, &metadata, data)?;
+            resolved += 1;
+        }
+    }
+    Ok(resolved)
+}
+
+/// 提取内容块引用的 file_id
+fn file_reference(block: &Value) -> Option<String> {
+    let source = block.get("source")?;
+    if source.get("type")?.as_str()? != "file" {
+        return None;
+    }
+    source.get("file_id")?.as_str().map(str::to_string)
+}
+
+/// 将引用文件的内容块转换为内联内容块
+fn inline_block(
+    block: &Value,
+    metadata: &FileMetadata,
+    data: Vec<u8>,
+) -> Result<Value, FileRefError> {
+    if block.get("type").and_then(Value::as_str) == Some("image") {
+        return Ok(json!({
+            "type": "image",
+            "source": {
+                "type": "base64",
+                "media_type": metadata.mime_type,
+                "data": BASE64.encode(&data)
+            }
+        }));
+    }
+
+    let unsupported = || FileRefError::UnsupportedDocument {
+        id: metadata.id.clone(),
+        mime_type: metadata.mime_type.clone(),
+    };
+    if metadata.mime_type.starts_with("image/") {
+        return Err(unsupported());
+    }
+    let title = block
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or(&metadata.filename);
+    // PDF 转为内联 base64 document 块，由请求转换时提取文本
+    if metadata.mime_type == "application/pdf" {
+        return Ok(json!({
+            "type": "document",
+            "title": title,
+            "source": {
+                "type": "base64",
+                "media_type": metadata.mime_type,
+                "data": BASE64.encode(&data)
+            }
+        }));
+    }
+    let text = String::from_utf8(data).map_err(|_| unsupported())?;
+    Ok(json!({
+        "type": "text",
+        "text": format!("<document title=\"{}\">\n{}\n</document>", title, text)
+    }))
+}
+
+/// 列表查询参数
+#[derive(Debug, Deserialize)]
+pub struct ListFilesQuery {
+    pub limit: Option<usize>,
+    pub after_id: Option<String>,
+}
+
+/// 生成错误响应
+fn error_response(status: StatusCode, error_type: &str, message: impl Into<String>) -> Response {
+    (status, Json(ErrorResponse::new(error_type, message))).into_response()
+}
+
+/// Files API 未启用时的错误响应
+fn files_disabled() -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "not_found_error",
+        "Files API is not enabled",
+    )
+}
+
+/// POST /v1/files
+///
+/// 上传文件（multipart/form-data，字段名 `file`）
+pub async fn upload_file(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    mut multipart: Multipart,
+) -> Response {
+    let Some(store) = state.file_store.as_ref() else {
+        return files_disabled();
+    };
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request_error",
+                    format!("解析上传内容失败: {}", e),
+                );
+            }
+        };
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let filename = field.file_name().unwrap_or("file").to_string();
+        let mime_type = field
+            .content_type()
+            .filter(|t| !t.is_empty() && *t != "application/octet-stream")
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                mime_guess::from_path(&filename)
+                    .first_or_octet_stream()
+                    .to_string()
+            });
+        let data = match field.bytes().await {
+            Ok(data) => data,
+            Err(e) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request_error",
+                    format!("读取上传内容失败: {}", e),
+                );
+            }
+        };
+
+        return match store
+            .upload(owner(&client), &filename, &mime_type, &data)
+            .await
+        {
+            Ok(metadata) => {
+                tracing::info!(
+                    file_id = %metadata.id,
+                    size = metadata.size_bytes,
+                    "已保存上传文件: {}",
+                    metadata.filename
+                );
+                Json(metadata).into_response()
+            }
+            Err(e) => {
+                tracing::error!("保存上传文件失败: {}", e);
+                error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "api_error",
+                    format!("保存文件失败: {}", e),
+                )
+            }
+        };
+    }
+
+    error_response(
+        StatusCode::BAD_REQUEST,
+        "invalid_request_error",
+        "Missing multipart field: file",
+    )
+}
+
+/// GET /v1/files
+///
+/// 列出当前 API Key 上传的文件（支持 `limit` 和 `after_id` 分页）
+pub async fn list_files(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    Query(query): Query<ListFilesQuery>,
+) -> Response {
+    let Some(store) = state.file_store.as_ref() else {
+        return files_disabled();
+    };
+    let files = match store.list(owner(&client)).await {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::error!("列出文件失败: {}", e);
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                format!("列出文件失败: {}", e),
+            );
+        }
+    };
+
+    let start = query
+        .after_id
+        .as_ref()
+        .and_then(|after| files.iter().position(|f| &f.id == after).map(|i| i + 1))
+        .unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let page: Vec<_> = files.iter().skip(start).take(limit).collect();
+    let has_more = start + page.len() < files.len();
+
+    Json(json!({
+        "data": page,
+        "first_id": page.first().map(|f| &f.id),
+        "last_id": page.last().map(|f| &f.id),
+        "has_more": has_more
+    }))
+    .into_response()
+}
+
+/// GET /v1/files/{file_id}
+///
+/// 获取文件元数据
+pub async fn get_file(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    PathParam(file_id): PathParam<String>,
+) -> Response {
+    let Some(store) = state.file_store.as_ref() else {
+        return files_disabled();
+    };
+    match store.get(owner(&client), &file_id).await {
+        Ok(Some(metadata)) => Json(metadata).into_response(),
+        Ok(None) => error_response(
+            StatusCode::NOT_FOUND,
+            "not_found_error",
+            format!("File not found: {}", file_id),
+        ),
+        Err(e) => {
+            tracing::error!("读取文件元数据失败: {}", e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                format!("读取文件失败: {}", e),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anthropic::types::Message;
+
+    fn temp_store() -> FileStore {
+        let dir = std::env::temp_dir().join(format!("kiro-files-{}", uuid::Uuid::new_v4()));
+        FileStore::open(dir).unwrap()
+    }
+
+    fn request(content: Value) -> MessagesRequest {
+        MessagesRequest {
+            model: "claude-sonnet-4-5".to_string(),
+            max_tokens: 1024,
+            messages: vec![Message {
+                role: "user".to_string(),
+                content,
+            }],
+            stream: false,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_list_get() {
+        let store = temp_store();
+        let a = store
+            .upload("alice", "a.txt", "text/plain", b"hello")
+            .await
+            .unwrap();
+        let b = store
+            .upload("alice", "b.png", "image/png", &[1, 2, 3])
+            .await
+            .unwrap();
+        store
+            .upload("bob", "c.txt", "text/plain", b"bob")
+            .await
+            .unwrap();
+
+        assert!(is_valid_id(&a.id));
+        assert_eq!(a.size_bytes, 5);
+        let get = |owner: &'static str, id: String| {
+            let store = &store;
+            async move { store.get(owner, &id).await.unwrap() }
+        };
+        assert_eq!(
+            get("alice", b.id.clone()).await.unwrap().mime_type,
+            "image/png"
+        );
+        assert!(get("bob", b.id.clone()).await.is_none());
+        assert!(get("alice", "file_missing".to_string()).await.is_none());
+        assert!(get("alice", "../config".to_string()).await.is_none());
+
+        let ids: Vec<_> = store
+            .list("alice")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|f| f.id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&a.id) && ids.contains(&b.id));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_file_references() {
+        let store = temp_store();
+        let image = store
+            .upload("alice", "a.png", "image/png", &[1, 2, 3])
+            .await
+            .unwrap();
+        let doc = store
+            .upload("alice", "notes.md", "text/markdown", b"# Notes")
+            .await
+            .unwrap();
+
+        let mut req = request(json!([
+            {"type": "image", "source": {"type": "file", "file_id": image.id}},
+            {"type": "document", "source": {"type": "file", "file_id": doc.id}},
+            {"type": "text", "text": "Summarize"}
+        ]));
+        // 其他 API Key 上传的文件按不存在处理
+        assert!(matches!(
+            resolve_file_references(Some(&store), "bob", &mut req.clone()).await,
+            Err(FileRefError::NotFound(_))
+        ));
+        assert_eq!(
+            resolve_file_references(Some(&store), "alice", &mut req)
+                .await
+                .unwrap(),
+            2
+        );
+
+        let blocks = &req.messages[0].content;
+        assert_eq!(blocks[0]["source"]["type"], "base64");
+        assert_eq!(blocks[0]["source"]["data"], BASE64.encode([1, 2, 3]));
+        assert_eq!(blocks[1]["type"], "text");
+        assert!(blocks[1]["text"].as_str().unwrap().contains("# Notes"));
+        assert_eq!(blocks[2]["text"], "Summarize");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_file_reference_errors() {
+        let store = temp_store();
+        let pdf = store
+            .upload("", "a.pdf", "application/pdf", b"%PDF")
+            .await
+            .unwrap();
+        let png = store
+            .upload("", "a.png", "image/png", &[1, 2, 3])
+            .await
+            .unwrap();
+
+        let mut req = request(json!([
+            {"type": "document", "source": {"type": "file", "file_id": pdf.id}}
+        ]));
+        assert_eq!(
+            resolve_file_references(Some(&store), "", &mut req)
+                .await
+                .unwrap(),
+            1
+        );
+        let block = &req.messages[0].content[0];
+        assert_eq!(block["type"], "document");
+        assert_eq!(block["title"], "a.pdf");
+        assert_eq!(block["source"]["media_type"], "application/pdf");
+
+        let mut req = request(json!([
+            {"type": "document", "source": {"type": "file", "file_id": png.id}}
+        ]));
+        assert!(matches!(
+            resolve_file_references(Some(&store), "", &mut req).await,
+            Err(FileRefError::UnsupportedDocument { .. })
+        ));
+        assert!(matches!(
+            resolve_file_references(None, "", &mut req).await,
+            Err(FileRefError::Disabled)
+        ));
+
+        let mut req = request(json!([
+            {"type": "image", "source": {"type": "file", "file_id": "file_missing"}}
+        ]));
+        assert!(matches!(
+            resolve_file_references(Some(&store), "", &mut req).await,
+            Err(FileRefError::NotFound(_))
+        ));
+
+        let mut req = request(json!("plain text"));
+        assert_eq!(
+            resolve_file_references(None, "", &mut req).await.unwrap(),
+            0
+        );
+    }
+}
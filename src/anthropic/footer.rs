@@ -0,0 +1,117 @@
+//! 响应页脚注入
+//!
+//! 按模型为最终响应追加固定文本（如内部合规声明）：非流式响应追加到文本末尾，
+//! 流式响应作为最后一个 text_delta 发送。列入 `footerOptOutKeys` 的 API Key 不注入
+
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+
+use crate::common::auth;
+use crate::model::config::Config;
+
+/// 匹配所有模型的配置键
+const WILDCARD: &str = "*";
+
+/// 解析本次请求需要追加的页脚（匹配规则见 [`match_model`]）
+pub fn resolve<'a>(config: &'a Config, model: &str, headers: &HeaderMap) -> Option<&'a str> {
+    if config.response_footers.is_empty() {
+        return None;
+    }
+
+    if let Some(key) = auth::extract_api_key_from_headers(headers)
+        && config
+            .footer_opt_out_keys
+            .iter()
+            .any(|k| auth::constant_time_eq(k, &key))
+    {
+        return None;
+    }
+
+    match_model(&config.response_footers, model)
+        .map(String::as_str)
+        .filter(|footer| !footer.is_empty())
+}
+
+/// 按模型名查找配置项（键为模型名或模型名片段，`*` 匹配所有模型）
+///
+/// 匹配顺序：模型名完全一致 → 模型名包含配置键（取最长的键）→ `*`
+pub(crate) fn match_model<'a, V>(entries: &'a HashMap<String, V>, model: &str) -> Option<&'a V> {
+    let model = model.to_lowercase();
+    entries
+        .iter()
+        .find(|(pattern, _)| pattern.to_lowercase() == model)
+        .or_else(|| {
+            entries
+                .iter()
+                .filter(|(pattern, _)| {
+                    pattern.as_str() != WILDCARD && model.contains(&pattern.to_lowercase())
+                })
+                .max_by_key(|(pattern, _)| pattern.len())
+        })
+        .or_else(|| entries.get_key_value(WILDCARD))
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn config() -> Config {
+        Config {
+            response_footers: [
+                ("*", "[default]"),
+                ("opus", "[opus]"),
+                ("claude-opus-4-5", "[exact]"),
+                ("sonnet", "[sonnet]"),
+                ("sonnet-4-5", "[sonnet-4-5]"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            footer_opt_out_keys: vec!["opt-out-key".to_string()],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_by_model() {
+        let config = config();
+        let headers = HeaderMap::new();
+        assert_eq!(
+            resolve(&config, "claude-opus-4-5", &headers),
+            Some("[exact]")
+        );
+        assert_eq!(
+            resolve(&config, "claude-opus-4-1", &headers),
+            Some("[opus]")
+        );
+        assert_eq!(
+            resolve(&config, "claude-sonnet-4-5-20250929", &headers),
+            Some("[sonnet-4-5]")
+        );
+        assert_eq!(
+            resolve(&config, "claude-haiku-4-5", &headers),
+            Some("[default]")
+        );
+        assert_eq!(
+            resolve(&Config::default(), "claude-opus-4-5", &headers),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resolve_opt_out_key() {
+        let config = config();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("opt-out-key"));
+        assert_eq!(resolve(&config, "claude-opus-4-5", &headers), None);
+
+        headers.insert("x-api-key", HeaderValue::from_static("other-key"));
+        assert_eq!(
+            resolve(&config, "claude-opus-4-5", &headers),
+            Some("[exact]")
+        );
+    }
+}
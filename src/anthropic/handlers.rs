@@ -1,83 +1,135 @@
 //! Anthropic API Handler 函数
 
 use std::convert::Infallible;
-
+use std::sync::Arc;
+
+use crate::common::api_keys::ClientKey;
+use crate::common::audit;
+use crate::common::disconnect;
+use crate::common::metrics;
+use crate::common::rate_limit::KeyRateLimiter;
+use crate::common::telemetry;
+use crate::kiro::error::{KiroApiError, StreamTimeout, UnavailableKind, UpstreamUnavailableError};
 use crate::kiro::model::events::Event;
 use crate::kiro::model::requests::kiro::KiroRequest;
-use crate::kiro::parser::decoder::EventStreamDecoder;
-use crate::kiro::provider::StreamResponse;
-use crate::kiro::token_manager::ConnectionGuard;
+use crate::kiro::parser::decoder::{DEFAULT_BUFFER_CAPACITY, EventStreamDecoder};
+use crate::kiro::parser::error::ParseError;
+use crate::kiro::provider::{FailoverInfo, StreamResponse, take_connection_guard};
+use crate::kiro::stream_failover::BodyStream;
+use crate::kiro::token_manager::{ConnectionGuard, Routing};
+use crate::model::config::{Config, WebSearchMode};
+use crate::storage::ledger::UsageLedger;
 use crate::token;
 use axum::{
     Json as JsonExtractor,
     body::Body,
-    extract::State,
-    http::{StatusCode, header},
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::{IntoResponse, Json, Response},
 };
 use bytes::Bytes;
 use futures::{Stream, StreamExt, stream};
 use serde_json::json;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::interval;
 use uuid::Uuid;
 
-use super::converter::{ConversionError, convert_request};
+use super::cancellation::{self, RequestHandle};
+use super::converter::{AUTO_COMPACT_MAX_BYTES, ConversionError, compact_history, convert_request};
+use super::credential_group;
+use super::event_buffer::{self, BufferedStream, CreateError, DEFAULT_WAIT_SECS, MAX_WAIT_SECS};
+use super::fallback::{self, KiroExhausted};
+use super::files;
+use super::footer;
+use super::idempotency::IdempotencyCache;
+use super::identity;
+use super::image_dedupe;
+use super::image_downscale;
+use super::image_fetch;
+use super::injection;
+use super::mcp_client;
 use super::middleware::AppState;
-use super::stream::{SseEvent, StreamContext};
+use super::model_override;
+use super::prompt_cache::PromptCacheUsage;
+use super::response_cache;
+use super::rules;
+use super::stream::{SseEvent, StreamContext, find_stop_sequence};
+use super::system_prompt;
 use super::types::{
-    CountTokensRequest, CountTokensResponse, ErrorResponse, MessagesRequest, Model, ModelsResponse,
+    CountTokensRequest, CountTokensResponse, ErrorResponse, MessageEventsQuery, MessagesRequest,
+    Model, ModelsResponse,
 };
 use super::websearch;
 
 /// GET /v1/models
 ///
-/// 返回可用的模型列表
-pub async fn get_models() -> impl IntoResponse {
+/// 返回配置中公布的模型列表
+pub async fn get_models(State(state): State<AppState>) -> impl IntoResponse {
     tracing::info!("Received GET /v1/models request");
 
-    let models = vec![
-        Model {
-            id: "claude-sonnet-4-5-20250929".to_string(),
-            object: "model".to_string(),
-            created: 1727568000,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Sonnet 4.5".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-opus-4-5-20251101".to_string(),
-            object: "model".to_string(),
-            created: 1730419200,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Opus 4.5".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-        Model {
-            id: "claude-haiku-4-5-20251001".to_string(),
-            object: "model".to_string(),
-            created: 1727740800,
-            owned_by: "anthropic".to_string(),
-            display_name: "Claude Haiku 4.5".to_string(),
-            model_type: "chat".to_string(),
-            max_tokens: 32000,
-        },
-    ];
-
     Json(ModelsResponse {
         object: "list".to_string(),
-        data: models,
+        data: with_config(&state, available_models),
     })
 }
 
+/// GET /v1/models/{id}
+///
+/// 返回单个模型的信息，未公布的模型返回 404
+pub async fn get_model(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match with_config(&state, available_models)
+        .into_iter()
+        .find(|m| m.id == id)
+    {
+        Some(model) => Json(model).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "not_found_error",
+                format!("model: {}", id),
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// 以当前配置（未配置 Provider 时为默认配置）调用 `f`
+pub(crate) fn with_config<T>(state: &AppState, f: impl FnOnce(&Config) -> T) -> T {
+    match &state.kiro_provider {
+        Some(provider) => f(&provider.token_manager().config()),
+        None => f(&Config::default()),
+    }
+}
+
+/// 可用的模型列表
+pub(crate) fn available_models(config: &Config) -> Vec<Model> {
+    config
+        .models
+        .iter()
+        .map(|m| Model {
+            id: m.id.clone(),
+            object: "model".to_string(),
+            created: m.created,
+            owned_by: "anthropic".to_string(),
+            display_name: m.display_name.clone().unwrap_or_else(|| m.id.clone()),
+            model_type: "chat".to_string(),
+            max_tokens: m.max_tokens,
+        })
+        .collect()
+}
+
 /// POST /v1/messages
 ///
 /// 创建消息（对话）
+#[tracing::instrument(
+    skip_all,
+    fields(model = %payload.model, stream = payload.stream, credential_id = tracing::field::Empty)
+)]
 pub async fn post_messages(
     State(state): State<AppState>,
-    JsonExtractor(payload): JsonExtractor<MessagesRequest>,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    JsonExtractor(mut payload): JsonExtractor<MessagesRequest>,
 ) -> Response {
     tracing::info!(
         model = %payload.model,
@@ -86,6 +138,7 @@ pub async fn post_messages(
         message_count = %payload.messages.len(),
         "Received POST /v1/messages request"
     );
+    audit::note_request(&payload.model, payload.stream);
     // 检查 KiroProvider 是否可用
     let provider = match &state.kiro_provider {
         Some(p) => p.clone(),
@@ -102,23 +155,167 @@ pub async fn post_messages(
         }
     };
 
+    // 将 file_id 引用解析为内联内容
+    let owner = client.as_deref().map_or("", |c| c.name.as_str());
+    if let Err(e) =
+        files::resolve_file_references(state.file_store.as_deref(), owner, &mut payload).await
+    {
+        tracing::warn!("解析文件引用失败: {}", e);
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("invalid_request_error", e.to_string())),
+        )
+            .into_response();
+    }
+
+    // 下载 URL 来源的图片
+    if let Err(e) =
+        image_fetch::resolve_image_urls(&provider.token_manager().config(), &mut payload).await
+    {
+        tracing::warn!("下载 URL 图片失败: {}", e);
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("invalid_request_error", e.to_string())),
+        )
+            .into_response();
+    }
+
+    // 应用请求改写规则（内部续写请求已在外层应用过）
+    if !state.internal {
+        let applied_rules = rules::apply(
+            &provider.token_manager().config().request_rules,
+            &mut payload,
+            &headers,
+            client.as_deref().map(|c| c.name.as_str()),
+        );
+        if !applied_rules.is_empty() {
+            tracing::info!(rules = ?applied_rules, "已应用请求改写规则");
+        }
+    }
+
+    // 解析凭据分组
+    let group =
+        match credential_group::resolve(client.as_deref(), &headers, provider.token_manager()) {
+            Ok(group) => group,
+            Err(group) => {
+                tracing::warn!("未知的凭据分组: {}", group);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new(
+                        "invalid_request_error",
+                        format!(
+                            "Unknown credential group in {}: {}",
+                            credential_group::GROUP_HEADER,
+                            group
+                        ),
+                    )),
+                )
+                    .into_response();
+            }
+        };
+
+    // 按配置处理 WebSearch 工具：拦截 / 移除 / 拒绝
+    match websearch::resolve_mode(&provider.token_manager().config(), &headers) {
+        WebSearchMode::Intercept => {}
+        WebSearchMode::Strip => {
+            let removed = websearch::strip_web_search_tools(&mut payload);
+            if removed > 0 {
+                tracing::info!(
+                    "WebSearch 拦截已禁用，已移除 {} 个 web_search 工具",
+                    removed
+                );
+            }
+        }
+        WebSearchMode::Reject => {
+            if websearch::contains_web_search_tool(&payload) {
+                tracing::warn!("WebSearch 已禁用，拒绝包含 web_search 工具的请求");
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new(
+                        "invalid_request_error",
+                        "The web_search tool is disabled on this server",
+                    )),
+                )
+                    .into_response();
+            }
+        }
+    }
+
     // 检查是否为 WebSearch 请求
     if websearch::has_web_search_tool(&payload) {
         tracing::info!("检测到 WebSearch 工具，路由到 WebSearch 处理");
 
         // 估算输入 tokens
         let input_tokens = token::count_all_tokens(
-            payload.model.clone(),
-            payload.system.clone(),
-            payload.messages.clone(),
-            payload.tools.clone(),
-        ) as i32;
+            &payload.model,
+            payload.system.as_deref(),
+            &payload.messages,
+            payload.tools.as_deref(),
+        )
+        .await as i32;
 
-        return websearch::handle_websearch_request(provider, &payload, input_tokens).await;
+        return websearch::handle_websearch_request(
+            provider,
+            &payload,
+            input_tokens,
+            group.as_deref(),
+            state.usage_ledger_for(client.as_deref()),
+            client.as_deref().map(|c| c.limiter.clone()),
+        )
+        .await;
+    }
+
+    // 启用服务端 MCP 工具且客户端 Key 有权使用时，由代理执行工具调用并续写对话
+    if let Some(pool) = state.mcp_tools.clone()
+        && !pool.is_empty()
+        && client.as_deref().is_some_and(|c| c.mcp_tools)
+    {
+        return mcp_client::handle_request(pool, state, client, headers, payload).await;
+    }
+
+    // 解析提示词注入选择
+    let config = provider.token_manager().config();
+    let selection = if config.allow_inject_header {
+        injection::parse_header(&headers)
+    } else {
+        None
+    };
+    let injected_prompt = match injection::resolve_prompt(selection.as_ref(), &config) {
+        Ok(prompt) => prompt,
+        Err(name) => {
+            tracing::warn!("未知的提示词配置: {}", name);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(
+                    "invalid_request_error",
+                    format!(
+                        "Unknown prompt profile in {}: {}",
+                        injection::INJECT_HEADER,
+                        name
+                    ),
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    // 解析响应页脚
+    let footer = footer::resolve(&config, &payload.model, &headers).map(str::to_string);
+
+    // 解析模型覆盖请求头
+    let model_override = model_override::resolve(&config, client.as_deref(), &headers);
+    if let Some(model_id) = &model_override {
+        tracing::info!("模型覆盖请求头生效: {} -> {}", payload.model, model_id);
     }
 
     // 转换请求
-    let conversion_result = match convert_request(&payload) {
+    let conversion_result = match convert_request(
+        &payload,
+        model_override.as_deref(),
+        injected_prompt.as_deref(),
+        system_prompt::resolve(&config, &payload.model, selection.as_ref()),
+        &config.synthetic_history,
+    ) {
         Ok(result) => result,
         Err(e) => {
             let (error_type, message) = match &e {
@@ -128,6 +325,9 @@ pub async fn post_messages(
                 ConversionError::EmptyMessages => {
                     ("invalid_request_error", "消息列表为空".to_string())
                 }
+                ConversionError::UnknownToolChoice(_) | ConversionError::InvalidParameter(_) => {
+                    ("invalid_request_error", e.to_string())
+                }
             };
             tracing::warn!("请求转换失败: {}", e);
             return (
@@ -139,8 +339,50 @@ pub async fn post_messages(
     };
 
     // 构建 Kiro 请求
+    let session_id = conversion_result.session_id;
+    let mut pinned_history = conversion_result.pinned_history;
+    let mut conversation_state = conversion_result.conversation_state;
+    let routing = Routing {
+        session: session_id.as_deref(),
+        group: group.as_deref(),
+        priority: client.as_ref().map(|c| c.priority).unwrap_or_default(),
+        ..Routing::default()
+    };
+    if let Some(tag) = identity::resolve(&config, &headers) {
+        conversation_state.agent_continuation_id = Some(identity::tagged_continuation_id(&tag));
+    }
+    if config.image_dedupe {
+        image_dedupe::dedupe_images(&mut conversation_state);
+    }
+    image_downscale::downscale_images(&mut conversation_state, &config.image_downscale).await;
+    if let Some(summarizer) = &state.summarizer {
+        let replaced = summarizer
+            .summarize(
+                &provider,
+                &mut conversation_state,
+                pinned_history,
+                &config.context_summary,
+                state.profile_arn.as_deref(),
+                routing,
+            )
+            .await;
+        if replaced > 0 {
+            // 摘要消息与系统提示词一样在自动压缩时保留
+            pinned_history += 2;
+        }
+    }
+    if config.auto_compact_history {
+        let dropped = compact_history(
+            &mut conversation_state,
+            pinned_history,
+            AUTO_COMPACT_MAX_BYTES,
+        );
+        if dropped > 0 {
+            tracing::info!("请求体接近上限，已自动丢弃 {} 条最早的历史消息", dropped);
+        }
+    }
     let kiro_request = KiroRequest {
-        conversation_state: conversion_result.conversation_state,
+        conversation_state,
         profile_arn: state.profile_arn.clone(),
     };
 
@@ -231,11 +473,12 @@ pub async fn post_messages(
 
     // 估算输入 tokens
     let input_tokens = token::count_all_tokens(
-        payload.model.clone(),
-        payload.system.clone(),
-        payload.messages.clone(),
-        payload.tools.clone(),
-    ) as i32;
+        &payload.model,
+        payload.system.as_deref(),
+        &payload.messages,
+        payload.tools.as_deref(),
+    )
+    .await as i32;
 
     tracing::info!(
         "Token 计数 - 消息数: {}, 输入 tokens: {}",
@@ -278,43 +521,291 @@ pub async fn post_messages(
         .as_ref()
         .map(|t| t.thinking_type == "enabled")
         .unwrap_or(false);
+    let stop_sequences = payload.stop_sequences.clone().unwrap_or_default();
+
+    // 登记请求以支持 DELETE /v1/messages/{id} 取消，请求 ID 即消息 ID
+    let message_id = match cancellation::requested_id(&headers) {
+        Ok(id) => id.unwrap_or_else(new_message_id),
+        Err(message) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new("invalid_request_error", message)),
+            )
+                .into_response();
+        }
+    };
+    let owner = client.as_ref().map(|c| c.name.as_str()).unwrap_or_default();
+
+    // 模拟 prompt caching：按 cache_control 断点拆分缓存写入 / 读取的 tokens
+    let prompt_cache = state
+        .prompt_cache
+        .as_ref()
+        .map(|cache| cache.observe(&format!("{}/{}", owner, payload.model), &payload))
+        .unwrap_or_default();
+
+    let Some(mut request) = state.requests.register(message_id.clone(), owner) else {
+        return (
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(
+                "invalid_request_error",
+                format!("Request {} is already in progress", message_id),
+            )),
+        )
+            .into_response();
+    };
 
-    if payload.stream {
-        // 流式响应
-        handle_stream_request(
+    let mut response = if payload.stream {
+        // 流式响应（x-kiro-transport: poll 时改为后台生成 + 长轮询，调用上游前先预留缓冲区）
+        let poll_buffer = match headers
+            .get(event_buffer::TRANSPORT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| v.eq_ignore_ascii_case("poll"))
+            .map(|_| state.event_buffer.create(owner, &message_id))
+            .transpose()
+        {
+            Ok(buffer) => buffer,
+            Err(CreateError::Conflict) => {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(ErrorResponse::new(
+                        "invalid_request_error",
+                        format!("Message id {} is already in use", message_id),
+                    )),
+                )
+                    .into_response();
+            }
+            Err(CreateError::Full) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(ErrorResponse::new(
+                        "rate_limit_error",
+                        "Too many buffered streams in progress, try again later",
+                    )),
+                )
+                    .into_response();
+            }
+        };
+        let response = handle_stream_request(
             provider,
             &request_body,
+            routing,
             &payload.model,
             input_tokens,
+            prompt_cache,
+            payload.max_tokens,
+            context_window_size,
+            stop_sequences,
             thinking_enabled,
+            footer.as_deref(),
+            state.usage_ledger_for(client.as_deref()),
+            client.as_deref().map(|c| c.limiter.clone()),
+            poll_buffer,
+            request,
         )
-        .await
+        .await;
+        try_fallback(&state, response, &payload, &headers).await
     } else {
-        // 非流式响应
-        handle_non_stream_request(provider, &request_body, &payload.model, input_tokens).await
+        let cache_params = json!({
+            "client": owner,
+            "model": payload.model,
+            "maxTokens": payload.max_tokens,
+            "stopSequences": stop_sequences,
+            "footer": footer,
+        });
+        // 非流式响应：携带 Idempotency-Key 时优先返回已缓存的响应（按客户端与请求内容隔离）
+        let idempotency = state
+            .idempotency
+            .as_ref()
+            .zip(IdempotencyCache::extract_key(&headers))
+            .map(|(cache, key)| {
+                let request_hash = response_cache::ResponseCache::key(
+                    "messages",
+                    &kiro_request.conversation_state,
+                    &cache_params,
+                );
+                (
+                    cache,
+                    IdempotencyCache::scoped_key(owner, &key, &request_hash),
+                )
+            });
+        if let Some((cache, key)) = &idempotency
+            && let Some(cached) = cache.lookup(key).await
+        {
+            return cached;
+        }
+        // 相同请求命中响应缓存时不再调用上游
+        let response_cache = response_cache::resolve(
+            state.response_cache.as_ref(),
+            &headers,
+            "messages",
+            &kiro_request.conversation_state,
+            &cache_params,
+        );
+        if let Some((cache, key)) = &response_cache
+            && let Some(cached) = cache.lookup(key)
+        {
+            return cached;
+        }
+
+        // 取消时丢弃处理中的 future，上游连接随之中止并释放连接占用
+        let response = tokio::select! {
+            response = handle_non_stream_request(
+                provider,
+                &request_body,
+                routing,
+                &message_id,
+                &payload.model,
+                input_tokens,
+                prompt_cache,
+                payload.max_tokens,
+                context_window_size,
+                &stop_sequences,
+                footer.as_deref(),
+                state.usage_ledger_for(client.as_deref()),
+                client.as_deref().map(|c| c.limiter.clone()),
+            ) => response,
+            _ = request.cancelled() => {
+                tracing::info!("请求 {} 已被取消", message_id);
+                cancellation::cancelled_response()
+            }
+        };
+        drop(request);
+        let response = try_fallback(&state, response, &payload, &headers).await;
+
+        let response = match response_cache {
+            Some((cache, key)) => cache.store(&key, response).await,
+            None => response,
+        };
+        match idempotency {
+            Some((cache, key)) => cache.store(&key, response).await,
+            None => response,
+        }
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&message_id) {
+        response
+            .headers_mut()
+            .insert(cancellation::REQUEST_ID_HEADER, value);
     }
+    response
 }
 
-/// 根据上游错误信息判断应返回的状态码
-fn determine_error_status(error_msg: &str) -> (StatusCode, &'static str) {
-    if error_msg.contains("400 Bad Request") {
-        (StatusCode::BAD_REQUEST, "invalid_request_error")
-    } else if error_msg.contains("429") {
-        (StatusCode::TOO_MANY_REQUESTS, "rate_limit_error")
-    } else if error_msg.contains("401") || error_msg.contains("403") {
-        (StatusCode::UNAUTHORIZED, "authentication_error")
+/// 取消进行中的消息请求
+///
+/// 只能取消同一个 API Key 发起的请求；请求不存在、已结束或属于其他 Key 时返回 404
+pub async fn cancel_message(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    Path(id): Path<String>,
+) -> Response {
+    let owner = client.as_ref().map(|c| c.name.as_str()).unwrap_or_default();
+    if state.requests.cancel(&id, owner) {
+        tracing::info!("收到取消请求: {}", id);
+        Json(json!({ "id": id, "type": "message_cancelled" })).into_response()
     } else {
-        (StatusCode::BAD_GATEWAY, "api_error")
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "not_found_error",
+                format!("No in-progress request: {}", id),
+            )),
+        )
+            .into_response()
     }
 }
 
-/// 检查错误信息是否为token超限错误
-fn is_token_limit_error(error_msg: &str) -> bool {
-    error_msg.contains("Input is too long")
-        || error_msg.contains("too long")
-        || error_msg.contains("exceeds")
-        || error_msg.contains("CONTENT_LENGTH_EXCEEDS_THRESHOLD")
-        || error_msg.contains("context limit")
+/// 生成新的消息 ID
+fn new_message_id() -> String {
+    format!("msg_{}", Uuid::new_v4().to_string().replace('-', ""))
+}
+
+/// 根据上游错误判断应返回的状态码
+///
+/// 上游返回了错误响应时按其状态码映射，网络错误等其他失败一律返回 502
+pub(crate) fn determine_error_status(e: &anyhow::Error) -> (StatusCode, &'static str) {
+    match e.downcast_ref::<KiroApiError>() {
+        Some(err) => err.client_status(),
+        None => (StatusCode::BAD_GATEWAY, "api_error"),
+    }
+}
+
+/// 生成上游调用失败的错误响应
+///
+/// 限流（429）与过载（529）响应附带 Retry-After，便于客户端 SDK 退避重试
+pub(crate) fn upstream_api_error_response(e: &anyhow::Error) -> Response {
+    let (status, error_type) = determine_error_status(e);
+    let body = Json(ErrorResponse::new(
+        error_type,
+        format!("上游 API 调用失败: {}", e),
+    ));
+    let retry_after = e
+        .downcast_ref::<KiroApiError>()
+        .and_then(|err| err.retry_after);
+    let mut response = match retry_after {
+        Some(wait) if matches!(status.as_u16(), 429 | 529) => {
+            let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+            (status, [(header::RETRY_AFTER, secs.to_string())], body).into_response()
+        }
+        _ => (status, body).into_response(),
+    };
+    if fallback::is_exhausted(e) {
+        response.extensions_mut().insert(KiroExhausted);
+    }
+    response
+}
+
+/// Kiro 凭据用尽或被限流时将请求转发到备用后端，未配置或全部失败时返回原响应
+async fn try_fallback(
+    state: &AppState,
+    response: Response,
+    payload: &MessagesRequest,
+    headers: &HeaderMap,
+) -> Response {
+    if response.extensions().get::<KiroExhausted>().is_none() {
+        return response;
+    }
+    let (Some(chain), Some(provider)) = (&state.fallback, &state.kiro_provider) else {
+        return response;
+    };
+    let config = provider.token_manager().config();
+    chain
+        .forward(&config.fallback_backends, payload, headers)
+        .await
+        .unwrap_or(response)
+}
+
+/// 生成上游维护 / 版本过低的错误响应
+///
+/// 返回 503，维护中时附带 Retry-After 提示客户端稍后重试
+pub(crate) fn upstream_unavailable_response(err: &UpstreamUnavailableError) -> Response {
+    let body = Json(ErrorResponse::new("api_error", err.client_message()));
+    match err.kind {
+        UnavailableKind::Maintenance => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "60")],
+            body,
+        )
+            .into_response(),
+        UnavailableKind::UpgradeRequired => (StatusCode::SERVICE_UNAVAILABLE, body).into_response(),
+    }
+}
+
+/// 生成上游流式响应超时的错误响应（504）
+pub(crate) fn stream_timeout_response(timeout: &StreamTimeout) -> Response {
+    (
+        StatusCode::GATEWAY_TIMEOUT,
+        Json(ErrorResponse::new(
+            "timeout_error",
+            timeout.client_message(),
+        )),
+    )
+        .into_response()
+}
+
+/// 检查上游错误是否为token超限错误
+fn is_token_limit_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<KiroApiError>()
+        .is_some_and(KiroApiError::is_token_limit)
 }
 
 /// 生成友好的token超限错误信息
@@ -330,13 +821,68 @@ fn create_token_limit_error(input_tokens: i32, max_tokens: i32, context_window:
     )
 }
 
+/// GET /v1/messages/{id}/events
+///
+/// 长轮询读取后台生成的流式事件（用于无法使用 SSE 的环境）
+pub async fn get_message_events(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    Path(id): Path<String>,
+    Query(query): Query<MessageEventsQuery>,
+) -> Response {
+    let wait = Duration::from_secs(query.wait.unwrap_or(DEFAULT_WAIT_SECS).min(MAX_WAIT_SECS));
+    let owner = super::batches::owner(&client);
+    let result = state
+        .event_buffer
+        .poll(owner, &id, query.cursor, wait)
+        .await;
+    match result {
+        Some(result) => Json(result).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "not_found_error",
+                format!("No buffered events for message: {}", id),
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// 记录一次请求的用量，并向客户端 Key 的速率限制器扣减本次消耗的 tokens
+pub(super) fn record_usage(
+    ledger: Option<&UsageLedger>,
+    limiter: Option<&KeyRateLimiter>,
+    model: &str,
+    input_tokens: i32,
+    output_tokens: i32,
+) {
+    if let Some(ledger) = ledger {
+        ledger.record(model, input_tokens, output_tokens);
+    }
+    if let Some(limiter) = limiter {
+        limiter.record_tokens(input_tokens.max(0) as i64 + output_tokens.max(0) as i64);
+    }
+}
+
 /// 处理流式请求
+#[allow(clippy::too_many_arguments)]
 async fn handle_stream_request(
     provider: std::sync::Arc<crate::kiro::provider::KiroProvider>,
     request_body: &str,
+    routing: Routing<'_>,
     model: &str,
     input_tokens: i32,
+    prompt_cache: PromptCacheUsage,
+    max_tokens: i32,
+    context_window: i32,
+    stop_sequences: Vec<String>,
     thinking_enabled: bool,
+    footer: Option<&str>,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    rate_limiter: Option<Arc<KeyRateLimiter>>,
+    poll_buffer: Option<Arc<BufferedStream>>,
+    mut request: RequestHandle,
 ) -> Response {
     tracing::info!(
         "开始处理流式请求 - model: {}, input_tokens: {}, thinking: {}",
@@ -345,18 +891,37 @@ async fn handle_stream_request(
         thinking_enabled
     );
 
-    // 调用 Kiro API（支持多凭据故障转移）
-    let stream_response = match provider.call_api_stream(request_body).await {
+    // 调用 Kiro API（支持多凭据故障转移），等待响应期间同样可被取消
+    let result = tokio::select! {
+        result = provider.call_api_stream(request_body, routing) => Some(result),
+        _ = request.cancelled() => None,
+    };
+    // 未开始生成时结束预留的缓冲区，使其按保留期过期
+    if !matches!(result, Some(Ok(_)))
+        && let Some(buffer) = &poll_buffer
+    {
+        buffer.finish();
+    }
+    let Some(result) = result else {
+        tracing::info!("请求 {} 已被取消", request.id());
+        return cancellation::cancelled_response();
+    };
+    let stream_response = match result {
         Ok(resp) => resp,
         Err(e) => {
             let error_msg = e.to_string();
             tracing::error!("Kiro API 调用失败: {}", error_msg);
 
+            // 上游维护 / 版本过低：返回专用错误，而非通用 502
+            if let Some(err) = e.downcast_ref::<UpstreamUnavailableError>() {
+                return upstream_unavailable_response(err);
+            }
+            if let Some(timeout) = e.downcast_ref::<StreamTimeout>() {
+                return stream_timeout_response(timeout);
+            }
+
             // 检查是否为token超限错误
-            if is_token_limit_error(&error_msg) {
-                let context_window = super::model_config::get_context_window_size(model);
-                // 从request_body解析max_tokens（简化处理，使用默认值）
-                let max_tokens = 8192; // 默认值，实际应该从payload获取
+            if is_token_limit_error(&e) {
                 return (
                     StatusCode::BAD_REQUEST,
                     Json(create_token_limit_error(input_tokens, max_tokens, context_window)),
@@ -364,38 +929,135 @@ async fn handle_stream_request(
                     .into_response();
             }
 
-            let (status, error_type) = determine_error_status(&error_msg);
-            return (
-                status,
-                Json(ErrorResponse::new(
-                    error_type,
-                    format!("上游 API 调用失败: {}", error_msg),
-                )),
-            )
-                .into_response();
+            return upstream_api_error_response(&e);
         }
     };
 
-    // 解构 StreamResponse，获取 response 和 guard
-    let StreamResponse { response, guard } = stream_response;
+    // 解构 StreamResponse，获取 response、guard 和故障转移信息
+    let StreamResponse {
+        body,
+        guard,
+        failover,
+    } = stream_response;
+    tracing::Span::current().record("credential_id", failover.credential_id);
+    audit::note_credential(failover.credential_id);
+    let usage_ledger = usage_ledger.map(|ledger| ledger.for_credential(failover.credential_id));
+    let config = provider.token_manager().config();
+    let expose_ids = config.expose_credential_ids;
 
     // 创建流处理上下文
-    let mut ctx = StreamContext::new_with_thinking(model, input_tokens, thinking_enabled);
+    let mut ctx = StreamContext::new_with_thinking(model, input_tokens, thinking_enabled)
+        .with_message_id(request.id())
+        .with_max_tokens(max_tokens)
+        .with_context_window(context_window)
+        .with_stop_sequences(stop_sequences)
+        .with_overlap_dedup(config.stream_dedup_min_overlap)
+        .with_footer(footer)
+        .with_prompt_cache(prompt_cache);
 
     // 生成初始事件
     let initial_events = ctx.generate_initial_events();
+    let message_id = ctx.message_id.clone();
 
     // 创建 SSE 流，传入 guard 以保持其生命周期
-    let stream = create_sse_stream(response, ctx, initial_events, guard);
+    // 发生过凭据切换时，在流开头附加 SSE 注释（客户端会忽略注释行）
+    let failover_comment = failover_sse_comment(failover, expose_ids).map(Ok);
+    let stream = stream::iter(failover_comment).chain(telemetry::instrument_stream(
+        create_sse_stream(
+            body,
+            ctx,
+            EventStreamDecoder::with_limits(
+                config.decoder_max_buffer_bytes,
+                config.decoder_overflow_policy,
+            ),
+            initial_events,
+            guard,
+            usage_ledger,
+            rate_limiter,
+            failover.credential_id,
+        ),
+        tracing::info_span!("sse_stream"),
+    ));
+    let stream = cancellation::cancellable(stream, request);
+    let stream = disconnect::on_disconnect(
+        stream,
+        format!("消息 {}（凭据 #{}）", message_id, failover.credential_id),
+    );
+
+    // 长轮询模式：后台消费事件流写入缓冲区，立即返回消息 ID
+    if let Some(buffered) = poll_buffer {
+        tokio::spawn(audit::scope(audit::current(), async move {
+            let mut stream = std::pin::pin!(stream);
+            while let Some(Ok(chunk)) = stream.next().await {
+                buffered.push_chunk(&chunk);
+            }
+            buffered.finish();
+        }));
+        tracing::info!("流式请求以长轮询模式处理: {}", message_id);
+
+        let mut response = (
+            StatusCode::ACCEPTED,
+            Json(json!({
+                "id": message_id,
+                "type": "message_poll",
+                "events_url": format!("/v1/messages/{}/events", message_id),
+            })),
+        )
+            .into_response();
+        apply_failover_headers(response.headers_mut(), failover, expose_ids);
+        return response;
+    }
 
     // 返回 SSE 响应
-    Response::builder()
+    let mut response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "text/event-stream")
         .header(header::CACHE_CONTROL, "no-cache")
         .header(header::CONNECTION, "keep-alive")
         .body(Body::from_stream(stream))
-        .unwrap()
+        .unwrap();
+    apply_failover_headers(response.headers_mut(), failover, expose_ids);
+    response
+}
+
+/// 故障转移响应头：本次请求发生的凭据切换次数
+const FAILOVER_HEADER: &str = "x-kiro-failover";
+
+/// 故障转移响应头：最终使用的凭据 ID（需开启 exposeCredentialIds）
+const CREDENTIAL_ID_HEADER: &str = "x-kiro-credential-id";
+
+/// 为发生过凭据切换的响应添加故障转移标注头
+pub(crate) fn apply_failover_headers(
+    headers: &mut HeaderMap,
+    failover: FailoverInfo,
+    expose_ids: bool,
+) {
+    if failover.switches == 0 {
+        return;
+    }
+    headers.insert(FAILOVER_HEADER, HeaderValue::from(failover.switches));
+    if expose_ids {
+        headers.insert(
+            CREDENTIAL_ID_HEADER,
+            HeaderValue::from(failover.credential_id),
+        );
+    }
+}
+
+/// 生成故障转移 SSE 注释（未发生切换时返回 None）
+pub(crate) fn failover_sse_comment(failover: FailoverInfo, expose_ids: bool) -> Option<Bytes> {
+    if failover.switches == 0 {
+        return None;
+    }
+    let comment = if expose_ids {
+        format!(
+            ": kiro-failover switches={} credential={}\n\n",
+            failover.switches, failover.credential_id
+        )
+    } else {
+        format!(": kiro-failover switches={}\n\n", failover.switches)
+    };
+    Some(Bytes::from(comment))
 }
 
 /// Ping 事件间隔（25秒）
@@ -406,16 +1068,49 @@ fn create_ping_sse() -> Bytes {
     Bytes::from("event: ping\ndata: {\"type\": \"ping\"}\n\n")
 }
 
+/// 向解码器写入上游数据
+///
+/// 超限帧被截断时解码器已记录日志，可继续解码；缓冲区溢出时返回错误，调用方应中止响应
+pub(crate) fn feed_decoder(
+    decoder: &mut EventStreamDecoder,
+    data: &[u8],
+) -> Result<(), ParseError> {
+    match decoder.feed(data) {
+        Ok(()) | Err(ParseError::FrameTruncated { .. }) => Ok(()),
+        Err(e) => {
+            tracing::error!("解码缓冲区溢出，中止响应: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// 记录流式响应的 token 用量
+fn log_stream_usage(ctx: &StreamContext) {
+    tracing::info!(
+        input_tokens = ctx.final_input_tokens(),
+        output_tokens = ctx.output_tokens,
+        "流式响应完成"
+    );
+}
+
 /// 创建 SSE 事件流
 ///
 /// guard 参数用于保持 ConnectionGuard 的生命周期，确保 active_connections 计数
 /// 在流完全结束后才递减
+#[allow(clippy::too_many_arguments)]
 fn create_sse_stream(
-    response: reqwest::Response,
+    body_stream: BodyStream<anyhow::Error>,
     ctx: StreamContext,
+    decoder: EventStreamDecoder,
     initial_events: Vec<SseEvent>,
     guard: ConnectionGuard,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    rate_limiter: Option<Arc<KeyRateLimiter>>,
+    credential_id: u64,
 ) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    // 上游响应已读到首个内容事件，从此处开始计算输出吞吐量
+    let started = Instant::now();
+
     // 先发送初始事件
     let initial_stream = stream::iter(
         initial_events
@@ -424,12 +1119,14 @@ fn create_sse_stream(
     );
 
     // 然后处理 Kiro 响应流，同时每25秒发送 ping 保活
-    let body_stream = response.bytes_stream();
 
     // guard 被移入闭包状态，随流一起存活
     let processing_stream = stream::unfold(
-        (body_stream, ctx, EventStreamDecoder::new(), false, interval(Duration::from_secs(PING_INTERVAL_SECS)), Some(guard)),
-        |(mut body_stream, mut ctx, mut decoder, finished, mut ping_interval, guard)| async move {
+        (body_stream, ctx, decoder, false, interval(Duration::from_secs(PING_INTERVAL_SECS)), Some(guard)),
+        move |(mut body_stream, mut ctx, mut decoder, finished, mut ping_interval, guard)| {
+            let usage_ledger = usage_ledger.clone();
+            let rate_limiter = rate_limiter.clone();
+            async move {
             if finished {
                 // 流结束时 guard 会被 drop，active_connections 递减
                 drop(guard);
@@ -442,9 +1139,24 @@ fn create_sse_stream(
                 chunk_result = body_stream.next() => {
                     match chunk_result {
                         Some(Ok(chunk)) => {
-                            // 解码事件
-                            if let Err(e) = decoder.feed(&chunk) {
-                                tracing::warn!("缓冲区溢出: {}", e);
+                            // 解码事件；缓冲区溢出时发送 error 事件并结束流
+                            if let Err(e) = feed_decoder(&mut decoder, &chunk) {
+                                record_usage(
+                                    usage_ledger.as_deref(),
+                                    rate_limiter.as_deref(),
+                                    &ctx.model,
+                                    ctx.final_input_tokens(),
+                                    ctx.output_tokens,
+                                );
+                                let error_event = SseEvent::new(
+                                    "error",
+                                    json!({
+                                        "type": "error",
+                                        "error": {"type": "api_error", "message": e.to_string()}
+                                    }),
+                                );
+                                let bytes = vec![Ok(Bytes::from(error_event.to_sse_string()))];
+                                return Some((stream::iter(bytes), (body_stream, ctx, decoder, true, ping_interval, guard)));
                             }
 
                             let mut events = Vec::new();
@@ -462,18 +1174,49 @@ fn create_sse_stream(
                                 }
                             }
 
+                            // 输出达到 max_tokens 上限或命中 stop_sequences：立即结束流，丢弃上游剩余输出
+                            let output_finished = ctx.output_finished;
+                            if output_finished {
+                                events.extend(ctx.generate_final_events());
+                                record_usage(
+                                    usage_ledger.as_deref(),
+                                    rate_limiter.as_deref(),
+                                    &ctx.model,
+                                    ctx.final_input_tokens(),
+                                    ctx.output_tokens,
+                                );
+                                metrics::OUTPUT_THROUGHPUT.record(&ctx.model, credential_id, ctx.output_tokens, started.elapsed());
+                                log_stream_usage(&ctx);
+                            }
+
                             // 转换为 SSE 字节流
                             let bytes: Vec<Result<Bytes, Infallible>> = events
                                 .into_iter()
                                 .map(|e| Ok(Bytes::from(e.to_sse_string())))
                                 .collect();
 
-                            Some((stream::iter(bytes), (body_stream, ctx, decoder, false, ping_interval, guard)))
+                            Some((stream::iter(bytes), (body_stream, ctx, decoder, output_finished, ping_interval, guard)))
                         }
                         Some(Err(e)) => {
                             tracing::error!("读取响应流失败: {}", e);
-                            // 发送最终事件并结束
-                            let final_events = ctx.generate_final_events();
+                            // 上游停滞超时：发送 error 事件并结束；其他读取错误发送最终事件并结束
+                            let final_events = match e.downcast_ref::<StreamTimeout>() {
+                                Some(timeout) => vec![SseEvent::new(
+                                    "error",
+                                    json!({
+                                        "type": "error",
+                                        "error": {"type": "timeout_error", "message": timeout.client_message()}
+                                    }),
+                                )],
+                                None => ctx.generate_final_events(),
+                            };
+                            record_usage(
+                                usage_ledger.as_deref(),
+                                rate_limiter.as_deref(),
+                                &ctx.model,
+                                ctx.final_input_tokens(),
+                                ctx.output_tokens,
+                            );
                             let bytes: Vec<Result<Bytes, Infallible>> = final_events
                                 .into_iter()
                                 .map(|e| Ok(Bytes::from(e.to_sse_string())))
@@ -483,6 +1226,15 @@ fn create_sse_stream(
                         None => {
                             // 流结束，发送最终事件
                             let final_events = ctx.generate_final_events();
+                            record_usage(
+                                usage_ledger.as_deref(),
+                                rate_limiter.as_deref(),
+                                &ctx.model,
+                                ctx.final_input_tokens(),
+                                ctx.output_tokens,
+                            );
+                            metrics::OUTPUT_THROUGHPUT.record(&ctx.model, credential_id, ctx.output_tokens, started.elapsed());
+                            log_stream_usage(&ctx);
                             let bytes: Vec<Result<Bytes, Infallible>> = final_events
                                 .into_iter()
                                 .map(|e| Ok(Bytes::from(e.to_sse_string())))
@@ -498,6 +1250,7 @@ fn create_sse_stream(
                     Some((stream::iter(bytes), (body_stream, ctx, decoder, false, ping_interval, guard)))
                 }
             }
+            }
         },
     )
     .flatten();
@@ -506,23 +1259,36 @@ fn create_sse_stream(
 }
 
 /// 处理非流式请求
+#[allow(clippy::too_many_arguments)]
 async fn handle_non_stream_request(
     provider: std::sync::Arc<crate::kiro::provider::KiroProvider>,
     request_body: &str,
+    routing: Routing<'_>,
+    message_id: &str,
     model: &str,
     input_tokens: i32,
+    prompt_cache: PromptCacheUsage,
+    max_tokens: i32,
+    context_window: i32,
+    stop_sequences: &[String],
+    footer: Option<&str>,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    rate_limiter: Option<Arc<KeyRateLimiter>>,
 ) -> Response {
     // 调用 Kiro API（支持多凭据故障转移）
-    let response = match provider.call_api(request_body).await {
+    let mut response = match provider.call_api(request_body, routing).await {
         Ok(resp) => resp,
         Err(e) => {
             let error_msg = e.to_string();
             tracing::error!("Kiro API 调用失败: {}", error_msg);
 
+            // 上游维护 / 版本过低：返回专用错误，而非通用 502
+            if let Some(err) = e.downcast_ref::<UpstreamUnavailableError>() {
+                return upstream_unavailable_response(err);
+            }
+
             // 检查是否为token超限错误
-            if is_token_limit_error(&error_msg) {
-                let context_window = super::model_config::get_context_window_size(model);
-                let max_tokens = 8192; // 默认值
+            if is_token_limit_error(&e) {
                 return (
                     StatusCode::BAD_REQUEST,
                     Json(create_token_limit_error(input_tokens, max_tokens, context_window)),
@@ -530,18 +1296,22 @@ async fn handle_non_stream_request(
                     .into_response();
             }
 
-            let (status, error_type) = determine_error_status(&error_msg);
-            return (
-                status,
-                Json(ErrorResponse::new(
-                    error_type,
-                    format!("上游 API 调用失败: {}", error_msg),
-                )),
-            )
-                .into_response();
+            return upstream_api_error_response(&e);
         }
     };
 
+    let failover = response
+        .extensions()
+        .get::<FailoverInfo>()
+        .copied()
+        .unwrap_or_default();
+    tracing::Span::current().record("credential_id", failover.credential_id);
+    audit::note_credential(failover.credential_id);
+    let usage_ledger = usage_ledger.map(|ledger| ledger.for_credential(failover.credential_id));
+
+    // 读取响应体期间仍计入该凭据的活跃连接数
+    let _guard = take_connection_guard(&mut response);
+
     // 读取响应体
     let body_bytes = match response.bytes().await {
         Ok(bytes) => bytes,
@@ -558,16 +1328,29 @@ async fn handle_non_stream_request(
         }
     };
 
-    // 解析事件流
-    let mut decoder = EventStreamDecoder::new();
-    if let Err(e) = decoder.feed(&body_bytes) {
-        tracing::warn!("缓冲区溢出: {}", e);
+    // 解析事件流（分块写入解码器，避免整个响应体一次性占满缓冲区）
+    let config = provider.token_manager().config();
+    let mut decoder = EventStreamDecoder::with_limits(
+        config.decoder_max_buffer_bytes,
+        config.decoder_overflow_policy,
+    );
+    let mut frames = Vec::new();
+    for chunk in body_bytes.chunks(DEFAULT_BUFFER_CAPACITY) {
+        if let Err(e) = feed_decoder(&mut decoder, chunk) {
+            return (
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponse::new("api_error", e.to_string())),
+            )
+                .into_response();
+        }
+        frames.extend(decoder.decode_iter());
     }
 
     let mut text_content = String::new();
     let mut tool_uses: Vec<serde_json::Value> = Vec::new();
     let mut has_tool_use = false;
     let mut stop_reason = "end_turn".to_string();
+    let mut stop_sequence: Option<String> = None;
     // 从 contextUsageEvent 计算的实际输入 tokens
     let mut context_input_tokens: Option<i32> = None;
 
@@ -575,7 +1358,7 @@ async fn handle_non_stream_request(
     let mut tool_json_buffers: std::collections::HashMap<String, String> =
         std::collections::HashMap::new();
 
-    for result in decoder.decode_iter() {
+    for result in frames {
         match result {
             Ok(frame) => {
                 if let Ok(event) = Event::from_frame(frame) {
@@ -613,18 +1396,13 @@ async fn handle_non_stream_request(
                         }
                         Event::ContextUsage(context_usage) => {
                             // 从上下文使用百分比计算实际的 input_tokens
-                            // 获取模型的context window大小
-                            let context_window_size = super::model_config::get_context_window_size(model);
-                            let actual_input_tokens = (context_usage.context_usage_percentage
-                                * (context_window_size as f64)
-                                / 100.0)
-                                as i32;
+                            let actual_input_tokens = context_usage.input_tokens(context_window);
                             context_input_tokens = Some(actual_input_tokens);
                             tracing::info!(
                                 "📊 收到 contextUsageEvent - 百分比: {:.2}%, 计算得出 input_tokens: {} (累积值), context_window: {}",
                                 context_usage.context_usage_percentage,
                                 actual_input_tokens,
-                                context_window_size
+                                context_window
                             );
                         }
                         Event::Exception { exception_type, .. } => {
@@ -642,11 +1420,39 @@ async fn handle_non_stream_request(
         }
     }
 
+    // 代理侧强制执行 max_tokens：上游并不总是遵守请求的输出上限
+    let allowed_len = token::truncate_to_budget(&text_content, max_tokens as i64, |s| {
+        token::count_tokens(s) as i64
+    })
+    .len();
+    if allowed_len < text_content.len() {
+        tracing::info!("输出达到 max_tokens 上限（{}），截断响应", max_tokens);
+        text_content.truncate(allowed_len);
+        tool_uses.clear();
+        stop_reason = "max_tokens".to_string();
+    }
+
+    // 代理侧执行 stop_sequences：在首个命中的序列之前截断
+    if let Some((pos, sequence)) = find_stop_sequence(&text_content, stop_sequences) {
+        tracing::info!("输出命中 stop_sequence {:?}，截断响应", sequence);
+        text_content.truncate(pos);
+        tool_uses.clear();
+        stop_reason = "stop_sequence".to_string();
+        stop_sequence = Some(sequence.to_string());
+    }
+
     // 确定 stop_reason
     if has_tool_use && stop_reason == "end_turn" {
         stop_reason = "tool_use".to_string();
     }
 
+    // 追加页脚（tool_use 结束的响应不是最终回复，不追加）
+    if let Some(footer) = footer
+        && stop_reason != "tool_use"
+    {
+        text_content.push_str(footer);
+    }
+
     // 构建响应内容
     let mut content: Vec<serde_json::Value> = Vec::new();
 
@@ -674,22 +1480,33 @@ async fn handle_non_stream_request(
 
     // 构建 Anthropic 响应
     let response_body = json!({
-        "id": format!("msg_{}", Uuid::new_v4().to_string().replace('-', "")),
+        "id": message_id,
         "type": "message",
         "role": "assistant",
         "content": content,
         "model": model,
         "stop_reason": stop_reason,
-        "stop_sequence": null,
-        "usage": {
-            "input_tokens": final_input_tokens,
-            "output_tokens": output_tokens
-        }
+        "stop_sequence": stop_sequence,
+        "usage": prompt_cache.usage_json(final_input_tokens, output_tokens)
     });
 
-    tracing::debug!("响应 usage 字段: {{ input_tokens: {}, output_tokens: {} }}", final_input_tokens, output_tokens);
+    tracing::info!(input_tokens = final_input_tokens, output_tokens, "响应完成");
+
+    record_usage(
+        usage_ledger.as_deref(),
+        rate_limiter.as_deref(),
+        model,
+        final_input_tokens,
+        output_tokens,
+    );
 
-    (StatusCode::OK, Json(response_body)).into_response()
+    let mut response = (StatusCode::OK, Json(response_body)).into_response();
+    apply_failover_headers(
+        response.headers_mut(),
+        failover,
+        provider.token_manager().config().expose_credential_ids,
+    );
+    response
 }
 
 /// POST /v1/messages/count_tokens
@@ -705,11 +1522,12 @@ pub async fn count_tokens(
     );
 
     let total_tokens = token::count_all_tokens(
-        payload.model,
-        payload.system,
-        payload.messages,
-        payload.tools,
-    ) as i32;
+        &payload.model,
+        payload.system.as_deref(),
+        &payload.messages,
+        payload.tools.as_deref(),
+    )
+    .await as i32;
 
     Json(CountTokensResponse {
         input_tokens: total_tokens.max(1) as i32,
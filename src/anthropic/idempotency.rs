@@ -0,0 +1,205 @@
+//! 幂等请求缓存
+//!
+//! 客户端通过 `Idempotency-Key` 请求头重试非流式请求时，直接返回首次成功的响应，
+//! 避免网络抖动导致的重复上游调用。缓存键按客户端 Key 名称与请求内容隔离：
+//! 其他客户端或内容不同的请求即使使用相同的幂等键也不会命中
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, to_bytes},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::common::cache::FlushableCache;
+use crate::storage::{self, Storage};
+
+/// 幂等缓存使用的存储命名空间
+const NAMESPACE: &str = "idempotency";
+
+/// 缓存有效期（24 小时）
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 幂等键最大长度
+const MAX_KEY_LEN: usize = 256;
+
+/// 可缓存响应体的最大大小（与请求体限制一致）
+const MAX_CACHED_BODY: usize = 50 * 1024 * 1024;
+
+/// 幂等请求缓存
+pub struct IdempotencyCache {
+    storage: Arc<dyn Storage>,
+}
+
+impl IdempotencyCache {
+    /// 基于存储后端创建幂等缓存
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// 从请求头提取幂等键（忽略空值和过长的值）
+    pub fn extract_key(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get("idempotency-key")
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LEN)
+            .map(str::to_string)
+    }
+
+    /// 计算存储键：`sha256(客户端 Key 名称, 幂等键, 请求内容哈希)`
+    pub fn scoped_key(client: &str, idempotency_key: &str, request_hash: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(client.as_bytes())
+            .chain_update([0])
+            .chain_update(idempotency_key.as_bytes())
+            .chain_update([0])
+            .chain_update(request_hash.as_bytes())
+            .finalize();
+        hex::encode(digest)
+    }
+
+    /// 查找已缓存的响应
+    pub async fn lookup(&self, key: &str) -> Option<Response> {
+        let lookup_key = key.to_string();
+        let cached =
+            storage::run_blocking(&self.storage, move |s| s.get(NAMESPACE, &lookup_key)).await;
+        match cached {
+            Ok(Some(body)) => {
+                tracing::info!("命中幂等缓存: {}", key);
+                Some(
+                    (
+                        StatusCode::OK,
+                        [
+                            (header::CONTENT_TYPE, "application/json"),
+                            (
+                                header::HeaderName::from_static("idempotent-replayed"),
+                                "true",
+                            ),
+                        ],
+                        body,
+                    )
+                        .into_response(),
+                )
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("读取幂等缓存失败: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 缓存成功的响应并原样返回（非 200 响应不缓存）
+    pub async fn store(&self, key: &str, response: Response) -> Response {
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let bytes = match to_bytes(body, MAX_CACHED_BODY).await {
+            Ok(b) => b,
+            Err(e) => {
+                tracing::warn!("读取响应体失败，无法写入幂等缓存: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "读取响应体失败").into_response();
+            }
+        };
+
+        match std::str::from_utf8(&bytes) {
+            Ok(text) => {
+                let (key, text) = (key.to_string(), text.to_string());
+                let stored = storage::run_blocking(&self.storage, move |s| {
+                    s.put(NAMESPACE, &key, &text, Some(CACHE_TTL))
+                })
+                .await;
+                if let Err(e) = stored {
+                    tracing::warn!("写入幂等缓存失败: {}", e);
+                }
+            }
+            Err(_) => tracing::warn!("响应体不是有效的 UTF-8，跳过幂等缓存"),
+        }
+
+        Response::from_parts(parts, Body::from(bytes))
+    }
+}
+
+impl FlushableCache for IdempotencyCache {
+    fn flush(&self) -> anyhow::Result<usize> {
+        self.storage.clear(NAMESPACE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_extract_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(IdempotencyCache::extract_key(&headers), None);
+
+        headers.insert("idempotency-key", HeaderValue::from_static("  abc "));
+        assert_eq!(
+            IdempotencyCache::extract_key(&headers).as_deref(),
+            Some("abc")
+        );
+
+        headers.insert("idempotency-key", HeaderValue::from_static(""));
+        assert_eq!(IdempotencyCache::extract_key(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn test_store_and_lookup() {
+        let cache = IdempotencyCache::new(Arc::new(MemoryStorage::new()));
+        assert!(cache.lookup("k1").await.is_none());
+
+        let response = (StatusCode::OK, "{\"id\":\"msg_1\"}").into_response();
+        let response = cache.store("k1", response).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let replayed = cache.lookup("k1").await.unwrap();
+        let body = to_bytes(replayed.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"{\"id\":\"msg_1\"}");
+    }
+
+    #[test]
+    fn test_scoped_key_isolates_clients_and_requests() {
+        let key = IdempotencyCache::scoped_key("team-a", "retry-1", "hash-1");
+        assert_eq!(
+            key,
+            IdempotencyCache::scoped_key("team-a", "retry-1", "hash-1")
+        );
+        assert_ne!(
+            key,
+            IdempotencyCache::scoped_key("team-b", "retry-1", "hash-1")
+        );
+        assert_ne!(
+            key,
+            IdempotencyCache::scoped_key("team-a", "retry-1", "hash-2")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_response_not_cached() {
+        let cache = IdempotencyCache::new(Arc::new(MemoryStorage::new()));
+        let response = (StatusCode::BAD_GATEWAY, "error").into_response();
+        cache.store("k2", response).await;
+        assert!(cache.lookup("k2").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        let cache = IdempotencyCache::new(Arc::new(MemoryStorage::new()));
+        cache
+            .store("k3", (StatusCode::OK, "{}").into_response())
+            .await;
+        assert_eq!(cache.flush().unwrap(), 1);
+        assert!(cache.lookup("k3").await.is_none());
+    }
+}
@@ -0,0 +1,72 @@
+//! 客户端身份标记
+//!
+//! Kiro 请求中没有可自由填写的元数据字段，唯一不影响会话语义的标识是每次请求随机生成的
+//! `agentContinuationId`（UUID）。配置 `clientIdentitySalt` 后，将其前 8 位十六进制替换为
+//! 加盐哈希后的 API Key 标记：上游反馈滥用问题时可据此关联到内部调用方，且不暴露原始 Key
+
+use axum::http::HeaderMap;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::common::auth;
+use crate::model::config::Config;
+
+/// 标记长度（十六进制字符数，对应 UUID 的第一段）
+const TAG_LEN: usize = 8;
+
+/// 解析本次请求的客户端标记（未配置 `clientIdentitySalt` 或缺少 API Key 时为 None）
+pub fn resolve(config: &Config, headers: &HeaderMap) -> Option<String> {
+    let salt = config.client_identity_salt.as_deref()?;
+    let key = auth::extract_api_key_from_headers(headers)?;
+    Some(client_tag(salt, &key))
+}
+
+/// 计算 API Key 的客户端标记：`sha256(salt + key)` 的前 8 位十六进制
+pub fn client_tag(salt: &str, api_key: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(api_key.as_bytes())
+        .finalize();
+    hex::encode(digest)[..TAG_LEN].to_string()
+}
+
+/// 生成携带客户端标记的 agentContinuationId（其余部分保持随机，格式仍为 UUID v4）
+pub fn tagged_continuation_id(tag: &str) -> String {
+    let id = Uuid::new_v4().to_string();
+    format!("{}{}", tag, &id[TAG_LEN..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_tagged_continuation_id() {
+        let tag = client_tag("salt", "sk-test");
+        assert_eq!(tag.len(), TAG_LEN);
+        assert_eq!(tag, client_tag("salt", "sk-test"));
+        assert_ne!(tag, client_tag("other-salt", "sk-test"));
+
+        let id = tagged_continuation_id(&tag);
+        assert!(id.starts_with(&tag));
+        assert_eq!(Uuid::parse_str(&id).unwrap().get_version_num(), 4);
+        assert_ne!(id, tagged_continuation_id(&tag));
+    }
+
+    #[test]
+    fn test_resolve_requires_salt() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-test"));
+        assert_eq!(resolve(&Config::default(), &headers), None);
+
+        let config = Config {
+            client_identity_salt: Some("salt".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            resolve(&config, &headers),
+            Some(client_tag("salt", "sk-test"))
+        );
+    }
+}
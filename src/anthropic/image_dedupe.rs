@@ -0,0 +1,188 @@
+//! 对话内图片去重
+//!
+//! Agent 类客户端常在每一轮重复发送同一张截图，而每次请求都携带完整历史，
+//! 同一张图片会在请求体中出现多次。这里按内容哈希识别重复图片：仅保留首次出现的图片，
+//! 之后的重复图片替换为指向首次出现位置的简短文本引用，大幅缩小请求体
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use sha2::{Digest, Sha256};
+
+use crate::kiro::model::requests::conversation::{ConversationState, KiroImage, Message};
+
+/// 图片首次出现的位置（均从 1 开始）
+#[derive(Debug, Clone, Copy)]
+struct FirstSeen {
+    /// 第几条用户消息
+    turn: usize,
+    /// 该消息中保留的第几张图片
+    index: usize,
+}
+
+/// 去重统计
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DedupeStats {
+    /// 被替换为文本引用的图片数
+    pub replaced: usize,
+    /// 节省的 base64 数据字节数
+    pub saved_bytes: usize,
+}
+
+/// 对话中的重复图片替换为文本引用（按历史消息、当前消息的顺序，首次出现的图片保留）
+pub fn dedupe_images(state: &mut ConversationState) -> DedupeStats {
+    let mut seen = HashMap::new();
+    let mut stats = DedupeStats::default();
+    let mut turn = 0;
+
+    for message in &mut state.history {
+        if let Message::User(user) = message {
+            turn += 1;
+            let msg = &mut user.user_input_message;
+            dedupe_turn(
+                &mut msg.images,
+                &mut msg.content,
+                turn,
+                &mut seen,
+                &mut stats,
+            );
+        }
+    }
+    let current = &mut state.current_message.user_input_message;
+    dedupe_turn(
+        &mut current.images,
+        &mut current.content,
+        turn + 1,
+        &mut seen,
+        &mut stats,
+    );
+
+    if stats.replaced > 0 {
+        tracing::info!(
+            "图片去重: {} 张重复图片已替换为引用，节省 {:.2} KB",
+            stats.replaced,
+            stats.saved_bytes as f64 / 1024.0
+        );
+    }
+    stats
+}
+
+/// 处理单条用户消息中的图片
+fn dedupe_turn(
+    images: &mut Vec<KiroImage>,
+    content: &mut String,
+    turn: usize,
+    seen: &mut HashMap<[u8; 32], FirstSeen>,
+    stats: &mut DedupeStats,
+) {
+    let mut kept = 0;
+    let mut references = Vec::new();
+
+    images.retain(|image| match seen.entry(image_digest(image)) {
+        Entry::Occupied(first) => {
+            let first = first.get();
+            references.push(format!(
+                "[Image omitted: same as image {} in user message {}]",
+                first.index, first.turn
+            ));
+            stats.replaced += 1;
+            stats.saved_bytes += image.source.bytes.len();
+            false
+        }
+        Entry::Vacant(slot) => {
+            kept += 1;
+            slot.insert(FirstSeen { turn, index: kept });
+            true
+        }
+    });
+
+    for reference in references {
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content.push_str(&reference);
+    }
+}
+
+/// 图片内容哈希（格式 + base64 数据）
+fn image_digest(image: &KiroImage) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(image.format.as_bytes())
+        .chain_update(b":")
+        .chain_update(image.source.bytes.as_bytes())
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::model::requests::conversation::{
+        CurrentMessage, HistoryAssistantMessage, HistoryUserMessage, UserInputMessage, UserMessage,
+    };
+
+    fn user(content: &str, images: &[&str]) -> Message {
+        let images = images
+            .iter()
+            .map(|data| KiroImage::from_base64("png", *data))
+            .collect();
+        Message::User(HistoryUserMessage {
+            user_input_message: UserMessage::new(content, "m").with_images(images),
+        })
+    }
+
+    #[test]
+    fn test_dedupe_images_across_turns() {
+        let current = UserInputMessage::new("again", "m").with_images(vec![
+            KiroImage::from_base64("png", "BBBB"),
+            KiroImage::from_base64("png", "CCCC"),
+        ]);
+        let mut state = ConversationState::new("c")
+            .with_history(vec![
+                user("look", &["AAAA", "BBBB"]),
+                Message::Assistant(HistoryAssistantMessage::new("ok")),
+                user("", &["AAAA"]),
+                Message::Assistant(HistoryAssistantMessage::new("ok")),
+            ])
+            .with_current_message(CurrentMessage::new(current));
+
+        let stats = dedupe_images(&mut state);
+        assert_eq!(
+            stats,
+            DedupeStats {
+                replaced: 2,
+                saved_bytes: 8
+            }
+        );
+
+        let Message::User(second) = &state.history[2] else {
+            panic!("expected user message");
+        };
+        assert!(second.user_input_message.images.is_empty());
+        assert_eq!(
+            second.user_input_message.content,
+            "[Image omitted: same as image 1 in user message 1]"
+        );
+
+        let current = &state.current_message.user_input_message;
+        assert_eq!(current.images.len(), 1);
+        assert_eq!(current.images[0].source.bytes, "CCCC");
+        assert_eq!(
+            current.content,
+            "again\n[Image omitted: same as image 2 in user message 1]"
+        );
+    }
+
+    #[test]
+    fn test_same_data_different_format_is_distinct() {
+        let mut state = ConversationState::new("c").with_history(vec![
+            user("", &["AAAA"]),
+            Message::Assistant(HistoryAssistantMessage::new("ok")),
+        ]);
+        state.current_message = CurrentMessage::new(
+            UserInputMessage::new("hi", "m")
+                .with_images(vec![KiroImage::from_base64("jpeg", "AAAA")]),
+        );
+        assert_eq!(dedupe_images(&mut state).replaced, 0);
+    }
+}
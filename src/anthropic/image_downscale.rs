@@ -0,0 +1,232 @@
+//! 超限图片缩小
+//!
+//! 截图等大尺寸图片的 base64 数据很容易让请求体超过 Kiro API 的上限（约 2MB）。
+//! 转发前检查每张图片：长边超过 `maxDimension` 时等比缩小，数据仍超过 `maxBytes` 时继续缩小，
+//! 并重新编码（不透明图片编码为 JPEG，带透明通道的编码为 PNG）。
+//! 解码与编码在阻塞线程池中进行；无法解码的图片、处理后没有变小的图片保持原样
+
+use std::io::Cursor;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+
+use crate::kiro::model::requests::conversation::{ConversationState, KiroImage, Message};
+use crate::model::config::ImageDownscaleConfig;
+
+/// 重新编码为 JPEG 时的质量
+const JPEG_QUALITY: u8 = 85;
+
+/// 数据仍超过上限时继续缩小的最多次数
+const MAX_SHRINK_ATTEMPTS: usize = 4;
+
+/// 缩小统计
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DownscaleStats {
+    /// 被缩小的图片数
+    pub resized: usize,
+    /// 节省的 base64 数据字节数
+    pub saved_bytes: usize,
+}
+
+/// 缩小对话中超限的图片（历史消息与当前消息）
+pub async fn downscale_images(
+    state: &mut ConversationState,
+    config: &ImageDownscaleConfig,
+) -> DownscaleStats {
+    let mut stats = DownscaleStats::default();
+    if !config.enabled {
+        return stats;
+    }
+    let images: Vec<KiroImage> = images_mut(state).map(|image| image.clone()).collect();
+    if images.is_empty() {
+        return stats;
+    }
+
+    let config = config.clone();
+    let replacements = match tokio::task::spawn_blocking(move || {
+        images
+            .iter()
+            .map(|image| match downscale(image, &config) {
+                Ok(replacement) => replacement,
+                Err(e) => {
+                    tracing::warn!("缩小 {} 图片失败，按原样转发: {}", image.format, e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    {
+        Ok(replacements) => replacements,
+        Err(e) => {
+            tracing::warn!("缩小图片的任务异常退出，按原样转发: {}", e);
+            return stats;
+        }
+    };
+
+    for (image, replacement) in images_mut(state).zip(replacements) {
+        if let Some(replacement) = replacement {
+            stats.resized += 1;
+            stats.saved_bytes += image.source.bytes.len() - replacement.source.bytes.len();
+            *image = replacement;
+        }
+    }
+    if stats.resized > 0 {
+        tracing::info!(
+            "图片缩小: {} 张超限图片已缩小，节省 {:.2} KB",
+            stats.resized,
+            stats.saved_bytes as f64 / 1024.0
+        );
+    }
+    stats
+}
+
+/// 按历史消息、当前消息的顺序遍历所有图片
+fn images_mut(state: &mut ConversationState) -> impl Iterator<Item = &mut KiroImage> {
+    state
+        .history
+        .iter_mut()
+        .filter_map(|message| match message {
+            Message::User(user) => Some(&mut user.user_input_message.images),
+            Message::Assistant(_) => None,
+        })
+        .chain(std::iter::once(
+            &mut state.current_message.user_input_message.images,
+        ))
+        .flatten()
+}
+
+/// 缩小单张图片，未超限或处理后没有变小时返回 None
+fn downscale(
+    image: &KiroImage,
+    config: &ImageDownscaleConfig,
+) -> anyhow::Result<Option<KiroImage>> {
+    let bytes = BASE64.decode(&image.source.bytes)?;
+    let (width, height) = ImageReader::new(Cursor::new(&bytes))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    let max_dimension = config.max_dimension.max(1);
+    if bytes.len() <= config.max_bytes && width.max(height) <= max_dimension {
+        return Ok(None);
+    }
+
+    let mut img = image::load_from_memory(&bytes)?;
+    if width.max(height) > max_dimension {
+        img = img.resize(max_dimension, max_dimension, FilterType::Triangle);
+    }
+    let (mut format, mut encoded) = encode(&img)?;
+    for _ in 0..MAX_SHRINK_ATTEMPTS {
+        if encoded.len() <= config.max_bytes {
+            break;
+        }
+        // 编码后的大小大致与像素数成正比
+        let scale = (config.max_bytes as f64 / encoded.len() as f64).sqrt() * 0.9;
+        let target_width = ((img.width() as f64 * scale) as u32).max(1);
+        let target_height = ((img.height() as f64 * scale) as u32).max(1);
+        img = img.resize(target_width, target_height, FilterType::Triangle);
+        (format, encoded) = encode(&img)?;
+    }
+
+    if encoded.len() >= bytes.len() {
+        return Ok(None);
+    }
+    tracing::debug!(
+        "图片 {}x{}（{} bytes）缩小为 {}x{} {}（{} bytes）",
+        width,
+        height,
+        bytes.len(),
+        img.width(),
+        img.height(),
+        format,
+        encoded.len()
+    );
+    Ok(Some(KiroImage::from_base64(format, BASE64.encode(encoded))))
+}
+
+/// 不透明图片编码为 JPEG，带透明通道的编码为 PNG
+fn encode(img: &DynamicImage) -> image::ImageResult<(&'static str, Vec<u8>)> {
+    let mut out = Vec::new();
+    if img.color().has_alpha() {
+        img.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)?;
+        Ok(("png", out))
+    } else {
+        JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode_image(&img.to_rgb8())?;
+        Ok(("jpeg", out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::model::requests::conversation::CurrentMessage;
+    use crate::kiro::model::requests::conversation::UserInputMessage;
+    use image::{Rgb, RgbImage};
+
+    /// 生成带渐变与噪点的 PNG 图片（base64）
+    fn png(width: u32, height: u32) -> String {
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x % 256) as u8,
+                (y % 256) as u8,
+                ((x * 7 + y * 13) % 256) as u8,
+            ])
+        });
+        let mut out = Vec::new();
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut Cursor::new(&mut out), ImageFormat::Png)
+            .unwrap();
+        BASE64.encode(out)
+    }
+
+    fn dimensions(image: &KiroImage) -> (u32, u32) {
+        let bytes = BASE64.decode(&image.source.bytes).unwrap();
+        image::load_from_memory(&bytes)
+            .unwrap()
+            .into_rgb8()
+            .dimensions()
+    }
+
+    #[tokio::test]
+    async fn test_downscale_oversized_images_only() {
+        let large = png(2000, 1000);
+        let small = png(64, 64);
+        let current = UserInputMessage::new("look", "m").with_images(vec![
+            KiroImage::from_base64("png", large.clone()),
+            KiroImage::from_base64("png", small.clone()),
+            KiroImage::from_base64("png", "not an image"),
+        ]);
+        let mut state =
+            ConversationState::new("c").with_current_message(CurrentMessage::new(current));
+        let config = ImageDownscaleConfig::default();
+
+        let stats = downscale_images(&mut state, &config).await;
+        assert_eq!(stats.resized, 1);
+        let images = &state.current_message.user_input_message.images;
+        assert_eq!(images[0].format, "jpeg");
+        let (width, height) = dimensions(&images[0]);
+        assert!(width <= 1568 && height <= 784);
+        assert!(BASE64.decode(&images[0].source.bytes).unwrap().len() <= config.max_bytes);
+        assert_eq!(
+            stats.saved_bytes,
+            large.len() - images[0].source.bytes.len()
+        );
+        assert_eq!(images[1].source.bytes, small);
+        assert_eq!(images[2].source.bytes, "not an image");
+    }
+
+    #[tokio::test]
+    async fn test_downscale_shrinks_until_under_byte_limit() {
+        let image = KiroImage::from_base64("png", png(800, 800));
+        let config = ImageDownscaleConfig {
+            max_bytes: 20 * 1024,
+            ..ImageDownscaleConfig::default()
+        };
+        let resized = downscale(&image, &config).unwrap().unwrap();
+        assert!(BASE64.decode(&resized.source.bytes).unwrap().len() <= config.max_bytes);
+        let (width, height) = dimensions(&resized);
+        assert_eq!(width, height);
+        assert!(width < 800);
+    }
+}
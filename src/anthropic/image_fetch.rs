@@ -0,0 +1,493 @@
+//! URL 图片下载
+//!
+//! Kiro API 只接受内联的 base64 图片。消息中 `source.type == "url"` 的图片块在请求转换前下载，
+//! 并替换为 base64 来源；同一请求中重复出现的 URL 只下载一次。
+//! 下载受 `imageFetch` 配置约束：
+//! - 仅允许 http / https，拒绝 localhost 与内网、回环等地址（域名在解析后、连接前校验，
+//!   重定向的每一跳同样校验）
+//! - 响应的 Content-Type 必须是 JPEG / PNG / GIF / WebP
+//! - 超过 `maxBytes` 或 `timeoutSecs` 时中止；单个请求最多 `maxUrls` 个 URL，并发下载
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use futures::{StreamExt, TryStreamExt, stream};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{Client, StatusCode, Url, header, redirect};
+use serde_json::{Value, json};
+
+use super::converter::get_image_format;
+use super::types::MessagesRequest;
+use crate::http_client::{ProxyConfig, client_builder};
+use crate::model::config::{Config, ImageFetchConfig, TlsBackend};
+
+/// 同一请求内的并发下载数
+const FETCH_CONCURRENCY: usize = 4;
+
+/// 最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 5;
+
+/// URL 图片下载错误
+#[derive(Debug)]
+pub enum ImageFetchError {
+    /// URL 无效或指向不允许访问的地址
+    InvalidUrl(String),
+    /// 请求失败（连接失败、超时等）
+    Request { url: String, error: reqwest::Error },
+    /// 响应状态码不是 2xx
+    Status { url: String, status: StatusCode },
+    /// 响应不是支持的图片类型
+    UnsupportedType { url: String, content_type: String },
+    /// 图片超过大小上限
+    TooLarge { url: String, max_bytes: usize },
+    /// 请求中的图片 URL 数超过上限
+    TooManyUrls { count: usize, max_urls: usize },
+    /// 创建 HTTP 客户端失败
+    Client(anyhow::Error),
+}
+
+impl std::fmt::Display for ImageFetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageFetchError::InvalidUrl(url) => write!(f, "不支持的图片 URL: {}", url),
+            ImageFetchError::Request { url, error } => {
+                write!(f, "下载图片失败: {} ({})", url, error)
+            }
+            ImageFetchError::Status { url, status } => {
+                write!(f, "下载图片失败: {} 返回 {}", url, status)
+            }
+            ImageFetchError::UnsupportedType { url, content_type } => {
+                write!(f, "不支持的图片类型: {} ({})", content_type, url)
+            }
+            ImageFetchError::TooLarge { url, max_bytes } => {
+                write!(f, "图片超过 {} 字节上限: {}", max_bytes, url)
+            }
+            ImageFetchError::TooManyUrls { count, max_urls } => {
+                write!(f, "图片 URL 数量 {} 超过上限 {}", count, max_urls)
+            }
+            ImageFetchError::Client(e) => write!(f, "创建 HTTP 客户端失败: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImageFetchError {}
+
+/// 下载消息中的 URL 图片并替换为 base64 来源，返回替换的图片块数量
+///
+/// 未启用时保持原样（请求转换时以文本引用的形式转发）
+pub async fn resolve_image_urls(
+    config: &Config,
+    req: &mut MessagesRequest,
+) -> Result<usize, ImageFetchError> {
+    let fetch_config = &config.image_fetch;
+    if !fetch_config.enabled {
+        return Ok(0);
+    }
+    let mut urls: Vec<String> = req
+        .messages
+        .iter()
+        .filter_map(|message| message.content.as_array())
+        .flatten()
+        .filter_map(image_url)
+        .map(str::to_string)
+        .collect();
+    if urls.is_empty() {
+        return Ok(0);
+    }
+    urls.sort();
+    urls.dedup();
+    if urls.len() > fetch_config.max_urls {
+        return Err(ImageFetchError::TooManyUrls {
+            count: urls.len(),
+            max_urls: fetch_config.max_urls,
+        });
+    }
+    for url in &urls {
+        check_url(url)?;
+        check_resolved(url).await?;
+    }
+
+    let client = shared_client(config)?;
+    let fetched: Vec<(String, (String, Vec<u8>))> = stream::iter(urls)
+        .map(|url| {
+            let client = client.clone();
+            let fetch_config = fetch_config.clone();
+            async move {
+                let image = fetch_image(&client, &url, &fetch_config).await?;
+                Ok((url, image))
+            }
+        })
+        .buffer_unordered(FETCH_CONCURRENCY)
+        .try_collect()
+        .await?;
+    let mut sources = HashMap::new();
+    for (url, (media_type, data)) in &fetched {
+        tracing::info!(
+            "已下载 URL 图片: {} ({}, {} bytes)",
+            url,
+            media_type,
+            data.len()
+        );
+        sources.insert(url.as_str(), (media_type, BASE64.encode(data)));
+    }
+
+    let mut resolved = 0;
+    for message in &mut req.messages {
+        let Value::Array(blocks) = &mut message.content else {
+            continue;
+        };
+        for block in blocks.iter_mut() {
+            let Some((media_type, data)) = image_url(block).and_then(|url| sources.get(url)) else {
+                continue;
+            };
+            block["source"] = json!({
+                "type": "base64",
+                "media_type": media_type,
+                "data": data
+            });
+            resolved += 1;
+        }
+    }
+    Ok(resolved)
+}
+
+/// 提取 URL 来源图片块的 URL
+fn image_url(block: &Value) -> Option<&str> {
+    if block.get("type")?.as_str()? != "image" {
+        return None;
+    }
+    let source = block.get("source")?;
+    if source.get("type")?.as_str()? != "url" {
+        return None;
+    }
+    source.get("url")?.as_str()
+}
+
+/// 下载客户端的构建参数，变化时（如热重载修改代理）重建客户端
+#[derive(Clone, PartialEq)]
+struct ClientSettings {
+    proxy: Option<ProxyConfig>,
+    timeout_secs: u64,
+    tls_backend: TlsBackend,
+}
+
+/// 复用的下载客户端
+static CLIENT: Mutex<Option<(ClientSettings, Client)>> = Mutex::new(None);
+
+/// 获取复用的下载客户端（参数变化时重建）
+fn shared_client(config: &Config) -> Result<Client, ImageFetchError> {
+    let settings = ClientSettings {
+        proxy: ProxyConfig::from_config(config),
+        timeout_secs: config.image_fetch.timeout_secs,
+        tls_backend: config.tls_backend,
+    };
+    let mut cached = CLIENT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached_settings, client)) = cached.as_ref()
+        && *cached_settings == settings
+    {
+        return Ok(client.clone());
+    }
+    let client = build_client(&settings).map_err(ImageFetchError::Client)?;
+    *cached = Some((settings, client.clone()));
+    Ok(client)
+}
+
+/// 构建下载客户端
+///
+/// 直连时使用只返回公网地址的 DNS 解析器，连接前校验解析结果（包括重定向后的域名），
+/// 避免域名指向内网地址或 DNS 重绑定。经代理访问时由代理解析域名，无法在连接时校验，
+/// 因此不跟随重定向（仅有 [`check_resolved`] 的预先校验）
+fn build_client(settings: &ClientSettings) -> anyhow::Result<Client> {
+    let builder = client_builder(
+        settings.proxy.as_ref(),
+        settings.timeout_secs,
+        Some(Duration::from_secs(settings.timeout_secs)),
+        settings.tls_backend,
+    )?;
+    let builder = if settings.proxy.is_some() {
+        builder.redirect(redirect::Policy::none())
+    } else {
+        builder
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("重定向次数过多")
+                } else if check_url(attempt.url().as_str()).is_err() {
+                    attempt.error("重定向到不允许访问的地址")
+                } else {
+                    attempt.follow()
+                }
+            }))
+    };
+    Ok(builder.build()?)
+}
+
+/// 只返回公网地址的 DNS 解析器
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// 解析域名，任一地址不是公网地址时拒绝
+async fn resolve_public(host: &str) -> std::io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{} 解析到非公网地址", host),
+        ));
+    }
+    Ok(addrs)
+}
+
+/// 预先解析 URL 中的域名并校验（IP 字面量已由 [`check_url`] 校验）
+async fn check_resolved(url: &str) -> Result<(), ImageFetchError> {
+    let invalid = || ImageFetchError::InvalidUrl(url.to_string());
+    let parsed = Url::parse(url).map_err(|_| invalid())?;
+    match parsed.domain() {
+        Some(domain) => resolve_public(domain)
+            .await
+            .map(|_| ())
+            .map_err(|_| invalid()),
+        None => Ok(()),
+    }
+}
+
+/// 校验 URL：仅允许 http / https，拒绝 localhost 与非公网 IP 字面量
+fn check_url(url: &str) -> Result<(), ImageFetchError> {
+    let invalid = || ImageFetchError::InvalidUrl(url.to_string());
+    let parsed = Url::parse(url).map_err(|_| invalid())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(invalid());
+    }
+    let host = parsed.host_str().ok_or_else(invalid)?;
+    let allowed = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => is_public(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        }
+    };
+    if allowed { Ok(()) } else { Err(invalid()) }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast())
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let segment = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (segment & 0xfe00) == 0xfc00
+                    || (segment & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// 下载单张图片，返回 media type 与图片数据
+async fn fetch_image(
+    client: &Client,
+    url: &str,
+    config: &ImageFetchConfig,
+) -> Result<(String, Vec<u8>), ImageFetchError> {
+    let request_error = |error| ImageFetchError::Request {
+        url: url.to_string(),
+        error,
+    };
+    let too_large = || ImageFetchError::TooLarge {
+        url: url.to_string(),
+        max_bytes: config.max_bytes,
+    };
+
+    let mut response = client.get(url).send().await.map_err(request_error)?;
+    if !response.status().is_success() {
+        return Err(ImageFetchError::Status {
+            url: url.to_string(),
+            status: response.status(),
+        });
+    }
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if get_image_format(&media_type).is_none() {
+        return Err(ImageFetchError::UnsupportedType {
+            url: url.to_string(),
+            content_type: content_type.to_string(),
+        });
+    }
+    if response
+        .content_length()
+        .is_some_and(|len| len > config.max_bytes as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(request_error)? {
+        if data.len() + chunk.len() > config.max_bytes {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok((media_type, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::HeaderValue, routing::get};
+
+    /// 启动本地图片服务器，返回其地址
+    async fn serve() -> String {
+        let app = Router::new()
+            .route(
+                "/cat.png",
+                get(|| async {
+                    (
+                        [(header::CONTENT_TYPE, HeaderValue::from_static("image/png"))],
+                        vec![0x89u8, b'P', b'N', b'G'],
+                    )
+                }),
+            )
+            .route(
+                "/page",
+                get(|| async { ([(header::CONTENT_TYPE, "text/html")], "<html></html>") }),
+            )
+            .route(
+                "/redirect",
+                get(|headers: axum::http::HeaderMap| async move {
+                    let host = headers[header::HOST].to_str().unwrap().to_string();
+                    axum::response::Redirect::temporary(&format!("http://{}/cat.png", host))
+                }),
+            )
+            .route(
+                "/huge.jpg",
+                get(|| async { ([(header::CONTENT_TYPE, "image/jpeg")], vec![0u8; 4096]) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_image_validates_type_and_size() {
+        let base = serve().await;
+        let client = Client::new();
+        let config = ImageFetchConfig {
+            max_bytes: 1024,
+            ..ImageFetchConfig::default()
+        };
+
+        let (media_type, data) = fetch_image(&client, &format!("{}/cat.png", base), &config)
+            .await
+            .unwrap();
+        assert_eq!(media_type, "image/png");
+        assert_eq!(data, b"\x89PNG");
+
+        let err = fetch_image(&client, &format!("{}/page", base), &config).await;
+        assert!(matches!(err, Err(ImageFetchError::UnsupportedType { .. })));
+        let err = fetch_image(&client, &format!("{}/huge.jpg", base), &config).await;
+        assert!(matches!(err, Err(ImageFetchError::TooLarge { .. })));
+        let err = fetch_image(&client, &format!("{}/missing.png", base), &config).await;
+        assert!(matches!(err, Err(ImageFetchError::Status { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_internal_address_is_rejected() {
+        let base = serve().await;
+        let client = build_client(&ClientSettings {
+            proxy: None,
+            timeout_secs: 5,
+            tls_backend: TlsBackend::Rustls,
+        })
+        .unwrap();
+        let config = ImageFetchConfig::default();
+
+        let err = fetch_image(&client, &format!("{}/redirect", base), &config).await;
+        assert!(matches!(err, Err(ImageFetchError::Request { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_rejects_hosts_resolving_to_internal_addresses() {
+        assert!(resolve_public("localhost").await.is_err());
+        assert!(check_resolved("http://localhost./cat.png").await.is_err());
+        assert!(check_resolved("http://93.184.216.34/cat.png").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_too_many_urls() {
+        let config = Config {
+            image_fetch: ImageFetchConfig {
+                max_urls: 1,
+                ..ImageFetchConfig::default()
+            },
+            ..Config::default()
+        };
+        let image = |url: &str| json!({"type": "image", "source": {"type": "url", "url": url}});
+        let mut req: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{"role": "user", "content": [
+                image("https://example.com/a.png"),
+                image("https://example.com/b.png"),
+                image("https://example.com/a.png")
+            ]}]
+        }))
+        .unwrap();
+
+        let err = resolve_image_urls(&config, &mut req).await;
+        assert!(matches!(
+            err,
+            Err(ImageFetchError::TooManyUrls {
+                count: 2,
+                max_urls: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_url_rejects_internal_addresses() {
+        assert!(check_url("https://example.com/cat.png").is_ok());
+        assert!(check_url("http://93.184.216.34/cat.png").is_ok());
+        for url in [
+            "file:///etc/passwd",
+            "ftp://example.com/cat.png",
+            "http://localhost:8080/cat.png",
+            "http://127.0.0.1/cat.png",
+            "http://10.0.0.1/cat.png",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/cat.png",
+            "http://[::ffff:192.168.1.1]/cat.png",
+            "not a url",
+        ] {
+            assert!(check_url(url).is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! 提示词注入选择
+//!
+//! 默认的按模型注入由 `systemPromptTemplates` 完成（Opus 专业助手提示词即内置的 `opus` 模板）；
+//! 受信任的客户端可以通过 `x-kiro-inject` 请求头按请求覆盖模板：关闭注入（`none`）或选择指定的提示词配置
+
+use axum::http::HeaderMap;
+
+use crate::model::config::Config;
+
+use super::converter::PROFESSIONAL_SYSTEM_PROMPT;
+
+/// 注入选择请求头
+pub const INJECT_HEADER: &str = "x-kiro-inject";
+
+/// 内置提示词配置名称（即专业助手提示词）
+pub const BUILTIN_PROFILE: &str = "professional";
+
+/// 客户端通过请求头指定的注入选择
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectSelection {
+    /// 关闭所有提示词注入
+    None,
+    /// 使用指定名称的提示词配置
+    Profile(String),
+}
+
+/// 从请求头解析注入选择（未设置或为空时返回 None，表示使用默认规则）
+pub fn parse_header(headers: &HeaderMap) -> Option<InjectSelection> {
+    let value = headers.get(INJECT_HEADER)?.to_str().ok()?.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if value.eq_ignore_ascii_case("none") {
+        Some(InjectSelection::None)
+    } else {
+        Some(InjectSelection::Profile(value.to_string()))
+    }
+}
+
+/// 专业助手提示词（优先使用 `opusPromptFile` 中的内容）
+fn professional_prompt(config: &Config) -> String {
+    config
+        .opus_prompt
+        .clone()
+        .unwrap_or_else(|| PROFESSIONAL_SYSTEM_PROMPT.to_string())
+}
+
+/// 解析请求头选择的提示词
+///
+/// - 未指定：不注入（由系统提示词模板决定）
+/// - `none`：不注入
+/// - 配置名称：优先查找 `promptProfiles`，其次为内置配置
+///
+/// 配置名称不存在时返回 Err(名称)
+pub fn resolve_prompt(
+    selection: Option<&InjectSelection>,
+    config: &Config,
+) -> Result<Option<String>, String> {
+    match selection {
+        None | Some(InjectSelection::None) => Ok(None),
+        Some(InjectSelection::Profile(name)) => {
+            if let Some(prompt) = config.prompt_profiles.get(name) {
+                Ok(Some(prompt.clone()))
+            } else if name == BUILTIN_PROFILE {
+                Ok(Some(professional_prompt(config)))
+            } else {
+                Err(name.clone())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_parse_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_header(&headers), None);
+
+        headers.insert(INJECT_HEADER, HeaderValue::from_static("NONE"));
+        assert_eq!(parse_header(&headers), Some(InjectSelection::None));
+
+        headers.insert(INJECT_HEADER, HeaderValue::from_static(" coder "));
+        assert_eq!(
+            parse_header(&headers),
+            Some(InjectSelection::Profile("coder".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_resolve_without_header() {
+        let config = Config {
+            opus_prompt_injection: true,
+            ..Config::default()
+        };
+        assert_eq!(resolve_prompt(None, &config), Ok(None));
+    }
+
+    #[test]
+    fn test_resolve_none_and_profiles() {
+        let mut config = Config::default();
+        config
+            .prompt_profiles
+            .insert("coder".to_string(), "You are a coder.".to_string());
+
+        let none = InjectSelection::None;
+        assert_eq!(resolve_prompt(Some(&none), &config), Ok(None));
+
+        let coder = InjectSelection::Profile("coder".to_string());
+        assert_eq!(
+            resolve_prompt(Some(&coder), &config),
+            Ok(Some("You are a coder.".to_string()))
+        );
+
+        let builtin = InjectSelection::Profile(BUILTIN_PROFILE.to_string());
+        assert_eq!(
+            resolve_prompt(Some(&builtin), &config),
+            Ok(Some(PROFESSIONAL_SYSTEM_PROMPT.to_string()))
+        );
+        config.opus_prompt = Some("Custom prompt".to_string());
+        assert_eq!(
+            resolve_prompt(Some(&builtin), &config),
+            Ok(Some("Custom prompt".to_string()))
+        );
+
+        let unknown = InjectSelection::Profile("missing".to_string());
+        assert_eq!(
+            resolve_prompt(Some(&unknown), &config),
+            Err("missing".to_string())
+        );
+    }
+}
@@ -0,0 +1,520 @@
+//! MCP（Model Context Protocol）服务端
+//!
+//! 向支持 MCP 的编辑器暴露以下工具，工具调用直接复用 `/v1/messages` 的处理流程：
+//!
+//! - `ask_claude`：发送提示词，返回模型的文本回答
+//! - `credential_status`：凭据池状态摘要（不含 token 等敏感信息）
+//!
+//! 支持两种传输：
+//!
+//! - stdio：`kiro-rs mcp` 子命令，每行一条 JSON-RPC 消息，日志输出到 stderr
+//! - SSE：`GET /v1/mcp/sse` 建立事件流（需启用 `mcpServer.enabled`），首个 `endpoint`
+//!   事件给出消息提交地址 `POST /v1/mcp/messages?sessionId=...`，响应通过事件流的
+//!   `message` 事件返回。会话只接受创建它的 API Key 提交的消息
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    Extension,
+    body::to_bytes,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures::{StreamExt, stream};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::common::api_keys::ClientKey;
+
+use super::handlers::post_messages;
+use super::middleware::AppState;
+use super::types::{ErrorResponse, MessagesRequest};
+
+/// 支持的 MCP 协议版本
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// `ask_claude` 未指定 max_tokens 时的默认值
+const DEFAULT_MAX_TOKENS: i64 = 4096;
+
+/// JSON-RPC 错误码
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// JSON-RPC 错误（错误码与消息）
+type RpcError = (i64, String);
+
+/// MCP 请求处理器
+#[derive(Clone)]
+pub struct McpServer {
+    state: AppState,
+}
+
+impl McpServer {
+    /// 基于 Anthropic API 的应用状态创建处理器
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+
+    /// 处理一条 JSON-RPC 消息，通知（无 id）不返回响应
+    pub async fn handle(&self, message: Value, client: Option<ClientKey>) -> Option<Value> {
+        let id = message.get("id").cloned();
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return Some(error_response(
+                id.unwrap_or(Value::Null),
+                INVALID_REQUEST,
+                "Invalid request: missing method",
+            ));
+        };
+        let Some(id) = id else {
+            tracing::debug!(method = %method, "收到 MCP 通知");
+            return None;
+        };
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {"tools": {}},
+                "serverInfo": {
+                    "name": "kiro-rs",
+                    "version": env!("CARGO_PKG_VERSION")
+                }
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({"tools": tools()})),
+            "tools/call" => self.call_tool(&params, client).await,
+            _ => Err((METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        params: &Value,
+        client: Option<ClientKey>,
+    ) -> Result<Value, RpcError> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or((INVALID_PARAMS, "Missing tool name".to_string()))?;
+        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+        tracing::info!(tool = %name, "MCP 工具调用");
+
+        match name {
+            "ask_claude" => self.ask_claude(&arguments, client).await,
+            "credential_status" => Ok(self.credential_status()),
+            _ => Err((INVALID_PARAMS, format!("Unknown tool: {}", name))),
+        }
+    }
+
+    /// 以非流式 `/v1/messages` 请求回答提示词
+    async fn ask_claude(&self, args: &Value, client: Option<ClientKey>) -> Result<Value, RpcError> {
+        let prompt = args.get("prompt").and_then(Value::as_str).ok_or((
+            INVALID_PARAMS,
+            "Missing required argument: prompt".to_string(),
+        ))?;
+        let Some(provider) = &self.state.kiro_provider else {
+            return Ok(tool_result("Kiro API provider not configured", true));
+        };
+        let model = args
+            .get("model")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                provider
+                    .token_manager()
+                    .config()
+                    .mcp_server
+                    .default_model
+                    .clone()
+            });
+
+        let max_tokens = args
+            .get("max_tokens")
+            .and_then(Value::as_i64)
+            .unwrap_or(DEFAULT_MAX_TOKENS);
+        let mut request = json!({
+            "model": model,
+            "max_tokens": max_tokens,
+            "messages": [{"role": "user", "content": prompt}]
+        });
+        if let Some(system) = args.get("system").and_then(Value::as_str) {
+            request["system"] = json!(system);
+        }
+        let request: MessagesRequest = serde_json::from_value(request)
+            .map_err(|e| (INVALID_PARAMS, format!("Invalid arguments: {}", e)))?;
+
+        let response = post_messages(
+            State(self.state.clone()),
+            client.map(Extension),
+            HeaderMap::new(),
+            Json(request),
+        )
+        .await;
+        let status = response.status();
+        let body = match to_bytes(response.into_body(), usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                return Ok(tool_result(
+                    &format!("Failed to read response: {}", e),
+                    true,
+                ));
+            }
+        };
+        let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        if !status.is_success() {
+            let message = body["error"]["message"]
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Upstream request failed with status {}", status));
+            return Ok(tool_result(&message, true));
+        }
+
+        let text: String = body["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|block| block["type"] == "text")
+            .filter_map(|block| block["text"].as_str())
+            .collect();
+        Ok(tool_result(&text, false))
+    }
+
+    /// 凭据池状态摘要
+    fn credential_status(&self) -> Value {
+        let Some(provider) = &self.state.kiro_provider else {
+            return tool_result("Kiro API provider not configured", true);
+        };
+        let snapshot = provider.token_manager().snapshot();
+        tool_result(
+            &serde_json::to_string_pretty(&snapshot).unwrap_or_default(),
+            false,
+        )
+    }
+}
+
+/// 工具定义列表
+fn tools() -> Value {
+    json!([
+        {
+            "name": "ask_claude",
+            "description": "Send a prompt to Claude through the Kiro backend and return the text of the answer.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "prompt": {"type": "string", "description": "The user prompt"},
+                    "system": {"type": "string", "description": "Optional system prompt"},
+                    "model": {"type": "string", "description": "Model ID, defaults to the configured MCP model"},
+                    "max_tokens": {"type": "integer", "minimum": 1, "description": "Maximum tokens to generate"}
+                },
+                "required": ["prompt"]
+            }
+        },
+        {
+            "name": "credential_status",
+            "description": "Summarize the state of the Kiro credential pool (availability, failures, circuit breakers).",
+            "inputSchema": {"type": "object", "properties": {}}
+        }
+    ])
+}
+
+/// 工具调用结果
+fn tool_result(text: &str, is_error: bool) -> Value {
+    json!({
+        "content": [{"type": "text", "text": text}],
+        "isError": is_error
+    })
+}
+
+/// JSON-RPC 错误响应
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message}
+    })
+}
+
+/// 解析一行 stdio 输入并处理
+async fn handle_line(server: &McpServer, line: &str) -> Option<Value> {
+    match serde_json::from_str::<Value>(line) {
+        Ok(message) => server.handle(message, None).await,
+        Err(e) => Some(error_response(
+            Value::Null,
+            PARSE_ERROR,
+            &format!("Parse error: {}", e),
+        )),
+    }
+}
+
+/// 以 stdio 传输运行 MCP 服务端，直到标准输入关闭
+///
+/// 多个请求并发处理，响应按完成顺序逐行写入标准输出
+pub async fn serve_stdio(state: AppState) -> std::io::Result<()> {
+    let server = McpServer::new(state);
+    let (tx, mut rx) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(response) = rx.recv().await {
+            let line = format!("{}\n", response);
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    tracing::info!("MCP 服务端已启动（stdio）");
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut tasks = JoinSet::new();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (server, tx) = (server.clone(), tx.clone());
+        tasks.spawn(async move {
+            if let Some(response) = handle_line(&server, &line).await {
+                let _ = tx.send(response);
+            }
+        });
+    }
+
+    while tasks.join_next().await.is_some() {}
+    drop(tx);
+    let _ = writer.await;
+    tracing::info!("标准输入已关闭，MCP 服务端退出");
+    Ok(())
+}
+
+/// SSE 传输的会话表：会话 ID -> (创建者 Key 名称, 事件流发送端)
+#[derive(Default)]
+pub struct McpSessions {
+    sessions: Mutex<HashMap<String, (String, mpsc::UnboundedSender<Value>)>>,
+}
+
+impl McpSessions {
+    fn open(&self, owner: &str) -> (String, mpsc::UnboundedReceiver<Value>) {
+        let id = Uuid::new_v4().simple().to_string();
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.sessions
+            .lock()
+            .insert(id.clone(), (owner.to_string(), tx));
+        (id, rx)
+    }
+
+    fn sender(&self, id: &str, owner: &str) -> Option<mpsc::UnboundedSender<Value>> {
+        self.sessions
+            .lock()
+            .get(id)
+            .filter(|(session_owner, _)| session_owner == owner)
+            .map(|(_, tx)| tx.clone())
+    }
+
+    fn close(&self, id: &str) {
+        self.sessions.lock().remove(id);
+    }
+}
+
+/// 事件流结束（客户端断开）时移除会话
+struct SessionGuard {
+    sessions: Arc<McpSessions>,
+    id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.close(&self.id);
+        tracing::debug!(session = %self.id, "MCP SSE 会话已关闭");
+    }
+}
+
+fn owner(client: &Option<Extension<ClientKey>>) -> &str {
+    client.as_ref().map(|c| c.name.as_str()).unwrap_or_default()
+}
+
+fn mcp_disabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new(
+            "not_found_error",
+            "MCP server is not enabled",
+        )),
+    )
+        .into_response()
+}
+
+/// GET /v1/mcp/sse
+///
+/// 建立 MCP SSE 事件流
+pub async fn mcp_sse(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+) -> Response {
+    let Some(sessions) = state.mcp_sessions.clone() else {
+        return mcp_disabled();
+    };
+    let (id, rx) = sessions.open(owner(&client));
+    tracing::info!(session = %id, "MCP SSE 会话已建立");
+
+    let endpoint = Event::default()
+        .event("endpoint")
+        .data(format!("/v1/mcp/messages?sessionId={}", id));
+    let guard = SessionGuard { sessions, id };
+    let messages = stream::unfold((rx, guard), |(mut rx, guard)| async move {
+        let message = rx.recv().await?;
+        let event = Event::default().event("message").data(message.to_string());
+        Some((event, (rx, guard)))
+    });
+    let stream = stream::once(async { endpoint })
+        .chain(messages)
+        .map(Ok::<_, Infallible>);
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// 消息提交地址的查询参数
+#[derive(Debug, Deserialize)]
+pub struct McpMessageQuery {
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+}
+
+/// POST /v1/mcp/messages
+///
+/// 提交 JSON-RPC 消息，立即返回 202，响应通过对应会话的事件流返回
+pub async fn mcp_message(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    Query(query): Query<McpMessageQuery>,
+    Json(message): Json<Value>,
+) -> Response {
+    let Some(sessions) = state.mcp_sessions.clone() else {
+        return mcp_disabled();
+    };
+    let Some(tx) = sessions.sender(&query.session_id, owner(&client)) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "not_found_error",
+                format!("MCP session not found: {}", query.session_id),
+            )),
+        )
+            .into_response();
+    };
+
+    let server = McpServer::new(state);
+    tokio::spawn(async move {
+        if let Some(response) = server.handle(message, client.map(|c| c.0)).await {
+            let _ = tx.send(response);
+        }
+    });
+    StatusCode::ACCEPTED.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::api_keys::ApiKeyRegistry;
+    use crate::model::config::Config;
+    use crate::storage::MemoryStorage;
+
+    fn server() -> McpServer {
+        let api_keys = ApiKeyRegistry::new(&Config::default(), Arc::new(MemoryStorage::new()));
+        McpServer::new(AppState::new(Arc::new(api_keys)))
+    }
+
+    #[tokio::test]
+    async fn test_initialize_and_list_tools() {
+        let server = server();
+        let response = server
+            .handle(
+                json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["serverInfo"]["name"], "kiro-rs");
+
+        let notification = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        assert!(server.handle(notification, None).await.is_none());
+
+        let response = server
+            .handle(
+                json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"}),
+                None,
+            )
+            .await
+            .unwrap();
+        let names: Vec<_> = response["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["ask_claude", "credential_status"]);
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let server = server();
+        let response = handle_line(&server, "{not json").await.unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+
+        let response = server
+            .handle(
+                json!({"jsonrpc": "2.0", "id": 3, "method": "resources/list"}),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let call = json!({
+            "jsonrpc": "2.0",
+            "id": 4,
+            "method": "tools/call",
+            "params": {"name": "ask_claude", "arguments": {}}
+        });
+        let response = server.handle(call, None).await.unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+        // 未配置 Provider 时工具调用返回 isError 结果而不是协议错误
+        let call = json!({
+            "jsonrpc": "2.0",
+            "id": 5,
+            "method": "tools/call",
+            "params": {"name": "credential_status"}
+        });
+        let response = server.handle(call, None).await.unwrap();
+        assert_eq!(response["result"]["isError"], true);
+    }
+
+    #[test]
+    fn test_sessions_bound_to_owner() {
+        let sessions = McpSessions::default();
+        let (id, _rx) = sessions.open("alice");
+        assert!(sessions.sender(&id, "alice").is_some());
+        assert!(sessions.sender(&id, "bob").is_none());
+        sessions.close(&id);
+        assert!(sessions.sender(&id, "alice").is_none());
+    }
+}
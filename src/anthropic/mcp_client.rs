@@ -0,0 +1,951 @@
+//! 服务端执行的 MCP 工具
+//!
+//! 启用 `mcpClient` 后，kiro-rs 在启动时以子进程方式连接配置的 MCP 服务器（stdio 传输），
+//! 并把它们的工具追加到每个 `/v1/messages` 请求中。模型调用这些工具时由代理执行，
+//! 结果作为 tool_result 写回对话并再次请求上游，直到模型不再调用 MCP 工具。
+//!
+//! 返回给客户端的消息包含全部轮次的内容：MCP 工具调用以 `mcp_tool_use` /
+//! `mcp_tool_result` 内容块表示（与 Anthropic MCP connector 一致）。客户端在后续请求中
+//! 原样回传这些内容块时，会被还原为上游可识别的 tool_use / tool_result 消息。
+
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use axum::{
+    Extension,
+    body::{Body, to_bytes},
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use bytes::Bytes;
+use futures::future::{self, BoxFuture};
+use futures::{StreamExt, stream};
+use parking_lot::Mutex;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::AbortHandle;
+use tokio::time::interval;
+use uuid::Uuid;
+
+use crate::common::api_keys::ClientKey;
+use crate::model::config::{McpClientConfig, McpServerEntry};
+use crate::token;
+
+use super::cancellation::REQUEST_ID_HEADER;
+use super::event_buffer::TRANSPORT_HEADER;
+use super::handlers::post_messages;
+use super::middleware::AppState;
+use super::stream::SseEvent;
+use super::types::{Message, MessagesRequest, Tool};
+
+/// 客户端声明的 MCP 协议版本
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// 初始化与 tools/list 的超时
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 等待工具执行期间发送 ping 的间隔
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// 与单个 MCP 服务器的连接
+struct McpConnection {
+    name: String,
+    stdin: tokio::sync::Mutex<ChildStdin>,
+    pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>,
+    next_id: AtomicU64,
+    /// 持有子进程，连接释放时随之结束
+    _child: Child,
+}
+
+impl McpConnection {
+    /// 启动 MCP 服务器子进程并完成初始化握手
+    async fn spawn(entry: &McpServerEntry) -> anyhow::Result<Self> {
+        let mut child = Command::new(&entry.command)
+            .args(&entry.args)
+            .envs(&entry.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("启动 {} 失败", entry.command))?;
+        let stdin = child.stdin.take().context("无法获取子进程 stdin")?;
+        let stdout = child.stdout.take().context("无法获取子进程 stdout")?;
+
+        let pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>> = Arc::default();
+        let reader_pending = pending.clone();
+        let name = entry.name.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    tracing::debug!(server = %name, "忽略无法解析的 MCP 输出: {}", line);
+                    continue;
+                };
+                // 服务器发起的请求与通知不需要处理
+                if message.get("method").is_some() {
+                    continue;
+                }
+                if let Some(tx) = message["id"]
+                    .as_u64()
+                    .and_then(|id| reader_pending.lock().remove(&id))
+                {
+                    let _ = tx.send(message);
+                }
+            }
+            tracing::warn!(server = %name, "MCP 服务器输出已关闭");
+            reader_pending.lock().clear();
+        });
+
+        let connection = Self {
+            name: entry.name.clone(),
+            stdin: tokio::sync::Mutex::new(stdin),
+            pending,
+            next_id: AtomicU64::new(1),
+            _child: child,
+        };
+        connection
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {"name": "kiro-rs", "version": env!("CARGO_PKG_VERSION")}
+                }),
+                HANDSHAKE_TIMEOUT,
+            )
+            .await?;
+        connection
+            .send(&json!({"jsonrpc": "2.0", "method": "notifications/initialized"}))
+            .await?;
+        Ok(connection)
+    }
+
+    async fn send(&self, message: &Value) -> anyhow::Result<()> {
+        let line = format!("{}\n", message);
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    /// 发送 JSON-RPC 请求并等待结果
+    async fn request(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> anyhow::Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().insert(id, tx);
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        if let Err(e) = self.send(&message).await {
+            self.pending.lock().remove(&id);
+            return Err(e);
+        }
+
+        let response = match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => bail!("MCP 服务器 {} 已断开", self.name),
+            Err(_) => {
+                self.pending.lock().remove(&id);
+                bail!("MCP 服务器 {} 响应 {} 超时", self.name, method);
+            }
+        };
+        if let Some(error) = response.get("error") {
+            bail!(
+                "{}",
+                error["message"].as_str().unwrap_or("unknown MCP error")
+            );
+        }
+        Ok(response["result"].clone())
+    }
+}
+
+/// MCP 服务器提供的工具
+struct McpTool {
+    /// 所属连接在 `McpToolPool::connections` 中的下标
+    server: usize,
+    name: String,
+    description: String,
+    input_schema: Value,
+}
+
+/// 工具执行结果
+struct ToolOutput {
+    /// MCP 返回的内容块（非文本内容以文本占位）
+    content: Vec<Value>,
+    is_error: bool,
+}
+
+impl ToolOutput {
+    fn error(message: String) -> Self {
+        Self {
+            content: vec![json!({"type": "text", "text": message})],
+            is_error: true,
+        }
+    }
+
+    /// 写回上游时使用的纯文本
+    fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// 已连接的 MCP 工具集合
+pub struct McpToolPool {
+    connections: Vec<McpConnection>,
+    tools: Vec<McpTool>,
+    max_rounds: u32,
+    tool_timeout: Duration,
+}
+
+impl McpToolPool {
+    /// 连接配置中的全部 MCP 服务器，连接失败的服务器会被跳过
+    pub async fn connect(config: &McpClientConfig) -> Self {
+        let mut connections = Vec::new();
+        let mut tools: Vec<McpTool> = Vec::new();
+        for entry in &config.servers {
+            let listed = async {
+                let connection = McpConnection::spawn(entry).await?;
+                let result = connection
+                    .request("tools/list", json!({}), HANDSHAKE_TIMEOUT)
+                    .await?;
+                anyhow::Ok((connection, result))
+            };
+            let (connection, result) = match listed.await {
+                Ok(listed) => listed,
+                Err(e) => {
+                    tracing::warn!(server = %entry.name, "连接 MCP 服务器失败: {:#}", e);
+                    continue;
+                }
+            };
+
+            let mut count = 0;
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                let Some(name) = tool["name"].as_str() else {
+                    continue;
+                };
+                if tools.iter().any(|t| t.name == name) {
+                    tracing::warn!(server = %entry.name, tool = %name, "MCP 工具名称重复，已忽略");
+                    continue;
+                }
+                tools.push(McpTool {
+                    server: connections.len(),
+                    name: name.to_string(),
+                    description: tool["description"].as_str().unwrap_or_default().to_string(),
+                    input_schema: tool
+                        .get("inputSchema")
+                        .cloned()
+                        .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                });
+                count += 1;
+            }
+            tracing::info!(server = %entry.name, tools = count, "已连接 MCP 服务器");
+            connections.push(connection);
+        }
+
+        Self {
+            connections,
+            tools,
+            max_rounds: config.max_rounds.max(1),
+            tool_timeout: Duration::from_secs(config.tool_timeout_secs),
+        }
+    }
+
+    /// 是否有可用工具
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    fn find(&self, name: &str) -> Option<&McpTool> {
+        self.tools.iter().find(|t| t.name == name)
+    }
+
+    /// 将 MCP 工具追加到请求的工具列表（与客户端工具同名的跳过）
+    fn inject_tools(&self, payload: &mut MessagesRequest) {
+        let tools = payload.tools.get_or_insert_with(Vec::new);
+        for tool in &self.tools {
+            if tools.iter().any(|t| t.name == tool.name) {
+                continue;
+            }
+            tools.push(Tool {
+                tool_type: None,
+                name: tool.name.clone(),
+                description: tool.description.clone(),
+                input_schema: serde_json::from_value(tool.input_schema.clone()).unwrap_or_default(),
+                max_uses: None,
+                cache_control: None,
+            });
+        }
+    }
+
+    /// 执行一次工具调用，失败时返回 is_error 结果交给模型处理
+    async fn call(&self, name: &str, input: &Value) -> ToolOutput {
+        let Some(tool) = self.find(name) else {
+            return ToolOutput::error(format!("Unknown MCP tool: {}", name));
+        };
+        let connection = &self.connections[tool.server];
+        tracing::info!(server = %connection.name, tool = %name, "执行 MCP 工具");
+        let params = json!({"name": name, "arguments": input});
+        match connection
+            .request("tools/call", params, self.tool_timeout)
+            .await
+        {
+            Ok(result) => ToolOutput {
+                content: result["content"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|block| match block["text"].as_str() {
+                        Some(text) => json!({"type": "text", "text": text}),
+                        None => json!({
+                            "type": "text",
+                            "text": format!("[{} content omitted]", block["type"].as_str().unwrap_or("unknown"))
+                        }),
+                    })
+                    .collect(),
+                is_error: result["isError"].as_bool().unwrap_or(false),
+            },
+            Err(e) => {
+                tracing::warn!(tool = %name, "MCP 工具执行失败: {:#}", e);
+                ToolOutput::error(e.to_string())
+            }
+        }
+    }
+
+    fn server_name(&self, tool: &str) -> &str {
+        self.find(tool)
+            .map(|t| self.connections[t.server].name.as_str())
+            .unwrap_or_default()
+    }
+}
+
+/// 将消息内容统一为内容块数组
+fn content_blocks(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(text) => vec![json!({"type": "text", "text": text})],
+        Value::Array(blocks) => blocks.clone(),
+        _ => Vec::new(),
+    }
+}
+
+/// 还原历史中的 `mcp_tool_use` / `mcp_tool_result` 内容块
+///
+/// assistant 消息在每段 mcp_tool_result 处拆分：之前的内容（mcp_tool_use 改为 tool_use）
+/// 留在 assistant 消息，结果作为 tool_result 放入紧随其后的 user 消息
+fn expand_history(messages: Vec<Message>) -> Vec<Message> {
+    let mut expanded: Vec<Message> = Vec::new();
+    for message in messages {
+        let blocks = content_blocks(&message.content);
+        let has_mcp_blocks =
+            message.role == "assistant" && blocks.iter().any(|b| b["type"] == "mcp_tool_result");
+        if !has_mcp_blocks {
+            // 紧跟在拆分出的 tool_result 之后的 user 消息与其合并
+            if message.role == "user"
+                && let Some(last) = expanded.last_mut()
+                && last.role == "user"
+            {
+                let mut merged = content_blocks(&last.content);
+                merged.extend(blocks);
+                last.content = Value::Array(merged);
+                continue;
+            }
+            expanded.push(message);
+            continue;
+        }
+
+        let mut turns: Vec<(&str, Vec<Value>)> = Vec::new();
+        for block in blocks {
+            let (role, block) = match block["type"].as_str() {
+                Some("mcp_tool_use") => (
+                    "assistant",
+                    json!({
+                        "type": "tool_use",
+                        "id": block["id"],
+                        "name": block["name"],
+                        "input": block["input"]
+                    }),
+                ),
+                Some("mcp_tool_result") => {
+                    let text = block["content"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|b| b["text"].as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    (
+                        "user",
+                        json!({
+                            "type": "tool_result",
+                            "tool_use_id": block["tool_use_id"],
+                            "content": text,
+                            "is_error": block["is_error"].as_bool().unwrap_or(false)
+                        }),
+                    )
+                }
+                _ => ("assistant", block),
+            };
+            match turns.last_mut() {
+                Some((last, blocks)) if *last == role => blocks.push(block),
+                _ => turns.push((role, vec![block])),
+            }
+        }
+        expanded.extend(turns.into_iter().map(|(role, blocks)| Message {
+            role: role.to_string(),
+            content: Value::Array(blocks),
+        }));
+    }
+    expanded
+}
+
+/// 一轮上游请求的结果
+enum Round {
+    /// 上游返回的完整消息
+    Message(Value),
+    /// 上游请求失败，原样返回给客户端
+    Failed(Response),
+}
+
+/// 一次 MCP 对话的上下文
+struct Conversation {
+    pool: Arc<McpToolPool>,
+    state: AppState,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    /// 客户端自己声明的工具（同名的调用交回客户端，不由 MCP 执行）
+    client_tools: HashSet<String>,
+    message_id: String,
+}
+
+impl Conversation {
+    /// 是否为应由代理执行的 MCP 工具调用
+    fn is_mcp_call(&self, block: &Value) -> bool {
+        let name = block["name"].as_str().unwrap_or_default();
+        block["type"] == "tool_use"
+            && !self.client_tools.contains(name)
+            && self.pool.find(name).is_some()
+    }
+
+    /// 以非流式方式请求上游一轮
+    async fn request_round(&self, payload: &MessagesRequest) -> Round {
+        let response = post_messages(
+            State(self.state.clone()),
+            self.client.clone(),
+            self.headers.clone(),
+            axum::Json(payload.clone()),
+        )
+        .await;
+        if !response.status().is_success() {
+            return Round::Failed(response);
+        }
+        match to_bytes(response.into_body(), usize::MAX).await {
+            Ok(body) => Round::Message(serde_json::from_slice(&body).unwrap_or(Value::Null)),
+            Err(e) => Round::Failed(
+                (
+                    StatusCode::BAD_GATEWAY,
+                    Json(super::types::ErrorResponse::new(
+                        "api_error",
+                        format!("Failed to read upstream response: {}", e),
+                    )),
+                )
+                    .into_response(),
+            ),
+        }
+    }
+
+    /// 执行工具并续写对话，返回合并后的完整消息
+    ///
+    /// 每轮结束后以该轮新增的内容块调用 `on_round`，流式响应据此逐轮输出
+    async fn run(
+        &self,
+        mut payload: MessagesRequest,
+        mut on_round: impl FnMut(&[Value]) + Send,
+    ) -> Result<Value, Response> {
+        let pool = &self.pool;
+        let mut content: Vec<Value> = Vec::new();
+        let (mut input_tokens, mut output_tokens) = (0, 0);
+        let mut last = Value::Null;
+
+        for round in 1..=pool.max_rounds {
+            let round_start = content.len();
+            let mut message = match self.request_round(&payload).await {
+                Round::Message(message) => message,
+                Round::Failed(response) => return Err(response),
+            };
+            input_tokens += message["usage"]["input_tokens"].as_i64().unwrap_or(0);
+            output_tokens += message["usage"]["output_tokens"].as_i64().unwrap_or(0);
+            let blocks = content_blocks(&message["content"]);
+            let calls: Vec<&Value> = blocks.iter().filter(|b| self.is_mcp_call(b)).collect();
+
+            if message["stop_reason"] != "tool_use" || calls.is_empty() || round == pool.max_rounds
+            {
+                // 客户端从未声明 MCP 工具，未执行的调用不能交给客户端
+                let round_limited = message["stop_reason"] == "tool_use" && !calls.is_empty();
+                let remaining: Vec<Value> = blocks
+                    .iter()
+                    .filter(|b| !self.is_mcp_call(b))
+                    .cloned()
+                    .collect();
+                if round_limited {
+                    tracing::warn!(
+                        "MCP 工具调用已达最大轮数（{}），已移除未执行的调用",
+                        pool.max_rounds
+                    );
+                    if !remaining.iter().any(|b| b["type"] == "tool_use") {
+                        message["stop_reason"] = json!("end_turn");
+                    }
+                }
+                content.extend(remaining);
+                if round_limited {
+                    content.push(json!({
+                        "type": "text",
+                        "text": format!(
+                            "[MCP tool round limit ({}) reached; the remaining MCP tool calls were not executed]",
+                            pool.max_rounds
+                        )
+                    }));
+                }
+                on_round(&content[round_start..]);
+                last = message;
+                break;
+            }
+
+            let outputs =
+                future::join_all(calls.iter().map(|call| {
+                    pool.call(call["name"].as_str().unwrap_or_default(), &call["input"])
+                }))
+                .await;
+            let mut results: HashMap<&str, ToolOutput> = calls
+                .iter()
+                .map(|call| call["id"].as_str().unwrap_or_default())
+                .zip(outputs)
+                .collect();
+
+            let mut tool_results = Vec::new();
+            let mut client_calls = false;
+            for block in &blocks {
+                let id = block["id"].as_str().unwrap_or_default();
+                let Some(output) = results.remove(id) else {
+                    client_calls |= block["type"] == "tool_use";
+                    content.push(block.clone());
+                    continue;
+                };
+                let name = block["name"].as_str().unwrap_or_default();
+                content.push(json!({
+                    "type": "mcp_tool_use",
+                    "id": id,
+                    "name": name,
+                    "server_name": pool.server_name(name),
+                    "input": block["input"]
+                }));
+                content.push(json!({
+                    "type": "mcp_tool_result",
+                    "tool_use_id": id,
+                    "is_error": output.is_error,
+                    "content": output.content
+                }));
+                tool_results.push(json!({
+                    "type": "tool_result",
+                    "tool_use_id": id,
+                    "content": output.text(),
+                    "is_error": output.is_error
+                }));
+            }
+            on_round(&content[round_start..]);
+
+            // 同时调用了客户端工具：交回客户端执行，MCP 结果随内容块一并返回
+            if client_calls {
+                last = message;
+                break;
+            }
+            payload.messages.push(Message {
+                role: "assistant".to_string(),
+                content: Value::Array(blocks),
+            });
+            payload.messages.push(Message {
+                role: "user".to_string(),
+                content: Value::Array(tool_results),
+            });
+            last = message;
+        }
+
+        let mut usage = last["usage"].clone();
+        usage["input_tokens"] = json!(input_tokens);
+        usage["output_tokens"] = json!(output_tokens);
+        Ok(json!({
+            "id": self.message_id,
+            "type": "message",
+            "role": "assistant",
+            "model": payload.model,
+            "content": content,
+            "stop_reason": last["stop_reason"],
+            "stop_sequence": last["stop_sequence"],
+            "usage": usage
+        }))
+    }
+}
+
+/// 单个内容块的流式事件
+fn block_events(index: usize, block: &Value) -> Vec<SseEvent> {
+    let (start, delta) = match block["type"].as_str() {
+        Some("text") => (
+            json!({"type": "text", "text": ""}),
+            Some(json!({"type": "text_delta", "text": block["text"]})),
+        ),
+        Some("tool_use") | Some("mcp_tool_use") => {
+            let mut start = block.clone();
+            start["input"] = json!({});
+            let partial_json = serde_json::to_string(&block["input"]).unwrap_or_default();
+            (
+                start,
+                Some(json!({"type": "input_json_delta", "partial_json": partial_json})),
+            )
+        }
+        _ => (block.clone(), None),
+    };
+    let mut events = vec![SseEvent::new(
+        "content_block_start",
+        json!({"type": "content_block_start", "index": index, "content_block": start}),
+    )];
+    if let Some(delta) = delta {
+        events.push(SseEvent::new(
+            "content_block_delta",
+            json!({"type": "content_block_delta", "index": index, "delta": delta}),
+        ));
+    }
+    events.push(SseEvent::new(
+        "content_block_stop",
+        json!({"type": "content_block_stop", "index": index}),
+    ));
+    events
+}
+
+/// 对话结束时的流式事件（message_delta 与 message_stop）
+fn finish_events(message: &Value) -> Vec<SseEvent> {
+    vec![
+        SseEvent::new(
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": {
+                    "stop_reason": message["stop_reason"],
+                    "stop_sequence": message["stop_sequence"]
+                },
+                "usage": {"output_tokens": message["usage"]["output_tokens"]}
+            }),
+        ),
+        SseEvent::new("message_stop", json!({"type": "message_stop"})),
+    ]
+}
+
+/// 上游请求失败时的流式 error 事件
+async fn error_event(response: Response) -> SseEvent {
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<Value>(&body).ok())
+        .unwrap_or(Value::Null);
+    let error = match body.get("error") {
+        Some(error) => error.clone(),
+        None => json!({
+            "type": "api_error",
+            "message": format!("Upstream request failed with status {}", status)
+        }),
+    };
+    SseEvent::new("error", json!({"type": "error", "error": error}))
+}
+
+fn new_message_id() -> String {
+    format!("msg_{}", &Uuid::new_v4().simple().to_string()[..24])
+}
+
+/// 响应流释放（客户端断开）时中止后台的对话任务
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// 处理启用了 MCP 工具的 `/v1/messages` 请求
+///
+/// 每轮均以非流式方式请求上游；流式请求先发送 message_start，每轮结束后立即输出该轮的
+/// 内容块，等待期间定期发送 ping。内部请求会再次进入 `post_messages`，
+/// 因此返回装箱的 future 以打断递归的类型推导
+pub fn handle_request(
+    pool: Arc<McpToolPool>,
+    state: AppState,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    payload: MessagesRequest,
+) -> BoxFuture<'static, Response> {
+    Box::pin(handle(pool, state, client, headers, payload))
+}
+
+async fn handle(
+    pool: Arc<McpToolPool>,
+    mut state: AppState,
+    client: Option<Extension<ClientKey>>,
+    mut headers: HeaderMap,
+    mut payload: MessagesRequest,
+) -> Response {
+    let stream = payload.stream;
+    payload.stream = false;
+    payload.messages = expand_history(std::mem::take(&mut payload.messages));
+    let client_tools = payload
+        .tools
+        .iter()
+        .flatten()
+        .map(|t| t.name.clone())
+        .collect();
+    pool.inject_tools(&mut payload);
+    tracing::info!(tools = pool.tools.len(), stream, "启用 MCP 工具执行");
+
+    // 内部请求不再经过 MCP 处理与请求改写规则，也不复用外层请求的幂等键、请求 ID 与传输方式
+    state.mcp_tools = None;
+    state.internal = true;
+    for name in ["idempotency-key", REQUEST_ID_HEADER, TRANSPORT_HEADER] {
+        headers.remove(name);
+    }
+    let conversation = Conversation {
+        pool,
+        state,
+        client,
+        headers,
+        client_tools,
+        message_id: new_message_id(),
+    };
+
+    if !stream {
+        return match conversation.run(payload, |_| {}).await {
+            Ok(message) => (StatusCode::OK, Json(message)).into_response(),
+            Err(response) => response,
+        };
+    }
+
+    let input_tokens = token::count_all_tokens(
+        &payload.model,
+        payload.system.as_deref(),
+        &payload.messages,
+        payload.tools.as_deref(),
+    )
+    .await;
+    let start = SseEvent::new(
+        "message_start",
+        json!({
+            "type": "message_start",
+            "message": {
+                "id": conversation.message_id,
+                "type": "message",
+                "role": "assistant",
+                "model": payload.model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": {"input_tokens": input_tokens, "output_tokens": 0}
+            }
+        }),
+    );
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn(async move {
+        let mut index = 0;
+        let result = conversation
+            .run(payload, |blocks| {
+                for block in blocks {
+                    for event in block_events(index, block) {
+                        let _ = tx.send(event);
+                    }
+                    index += 1;
+                }
+            })
+            .await;
+        let events = match result {
+            Ok(message) => finish_events(&message),
+            Err(response) => vec![error_event(response).await],
+        };
+        for event in events {
+            let _ = tx.send(event);
+        }
+    });
+    let guard = AbortOnDrop(task.abort_handle());
+    let ping = || SseEvent::new("ping", json!({"type": "ping"}));
+    // 任务结束后发送端释放，接收端返回 None 时流结束
+    let body = stream::unfold(
+        (rx, interval(PING_INTERVAL), guard),
+        move |(mut rx, mut ticker, guard)| async move {
+            tokio::select! {
+                biased;
+                event = rx.recv() => event.map(|event| (event, (rx, ticker, guard))),
+                _ = ticker.tick() => Some((ping(), (rx, ticker, guard))),
+            }
+        },
+    );
+    let stream = stream::once(async { start })
+        .chain(body)
+        .map(|e| Ok::<_, Infallible>(Bytes::from(e.to_sse_string())));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::CONNECTION, "keep-alive")
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: Value) -> Message {
+        Message {
+            role: role.to_string(),
+            content,
+        }
+    }
+
+    #[test]
+    fn test_expand_history_restores_tool_turns() {
+        let messages = vec![
+            message("user", json!("what time is it?")),
+            message(
+                "assistant",
+                json!([
+                    {"type": "text", "text": "Checking."},
+                    {"type": "mcp_tool_use", "id": "toolu_1", "name": "clock", "server_name": "time", "input": {}},
+                    {"type": "mcp_tool_result", "tool_use_id": "toolu_1", "is_error": false, "content": [{"type": "text", "text": "12:00"}]},
+                    {"type": "tool_use", "id": "toolu_2", "name": "Read", "input": {"path": "a"}}
+                ]),
+            ),
+            message(
+                "user",
+                json!([{"type": "tool_result", "tool_use_id": "toolu_2", "content": "file"}]),
+            ),
+        ];
+
+        let expanded = expand_history(messages);
+        let roles: Vec<_> = expanded.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "user", "assistant", "user"]);
+        assert_eq!(expanded[1].content[1]["type"], "tool_use");
+        assert_eq!(expanded[1].content[1]["id"], "toolu_1");
+        assert_eq!(expanded[2].content[0]["type"], "tool_result");
+        assert_eq!(expanded[2].content[0]["content"], "12:00");
+        assert_eq!(expanded[3].content[0]["id"], "toolu_2");
+        assert_eq!(expanded[4].content[0]["tool_use_id"], "toolu_2");
+    }
+
+    #[test]
+    fn test_expand_history_merges_trailing_results_into_next_user_turn() {
+        let messages = vec![
+            message(
+                "assistant",
+                json!([
+                    {"type": "mcp_tool_use", "id": "toolu_1", "name": "clock", "input": {}},
+                    {"type": "mcp_tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "12:00"}]}
+                ]),
+            ),
+            message("user", json!("thanks")),
+        ];
+
+        let expanded = expand_history(messages);
+        assert_eq!(expanded.len(), 2);
+        assert_eq!(expanded[1].content[0]["type"], "tool_result");
+        assert_eq!(expanded[1].content[1]["text"], "thanks");
+    }
+
+    #[test]
+    fn test_inject_tools_skips_client_tools_with_same_name() {
+        let tool = |name: &str| McpTool {
+            server: 0,
+            name: name.to_string(),
+            description: format!("{} tool", name),
+            input_schema: json!({"type": "object", "properties": {}}),
+        };
+        let pool = McpToolPool {
+            connections: Vec::new(),
+            tools: vec![tool("clock"), tool("Read")],
+            max_rounds: 1,
+            tool_timeout: Duration::from_secs(1),
+        };
+        let mut payload: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5-20250929",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "hi"}],
+            "tools": [{"name": "Read", "description": "client", "input_schema": {"type": "object"}}]
+        }))
+        .unwrap();
+        pool.inject_tools(&mut payload);
+        let tools = payload.tools.unwrap();
+        let names: Vec<_> = tools.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["Read", "clock"]);
+        assert_eq!(tools[0].description, "client");
+        assert_eq!(tools[1].input_schema["type"], "object");
+    }
+
+    #[test]
+    fn test_round_events() {
+        let blocks = [
+            json!({"type": "mcp_tool_use", "id": "toolu_1", "name": "clock", "server_name": "time", "input": {"tz": "UTC"}}),
+            json!({"type": "mcp_tool_result", "tool_use_id": "toolu_1", "is_error": false, "content": []}),
+            json!({"type": "text", "text": "It is noon."}),
+        ];
+        let message = json!({
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        });
+        let events: Vec<SseEvent> = blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(index, block)| block_events(index, block))
+            .chain(finish_events(&message))
+            .collect();
+        let names: Vec<_> = events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        assert_eq!(events[0].data["content_block"]["input"], json!({}));
+        assert_eq!(events[1].data["delta"]["partial_json"], r#"{"tz":"UTC"}"#);
+        assert_eq!(events[3].data["content_block"]["type"], "mcp_tool_result");
+        assert_eq!(events[5].data["index"], 2);
+        assert_eq!(events[6].data["delta"]["text"], "It is noon.");
+        assert_eq!(events[8].data["usage"]["output_tokens"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_abort_on_drop_cancels_task() {
+        let task = tokio::spawn(future::pending::<()>());
+        drop(AbortOnDrop(task.abort_handle()));
+        assert!(task.await.unwrap_err().is_cancelled());
+    }
+}
@@ -5,35 +5,97 @@ use std::sync::Arc;
 use axum::{
     body::Body,
     extract::State,
-    http::{Request, StatusCode},
+    http::{Request, StatusCode, header},
     middleware::Next,
     response::{IntoResponse, Json, Response},
 };
+use tracing::Instrument;
 
+use crate::common::admission::AdmissionControl;
+use crate::common::api_keys::{ApiKeyRegistry, ClientKey};
 use crate::common::auth;
 use crate::kiro::provider::KiroProvider;
+use crate::storage::audit_log::AuditLog;
+use crate::storage::ledger::UsageLedger;
 
+use super::batches::BatchStore;
+use super::cancellation::{REQUEST_ID_HEADER, RequestRegistry};
+use super::event_buffer::EventBuffer;
+use super::fallback::FallbackChain;
+use super::files::FileStore;
+use super::idempotency::IdempotencyCache;
+use super::mcp::McpSessions;
+use super::mcp_client::McpToolPool;
+use super::prompt_cache::PromptCache;
+use super::response_cache::ResponseCache;
+use super::summarizer::ContextSummarizer;
 use super::types::ErrorResponse;
 
 /// 应用共享状态
 #[derive(Clone)]
 pub struct AppState {
-    /// API 密钥
-    pub api_key: String,
+    /// 客户端 API Key 注册表
+    pub api_keys: Arc<ApiKeyRegistry>,
     /// Kiro Provider（可选，用于实际 API 调用）
     /// 内部使用 MultiTokenManager，已支持线程安全的多凭据管理
     pub kiro_provider: Option<Arc<KiroProvider>>,
     /// Profile ARN（可选，用于请求）
     pub profile_arn: Option<String>,
+    /// 用量账本（可选）
+    pub usage_ledger: Option<Arc<UsageLedger>>,
+    /// 请求审计日志（可选）
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// 全局并发上限（可选）
+    pub admission: Option<Arc<AdmissionControl>>,
+    /// 幂等请求缓存（可选）
+    pub idempotency: Option<Arc<IdempotencyCache>>,
+    /// 非流式响应缓存（可选）
+    pub response_cache: Option<Arc<ResponseCache>>,
+    /// Prompt caching 前缀指纹存储（可选）
+    pub prompt_cache: Option<Arc<PromptCache>>,
+    /// 上下文摘要器（可选，是否生效由 `contextSummary.enabled` 决定）
+    pub summarizer: Option<Arc<ContextSummarizer>>,
+    /// 备用后端转发器（可选，是否生效由 `fallbackBackends` 决定）
+    pub fallback: Option<Arc<FallbackChain>>,
+    /// 长轮询事件缓冲区
+    pub event_buffer: Arc<EventBuffer>,
+    /// Files API 本地文件存储（可选）
+    pub file_store: Option<Arc<FileStore>>,
+    /// Message Batches API 任务存储（可选）
+    pub batch_store: Option<Arc<BatchStore>>,
+    /// MCP SSE 会话表（未启用 MCP 服务端时为 None）
+    pub mcp_sessions: Option<Arc<McpSessions>>,
+    /// 服务端执行的 MCP 工具（未启用 mcpClient 时为 None）
+    pub mcp_tools: Option<Arc<McpToolPool>>,
+    /// 进行中的请求（用于取消）
+    pub requests: Arc<RequestRegistry>,
+    /// 是否为代理内部重新进入 `post_messages` 的请求（如 MCP 工具续写），
+    /// 请求改写规则已在外层请求中应用，不再重复应用
+    pub internal: bool,
 }
 
 impl AppState {
     /// 创建新的应用状态
-    pub fn new(api_key: impl Into<String>) -> Self {
+    pub fn new(api_keys: Arc<ApiKeyRegistry>) -> Self {
         Self {
-            api_key: api_key.into(),
+            api_keys,
             kiro_provider: None,
             profile_arn: None,
+            usage_ledger: None,
+            audit_log: None,
+            admission: None,
+            idempotency: None,
+            response_cache: None,
+            prompt_cache: None,
+            summarizer: None,
+            fallback: None,
+            event_buffer: Arc::new(EventBuffer::new()),
+            file_store: None,
+            batch_store: None,
+            mcp_sessions: None,
+            mcp_tools: None,
+            requests: Arc::new(RequestRegistry::new()),
+            internal: false,
         }
     }
 
@@ -48,23 +110,203 @@ impl AppState {
         self.profile_arn = Some(arn.into());
         self
     }
+
+    /// 设置用量账本
+    pub fn with_usage_ledger(mut self, ledger: Arc<UsageLedger>) -> Self {
+        self.usage_ledger = Some(ledger);
+        self
+    }
+
+    /// 启用请求审计日志
+    pub fn with_audit_log(mut self, log: Arc<AuditLog>) -> Self {
+        self.audit_log = Some(log);
+        self
+    }
+
+    /// 启用全局并发上限
+    pub fn with_admission(mut self, admission: Arc<AdmissionControl>) -> Self {
+        self.admission = Some(admission);
+        self
+    }
+
+    /// 启用幂等请求缓存
+    pub fn with_idempotency(mut self, cache: Arc<IdempotencyCache>) -> Self {
+        self.idempotency = Some(cache);
+        self
+    }
+
+    /// 启用非流式响应缓存
+    pub fn with_response_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.response_cache = Some(cache);
+        self
+    }
+
+    /// 启用 prompt caching 用量模拟
+    pub fn with_prompt_cache(mut self, cache: Arc<PromptCache>) -> Self {
+        self.prompt_cache = Some(cache);
+        self
+    }
+
+    /// 设置上下文摘要器
+    pub fn with_summarizer(mut self, summarizer: Arc<ContextSummarizer>) -> Self {
+        self.summarizer = Some(summarizer);
+        self
+    }
+
+    /// 设置备用后端转发器
+    pub fn with_fallback(mut self, fallback: Arc<FallbackChain>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// 当前请求使用的用量账本（同时计入客户端 Key 的 token 预算）
+    pub fn usage_ledger_for(&self, client: Option<&ClientKey>) -> Option<Arc<UsageLedger>> {
+        match client {
+            Some(client) => self
+                .usage_ledger
+                .as_ref()
+                .map(|ledger| ledger.for_client(client)),
+            None => self.usage_ledger.clone(),
+        }
+    }
+
+    /// 启用 Files API
+    pub fn with_file_store(mut self, store: Arc<FileStore>) -> Self {
+        self.file_store = Some(store);
+        self
+    }
+
+    /// 启用 Message Batches API
+    pub fn with_batch_store(mut self, store: Arc<BatchStore>) -> Self {
+        self.batch_store = Some(store);
+        self
+    }
+
+    /// 启用 MCP 服务端的 SSE 传输
+    pub fn with_mcp_sessions(mut self, sessions: Arc<McpSessions>) -> Self {
+        self.mcp_sessions = Some(sessions);
+        self
+    }
+
+    /// 启用服务端执行的 MCP 工具
+    pub fn with_mcp_tools(mut self, tools: Arc<McpToolPool>) -> Self {
+        self.mcp_tools = Some(tools);
+        self
+    }
 }
 
 /// API Key 认证中间件
+///
+/// 认证通过后在 `request` span 中记录 Key 名称，后续日志与链路追踪均归属到该 Key；
+/// 超出该 Key 的速率限制或 token 预算时返回 429 并附带 `retry-after`
 pub async fn auth_middleware(
     State(state): State<AppState>,
-    request: Request<Body>,
+    mut request: Request<Body>,
     next: Next,
 ) -> Response {
-    match auth::extract_api_key(&request) {
-        Some(key) if auth::constant_time_eq(&key, &state.api_key) => next.run(request).await,
-        _ => {
+    match auth::extract_api_key(&request).and_then(|key| state.api_keys.authenticate(&key)) {
+        Some(client) => {
+            if let Err(wait) = client.limiter.check() {
+                let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                tracing::info!(
+                    "API Key {} 超出速率限制，{} 秒后可重试",
+                    client.name,
+                    retry_after
+                );
+                let error = ErrorResponse::rate_limit_error(format!(
+                    "Rate limit exceeded for this API key, retry after {} seconds",
+                    retry_after
+                ));
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(error),
+                )
+                    .into_response();
+            }
+            if let Err(exceeded) = state.api_keys.check_quota(&client).await {
+                let retry_after = exceeded.resets_in.as_secs_f64().ceil().max(1.0) as u64;
+                tracing::info!(
+                    "API Key {} 已用完 {} token 预算（{} / {}），{} 秒后重置",
+                    client.name,
+                    exceeded.period.as_str(),
+                    exceeded.used,
+                    exceeded.limit,
+                    retry_after
+                );
+                let error = ErrorResponse::new(
+                    "quota_exceeded",
+                    format!(
+                        "The {} token quota for this API key is exhausted ({} / {}), resets in {} seconds",
+                        exceeded.period.as_str(),
+                        exceeded.used,
+                        exceeded.limit,
+                        retry_after
+                    ),
+                );
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(error),
+                )
+                    .into_response();
+            }
+            state.api_keys.record_request(&client.name);
+
+            // 日志关联 ID：优先使用客户端提供的请求 ID，否则随机生成
+            let request_id = request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+                .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+            let span = tracing::info_span!(
+                "request",
+                api_key = %client.name,
+                request_id = %request_id
+            );
+            request.extensions_mut().insert(client);
+            next.run(request).instrument(span).await
+        }
+        None => {
             let error = ErrorResponse::authentication_error();
             (StatusCode::UNAUTHORIZED, Json(error)).into_response()
         }
     }
 }
 
+/// 全局并发上限中间件
+///
+/// 超出上限的请求按客户端 Key 的优先级排队等待，队列已满或排队超时时返回 429 并附带 `retry-after`；
+/// 许可随响应体释放，流式响应输出期间同样占用并发
+pub async fn admission_middleware(
+    State(admission): State<Arc<AdmissionControl>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let priority = request
+        .extensions()
+        .get::<ClientKey>()
+        .map(|client| client.priority)
+        .unwrap_or_default();
+    match admission.acquire(priority).await {
+        Ok(permit) => permit.hold(next.run(request).await),
+        Err(wait) => {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            let error = ErrorResponse::rate_limit_error(format!(
+                "Server is at capacity, retry after {} seconds",
+                retry_after
+            ));
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Json(error),
+            )
+                .into_response()
+        }
+    }
+}
+
 /// CORS 中间件层
 ///
 /// **安全说明**：当前配置允许所有来源（Any），这是为了支持公开 API 服务。
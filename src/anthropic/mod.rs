@@ -4,8 +4,14 @@
 //!
 //! # 支持的端点
 //! - `GET /v1/models` - 获取可用模型列表
+//! - `GET /v1/models/{id}` - 获取单个模型信息
+//! - `GET /v1/capabilities` - 获取部署能力描述（特性探测）
 //! - `POST /v1/messages` - 创建消息（对话）
 //! - `POST /v1/messages/count_tokens` - 计算 token 数量
+//! - `GET /v1/messages/{id}/events` - 长轮询读取流式事件
+//! - `POST /v1/files` / `GET /v1/files` / `GET /v1/files/{id}` - Files API
+//! - `POST /v1/messages/batches` 等 - Message Batches API
+//! - `GET /v1/mcp/sse` / `POST /v1/mcp/messages` - MCP 服务端（SSE 传输）
 //!
 //! # 使用示例
 //! ```rust,ignore
@@ -16,13 +22,41 @@
 //! axum::serve(listener, app).await?;
 //! ```
 
-mod converter;
-mod handlers;
-mod middleware;
-mod model_config;
+mod batches;
+mod cancellation;
+mod capabilities;
+pub(crate) mod converter;
+pub(crate) mod credential_group;
+pub(crate) mod document;
+mod event_buffer;
+mod fallback;
+mod files;
+pub(crate) mod footer;
+pub(crate) mod handlers;
+mod idempotency;
+pub(crate) mod identity;
+pub(crate) mod image_dedupe;
+pub(crate) mod image_downscale;
+pub(crate) mod image_fetch;
+pub(crate) mod injection;
+mod mcp;
+mod mcp_client;
+pub(crate) mod middleware;
+pub(crate) mod model_config;
+pub(crate) mod model_override;
+pub(crate) mod prompt_cache;
+pub(crate) mod response_cache;
 mod router;
-mod stream;
+mod rules;
+pub(crate) mod stream;
+pub(crate) mod summarizer;
+pub(crate) mod system_prompt;
+pub(crate) mod thinking_signature;
 pub mod types;
+mod validation;
 mod websearch;
 
-pub use router::create_router_with_provider;
+pub use files::FileStore;
+pub use mcp::serve_stdio as serve_mcp_stdio;
+pub use mcp_client::McpToolPool;
+pub use router::{create_app_state, create_router};
@@ -0,0 +1,85 @@
+//! 模型覆盖请求头
+//!
+//! `x-kiro-model-override` 请求头直接指定 Kiro 模型 ID，跳过按 `model` 字段的模型映射，
+//! 便于在不修改客户端的情况下对模型映射做 A/B 测试。仅在开启 `allowModelOverrideHeader`
+//! 或客户端 Key 设置了 `modelOverride` 时生效，其他情况下忽略该请求头
+
+use axum::http::HeaderMap;
+
+use crate::common::api_keys::ClientKey;
+use crate::model::config::Config;
+
+/// 模型覆盖请求头
+pub const MODEL_OVERRIDE_HEADER: &str = "x-kiro-model-override";
+
+/// 本次请求是否允许使用模型覆盖请求头
+pub fn is_allowed(config: &Config, client: Option<&ClientKey>) -> bool {
+    config.allow_model_override_header || client.is_some_and(|c| c.model_override)
+}
+
+/// 解析请求头指定的 Kiro 模型 ID（未允许、未设置或为空时返回 None）
+pub fn resolve(config: &Config, client: Option<&ClientKey>, headers: &HeaderMap) -> Option<String> {
+    if !is_allowed(config, client) {
+        return None;
+    }
+    let value = headers.get(MODEL_OVERRIDE_HEADER)?.to_str().ok()?.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use axum::http::HeaderValue;
+
+    use crate::common::rate_limit::KeyRateLimiter;
+    use crate::model::config::RateLimitConfig;
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(MODEL_OVERRIDE_HEADER, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn client(model_override: bool) -> ClientKey {
+        ClientKey {
+            name: "team".to_string(),
+            limiter: Arc::new(KeyRateLimiter::new(&RateLimitConfig::default())),
+            group: None,
+            priority: Default::default(),
+            quota: Default::default(),
+            mcp_tools: false,
+            model_override,
+        }
+    }
+
+    #[test]
+    fn test_resolve_gated_by_config() {
+        let config = Config::default();
+        assert_eq!(resolve(&config, None, &headers(" claude-haiku-4.5 ")), None);
+
+        let config = Config {
+            allow_model_override_header: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            resolve(&config, None, &headers(" claude-haiku-4.5 ")),
+            Some("claude-haiku-4.5".to_string())
+        );
+        assert_eq!(resolve(&config, None, &headers("  ")), None);
+    }
+
+    #[test]
+    fn test_resolve_gated_by_client_permission() {
+        let config = Config::default();
+        assert_eq!(
+            resolve(&config, Some(&client(false)), &headers("claude-haiku-4.5")),
+            None
+        );
+        assert_eq!(
+            resolve(&config, Some(&client(true)), &headers("claude-haiku-4.5")),
+            Some("claude-haiku-4.5".to_string())
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Prompt caching 用量模拟
+//!
+//! Kiro 上游不支持 Anthropic 的 prompt caching，`cache_control` 标记在转换请求时即被丢弃。
+//! 为了让依赖缓存用量的客户端（成本统计、缓存命中率展示等）正常工作，代理在本地记录
+//! 每个缓存断点之前的前缀指纹：
+//!
+//! - 前缀按 tools → system → messages 的顺序计算滚动 SHA-256，并以客户端与模型区分
+//! - 命中已记录的最长前缀时，该前缀的 tokens 计入 `cache_read_input_tokens`
+//! - 最后一个断点之前其余的 tokens 计入 `cache_creation_input_tokens`
+//! - 指纹有效期默认 5 分钟，`cache_control.ttl` 为 `1h` 时为 1 小时，每次命中都会刷新
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::common::cache::FlushableCache;
+use crate::storage::Storage;
+use crate::token;
+
+use super::types::{CacheControl, MessagesRequest};
+
+/// 前缀指纹使用的存储命名空间
+const NAMESPACE: &str = "prompt_cache";
+
+/// 默认缓存有效期（5 分钟）
+const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// 扩展缓存有效期（1 小时）
+const EXTENDED_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// 单次请求的缓存用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PromptCacheUsage {
+    /// 本次写入缓存的 tokens
+    pub cache_creation_input_tokens: i32,
+    /// 从缓存读取的 tokens
+    pub cache_read_input_tokens: i32,
+}
+
+impl PromptCacheUsage {
+    /// 将总输入 tokens 拆分为（未缓存部分, 缓存写入, 缓存读取），三者之和等于总数
+    pub fn split(&self, total_input_tokens: i32) -> (i32, i32, i32) {
+        let total = total_input_tokens.max(0);
+        let read = self.cache_read_input_tokens.clamp(0, total);
+        let creation = self.cache_creation_input_tokens.clamp(0, total - read);
+        (total - read - creation, creation, read)
+    }
+
+    /// 生成 Anthropic 响应中的 usage 字段
+    pub fn usage_json(&self, total_input_tokens: i32, output_tokens: i32) -> Value {
+        let (input, creation, read) = self.split(total_input_tokens);
+        json!({
+            "input_tokens": input,
+            "output_tokens": output_tokens,
+            "cache_creation_input_tokens": creation,
+            "cache_read_input_tokens": read
+        })
+    }
+}
+
+/// 缓存断点：前缀指纹、前缀累计 tokens 与有效期
+struct Breakpoint {
+    fingerprint: String,
+    tokens: i32,
+    ttl: Duration,
+}
+
+/// Prompt caching 前缀指纹存储
+pub struct PromptCache {
+    storage: Arc<dyn Storage>,
+}
+
+impl PromptCache {
+    /// 基于存储后端创建指纹存储
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// 计算请求的缓存用量并记录本次请求的断点
+    ///
+    /// `scope` 用于隔离不同客户端与模型的缓存（如 `客户端名/模型名`）；
+    /// 请求中没有 `cache_control` 标记时返回全 0
+    pub fn observe(&self, scope: &str, request: &MessagesRequest) -> PromptCacheUsage {
+        let breakpoints = breakpoints(scope, request);
+        let Some(last) = breakpoints.last() else {
+            return PromptCacheUsage::default();
+        };
+
+        // 从最长的前缀开始查找已记录的断点
+        let read = breakpoints
+            .iter()
+            .rev()
+            .find(|bp| match self.storage.get(NAMESPACE, &bp.fingerprint) {
+                Ok(value) => value.is_some(),
+                Err(e) => {
+                    tracing::warn!("读取 prompt cache 指纹失败: {}", e);
+                    false
+                }
+            })
+            .map(|bp| bp.tokens)
+            .unwrap_or(0);
+
+        for bp in &breakpoints {
+            if let Err(e) = self.storage.put(
+                NAMESPACE,
+                &bp.fingerprint,
+                &bp.tokens.to_string(),
+                Some(bp.ttl),
+            ) {
+                tracing::warn!("写入 prompt cache 指纹失败: {}", e);
+            }
+        }
+
+        let usage = PromptCacheUsage {
+            cache_creation_input_tokens: (last.tokens - read).max(0),
+            cache_read_input_tokens: read,
+        };
+        tracing::debug!(
+            "Prompt cache - 断点数: {}, 写入 tokens: {}, 读取 tokens: {}",
+            breakpoints.len(),
+            usage.cache_creation_input_tokens,
+            usage.cache_read_input_tokens
+        );
+        usage
+    }
+}
+
+impl FlushableCache for PromptCache {
+    fn flush(&self) -> anyhow::Result<usize> {
+        self.storage.clear(NAMESPACE)
+    }
+}
+
+/// 按 tools → system → messages 的顺序遍历请求，收集所有缓存断点
+fn breakpoints(scope: &str, request: &MessagesRequest) -> Vec<Breakpoint> {
+    let mut hasher = Sha256::new();
+    hasher.update(scope.as_bytes());
+    let mut tokens = 0i32;
+    let mut breakpoints = Vec::new();
+
+    let mut visit = |segment: &str, marker: Option<&CacheControl>| {
+        hasher.update(segment.as_bytes());
+        hasher.update([0u8]);
+        tokens += token::count_tokens(segment) as i32;
+        if let Some(marker) = marker {
+            breakpoints.push(Breakpoint {
+                fingerprint: hex::encode(hasher.clone().finalize()),
+                tokens,
+                ttl: marker.ttl(),
+            });
+        }
+    };
+
+    for tool in request.tools.iter().flatten() {
+        let mut tool = tool.clone();
+        let marker = tool.cache_control.take();
+        visit(
+            &serde_json::to_string(&tool).unwrap_or_default(),
+            marker.as_ref(),
+        );
+    }
+
+    for system in request.system.iter().flatten() {
+        visit(&system.text, system.cache_control.as_ref());
+    }
+
+    for message in &request.messages {
+        match &message.content {
+            Value::Array(blocks) => {
+                for block in blocks {
+                    let mut block = block.clone();
+                    let marker = block
+                        .as_object_mut()
+                        .and_then(|b| b.remove("cache_control"))
+                        .and_then(|m| serde_json::from_value::<CacheControl>(m).ok());
+                    let segment = format!("{}:{}", message.role, block);
+                    visit(&segment, marker.as_ref());
+                }
+            }
+            content => visit(&format!("{}:{}", message.role, content), None),
+        }
+    }
+
+    breakpoints
+}
+
+impl CacheControl {
+    /// 标记对应的缓存有效期
+    fn ttl(&self) -> Duration {
+        match self.ttl.as_deref() {
+            Some("1h") => EXTENDED_TTL,
+            _ => DEFAULT_TTL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn request(system: &str, question: &str) -> MessagesRequest {
+        serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "system": [
+                {"type": "text", "text": system, "cache_control": {"type": "ephemeral"}}
+            ],
+            "messages": [
+                {"role": "user", "content": [{"type": "text", "text": question}]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_split_clamps_to_total() {
+        let usage = PromptCacheUsage {
+            cache_creation_input_tokens: 30,
+            cache_read_input_tokens: 80,
+        };
+        assert_eq!(usage.split(100), (0, 20, 80));
+        assert_eq!(usage.split(200), (90, 30, 80));
+        assert_eq!(PromptCacheUsage::default().split(42), (42, 0, 0));
+    }
+
+    #[test]
+    fn test_creation_then_read() {
+        let cache = PromptCache::new(Arc::new(MemoryStorage::new()));
+        let system = "You are a helpful assistant. ".repeat(50);
+
+        let first = cache.observe("client/model", &request(&system, "hello"));
+        assert_eq!(first.cache_read_input_tokens, 0);
+        assert!(first.cache_creation_input_tokens > 0);
+
+        // 相同前缀、不同问题：system 前缀命中缓存
+        let second = cache.observe("client/model", &request(&system, "another question"));
+        assert_eq!(
+            second.cache_read_input_tokens,
+            first.cache_creation_input_tokens
+        );
+        assert_eq!(second.cache_creation_input_tokens, 0);
+
+        // 不同客户端互不共享
+        let other = cache.observe("other/model", &request(&system, "hello"));
+        assert_eq!(other.cache_read_input_tokens, 0);
+
+        assert!(cache.flush().unwrap() > 0);
+        let flushed = cache.observe("client/model", &request(&system, "hello"));
+        assert_eq!(flushed.cache_read_input_tokens, 0);
+    }
+
+    #[test]
+    fn test_no_markers() {
+        let cache = PromptCache::new(Arc::new(MemoryStorage::new()));
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "system": "plain system prompt",
+            "messages": [{"role": "user", "content": "hi"}]
+        }))
+        .unwrap();
+        assert_eq!(
+            cache.observe("client/model", &request),
+            PromptCacheUsage::default()
+        );
+    }
+}
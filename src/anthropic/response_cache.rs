@@ -0,0 +1,293 @@
+//! 非流式响应缓存
+//!
+//! 评测脚本等场景会反复发送完全相同的非流式请求，启用 `responseCache` 后，
+//! 这类请求直接返回进程内缓存的响应，不再调用上游。
+//!
+//! 缓存键为转换后的 `ConversationState`（不含每次请求随机生成的会话 ID 与延续 ID）
+//! 与本地处理参数（模型名、max_tokens、stop_sequences、页脚等）的 SHA-256 摘要；
+//! 请求头 `x-kiro-cache: bypass` 或 `Cache-Control: no-cache` / `no-store` 可绕过缓存
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{Body, Bytes, to_bytes},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::common::cache::FlushableCache;
+use crate::kiro::model::requests::conversation::ConversationState;
+use crate::model::config::ResponseCacheConfig;
+
+/// 缓存状态请求头/响应头（请求：`bypass` 绕过缓存；响应：`hit` / `miss`）
+pub const CACHE_HEADER: &str = "x-kiro-cache";
+
+/// 可缓存响应体的最大大小
+const MAX_CACHED_BODY: usize = 10 * 1024 * 1024;
+
+struct Entry {
+    body: Bytes,
+    stored_at: Instant,
+    /// 最近使用序号（越大越新）
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    /// 最近使用序号 → 缓存键，用于淘汰最久未使用的条目
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.order.remove(&entry.last_used);
+            entry.last_used = self.tick;
+            self.order.insert(self.tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.last_used);
+        }
+    }
+}
+
+/// 非流式响应缓存（LRU + TTL，仅保存在内存中）
+pub struct ResponseCache {
+    lru: Mutex<Lru>,
+    max_entries: usize,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(config: &ResponseCacheConfig) -> Self {
+        Self {
+            lru: Mutex::new(Lru::default()),
+            max_entries: config.max_entries.max(1),
+            ttl: Duration::from_secs(config.ttl_secs),
+        }
+    }
+
+    /// 请求是否要求绕过缓存
+    pub fn bypass(headers: &HeaderMap) -> bool {
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_ascii_lowercase)
+                .unwrap_or_default()
+        };
+        header_value(CACHE_HEADER) == "bypass"
+            || header_value(header::CACHE_CONTROL.as_str())
+                .split(',')
+                .any(|d| matches!(d.trim(), "no-cache" | "no-store"))
+    }
+
+    /// 计算缓存键
+    ///
+    /// `endpoint` 区分响应格式（Anthropic / OpenAI），`params` 为影响响应内容的本地处理参数
+    pub fn key(endpoint: &str, state: &ConversationState, params: &impl Serialize) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(endpoint.as_bytes());
+        // 会话 ID 与延续 ID 每次请求都不同，不参与计算
+        let _ = serde_json::to_writer(&mut hasher, &state.agent_task_type);
+        let _ = serde_json::to_writer(&mut hasher, &state.chat_trigger_type);
+        let _ = serde_json::to_writer(&mut hasher, &state.current_message);
+        let _ = serde_json::to_writer(&mut hasher, &state.history);
+        let _ = serde_json::to_writer(&mut hasher, params);
+        hex::encode(hasher.finalize())
+    }
+
+    /// 查找未过期的缓存响应
+    pub fn lookup(&self, key: &str) -> Option<Response> {
+        let mut lru = self.lru.lock();
+        let entry = lru.entries.get(key)?;
+        if entry.stored_at.elapsed() >= self.ttl {
+            lru.remove(key);
+            return None;
+        }
+        let body = entry.body.clone();
+        lru.touch(key);
+        drop(lru);
+
+        tracing::info!("命中响应缓存: {}", key);
+        Some(
+            (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, "application/json"),
+                    (header::HeaderName::from_static(CACHE_HEADER), "hit"),
+                ],
+                body,
+            )
+                .into_response(),
+        )
+    }
+
+    /// 缓存成功的响应并原样返回（非 200 响应不缓存）
+    pub async fn store(&self, key: &str, response: Response) -> Response {
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let bytes = match to_bytes(body, MAX_CACHED_BODY).await {
+            Ok(b) => b,
+            Err(e) => {
+                tracing::warn!("读取响应体失败，无法写入响应缓存: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "读取响应体失败").into_response();
+            }
+        };
+
+        {
+            let mut lru = self.lru.lock();
+            lru.remove(key);
+            while lru.entries.len() >= self.max_entries {
+                let Some((_, oldest)) = lru.order.pop_first() else {
+                    break;
+                };
+                lru.entries.remove(&oldest);
+            }
+            lru.entries.insert(
+                key.to_string(),
+                Entry {
+                    body: bytes.clone(),
+                    stored_at: Instant::now(),
+                    last_used: 0,
+                },
+            );
+            lru.touch(key);
+        }
+
+        parts
+            .headers
+            .insert(CACHE_HEADER, HeaderValue::from_static("miss"));
+        Response::from_parts(parts, Body::from(bytes))
+    }
+}
+
+/// 非流式请求使用的缓存与缓存键（未启用缓存或请求要求绕过缓存时为 `None`）
+pub fn resolve<'a>(
+    cache: Option<&'a Arc<ResponseCache>>,
+    headers: &HeaderMap,
+    endpoint: &str,
+    state: &ConversationState,
+    params: &impl Serialize,
+) -> Option<(&'a ResponseCache, String)> {
+    let cache = cache?;
+    if ResponseCache::bypass(headers) {
+        return None;
+    }
+    Some((cache, ResponseCache::key(endpoint, state, params)))
+}
+
+impl FlushableCache for ResponseCache {
+    fn flush(&self) -> anyhow::Result<usize> {
+        let mut lru = self.lru.lock();
+        let flushed = lru.entries.len();
+        *lru = Lru::default();
+        Ok(flushed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(max_entries: usize) -> ResponseCache {
+        ResponseCache::new(&ResponseCacheConfig {
+            enabled: true,
+            max_entries,
+            ttl_secs: 60,
+        })
+    }
+
+    #[test]
+    fn test_key_ignores_per_request_ids() {
+        let a = ConversationState::new("conv-a").with_agent_continuation_id("cont-a");
+        let b = ConversationState::new("conv-b").with_agent_continuation_id("cont-b");
+
+        let params = ("claude-sonnet-4-5", 1024);
+        assert_eq!(
+            ResponseCache::key("messages", &a, &params),
+            ResponseCache::key("messages", &b, &params)
+        );
+        assert_ne!(
+            ResponseCache::key("messages", &a, &params),
+            ResponseCache::key("messages", &a, &("claude-sonnet-4-5", 2048))
+        );
+        assert_ne!(
+            ResponseCache::key("messages", &a, &params),
+            ResponseCache::key("chat", &a, &params)
+        );
+    }
+
+    #[test]
+    fn test_bypass_headers() {
+        let mut headers = HeaderMap::new();
+        assert!(!ResponseCache::bypass(&headers));
+        headers.insert(CACHE_HEADER, HeaderValue::from_static("Bypass"));
+        assert!(ResponseCache::bypass(&headers));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("max-age=0, no-cache"),
+        );
+        assert!(ResponseCache::bypass(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_store_lookup_and_evict() {
+        let cache = cache(2);
+        assert!(cache.lookup("k1").is_none());
+
+        let response = cache
+            .store("k1", (StatusCode::OK, "{\"id\":\"msg_1\"}").into_response())
+            .await;
+        assert_eq!(response.headers()[CACHE_HEADER], "miss");
+        cache
+            .store("k2", (StatusCode::OK, "{}").into_response())
+            .await;
+
+        // k1 最近被使用过，写入 k3 时淘汰 k2
+        let hit = cache.lookup("k1").unwrap();
+        assert_eq!(hit.headers()[CACHE_HEADER], "hit");
+        let body = to_bytes(hit.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"{\"id\":\"msg_1\"}");
+        cache
+            .store("k3", (StatusCode::OK, "{}").into_response())
+            .await;
+        assert!(cache.lookup("k2").is_none());
+        assert!(cache.lookup("k1").is_some());
+
+        cache
+            .store("k4", (StatusCode::BAD_GATEWAY, "error").into_response())
+            .await;
+        assert!(cache.lookup("k4").is_none());
+        assert_eq!(cache.flush().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_entry_is_dropped() {
+        let cache = ResponseCache::new(&ResponseCacheConfig {
+            enabled: true,
+            max_entries: 10,
+            ttl_secs: 0,
+        });
+        cache
+            .store("k1", (StatusCode::OK, "{}").into_response())
+            .await;
+        assert!(cache.lookup("k1").is_none());
+    }
+}
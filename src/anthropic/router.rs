@@ -4,61 +4,218 @@ use axum::{
     Router,
     extract::DefaultBodyLimit,
     middleware,
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 
+use std::sync::Arc;
+
+use crate::common::admission::AdmissionControl;
+use crate::common::api_keys::ApiKeyRegistry;
+use crate::common::audit::{AuditWriter, audit_middleware};
+use crate::common::cache::{CacheKind, CacheRegistry};
+use crate::gemini::model_action;
 use crate::kiro::provider::KiroProvider;
+use crate::openai::chat_completions;
+use crate::storage::Storage;
+use crate::storage::audit_log::AuditLog;
+use crate::storage::ledger::UsageLedger;
 
 use super::{
-    handlers::{count_tokens, get_models, post_messages},
-    middleware::{AppState, auth_middleware, cors_layer},
+    batches::{BatchStore, cancel_batch, create_batch, get_batch, get_batch_results, list_batches},
+    capabilities::get_capabilities,
+    fallback::FallbackChain,
+    files::{FileStore, get_file, list_files, upload_file},
+    handlers::{
+        cancel_message, count_tokens, get_message_events, get_model, get_models, post_messages,
+    },
+    idempotency::IdempotencyCache,
+    mcp::{McpSessions, mcp_message, mcp_sse},
+    middleware::{AppState, admission_middleware, auth_middleware, cors_layer},
+    prompt_cache::PromptCache,
+    response_cache::ResponseCache,
+    summarizer::ContextSummarizer,
+    validation::validate_request,
 };
 
 /// 请求体最大大小限制 (50MB)
-const MAX_BODY_SIZE: usize = 50 * 1024 * 1024;
+pub(super) const MAX_BODY_SIZE: usize = 50 * 1024 * 1024;
+
+/// 创建 Anthropic API 的应用状态（带有 KiroProvider）
+///
+/// # 参数
+/// - `api_keys`: 客户端 API Key 注册表，用于验证客户端请求
+/// - `kiro_provider`: 可选的 KiroProvider，用于调用上游 API
+pub fn create_app_state(
+    api_keys: Arc<ApiKeyRegistry>,
+    kiro_provider: Option<KiroProvider>,
+    profile_arn: Option<String>,
+    storage: Arc<dyn Storage>,
+    usage_ledger: Arc<UsageLedger>,
+    file_store: Option<Arc<FileStore>>,
+    caches: &CacheRegistry,
+) -> AppState {
+    let idempotency = Arc::new(IdempotencyCache::new(storage.clone()));
+    caches.register(CacheKind::Response, idempotency.clone());
+    let prompt_cache = Arc::new(PromptCache::new(storage.clone()));
+    caches.register(CacheKind::Response, prompt_cache.clone());
+
+    let mut state = AppState::new(api_keys)
+        .with_usage_ledger(usage_ledger)
+        .with_idempotency(idempotency)
+        .with_prompt_cache(prompt_cache)
+        .with_summarizer(Arc::new(ContextSummarizer::new(storage.clone())));
+    if let Some(provider) = kiro_provider {
+        let cache_config = &provider.token_manager().config().response_cache;
+        if cache_config.enabled {
+            let cache = Arc::new(ResponseCache::new(cache_config));
+            caches.register(CacheKind::Response, cache.clone());
+            state = state.with_response_cache(cache);
+            tracing::info!(
+                "已启用非流式响应缓存（最多 {} 条，有效期 {} 秒）",
+                cache_config.max_entries,
+                cache_config.ttl_secs
+            );
+        }
+        match FallbackChain::new(&provider.token_manager().config()) {
+            Ok(fallback) => state = state.with_fallback(Arc::new(fallback)),
+            Err(e) => tracing::warn!("创建备用后端 HTTP 客户端失败，备用后端不可用: {}", e),
+        }
+        let batch_config = &provider.token_manager().config().batches;
+        match BatchStore::open(batch_config) {
+            Ok(store) => {
+                tracing::info!(
+                    "已启用 Message Batches API（目录: {}，并发: {}）",
+                    batch_config.dir,
+                    batch_config.concurrency
+                );
+                state = state.with_batch_store(Arc::new(store));
+            }
+            Err(e) => tracing::warn!("打开批处理任务目录失败，Message Batches API 不可用: {}", e),
+        }
+        let audit_config = &provider.token_manager().config().audit_log;
+        if audit_config.enabled {
+            tracing::info!("已启用请求审计日志（最多 {} 条）", audit_config.max_entries);
+            state = state.with_audit_log(Arc::new(AuditLog::new(
+                storage.clone(),
+                audit_config.max_entries,
+            )));
+        }
+        let admission_config = &provider.token_manager().config().admission;
+        if admission_config.max_in_flight > 0 {
+            tracing::info!(
+                "已启用全局并发上限（{} 个请求，最多排队 {} 个）",
+                admission_config.max_in_flight,
+                admission_config.max_queue
+            );
+            state = state.with_admission(Arc::new(AdmissionControl::new(admission_config)));
+        }
+        if provider.token_manager().config().mcp_server.enabled {
+            tracing::info!("已启用 MCP 服务端（SSE）");
+            state = state.with_mcp_sessions(Arc::new(McpSessions::default()));
+        }
+        state = state.with_kiro_provider(provider);
+    }
+    if let Some(arn) = profile_arn {
+        state = state.with_profile_arn(arn);
+    }
+    if let Some(store) = file_store {
+        state = state.with_file_store(store);
+    }
+    state
+}
 
 /// 创建 Anthropic API 路由
 ///
 /// # 端点
 /// - `GET /v1/models` - 获取可用模型列表
+/// - `GET /v1/models/{id}` - 获取单个模型信息
+/// - `GET /v1/capabilities` - 获取部署能力描述
 /// - `POST /v1/messages` - 创建消息（对话）
 /// - `POST /v1/messages/count_tokens` - 计算 token 数量
+/// - `DELETE /v1/messages/{id}` - 取消进行中的消息请求
+/// - `GET /v1/messages/{id}/events` - 长轮询读取流式事件
+/// - `POST /v1/chat/completions` - OpenAI 兼容的对话补全
+/// - `POST /v1/files` - 上传文件
+/// - `GET /v1/files` - 列出文件
+/// - `GET /v1/files/{file_id}` - 获取文件元数据
+/// - `POST /v1/messages/batches` - 创建批处理任务
+/// - `GET /v1/messages/batches` - 列出批处理任务
+/// - `GET /v1/messages/batches/{batch_id}` - 获取批处理任务状态
+/// - `POST /v1/messages/batches/{batch_id}/cancel` - 取消批处理任务
+/// - `GET /v1/messages/batches/{batch_id}/results` - 下载批处理结果（JSONL）
+/// - `GET /v1/mcp/sse` - 建立 MCP SSE 会话
+/// - `POST /v1/mcp/messages` - 提交 MCP JSON-RPC 消息
+/// - `POST /v1beta/models/{model}:generateContent` - Gemini 兼容的内容生成（另有
+///   `:streamGenerateContent` 与 `:countTokens`）
 ///
 /// # 认证
-/// 所有 `/v1` 路径需要 API Key 认证，支持：
+/// 所有 `/v1` 与 `/v1beta` 路径需要 API Key 认证，支持：
 /// - `x-api-key` header
 /// - `Authorization: Bearer <token>` header
-///
-/// # 参数
-/// - `api_key`: API 密钥，用于验证客户端请求
-/// - `kiro_provider`: 可选的 KiroProvider，用于调用上游 API
-
-/// 创建带有 KiroProvider 的 Anthropic API 路由
-pub fn create_router_with_provider(
-    api_key: impl Into<String>,
-    kiro_provider: Option<KiroProvider>,
-    profile_arn: Option<String>,
-) -> Router {
-    let mut state = AppState::new(api_key);
-    if let Some(provider) = kiro_provider {
-        state = state.with_kiro_provider(provider);
+/// - `x-goog-api-key` header
+pub fn create_router(state: AppState) -> Router {
+    let mut messages = post(post_messages);
+    let mut completions = post(chat_completions);
+    let mut generate = post(model_action);
+    // 并发上限位于 schema 校验之内，校验失败的请求不占用并发
+    if let Some(admission) = &state.admission {
+        let layer = || middleware::from_fn_with_state(admission.clone(), admission_middleware);
+        messages = messages.layer(layer());
+        completions = completions.layer(layer());
+        generate = generate.layer(layer());
     }
-    if let Some(arn) = profile_arn {
-        state = state.with_profile_arn(arn);
+    messages = messages.layer(middleware::from_fn_with_state(
+        state.clone(),
+        validate_request,
+    ));
+    // 审计位于 schema 校验之外，校验失败的请求同样记录
+    if let Some(log) = &state.audit_log {
+        let writer = AuditWriter::spawn(log.clone());
+        messages = messages.layer(middleware::from_fn_with_state(
+            writer.clone(),
+            audit_middleware,
+        ));
+        completions = completions.layer(middleware::from_fn_with_state(writer, audit_middleware));
     }
 
     // 需要认证的 /v1 路由
     let v1_routes = Router::new()
         .route("/models", get(get_models))
-        .route("/messages", post(post_messages))
+        .route("/models/{id}", get(get_model))
+        .route("/capabilities", get(get_capabilities))
+        .route("/messages", messages)
         .route("/messages/count_tokens", post(count_tokens))
+        .route("/messages/batches", post(create_batch).get(list_batches))
+        .route("/messages/batches/{batch_id}", get(get_batch))
+        .route("/messages/batches/{batch_id}/cancel", post(cancel_batch))
+        .route(
+            "/messages/batches/{batch_id}/results",
+            get(get_batch_results),
+        )
+        .route("/messages/{id}", delete(cancel_message))
+        .route("/messages/{id}/events", get(get_message_events))
+        .route("/chat/completions", completions)
+        .route("/files", post(upload_file).get(list_files))
+        .route("/files/{file_id}", get(get_file))
+        .route("/mcp/sse", get(mcp_sse))
+        .route("/mcp/messages", post(mcp_message))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
         ));
 
+    // 需要认证的 Gemini 兼容路由
+    let v1beta_routes =
+        Router::new()
+            .route("/models/{target}", generate)
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ));
+
     Router::new()
         .nest("/v1", v1_routes)
+        .nest("/v1beta", v1beta_routes)
         .layer(cors_layer())
         .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
         .with_state(state)
@@ -0,0 +1,231 @@
+//! 请求改写规则
+//!
+//! `requestRules` 在 `/v1/messages` 转换请求之前按顺序匹配（模型名、请求头、客户端 Key），
+//! 命中的规则依次执行动作：插入系统提示、移除工具、强制 thinking 预算、限制 max_tokens。
+//! 运维无需改代码即可按调用方调整流量
+
+use axum::http::HeaderMap;
+
+use crate::model::config::{RequestRule, RuleActions, RuleMatch};
+
+use super::types::{MAX_BUDGET_TOKENS, MessagesRequest, SystemMessage, Thinking};
+
+/// 匹配所有模型 / 所有工具 / 任意请求头值的通配符
+const WILDCARD: &str = "*";
+
+/// 按顺序应用命中的规则，返回命中的规则名称
+pub fn apply(
+    rules: &[RequestRule],
+    payload: &mut MessagesRequest,
+    headers: &HeaderMap,
+    client_key: Option<&str>,
+) -> Vec<String> {
+    let mut applied = Vec::new();
+    for rule in rules {
+        if matches(&rule.matcher, &payload.model, headers, client_key) {
+            apply_actions(&rule.actions, payload);
+            applied.push(rule.name.clone());
+        }
+    }
+    applied
+}
+
+/// 所有已设置的条件都满足时命中
+fn matches(
+    matcher: &RuleMatch,
+    model: &str,
+    headers: &HeaderMap,
+    client_key: Option<&str>,
+) -> bool {
+    let model_matches = matcher.model.as_deref().is_none_or(|pattern| {
+        pattern == WILDCARD || model.to_lowercase().contains(&pattern.to_lowercase())
+    });
+    let headers_match = matcher.headers.iter().all(|(name, expected)| {
+        headers
+            .get(name.as_str())
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|value| expected == WILDCARD || value.eq_ignore_ascii_case(expected))
+    });
+    let client_matches = matcher
+        .client_key
+        .as_deref()
+        .is_none_or(|name| client_key == Some(name));
+    model_matches && headers_match && client_matches
+}
+
+fn apply_actions(actions: &RuleActions, payload: &mut MessagesRequest) {
+    if let Some(text) = actions.inject_system.as_deref().filter(|t| !t.is_empty()) {
+        payload.system.get_or_insert_with(Vec::new).insert(
+            0,
+            SystemMessage {
+                text: text.to_string(),
+                cache_control: None,
+            },
+        );
+    }
+
+    if !actions.strip_tools.is_empty() {
+        strip_tools(&actions.strip_tools, payload);
+    }
+
+    if let Some(budget) = actions.thinking_budget {
+        payload.thinking = (budget > 0).then(|| Thinking {
+            thinking_type: "enabled".to_string(),
+            budget_tokens: budget.min(MAX_BUDGET_TOKENS),
+        });
+    }
+
+    if let Some(cap) = actions.max_tokens.filter(|&cap| cap > 0) {
+        payload.max_tokens = payload.max_tokens.min(cap);
+    }
+}
+
+/// 移除指定工具；tool_choice 指向的工具被移除或已没有工具时一并清除 tool_choice
+fn strip_tools(names: &[String], payload: &mut MessagesRequest) {
+    let Some(tools) = payload.tools.as_mut() else {
+        return;
+    };
+    let strip_all = names.iter().any(|n| n == WILDCARD);
+    tools.retain(|tool| !strip_all && !names.contains(&tool.name));
+
+    let forced_tool = payload
+        .tool_choice
+        .as_ref()
+        .and_then(|choice| choice.get("name"))
+        .and_then(|name| name.as_str());
+    let forced_tool_removed = forced_tool.is_some_and(|name| !tools.iter().any(|t| t.name == name));
+    if tools.is_empty() || forced_tool_removed {
+        payload.tool_choice = None;
+    }
+    if tools.is_empty() {
+        payload.tools = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn request(value: serde_json::Value) -> MessagesRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn tool(name: &str) -> serde_json::Value {
+        serde_json::json!({"name": name, "description": "", "input_schema": {"type": "object"}})
+    }
+
+    #[test]
+    fn test_match_conditions() {
+        let rules = vec![
+            RequestRule {
+                name: "opus-batch".to_string(),
+                matcher: RuleMatch {
+                    model: Some("opus".to_string()),
+                    headers: [("x-traffic".to_string(), "batch".to_string())].into(),
+                    client_key: None,
+                },
+                actions: RuleActions::default(),
+            },
+            RequestRule {
+                name: "ci".to_string(),
+                matcher: RuleMatch {
+                    client_key: Some("ci".to_string()),
+                    ..RuleMatch::default()
+                },
+                actions: RuleActions::default(),
+            },
+        ];
+        let mut payload = request(serde_json::json!({
+            "model": "claude-opus-4-5",
+            "max_tokens": 1024,
+            "messages": [{"role": "user", "content": "hi"}]
+        }));
+
+        let mut headers = HeaderMap::new();
+        assert!(apply(&rules, &mut payload, &headers, None).is_empty());
+
+        headers.insert("x-traffic", HeaderValue::from_static("Batch"));
+        assert_eq!(
+            apply(&rules, &mut payload, &headers, Some("ci")),
+            vec!["opus-batch", "ci"]
+        );
+
+        payload.model = "claude-sonnet-4-5".to_string();
+        assert_eq!(
+            apply(&rules, &mut payload, &headers, Some("ci")),
+            vec!["ci"]
+        );
+    }
+
+    #[test]
+    fn test_actions() {
+        let rules = vec![RequestRule {
+            name: "shape".to_string(),
+            matcher: RuleMatch::default(),
+            actions: RuleActions {
+                inject_system: Some("Be brief.".to_string()),
+                strip_tools: vec!["Bash".to_string()],
+                thinking_budget: Some(100_000),
+                max_tokens: Some(4096),
+            },
+        }];
+        let mut payload = request(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 32000,
+            "messages": [{"role": "user", "content": "hi"}],
+            "system": [{"type": "text", "text": "Original."}],
+            "tools": [tool("Bash"), tool("Read")],
+            "tool_choice": {"type": "tool", "name": "Bash"}
+        }));
+
+        apply(&rules, &mut payload, &HeaderMap::new(), None);
+
+        let system: Vec<&str> = payload
+            .system
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| s.text.as_str())
+            .collect();
+        assert_eq!(system, vec!["Be brief.", "Original."]);
+        let tools: Vec<&str> = payload
+            .tools
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect();
+        assert_eq!(tools, vec!["Read"]);
+        assert!(payload.tool_choice.is_none());
+        assert_eq!(payload.thinking.unwrap().budget_tokens, MAX_BUDGET_TOKENS);
+        assert_eq!(payload.max_tokens, 4096);
+    }
+
+    #[test]
+    fn test_strip_all_tools_and_disable_thinking() {
+        let rules = vec![RequestRule {
+            name: "plain".to_string(),
+            matcher: RuleMatch::default(),
+            actions: RuleActions {
+                strip_tools: vec![WILDCARD.to_string()],
+                thinking_budget: Some(0),
+                ..RuleActions::default()
+            },
+        }];
+        let mut payload = request(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "messages": [{"role": "user", "content": "hi"}],
+            "tools": [tool("Bash")],
+            "tool_choice": {"type": "auto"},
+            "thinking": {"type": "enabled", "budget_tokens": 2048}
+        }));
+
+        apply(&rules, &mut payload, &HeaderMap::new(), None);
+
+        assert!(payload.tools.is_none());
+        assert!(payload.tool_choice.is_none());
+        assert!(payload.thinking.is_none());
+    }
+}
@@ -2,12 +2,16 @@
 //!
 //! 实现 Kiro → Anthropic 流式响应转换和 SSE 状态管理
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::kiro::model::events::Event;
+use crate::token;
+
+use super::prompt_cache::PromptCacheUsage;
+use super::thinking_signature;
 
 /// 找到小于等于目标位置的最近有效UTF-8字符边界
 ///
@@ -196,6 +200,15 @@ impl SseEvent {
     }
 }
 
+/// 等待输出的工具调用（其他工具块输出期间收到的增量）
+#[derive(Debug)]
+struct PendingToolUse {
+    tool_use_id: String,
+    name: String,
+    input: String,
+    stop: bool,
+}
+
 /// 内容块状态
 #[derive(Debug, Clone)]
 struct BlockState {
@@ -235,6 +248,8 @@ pub struct SseStateManager {
     next_block_index: i32,
     /// 当前 stop_reason
     stop_reason: Option<String>,
+    /// 命中的 stop_sequence
+    stop_sequence: Option<String>,
     /// 是否有工具调用
     has_tool_use: bool,
 }
@@ -254,6 +269,7 @@ impl SseStateManager {
             message_ended: false,
             next_block_index: 0,
             stop_reason: None,
+            stop_sequence: None,
             has_tool_use: false,
         }
     }
@@ -282,6 +298,12 @@ impl SseStateManager {
         self.stop_reason = Some(reason.into());
     }
 
+    /// 记录命中的 stop_sequence，并以 stop_reason = stop_sequence 结束
+    pub fn set_stop_sequence(&mut self, sequence: impl Into<String>) {
+        self.stop_reason = Some("stop_sequence".to_string());
+        self.stop_sequence = Some(sequence.into());
+    }
+
     /// 获取最终的 stop_reason
     pub fn get_stop_reason(&self) -> String {
         if let Some(ref reason) = self.stop_reason {
@@ -347,6 +369,13 @@ impl SseStateManager {
         events
     }
 
+    /// 块是否已开始且尚未结束
+    pub fn is_block_open(&self, index: i32) -> bool {
+        self.active_blocks
+            .get(&index)
+            .is_some_and(|block| block.started && !block.stopped)
+    }
+
     /// 处理 content_block_delta 事件
     pub fn handle_content_block_delta(
         &mut self,
@@ -397,6 +426,7 @@ impl SseStateManager {
         &mut self,
         input_tokens: i32,
         output_tokens: i32,
+        prompt_cache: PromptCacheUsage,
     ) -> Vec<SseEvent> {
         let mut events = Vec::new();
 
@@ -430,12 +460,9 @@ impl SseStateManager {
                     "type": "message_delta",
                     "delta": {
                         "stop_reason": self.get_stop_reason(),
-                        "stop_sequence": null
+                        "stop_sequence": self.stop_sequence
                     },
-                    "usage": {
-                        "input_tokens": input_tokens,
-                        "output_tokens": output_tokens
-                    }
+                    "usage": prompt_cache.usage_json(input_tokens, output_tokens)
                 }),
             ));
         }
@@ -453,9 +480,157 @@ impl SseStateManager {
     }
 }
 
-/// 上下文窗口大小（200k tokens）
+/// 查找文本中最早出现的 stop_sequence，返回（字节位置，命中的序列）
+///
+/// 多个序列在同一位置命中时取最长者
+pub(crate) fn find_stop_sequence<'a>(
+    text: &str,
+    sequences: &'a [String],
+) -> Option<(usize, &'a str)> {
+    sequences
+        .iter()
+        .filter(|seq| !seq.is_empty())
+        .filter_map(|seq| text.find(seq.as_str()).map(|pos| (pos, seq.as_str())))
+        .min_by(|a, b| a.0.cmp(&b.0).then(b.1.len().cmp(&a.1.len())))
+}
+
+/// stop_sequences 匹配器
+///
+/// 上游不支持 stop_sequences，由代理侧扫描输出文本。序列可能跨越多个
+/// assistantResponse，因此暂存可能是某个序列前缀的尾部文本，待后续内容到达后再判断
+#[derive(Debug)]
+struct StopSequenceMatcher {
+    sequences: Vec<String>,
+    /// 暂存的尾部文本（可能是某个序列的前缀）
+    pending: String,
+    /// 是否已命中（命中后丢弃所有后续文本）
+    matched: bool,
+}
+
+impl StopSequenceMatcher {
+    fn new(sequences: Vec<String>) -> Self {
+        Self {
+            sequences: sequences.into_iter().filter(|s| !s.is_empty()).collect(),
+            pending: String::new(),
+            matched: false,
+        }
+    }
+
+    /// 写入新文本，返回可以安全输出的文本及命中的序列
+    ///
+    /// 命中后返回的文本截止到序列之前（不含序列本身）
+    fn feed(&mut self, text: &str) -> (String, Option<String>) {
+        if self.matched {
+            return (String::new(), None);
+        }
+        self.pending.push_str(text);
+
+        if let Some((pos, seq)) = find_stop_sequence(&self.pending, &self.sequences) {
+            let seq = seq.to_string();
+            self.matched = true;
+            let mut emit = std::mem::take(&mut self.pending);
+            emit.truncate(pos);
+            return (emit, Some(seq));
+        }
+
+        let held = self.partial_match_len();
+        let emit_len = self.pending.len() - held;
+        let emit = self.pending[..emit_len].to_string();
+        self.pending.drain(..emit_len);
+        (emit, None)
+    }
+
+    /// 取出暂存的尾部文本（流结束或被工具调用打断时）
+    fn flush(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// 暂存区末尾可能是某个序列前缀的最长长度（字节）
+    fn partial_match_len(&self) -> usize {
+        let longest = self.sequences.iter().map(String::len).max().unwrap_or(0);
+        let max_len = longest.saturating_sub(1).min(self.pending.len());
+        (1..=max_len)
+            .rev()
+            .find(|&len| {
+                let start = self.pending.len() - len;
+                self.pending.is_char_boundary(start)
+                    && self
+                        .sequences
+                        .iter()
+                        .any(|seq| seq.starts_with(&self.pending[start..]))
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// 默认上下文窗口大小（200k tokens），未指定模型上下文窗口时使用
 const CONTEXT_WINDOW_SIZE: i32 = 200_000;
 
+/// 重叠检测保留的已输出内容窗口（字节）
+const OVERLAP_WINDOW_BYTES: usize = 2048;
+
+/// 重复片段检测器
+///
+/// Kiro 内部瞬时重试后偶尔会重发与已输出内容重叠的 assistantResponse，
+/// 表现为客户端看到重复的句子。检测器保留最近输出的内容窗口，
+/// 若新内容的前缀与窗口后缀重叠且长度不小于 `min_overlap`，则丢弃重叠部分
+#[derive(Debug)]
+struct OverlapDetector {
+    /// 最近输出的内容
+    window: String,
+    /// 判定为重复的最小重叠字节数（避免误伤正常的短重复）
+    min_overlap: usize,
+}
+
+impl OverlapDetector {
+    fn new(min_overlap: usize) -> Self {
+        Self {
+            window: String::new(),
+            min_overlap,
+        }
+    }
+
+    /// 过滤新内容，返回去除重叠前缀后的部分
+    fn filter<'a>(&mut self, content: &'a str) -> &'a str {
+        let overlap = self.find_overlap(content);
+        if overlap > 0 {
+            crate::common::metrics::STREAM_DUPLICATE_SPANS.inc_by(1);
+            crate::common::metrics::STREAM_DUPLICATE_BYTES.inc_by(overlap as u64);
+            tracing::warn!("检测到上游重发的重叠内容，已抑制 {} 字节", overlap);
+        }
+        let remaining = &content[overlap..];
+        self.record(remaining);
+        remaining
+    }
+
+    /// 查找窗口后缀与内容前缀的最长重叠（字节数），不足 `min_overlap` 时返回 0
+    fn find_overlap(&self, content: &str) -> usize {
+        if content.len() < self.min_overlap {
+            return 0;
+        }
+        // 从窗口最靠前的位置开始尝试，首个匹配即为最长重叠
+        for (start, _) in self.window.char_indices() {
+            let suffix = &self.window[start..];
+            if suffix.len() < self.min_overlap {
+                break;
+            }
+            if content.starts_with(suffix) {
+                return suffix.len();
+            }
+        }
+        0
+    }
+
+    /// 记录已输出内容，仅保留最近的窗口
+    fn record(&mut self, content: &str) {
+        self.window.push_str(content);
+        if self.window.len() > OVERLAP_WINDOW_BYTES {
+            let cut = find_char_boundary(&self.window, self.window.len() - OVERLAP_WINDOW_BYTES);
+            self.window.drain(..cut);
+        }
+    }
+}
+
 /// 流处理上下文
 pub struct StreamContext {
     /// SSE 状态管理器
@@ -468,10 +643,16 @@ pub struct StreamContext {
     pub input_tokens: i32,
     /// 从 contextUsageEvent 计算的实际输入 tokens
     pub context_input_tokens: Option<i32>,
+    /// 模型上下文窗口大小（用于将 contextUsageEvent 的百分比换算为 tokens）
+    pub context_window: i32,
     /// 输出 tokens 累计
     pub output_tokens: i32,
     /// 工具块索引映射 (tool_id -> block_index)
     pub tool_block_indices: HashMap<String, i32>,
+    /// 正在输出的工具块 ID（同一时间只输出一个工具块）
+    active_tool: Option<String>,
+    /// 正在输出其他工具块时收到的工具调用，按首次出现的顺序排队
+    pending_tools: VecDeque<PendingToolUse>,
     /// thinking 是否启用
     pub thinking_enabled: bool,
     /// thinking 内容缓冲区
@@ -482,8 +663,22 @@ pub struct StreamContext {
     pub thinking_extracted: bool,
     /// thinking 块索引
     pub thinking_block_index: Option<i32>,
+    /// 已输出的 thinking 内容（关闭 thinking 块时据此签发签名）
+    thinking_content: String,
     /// 文本块索引（thinking 启用时动态分配）
     pub text_block_index: Option<i32>,
+    /// 客户端请求的输出 token 上限（代理侧强制执行）
+    pub max_tokens: Option<i32>,
+    /// 输出是否已提前结束（达到 max_tokens 或命中 stop_sequences），结束后不再输出内容并结束流
+    pub output_finished: bool,
+    /// 重复片段检测器（未启用时为 None）
+    overlap_detector: Option<OverlapDetector>,
+    /// 响应结束时追加的页脚文本
+    footer: Option<String>,
+    /// stop_sequences 匹配器（未设置时为 None）
+    stop_matcher: Option<StopSequenceMatcher>,
+    /// 本地模拟的 prompt caching 用量
+    prompt_cache: PromptCacheUsage,
 }
 
 impl StreamContext {
@@ -499,17 +694,80 @@ impl StreamContext {
             message_id: format!("msg_{}", Uuid::new_v4().to_string().replace('-', "")),
             input_tokens,
             context_input_tokens: None,
+            context_window: CONTEXT_WINDOW_SIZE,
             output_tokens: 0,
             tool_block_indices: HashMap::new(),
+            active_tool: None,
+            pending_tools: VecDeque::new(),
             thinking_enabled,
             thinking_buffer: String::new(),
             in_thinking_block: false,
             thinking_extracted: false,
             thinking_block_index: None,
+            thinking_content: String::new(),
             text_block_index: None,
+            max_tokens: None,
+            output_finished: false,
+            overlap_detector: None,
+            footer: None,
+            stop_matcher: None,
+            prompt_cache: PromptCacheUsage::default(),
         }
     }
 
+    /// 使用指定的消息 ID（如客户端通过 `x-kiro-request-id` 指定的请求 ID）
+    pub fn with_message_id(mut self, message_id: impl Into<String>) -> Self {
+        self.message_id = message_id.into();
+        self
+    }
+
+    /// 设置页脚：响应正常结束（非 tool_use）时作为最后一个 text_delta 发送
+    pub fn with_footer(mut self, footer: Option<&str>) -> Self {
+        self.footer = footer.map(str::to_string);
+        self
+    }
+
+    /// 设置 prompt caching 用量（从总输入 tokens 中拆分出缓存写入与读取部分）
+    pub fn with_prompt_cache(mut self, usage: PromptCacheUsage) -> Self {
+        self.prompt_cache = usage;
+        self
+    }
+
+    /// 启用重复片段抑制，`min_overlap` 为判定重复的最小重叠字节数（0 表示不启用）
+    pub fn with_overlap_dedup(mut self, min_overlap: usize) -> Self {
+        self.overlap_detector = (min_overlap > 0).then(|| OverlapDetector::new(min_overlap));
+        self
+    }
+
+    /// 设置输出 token 上限
+    ///
+    /// 上游并不总是遵守请求的 max_tokens，达到上限后代理侧截断输出，
+    /// 并以 stop_reason = max_tokens 结束流
+    pub fn with_max_tokens(mut self, max_tokens: i32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// 设置模型上下文窗口大小
+    pub fn with_context_window(mut self, context_window: i32) -> Self {
+        self.context_window = context_window;
+        self
+    }
+
+    /// 最终的输入 tokens：优先使用从 contextUsageEvent 计算的值，没有则使用估算值
+    pub fn final_input_tokens(&self) -> i32 {
+        self.context_input_tokens.unwrap_or(self.input_tokens)
+    }
+
+    /// 设置 stop_sequences
+    ///
+    /// 输出文本命中任一序列时在序列之前截断（不输出序列本身），
+    /// 并以 stop_reason = stop_sequence 结束流
+    pub fn with_stop_sequences(mut self, sequences: Vec<String>) -> Self {
+        self.stop_matcher = (!sequences.is_empty()).then(|| StopSequenceMatcher::new(sequences));
+        self
+    }
+
     /// 生成 message_start 事件
     pub fn create_message_start_event(&self) -> serde_json::Value {
         json!({
@@ -522,10 +780,7 @@ impl StreamContext {
                 "model": self.model,
                 "stop_reason": null,
                 "stop_sequence": null,
-                "usage": {
-                    "input_tokens": self.input_tokens,
-                    "output_tokens": 1
-                }
+                "usage": self.prompt_cache.usage_json(self.input_tokens, 1)
             }
         })
     }
@@ -572,21 +827,19 @@ impl StreamContext {
     /// 处理 Kiro 事件并转换为 Anthropic SSE 事件
     pub fn process_kiro_event(&mut self, event: &Event) -> Vec<SseEvent> {
         match event {
+            // 输出已提前结束，丢弃后续内容
+            Event::AssistantResponse(_) | Event::ToolUse(_) if self.output_finished => Vec::new(),
             Event::AssistantResponse(resp) => self.process_assistant_response(&resp.content),
             Event::ToolUse(tool_use) => self.process_tool_use(tool_use),
             Event::ContextUsage(context_usage) => {
-                // 从上下文使用百分比计算实际的 input_tokens
-                // 公式: percentage * 200000 / 100 = percentage * 2000
-                // 注意：context_usage_percentage 是上游返回的累积上下文使用百分比
-                let actual_input_tokens = (context_usage.context_usage_percentage
-                    * (CONTEXT_WINDOW_SIZE as f64)
-                    / 100.0) as i32;
+                // 从上下文使用百分比计算实际的 input_tokens（message_delta 中使用）
+                let actual_input_tokens = context_usage.input_tokens(self.context_window);
                 self.context_input_tokens = Some(actual_input_tokens);
                 tracing::info!(
                     "📊 收到 contextUsageEvent - 百分比: {:.2}%, 计算得出 input_tokens: {} (累积值), context_window: {}",
                     context_usage.context_usage_percentage,
                     actual_input_tokens,
-                    CONTEXT_WINDOW_SIZE
+                    self.context_window
                 );
                 Vec::new()
             }
@@ -618,6 +871,34 @@ impl StreamContext {
             return Vec::new();
         }
 
+        // 抑制上游重发的重叠内容
+        let content = match self.overlap_detector.as_mut() {
+            Some(detector) => detector.filter(content),
+            None => content,
+        };
+        if content.is_empty() {
+            return Vec::new();
+        }
+
+        // 代理侧强制执行 max_tokens：超出部分截断
+        let content = match self.max_tokens {
+            Some(max_tokens) => {
+                let remaining = (max_tokens - self.output_tokens) as i64;
+                let allowed =
+                    token::truncate_to_budget(content, remaining, |s| estimate_tokens(s) as i64);
+                if allowed.len() < content.len() {
+                    self.output_finished = true;
+                    self.state_manager.set_stop_reason("max_tokens");
+                    tracing::info!("输出达到 max_tokens 上限（{}），截断并结束流", max_tokens);
+                }
+                allowed
+            }
+            None => content,
+        };
+        if content.is_empty() {
+            return Vec::new();
+        }
+
         // 估算 tokens
         self.output_tokens += estimate_tokens(content);
 
@@ -628,7 +909,7 @@ impl StreamContext {
 
         // 非 thinking 模式同样复用统一的 text_delta 发送逻辑，
         // 以便在 tool_use 自动关闭文本块后能够自愈重建新的文本块，避免“吞字”。
-        self.create_text_delta_events(content)
+        self.emit_text_events(content)
     }
 
     /// 处理包含thinking块的内容
@@ -645,7 +926,7 @@ impl StreamContext {
                     // 发送 <thinking> 之前的内容作为 text_delta
                     let before_thinking = self.thinking_buffer[..start_pos].to_string();
                     if !before_thinking.is_empty() {
-                        events.extend(self.create_text_delta_events(&before_thinking));
+                        events.extend(self.emit_text_events(&before_thinking));
                     }
 
                     // 进入 thinking 块
@@ -664,7 +945,8 @@ impl StreamContext {
                             "index": thinking_index,
                             "content_block": {
                                 "type": "thinking",
-                                "thinking": ""
+                                "thinking": "",
+                                "signature": ""
                             }
                         }),
                     );
@@ -680,7 +962,7 @@ impl StreamContext {
                     if safe_len > 0 {
                         let safe_content = self.thinking_buffer[..safe_len].to_string();
                         if !safe_content.is_empty() {
-                            events.extend(self.create_text_delta_events(&safe_content));
+                            events.extend(self.emit_text_events(&safe_content));
                         }
                         self.thinking_buffer = self.thinking_buffer[safe_len..].to_string();
                     }
@@ -703,20 +985,11 @@ impl StreamContext {
                     self.in_thinking_block = false;
                     self.thinking_extracted = true;
 
-                    // 发送空的 thinking_delta 事件，然后发送 content_block_stop 事件
-                    if let Some(thinking_index) = self.thinking_block_index {
-                        // 先发送空的 thinking_delta
-                        events.push(self.create_thinking_delta_event(thinking_index, ""));
-                        // 再发送 content_block_stop
-                        if let Some(stop_event) =
-                            self.state_manager.handle_content_block_stop(thinking_index)
-                        {
-                            events.push(stop_event);
-                        }
-                    }
+                    events.extend(self.close_thinking_block());
 
-                    self.thinking_buffer =
-                        self.thinking_buffer[end_pos + "</thinking>".len()..].to_string();
+                    // 结束标签后的 `\n\n` 是 thinking 与正文的分隔符，不输出到文本块
+                    let after = &self.thinking_buffer[end_pos + "</thinking>".len()..];
+                    self.thinking_buffer = after.strip_prefix("\n\n").unwrap_or(after).to_string();
                 } else {
                     // 没有找到结束标签，发送当前缓冲区内容作为 thinking_delta
                     // 保留可能是部分标签的内容
@@ -743,7 +1016,7 @@ impl StreamContext {
                 if !self.thinking_buffer.is_empty() {
                     let remaining = self.thinking_buffer.clone();
                     self.thinking_buffer.clear();
-                    events.extend(self.create_text_delta_events(&remaining));
+                    events.extend(self.emit_text_events(&remaining));
                 }
                 break;
             }
@@ -752,6 +1025,38 @@ impl StreamContext {
         events
     }
 
+    /// 输出模型生成的文本：经过 stop_sequences 扫描后再创建 text_delta 事件
+    fn emit_text_events(&mut self, text: &str) -> Vec<SseEvent> {
+        let Some(matcher) = self.stop_matcher.as_mut() else {
+            return self.create_text_delta_events(text);
+        };
+
+        let (emit, matched) = matcher.feed(text);
+        let mut events = Vec::new();
+        if !emit.is_empty() {
+            events.extend(self.create_text_delta_events(&emit));
+        }
+        if let Some(sequence) = matched {
+            tracing::info!("输出命中 stop_sequence {:?}，截断并结束流", sequence);
+            self.output_finished = true;
+            self.state_manager.set_stop_sequence(sequence);
+        }
+        events
+    }
+
+    /// 输出 stop_sequences 匹配器暂存的尾部文本
+    fn flush_stop_matcher(&mut self) -> Vec<SseEvent> {
+        let pending = self
+            .stop_matcher
+            .as_mut()
+            .map(StopSequenceMatcher::flush)
+            .unwrap_or_default();
+        if pending.is_empty() {
+            return Vec::new();
+        }
+        self.create_text_delta_events(&pending)
+    }
+
     /// 创建 text_delta 事件
     ///
     /// 如果文本块尚未创建，会先创建文本块。
@@ -812,8 +1117,35 @@ impl StreamContext {
         events
     }
 
-    /// 创建 thinking_delta 事件
-    fn create_thinking_delta_event(&self, index: i32, thinking: &str) -> SseEvent {
+    /// 关闭 thinking 块：先发送 signature_delta，再发送 content_block_stop
+    ///
+    /// Kiro 不返回 thinking 签名，按已输出的 thinking 内容签发签名，客户端在后续请求中
+    /// 回传后由转换器校验（见 `thinking_signature`）。块已关闭时不产生事件
+    fn close_thinking_block(&mut self) -> Vec<SseEvent> {
+        let Some(index) = self
+            .thinking_block_index
+            .filter(|&index| self.state_manager.is_block_open(index))
+        else {
+            return Vec::new();
+        };
+        let mut events = vec![SseEvent::new(
+            "content_block_delta",
+            json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {
+                    "type": "signature_delta",
+                    "signature": thinking_signature::sign(&self.thinking_content)
+                }
+            }),
+        )];
+        events.extend(self.state_manager.handle_content_block_stop(index));
+        events
+    }
+
+    /// 创建 thinking_delta 事件（同时记录已输出的 thinking 内容）
+    fn create_thinking_delta_event(&mut self, index: i32, thinking: &str) -> SseEvent {
+        self.thinking_content.push_str(thinking);
         SseEvent::new(
             "content_block_delta",
             json!({
@@ -855,23 +1187,14 @@ impl StreamContext {
                 self.in_thinking_block = false;
                 self.thinking_extracted = true;
 
-                if let Some(thinking_index) = self.thinking_block_index {
-                    // 先发送空的 thinking_delta
-                    events.push(self.create_thinking_delta_event(thinking_index, ""));
-                    // 再发送 content_block_stop
-                    if let Some(stop_event) =
-                        self.state_manager.handle_content_block_stop(thinking_index)
-                    {
-                        events.push(stop_event);
-                    }
-                }
+                events.extend(self.close_thinking_block());
 
                 // 把结束标签后的内容当作普通文本（通常为空或空白）
                 let after_pos = end_pos + "</thinking>".len();
                 let remaining = self.thinking_buffer[after_pos..].to_string();
                 self.thinking_buffer.clear();
                 if !remaining.is_empty() {
-                    events.extend(self.create_text_delta_events(&remaining));
+                    events.extend(self.emit_text_events(&remaining));
                 }
             }
         }
@@ -885,16 +1208,83 @@ impl StreamContext {
             && !self.thinking_buffer.is_empty()
         {
             let buffered = std::mem::take(&mut self.thinking_buffer);
-            events.extend(self.create_text_delta_events(&buffered));
+            events.extend(self.emit_text_events(&buffered));
+        }
+
+        // 暂存的尾部文本不可能再与后续内容组成 stop_sequence，在工具调用开始前输出
+        events.extend(self.flush_stop_matcher());
+
+        // Anthropic SSE 要求内容块依次输出：其他工具块尚未结束时，先缓冲本工具的增量，
+        // 待当前工具块结束后再按顺序输出
+        if let Some(active) = &self.active_tool
+            && *active != tool_use.tool_use_id
+        {
+            self.buffer_tool_use(tool_use);
+            return events;
+        }
+
+        events.extend(self.emit_tool_use(
+            &tool_use.tool_use_id,
+            &tool_use.name,
+            &tool_use.input,
+            tool_use.stop,
+        ));
+        events.extend(self.drain_pending_tools());
+        events
+    }
+
+    /// 缓冲其他工具块输出期间收到的工具调用增量
+    fn buffer_tool_use(&mut self, tool_use: &crate::kiro::model::events::ToolUseEvent) {
+        match self
+            .pending_tools
+            .iter_mut()
+            .find(|pending| pending.tool_use_id == tool_use.tool_use_id)
+        {
+            Some(pending) => {
+                pending.input.push_str(&tool_use.input);
+                pending.stop |= tool_use.stop;
+            }
+            None => self.pending_tools.push_back(PendingToolUse {
+                tool_use_id: tool_use.tool_use_id.clone(),
+                name: tool_use.name.clone(),
+                input: tool_use.input.clone(),
+                stop: tool_use.stop,
+            }),
         }
+    }
+
+    /// 当前没有正在输出的工具块时，依次输出缓冲的工具调用
+    fn drain_pending_tools(&mut self) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        while self.active_tool.is_none()
+            && let Some(pending) = self.pending_tools.pop_front()
+        {
+            events.extend(self.emit_tool_use(
+                &pending.tool_use_id,
+                &pending.name,
+                &pending.input,
+                pending.stop,
+            ));
+        }
+        events
+    }
+
+    /// 输出工具调用增量：content_block_start（首次）+ input_json_delta + content_block_stop（stop 时）
+    fn emit_tool_use(
+        &mut self,
+        tool_use_id: &str,
+        name: &str,
+        input: &str,
+        stop: bool,
+    ) -> Vec<SseEvent> {
+        let mut events = Vec::new();
 
         // 获取或分配块索引
-        let block_index = if let Some(&idx) = self.tool_block_indices.get(&tool_use.tool_use_id) {
+        let block_index = if let Some(&idx) = self.tool_block_indices.get(tool_use_id) {
             idx
         } else {
             let idx = self.state_manager.next_block_index();
-            self.tool_block_indices
-                .insert(tool_use.tool_use_id.clone(), idx);
+            self.tool_block_indices.insert(tool_use_id.to_string(), idx);
             idx
         };
 
@@ -907,8 +1297,8 @@ impl StreamContext {
                 "index": block_index,
                 "content_block": {
                     "type": "tool_use",
-                    "id": tool_use.tool_use_id,
-                    "name": tool_use.name,
+                    "id": tool_use_id,
+                    "name": name,
                     "input": {}
                 }
             }),
@@ -916,8 +1306,8 @@ impl StreamContext {
         events.extend(start_events);
 
         // 发送参数增量 (ToolUseEvent.input 是 String 类型)
-        if !tool_use.input.is_empty() {
-            self.output_tokens += (tool_use.input.len() as i32 + 3) / 4; // 估算 token
+        if !input.is_empty() {
+            self.output_tokens += (input.len() as i32 + 3) / 4; // 估算 token
 
             if let Some(delta_event) = self.state_manager.handle_content_block_delta(
                 block_index,
@@ -926,7 +1316,7 @@ impl StreamContext {
                     "index": block_index,
                     "delta": {
                         "type": "input_json_delta",
-                        "partial_json": tool_use.input
+                        "partial_json": input
                     }
                 }),
             ) {
@@ -935,11 +1325,15 @@ impl StreamContext {
         }
 
         // 如果是完整的工具调用（stop=true），发送 content_block_stop
-        if tool_use.stop {
+        if stop {
             if let Some(stop_event) = self.state_manager.handle_content_block_stop(block_index) {
                 events.push(stop_event);
             }
         }
+        self.active_tool = self
+            .state_manager
+            .is_block_open(block_index)
+            .then(|| tool_use_id.to_string());
 
         events
     }
@@ -964,15 +1358,7 @@ impl StreamContext {
                         }
                     }
 
-                    // 关闭 thinking 块：先发送空的 thinking_delta，再发送 content_block_stop
-                    if let Some(thinking_index) = self.thinking_block_index {
-                        events.push(self.create_thinking_delta_event(thinking_index, ""));
-                        if let Some(stop_event) =
-                            self.state_manager.handle_content_block_stop(thinking_index)
-                        {
-                            events.push(stop_event);
-                        }
-                    }
+                    events.extend(self.close_thinking_block());
 
                     // 把结束标签后的内容当作普通文本（通常为空或空白）
                     let after_pos = end_pos + "</thinking>".len();
@@ -981,43 +1367,65 @@ impl StreamContext {
                     self.in_thinking_block = false;
                     self.thinking_extracted = true;
                     if !remaining.is_empty() {
-                        events.extend(self.create_text_delta_events(&remaining));
+                        events.extend(self.emit_text_events(&remaining));
                     }
                 } else {
                     // 如果还在 thinking 块内，发送剩余内容作为 thinking_delta
                     if let Some(thinking_index) = self.thinking_block_index {
+                        let thinking_content = std::mem::take(&mut self.thinking_buffer);
                         events.push(
-                            self.create_thinking_delta_event(thinking_index, &self.thinking_buffer),
+                            self.create_thinking_delta_event(thinking_index, &thinking_content),
                         );
                     }
-                    // 关闭 thinking 块：先发送空的 thinking_delta，再发送 content_block_stop
-                    if let Some(thinking_index) = self.thinking_block_index {
-                        // 先发送空的 thinking_delta
-                        events.push(self.create_thinking_delta_event(thinking_index, ""));
-                        // 再发送 content_block_stop
-                        if let Some(stop_event) =
-                            self.state_manager.handle_content_block_stop(thinking_index)
-                        {
-                            events.push(stop_event);
-                        }
-                    }
+                    events.extend(self.close_thinking_block());
                 }
             } else {
                 // 否则发送剩余内容作为 text_delta
                 let buffer_content = self.thinking_buffer.clone();
-                events.extend(self.create_text_delta_events(&buffer_content));
+                events.extend(self.emit_text_events(&buffer_content));
             }
             self.thinking_buffer.clear();
         }
+        // 流在 thinking 块内结束且缓冲区为空时，同样补发签名并关闭
+        events.extend(self.close_thinking_block());
+
+        events.extend(self.flush_stop_matcher());
+
+        // 输出仍在缓冲的工具调用：流已结束，先关闭正在输出的工具块，缓冲的工具块输出后立即结束
+        if !self.pending_tools.is_empty() && !self.output_finished {
+            if let Some(index) = self
+                .active_tool
+                .take()
+                .and_then(|id| self.tool_block_indices.get(&id).copied())
+            {
+                events.extend(self.state_manager.handle_content_block_stop(index));
+            }
+            for pending in std::mem::take(&mut self.pending_tools) {
+                events.extend(self.emit_tool_use(
+                    &pending.tool_use_id,
+                    &pending.name,
+                    &pending.input,
+                    true,
+                ));
+            }
+        }
+
+        // 追加页脚（tool_use 结束的响应不是最终回复，不追加）
+        if let Some(footer) = self.footer.take()
+            && self.state_manager.get_stop_reason() != "tool_use"
+        {
+            events.extend(self.create_text_delta_events(&footer));
+        }
 
         // 使用从 contextUsageEvent 计算的 input_tokens，如果没有则使用估算值
-        let final_input_tokens = self.context_input_tokens.unwrap_or(self.input_tokens);
+        let final_input_tokens = self.final_input_tokens();
 
         // 生成最终事件
-        events.extend(
-            self.state_manager
-                .generate_final_events(final_input_tokens, self.output_tokens),
-        );
+        events.extend(self.state_manager.generate_final_events(
+            final_input_tokens,
+            self.output_tokens,
+            self.prompt_cache,
+        ));
         events
     }
 }
@@ -1070,6 +1478,60 @@ mod tests {
         assert!(event.is_none());
     }
 
+    #[test]
+    fn test_message_start_includes_cache_usage() {
+        let ctx = StreamContext::new_with_thinking("test-model", 42, false);
+        let event = ctx.create_message_start_event();
+        let usage = &event["message"]["usage"];
+        assert_eq!(usage["input_tokens"], 42);
+        assert_eq!(usage["cache_creation_input_tokens"], 0);
+        assert_eq!(usage["cache_read_input_tokens"], 0);
+
+        let ctx = StreamContext::new_with_thinking("test-model", 42, false).with_prompt_cache(
+            PromptCacheUsage {
+                cache_creation_input_tokens: 10,
+                cache_read_input_tokens: 30,
+            },
+        );
+        let event = ctx.create_message_start_event();
+        let usage = &event["message"]["usage"];
+        assert_eq!(usage["input_tokens"], 2);
+        assert_eq!(usage["cache_creation_input_tokens"], 10);
+        assert_eq!(usage["cache_read_input_tokens"], 30);
+    }
+
+    #[test]
+    fn test_message_delta_uses_context_usage_input_tokens() {
+        let usage = |ctx: &mut StreamContext| {
+            let final_events = ctx.generate_final_events();
+            let delta = final_events
+                .iter()
+                .find(|e| e.event == "message_delta")
+                .unwrap();
+            delta.data["usage"]["input_tokens"].clone()
+        };
+        let context_usage = Event::ContextUsage(crate::kiro::model::events::ContextUsageEvent {
+            context_usage_percentage: 12.5,
+        });
+
+        // 未收到 contextUsageEvent 时使用估算值
+        let mut ctx = StreamContext::new_with_thinking("test-model", 42, false);
+        ctx.generate_initial_events();
+        assert_eq!(usage(&mut ctx), 42);
+
+        let mut ctx = StreamContext::new_with_thinking("test-model", 42, false);
+        ctx.generate_initial_events();
+        ctx.process_kiro_event(&context_usage);
+        assert_eq!(usage(&mut ctx), 25_000);
+
+        // 按模型上下文窗口换算
+        let mut ctx = StreamContext::new_with_thinking("test-model", 42, false)
+            .with_context_window(1_000_000);
+        ctx.generate_initial_events();
+        ctx.process_kiro_event(&context_usage);
+        assert_eq!(usage(&mut ctx), 125_000);
+    }
+
     #[test]
     fn test_sse_state_manager_block_lifecycle() {
         let mut manager = SseStateManager::new();
@@ -1091,6 +1553,78 @@ mod tests {
         assert!(event.is_none());
     }
 
+    /// 将事件序列概括为 `start:<index>` / `delta:<index>:<partial_json>` / `stop:<index>`
+    fn block_trace(events: &[SseEvent]) -> Vec<String> {
+        events
+            .iter()
+            .filter_map(|e| {
+                let index = &e.data["index"];
+                match e.event.as_str() {
+                    "content_block_start" => Some(format!("start:{}", index)),
+                    "content_block_delta" => Some(format!(
+                        "delta:{}:{}",
+                        index,
+                        e.data["delta"]["partial_json"].as_str().unwrap_or_default()
+                    )),
+                    "content_block_stop" => Some(format!("stop:{}", index)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_interleaved_tool_use_blocks_are_sequential() {
+        let tool_use = |id: &str, input: &str, stop: bool| {
+            Event::ToolUse(crate::kiro::model::events::ToolUseEvent {
+                name: format!("tool_{}", id),
+                tool_use_id: id.to_string(),
+                input: input.to_string(),
+                stop,
+            })
+        };
+        // 初始事件包含索引 0 的文本块，工具调用开始时关闭
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false);
+        let mut events = ctx.generate_initial_events();
+        for event in [
+            tool_use("a", "{\"x\":", false),
+            tool_use("b", "{\"y\":", false),
+            tool_use("a", "1}", false),
+            tool_use("b", "2}", true),
+            tool_use("c", "{}", false),
+            tool_use("a", "", true),
+        ] {
+            events.extend(ctx.process_kiro_event(&event));
+        }
+        events.extend(ctx.generate_final_events());
+
+        assert_eq!(
+            block_trace(&events),
+            [
+                "start:0",
+                "stop:0",
+                "start:1",
+                "delta:1:{\"x\":",
+                "delta:1:1}",
+                "stop:1",
+                "start:2",
+                "delta:2:{\"y\":2}",
+                "stop:2",
+                "start:3",
+                "delta:3:{}",
+                "stop:3",
+            ]
+        );
+        let start = events
+            .iter()
+            .find(|e| e.event == "content_block_start" && e.data["index"] == 2)
+            .unwrap();
+        assert_eq!(start.data["content_block"]["id"], "b");
+        assert_eq!(start.data["content_block"]["name"], "tool_b");
+        let delta = events.iter().find(|e| e.event == "message_delta").unwrap();
+        assert_eq!(delta.data["delta"]["stop_reason"], "tool_use");
+    }
+
     #[test]
     fn test_text_delta_after_tool_use_restarts_text_block() {
         let mut ctx = StreamContext::new_with_thinking("test-model", 1, false);
@@ -1236,6 +1770,173 @@ mod tests {
         assert!(estimate_tokens("Hello 你好") > 0);
     }
 
+    #[test]
+    fn test_max_tokens_budget_truncates_and_stops() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false).with_max_tokens(3);
+        ctx.generate_initial_events();
+
+        // 3 tokens ≈ 12 个英文字符
+        let events = ctx.process_assistant_response("abcdefghijklmnopqrstuvwxyz");
+        let text: String = events
+            .iter()
+            .filter_map(|e| e.data["delta"]["text"].as_str())
+            .collect();
+        assert_eq!(text, "abcdefghijkl");
+        assert!(ctx.output_finished);
+        assert_eq!(ctx.output_tokens, 3);
+
+        // 达到上限后不再输出内容
+        let tool_use = Event::ToolUse(crate::kiro::model::events::ToolUseEvent {
+            name: "test_tool".to_string(),
+            tool_use_id: "tool_1".to_string(),
+            input: "{}".to_string(),
+            stop: true,
+        });
+        assert!(ctx.process_kiro_event(&tool_use).is_empty());
+
+        let final_events = ctx.generate_final_events();
+        let delta = final_events
+            .iter()
+            .find(|e| e.event == "message_delta")
+            .unwrap();
+        assert_eq!(delta.data["delta"]["stop_reason"], "max_tokens");
+    }
+
+    #[test]
+    fn test_stop_sequence_across_chunks() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false)
+            .with_stop_sequences(vec!["STOP".to_string(), "\n\nHuman:".to_string()]);
+        ctx.generate_initial_events();
+
+        let text_of = |events: &[SseEvent]| -> String {
+            events
+                .iter()
+                .filter_map(|e| e.data["delta"]["text"].as_str())
+                .collect()
+        };
+
+        // 可能是序列前缀的尾部文本暂不输出
+        assert_eq!(
+            text_of(&ctx.process_assistant_response("Hello ST")),
+            "Hello "
+        );
+        assert_eq!(text_of(&ctx.process_assistant_response("ATE ")), "STATE ");
+        assert!(!ctx.output_finished);
+
+        // 序列跨越两个事件
+        assert_eq!(
+            text_of(&ctx.process_assistant_response("done\n\nHu")),
+            "done"
+        );
+        assert_eq!(text_of(&ctx.process_assistant_response("man: next")), "");
+        assert!(ctx.output_finished);
+
+        assert!(ctx.process_assistant_response("ignored").is_empty());
+
+        let final_events = ctx.generate_final_events();
+        let delta = final_events
+            .iter()
+            .find(|e| e.event == "message_delta")
+            .unwrap();
+        assert_eq!(delta.data["delta"]["stop_reason"], "stop_sequence");
+        assert_eq!(delta.data["delta"]["stop_sequence"], "\n\nHuman:");
+    }
+
+    #[test]
+    fn test_stop_sequence_pending_text_flushed_at_end() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false)
+            .with_stop_sequences(vec!["###".to_string()]);
+        ctx.generate_initial_events();
+
+        let mut events = ctx.process_assistant_response("total: 5 #");
+        events.extend(ctx.generate_final_events());
+        let text: String = events
+            .iter()
+            .filter_map(|e| e.data["delta"]["text"].as_str())
+            .collect();
+        assert_eq!(text, "total: 5 #");
+
+        let delta = events.iter().find(|e| e.event == "message_delta").unwrap();
+        assert_eq!(delta.data["delta"]["stop_reason"], "end_turn");
+        assert!(delta.data["delta"]["stop_sequence"].is_null());
+    }
+
+    #[test]
+    fn test_find_stop_sequence_earliest() {
+        let seqs = vec!["b".to_string(), "ab".to_string(), "c".to_string()];
+        assert_eq!(find_stop_sequence("xxabc", &seqs), Some((2, "ab")));
+        assert_eq!(find_stop_sequence("xyz", &seqs), None);
+    }
+
+    #[test]
+    fn test_overlap_detector_suppresses_resent_span() {
+        let mut detector = OverlapDetector::new(16);
+        assert_eq!(
+            detector.filter("The quick brown fox jumps over "),
+            "The quick brown fox jumps over "
+        );
+        // 重发了已输出的后半句
+        assert_eq!(
+            detector.filter("brown fox jumps over the lazy dog."),
+            "the lazy dog."
+        );
+        // 短于阈值的正常重复不受影响
+        assert_eq!(detector.filter("dog."), "dog.");
+        // 完全重复的片段被整体丢弃
+        assert_eq!(detector.filter("the lazy dog.dog."), "");
+    }
+
+    #[test]
+    fn test_overlap_dedup_in_stream_context() {
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_overlap_dedup(8);
+        ctx.generate_initial_events();
+
+        ctx.process_assistant_response("你好，世界。今天天气很好。");
+        let events = ctx.process_assistant_response("今天天气很好。我们出去走走吧。");
+        let text: String = events
+            .iter()
+            .filter_map(|e| e.data["delta"]["text"].as_str())
+            .collect();
+        assert_eq!(text, "我们出去走走吧。");
+    }
+
+    #[test]
+    fn test_footer_emitted_as_last_text_delta() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, false)
+            .with_footer(Some("\n\n[notice]"));
+        ctx.generate_initial_events();
+        ctx.process_assistant_response("hello");
+
+        let final_events = ctx.generate_final_events();
+        let footer_pos = final_events
+            .iter()
+            .position(|e| e.data["delta"]["text"] == "\n\n[notice]")
+            .expect("footer should be emitted");
+        let delta_pos = final_events
+            .iter()
+            .position(|e| e.event == "message_delta")
+            .unwrap();
+        assert!(footer_pos < delta_pos);
+
+        // tool_use 结束的响应不追加页脚
+        let mut ctx =
+            StreamContext::new_with_thinking("test-model", 1, false).with_footer(Some("[notice]"));
+        ctx.generate_initial_events();
+        ctx.process_tool_use(&crate::kiro::model::events::ToolUseEvent {
+            name: "test_tool".to_string(),
+            tool_use_id: "tool_1".to_string(),
+            input: "{}".to_string(),
+            stop: true,
+        });
+        let final_events = ctx.generate_final_events();
+        assert!(
+            !final_events
+                .iter()
+                .any(|e| e.data["delta"]["text"] == "[notice]")
+        );
+    }
+
     #[test]
     fn test_find_real_thinking_start_tag_basic() {
         // 基本情况：正常的开始标签
@@ -1411,6 +2112,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_thinking_block_streamed_with_signature() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, true);
+        let mut events = ctx.generate_initial_events();
+        for chunk in [
+            "<think",
+            "ing>let me ",
+            "think</thin",
+            "king>\n\nHello",
+            " world",
+        ] {
+            events.extend(ctx.process_assistant_response(chunk));
+        }
+        events.extend(ctx.generate_final_events());
+
+        let thinking_index = ctx.thinking_block_index.unwrap();
+        let start = events
+            .iter()
+            .find(|e| e.event == "content_block_start" && e.data["index"] == thinking_index)
+            .unwrap();
+        assert_eq!(start.data["content_block"]["type"], "thinking");
+
+        let deltas: Vec<&serde_json::Value> = events
+            .iter()
+            .filter(|e| e.event == "content_block_delta")
+            .map(|e| &e.data["delta"])
+            .collect();
+        let collect = |kind: &str, key: &str| -> String {
+            deltas
+                .iter()
+                .filter(|d| d["type"] == kind)
+                .filter_map(|d| d[key].as_str())
+                .collect()
+        };
+        assert_eq!(collect("thinking_delta", "thinking"), "let me think");
+        assert_eq!(collect("text_delta", "text"), "Hello world");
+        let signature = collect("signature_delta", "signature");
+        assert_eq!(signature, thinking_signature::sign("let me think"));
+        assert!(thinking_signature::verify("let me think", &signature));
+
+        // signature_delta 紧挨在 thinking 块的 content_block_stop 之前
+        let stop = events
+            .iter()
+            .position(|e| e.event == "content_block_stop" && e.data["index"] == thinking_index)
+            .unwrap();
+        assert_eq!(events[stop - 1].data["delta"]["type"], "signature_delta");
+    }
+
+    #[test]
+    fn test_thinking_block_closed_when_stream_ends_inside_it() {
+        let mut ctx = StreamContext::new_with_thinking("test-model", 1, true);
+        let mut events = ctx.generate_initial_events();
+        events.extend(ctx.process_assistant_response("<thinking>unfinished"));
+        events.extend(ctx.generate_final_events());
+
+        let thinking_index = ctx.thinking_block_index.unwrap();
+        let signatures = events
+            .iter()
+            .filter(|e| e.data["delta"]["type"] == "signature_delta")
+            .count();
+        assert_eq!(signatures, 1);
+        assert!(
+            events
+                .iter()
+                .any(|e| e.event == "content_block_stop" && e.data["index"] == thinking_index)
+        );
+    }
+
     #[test]
     fn test_final_flush_filters_standalone_thinking_end_tag() {
         let mut ctx = StreamContext::new_with_thinking("test-model", 1, true);
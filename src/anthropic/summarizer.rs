@@ -0,0 +1,442 @@
+//! 上下文摘要
+//!
+//! 历史消息超过 `contextSummary.maxHistoryTokens` 时，代理向 Kiro 发起一次内部请求，
+//! 将较早的对话轮次压缩为摘要，并以一对合成的 user / assistant 消息替换，客户端无感知：
+//! - 系统提示词、预置对话与最近 `keepRecentTurns` 轮对话原样保留
+//! - 摘要按其覆盖的历史前缀（逐轮滚动 SHA-256）保存在存储后端中。客户端每次都会重发完整历史，
+//!   后续请求直接复用已有摘要，只有未摘要的部分再次超出预算时才在已有摘要的基础上继续压缩
+//! - 摘要请求失败时记录日志，回退到已有摘要或按原样转发
+
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::kiro::model::events::Event;
+use crate::kiro::model::requests::conversation::{
+    ConversationState, CurrentMessage, HistoryAssistantMessage, HistoryUserMessage, Message,
+    UserInputMessage,
+};
+use crate::kiro::model::requests::kiro::KiroRequest;
+use crate::kiro::parser::decoder::{DEFAULT_BUFFER_CAPACITY, EventStreamDecoder};
+use crate::kiro::provider::{KiroProvider, take_connection_guard};
+use crate::kiro::token_manager::Routing;
+use crate::model::config::ContextSummaryConfig;
+use crate::storage::Storage;
+use crate::token;
+
+use super::converter::drop_orphan_tool_results;
+use super::handlers::feed_decoder;
+
+/// 摘要使用的存储命名空间
+const NAMESPACE: &str = "context_summary";
+
+/// 摘要有效期（每次生成新摘要时刷新）
+const SUMMARY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 生成摘要的指令
+const SUMMARY_PROMPT: &str = "Summarize the earlier part of the conversation below between a user and an AI assistant, so that the assistant can continue the conversation without seeing it. Preserve the user's goals and requirements, decisions made, important facts, file paths, code identifiers, tool results that still matter, and any unfinished tasks. Write concise bullet points and reply with the summary only.";
+
+/// 摘要消息的开头
+const SUMMARY_HEADER: &str = "[Summary of the earlier conversation]";
+
+/// 摘要之后合成的助手回复
+const SUMMARY_ACK: &str = "Understood. I will continue the conversation from this summary.";
+
+/// 本次请求的摘要方案
+#[derive(Debug, PartialEq, Eq)]
+enum Plan {
+    /// 历史未超出预算，不做处理
+    Skip,
+    /// 复用已有摘要，替换前 `turns` 轮对话
+    Cached { turns: usize, summary: String },
+    /// 在 `previous`（覆盖前 `from` 轮）的基础上，生成覆盖前 `to` 轮的新摘要
+    Summarize {
+        from: usize,
+        to: usize,
+        previous: Option<String>,
+    },
+}
+
+/// 上下文摘要器
+pub struct ContextSummarizer {
+    storage: Arc<dyn Storage>,
+}
+
+impl ContextSummarizer {
+    /// 基于存储后端创建摘要器
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// 历史超出预算时将较早的轮次替换为摘要，返回被替换的历史消息数
+    ///
+    /// `pinned` 为历史开头需要原样保留的消息数（系统提示词与预置对话）
+    pub async fn summarize(
+        &self,
+        provider: &KiroProvider,
+        state: &mut ConversationState,
+        pinned: usize,
+        config: &ContextSummaryConfig,
+        profile_arn: Option<&str>,
+        routing: Routing<'_>,
+    ) -> usize {
+        if !config.enabled {
+            return 0;
+        }
+        let turns = render_turns(&state.history[pinned..]);
+        let keys = prefix_keys(&turns);
+        let (covered, summary) = match self.plan(&turns, &keys, config) {
+            Plan::Skip => return 0,
+            Plan::Cached { turns, summary } => (turns, summary),
+            Plan::Summarize { from, to, previous } => {
+                let model_id = state.current_message.user_input_message.model_id.clone();
+                let transcript = turns[from..to].concat();
+                match request_summary(
+                    provider,
+                    &model_id,
+                    previous.as_deref(),
+                    &transcript,
+                    profile_arn,
+                    routing,
+                )
+                .await
+                {
+                    Ok(summary) => {
+                        tracing::info!(
+                            "已将前 {} 轮对话压缩为摘要（{} tokens）",
+                            to,
+                            token::count_tokens(&summary)
+                        );
+                        self.store(&keys, to, &summary);
+                        (to, summary)
+                    }
+                    Err(e) => {
+                        tracing::warn!("生成上下文摘要失败，按原样转发较早的历史: {}", e);
+                        match previous {
+                            Some(previous) => (from, previous),
+                            None => return 0,
+                        }
+                    }
+                }
+            }
+        };
+        apply(state, pinned, covered, &summary)
+    }
+
+    fn plan(&self, turns: &[String], keys: &[String], config: &ContextSummaryConfig) -> Plan {
+        let tokens: Vec<u64> = turns.iter().map(|t| token::count_tokens(t)).collect();
+        if tokens.iter().sum::<u64>() <= config.max_history_tokens {
+            return Plan::Skip;
+        }
+        let limit = turns.len().saturating_sub(config.keep_recent_turns);
+        if limit == 0 {
+            return Plan::Skip;
+        }
+
+        let (from, previous) = match self.lookup(keys, limit) {
+            Some((from, summary)) => (from, Some(summary)),
+            None => (0, None),
+        };
+        if let Some(summary) = previous.as_deref() {
+            let remaining = token::count_tokens(summary) + tokens[from..].iter().sum::<u64>();
+            if remaining <= config.max_history_tokens || from == limit {
+                return Plan::Cached {
+                    turns: from,
+                    summary: previous.unwrap_or_default(),
+                };
+            }
+        }
+        Plan::Summarize {
+            from,
+            to: limit,
+            previous,
+        }
+    }
+
+    /// 查找本会话最近一次生成的摘要（仅当其覆盖的前缀与当前历史一致且不超过 `limit` 轮时可用）
+    fn lookup(&self, keys: &[String], limit: usize) -> Option<(usize, String)> {
+        let read = |key: &str| match self.storage.get(NAMESPACE, key) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("读取上下文摘要失败: {}", e);
+                None
+            }
+        };
+        let turns: usize = read(&latest_key(keys))?.parse().ok()?;
+        if turns == 0 || turns > limit {
+            return None;
+        }
+        read(&keys[turns]).map(|summary| (turns, summary))
+    }
+
+    /// 保存覆盖前 `turns` 轮的摘要，并记为本会话最近一次生成的摘要（写入失败只记录日志）
+    fn store(&self, keys: &[String], turns: usize, summary: &str) {
+        let result = self
+            .storage
+            .put(NAMESPACE, &keys[turns], summary, Some(SUMMARY_TTL))
+            .and_then(|_| {
+                self.storage.put(
+                    NAMESPACE,
+                    &latest_key(keys),
+                    &turns.to_string(),
+                    Some(SUMMARY_TTL),
+                )
+            });
+        if let Err(e) = result {
+            tracing::warn!("保存上下文摘要失败: {}", e);
+        }
+    }
+}
+
+/// 以文本形式渲染每轮对话（user + assistant），用于计算 tokens、前缀指纹与摘要请求
+fn render_turns(history: &[Message]) -> Vec<String> {
+    history
+        .chunks(2)
+        .map(|turn| {
+            let mut text = String::new();
+            for message in turn {
+                render_message(message, &mut text);
+            }
+            text
+        })
+        .collect()
+}
+
+fn render_message(message: &Message, out: &mut String) {
+    match message {
+        Message::User(user) => {
+            let msg = &user.user_input_message;
+            let _ = write!(out, "User: {}", msg.content);
+            for result in &msg.user_input_message_context.tool_results {
+                let text: Vec<&str> = result
+                    .content
+                    .iter()
+                    .filter_map(|c| c.get("text").and_then(|v| v.as_str()))
+                    .collect();
+                let _ = write!(
+                    out,
+                    "\n[Tool result {}]: {}",
+                    result.tool_use_id,
+                    text.join("\n")
+                );
+            }
+            if !msg.images.is_empty() {
+                let _ = write!(out, "\n[{} image(s)]", msg.images.len());
+            }
+        }
+        Message::Assistant(assistant) => {
+            let msg = &assistant.assistant_response_message;
+            let _ = write!(out, "Assistant: {}", msg.content);
+            for tool_use in msg.tool_uses.iter().flatten() {
+                let _ = write!(
+                    out,
+                    "\n[Tool call {} {}]: {}",
+                    tool_use.name, tool_use.tool_use_id, tool_use.input
+                );
+            }
+        }
+    }
+    out.push_str("\n\n");
+}
+
+/// 逐轮滚动的前缀指纹：`keys[k]` 为前 k 轮对话的指纹（`keys[0]` 为空前缀）
+fn prefix_keys(turns: &[String]) -> Vec<String> {
+    let mut hasher = Sha256::new();
+    let mut keys = vec![hex::encode(hasher.clone().finalize())];
+    for turn in turns {
+        hasher.update(turn.as_bytes());
+        hasher.update([0]);
+        keys.push(hex::encode(hasher.clone().finalize()));
+    }
+    keys
+}
+
+/// 会话最近一次摘要的索引键（以第一轮对话的指纹标识会话）
+fn latest_key(keys: &[String]) -> String {
+    format!("latest|{}", keys.get(1).unwrap_or(&keys[0]))
+}
+
+/// 用摘要替换前 `turns` 轮对话，返回被替换的消息数
+fn apply(state: &mut ConversationState, pinned: usize, turns: usize, summary: &str) -> usize {
+    let model_id = state.current_message.user_input_message.model_id.clone();
+    let end = pinned + turns * 2;
+    state.history.splice(
+        pinned..end,
+        [
+            Message::User(HistoryUserMessage::new(
+                format!("{}\n\n{}", SUMMARY_HEADER, summary),
+                model_id,
+            )),
+            Message::Assistant(HistoryAssistantMessage::new(SUMMARY_ACK)),
+        ],
+    );
+    drop_orphan_tool_results(&mut state.history);
+    turns * 2
+}
+
+/// 调用 Kiro 生成摘要
+async fn request_summary(
+    provider: &KiroProvider,
+    model_id: &str,
+    previous: Option<&str>,
+    transcript: &str,
+    profile_arn: Option<&str>,
+    routing: Routing<'_>,
+) -> anyhow::Result<String> {
+    let mut prompt = String::from(SUMMARY_PROMPT);
+    if let Some(previous) = previous {
+        let _ = write!(
+            prompt,
+            "\n\nThe conversation so far has already been summarized as:\n<previous_summary>\n{}\n</previous_summary>\nMerge it with the conversation that follows into a single summary.",
+            previous
+        );
+    }
+    let _ = write!(prompt, "\n\n<conversation>\n{}</conversation>", transcript);
+
+    let conversation_state = ConversationState::new(Uuid::new_v4().to_string())
+        .with_agent_task_type("vibe")
+        .with_chat_trigger_type("MANUAL")
+        .with_current_message(CurrentMessage::new(
+            UserInputMessage::new(prompt, model_id).with_origin("AI_EDITOR"),
+        ));
+    let body = serde_json::to_string(&KiroRequest {
+        conversation_state,
+        profile_arn: profile_arn.map(str::to_string),
+    })?;
+
+    let mut response = provider.call_api(&body, routing).await?;
+    let _guard = take_connection_guard(&mut response);
+    let bytes = response.bytes().await?;
+
+    let config = provider.token_manager().config();
+    let mut decoder = EventStreamDecoder::with_limits(
+        config.decoder_max_buffer_bytes,
+        config.decoder_overflow_policy,
+    );
+    let mut summary = String::new();
+    for chunk in bytes.chunks(DEFAULT_BUFFER_CAPACITY) {
+        feed_decoder(&mut decoder, chunk)?;
+        for frame in decoder.decode_iter() {
+            if let Ok(frame) = frame
+                && let Ok(Event::AssistantResponse(resp)) = Event::from_frame(frame)
+            {
+                summary.push_str(&resp.content);
+            }
+        }
+    }
+    let summary = summary.trim();
+    if summary.is_empty() {
+        anyhow::bail!("上游返回的摘要为空");
+    }
+    Ok(summary.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn state(turns: usize) -> ConversationState {
+        let mut history = vec![
+            Message::user("system prompt", "claude-sonnet-4.5"),
+            Message::assistant("I will follow these instructions."),
+        ];
+        for i in 0..turns {
+            history.push(Message::user(
+                format!(
+                    "question {} {}",
+                    i,
+                    "lorem ipsum dolor sit amet ".repeat(40)
+                ),
+                "claude-sonnet-4.5",
+            ));
+            history.push(Message::assistant(format!("answer {}", i)));
+        }
+        ConversationState::new("c").with_history(history)
+    }
+
+    fn config(max_history_tokens: u64) -> ContextSummaryConfig {
+        ContextSummaryConfig {
+            enabled: true,
+            max_history_tokens,
+            keep_recent_turns: 2,
+        }
+    }
+
+    #[test]
+    fn test_plan_summarizes_then_reuses_summary() {
+        let summarizer = ContextSummarizer::new(Arc::new(MemoryStorage::new()));
+        let state = state(6);
+        let turns = render_turns(&state.history[2..]);
+        let keys = prefix_keys(&turns);
+        let per_turn = token::count_tokens(&turns[0]);
+
+        assert_eq!(
+            summarizer.plan(&turns, &keys, &config(u64::MAX)),
+            Plan::Skip
+        );
+        assert_eq!(
+            summarizer.plan(&turns, &keys, &config(100)),
+            Plan::Summarize {
+                from: 0,
+                to: 4,
+                previous: None
+            }
+        );
+
+        // 下一轮请求多出一轮简短的对话：未摘要的部分未超出预算时直接复用已有摘要
+        summarizer.store(&keys, 4, "- earlier");
+        let mut next = state.clone();
+        next.history
+            .push(Message::user("question 6", "claude-sonnet-4.5"));
+        next.history.push(Message::assistant("answer 6"));
+        let turns = render_turns(&next.history[2..]);
+        let keys = prefix_keys(&turns);
+        assert_eq!(
+            summarizer.plan(&turns, &keys, &config(per_turn * 5)),
+            Plan::Cached {
+                turns: 4,
+                summary: "- earlier".to_string()
+            }
+        );
+        // 再次超出预算时在已有摘要的基础上继续压缩
+        assert_eq!(
+            summarizer.plan(&turns, &keys, &config(100)),
+            Plan::Summarize {
+                from: 4,
+                to: 5,
+                previous: Some("- earlier".to_string())
+            }
+        );
+
+        // 历史被改写后前缀不再匹配，不复用
+        let mut edited = next.clone();
+        edited.history[3] = Message::assistant("different answer");
+        let turns = render_turns(&edited.history[2..]);
+        let keys = prefix_keys(&turns);
+        assert!(summarizer.lookup(&keys, 5).is_none());
+    }
+
+    #[test]
+    fn test_apply_replaces_turns_with_summary_pair() {
+        let mut state = state(4);
+        assert_eq!(apply(&mut state, 2, 3, "- earlier"), 6);
+        assert_eq!(state.history.len(), 6);
+        let Message::User(summary) = &state.history[2] else {
+            panic!("expected user message");
+        };
+        assert!(
+            summary
+                .user_input_message
+                .content
+                .starts_with(SUMMARY_HEADER)
+        );
+        let Message::User(kept) = &state.history[4] else {
+            panic!("expected user message");
+        };
+        assert!(kept.user_input_message.content.starts_with("question 3"));
+    }
+}
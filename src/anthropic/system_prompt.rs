@@ -0,0 +1,140 @@
+//! 按模型的系统提示词模板
+//!
+//! `systemPromptTemplates` 按模型名匹配（规则同响应页脚），在系统消息前后插入文本。
+//! 启用 `opusPromptInjection` 时专业助手提示词作为内置的 `opus` 模板加入（见 `Config::load`）。
+//! 模板变量：`{date}` 为当前 UTC 日期（YYYY-MM-DD），`{model}` 为请求中的模型名
+
+use crate::model::config::{Config, SystemPromptTemplate};
+
+use super::footer;
+use super::injection::InjectSelection;
+
+/// 解析本次请求使用的系统提示词模板
+///
+/// 客户端通过 `x-kiro-inject` 请求头指定了注入选择时，该选择覆盖模板
+pub fn resolve<'a>(
+    config: &'a Config,
+    model: &str,
+    selection: Option<&InjectSelection>,
+) -> Option<&'a SystemPromptTemplate> {
+    if selection.is_some() {
+        return None;
+    }
+    footer::match_model(&config.system_prompt_templates, model)
+}
+
+/// 将模板应用到系统消息（按当前日期渲染变量）
+pub fn apply(template: &SystemPromptTemplate, system: &str, model: &str) -> String {
+    let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    apply_with_date(template, system, model, &date)
+}
+
+/// 按给定日期应用模板：prepend、系统消息、append 之间以空行分隔，空的部分跳过
+fn apply_with_date(
+    template: &SystemPromptTemplate,
+    system: &str,
+    model: &str,
+    date: &str,
+) -> String {
+    let prepend = template.prepend.as_deref().map(|t| render(t, model, date));
+    let append = template.append.as_deref().map(|t| render(t, model, date));
+    [prepend.as_deref(), Some(system), append.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// 渲染模板变量
+fn render(text: &str, model: &str, date: &str) -> String {
+    text.replace("{date}", date).replace("{model}", model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_by_model() {
+        let template = |prepend: &str| SystemPromptTemplate {
+            prepend: Some(prepend.to_string()),
+            append: None,
+        };
+        let config = Config {
+            system_prompt_templates: [("*", template("default")), ("opus", template("opus"))]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            ..Config::default()
+        };
+        assert_eq!(
+            resolve(&config, "claude-opus-4-5", None),
+            Some(&template("opus"))
+        );
+        assert_eq!(
+            resolve(&config, "claude-sonnet-4-5", None),
+            Some(&template("default"))
+        );
+        assert!(resolve(&Config::default(), "claude-opus-4-5", None).is_none());
+        assert!(resolve(&config, "claude-opus-4-5", Some(&InjectSelection::None)).is_none());
+    }
+
+    #[test]
+    fn test_opus_injection_is_builtin_template() {
+        let load = |json: &str| {
+            let path =
+                std::env::temp_dir().join(format!("kiro-config-{}.json", uuid::Uuid::new_v4()));
+            std::fs::write(&path, json).unwrap();
+            let config = Config::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            config
+        };
+
+        let config = load(r#"{"opusPromptInjection": true}"#);
+        let template = resolve(&config, "claude-opus-4-5", None).unwrap();
+        assert_eq!(
+            template.prepend.as_deref(),
+            Some(super::super::converter::PROFESSIONAL_SYSTEM_PROMPT)
+        );
+        assert!(resolve(&config, "claude-sonnet-4-5", None).is_none());
+
+        // 已配置的同名模板优先于内置模板
+        let config = load(
+            r#"{"opusPromptInjection": true, "systemPromptTemplates": {"opus": {"append": "Custom"}}}"#,
+        );
+        let template = resolve(&config, "claude-opus-4-5", None).unwrap();
+        assert_eq!(template.prepend, None);
+        assert_eq!(template.append.as_deref(), Some("Custom"));
+
+        // 更具体的模板键优先于内置的 `opus` 模板，匹配的模型不再注入专业助手提示词
+        let config = load(
+            r#"{"opusPromptInjection": true, "systemPromptTemplates": {"claude-opus-4-5": {"append": "Specific"}}}"#,
+        );
+        let template = resolve(&config, "claude-opus-4-5", None).unwrap();
+        assert_eq!(template.prepend, None);
+        assert_eq!(template.append.as_deref(), Some("Specific"));
+        assert!(
+            resolve(&config, "claude-opus-4-1", None)
+                .unwrap()
+                .prepend
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_apply_renders_variables() {
+        let template = SystemPromptTemplate {
+            prepend: Some("Today is {date}.".to_string()),
+            append: Some("You are {model}.".to_string()),
+        };
+        assert_eq!(
+            apply_with_date(&template, "Be concise.", "claude-opus-4-5", "2026-01-02"),
+            "Today is 2026-01-02.\n\nBe concise.\n\nYou are claude-opus-4-5."
+        );
+        assert_eq!(
+            apply_with_date(&template, "", "claude-opus-4-5", "2026-01-02"),
+            "Today is 2026-01-02.\n\nYou are claude-opus-4-5."
+        );
+    }
+}
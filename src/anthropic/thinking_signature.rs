@@ -0,0 +1,45 @@
+//! thinking 签名
+//!
+//! Kiro 不返回 thinking 签名，而开启 extended thinking 的严格客户端（如 Claude Code）要求
+//! thinking 块带 `signature`，并在后续请求中原样回传。流式输出时按 thinking 内容生成签名，
+//! 历史转换时据此发现回传的 thinking 块被意外改动。
+//!
+//! 签名是不带密钥的内容摘要，任何人都能为任意内容算出有效签名，因此只能检测意外修改，
+//! 不能证明 thinking 块来自本服务
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use sha2::{Digest, Sha256};
+
+/// 本服务生成的签名前缀，用于和 Anthropic 签发的签名区分
+const SIGNATURE_PREFIX: &str = "kiro-rs.";
+
+/// 为 thinking 内容生成签名：`kiro-rs.` + base64(sha256(thinking))
+pub fn sign(thinking: &str) -> String {
+    let digest = Sha256::digest(thinking.as_bytes());
+    format!("{}{}", SIGNATURE_PREFIX, BASE64.encode(digest))
+}
+
+/// 签名是否为本服务的格式（不校验内容）
+pub fn is_local(signature: &str) -> bool {
+    signature.starts_with(SIGNATURE_PREFIX)
+}
+
+/// 校验签名与 thinking 内容是否匹配
+pub fn verify(thinking: &str, signature: &str) -> bool {
+    is_local(signature) && sign(thinking) == signature
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signature = sign("let me think");
+        assert!(is_local(&signature));
+        assert_eq!(signature, sign("let me think"));
+        assert!(verify("let me think", &signature));
+        assert!(!verify("let me think again", &signature));
+        assert!(!verify("let me think", "EqQBCkYIBxgCKkD"));
+    }
+}
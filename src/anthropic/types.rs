@@ -34,6 +34,11 @@ impl ErrorResponse {
     pub fn authentication_error() -> Self {
         Self::new("authentication_error", "Invalid API key")
     }
+
+    /// 创建速率限制错误响应
+    pub fn rate_limit_error(message: impl Into<String>) -> Self {
+        Self::new("rate_limit_error", message)
+    }
 }
 
 // === Models 端点类型 ===
@@ -61,7 +66,7 @@ pub struct ModelsResponse {
 // === Messages 端点类型 ===
 
 /// 最大思考预算 tokens
-const MAX_BUDGET_TOKENS: i32 = 24576;
+pub(crate) const MAX_BUDGET_TOKENS: i32 = 24576;
 
 /// Thinking 配置
 #[derive(Debug, Deserialize, Clone)]
@@ -94,7 +99,7 @@ pub struct Metadata {
 }
 
 /// Messages 请求体
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct MessagesRequest {
     pub model: String,
     pub max_tokens: i32,
@@ -108,6 +113,14 @@ pub struct MessagesRequest {
     pub thinking: Option<Thinking>,
     /// Claude Code 请求中的 metadata，包含 session 信息
     pub metadata: Option<Metadata>,
+    /// 自定义停止序列（上游不支持，由代理侧扫描输出并截断）
+    pub stop_sequences: Option<Vec<String>>,
+    /// 采样温度（0.0 ~ 1.0）
+    pub temperature: Option<f64>,
+    /// 核采样概率阈值（0.0 ~ 1.0）
+    pub top_p: Option<f64>,
+    /// 仅从概率最高的 K 个 token 中采样
+    pub top_k: Option<i32>,
 }
 
 /// 反序列化 system 字段，支持字符串或数组格式
@@ -133,6 +146,7 @@ where
         {
             Ok(Some(vec![SystemMessage {
                 text: value.to_string(),
+                cache_control: None,
             }]))
         }
 
@@ -181,6 +195,19 @@ pub struct Message {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SystemMessage {
     pub text: String,
+    /// Prompt caching 断点标记（不发送到上游，仅用于本地模拟缓存用量）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
+}
+
+/// Prompt caching 断点标记，如 `{"type": "ephemeral", "ttl": "1h"}`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheControl {
+    #[serde(rename = "type")]
+    pub cache_type: String,
+    /// 缓存有效期：`5m`（默认）或 `1h`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
 }
 
 /// 工具定义
@@ -205,6 +232,9 @@ pub struct Tool {
     /// 最大使用次数（仅 WebSearch 工具）
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_uses: Option<i32>,
+    /// Prompt caching 断点标记
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
 }
 
 impl Tool {
@@ -225,6 +255,12 @@ pub struct ContentBlock {
     pub text: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking: Option<String>,
+    /// thinking 块的签名（客户端回传本服务签发的签名）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// redacted_thinking 块的加密内容
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_use_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -246,8 +282,13 @@ pub struct ContentBlock {
 pub struct ImageSource {
     #[serde(rename = "type")]
     pub source_type: String,
+    #[serde(default)]
     pub media_type: String,
+    #[serde(default)]
     pub data: String,
+    /// `type == "url"` 时的图片地址
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
 }
 
 // === Count Tokens 端点类型 ===
@@ -272,3 +313,15 @@ pub struct CountTokensRequest {
 pub struct CountTokensResponse {
     pub input_tokens: i32,
 }
+
+// === 长轮询事件端点类型 ===
+
+/// 长轮询查询参数
+#[derive(Debug, Deserialize)]
+pub struct MessageEventsQuery {
+    /// 已读取的事件数（首次轮询为 0）
+    #[serde(default)]
+    pub cursor: usize,
+    /// 无新事件时的最长等待时间（秒）
+    pub wait: Option<u64>,
+}
@@ -0,0 +1,471 @@
+//! 请求 schema 校验
+//!
+//! 格式错误的请求原本要到转换阶段才失败，错误信息往往难以定位。启用 `requestValidation` 后，
+//! `/v1/messages` 请求体在反序列化之前按内置的 Anthropic Messages JSON Schema
+//! （`schema/messages_request.json`）校验，给出精确到字段路径的错误：
+//!
+//! - `shadow`：只记录警告日志与指标，请求照常处理，适合上线前观察
+//! - `strict`：校验失败时直接返回 400
+//!
+//! 校验器只实现该 schema 用到的关键字（`$ref`、`type`、`const`、`enum`、`required`、
+//! `properties`、`items`、`anyOf` 及长度 / 数值范围约束）
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::Value;
+
+use crate::common::metrics;
+use crate::model::config::RequestValidation;
+
+use super::middleware::AppState;
+use super::router::MAX_BODY_SIZE;
+use super::types::ErrorResponse;
+
+/// 内置的 Messages 请求 schema
+static MESSAGES_SCHEMA: OnceLock<Value> = OnceLock::new();
+
+/// 错误响应中最多列出的字段错误数
+const MAX_REPORTED_ERRORS: usize = 10;
+
+fn messages_schema() -> &'static Value {
+    MESSAGES_SCHEMA.get_or_init(|| {
+        serde_json::from_str(include_str!("schema/messages_request.json"))
+            .expect("内置的 Messages schema 不是有效的 JSON")
+    })
+}
+
+/// 字段级校验错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    /// 字段路径，如 `messages.0.content.1.text`（根对象为空）
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// 按 Messages schema 校验请求体，返回所有字段错误
+pub fn validate_messages_request(body: &Value) -> Vec<SchemaError> {
+    let schema = messages_schema();
+    let mut validator = Validator {
+        root: schema,
+        errors: Vec::new(),
+    };
+    validator.check(schema, body, "");
+    validator.errors
+}
+
+/// `/v1/messages` 的 schema 校验中间件
+pub async fn validate_request(
+    State(state): State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let mode = state
+        .kiro_provider
+        .as_ref()
+        .map(|p| p.token_manager().config().request_validation)
+        .unwrap_or_default();
+    if mode == RequestValidation::Off {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, MAX_BODY_SIZE).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(ErrorResponse::new(
+                    "request_too_large",
+                    format!("Failed to read request body: {}", e),
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    let errors = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => validate_messages_request(&value),
+        Err(e) => vec![SchemaError {
+            path: String::new(),
+            message: format!("Invalid JSON body: {}", e),
+        }],
+    };
+    if !errors.is_empty() {
+        metrics::REQUEST_SCHEMA_VIOLATIONS.inc_by(1);
+        let summary = summarize(&errors);
+        if mode == RequestValidation::Strict {
+            tracing::info!("请求未通过 schema 校验: {}", summary);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new("invalid_request_error", summary)),
+            )
+                .into_response();
+        }
+        tracing::warn!(
+            "请求未通过 schema 校验（shadow 模式，继续处理）: {}",
+            summary
+        );
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+/// 拼接错误信息（超出上限的部分只给出数量）
+fn summarize(errors: &[SchemaError]) -> String {
+    let mut summary = errors
+        .iter()
+        .take(MAX_REPORTED_ERRORS)
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ");
+    if errors.len() > MAX_REPORTED_ERRORS {
+        summary.push_str(&format!(
+            " (and {} more)",
+            errors.len() - MAX_REPORTED_ERRORS
+        ));
+    }
+    summary
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    errors: Vec<SchemaError>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, path: &str, message: impl Into<String>) {
+        self.errors.push(SchemaError {
+            path: path.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// 解析 `#/$defs/Name` 形式的引用
+    fn resolve(&self, schema: &'a Value) -> &'a Value {
+        match schema.get("$ref").and_then(Value::as_str) {
+            Some(reference) => reference
+                .strip_prefix("#/$defs/")
+                .and_then(|name| self.root.get("$defs")?.get(name))
+                .map(|target| self.resolve(target))
+                .unwrap_or_else(|| panic!("内置 schema 引用不存在: {}", reference)),
+            None => schema,
+        }
+    }
+
+    fn check(&mut self, schema: &'a Value, value: &Value, path: &str) {
+        let schema = self.resolve(schema);
+
+        if let Some(branches) = schema.get("anyOf").and_then(Value::as_array) {
+            self.check_any_of(branches, value, path);
+            return;
+        }
+
+        if let Some(expected) = schema.get("type")
+            && !type_matches(expected, value)
+        {
+            self.error(
+                path,
+                format!(
+                    "Input should be a valid {}, got {}",
+                    type_names(expected).join(" or "),
+                    type_name(value)
+                ),
+            );
+            return;
+        }
+
+        if let Some(expected) = schema.get("const")
+            && value != expected
+        {
+            self.error(path, format!("Input should be {}", expected));
+            return;
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array)
+            && !allowed.contains(value)
+        {
+            let allowed: Vec<String> = allowed.iter().map(ToString::to_string).collect();
+            self.error(
+                path,
+                format!("Input should be one of {}", allowed.join(", ")),
+            );
+            return;
+        }
+
+        match value {
+            Value::String(s) => self.check_length(schema, s.chars().count(), path),
+            Value::Number(n) => self.check_range(schema, n.as_f64().unwrap_or_default(), path),
+            Value::Array(items) => self.check_array(schema, items, path),
+            Value::Object(map) => {
+                for field in schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                {
+                    if !map.contains_key(field) {
+                        self.error(&join(path, field), "Field required");
+                    }
+                }
+                if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+                    for (field, property) in properties {
+                        if let Some(child) = map.get(field) {
+                            self.check(property, child, &join(path, field));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_length(&mut self, schema: &Value, len: usize, path: &str) {
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64)
+            && (len as u64) < min
+        {
+            self.error(
+                path,
+                format!("String should have at least {} characters", min),
+            );
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64)
+            && (len as u64) > max
+        {
+            self.error(
+                path,
+                format!("String should have at most {} characters", max),
+            );
+        }
+    }
+
+    fn check_range(&mut self, schema: &Value, number: f64, path: &str) {
+        if let Some(min) = schema.get("minimum").and_then(Value::as_f64)
+            && number < min
+        {
+            self.error(
+                path,
+                format!("Input should be greater than or equal to {}", min),
+            );
+        }
+        if let Some(max) = schema.get("maximum").and_then(Value::as_f64)
+            && number > max
+        {
+            self.error(
+                path,
+                format!("Input should be less than or equal to {}", max),
+            );
+        }
+    }
+
+    fn check_array(&mut self, schema: &'a Value, items: &[Value], path: &str) {
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+            && (items.len() as u64) < min
+        {
+            self.error(path, format!("List should have at least {} items", min));
+        }
+        if let Some(item_schema) = schema.get("items") {
+            for (index, item) in items.iter().enumerate() {
+                self.check(item_schema, item, &join(path, &index.to_string()));
+            }
+        }
+    }
+
+    /// `anyOf`：任一分支通过即可
+    ///
+    /// 都不通过时，先按 JSON 类型、再按 `type` 字段的 `const` 值确定唯一分支并报告其错误，
+    /// 从而给出精确的字段级错误而不是笼统的“不匹配任何分支”
+    fn check_any_of(&mut self, branches: &'a [Value], value: &Value, path: &str) {
+        let branches: Vec<&'a Value> = branches.iter().map(|b| self.resolve(b)).collect();
+
+        let mut candidates: Vec<&'a Value> = branches
+            .iter()
+            .copied()
+            .filter(|b| b.get("type").is_none_or(|t| type_matches(t, value)))
+            .collect();
+        if candidates.is_empty() {
+            let mut expected: Vec<&str> = branches
+                .iter()
+                .filter_map(|b| b.get("type"))
+                .flat_map(type_names)
+                .collect();
+            expected.dedup();
+            self.error(
+                path,
+                format!(
+                    "Input should be a valid {}, got {}",
+                    expected.join(" or "),
+                    type_name(value)
+                ),
+            );
+            return;
+        }
+
+        let tag_of = |b: &Value| b.get("properties")?.get("type")?.get("const").cloned();
+        if let Some(tag) = value.get("type")
+            && candidates.iter().all(|b| tag_of(b).is_some())
+        {
+            let tags: Vec<Value> = candidates.iter().filter_map(|b| tag_of(b)).collect();
+            candidates.retain(|b| tag_of(b).as_ref() == Some(tag));
+            if candidates.is_empty() {
+                let tags: Vec<String> = tags.iter().map(ToString::to_string).collect();
+                self.error(
+                    &join(path, "type"),
+                    format!("Input should be one of {}", tags.join(", ")),
+                );
+                return;
+            }
+        }
+
+        let mut best: Option<Vec<SchemaError>> = None;
+        for branch in &candidates {
+            let mut sub = Validator {
+                root: self.root,
+                errors: Vec::new(),
+            };
+            sub.check(branch, value, path);
+            if sub.errors.is_empty() {
+                return;
+            }
+            if best.as_ref().is_none_or(|b| sub.errors.len() < b.len()) {
+                best = Some(sub.errors);
+            }
+        }
+        match best {
+            Some(errors) if candidates.len() == 1 => self.errors.extend(errors),
+            _ => self.error(path, "Input does not match any of the allowed forms"),
+        }
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+fn type_names(expected: &Value) -> Vec<&str> {
+    match expected {
+        Value::String(name) => vec![name.as_str()],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn type_matches(expected: &Value, value: &Value) -> bool {
+    type_names(expected).into_iter().any(|name| match name {
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        other => other == type_name(value),
+    })
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn errors(body: Value) -> Vec<String> {
+        validate_messages_request(&body)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_request() {
+        let body = json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 1024,
+            "system": [{"type": "text", "text": "sys", "cache_control": {"type": "ephemeral"}}],
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "t1", "name": "get_weather", "input": {}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": "sunny"}
+                ]}
+            ],
+            "tools": [{"name": "get_weather", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "auto"},
+            "thinking": {"type": "enabled", "budget_tokens": 2048},
+            "unknown_field": true
+        });
+        assert!(errors(body).is_empty());
+    }
+
+    #[test]
+    fn test_field_level_errors() {
+        let body = json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": "1024",
+            "messages": [
+                {"role": "system", "content": "hi"},
+                {"role": "user", "content": [{"type": "text", "text": 42}]},
+                {"role": "user", "content": [{"type": "image", "source": {"type": "base64", "media_type": "image/bmp", "data": "x"}}]},
+                {"role": "user", "content": [{"type": "video"}]},
+                {"role": "user"}
+            ],
+            "tool_choice": {"type": "tool"}
+        });
+        assert_eq!(
+            errors(body),
+            vec![
+                "max_tokens: Input should be a valid integer, got string",
+                "messages.0.role: Input should be one of \"user\", \"assistant\"",
+                "messages.1.content.0.text: Input should be a valid string, got number",
+                "messages.2.content.0.source.media_type: Input should be one of \"image/jpeg\", \"image/png\", \"image/gif\", \"image/webp\"",
+                "messages.3.content.0.type: Input should be one of \"text\", \"image\", \"document\", \"tool_use\", \"tool_result\", \"thinking\", \"redacted_thinking\", \"server_tool_use\", \"web_search_tool_result\"",
+                "messages.4.content: Field required",
+                "tool_choice.name: Field required",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_required_and_summary() {
+        let errors = validate_messages_request(&json!({"messages": []}));
+        let summary = summarize(&errors);
+        assert_eq!(
+            summary,
+            "model: Field required; max_tokens: Field required; messages: List should have at least 1 items"
+        );
+        assert_eq!(
+            validate_messages_request(&json!([]))[0].to_string(),
+            "Input should be a valid object, got array"
+        );
+    }
+}
@@ -3,20 +3,32 @@
 //! 实现 Anthropic WebSearch 请求到 Kiro MCP 的转换和响应生成
 
 use std::convert::Infallible;
+use std::sync::Arc;
 
 use axum::{
     body::Body,
-    http::{StatusCode, header},
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Json, Response},
 };
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use bytes::Bytes;
-use futures::{Stream, stream};
+use chrono::DateTime;
+use futures::{StreamExt, stream};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use uuid::Uuid;
 
+use crate::common::auth;
+use crate::common::rate_limit::KeyRateLimiter;
+use crate::kiro::provider::take_connection_guard;
+use crate::kiro::token_manager::Routing;
+use crate::model::config::{Config, WebSearchMode};
+use crate::storage::ledger::UsageLedger;
+use crate::token;
+
+use super::handlers::record_usage;
 use super::stream::SseEvent;
-use super::types::{ErrorResponse, MessagesRequest};
+use super::types::{ErrorResponse, MessagesRequest, Tool};
 
 /// MCP 请求
 #[derive(Debug, Serialize)]
@@ -107,6 +119,57 @@ pub fn has_web_search_tool(req: &MessagesRequest) -> bool {
     })
 }
 
+/// 解析本次请求的 WebSearch 处理方式
+///
+/// `websearchKeyOverrides` 中匹配当前 API Key 的配置优先于全局 `websearchMode`
+pub fn resolve_mode(config: &Config, headers: &HeaderMap) -> WebSearchMode {
+    auth::extract_api_key_from_headers(headers)
+        .and_then(|key| {
+            config
+                .websearch_key_overrides
+                .iter()
+                .find(|(k, _)| auth::constant_time_eq(k, &key))
+                .map(|(_, mode)| *mode)
+        })
+        .unwrap_or(config.websearch_mode)
+}
+
+/// 检查工具是否为 WebSearch 工具（按名称或类型）
+fn is_web_search(tool: &Tool) -> bool {
+    tool.name == "web_search" || tool.is_web_search()
+}
+
+/// 检查请求中是否包含任意 WebSearch 工具
+pub fn contains_web_search_tool(req: &MessagesRequest) -> bool {
+    req.tools
+        .as_ref()
+        .is_some_and(|tools| tools.iter().any(is_web_search))
+}
+
+/// 移除请求中的 WebSearch 工具，返回移除的数量
+///
+/// 工具列表清空时置为 None；指定使用 web_search 的 tool_choice 一并移除
+pub fn strip_web_search_tools(req: &mut MessagesRequest) -> usize {
+    let Some(tools) = req.tools.as_mut() else {
+        return 0;
+    };
+    let before = tools.len();
+    tools.retain(|t| !is_web_search(t));
+    let removed = before - tools.len();
+    if tools.is_empty() {
+        req.tools = None;
+    }
+    if removed > 0
+        && req
+            .tool_choice
+            .as_ref()
+            .is_some_and(|c| c.get("name").and_then(|n| n.as_str()) == Some("web_search"))
+    {
+        req.tool_choice = None;
+    }
+    removed
+}
+
 /// 从消息中提取搜索查询
 ///
 /// 读取 messages 的第一条消息的第一个内容块
@@ -209,37 +272,137 @@ pub fn parse_search_results(mcp_response: &McpResponse) -> Option<WebSearchResul
     serde_json::from_str(&content.text).ok()
 }
 
-/// 生成 WebSearch SSE 响应流
-pub fn create_websearch_sse_stream(
-    model: String,
-    query: String,
-    tool_use_id: String,
-    search_results: Option<WebSearchResults>,
-    input_tokens: i32,
-) -> impl Stream<Item = Result<Bytes, Infallible>> {
-    let events =
-        generate_websearch_events(&model, &query, &tool_use_id, search_results, input_tokens);
-
-    stream::iter(
-        events
-            .into_iter()
-            .map(|e| Ok(Bytes::from(e.to_sse_string()))),
+/// 生成消息 ID
+fn new_message_id() -> String {
+    format!(
+        "msg_{}",
+        Uuid::new_v4().to_string().replace('-', "")[..24].to_string()
     )
 }
 
-/// 生成 WebSearch SSE 事件序列
-fn generate_websearch_events(
+/// WebSearch 响应的 usage（含服务端工具调用次数）
+fn websearch_usage(input_tokens: i32, output_tokens: i32) -> serde_json::Value {
+    json!({
+        "input_tokens": input_tokens,
+        "output_tokens": output_tokens,
+        "cache_creation_input_tokens": 0,
+        "cache_read_input_tokens": 0,
+        "server_tool_use": {
+            "web_search_requests": 1
+        }
+    })
+}
+
+/// 引用中 cited_text 的最大字符数
+const MAX_CITED_TEXT_CHARS: usize = 150;
+
+/// 搜索结果摘要的最大字符数
+const MAX_SNIPPET_CHARS: usize = 200;
+
+/// 按字符数截断文本，超出时追加省略号
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// 生成不透明的占位字段（encrypted_content / encrypted_index）
+///
+/// Anthropic 返回的是加密内容，客户端只会原样回传；这里用 base64 编码原文占位
+fn opaque(value: &str) -> String {
+    BASE64.encode(value.as_bytes())
+}
+
+/// 将发布时间（毫秒或秒级时间戳）格式化为 page_age，如 `April 30, 2025`
+fn page_age(published_date: Option<i64>) -> Option<String> {
+    let timestamp = published_date?;
+    let datetime = if timestamp.abs() >= 100_000_000_000 {
+        DateTime::from_timestamp_millis(timestamp)
+    } else {
+        DateTime::from_timestamp(timestamp, 0)
+    };
+    datetime.map(|d| d.format("%B %-d, %Y").to_string())
+}
+
+/// web_search_tool_result 内容块中的搜索结果列表
+fn search_result_content(search_results: &Option<WebSearchResults>) -> Vec<serde_json::Value> {
+    search_results
+        .iter()
+        .flat_map(|results| &results.results)
+        .map(|r| {
+            json!({
+                "type": "web_search_result",
+                "title": r.title,
+                "url": r.url,
+                "encrypted_content": opaque(r.snippet.as_deref().unwrap_or_default()),
+                "page_age": page_age(r.published_date)
+            })
+        })
+        .collect()
+}
+
+/// 指向搜索结果的引用（web_search_result_location）
+fn citation(result: &WebSearchResult) -> serde_json::Value {
+    let snippet = result.snippet.as_deref().unwrap_or(&result.title);
+    json!({
+        "type": "web_search_result_location",
+        "url": result.url,
+        "title": result.title,
+        "encrypted_index": opaque(&result.url),
+        "cited_text": truncate_chars(snippet, MAX_CITED_TEXT_CHARS)
+    })
+}
+
+/// 回答中的文本块：每条搜索结果一个带引用的文本块，前后为说明文字
+fn answer_blocks(
+    query: &str,
+    results: &Option<WebSearchResults>,
+) -> Vec<(String, Option<serde_json::Value>)> {
+    let mut blocks = vec![(
+        format!("Here are the search results for \"{}\":\n\n", query),
+        None,
+    )];
+
+    match results {
+        Some(results) if !results.results.is_empty() => {
+            for (i, result) in results.results.iter().enumerate() {
+                let mut text = format!("{}. **{}**", i + 1, result.title);
+                if let Some(ref snippet) = result.snippet {
+                    text.push_str(&format!(": {}", truncate_chars(snippet, MAX_SNIPPET_CHARS)));
+                }
+                text.push('\n');
+                blocks.push((text, Some(citation(result))));
+            }
+        }
+        _ => blocks.push(("No results found.\n".to_string(), None)),
+    }
+
+    blocks.push((
+        "\nPlease note that these are web search results and may not be fully accurate or up-to-date."
+            .to_string(),
+        None,
+    ));
+    blocks
+}
+
+/// 回答文本的输出 token 数
+fn answer_output_tokens(blocks: &[(String, Option<serde_json::Value>)]) -> i32 {
+    blocks
+        .iter()
+        .map(|(text, _)| token::count_tokens(text))
+        .sum::<u64>() as i32
+}
+
+/// 搜索开始前即可发送的事件：message_start、ping 与完整的 server_tool_use 内容块
+fn websearch_start_events(
+    message_id: &str,
     model: &str,
     query: &str,
     tool_use_id: &str,
-    search_results: Option<WebSearchResults>,
     input_tokens: i32,
 ) -> Vec<SseEvent> {
     let mut events = Vec::new();
-    let message_id = format!(
-        "msg_{}",
-        Uuid::new_v4().to_string().replace('-', "")[..24].to_string()
-    );
 
     // 1. message_start
     events.push(SseEvent::new(
@@ -263,6 +426,7 @@ fn generate_websearch_events(
             }
         }),
     ));
+    events.push(SseEvent::new("ping", json!({"type": "ping"})));
 
     // 2. content_block_start (server_tool_use)
     events.push(SseEvent::new(
@@ -302,25 +466,20 @@ fn generate_websearch_events(
         }),
     ));
 
-    // 5. content_block_start (web_search_tool_result)
-    let search_content = if let Some(ref results) = search_results {
-        results
-            .results
-            .iter()
-            .map(|r| {
-                json!({
-                    "type": "web_search_result",
-                    "title": r.title,
-                    "url": r.url,
-                    "encrypted_content": r.snippet.clone().unwrap_or_default(),
-                    "page_age": null
-                })
-            })
-            .collect::<Vec<_>>()
-    } else {
-        vec![]
-    };
+    events
+}
+
+/// 搜索完成后的事件：web_search_tool_result、文本摘要、message_delta 与 message_stop
+///
+/// 同时返回摘要的输出 tokens
+fn websearch_result_events(
+    query: &str,
+    tool_use_id: &str,
+    search_results: Option<WebSearchResults>,
+) -> (Vec<SseEvent>, i32) {
+    let mut events = Vec::new();
 
+    // 5. content_block_start (web_search_tool_result)
     events.push(SseEvent::new(
         "content_block_start",
         json!({
@@ -329,7 +488,7 @@ fn generate_websearch_events(
             "content_block": {
                 "type": "web_search_tool_result",
                 "tool_use_id": tool_use_id,
-                "content": search_content
+                "content": search_result_content(&search_results)
             }
         }),
     ));
@@ -343,50 +502,72 @@ fn generate_websearch_events(
         }),
     ));
 
-    // 7. content_block_start (text)
-    events.push(SseEvent::new(
-        "content_block_start",
-        json!({
-            "type": "content_block_start",
-            "index": 2,
-            "content_block": {
-                "type": "text",
-                "text": ""
-            }
-        }),
-    ));
+    // 7. 文本块：每条搜索结果一个带引用的文本块
+    let blocks = answer_blocks(query, &search_results);
+    for (i, (text, citation)) in blocks.iter().enumerate() {
+        let index = i + 2;
+        events.push(SseEvent::new(
+            "content_block_start",
+            json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": {
+                    "type": "text",
+                    "text": ""
+                }
+            }),
+        ));
 
-    // 8. content_block_delta (text_delta) - 生成搜索结果摘要
-    let summary = generate_search_summary(query, &search_results);
+        // 8. citations_delta 与 text_delta
+        if let Some(citation) = citation {
+            events.push(SseEvent::new(
+                "content_block_delta",
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {
+                        "type": "citations_delta",
+                        "citation": citation
+                    }
+                }),
+            ));
+        }
+
+        // 分块发送文本
+        let chunk_size = 100;
+        for chunk in text.chars().collect::<Vec<_>>().chunks(chunk_size) {
+            let text: String = chunk.iter().collect();
+            events.push(SseEvent::new(
+                "content_block_delta",
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {
+                        "type": "text_delta",
+                        "text": text
+                    }
+                }),
+            ));
+        }
 
-    // 分块发送文本
-    let chunk_size = 100;
-    for chunk in summary.chars().collect::<Vec<_>>().chunks(chunk_size) {
-        let text: String = chunk.iter().collect();
+        // 9. content_block_stop (text)
         events.push(SseEvent::new(
-            "content_block_delta",
+            "content_block_stop",
             json!({
-                "type": "content_block_delta",
-                "index": 2,
-                "delta": {
-                    "type": "text_delta",
-                    "text": text
-                }
+                "type": "content_block_stop",
+                "index": index
             }),
         ));
     }
 
-    // 9. content_block_stop (text)
-    events.push(SseEvent::new(
-        "content_block_stop",
-        json!({
-            "type": "content_block_stop",
-            "index": 2
-        }),
-    ));
-
     // 10. message_delta
-    let output_tokens = (summary.len() as i32 + 3) / 4; // 简单估算
+    let output_tokens = answer_output_tokens(&blocks);
+    let mut usage = websearch_usage(0, output_tokens);
+    if let Some(usage) = usage.as_object_mut() {
+        usage.remove("input_tokens");
+        usage.remove("cache_creation_input_tokens");
+        usage.remove("cache_read_input_tokens");
+    }
     events.push(SseEvent::new(
         "message_delta",
         json!({
@@ -395,9 +576,7 @@ fn generate_websearch_events(
                 "stop_reason": "end_turn",
                 "stop_sequence": null
             },
-            "usage": {
-                "output_tokens": output_tokens
-            }
+            "usage": usage
         }),
     ));
 
@@ -409,41 +588,61 @@ fn generate_websearch_events(
         }),
     ));
 
-    events
+    (events, output_tokens)
 }
 
-/// 生成搜索结果摘要
-fn generate_search_summary(query: &str, results: &Option<WebSearchResults>) -> String {
-    let mut summary = format!("Here are the search results for \"{}\":\n\n", query);
-
-    if let Some(results) = results {
-        for (i, result) in results.results.iter().enumerate() {
-            summary.push_str(&format!("{}. **{}**\n", i + 1, result.title));
-            if let Some(ref snippet) = result.snippet {
-                // 截断过长的摘要
-                let truncated = if snippet.len() > 200 {
-                    format!("{}...", &snippet[..200])
-                } else {
-                    snippet.clone()
-                };
-                summary.push_str(&format!("   {}\n", truncated));
-            }
-            summary.push_str(&format!("   Source: {}\n\n", result.url));
-        }
-    } else {
-        summary.push_str("No results found.\n");
-    }
-
-    summary.push_str("\nPlease note that these are web search results and may not be fully accurate or up-to-date.");
-
-    summary
+/// 生成非流式 WebSearch 响应消息，同时返回输出 tokens
+fn websearch_message(
+    message_id: &str,
+    model: &str,
+    query: &str,
+    tool_use_id: &str,
+    search_results: Option<WebSearchResults>,
+    input_tokens: i32,
+) -> (serde_json::Value, i32) {
+    let blocks = answer_blocks(query, &search_results);
+    let output_tokens = answer_output_tokens(&blocks);
+    let mut content = vec![
+        json!({
+            "type": "server_tool_use",
+            "id": tool_use_id,
+            "name": "web_search",
+            "input": {"query": query}
+        }),
+        json!({
+            "type": "web_search_tool_result",
+            "tool_use_id": tool_use_id,
+            "content": search_result_content(&search_results)
+        }),
+    ];
+    content.extend(blocks.into_iter().map(|(text, citation)| match citation {
+        Some(citation) => json!({"type": "text", "text": text, "citations": [citation]}),
+        None => json!({"type": "text", "text": text}),
+    }));
+    let message = json!({
+        "id": message_id,
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": content,
+        "stop_reason": "end_turn",
+        "stop_sequence": null,
+        "usage": websearch_usage(input_tokens, output_tokens)
+    });
+    (message, output_tokens)
 }
 
 /// 处理 WebSearch 请求
+///
+/// 流式请求先发送 message_start 与 server_tool_use 内容块，搜索完成后再发送结果与摘要；
+/// 非流式请求返回包含全部内容块的完整消息；搜索完成后记录用量并扣减客户端 Key 的速率限制
 pub async fn handle_websearch_request(
     provider: std::sync::Arc<crate::kiro::provider::KiroProvider>,
     payload: &MessagesRequest,
     input_tokens: i32,
+    group: Option<&str>,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    rate_limiter: Option<Arc<KeyRateLimiter>>,
 ) -> Response {
     // 1. 提取搜索查询
     let query = match extract_search_query(payload) {
@@ -460,24 +659,53 @@ pub async fn handle_websearch_request(
         }
     };
 
-    tracing::info!(query = %query, "处理 WebSearch 请求");
+    tracing::info!(query = %query, stream = payload.stream, "处理 WebSearch 请求");
 
     // 2. 创建 MCP 请求
     let (tool_use_id, mcp_request) = create_mcp_request(&query);
+    let message_id = new_message_id();
+    let model = payload.model.clone();
 
-    // 3. 调用 Kiro MCP API
-    let search_results = match call_mcp_api(&provider, &mcp_request).await {
-        Ok(response) => parse_search_results(&response),
-        Err(e) => {
-            tracing::warn!("MCP API 调用失败: {}", e);
-            None
-        }
-    };
+    if !payload.stream {
+        // 3. 调用 Kiro MCP API 并生成完整响应
+        let search_results = search(&provider, &mcp_request, group).await;
+        let (message, output_tokens) = websearch_message(
+            &message_id,
+            &model,
+            &query,
+            &tool_use_id,
+            search_results,
+            input_tokens,
+        );
+        record_usage(
+            usage_ledger.as_deref(),
+            rate_limiter.as_deref(),
+            &model,
+            input_tokens,
+            output_tokens,
+        );
+        return (StatusCode::OK, Json(message)).into_response();
+    }
 
-    // 4. 生成 SSE 响应
-    let model = payload.model.clone();
-    let stream =
-        create_websearch_sse_stream(model, query, tool_use_id, search_results, input_tokens);
+    // 3. 立即发送开始事件，搜索完成后发送结果事件
+    let start = websearch_start_events(&message_id, &model, &query, &tool_use_id, input_tokens);
+    let group = group.map(str::to_string);
+    let results = stream::once(async move {
+        let search_results = search(&provider, &mcp_request, group.as_deref()).await;
+        let (events, output_tokens) = websearch_result_events(&query, &tool_use_id, search_results);
+        record_usage(
+            usage_ledger.as_deref(),
+            rate_limiter.as_deref(),
+            &model,
+            input_tokens,
+            output_tokens,
+        );
+        stream::iter(events)
+    })
+    .flatten();
+    let stream = stream::iter(start)
+        .chain(results)
+        .map(|e| Ok::<_, Infallible>(Bytes::from(e.to_sse_string())));
 
     Response::builder()
         .status(StatusCode::OK)
@@ -488,16 +716,41 @@ pub async fn handle_websearch_request(
         .unwrap()
 }
 
+/// 调用 Kiro MCP API 执行搜索（失败时返回 None，响应中显示无结果）
+async fn search(
+    provider: &crate::kiro::provider::KiroProvider,
+    request: &McpRequest,
+    group: Option<&str>,
+) -> Option<WebSearchResults> {
+    match call_mcp_api(provider, request, group).await {
+        Ok(response) => parse_search_results(&response),
+        Err(e) => {
+            tracing::warn!("MCP API 调用失败: {}", e);
+            None
+        }
+    }
+}
+
 /// 调用 Kiro MCP API
 async fn call_mcp_api(
     provider: &crate::kiro::provider::KiroProvider,
     request: &McpRequest,
+    group: Option<&str>,
 ) -> anyhow::Result<McpResponse> {
     let request_body = serde_json::to_string(request)?;
 
     tracing::debug!("MCP request: {}", request_body);
 
-    let response = provider.call_mcp(&request_body).await?;
+    let mut response = provider
+        .call_mcp(
+            &request_body,
+            Routing {
+                group,
+                ..Routing::default()
+            },
+        )
+        .await?;
+    let _guard = take_connection_guard(&mut response);
 
     let body = response.text().await?;
     tracing::debug!("MCP response: {}", body);
@@ -538,10 +791,15 @@ mod tests {
                 description: String::new(),
                 input_schema: Default::default(),
                 max_uses: Some(8),
+                cache_control: None,
             }]),
             tool_choice: None,
             thinking: None,
             metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         assert!(has_web_search_tool(&req));
@@ -567,6 +825,7 @@ mod tests {
                     description: String::new(),
                     input_schema: Default::default(),
                     max_uses: Some(8),
+                    cache_control: None,
                 },
                 Tool {
                     tool_type: None,
@@ -574,11 +833,16 @@ mod tests {
                     description: "Other tool".to_string(),
                     input_schema: Default::default(),
                     max_uses: None,
+                    cache_control: None,
                 },
             ]),
             tool_choice: None,
             thinking: None,
             metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         // 多个工具时不应该被识别为纯 websearch 请求
@@ -605,6 +869,10 @@ mod tests {
             tool_choice: None,
             thinking: None,
             metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         let query = extract_search_query(&req);
@@ -629,6 +897,10 @@ mod tests {
             tool_choice: None,
             thinking: None,
             metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
         };
 
         let query = extract_search_query(&req);
@@ -700,13 +972,13 @@ mod tests {
     }
 
     #[test]
-    fn test_generate_search_summary() {
+    fn test_answer_blocks_cite_results() {
         let results = WebSearchResults {
             results: vec![WebSearchResult {
                 title: "Test Result".to_string(),
                 url: "https://example.com".to_string(),
-                snippet: Some("This is a test snippet".to_string()),
-                published_date: None,
+                snippet: Some("这是一段很长的摘要".repeat(30)),
+                published_date: Some(1_746_000_000_000),
                 id: None,
                 domain: None,
                 max_verbatim_word_limit: None,
@@ -717,10 +989,177 @@ mod tests {
             error: None,
         };
 
-        let summary = generate_search_summary("test", &Some(results));
+        let blocks = answer_blocks("test", &Some(results));
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks[0].1.is_none());
+        let (text, citation) = &blocks[1];
+        assert!(text.starts_with("1. **Test Result**"));
+        let citation = citation.as_ref().unwrap();
+        assert_eq!(citation["type"], "web_search_result_location");
+        assert_eq!(citation["url"], "https://example.com");
+        assert_eq!(
+            citation["cited_text"].as_str().unwrap().chars().count(),
+            MAX_CITED_TEXT_CHARS + 3
+        );
+
+        assert_eq!(page_age(Some(1_746_000_000_000)).unwrap(), "April 30, 2025");
+        assert_eq!(page_age(Some(1_746_000_000)).unwrap(), "April 30, 2025");
+        assert!(
+            answer_blocks("test", &None)[1]
+                .0
+                .contains("No results found")
+        );
+    }
+
+    #[test]
+    fn test_websearch_stream_event_order() {
+        let start = websearch_start_events("msg_1", "claude-sonnet-4", "rust", "srvtoolu_1", 10);
+        let names: Vec<_> = start.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "message_start",
+                "ping",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop"
+            ]
+        );
+
+        let (result, output_tokens) = websearch_result_events("rust", "srvtoolu_1", None);
+        assert!(output_tokens > 0);
+        assert_eq!(
+            result[0].data["content_block"]["type"],
+            "web_search_tool_result"
+        );
+        let delta = &result[result.len() - 2];
+        assert_eq!(delta.event, "message_delta");
+        assert_eq!(
+            delta.data["usage"]["server_tool_use"]["web_search_requests"],
+            1
+        );
+        assert_eq!(result.last().unwrap().event, "message_stop");
+    }
+
+    #[test]
+    fn test_websearch_message_non_stream() {
+        let results = WebSearchResults {
+            results: vec![WebSearchResult {
+                title: "Rust".to_string(),
+                url: "https://www.rust-lang.org".to_string(),
+                snippet: Some("A language".to_string()),
+                published_date: None,
+                id: None,
+                domain: None,
+                max_verbatim_word_limit: None,
+                public_domain: None,
+            }],
+            total_results: Some(1),
+            query: Some("rust".to_string()),
+            error: None,
+        };
+        let (message, output_tokens) = websearch_message(
+            "msg_1",
+            "claude-sonnet-4",
+            "rust",
+            "srvtoolu_1",
+            Some(results),
+            10,
+        );
+
+        let types: Vec<_> = message["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "server_tool_use",
+                "web_search_tool_result",
+                "text",
+                "text",
+                "text"
+            ]
+        );
+        assert_eq!(
+            message["content"][3]["citations"][0]["cited_text"],
+            "A language"
+        );
+        assert_eq!(message["content"][0]["input"]["query"], "rust");
+        assert_eq!(
+            message["content"][1]["content"][0]["url"],
+            "https://www.rust-lang.org"
+        );
+        assert_eq!(message["stop_reason"], "end_turn");
+        assert_eq!(message["usage"]["input_tokens"], 10);
+        assert_eq!(message["usage"]["output_tokens"], output_tokens);
+    }
+
+    #[test]
+    fn test_strip_web_search_tools() {
+        use crate::anthropic::types::Message;
+
+        let tool = |name: &str, tool_type: Option<&str>| Tool {
+            tool_type: tool_type.map(str::to_string),
+            name: name.to_string(),
+            description: String::new(),
+            input_schema: Default::default(),
+            max_uses: None,
+            cache_control: None,
+        };
+        let mut req = MessagesRequest {
+            model: "claude-sonnet-4".to_string(),
+            max_tokens: 1024,
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: serde_json::json!("test"),
+            }],
+            stream: false,
+            system: None,
+            tools: Some(vec![
+                tool("web_search", Some("web_search_20250305")),
+                tool("read", None),
+            ]),
+            tool_choice: Some(json!({"type": "tool", "name": "web_search"})),
+            thinking: None,
+            metadata: None,
+            stop_sequences: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+        };
+
+        assert!(contains_web_search_tool(&req));
+        assert_eq!(strip_web_search_tools(&mut req), 1);
+        assert!(!contains_web_search_tool(&req));
+        assert_eq!(req.tools.as_ref().unwrap()[0].name, "read");
+        assert!(req.tool_choice.is_none());
+
+        req.tools = Some(vec![tool("web_search", None)]);
+        assert_eq!(strip_web_search_tools(&mut req), 1);
+        assert!(req.tools.is_none());
+    }
+
+    #[test]
+    fn test_resolve_mode_key_override() {
+        use axum::http::HeaderValue;
+
+        let config = Config {
+            websearch_mode: WebSearchMode::Reject,
+            websearch_key_overrides: [("trusted-key".to_string(), WebSearchMode::Intercept)]
+                .into_iter()
+                .collect(),
+            ..Config::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert_eq!(resolve_mode(&config, &headers), WebSearchMode::Reject);
+
+        headers.insert("x-api-key", HeaderValue::from_static("trusted-key"));
+        assert_eq!(resolve_mode(&config, &headers), WebSearchMode::Intercept);
 
-        assert!(summary.contains("Test Result"));
-        assert!(summary.contains("https://example.com"));
-        assert!(summary.contains("This is a test snippet"));
+        headers.insert("x-api-key", HeaderValue::from_static("other-key"));
+        assert_eq!(resolve_mode(&config, &headers), WebSearchMode::Reject);
     }
 }
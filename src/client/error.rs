@@ -0,0 +1,88 @@
+//! 客户端错误类型
+
+use std::fmt;
+
+use serde::Deserialize;
+
+/// 客户端调用结果
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// 客户端错误
+#[derive(Debug)]
+pub enum ClientError {
+    /// 网络错误或响应体无法解析
+    Http(reqwest::Error),
+    /// 代理返回的错误响应
+    Api {
+        /// HTTP 状态码
+        status: u16,
+        /// 错误类型（如 `invalid_request_error`、`rate_limit_error`）
+        error_type: String,
+        /// 错误消息
+        message: String,
+    },
+    /// 流式事件的 data 不是合法 JSON
+    Decode(serde_json::Error),
+    /// 调用 Admin API 前未设置 Admin API Key
+    MissingAdminKey,
+}
+
+impl ClientError {
+    /// 由错误响应构造，响应体不是标准错误格式时原样作为消息
+    pub(crate) fn from_response(status: u16, body: &str) -> Self {
+        #[derive(Deserialize)]
+        struct Envelope {
+            error: Detail,
+        }
+        #[derive(Deserialize)]
+        struct Detail {
+            #[serde(rename = "type")]
+            error_type: String,
+            message: String,
+        }
+
+        match serde_json::from_str::<Envelope>(body) {
+            Ok(envelope) => ClientError::Api {
+                status,
+                error_type: envelope.error.error_type,
+                message: envelope.error.message,
+            },
+            Err(_) => ClientError::Api {
+                status,
+                error_type: "unknown".to_string(),
+                message: body.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "请求失败: {}", e),
+            ClientError::Api {
+                status,
+                error_type,
+                message,
+            } => write!(f, "API 错误 {} ({}): {}", status, error_type, message),
+            ClientError::Decode(e) => write!(f, "无法解析流式事件: {}", e),
+            ClientError::MissingAdminKey => write!(f, "未设置 Admin API Key"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Http(e) => Some(e),
+            ClientError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
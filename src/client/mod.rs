@@ -0,0 +1,374 @@
+//! kiro-rs 代理 API 的类型化异步客户端（`client` feature）
+//!
+//! 覆盖 Messages API（含流式）、count_tokens、请求取消与常用 Admin API：
+//!
+//! ```no_run
+//! use kiro_rs::client::{KiroClient, Message, MessagesRequest};
+//!
+//! # async fn run() -> kiro_rs::client::Result<()> {
+//! let client = KiroClient::new("http://127.0.0.1:8990", "sk-your-api-key");
+//! let request = MessagesRequest::new("claude-sonnet-4-5", 1024, vec![Message::user("你好")]);
+//! let response = client.messages(&request).await?;
+//! println!("{}", response.text());
+//! # Ok(())
+//! # }
+//! ```
+
+mod error;
+mod types;
+
+pub use error::{ClientError, Result};
+pub use types::*;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt, future, stream};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+/// 请求 ID 请求头（与代理的 `x-kiro-request-id` 一致）
+const REQUEST_ID_HEADER: &str = "x-kiro-request-id";
+
+/// kiro-rs 代理客户端
+///
+/// 内部持有 `reqwest::Client`，克隆开销很小，可在任务间共享
+#[derive(Debug, Clone)]
+pub struct KiroClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    admin_key: Option<String>,
+}
+
+impl KiroClient {
+    /// `base_url` 为代理地址（如 `http://127.0.0.1:8990`），`api_key` 为客户端 API Key
+    pub fn new(base_url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: api_key.into(),
+            admin_key: None,
+        }
+    }
+
+    /// 设置 Admin API Key（调用 Admin API 时需要）
+    pub fn with_admin_key(mut self, admin_key: impl Into<String>) -> Self {
+        self.admin_key = Some(admin_key.into());
+        self
+    }
+
+    /// 使用自定义的 `reqwest::Client`（超时、代理等）
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    // === Messages API ===
+
+    /// 发送非流式消息请求
+    pub async fn messages(&self, request: &MessagesRequest) -> Result<MessagesResponse> {
+        let response = self
+            .post("/v1/messages", &self.api_key)
+            .json(request)
+            .send()
+            .await?;
+        json_body(response).await
+    }
+
+    /// 以指定的请求 ID 发送非流式消息请求，便于之后通过 [`cancel_message`](Self::cancel_message) 取消
+    pub async fn messages_with_id(
+        &self,
+        request: &MessagesRequest,
+        request_id: &str,
+    ) -> Result<MessagesResponse> {
+        let response = self
+            .post("/v1/messages", &self.api_key)
+            .header(REQUEST_ID_HEADER, request_id)
+            .json(request)
+            .send()
+            .await?;
+        json_body(response).await
+    }
+
+    /// 发送流式消息请求，返回 SSE 事件流
+    ///
+    /// 流中的 `error` 事件原样返回，由调用方决定如何处理
+    pub async fn messages_stream(
+        &self,
+        request: &MessagesRequest,
+    ) -> Result<impl Stream<Item = Result<StreamEvent>> + Send + use<>> {
+        #[derive(Serialize)]
+        struct Streaming<'a> {
+            #[serde(flatten)]
+            request: &'a MessagesRequest,
+            stream: bool,
+        }
+
+        let response = self
+            .post("/v1/messages", &self.api_key)
+            .json(&Streaming {
+                request,
+                stream: true,
+            })
+            .send()
+            .await?;
+        let response = check_status(response).await?;
+        Ok(sse_events(response.bytes_stream()))
+    }
+
+    /// 估算请求的输入 tokens
+    pub async fn count_tokens(&self, request: &MessagesRequest) -> Result<u32> {
+        let response = self
+            .post("/v1/messages/count_tokens", &self.api_key)
+            .json(request)
+            .send()
+            .await?;
+        let count: CountTokensResponse = json_body(response).await?;
+        Ok(count.input_tokens)
+    }
+
+    /// 取消进行中的请求（`DELETE /v1/messages/{id}`）
+    pub async fn cancel_message(&self, request_id: &str) -> Result<()> {
+        let url = format!("{}/v1/messages/{}", self.base_url, request_id);
+        let response = self
+            .http
+            .delete(url)
+            .header("x-api-key", &self.api_key)
+            .send()
+            .await?;
+        check_status(response).await.map(drop)
+    }
+
+    // === Admin API ===
+
+    /// 获取所有凭据状态
+    pub async fn credentials(&self) -> Result<CredentialsStatus> {
+        let url = format!("{}/api/admin/credentials", self.base_url);
+        let response = self
+            .http
+            .get(url)
+            .header("x-api-key", self.admin_key()?)
+            .send()
+            .await?;
+        json_body(response).await
+    }
+
+    /// 设置凭据禁用状态
+    pub async fn set_credential_disabled(&self, id: u64, disabled: bool) -> Result<()> {
+        self.admin_post(id, "disabled", json!({ "disabled": disabled }))
+            .await
+    }
+
+    /// 设置凭据优先级（数字越小优先级越高）
+    pub async fn set_credential_priority(&self, id: u64, priority: u32) -> Result<()> {
+        self.admin_post(id, "priority", json!({ "priority": priority }))
+            .await
+    }
+
+    /// 重置凭据失败计数并重新启用
+    pub async fn reset_credential(&self, id: u64) -> Result<()> {
+        self.admin_post(id, "reset", json!({})).await
+    }
+
+    /// 查询凭据余额
+    pub async fn credential_balance(&self, id: u64) -> Result<Balance> {
+        let url = format!("{}/api/admin/credentials/{}/balance", self.base_url, id);
+        let response = self
+            .http
+            .get(url)
+            .header("x-api-key", self.admin_key()?)
+            .send()
+            .await?;
+        json_body(response).await
+    }
+
+    fn admin_key(&self) -> Result<&str> {
+        self.admin_key
+            .as_deref()
+            .ok_or(ClientError::MissingAdminKey)
+    }
+
+    fn post(&self, path: &str, key: &str) -> reqwest::RequestBuilder {
+        self.http
+            .post(format!("{}{}", self.base_url, path))
+            .header("x-api-key", key)
+    }
+
+    async fn admin_post(&self, id: u64, action: &str, body: serde_json::Value) -> Result<()> {
+        let path = format!("/api/admin/credentials/{}/{}", id, action);
+        let response = self
+            .post(&path, self.admin_key()?)
+            .json(&body)
+            .send()
+            .await?;
+        check_status(response).await.map(drop)
+    }
+}
+
+/// 非 2xx 响应转换为 [`ClientError::Api`]
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(ClientError::from_response(status.as_u16(), &body))
+}
+
+async fn json_body<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    Ok(check_status(response).await?.json().await?)
+}
+
+/// 将响应体字节流解析为 SSE 事件流（注释行与没有 data 的事件会被跳过）
+fn sse_events<S>(body: S) -> impl Stream<Item = Result<StreamEvent>> + Send + use<S>
+where
+    S: Stream<Item = reqwest::Result<Bytes>> + Send,
+{
+    body.scan(Vec::new(), |buffer: &mut Vec<u8>, chunk| {
+        let events = match chunk {
+            Ok(chunk) => {
+                buffer.extend_from_slice(&chunk);
+                drain_events(buffer)
+            }
+            Err(e) => vec![Err(e.into())],
+        };
+        future::ready(Some(stream::iter(events)))
+    })
+    .flatten()
+}
+
+/// 取出缓冲区中所有完整的事件（以空行结尾）
+fn drain_events(buffer: &mut Vec<u8>) -> Vec<Result<StreamEvent>> {
+    let mut events = Vec::new();
+    while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+        let block: Vec<u8> = buffer.drain(..end + 2).collect();
+        let block = String::from_utf8_lossy(&block);
+
+        let mut event = String::from("message");
+        let mut data = Vec::new();
+        for line in block.lines() {
+            if let Some(value) = line.strip_prefix("event:") {
+                event = value.trim().to_string();
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data.push(value.trim_start());
+            }
+        }
+        if data.is_empty() {
+            continue;
+        }
+        events.push(
+            serde_json::from_str(&data.join("\n"))
+                .map(|data| StreamEvent { event, data })
+                .map_err(ClientError::Decode),
+        );
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        http::StatusCode,
+        response::IntoResponse,
+        routing::{get, post},
+    };
+
+    #[test]
+    fn test_drain_events_handles_partial_and_comments() {
+        let mut buffer = b": kiro-failover switches=1\n\nevent: ping\ndata: {\"type\":\"ping\"}\n\nevent: message_st"
+            .to_vec();
+        let events = drain_events(&mut buffer);
+        assert_eq!(events.len(), 1);
+        let event = events.into_iter().next().unwrap().unwrap();
+        assert_eq!(event.event, "ping");
+        assert_eq!(event.data["type"], "ping");
+        assert_eq!(buffer, b"event: message_st");
+
+        buffer.extend_from_slice(b"art\ndata: {}\n\n");
+        let events = drain_events(&mut buffer);
+        assert_eq!(events[0].as_ref().unwrap().event, "message_start");
+        assert!(buffer.is_empty());
+    }
+
+    /// 启动一个模拟代理，返回其地址
+    async fn mock_server() -> String {
+        let app = Router::new()
+            .route(
+                "/v1/messages",
+                post(|| async {
+                    Json(json!({
+                        "id": "msg_1",
+                        "type": "message",
+                        "model": "claude-sonnet-4-5",
+                        "role": "assistant",
+                        "content": [
+                            {"type": "thinking", "thinking": "..."},
+                            {"type": "text", "text": "你好"},
+                            {"type": "redacted_thinking", "data": "EmwKAhgB"},
+                            {"type": "server_tool_use"}
+                        ],
+                        "stop_reason": "end_turn",
+                        "usage": {"input_tokens": 5, "output_tokens": 2}
+                    }))
+                }),
+            )
+            .route(
+                "/v1/messages/count_tokens",
+                post(|| async {
+                    (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        Json(
+                            json!({"error": {"type": "rate_limit_error", "message": "slow down"}}),
+                        ),
+                    )
+                        .into_response()
+                }),
+            )
+            .route(
+                "/api/admin/credentials",
+                get(|| async { StatusCode::UNAUTHORIZED }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}/", addr)
+    }
+
+    #[tokio::test]
+    async fn test_client_messages_and_errors() {
+        let client = KiroClient::new(mock_server().await, "sk-test");
+        let request = MessagesRequest::new("claude-sonnet-4-5", 16, vec![Message::user("hi")]);
+
+        let response = client.messages(&request).await.unwrap();
+        assert_eq!(response.text(), "你好");
+        // redacted_thinking 可原样回传
+        assert_eq!(
+            serde_json::to_value(&response.content[2]).unwrap(),
+            json!({"type": "redacted_thinking", "data": "EmwKAhgB"})
+        );
+        assert_eq!(response.content[3], ContentBlock::Unknown);
+        assert_eq!(response.usage.output_tokens, 2);
+
+        match client.count_tokens(&request).await {
+            Err(ClientError::Api {
+                status, error_type, ..
+            }) => {
+                assert_eq!(status, 429);
+                assert_eq!(error_type, "rate_limit_error");
+            }
+            other => panic!("应返回 API 错误: {:?}", other),
+        }
+
+        assert!(matches!(
+            client.credentials().await,
+            Err(ClientError::MissingAdminKey)
+        ));
+        let admin = client.with_admin_key("admin");
+        assert!(matches!(
+            admin.credentials().await,
+            Err(ClientError::Api { status: 401, .. })
+        ));
+    }
+}
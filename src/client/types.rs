@@ -0,0 +1,269 @@
+//! 客户端请求与响应类型
+//!
+//! 只覆盖常用字段；响应中未声明的字段会被忽略，便于与更新版本的代理兼容
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// === Messages API ===
+
+/// `POST /v1/messages` 请求
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MessagesRequest {
+    pub model: String,
+    pub max_tokens: u32,
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<Thinking>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+}
+
+impl MessagesRequest {
+    pub fn new(model: impl Into<String>, max_tokens: u32, messages: Vec<Message>) -> Self {
+        Self {
+            model: model.into(),
+            max_tokens,
+            messages,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    pub fn with_tools(mut self, tools: Vec<Tool>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// 启用 thinking，`budget_tokens` 为思考预算
+    pub fn with_thinking(mut self, budget_tokens: u32) -> Self {
+        self.thinking = Some(Thinking {
+            thinking_type: "enabled".to_string(),
+            budget_tokens,
+        });
+        self
+    }
+
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequences = stop_sequences;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+}
+
+/// 对话角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// 对话消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: Vec<ContentBlock>,
+}
+
+impl Message {
+    /// 纯文本的用户消息
+    pub fn user(text: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: vec![ContentBlock::text(text)],
+        }
+    }
+
+    /// 纯文本的助手消息
+    pub fn assistant(text: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: vec![ContentBlock::text(text)],
+        }
+    }
+}
+
+/// 内容块
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    /// 加密的 thinking 内容，多轮对话时需原样回传
+    RedactedThinking {
+        data: String,
+    },
+    /// 客户端未声明的内容块类型
+    #[serde(other)]
+    Unknown,
+}
+
+impl ContentBlock {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentBlock::Text { text: text.into() }
+    }
+}
+
+/// 图片来源（base64 编码）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageSource {
+    #[serde(rename = "type")]
+    pub source_type: String,
+    pub media_type: String,
+    pub data: String,
+}
+
+impl ImageSource {
+    /// `media_type` 如 `image/png`，`data` 为 base64 编码的图片数据
+    pub fn base64(media_type: impl Into<String>, data: impl Into<String>) -> Self {
+        Self {
+            source_type: "base64".to_string(),
+            media_type: media_type.into(),
+            data: data.into(),
+        }
+    }
+}
+
+/// 工具定义
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Tool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// 参数的 JSON Schema
+    pub input_schema: Value,
+}
+
+/// thinking 配置
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Thinking {
+    #[serde(rename = "type")]
+    pub thinking_type: String,
+    pub budget_tokens: u32,
+}
+
+/// `POST /v1/messages` 非流式响应
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagesResponse {
+    pub id: String,
+    pub model: String,
+    pub role: Role,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<String>,
+    pub usage: Usage,
+}
+
+impl MessagesResponse {
+    /// 拼接所有文本块
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Token 用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+/// 流式响应中的一个 SSE 事件
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEvent {
+    /// 事件名（如 `message_start`、`content_block_delta`）
+    pub event: String,
+    /// 事件数据
+    pub data: Value,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CountTokensResponse {
+    pub input_tokens: u32,
+}
+
+// === Admin API ===
+
+/// `GET /api/admin/credentials` 响应
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsStatus {
+    pub total: usize,
+    pub available: usize,
+    pub current_id: u64,
+    pub credentials: Vec<CredentialStatus>,
+}
+
+/// 单个凭据的状态
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialStatus {
+    pub id: u64,
+    pub priority: u32,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub disabled: bool,
+    pub failure_count: u32,
+    pub is_current: bool,
+    pub expires_at: Option<String>,
+    pub auth_method: Option<String>,
+    pub active_connections: u32,
+    pub max_concurrent: u32,
+    pub degraded_reason: Option<String>,
+    /// 熔断器状态：`closed` / `open` / `half_open`
+    pub breaker_state: Option<String>,
+}
+
+/// `GET /api/admin/credentials/:id/balance` 响应
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Balance {
+    pub id: u64,
+    pub subscription_title: Option<String>,
+    pub current_usage: f64,
+    pub usage_limit: f64,
+    pub remaining: f64,
+    pub usage_percentage: f64,
+    /// 下次重置时间（Unix 时间戳）
+    pub next_reset_at: Option<f64>,
+}
@@ -0,0 +1,348 @@
+//! 全局并发请求上限
+//!
+//! 同时处理的请求数超过 `maxInFlight` 时，新请求按客户端 Key 的优先级排队等待，
+//! 同一优先级内按到达顺序（FIFO）处理；有请求完成时由最高优先级队列的队首获得许可。
+//! 队列已满或排队超过 `queueTimeoutMs` 时拒绝，并根据近期请求的平均占用时长估算重试等待时间：
+//! - 低优先级请求最多占用一半的排队名额，更早被拒绝
+//! - 队列已满时，高、普通优先级的请求挤出最晚排队的低优先级请求
+//!
+//! 许可随响应体一同释放，流式响应输出期间同样计入并发
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::{body::Body, response::Response};
+use futures::Stream;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+use crate::common::metrics;
+use crate::model::config::{AdmissionConfig, ClientPriority};
+
+/// 请求占用时长均值（EWMA）的平滑系数
+const HOLD_EWMA_ALPHA: f64 = 0.1;
+
+/// 尚无样本时假定的请求占用时长
+const DEFAULT_HOLD: Duration = Duration::from_secs(1);
+
+/// 全局并发控制
+pub struct AdmissionControl {
+    max_in_flight: usize,
+    max_queue: usize,
+    queue_timeout: Duration,
+    state: Mutex<AdmissionState>,
+    /// 请求占用许可的平均时长（毫秒）
+    hold_ewma_ms: Mutex<Option<f64>>,
+}
+
+#[derive(Default)]
+struct AdmissionState {
+    /// 已发放的许可数
+    in_flight: usize,
+    /// 按优先级（从高到低）划分的等待队列
+    queues: [VecDeque<Waiter>; 3],
+    next_waiter_id: u64,
+}
+
+impl AdmissionState {
+    fn queued(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+}
+
+/// 排队中的请求，收到 `true` 表示获得许可，`false` 表示被更高优先级的请求挤出
+struct Waiter {
+    id: u64,
+    tx: oneshot::Sender<bool>,
+}
+
+impl AdmissionControl {
+    pub fn new(config: &AdmissionConfig) -> Self {
+        Self {
+            max_in_flight: config.max_in_flight.max(1),
+            max_queue: config.max_queue,
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            state: Mutex::new(AdmissionState::default()),
+            hold_ewma_ms: Mutex::new(None),
+        }
+    }
+
+    /// 获取处理许可，必要时按优先级排队等待
+    ///
+    /// 队列已满、被挤出或排队超时时返回建议的重试等待时间
+    pub async fn acquire(
+        self: &Arc<Self>,
+        priority: ClientPriority,
+    ) -> Result<AdmissionPermit, Duration> {
+        let mut queued = {
+            let mut state = self.state.lock();
+            if state.in_flight < self.max_in_flight {
+                state.in_flight += 1;
+                return Ok(self.permit());
+            }
+            if !self.make_room(&mut state, priority) {
+                metrics::REQUESTS_SHED.inc_by(1);
+                tracing::warn!(
+                    "并发请求数已达上限（{}）且排队已满（{}），拒绝 {:?} 优先级请求",
+                    self.max_in_flight,
+                    self.max_queue,
+                    priority
+                );
+                return Err(self.retry_after(&state));
+            }
+            let (tx, rx) = oneshot::channel();
+            let id = state.next_waiter_id;
+            state.next_waiter_id += 1;
+            state.queues[priority.rank()].push_back(Waiter { id, tx });
+            Queued {
+                control: self,
+                priority,
+                id,
+                rx,
+            }
+        };
+
+        // 超时或客户端断开（future 被丢弃）时由 Queued 退出队列
+        let outcome = tokio::time::timeout(self.queue_timeout, &mut queued.rx).await;
+        if let Ok(Ok(true)) = outcome {
+            return Ok(self.permit());
+        }
+        drop(queued);
+
+        metrics::REQUESTS_SHED.inc_by(1);
+        match outcome {
+            Err(_) => tracing::warn!(
+                "请求排队超过 {} ms 仍未获得处理许可，拒绝请求",
+                self.queue_timeout.as_millis()
+            ),
+            _ => tracing::warn!("排队中的低优先级请求被更高优先级的请求挤出"),
+        }
+        Err(self.retry_after(&self.state.lock()))
+    }
+
+    /// 检查是否还有排队名额，必要时挤出最晚排队的低优先级请求
+    fn make_room(&self, state: &mut AdmissionState, priority: ClientPriority) -> bool {
+        let limit = match priority {
+            ClientPriority::Low => self.max_queue / 2,
+            _ => self.max_queue,
+        };
+        if state.queued() < limit {
+            return true;
+        }
+        if priority == ClientPriority::Low {
+            return false;
+        }
+        match state.queues[ClientPriority::Low.rank()].pop_back() {
+            Some(evicted) => {
+                let _ = evicted.tx.send(false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn permit(self: &Arc<Self>) -> AdmissionPermit {
+        AdmissionPermit {
+            control: Arc::clone(self),
+            started: Instant::now(),
+        }
+    }
+
+    /// 释放一个许可：优先转交给最高优先级队列的队首，无人排队时归还
+    fn release(&self, state: &mut AdmissionState) {
+        for queue in state.queues.iter_mut() {
+            while let Some(waiter) = queue.pop_front() {
+                if waiter.tx.send(true).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.in_flight -= 1;
+    }
+
+    /// 估算排队中的请求全部获得许可所需的时间
+    fn retry_after(&self, state: &AdmissionState) -> Duration {
+        let hold_ms = self
+            .hold_ewma_ms
+            .lock()
+            .unwrap_or(DEFAULT_HOLD.as_millis() as f64);
+        let waiting = state.queued() + 1;
+        Duration::from_secs_f64(hold_ms * waiting as f64 / self.max_in_flight as f64 / 1000.0)
+    }
+
+    fn record_hold(&self, elapsed: Duration) {
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+        let mut ewma = self.hold_ewma_ms.lock();
+        *ewma = Some(match *ewma {
+            Some(avg) => avg + HOLD_EWMA_ALPHA * (elapsed_ms - avg),
+            None => elapsed_ms,
+        });
+    }
+}
+
+/// 排队中的请求，释放时退出队列
+///
+/// 已被转交许可但未及取走（超时与转交同时发生，或 future 被丢弃）时归还该许可
+struct Queued<'a> {
+    control: &'a AdmissionControl,
+    priority: ClientPriority,
+    id: u64,
+    rx: oneshot::Receiver<bool>,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let mut state = self.control.state.lock();
+        let queue = &mut state.queues[self.priority.rank()];
+        if let Some(index) = queue.iter().position(|w| w.id == self.id) {
+            queue.remove(index);
+        } else if let Ok(true) = self.rx.try_recv() {
+            self.control.release(&mut state);
+        }
+    }
+}
+
+/// 处理许可，释放时记录占用时长
+pub struct AdmissionPermit {
+    control: Arc<AdmissionControl>,
+    started: Instant,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.control.record_hold(self.started.elapsed());
+        self.control.release(&mut self.control.state.lock());
+    }
+}
+
+impl AdmissionPermit {
+    /// 将许可绑定到响应体，响应体结束或客户端断开时释放
+    pub fn hold(self, response: Response) -> Response {
+        let (parts, body) = response.into_parts();
+        let body = Body::from_stream(PermitBody {
+            inner: Box::pin(body.into_data_stream()),
+            _permit: self,
+        });
+        Response::from_parts(parts, body)
+    }
+}
+
+/// 持有处理许可的响应体流
+struct PermitBody<S> {
+    inner: Pin<Box<S>>,
+    _permit: AdmissionPermit,
+}
+
+impl<S: Stream> Stream for PermitBody<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().inner.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(
+        max_in_flight: usize,
+        max_queue: usize,
+        queue_timeout_ms: u64,
+    ) -> Arc<AdmissionControl> {
+        Arc::new(AdmissionControl::new(&AdmissionConfig {
+            max_in_flight,
+            max_queue,
+            queue_timeout_ms,
+        }))
+    }
+
+    fn queued(control: &AdmissionControl) -> usize {
+        control.state.lock().queued()
+    }
+
+    /// 在后台排队获取许可，获得后记录标签并立即释放
+    fn spawn_waiter(
+        control: &Arc<AdmissionControl>,
+        priority: ClientPriority,
+        label: &'static str,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> tokio::task::JoinHandle<bool> {
+        let (control, order) = (control.clone(), order.clone());
+        tokio::spawn(async move {
+            let admitted = control.acquire(priority).await.is_ok();
+            if admitted {
+                order.lock().push(label);
+            }
+            admitted
+        })
+    }
+
+    async fn wait_queued(control: &AdmissionControl, n: usize) {
+        while queued(control) < n {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_bounded_and_fifo() {
+        let control = control(1, 2, 1000);
+        let first = control.acquire(ClientPriority::Normal).await.unwrap();
+
+        // 两个请求按顺序排队，第三个因队列已满被拒绝
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let a = spawn_waiter(&control, ClientPriority::Normal, "a", &order);
+        wait_queued(&control, 1).await;
+        let b = spawn_waiter(&control, ClientPriority::Normal, "b", &order);
+        wait_queued(&control, 2).await;
+        assert!(control.acquire(ClientPriority::Normal).await.is_err());
+
+        drop(first);
+        assert!(a.await.unwrap() && b.await.unwrap());
+        assert_eq!(*order.lock(), vec!["a", "b"]);
+        let state = control.state.lock();
+        assert_eq!((state.in_flight, state.queued()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_priority_order_and_low_priority_shed_first() {
+        let control = control(1, 2, 1000);
+        let first = control.acquire(ClientPriority::Normal).await.unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // 低优先级最多占用一半排队名额
+        let low = spawn_waiter(&control, ClientPriority::Low, "low", &order);
+        wait_queued(&control, 1).await;
+        assert!(control.acquire(ClientPriority::Low).await.is_err());
+        let normal = spawn_waiter(&control, ClientPriority::Normal, "normal", &order);
+        wait_queued(&control, 2).await;
+
+        // 队列已满：高优先级请求挤出排队中的低优先级请求
+        let high = spawn_waiter(&control, ClientPriority::High, "high", &order);
+        assert!(!low.await.unwrap());
+        wait_queued(&control, 2).await;
+
+        drop(first);
+        assert!(high.await.unwrap() && normal.await.unwrap());
+        assert_eq!(*order.lock(), vec!["high", "normal"]);
+        assert_eq!(control.state.lock().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout_rejects_with_estimate() {
+        let control = control(2, 10, 20);
+        let _held = (
+            control.acquire(ClientPriority::Normal).await.unwrap(),
+            control.acquire(ClientPriority::Normal).await.unwrap(),
+        );
+        control.record_hold(Duration::from_secs(8));
+
+        // 等待超时后拒绝：平均占用 8 秒、2 个并发，1 个排队请求约需 4 秒
+        let wait = control.acquire(ClientPriority::High).await.err().unwrap();
+        assert_eq!(wait, Duration::from_secs(4));
+        assert_eq!(queued(&control), 0);
+    }
+}
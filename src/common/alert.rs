@@ -0,0 +1,140 @@
+//! Webhook 告警模块
+//!
+//! 将需要运维关注的事件（上游维护、客户端版本过低、凭据余额不足等）推送到配置的 Webhook
+//! 同一事件键在冷却时间内只发送一次，避免告警风暴
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::http_client::{ProxyConfig, build_client};
+use crate::model::config::TlsBackend;
+
+/// 同一事件键的告警冷却时间
+const ALERT_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Webhook 请求超时（秒）
+const ALERT_TIMEOUT_SECS: u64 = 10;
+
+/// 告警配置
+#[derive(Clone, Default)]
+pub struct AlertConfig {
+    /// Webhook 地址（未配置时不发送告警）
+    pub webhook_url: Option<String>,
+    /// 代理配置
+    pub proxy: Option<ProxyConfig>,
+
+    pub tls_backend: TlsBackend,
+}
+
+/// 告警负载
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AlertPayload<'a> {
+    /// 事件类型
+    event: &'a str,
+    /// 可读的告警信息
+    message: &'a str,
+    /// 附加信息
+    details: serde_json::Value,
+    /// 事件时间（RFC3339）
+    timestamp: String,
+}
+
+/// 全局配置存储
+static ALERT_CONFIG: OnceLock<AlertConfig> = OnceLock::new();
+
+/// 最近一次发送时间（按事件键）
+static LAST_SENT: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+
+/// 初始化告警配置
+///
+/// 应在应用启动时调用一次
+pub fn init_config(config: AlertConfig) {
+    let _ = ALERT_CONFIG.set(config);
+}
+
+/// 检查并记录冷却状态，返回是否允许发送
+fn should_send(key: &str) -> bool {
+    let mut last_sent = LAST_SENT.get_or_init(|| Mutex::new(HashMap::new())).lock();
+    let now = Instant::now();
+    match last_sent.get(key) {
+        Some(at) if now.duration_since(*at) < ALERT_COOLDOWN => false,
+        _ => {
+            last_sent.insert(key.to_string(), now);
+            true
+        }
+    }
+}
+
+/// 发送告警（异步后台发送，不阻塞调用方）
+///
+/// # Arguments
+/// * `event` - 事件类型（如 `upstream_maintenance`）
+/// * `key` - 冷却去重键（如 `upstream_maintenance:1`）
+/// * `message` - 可读的告警信息
+/// * `details` - 附加信息
+pub fn notify(event: &str, key: &str, message: String, details: serde_json::Value) {
+    let Some(config) = ALERT_CONFIG.get() else {
+        return;
+    };
+    let Some(url) = config.webhook_url.clone().filter(|u| !u.trim().is_empty()) else {
+        return;
+    };
+    if !should_send(key) {
+        tracing::debug!("告警 {} 处于冷却期，跳过发送", key);
+        return;
+    }
+    // 非 tokio 运行时环境（如单元测试）下直接跳过
+    let Ok(handle) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+
+    let event = event.to_string();
+    let proxy = config.proxy.clone();
+    let tls_backend = config.tls_backend;
+
+    handle.spawn(async move {
+        let payload = AlertPayload {
+            event: &event,
+            message: &message,
+            details,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        };
+
+        let client = match build_client(proxy.as_ref(), ALERT_TIMEOUT_SECS, tls_backend) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("创建告警 HTTP 客户端失败: {}", e);
+                return;
+            }
+        };
+
+        match client.post(&url).json(&payload).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                tracing::info!("已发送告警: {}", event);
+            }
+            Ok(resp) => {
+                tracing::warn!("告警 Webhook 返回非成功状态: {}", resp.status());
+            }
+            Err(e) => {
+                tracing::warn!("发送告警失败: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_send_cooldown() {
+        assert!(should_send("test:cooldown"));
+        assert!(!should_send("test:cooldown"));
+        assert!(should_send("test:cooldown-other"));
+    }
+}
@@ -0,0 +1,591 @@
+//! 客户端 API Key 注册表
+//!
+//! 除主 `apiKey` 外，可为每个下游调用方分配独立的 Key：
+//! - 配置文件 `apiKeys` 中的 Key 只读，修改需编辑配置并重启
+//! - 通过 Admin API 创建的 Key 保存在存储后端中，重启后仍然有效
+//!
+//! 认证通过后请求归属到对应 Key 的名称，用于日志、链路追踪、请求计数、速率限制与 token 预算
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::common::auth;
+use crate::common::rate_limit::KeyRateLimiter;
+use crate::model::config::{ClientApiKey, ClientPriority, Config, RateLimitConfig, TokenQuota};
+use crate::storage::Storage;
+use crate::storage::key_usage::{KeyUsage, KeyUsageTotals, QuotaExceeded};
+
+/// 主 `apiKey` 对应的名称
+pub const PRIMARY_KEY_NAME: &str = "default";
+
+/// 存储命名空间
+const NAMESPACE: &str = "api_keys";
+
+/// 所有 Admin 创建的 Key 以 JSON 数组整体保存在该键下
+const ENTRIES_KEY: &str = "entries";
+
+/// 名称最大长度
+const MAX_NAME_LEN: usize = 64;
+
+/// 自定义 Key 最小长度
+const MIN_KEY_LEN: usize = 16;
+
+/// Key 来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApiKeySource {
+    /// 配置文件（只读）
+    Config,
+    /// Admin API 创建
+    Admin,
+}
+
+/// 单个 Key 的状态快照（不含完整 Key）
+#[derive(Debug, Clone)]
+pub struct ApiKeySnapshot {
+    pub name: String,
+    pub key_preview: String,
+    pub source: ApiKeySource,
+    pub disabled: bool,
+    pub rate_limit: RateLimitConfig,
+    pub group: Option<String>,
+    pub priority: ClientPriority,
+    pub quota: TokenQuota,
+    pub mcp_tools: bool,
+    pub model_override: bool,
+    pub requests: u64,
+}
+
+/// 认证通过的客户端
+#[derive(Clone)]
+pub struct ClientKey {
+    /// Key 名称
+    pub name: String,
+    /// 该 Key 的速率限制器
+    pub limiter: Arc<KeyRateLimiter>,
+    /// 该 Key 绑定的凭据分组
+    pub group: Option<String>,
+    /// 该 Key 的优先级
+    pub priority: ClientPriority,
+    /// 该 Key 的 token 预算
+    pub quota: TokenQuota,
+    /// 是否允许使用服务端执行的 MCP 工具
+    pub mcp_tools: bool,
+    /// 是否允许使用模型覆盖请求头
+    pub model_override: bool,
+}
+
+struct Entry {
+    key: ClientApiKey,
+    source: ApiKeySource,
+    requests: AtomicU64,
+    limiter: Arc<KeyRateLimiter>,
+}
+
+impl Entry {
+    fn new(key: ClientApiKey, source: ApiKeySource) -> Arc<Self> {
+        Arc::new(Self {
+            limiter: Arc::new(KeyRateLimiter::new(&key.rate_limit)),
+            key,
+            source,
+            requests: AtomicU64::new(0),
+        })
+    }
+
+    /// 以新的 Key 设置替换，保留请求计数；速率限制未变化时沿用原限制器（保留桶状态）
+    fn replace(&self, key: ClientApiKey) -> Arc<Self> {
+        let limiter = if key.rate_limit == self.key.rate_limit {
+            self.limiter.clone()
+        } else {
+            Arc::new(KeyRateLimiter::new(&key.rate_limit))
+        };
+        Arc::new(Self {
+            key,
+            source: self.source,
+            requests: AtomicU64::new(self.requests.load(Ordering::Relaxed)),
+            limiter,
+        })
+    }
+}
+
+/// 客户端 API Key 注册表
+pub struct ApiKeyRegistry {
+    entries: RwLock<Vec<Arc<Entry>>>,
+    storage: Option<Arc<dyn Storage>>,
+    /// 各 Key 当前周期的 token 用量（未启用存储时不统计、不限制）
+    usage: Option<KeyUsage>,
+}
+
+impl ApiKeyRegistry {
+    /// 从配置与存储后端加载所有 Key
+    pub fn new(config: &Config, storage: Arc<dyn Storage>) -> Self {
+        let registry = Self::from_config(config);
+        match load_admin_keys(storage.as_ref()) {
+            Ok(keys) => {
+                let mut entries = registry.entries.write();
+                for key in keys {
+                    if entries.iter().any(|e| e.key.name == key.name) {
+                        tracing::warn!(
+                            "API Key 名称 {} 与配置重复，已忽略存储中的同名 Key",
+                            key.name
+                        );
+                        continue;
+                    }
+                    entries.push(Entry::new(key, ApiKeySource::Admin));
+                }
+            }
+            Err(e) => tracing::warn!("读取已保存的 API Key 失败: {}", e),
+        }
+        Self {
+            usage: Some(KeyUsage::new(storage.clone())),
+            storage: Some(storage),
+            ..registry
+        }
+    }
+
+    /// 仅从配置加载（不持久化 Admin 创建的 Key）
+    fn from_config(config: &Config) -> Self {
+        let primary = config
+            .api_key
+            .iter()
+            .filter(|k| !k.trim().is_empty())
+            .map(|k| ClientApiKey {
+                name: PRIMARY_KEY_NAME.to_string(),
+                key: k.clone(),
+                disabled: false,
+                rate_limit: RateLimitConfig::default(),
+                group: None,
+                priority: ClientPriority::default(),
+                quota: TokenQuota::default(),
+                // 主 Key 属于运维者本人，拥有全部权限
+                mcp_tools: true,
+                model_override: true,
+            });
+
+        let mut entries: Vec<Arc<Entry>> = Vec::new();
+        for key in primary.chain(config.api_keys.iter().cloned()) {
+            if key.key.trim().is_empty() {
+                tracing::warn!("API Key {} 的 key 为空，已忽略", key.name);
+                continue;
+            }
+            if entries.iter().any(|e| e.key.name == key.name) {
+                tracing::warn!("API Key 名称 {} 重复，已忽略", key.name);
+                continue;
+            }
+            entries.push(Entry::new(key, ApiKeySource::Config));
+        }
+
+        Self {
+            entries: RwLock::new(entries),
+            storage: None,
+            usage: None,
+        }
+    }
+
+    /// Key 总数（含已禁用）
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    /// 是否没有任何 Key
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().is_empty()
+    }
+
+    /// 验证 Key，成功时返回对应客户端（请求数在通过准入检查后由 [`Self::record_request`] 累计）
+    ///
+    /// 逐个进行常量时间比较，不因匹配位置提前返回
+    pub fn authenticate(&self, key: &str) -> Option<ClientKey> {
+        let entries = self.entries.read();
+        let mut matched = None;
+        for entry in entries.iter() {
+            if auth::constant_time_eq(key, &entry.key.key) && !entry.key.disabled {
+                matched = Some(entry);
+            }
+        }
+        matched.map(|entry| ClientKey {
+            name: entry.key.name.clone(),
+            limiter: entry.limiter.clone(),
+            group: entry.key.group.clone(),
+            priority: entry.key.priority,
+            quota: entry.key.quota,
+            mcp_tools: entry.key.mcp_tools,
+            model_override: entry.key.model_override,
+        })
+    }
+
+    /// 累计一次已放行（未被速率限制或 token 预算拒绝）的请求
+    pub fn record_request(&self, name: &str) {
+        if let Some(entry) = self.entries.read().iter().find(|e| e.key.name == name) {
+            entry.requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 所有 Key 的状态快照
+    pub fn snapshot(&self) -> Vec<ApiKeySnapshot> {
+        self.entries
+            .read()
+            .iter()
+            .map(|entry| ApiKeySnapshot {
+                name: entry.key.name.clone(),
+                key_preview: preview(&entry.key.key),
+                source: entry.source,
+                disabled: entry.key.disabled,
+                rate_limit: entry.key.rate_limit,
+                group: entry.key.group.clone(),
+                priority: entry.key.priority,
+                quota: entry.key.quota,
+                mcp_tools: entry.key.mcp_tools,
+                model_override: entry.key.model_override,
+                requests: entry.requests.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// 创建新 Key（`key` 为空时自动生成），返回完整的 Key
+    pub fn create(
+        &self,
+        name: &str,
+        key: Option<String>,
+        rate_limit: RateLimitConfig,
+        group: Option<String>,
+        priority: ClientPriority,
+        quota: TokenQuota,
+    ) -> anyhow::Result<ClientApiKey> {
+        let name = name.trim();
+        if name.is_empty()
+            || name.len() > MAX_NAME_LEN
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("名称无效：需为 1-{} 个字母、数字、- 或 _", MAX_NAME_LEN);
+        }
+        let key = match key {
+            Some(key) => {
+                let key = key.trim().to_string();
+                if key.len() < MIN_KEY_LEN || key.contains(char::is_whitespace) {
+                    anyhow::bail!("Key 无效：至少 {} 个字符且不能包含空白", MIN_KEY_LEN);
+                }
+                key
+            }
+            None => format!("sk-kiro-{}", Uuid::new_v4().simple()),
+        };
+
+        let mut entries = self.entries.write();
+        if entries.iter().any(|e| e.key.name == name) {
+            anyhow::bail!("名称 {} 已存在", name);
+        }
+        if entries
+            .iter()
+            .any(|e| auth::constant_time_eq(&key, &e.key.key))
+        {
+            anyhow::bail!("Key 已被其他名称使用");
+        }
+
+        let new_key = ClientApiKey {
+            name: name.to_string(),
+            key,
+            disabled: false,
+            rate_limit,
+            group: group
+                .map(|g| g.trim().to_string())
+                .filter(|g| !g.is_empty()),
+            priority,
+            quota,
+            mcp_tools: false,
+            model_override: false,
+        };
+        let mut updated = entries.clone();
+        updated.push(Entry::new(new_key.clone(), ApiKeySource::Admin));
+        self.persist(&updated)?;
+        *entries = updated;
+        tracing::info!("已创建 API Key: {}", name);
+        Ok(new_key)
+    }
+
+    /// 设置 Key 禁用状态
+    pub fn set_disabled(&self, name: &str, disabled: bool) -> anyhow::Result<()> {
+        self.update(name, |entries, index| {
+            let entry = &entries[index];
+            entries[index] = entry.replace(ClientApiKey {
+                disabled,
+                ..entry.key.clone()
+            });
+        })
+    }
+
+    /// 设置 Key 速率限制
+    pub fn set_rate_limit(&self, name: &str, rate_limit: RateLimitConfig) -> anyhow::Result<()> {
+        self.update(name, |entries, index| {
+            let entry = &entries[index];
+            entries[index] = entry.replace(ClientApiKey {
+                rate_limit,
+                ..entry.key.clone()
+            });
+        })
+    }
+
+    /// 设置 Key 的 token 预算
+    pub fn set_quota(&self, name: &str, quota: TokenQuota) -> anyhow::Result<()> {
+        self.update(name, |entries, index| {
+            let entry = &entries[index];
+            entries[index] = entry.replace(ClientApiKey {
+                quota,
+                ..entry.key.clone()
+            });
+        })
+    }
+
+    /// 检查客户端是否已用完 token 预算
+    pub async fn check_quota(&self, client: &ClientKey) -> Result<(), QuotaExceeded> {
+        match &self.usage {
+            Some(usage) => usage.check_async(&client.name, &client.quota).await,
+            None => Ok(()),
+        }
+    }
+
+    /// 查询 Key 的 token 预算与当前周期用量
+    pub fn usage(&self, name: &str) -> anyhow::Result<(TokenQuota, KeyUsageTotals)> {
+        let quota = self.find(name)?.key.quota;
+        let totals = match &self.usage {
+            Some(usage) => usage.get(name)?,
+            None => KeyUsageTotals::default(),
+        };
+        Ok((quota, totals))
+    }
+
+    /// 清零 Key 当前周期的 token 用量（配置文件中的 Key 同样适用）
+    pub fn reset_usage(&self, name: &str) -> anyhow::Result<()> {
+        self.find(name)?;
+        if let Some(usage) = &self.usage {
+            usage.reset(name)?;
+        }
+        tracing::info!("已清零 API Key {} 的 token 用量", name);
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> anyhow::Result<Arc<Entry>> {
+        self.entries
+            .read()
+            .iter()
+            .find(|e| e.key.name == name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("API Key 不存在: {}", name))
+    }
+
+    /// 删除 Key
+    pub fn delete(&self, name: &str) -> anyhow::Result<()> {
+        self.update(name, |entries, index| {
+            entries.remove(index);
+        })?;
+        tracing::info!("已删除 API Key: {}", name);
+        Ok(())
+    }
+
+    /// 修改 Admin 创建的 Key 并持久化
+    fn update(
+        &self,
+        name: &str,
+        apply: impl FnOnce(&mut Vec<Arc<Entry>>, usize),
+    ) -> anyhow::Result<()> {
+        let mut entries = self.entries.write();
+        let index = entries
+            .iter()
+            .position(|e| e.key.name == name)
+            .ok_or_else(|| anyhow::anyhow!("API Key 不存在: {}", name))?;
+        if entries[index].source == ApiKeySource::Config {
+            anyhow::bail!("API Key {} 来自配置文件，只读", name);
+        }
+
+        let mut updated = entries.clone();
+        apply(&mut updated, index);
+        self.persist(&updated)?;
+        *entries = updated;
+        Ok(())
+    }
+
+    /// 保存所有 Admin 创建的 Key
+    fn persist(&self, entries: &[Arc<Entry>]) -> anyhow::Result<()> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        let keys: Vec<&ClientApiKey> = entries
+            .iter()
+            .filter(|e| e.source == ApiKeySource::Admin)
+            .map(|e| &e.key)
+            .collect();
+        storage.put(NAMESPACE, ENTRIES_KEY, &serde_json::to_string(&keys)?, None)
+    }
+}
+
+/// 读取已保存的 Admin Key
+fn load_admin_keys(storage: &dyn Storage) -> anyhow::Result<Vec<ClientApiKey>> {
+    match storage.get(NAMESPACE, ENTRIES_KEY)? {
+        Some(json) => Ok(serde_json::from_str(&json)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Key 预览：保留前 8 个字符（不超过一半）
+fn preview(key: &str) -> String {
+    let visible = (key.chars().count() / 2).min(8);
+    format!("{}***", key.chars().take(visible).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn config() -> Config {
+        Config {
+            api_key: Some("sk-primary-key-000".to_string()),
+            api_keys: vec![ClientApiKey {
+                name: "team-a".to_string(),
+                key: "sk-team-a-key-000".to_string(),
+                disabled: false,
+                rate_limit: RateLimitConfig {
+                    requests_per_minute: Some(1),
+                    tokens_per_minute: None,
+                },
+                group: Some("work".to_string()),
+                priority: ClientPriority::High,
+                quota: TokenQuota::default(),
+                mcp_tools: false,
+                model_override: false,
+            }],
+            ..Config::default()
+        }
+    }
+
+    fn name(client: Option<ClientKey>) -> Option<String> {
+        client.map(|c| c.name)
+    }
+
+    #[test]
+    fn test_authenticate_attributes_to_name() {
+        let registry = ApiKeyRegistry::new(&config(), Arc::new(MemoryStorage::new()));
+        assert_eq!(registry.len(), 2);
+        assert_eq!(
+            name(registry.authenticate("sk-primary-key-000")),
+            Some(PRIMARY_KEY_NAME.to_string())
+        );
+        let client = registry.authenticate("sk-team-a-key-000").unwrap();
+        assert_eq!(client.name, "team-a");
+        assert_eq!(client.group.as_deref(), Some("work"));
+        assert_eq!(client.priority, ClientPriority::High);
+        assert!(client.limiter.check().is_ok());
+        assert!(client.limiter.check().is_err());
+        assert!(registry.authenticate("sk-unknown").is_none());
+
+        // 只有通过准入检查的请求才计数
+        assert_eq!(registry.snapshot()[1].requests, 0);
+        registry.record_request("team-a");
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot[1].requests, 1);
+        assert_eq!(snapshot[1].key_preview, "sk-team-***");
+    }
+
+    #[test]
+    fn test_admin_keys_persist_and_config_is_read_only() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let registry = ApiKeyRegistry::new(&config(), storage.clone());
+
+        let created = registry
+            .create(
+                "team-b",
+                None,
+                RateLimitConfig::default(),
+                None,
+                ClientPriority::Low,
+                TokenQuota::default(),
+            )
+            .unwrap();
+        assert!(created.key.starts_with("sk-kiro-"));
+        assert!(
+            registry
+                .create(
+                    "team-b",
+                    None,
+                    RateLimitConfig::default(),
+                    None,
+                    ClientPriority::default(),
+                    TokenQuota::default()
+                )
+                .is_err()
+        );
+        assert!(
+            registry
+                .create(
+                    "bad name",
+                    None,
+                    RateLimitConfig::default(),
+                    None,
+                    ClientPriority::default(),
+                    TokenQuota::default()
+                )
+                .is_err()
+        );
+        assert!(registry.set_disabled("team-a", true).is_err());
+
+        registry.set_disabled("team-b", true).unwrap();
+        assert!(registry.authenticate(&created.key).is_none());
+
+        // 重启后仍然有效
+        let reloaded = ApiKeyRegistry::new(&config(), storage);
+        assert_eq!(reloaded.len(), 3);
+        reloaded.set_disabled("team-b", false).unwrap();
+        assert_eq!(
+            name(reloaded.authenticate(&created.key)),
+            Some("team-b".to_string())
+        );
+        assert_eq!(
+            reloaded.authenticate(&created.key).unwrap().priority,
+            ClientPriority::Low
+        );
+        reloaded.delete("team-b").unwrap();
+        assert!(reloaded.authenticate(&created.key).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_quota_enforced_until_reset() {
+        let registry = ApiKeyRegistry::new(&config(), Arc::new(MemoryStorage::new()));
+        let created = registry
+            .create(
+                "team-b",
+                None,
+                RateLimitConfig::default(),
+                None,
+                ClientPriority::default(),
+                TokenQuota::default(),
+            )
+            .unwrap();
+        assert!(registry.set_quota("team-a", TokenQuota::default()).is_err());
+        registry
+            .set_quota(
+                "team-b",
+                TokenQuota {
+                    daily_tokens: Some(100),
+                    monthly_tokens: None,
+                },
+            )
+            .unwrap();
+        let client = registry.authenticate(&created.key).unwrap();
+        assert!(registry.check_quota(&client).await.is_ok());
+
+        registry.usage.as_ref().unwrap().record("team-b", 100);
+        assert!(registry.check_quota(&client).await.is_err());
+        let (quota, totals) = registry.usage("team-b").unwrap();
+        assert_eq!(quota.daily_tokens, Some(100));
+        assert_eq!(totals.daily_tokens, 100);
+
+        registry.reset_usage("team-b").unwrap();
+        assert!(registry.check_quota(&client).await.is_ok());
+        assert!(registry.usage("unknown").is_err());
+    }
+}
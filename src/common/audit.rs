@@ -0,0 +1,222 @@
+//! 请求审计
+//!
+//! 中间件为每个请求创建一条审计草稿，并在请求处理与响应体输出（含流式响应）期间设为当前任务的作用域；
+//! 处理过程中通过 `note_*` 函数补充模型、凭据与 token 用量。草稿的最后一个引用释放时
+//! （响应体结束或客户端断开，长轮询模式下为后台任务结束）把记录交给后台写入线程，
+//! 释放草稿的任务不会阻塞在存储写入上
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use futures::Stream;
+use parking_lot::Mutex;
+
+use crate::common::api_keys::ClientKey;
+use crate::storage::audit_log::{AuditLog, AuditRecord};
+
+tokio::task_local! {
+    /// 当前请求的审计草稿
+    static CURRENT: Arc<AuditDraft>;
+}
+
+/// 审计日志的后台写入端
+///
+/// 记录经通道交给专用线程按顺序写入存储后端
+#[derive(Clone)]
+pub struct AuditWriter {
+    sender: Sender<AuditRecord>,
+}
+
+impl AuditWriter {
+    /// 启动写入线程（所有写入端释放后线程退出）
+    pub fn spawn(log: Arc<AuditLog>) -> Self {
+        let (sender, receiver) = mpsc::channel::<AuditRecord>();
+        std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || {
+                for record in receiver {
+                    log.append(record);
+                }
+            })
+            .expect("启动审计日志写入线程失败");
+        Self { sender }
+    }
+}
+
+/// 进行中请求的审计草稿
+pub struct AuditDraft {
+    sender: Sender<AuditRecord>,
+    started: Instant,
+    record: Mutex<AuditRecord>,
+}
+
+impl Drop for AuditDraft {
+    fn drop(&mut self) {
+        let mut record = std::mem::take(self.record.get_mut());
+        record.latency_ms = self.started.elapsed().as_millis() as u64;
+        if self.sender.send(record).is_err() {
+            tracing::warn!("写入审计日志失败: 写入线程已退出");
+        }
+    }
+}
+
+/// 当前任务所属请求的审计草稿（未启用审计或不在请求作用域内时为 None）
+///
+/// 用于把审计作用域延续到请求处理中派生的后台任务
+pub fn current() -> Option<Arc<AuditDraft>> {
+    CURRENT.try_with(Arc::clone).ok()
+}
+
+/// 在指定审计草稿的作用域内执行 future（草稿为 None 时直接执行）
+pub async fn scope<F: Future>(draft: Option<Arc<AuditDraft>>, future: F) -> F::Output {
+    match draft {
+        Some(draft) => CURRENT.scope(draft, future).await,
+        None => future.await,
+    }
+}
+
+fn with_record(f: impl FnOnce(&mut AuditRecord)) {
+    let _ = CURRENT.try_with(|draft| f(&mut draft.record.lock()));
+}
+
+/// 记录请求的模型与是否流式
+pub fn note_request(model: &str, stream: bool) {
+    with_record(|record| {
+        record.model = Some(model.to_string());
+        record.stream = stream;
+    });
+}
+
+/// 记录本次请求使用的凭据
+pub fn note_credential(id: u64) {
+    with_record(|record| record.credential_id = Some(id));
+}
+
+/// 累计本次请求的 token 用量
+pub fn note_usage(input_tokens: i32, output_tokens: i32) {
+    with_record(|record| {
+        record.input_tokens += input_tokens.max(0) as i64;
+        record.output_tokens += output_tokens.max(0) as i64;
+    });
+}
+
+/// 请求审计中间件
+///
+/// 需位于认证中间件之内，以便从请求扩展中读取客户端 Key
+pub async fn audit_middleware(
+    State(writer): State<AuditWriter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let draft = Arc::new(AuditDraft {
+        sender: writer.sender,
+        started: Instant::now(),
+        record: Mutex::new(AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            endpoint: request.uri().path().to_string(),
+            client_key: request
+                .extensions()
+                .get::<ClientKey>()
+                .map(|client| client.name.clone()),
+            ..Default::default()
+        }),
+    });
+    let response = CURRENT.scope(draft.clone(), next.run(request)).await;
+    draft.record.lock().status = response.status().as_u16();
+
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(AuditScoped {
+        inner: Box::pin(body.into_data_stream()),
+        draft,
+    });
+    Response::from_parts(parts, body)
+}
+
+/// 在审计作用域内轮询的响应体流，随响应体一同释放草稿引用
+struct AuditScoped<S> {
+    inner: Pin<Box<S>>,
+    draft: Arc<AuditDraft>,
+}
+
+impl<S: Stream> Stream for AuditScoped<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        CURRENT.sync_scope(this.draft.clone(), || this.inner.as_mut().poll_next(cx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::storage::audit_log::AuditFilter;
+
+    #[tokio::test]
+    async fn test_draft_written_when_last_reference_dropped() {
+        let (sender, receiver) = mpsc::channel();
+        let draft = Arc::new(AuditDraft {
+            sender,
+            started: Instant::now(),
+            record: Mutex::new(AuditRecord::default()),
+        });
+
+        CURRENT
+            .scope(draft.clone(), async {
+                note_request("claude-sonnet-4", true);
+                note_credential(3);
+                let background = current();
+                tokio::spawn(scope(background, async { note_usage(100, 20) }))
+                    .await
+                    .unwrap();
+                note_usage(0, 5);
+            })
+            .await;
+        // 仍有引用时不写入
+        assert!(receiver.try_recv().is_err());
+
+        drop(draft);
+        let record = receiver.try_recv().unwrap();
+        assert_eq!(record.model.as_deref(), Some("claude-sonnet-4"));
+        assert_eq!(record.credential_id, Some(3));
+        assert_eq!((record.input_tokens, record.output_tokens), (100, 25));
+
+        // 作用域外调用无效果
+        note_usage(1, 1);
+    }
+
+    #[tokio::test]
+    async fn test_writer_appends_in_background() {
+        let log = Arc::new(AuditLog::new(Arc::new(MemoryStorage::new()), 10));
+        let writer = AuditWriter::spawn(log.clone());
+        drop(AuditDraft {
+            sender: writer.sender.clone(),
+            started: Instant::now(),
+            record: Mutex::new(AuditRecord {
+                endpoint: "/v1/messages".to_string(),
+                ..Default::default()
+            }),
+        });
+
+        let mut page = log.query(&AuditFilter::default(), 0, 10).unwrap();
+        for _ in 0..100 {
+            if page.total > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            page = log.query(&AuditFilter::default(), 0, 10).unwrap();
+        }
+        assert_eq!(page.total, 1);
+        assert_eq!(page.records[0].endpoint, "/v1/messages");
+    }
+}
@@ -2,31 +2,40 @@
 
 use axum::{
     body::Body,
-    http::{Request, header},
+    http::{HeaderMap, Request, header},
 };
 use subtle::ConstantTimeEq;
 
 /// 从请求中提取 API Key
 ///
-/// 支持两种认证方式：
+/// 支持三种认证方式：
 /// - `x-api-key` header
 /// - `Authorization: Bearer <token>` header
+/// - `x-goog-api-key` header（Gemini 兼容端点的客户端使用）
 pub fn extract_api_key(request: &Request<Body>) -> Option<String> {
+    extract_api_key_from_headers(request.headers())
+}
+
+/// 从请求头中提取 API Key（规则同 [`extract_api_key`]）
+pub fn extract_api_key_from_headers(headers: &HeaderMap) -> Option<String> {
     // 优先检查 x-api-key
-    if let Some(key) = request
-        .headers()
-        .get("x-api-key")
-        .and_then(|v| v.to_str().ok())
-    {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
         return Some(key.to_string());
     }
 
     // 其次检查 Authorization: Bearer
-    request
-        .headers()
+    if let Some(key) = headers
         .get(header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(key.to_string());
+    }
+
+    // 最后检查 x-goog-api-key
+    headers
+        .get("x-goog-api-key")
+        .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string())
 }
 
@@ -0,0 +1,142 @@
+//! 可清空缓存注册表
+//!
+//! 各子系统将自己的缓存注册到统一的注册表中，
+//! Admin API 据此按类型清空缓存，无需重启服务
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+/// 缓存类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheKind {
+    /// Token 计数缓存
+    TokenCount,
+    /// 凭据额度（usage limits）缓存
+    UsageLimits,
+    /// 响应缓存（含幂等缓存）
+    Response,
+    /// WebSearch 结果缓存
+    Search,
+}
+
+impl CacheKind {
+    /// 所有缓存类型
+    pub const ALL: [CacheKind; 4] = [
+        CacheKind::TokenCount,
+        CacheKind::UsageLimits,
+        CacheKind::Response,
+        CacheKind::Search,
+    ];
+}
+
+/// 可清空的缓存
+pub trait FlushableCache: Send + Sync {
+    /// 清空缓存，返回清除的条目数
+    fn flush(&self) -> anyhow::Result<usize>;
+}
+
+/// 单个缓存类型的清空结果
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlushResult {
+    /// 缓存类型
+    pub cache: CacheKind,
+    /// 当前部署是否启用了该缓存
+    pub registered: bool,
+    /// 清除的条目数
+    pub flushed: usize,
+}
+
+/// 缓存注册表
+#[derive(Default)]
+pub struct CacheRegistry {
+    caches: RwLock<BTreeMap<CacheKind, Vec<Arc<dyn FlushableCache>>>>,
+}
+
+impl CacheRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册缓存（同一类型可注册多个）
+    pub fn register(&self, kind: CacheKind, cache: Arc<dyn FlushableCache>) {
+        self.caches.write().entry(kind).or_default().push(cache);
+    }
+
+    /// 清空指定类型的缓存，`kinds` 为空时清空全部
+    pub fn flush(&self, kinds: &[CacheKind]) -> anyhow::Result<Vec<FlushResult>> {
+        let kinds: &[CacheKind] = if kinds.is_empty() {
+            &CacheKind::ALL
+        } else {
+            kinds
+        };
+
+        let caches = self.caches.read();
+        let mut results = Vec::with_capacity(kinds.len());
+        for kind in kinds {
+            let registered = caches.get(kind);
+            let mut flushed = 0;
+            for cache in registered.into_iter().flatten() {
+                flushed += cache.flush()?;
+            }
+            tracing::info!("已清空缓存 {:?}: {} 条", kind, flushed);
+            results.push(FlushResult {
+                cache: *kind,
+                registered: registered.is_some(),
+                flushed,
+            });
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingCache(AtomicUsize);
+
+    impl FlushableCache for CountingCache {
+        fn flush(&self) -> anyhow::Result<usize> {
+            Ok(self.0.swap(0, Ordering::SeqCst))
+        }
+    }
+
+    #[test]
+    fn test_flush_selected_and_all() {
+        let registry = CacheRegistry::new();
+        let cache = Arc::new(CountingCache(AtomicUsize::new(3)));
+        registry.register(CacheKind::Response, cache.clone());
+
+        let results = registry.flush(&[CacheKind::Response]).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].registered);
+        assert_eq!(results[0].flushed, 3);
+
+        cache.0.store(2, Ordering::SeqCst);
+        let results = registry.flush(&[]).unwrap();
+        assert_eq!(results.len(), CacheKind::ALL.len());
+        let search = results
+            .iter()
+            .find(|r| r.cache == CacheKind::Search)
+            .unwrap();
+        assert!(!search.registered);
+        let response = results
+            .iter()
+            .find(|r| r.cache == CacheKind::Response)
+            .unwrap();
+        assert_eq!(response.flushed, 2);
+    }
+
+    #[test]
+    fn test_cache_kind_serde() {
+        let kinds: Vec<CacheKind> =
+            serde_json::from_str(r#"["token-count","usage-limits","response","search"]"#).unwrap();
+        assert_eq!(kinds, CacheKind::ALL.to_vec());
+    }
+}
@@ -0,0 +1,94 @@
+//! 下游断开检测
+//!
+//! 客户端断开连接时 hyper 会立即丢弃响应体，包装在其中的 SSE 流随之被 drop：上游
+//! reqwest 响应体与连接守卫一同释放，上游连接被中止，凭据的连接占用也随即归还。
+//! [`on_disconnect`] 在流未结束就被丢弃时记录日志与指标，使这一中止过程可观测
+
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::Stream;
+
+use crate::common::metrics;
+
+/// 检测下游断开的流包装
+pub struct DisconnectAware<S> {
+    inner: Pin<Box<S>>,
+    /// 描述信息（用于日志）
+    label: String,
+    /// 内部流是否已正常结束
+    finished: bool,
+    /// 已交给下游的字节数
+    sent_bytes: usize,
+}
+
+/// 包装响应流：流结束前被丢弃时视为客户端断开
+pub fn on_disconnect<S>(stream: S, label: impl Into<String>) -> DisconnectAware<S>
+where
+    S: Stream<Item = Result<Bytes, Infallible>>,
+{
+    DisconnectAware {
+        inner: Box::pin(stream),
+        label: label.into(),
+        finished: false,
+        sent_bytes: 0,
+    }
+}
+
+impl<S> Stream for DisconnectAware<S>
+where
+    S: Stream<Item = Result<Bytes, Infallible>>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let poll = this.inner.as_mut().poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => this.sent_bytes += chunk.len(),
+            Poll::Ready(None) => this.finished = true,
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl<S> Drop for DisconnectAware<S> {
+    fn drop(&mut self) {
+        // 内部流（含上游响应体）在此之后随字段一起释放
+        if !self.finished {
+            metrics::CLIENT_DISCONNECTS.inc_by(1);
+            tracing::info!(
+                "客户端在响应结束前断开连接，中止上游请求: {}（已发送 {} 字节）",
+                self.label,
+                self.sent_bytes
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{StreamExt, stream};
+
+    #[tokio::test]
+    async fn test_counts_only_unfinished_streams() {
+        let before = metrics::CLIENT_DISCONNECTS.get();
+
+        let chunks = stream::iter([Ok(Bytes::from_static(b"data"))]);
+        let mut finished = on_disconnect(chunks, "finished");
+        while finished.next().await.is_some() {}
+        drop(finished);
+        assert_eq!(metrics::CLIENT_DISCONNECTS.get(), before);
+
+        let chunks = stream::iter([Ok(Bytes::from_static(b"data"))]).chain(stream::pending());
+        let mut abandoned = on_disconnect(chunks, "abandoned");
+        assert!(abandoned.next().await.is_some());
+        assert_eq!(abandoned.sent_bytes, 4);
+        drop(abandoned);
+        assert_eq!(metrics::CLIENT_DISCONNECTS.get(), before + 1);
+    }
+}
@@ -0,0 +1,284 @@
+//! 运行时指标
+//!
+//! 以 Prometheus 文本格式通过 `GET /metrics` 暴露的进程内计数器与输出吞吐量分位数
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// 单调递增计数器
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    /// 增加指定值
+    pub fn inc_by(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// 当前值
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// 流式响应中被抑制的重复片段次数
+pub static STREAM_DUPLICATE_SPANS: Counter = Counter::new(
+    "kiro_stream_duplicate_spans_total",
+    "Overlapping assistantResponse spans suppressed in streaming responses",
+);
+
+/// 流式响应中被抑制的重复内容字节数
+pub static STREAM_DUPLICATE_BYTES: Counter = Counter::new(
+    "kiro_stream_duplicate_bytes_total",
+    "Bytes of overlapping assistantResponse content suppressed in streaming responses",
+);
+
+/// 超出解码缓冲区上限而被丢弃的帧数（截断策略）
+pub static DECODER_FRAMES_TRUNCATED: Counter = Counter::new(
+    "kiro_decoder_frames_truncated_total",
+    "Event stream frames dropped because they exceeded the decoder buffer limit",
+);
+
+/// 解码缓冲区溢出次数（中止策略或无法截断时）
+pub static DECODER_BUFFER_OVERFLOWS: Counter = Counter::new(
+    "kiro_decoder_buffer_overflows_total",
+    "Event stream decoder buffer overflows that aborted decoding",
+);
+
+/// 上游限流（429）次数
+pub static CREDENTIAL_THROTTLES: Counter = Counter::new(
+    "kiro_credential_throttles_total",
+    "Upstream 429 responses that shrank a credential's concurrency limit",
+);
+
+/// 未通过 Messages schema 校验的请求数（含 shadow 模式下放行的请求）
+pub static REQUEST_SCHEMA_VIOLATIONS: Counter = Counter::new(
+    "kiro_request_schema_violations_total",
+    "Requests to /v1/messages that failed Anthropic Messages schema validation",
+);
+
+/// 客户端在流式响应结束前断开连接的次数（上游请求随之中止）
+pub static CLIENT_DISCONNECTS: Counter = Counter::new(
+    "kiro_client_disconnects_total",
+    "Streaming responses abandoned by the client before completion; the upstream request is aborted",
+);
+
+/// 超出全局并发上限被拒绝的请求数（队列已满或排队超时）
+pub static REQUESTS_SHED: Counter = Counter::new(
+    "kiro_requests_shed_total",
+    "Requests rejected with 429 because the global in-flight limit and queue were full",
+);
+
+/// 所有已注册的计数器
+const COUNTERS: &[&Counter] = &[
+    &STREAM_DUPLICATE_SPANS,
+    &STREAM_DUPLICATE_BYTES,
+    &DECODER_FRAMES_TRUNCATED,
+    &DECODER_BUFFER_OVERFLOWS,
+    &CREDENTIAL_THROTTLES,
+    &CLIENT_DISCONNECTS,
+    &REQUEST_SCHEMA_VIOLATIONS,
+    &REQUESTS_SHED,
+];
+
+/// 每个模型 + 凭据保留的最近吞吐量样本数
+const THROUGHPUT_WINDOW: usize = 512;
+
+/// 计入统计的最短输出耗时，过短的响应算出的速率没有参考意义
+const MIN_THROUGHPUT_ELAPSED: Duration = Duration::from_millis(100);
+
+/// 输出的分位数
+const QUANTILES: [(f64, &str); 3] = [(0.5, "0.5"), (0.9, "0.9"), (0.99, "0.99")];
+
+/// 已完成流式响应的输出吞吐量（tokens/s），按模型与凭据分组
+pub static OUTPUT_THROUGHPUT: Throughput = Throughput::new();
+
+/// 输出吞吐量统计
+///
+/// 分位数基于每组最近 [`THROUGHPUT_WINDOW`] 个样本计算，总数与总和为进程启动以来的累计值
+pub struct Throughput {
+    buckets: Mutex<BTreeMap<(String, u64), ThroughputBucket>>,
+}
+
+#[derive(Default)]
+struct ThroughputBucket {
+    samples: VecDeque<f64>,
+    count: u64,
+    sum: f64,
+}
+
+/// 单个模型 + 凭据的吞吐量汇总
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThroughputSummary {
+    pub model: String,
+    pub credential_id: u64,
+    /// 累计样本数
+    pub count: u64,
+    /// 累计平均值（tokens/s）
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl Throughput {
+    const fn new() -> Self {
+        Self {
+            buckets: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 记录一次完成的流式响应（`elapsed` 为输出阶段耗时）
+    pub fn record(&self, model: &str, credential_id: u64, output_tokens: i32, elapsed: Duration) {
+        if output_tokens <= 0 || elapsed < MIN_THROUGHPUT_ELAPSED {
+            return;
+        }
+        let rate = output_tokens as f64 / elapsed.as_secs_f64();
+
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets
+            .entry((model.to_string(), credential_id))
+            .or_default();
+        if bucket.samples.len() == THROUGHPUT_WINDOW {
+            bucket.samples.pop_front();
+        }
+        bucket.samples.push_back(rate);
+        bucket.count += 1;
+        bucket.sum += rate;
+    }
+
+    /// 各分组的汇总（按模型、凭据排序）
+    pub fn summaries(&self) -> Vec<ThroughputSummary> {
+        self.buckets
+            .lock()
+            .iter()
+            .map(|((model, credential_id), bucket)| {
+                let mut sorted: Vec<f64> = bucket.samples.iter().copied().collect();
+                sorted.sort_by(f64::total_cmp);
+                ThroughputSummary {
+                    model: model.clone(),
+                    credential_id: *credential_id,
+                    count: bucket.count,
+                    mean: bucket.sum / bucket.count as f64,
+                    p50: percentile(&sorted, QUANTILES[0].0),
+                    p90: percentile(&sorted, QUANTILES[1].0),
+                    p99: percentile(&sorted, QUANTILES[2].0),
+                }
+            })
+            .collect()
+    }
+
+    /// 以 Prometheus summary 格式输出
+    fn render(&self, out: &mut String) {
+        const NAME: &str = "kiro_output_tokens_per_second";
+        let _ = writeln!(
+            out,
+            "# HELP {} Output tokens per second of completed streaming responses by model and credential",
+            NAME
+        );
+        let _ = writeln!(out, "# TYPE {} summary", NAME);
+        for summary in self.summaries() {
+            let labels = format!(
+                "model=\"{}\",credential=\"{}\"",
+                escape_label(&summary.model),
+                summary.credential_id
+            );
+            let values = [summary.p50, summary.p90, summary.p99];
+            for ((_, quantile), value) in QUANTILES.iter().zip(values) {
+                let _ = writeln!(
+                    out,
+                    "{}{{{},quantile=\"{}\"}} {:.3}",
+                    NAME, labels, quantile, value
+                );
+            }
+            let sum = summary.mean * summary.count as f64;
+            let _ = writeln!(out, "{}_sum{{{}}} {:.3}", NAME, labels, sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", NAME, labels, summary.count);
+        }
+    }
+}
+
+/// 最近秩法计算分位数（`sorted` 已升序排列）
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// 转义 Prometheus 标签值
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 以 Prometheus 文本格式输出所有指标
+pub fn render() -> String {
+    let mut out = String::new();
+    for counter in COUNTERS {
+        let _ = writeln!(out, "# HELP {} {}", counter.name, counter.help);
+        let _ = writeln!(out, "# TYPE {} counter", counter.name);
+        let _ = writeln!(out, "{} {}", counter.name, counter.get());
+    }
+    OUTPUT_THROUGHPUT.render(&mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let text = render();
+        assert!(text.contains("# TYPE kiro_stream_duplicate_spans_total counter"));
+        assert!(text.contains("\nkiro_stream_duplicate_bytes_total "));
+        assert!(text.contains("# TYPE kiro_output_tokens_per_second summary"));
+    }
+
+    #[test]
+    fn test_throughput_percentiles() {
+        let throughput = Throughput::new();
+        for tokens in 1..=100 {
+            throughput.record("claude-haiku-4-5", 2, tokens, Duration::from_secs(1));
+        }
+        // 过短的响应不计入
+        throughput.record("claude-haiku-4-5", 2, 1000, Duration::from_millis(10));
+
+        let summaries = throughput.summaries();
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.mean, 50.5);
+        assert_eq!((summary.p50, summary.p90, summary.p99), (50.0, 90.0, 99.0));
+
+        let mut out = String::new();
+        throughput.render(&mut out);
+        assert!(out.contains(
+            "kiro_output_tokens_per_second{model=\"claude-haiku-4-5\",credential=\"2\",quantile=\"0.9\"} 90.000"
+        ));
+        assert!(out.contains(
+            "kiro_output_tokens_per_second_count{model=\"claude-haiku-4-5\",credential=\"2\"} 100"
+        ));
+    }
+}
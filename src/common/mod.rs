@@ -1,3 +1,13 @@
 //! 公共工具模块
 
+pub mod admission;
+pub mod alert;
+pub mod api_keys;
+pub mod audit;
 pub mod auth;
+pub mod cache;
+pub mod disconnect;
+pub mod metrics;
+pub mod rate_limit;
+pub mod shutdown;
+pub mod telemetry;
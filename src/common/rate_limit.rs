@@ -0,0 +1,163 @@
+//! 客户端 API Key 速率限制
+//!
+//! 每个 Key 最多两个令牌桶，容量均为一分钟的配额并按秒匀速补充：
+//! - 请求桶：每个请求在认证时消耗 1 个令牌，不足时拒绝
+//! - Token 桶：请求完成后按实际输入 + 输出 tokens 扣减，允许透支；
+//!   透支期间新请求被拒绝，直到补充回正
+
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::model::config::RateLimitConfig;
+
+/// 令牌桶
+struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    level: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(limit: u32) -> Self {
+        let capacity = f64::from(limit.max(1));
+        Self {
+            capacity,
+            refill_per_sec: capacity / 60.0,
+            state: Mutex::new(BucketState {
+                level: capacity,
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// 按经过时间补充令牌后返回状态
+    fn refill<'a>(&self, state: &'a mut BucketState, now: Instant) -> &'a mut BucketState {
+        let elapsed = now
+            .saturating_duration_since(state.updated_at)
+            .as_secs_f64();
+        state.level = (state.level + elapsed * self.refill_per_sec).min(self.capacity);
+        state.updated_at = now;
+        state
+    }
+
+    /// 补充到 `target` 所需的等待时间
+    fn wait_until(&self, level: f64, target: f64) -> Duration {
+        Duration::from_secs_f64(((target - level) / self.refill_per_sec).max(0.0))
+    }
+
+    /// 消耗 `n` 个令牌，不足时返回需要等待的时间
+    fn try_acquire(&self, n: f64, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock();
+        let state = self.refill(&mut state, now);
+        if state.level >= n {
+            state.level -= n;
+            Ok(())
+        } else {
+            Err(self.wait_until(state.level, n))
+        }
+    }
+
+    /// 检查是否处于透支状态（不消耗令牌）
+    fn check_positive(&self, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock();
+        let state = self.refill(&mut state, now);
+        if state.level > 0.0 {
+            Ok(())
+        } else {
+            Err(self.wait_until(state.level, f64::MIN_POSITIVE))
+        }
+    }
+
+    /// 扣减令牌（允许透支）
+    fn charge(&self, n: f64, now: Instant) {
+        let mut state = self.state.lock();
+        let state = self.refill(&mut state, now);
+        state.level -= n;
+    }
+}
+
+/// 单个 Key 的速率限制器
+pub struct KeyRateLimiter {
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+}
+
+impl KeyRateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            requests: config.requests_per_minute.map(TokenBucket::per_minute),
+            tokens: config.tokens_per_minute.map(TokenBucket::per_minute),
+        }
+    }
+
+    /// 准入检查：Token 桶未透支且请求桶有余量时放行，否则返回建议的重试等待时间
+    pub fn check(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        if let Some(tokens) = &self.tokens {
+            tokens.check_positive(now)?;
+        }
+        if let Some(requests) = &self.requests {
+            requests.try_acquire(1.0, now)?;
+        }
+        Ok(())
+    }
+
+    /// 记录请求实际消耗的 tokens
+    pub fn record_tokens(&self, tokens: i64) {
+        if let Some(bucket) = &self.tokens {
+            bucket.charge(tokens.max(0) as f64, Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_bucket_refills() {
+        let bucket = TokenBucket::per_minute(2);
+        let start = Instant::now();
+        assert!(bucket.try_acquire(1.0, start).is_ok());
+        assert!(bucket.try_acquire(1.0, start).is_ok());
+
+        // 每分钟 2 个，补充 1 个需要 30 秒
+        let wait = bucket.try_acquire(1.0, start).unwrap_err();
+        assert_eq!(wait.as_secs_f64().round(), 30.0);
+        assert!(
+            bucket
+                .try_acquire(1.0, start + Duration::from_secs(30))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_token_bucket_allows_overdraft_then_blocks() {
+        let limiter = KeyRateLimiter::new(&RateLimitConfig {
+            requests_per_minute: None,
+            tokens_per_minute: Some(600),
+        });
+        assert!(limiter.check().is_ok());
+
+        // 一次请求消耗超过整分钟配额：透支 600，约需 60 秒恢复
+        limiter.record_tokens(1200);
+        let wait = limiter.check().unwrap_err();
+        assert!(wait.as_secs() >= 59 && wait.as_secs() <= 60);
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let limiter = KeyRateLimiter::new(&RateLimitConfig::default());
+        for _ in 0..1000 {
+            assert!(limiter.check().is_ok());
+        }
+        limiter.record_tokens(i64::MAX);
+        assert!(limiter.check().is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+//! 优雅关闭
+//!
+//! 收到 SIGTERM（或 Ctrl+C）后服务器停止接受新连接，`/readyz` 返回 503 以便负载均衡摘除实例；
+//! 进行中的流式响应按凭据连接守卫计数等待结束，超过宽限期后强制退出
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::kiro::token_manager::MultiTokenManager;
+
+/// 排空检查间隔
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// 是否已进入关闭排空阶段
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Acquire)
+}
+
+/// 等待关闭信号（SIGTERM 或 Ctrl+C），返回后标记为排空阶段
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("监听 Ctrl+C 失败: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("监听 SIGTERM 失败: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("收到 Ctrl+C，开始优雅关闭"),
+        _ = terminate => tracing::info!("收到 SIGTERM，开始优雅关闭"),
+    }
+    DRAINING.store(true, Ordering::Release);
+}
+
+/// 宽限期计时：期间定期记录进行中的连接数
+///
+/// 所有连接结束时服务器会先行退出，本函数仅在宽限期耗尽时返回，返回值为仍未完成的连接数
+pub async fn drain(token_manager: Arc<MultiTokenManager>, grace: Duration) -> usize {
+    let deadline = tokio::time::Instant::now() + grace;
+    let mut last_reported = 0;
+    loop {
+        let active = token_manager.active_connections();
+        if tokio::time::Instant::now() >= deadline {
+            return active;
+        }
+        if active != last_reported {
+            tracing::info!("等待 {} 个进行中的请求完成", active);
+            last_reported = active;
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,566 @@
+//! 日志与链路追踪初始化
+//!
+//! 请求处理的各阶段（`post_messages` → `convert_request` → `upstream` → `sse_stream`）
+//! 均有对应的 tracing span。启用 `otel` 特性并配置 `otlpEndpoint` 后，
+//! span 会通过 OTLP/HTTP 导出到 Jaeger、Tempo 等后端，用于查看各阶段耗时
+//!
+//! 日志级别默认由 `RUST_LOG` 控制；`logging.routes` 可按路由前缀覆盖本服务自身日志的级别，
+//! 并可通过 Admin API 运行时调整
+//!
+//! 通过全局过滤的日志同时推送到进程内广播通道，供 Admin API 实时查看（`GET /api/admin/logs/stream`）
+//!
+//! `logging.format` 为 `json` 时每行输出一个 JSON 对象，所在 span 的字段（`request_id`、`model`、
+//! `credential_id` 等）与事件字段（`input_tokens`、`output_tokens` 等）平铺到顶层
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+
+use axum::{body::Body, extract::Request, middleware::Next, response::Response};
+use futures::Stream;
+use parking_lot::RwLock;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Span, Subscriber, span};
+use tracing_subscriber::filter::dynamic_filter_fn;
+use tracing_subscriber::fmt::format::{JsonFields, Writer};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::{self, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, fmt};
+
+use crate::model::config::{Config, LogFormat};
+
+/// 本服务日志的 target 前缀（路由级别只覆盖这部分日志，依赖库日志仍由 RUST_LOG 控制）
+const CRATE_TARGET: &str = env!("CARGO_CRATE_NAME");
+
+/// 路由前缀 → 日志级别，按前缀长度降序排列
+static ROUTE_LEVELS: RwLock<Vec<(String, LevelFilter)>> = RwLock::new(Vec::new());
+
+/// 是否以 JSON 行格式输出日志（配置加载前使用文本格式）
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// 日志广播通道容量（订阅方处理不及时时丢弃最旧的日志）
+const LOG_STREAM_CAPACITY: usize = 1024;
+
+/// 实时日志广播通道
+static LOG_STREAM: LazyLock<broadcast::Sender<LogRecord>> =
+    LazyLock::new(|| broadcast::channel(LOG_STREAM_CAPACITY).0);
+
+tokio::task_local! {
+    /// 当前请求匹配到的路由日志级别
+    static ROUTE_LEVEL: LevelFilter;
+}
+
+#[cfg(feature = "otel")]
+type OtelLayer = tracing_opentelemetry::OpenTelemetryLayer<
+    tracing_subscriber::Registry,
+    opentelemetry_sdk::trace::SdkTracer,
+>;
+
+/// 链路追踪导出句柄
+///
+/// 日志需要在加载配置前初始化，导出层在配置加载后通过该句柄启用
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    otel: tracing_subscriber::reload::Handle<Option<OtelLayer>, tracing_subscriber::Registry>,
+}
+
+/// 初始化日志输出，返回用于启用链路追踪导出的句柄
+///
+/// `stderr` 为 true 时日志输出到标准错误（stdio 传输的 MCP 模式下标准输出用于协议消息）
+pub fn init(stderr: bool) -> Telemetry {
+    let registry = tracing_subscriber::registry();
+    #[cfg(feature = "otel")]
+    let (otel_layer, otel) = tracing_subscriber::reload::Layer::new(None::<OtelLayer>);
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel_layer);
+
+    let writer = move || {
+        if stderr {
+            BoxMakeWriter::new(std::io::stderr)
+        } else {
+            BoxMakeWriter::new(std::io::stdout)
+        }
+    };
+    registry
+        .with(
+            fmt::layer()
+                .with_writer(writer())
+                .with_filter(dynamic_filter_fn(|_, _| {
+                    !JSON_FORMAT.load(Ordering::Relaxed)
+                })),
+        )
+        .with(
+            fmt::layer()
+                .fmt_fields(JsonFields::new())
+                .event_format(JsonLines)
+                .with_writer(writer())
+                .with_filter(dynamic_filter_fn(|_, _| {
+                    JSON_FORMAT.load(Ordering::Relaxed)
+                })),
+        )
+        .with(LogStreamLayer)
+        .with(RouteFilter {
+            env: EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()),
+        })
+        .init();
+    Telemetry {
+        #[cfg(feature = "otel")]
+        otel,
+    }
+}
+
+impl Telemetry {
+    /// 按配置启用 OTLP 导出（未配置 `otlpEndpoint` 时不启用）
+    #[cfg(feature = "otel")]
+    pub fn enable_export(&self, config: &Config) {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry_otlp::WithExportConfig;
+
+        let Some(endpoint) = config.otlp_endpoint.as_deref() else {
+            return;
+        };
+
+        let exporter = match opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+        {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                tracing::error!("创建 OTLP 导出器失败: {}", e);
+                return;
+            }
+        };
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(config.otel_service_name.clone())
+                    .build(),
+            )
+            .build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("kiro-rs"));
+
+        match self.otel.reload(Some(layer)) {
+            Ok(()) => tracing::info!("OTLP 链路追踪已启用: {}", endpoint),
+            Err(e) => tracing::error!("启用 OTLP 链路追踪失败: {}", e),
+        }
+    }
+
+    /// 按配置启用 OTLP 导出（未启用 `otel` 特性时仅提示）
+    #[cfg(not(feature = "otel"))]
+    pub fn enable_export(&self, config: &Config) {
+        if config.otlp_endpoint.is_some() {
+            tracing::warn!("已配置 otlpEndpoint，但当前构建未启用 otel 特性，链路追踪不会导出");
+        }
+    }
+}
+
+/// 在 span 内轮询的流
+///
+/// 流的每次轮询都在 span 内执行；span 随流一起释放，其持续时间即为整个流式输出的耗时
+pub struct Instrumented<S> {
+    inner: Pin<Box<S>>,
+    span: Span,
+}
+
+/// 为流附加 span
+pub fn instrument_stream<S: Stream>(stream: S, span: Span) -> Instrumented<S> {
+    Instrumented {
+        inner: Box::pin(stream),
+        span,
+    }
+}
+
+impl<S: Stream> Stream for Instrumented<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let _enter = this.span.enter();
+        this.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// 替换路由日志级别（级别无效时整体不生效）
+pub fn set_route_levels(routes: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let mut levels = routes
+        .iter()
+        .map(|(route, level)| {
+            LevelFilter::from_str(level)
+                .map(|level| (route.clone(), level))
+                .map_err(|_| anyhow::anyhow!("路由 {} 的日志级别无效: {}", route, level))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    levels.sort_by_key(|(route, _)| std::cmp::Reverse(route.len()));
+    *ROUTE_LEVELS.write() = levels;
+    Ok(())
+}
+
+/// 切换日志输出格式
+pub fn set_format(format: LogFormat) {
+    JSON_FORMAT.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+/// 当前日志输出格式
+pub fn format() -> LogFormat {
+    if JSON_FORMAT.load(Ordering::Relaxed) {
+        LogFormat::Json
+    } else {
+        LogFormat::Text
+    }
+}
+
+/// 当前生效的路由日志级别
+pub fn route_levels() -> BTreeMap<String, String> {
+    ROUTE_LEVELS
+        .read()
+        .iter()
+        .map(|(route, level)| (route.clone(), level.to_string().to_lowercase()))
+        .collect()
+}
+
+/// 按最长前缀匹配路由的日志级别
+fn match_route(path: &str) -> Option<LevelFilter> {
+    ROUTE_LEVELS
+        .read()
+        .iter()
+        .find(|(route, _)| path.starts_with(route.as_str()))
+        .map(|(_, level)| *level)
+}
+
+/// 路由日志级别中间件
+///
+/// 匹配到路由级别时，请求处理与响应体输出（含流式响应）期间本服务的日志按该级别过滤
+pub async fn route_log_middleware(request: Request, next: Next) -> Response {
+    let Some(level) = match_route(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let response = ROUTE_LEVEL.scope(level, next.run(request)).await;
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(RouteScoped {
+        inner: Box::pin(body.into_data_stream()),
+        level,
+    });
+    Response::from_parts(parts, body)
+}
+
+/// 在路由日志级别作用域内轮询的流
+struct RouteScoped<S> {
+    inner: Pin<Box<S>>,
+    level: LevelFilter,
+}
+
+impl<S: Stream> Stream for RouteScoped<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        ROUTE_LEVEL.sync_scope(this.level, || this.inner.as_mut().poll_next(cx))
+    }
+}
+
+/// 实时推送的日志记录
+#[derive(Debug, Clone, Serialize)]
+pub struct LogRecord {
+    /// 时间（RFC3339）
+    pub timestamp: String,
+    /// 日志级别（`ERROR` / `WARN` / `INFO` / `DEBUG` / `TRACE`）
+    pub level: String,
+    /// 模块路径（tracing target）
+    pub target: String,
+    pub message: String,
+    /// 结构化字段
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+impl LogRecord {
+    /// 是否满足最低级别与模块前缀过滤条件
+    pub fn matches(&self, level: Option<Level>, module: Option<&str>) -> bool {
+        let level_ok = match (level, Level::from_str(&self.level)) {
+            (Some(min), Ok(record)) => record <= min,
+            _ => true,
+        };
+        level_ok && module.is_none_or(|m| self.target.starts_with(m))
+    }
+}
+
+/// 订阅实时日志
+pub fn subscribe_logs() -> broadcast::Receiver<LogRecord> {
+    LOG_STREAM.subscribe()
+}
+
+/// 将日志事件推送到广播通道（没有订阅方时跳过格式化）
+struct LogStreamLayer;
+
+impl<S: Subscriber> Layer<S> for LogStreamLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+        if LOG_STREAM.receiver_count() == 0 {
+            return;
+        }
+        let mut visitor = RecordVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        let _ = LOG_STREAM.send(LogRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+        });
+    }
+}
+
+/// 收集日志事件的消息与字段
+#[derive(Default)]
+struct RecordVisitor {
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for RecordVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+}
+
+/// JSON 行格式：时间、级别、target、消息，以及从根到当前 span 的字段与事件字段（同名时内层覆盖外层）
+struct JsonLines;
+
+impl<S, N> FormatEvent<S, N> for JsonLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let mut record = Map::new();
+        record.insert(
+            "timestamp".to_string(),
+            Value::from(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+        );
+        record.insert("level".to_string(), Value::from(metadata.level().as_str()));
+        record.insert("target".to_string(), Value::from(metadata.target()));
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>()
+                    && let Ok(Value::Object(fields)) = serde_json::from_str(fields)
+                {
+                    record.extend(fields);
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut record));
+        writeln!(writer, "{}", Value::Object(record))
+    }
+}
+
+/// 将事件字段按类型写入 JSON 对象
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(
+            field.name().to_string(),
+            Value::from(format!("{:?}", value)),
+        );
+    }
+}
+
+/// 全局日志过滤：本服务日志优先使用当前请求的路由级别，其余情况交给 `EnvFilter`
+struct RouteFilter {
+    env: EnvFilter,
+}
+
+impl RouteFilter {
+    fn is_own(metadata: &Metadata<'_>) -> bool {
+        metadata.target().starts_with(CRATE_TARGET)
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RouteFilter {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        // 本服务的日志需要逐次判断（路由级别随请求变化）
+        if Self::is_own(metadata) {
+            Interest::sometimes()
+        } else {
+            Layer::<S>::register_callsite(&self.env, metadata)
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        // 路由级别可在运行时调高，不能提供上限
+        None
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: layer::Context<'_, S>) -> bool {
+        if Self::is_own(metadata)
+            && let Ok(level) = ROUTE_LEVEL.try_with(|level| *level)
+        {
+            return level >= *metadata.level();
+        }
+        self.env.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: layer::Context<'_, S>) {
+        self.env.on_new_span(attrs, id, ctx)
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: layer::Context<'_, S>) {
+        self.env.on_record(id, values, ctx)
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        self.env.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        self.env.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: span::Id, ctx: layer::Context<'_, S>) {
+        self.env.on_close(id, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_levels_longest_prefix() {
+        let routes = BTreeMap::from([
+            ("/v1".to_string(), "warn".to_string()),
+            ("/v1/messages".to_string(), "DEBUG".to_string()),
+        ]);
+        set_route_levels(&routes).unwrap();
+        assert_eq!(
+            match_route("/v1/messages/count_tokens"),
+            Some(LevelFilter::DEBUG)
+        );
+        assert_eq!(match_route("/v1/models"), Some(LevelFilter::WARN));
+        assert_eq!(match_route("/api/admin/config"), None);
+        assert_eq!(route_levels()["/v1/messages"], "debug");
+
+        let invalid = BTreeMap::from([("/v1".to_string(), "verbose".to_string())]);
+        assert!(set_route_levels(&invalid).is_err());
+        assert_eq!(match_route("/v1/models"), Some(LevelFilter::WARN));
+
+        set_route_levels(&BTreeMap::new()).unwrap();
+        assert_eq!(match_route("/v1/models"), None);
+    }
+
+    #[test]
+    fn test_json_lines_flatten_span_fields() {
+        #[derive(Clone, Default)]
+        struct Buffer(std::sync::Arc<parking_lot::Mutex<Vec<u8>>>);
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let output = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            fmt::layer()
+                .fmt_fields(JsonFields::new())
+                .event_format(JsonLines)
+                .with_writer(move || output.clone()),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request", request_id = "req_1");
+            let _request = request.enter();
+            let handler = tracing::info_span!(
+                "post_messages",
+                model = "claude-sonnet-4-5",
+                credential_id = tracing::field::Empty
+            );
+            let _handler = handler.enter();
+            handler.record("credential_id", 3);
+            tracing::info!(input_tokens = 12, output_tokens = 34, "流式响应完成");
+        });
+
+        let line = String::from_utf8(buffer.0.lock().clone()).unwrap();
+        let record: Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["message"], "流式响应完成");
+        assert_eq!(record["request_id"], "req_1");
+        assert_eq!(record["model"], "claude-sonnet-4-5");
+        assert_eq!(record["credential_id"], 3);
+        assert_eq!(record["output_tokens"], 34);
+    }
+
+    #[test]
+    fn test_log_stream_layer() {
+        let subscriber = tracing_subscriber::registry().with(LogStreamLayer);
+        let mut logs = subscribe_logs();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(credential = 3, "凭据 Token 刷新失败");
+        });
+
+        let record = logs.try_recv().unwrap();
+        assert_eq!(record.level, "WARN");
+        assert_eq!(record.message, "凭据 Token 刷新失败");
+        assert_eq!(record.fields["credential"], "3");
+        assert!(record.matches(Some(Level::WARN), Some(CRATE_TARGET)));
+        assert!(!record.matches(Some(Level::ERROR), None));
+        assert!(!record.matches(None, Some("kiro_rs::admin")));
+    }
+}
@@ -0,0 +1,455 @@
+//! 启动自检命令（`kiro-rs doctor`）
+//!
+//! 依次检查配置、tokenizer、凭据有效性、上游连通性、代理连通性和时钟偏差，
+//! 输出汇总的通过/失败报告，便于排查"无法使用"类问题
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::http_client::{ProxyConfig, build_client};
+use crate::kiro::model::credentials::CredentialsConfig;
+use crate::kiro::token_manager::{
+    MultiTokenManager, get_usage_limits, is_token_expired, validate_refresh_token,
+};
+use crate::model::config::Config;
+use crate::token::TokenizerStatus;
+use crate::{storage, token};
+
+/// 网络检查超时（秒）
+const NETWORK_TIMEOUT_SECS: u64 = 15;
+
+/// 允许的最大时钟偏差（秒），超过后签名/Token 过期判断可能出错
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+/// 检查结果状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        }
+    }
+}
+
+/// 单项检查结果
+struct CheckResult {
+    name: String,
+    status: Status,
+    detail: String,
+}
+
+/// 自检报告
+#[derive(Default)]
+struct Report {
+    results: Vec<CheckResult>,
+}
+
+impl Report {
+    fn add(&mut self, name: impl Into<String>, status: Status, detail: impl Into<String>) {
+        let result = CheckResult {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        };
+        println!(
+            "[{}] {:<28} {}",
+            result.status.label(),
+            result.name,
+            result.detail
+        );
+        self.results.push(result);
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.results.iter().filter(|r| r.status == status).count()
+    }
+}
+
+/// 执行自检，返回进程退出码（存在失败项时为 1）
+pub async fn run(config_path: &str, credentials_path: &str) -> i32 {
+    println!("kiro-rs doctor v{}", env!("CARGO_PKG_VERSION"));
+    println!("配置文件: {}", config_path);
+    println!("凭证文件: {}", credentials_path);
+    println!();
+
+    let mut report = Report::default();
+
+    // 1. 配置
+    let config = match Config::load(config_path) {
+        Ok(config) => {
+            if std::path::Path::new(config_path).exists() {
+                report.add("config", Status::Pass, "配置文件解析成功");
+            } else {
+                report.add("config", Status::Warn, "配置文件不存在，使用默认配置");
+            }
+            config
+        }
+        Err(e) => {
+            report.add("config", Status::Fail, format!("配置文件解析失败: {}", e));
+            return finish(&report);
+        }
+    };
+    check_config(&config, &mut report);
+
+    // 2. Tokenizer
+    if let Err(e) = token::init_tokenizer(
+        None,
+        config.tokenizer_path.as_deref(),
+        &config.model_tokenizers,
+    )
+    .await
+    {
+        report.add("tokenizer.models", Status::Fail, e.to_string());
+    } else if !config.model_tokenizers.is_empty() {
+        report.add(
+            "tokenizer.models",
+            Status::Pass,
+            format!("已加载 {} 个模型 tokenizer", config.model_tokenizers.len()),
+        );
+    }
+    match token::tokenizer_status() {
+        TokenizerStatus::Loaded { .. } => {
+            report.add("tokenizer", Status::Pass, "Claude tokenizer 已加载")
+        }
+        TokenizerStatus::Missing => report.add(
+            "tokenizer",
+            Status::Warn,
+            "未找到 tokenizer 文件，将使用简单估算",
+        ),
+        TokenizerStatus::Corrupted { path, error } => report.add(
+            "tokenizer",
+            Status::Fail,
+            format!(
+                "{} 已损坏: {}（可配置 tokenizerUrl 与 tokenizerSha256 自动重新下载）",
+                path, error
+            ),
+        ),
+    }
+
+    let proxy = build_proxy_config(&config);
+
+    // 3. 代理连通性
+    if let Some(proxy) = &proxy {
+        check_proxy(proxy, &mut report).await;
+    }
+
+    // 4. 凭据有效性
+    let credential_regions =
+        check_credentials(&config, proxy.clone(), credentials_path, &mut report).await;
+
+    // 5. 上游连通性 + 6. 时钟偏差
+    let mut regions: BTreeSet<String> = credential_regions;
+    regions.insert(config.region.clone());
+    check_upstream(&config, proxy.as_ref(), &regions, &mut report).await;
+
+    finish(&report)
+}
+
+/// 输出汇总并返回退出码
+fn finish(report: &Report) -> i32 {
+    let failed = report.count(Status::Fail);
+    println!();
+    println!(
+        "汇总: {} 通过, {} 警告, {} 失败",
+        report.count(Status::Pass),
+        report.count(Status::Warn),
+        failed
+    );
+    if failed > 0 { 1 } else { 0 }
+}
+
+/// 构建代理配置（与主服务逻辑一致）
+fn build_proxy_config(config: &Config) -> Option<ProxyConfig> {
+    config.proxy_url.as_ref().map(|url| {
+        let mut proxy = ProxyConfig::new(url);
+        if let (Some(username), Some(password)) = (&config.proxy_username, &config.proxy_password) {
+            proxy = proxy.with_auth(username, password);
+        }
+        proxy
+    })
+}
+
+/// 检查配置项的有效性
+fn check_config(config: &Config, report: &mut Report) {
+    match config.api_key.as_deref().map(str::trim) {
+        Some(key) if !key.is_empty() => report.add("config.apiKey", Status::Pass, "已配置"),
+        _ if !config.api_keys.is_empty() => report.add(
+            "config.apiKey",
+            Status::Pass,
+            format!(
+                "未配置 apiKey，使用 apiKeys 中的 {} 个 Key",
+                config.api_keys.len()
+            ),
+        ),
+        _ => report.add(
+            "config.apiKey",
+            Status::Fail,
+            "未配置 apiKey 或 apiKeys，服务无法启动",
+        ),
+    }
+
+    if config.port == 0 {
+        report.add("config.port", Status::Fail, "端口不能为 0");
+    }
+
+    if config.region.trim().is_empty() {
+        report.add("config.region", Status::Fail, "region 不能为空");
+    }
+
+    if let Some(url) = &config.proxy_url
+        && let Err(e) = reqwest::Proxy::all(url)
+    {
+        report.add(
+            "config.proxyUrl",
+            Status::Fail,
+            format!("代理地址无效: {}", e),
+        );
+    }
+
+    match storage::open(config) {
+        Ok(s) => report.add(
+            "config.storageBackend",
+            Status::Pass,
+            format!("存储后端可用: {}", s.backend()),
+        ),
+        Err(e) => report.add("config.storageBackend", Status::Fail, e.to_string()),
+    }
+}
+
+/// 检查代理是否可以建立 TCP 连接
+async fn check_proxy(proxy: &ProxyConfig, report: &mut Report) {
+    let addr = match reqwest::Url::parse(&proxy.url) {
+        Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            _ => {
+                report.add(
+                    "proxy",
+                    Status::Fail,
+                    format!("无法解析代理地址: {}", proxy.url),
+                );
+                return;
+            }
+        },
+        Err(e) => {
+            report.add("proxy", Status::Fail, format!("无法解析代理地址: {}", e));
+            return;
+        }
+    };
+
+    let connect = tokio::net::TcpStream::connect(&addr);
+    match tokio::time::timeout(Duration::from_secs(NETWORK_TIMEOUT_SECS), connect).await {
+        Ok(Ok(_)) => report.add("proxy", Status::Pass, format!("代理 {} 可连接", addr)),
+        Ok(Err(e)) => report.add(
+            "proxy",
+            Status::Fail,
+            format!("连接代理 {} 失败: {}", addr, e),
+        ),
+        Err(_) => report.add("proxy", Status::Fail, format!("连接代理 {} 超时", addr)),
+    }
+}
+
+/// 逐个检查凭据 Token，返回凭据中配置的 region 集合
+///
+/// 只校验 refresh token 格式与 access token 有效期，并用未过期的 access token 查询额度；
+/// 不刷新 Token，避免轮换正在运行的实例所使用的 refresh token，也不回写凭据文件
+async fn check_credentials(
+    config: &Config,
+    proxy: Option<ProxyConfig>,
+    credentials_path: &str,
+    report: &mut Report,
+) -> BTreeSet<String> {
+    let mut regions = BTreeSet::new();
+
+    let credentials_config = match CredentialsConfig::load(credentials_path) {
+        Ok(c) => c,
+        Err(e) => {
+            report.add("credentials", Status::Fail, format!("加载凭证失败: {}", e));
+            return regions;
+        }
+    };
+    let is_multiple_format = credentials_config.is_multiple();
+    let credentials = credentials_config.into_sorted_credentials();
+    if credentials.is_empty() {
+        report.add("credentials", Status::Fail, "凭证文件中没有任何凭据");
+        return regions;
+    }
+    regions.extend(credentials.iter().flat_map(|c| {
+        c.region
+            .iter()
+            .chain(&c.api_region)
+            .chain(c.failover_regions.iter().filter(|r| !r.contains("://")))
+            .cloned()
+    }));
+
+    // 不传入凭据文件路径：补全 ID / machineId 也只在内存中进行
+    let manager = match MultiTokenManager::new(
+        config.clone(),
+        credentials,
+        proxy.clone(),
+        None,
+        is_multiple_format,
+    ) {
+        Ok(m) => m,
+        Err(e) => {
+            report.add("credentials", Status::Fail, format!("凭据配置无效: {}", e));
+            return regions;
+        }
+    };
+
+    let snapshot = manager.snapshot();
+    report.add(
+        "credentials",
+        Status::Pass,
+        format!("已加载 {} 个凭据", snapshot.total),
+    );
+
+    let credentials = manager.export_credentials();
+    for entry in snapshot.entries {
+        let name = format!("credential #{}", entry.id);
+        if entry.disabled {
+            report.add(name, Status::Warn, "凭据已禁用，跳过检查");
+            continue;
+        }
+        let Some(cred) = credentials.iter().find(|c| c.id == Some(entry.id)) else {
+            continue;
+        };
+        if let Err(e) = validate_refresh_token(cred) {
+            report.add(name, Status::Fail, format!("refreshToken 无效: {}", e));
+            continue;
+        }
+        let token = match cred.access_token.as_deref() {
+            Some(token) if !is_token_expired(cred) => token,
+            _ => {
+                report.add(
+                    name,
+                    Status::Warn,
+                    "access token 已过期或缺失，将在服务运行时刷新（自检不刷新 Token）",
+                );
+                continue;
+            }
+        };
+        match get_usage_limits(cred, config, token, proxy.as_ref()).await {
+            Ok(_) => report.add(name, Status::Pass, "Token 有效，额度查询成功"),
+            Err(e) => report.add(name, Status::Fail, format!("额度查询失败: {}", e)),
+        }
+    }
+
+    regions
+}
+
+/// 检查各 region 上游可达性，并基于响应 Date 头检测时钟偏差
+async fn check_upstream(
+    config: &Config,
+    proxy: Option<&ProxyConfig>,
+    regions: &BTreeSet<String>,
+    report: &mut Report,
+) {
+    let client = match build_client(proxy, NETWORK_TIMEOUT_SECS, config.tls_backend) {
+        Ok(c) => c,
+        Err(e) => {
+            report.add(
+                "upstream",
+                Status::Fail,
+                format!("创建 HTTP 客户端失败: {}", e),
+            );
+            return;
+        }
+    };
+
+    let mut skew_checked = false;
+    for region in regions {
+        let url = format!("https://q.{}.amazonaws.com/", region);
+        let name = format!("upstream {}", region);
+        match client.get(&url).send().await {
+            Ok(resp) => {
+                // 任何 HTTP 响应都说明网络可达（根路径通常返回 4xx）
+                report.add(
+                    name,
+                    Status::Pass,
+                    format!("可达（HTTP {}）", resp.status()),
+                );
+
+                if !skew_checked
+                    && let Some(server_time) = resp
+                        .headers()
+                        .get(reqwest::header::DATE)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(parse_http_date)
+                {
+                    skew_checked = true;
+                    check_clock_skew(server_time, Utc::now(), report);
+                }
+            }
+            Err(e) => report.add(name, Status::Fail, format!("无法访问 {}: {}", url, e)),
+        }
+    }
+
+    if !skew_checked {
+        report.add(
+            "clock skew",
+            Status::Warn,
+            "无法获取上游时间，跳过时钟偏差检查",
+        );
+    }
+}
+
+/// 比较上游时间与本地时间
+fn check_clock_skew(server_time: DateTime<Utc>, local_time: DateTime<Utc>, report: &mut Report) {
+    let skew = (local_time - server_time).num_seconds();
+    if skew.abs() > MAX_CLOCK_SKEW_SECS {
+        report.add(
+            "clock skew",
+            Status::Fail,
+            format!("本地时钟与上游相差 {} 秒，请同步系统时间", skew),
+        );
+    } else {
+        report.add("clock skew", Status::Pass, format!("偏差 {} 秒", skew));
+    }
+}
+
+/// 解析 HTTP Date 头（RFC 7231 IMF-fixdate，如 `Tue, 15 Nov 1994 08:12:31 GMT`）
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_http_date() {
+        let dt = parse_http_date("Tue, 15 Nov 1994 08:12:31 GMT").unwrap();
+        assert_eq!(dt.to_rfc3339(), "1994-11-15T08:12:31+00:00");
+        assert!(parse_http_date("not a date").is_none());
+    }
+
+    #[test]
+    fn test_check_clock_skew() {
+        let now = Utc::now();
+        let mut report = Report::default();
+        check_clock_skew(now, now + chrono::Duration::seconds(5), &mut report);
+        check_clock_skew(now, now + chrono::Duration::seconds(300), &mut report);
+        assert_eq!(report.results[0].status, Status::Pass);
+        assert_eq!(report.results[1].status, Status::Fail);
+    }
+
+    #[test]
+    fn test_check_config_missing_api_key() {
+        let mut report = Report::default();
+        check_config(&Config::default(), &mut report);
+        assert_eq!(report.count(Status::Fail), 1);
+        assert_eq!(report.results[0].name, "config.apiKey");
+    }
+}
@@ -0,0 +1,392 @@
+//! Gemini → Anthropic 请求转换
+//!
+//! 将 generateContent 请求转换为 Anthropic Messages 请求，
+//! 之后复用 `/v1/messages` 的处理流程生成 Kiro ConversationState
+
+use std::collections::VecDeque;
+
+use serde_json::{Value, json};
+
+use crate::anthropic::converter::map_model;
+use crate::anthropic::types::{Message, MessagesRequest, SystemMessage, Thinking, Tool};
+
+use super::types::{
+    Content, FunctionCallingConfig, FunctionDeclaration, GenerateContentRequest, Part,
+};
+
+/// 未指定输出上限时使用的默认值
+pub const DEFAULT_MAX_TOKENS: i32 = 8192;
+
+/// thinkingBudget 为 -1（由模型决定）时使用的思考预算
+const DYNAMIC_THINKING_BUDGET: i32 = 8192;
+
+/// Gemini 模型名映射到的 Claude 模型
+const FLASH_MODEL: &str = "claude-haiku-4-5-20251001";
+const DEFAULT_MODEL: &str = "claude-sonnet-4-5-20250929";
+
+/// 解析请求路径中的模型名
+///
+/// Claude 模型名原样使用；Gemini 模型名中 flash 系列映射到 Haiku，其余映射到 Sonnet
+pub fn resolve_model(model: &str) -> String {
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    if map_model(model).is_some() {
+        model.to_string()
+    } else if model.to_lowercase().contains("flash") {
+        FLASH_MODEL.to_string()
+    } else {
+        DEFAULT_MODEL.to_string()
+    }
+}
+
+/// 转换 generateContent 请求
+pub fn to_messages_request(
+    req: &GenerateContentRequest,
+    model: &str,
+    stream: bool,
+) -> MessagesRequest {
+    let system: Vec<SystemMessage> = req
+        .system_instruction
+        .iter()
+        .flat_map(|content| &content.parts)
+        .filter_map(|part| part.text.clone())
+        .filter(|text| !text.is_empty())
+        .map(|text| SystemMessage {
+            text,
+            cache_control: None,
+        })
+        .collect();
+
+    // 未携带 id 的 functionResponse 按名称匹配此前尚未返回结果的 functionCall
+    let mut pending_calls: VecDeque<(String, String)> = VecDeque::new();
+    let mut call_count = 0;
+    let messages = req
+        .contents
+        .iter()
+        .map(|content| convert_content(content, &mut pending_calls, &mut call_count))
+        .filter(|message| {
+            message
+                .content
+                .as_array()
+                .is_some_and(|blocks| !blocks.is_empty())
+        })
+        .collect();
+
+    let tools: Vec<Tool> = req
+        .tools
+        .iter()
+        .flat_map(|tool| &tool.function_declarations)
+        .map(convert_tool)
+        .collect();
+    let tool_choice = req
+        .tool_config
+        .as_ref()
+        .and_then(|c| c.function_calling_config.as_ref())
+        .and_then(convert_tool_choice);
+
+    let generation = req.generation_config.clone().unwrap_or_default();
+    let thinking = generation
+        .thinking_config
+        .as_ref()
+        .and_then(|c| c.thinking_budget)
+        .filter(|&budget| budget != 0)
+        .map(|budget| Thinking {
+            thinking_type: "enabled".to_string(),
+            budget_tokens: if budget > 0 {
+                budget
+            } else {
+                DYNAMIC_THINKING_BUDGET
+            },
+        });
+
+    MessagesRequest {
+        model: model.to_string(),
+        max_tokens: generation.max_output_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        messages,
+        stream,
+        system: (!system.is_empty()).then_some(system),
+        tools: (!tools.is_empty()).then_some(tools),
+        tool_choice,
+        thinking,
+        metadata: None,
+        stop_sequences: generation.stop_sequences,
+        // Gemini 温度取值范围为 0 ~ 2，Anthropic 为 0 ~ 1，超出部分按上限处理
+        temperature: generation.temperature.map(|t| t.clamp(0.0, 1.0)),
+        top_p: generation.top_p,
+        top_k: generation.top_k,
+    }
+}
+
+/// 将 Anthropic stop_reason 映射为 Gemini finishReason
+pub fn finish_reason(stop_reason: &str) -> &'static str {
+    match stop_reason {
+        "max_tokens" => "MAX_TOKENS",
+        _ => "STOP",
+    }
+}
+
+/// 转换一轮对话内容
+fn convert_content(
+    content: &Content,
+    pending_calls: &mut VecDeque<(String, String)>,
+    call_count: &mut usize,
+) -> Message {
+    let role = match content.role.as_deref() {
+        Some("model") => "assistant",
+        _ => "user",
+    };
+    let blocks = content
+        .parts
+        .iter()
+        .filter_map(|part| convert_part(part, role, pending_calls, call_count))
+        .collect();
+    Message {
+        role: role.to_string(),
+        content: Value::Array(blocks),
+    }
+}
+
+/// 转换单个内容片段
+fn convert_part(
+    part: &Part,
+    role: &str,
+    pending_calls: &mut VecDeque<(String, String)>,
+    call_count: &mut usize,
+) -> Option<Value> {
+    if let Some(call) = &part.function_call {
+        *call_count += 1;
+        let id = call
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("toolu_gemini_{}", call_count));
+        pending_calls.push_back((call.name.clone(), id.clone()));
+        return Some(json!({
+            "type": "tool_use",
+            "id": id,
+            "name": call.name,
+            "input": if call.args.is_object() { call.args.clone() } else { json!({}) }
+        }));
+    }
+
+    if let Some(response) = &part.function_response {
+        let id = match &response.id {
+            Some(id) => {
+                pending_calls.retain(|(_, pending)| pending != id);
+                id.clone()
+            }
+            None => pending_calls
+                .iter()
+                .position(|(name, _)| *name == response.name)
+                .and_then(|i| pending_calls.remove(i))
+                .map(|(_, id)| id)
+                .unwrap_or_else(|| format!("toolu_gemini_{}", response.name)),
+        };
+        let (content, is_error) = function_response_content(&response.response);
+        return Some(json!({
+            "type": "tool_result",
+            "tool_use_id": id,
+            "content": content,
+            "is_error": is_error
+        }));
+    }
+
+    if let Some(blob) = &part.inline_data {
+        let block_type = if blob.mime_type.starts_with("image/") {
+            "image"
+        } else if blob.mime_type == "application/pdf" {
+            "document"
+        } else {
+            tracing::warn!("不支持的 inlineData 类型，已忽略: {}", blob.mime_type);
+            return None;
+        };
+        return Some(json!({
+            "type": block_type,
+            "source": {"type": "base64", "media_type": blob.mime_type, "data": blob.data}
+        }));
+    }
+
+    // 历史中的思考内容没有签名，无法回传上游
+    let text = part.text.as_deref().filter(|text| !text.is_empty())?;
+    if part.thought && role == "assistant" {
+        return None;
+    }
+    Some(json!({"type": "text", "text": text}))
+}
+
+/// functionResponse.response 转换为 tool_result 内容
+///
+/// `{"output": "..."}` 取 output 文本，仅含 `error` 时标记为错误，其余按 JSON 文本传递
+fn function_response_content(response: &Value) -> (String, bool) {
+    if let Some(output) = response.get("output").and_then(Value::as_str) {
+        return (output.to_string(), false);
+    }
+    if let Some(error) = response.get("error")
+        && response.as_object().is_some_and(|o| o.len() == 1)
+    {
+        let message = error
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| error.to_string());
+        return (message, true);
+    }
+    (response.to_string(), false)
+}
+
+/// 转换函数声明
+fn convert_tool(declaration: &FunctionDeclaration) -> Tool {
+    let schema = declaration
+        .parameters_json_schema
+        .clone()
+        .or_else(|| declaration.parameters.clone().map(normalize_schema));
+    let input_schema = match schema {
+        Some(Value::Object(map)) => map.into_iter().collect(),
+        _ => [
+            ("type".to_string(), json!("object")),
+            ("properties".to_string(), json!({})),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    Tool {
+        tool_type: None,
+        name: declaration.name.clone(),
+        description: declaration.description.clone(),
+        input_schema,
+        max_uses: None,
+        cache_control: None,
+    }
+}
+
+/// 将 OpenAPI 风格 schema 中的大写类型名（如 `OBJECT`）转换为 JSON Schema 的小写形式
+fn normalize_schema(schema: Value) -> Value {
+    match schema {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| match (key.as_str(), value) {
+                    ("type", Value::String(t)) => (key, Value::String(t.to_lowercase())),
+                    (_, value) => (key, normalize_schema(value)),
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(normalize_schema).collect()),
+        other => other,
+    }
+}
+
+/// 转换函数调用模式（AUTO / ANY / NONE）
+fn convert_tool_choice(config: &FunctionCallingConfig) -> Option<Value> {
+    match config.mode.as_deref()?.to_uppercase().as_str() {
+        "AUTO" => Some(json!({"type": "auto"})),
+        "NONE" => Some(json!({"type": "none"})),
+        "ANY" => match config.allowed_function_names.as_slice() {
+            [name] => Some(json!({"type": "tool", "name": name})),
+            _ => Some(json!({"type": "any"})),
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: Value) -> GenerateContentRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_model() {
+        assert_eq!(
+            resolve_model("claude-opus-4-5-20251101"),
+            "claude-opus-4-5-20251101"
+        );
+        assert_eq!(resolve_model("models/gemini-2.5-flash"), FLASH_MODEL);
+        assert_eq!(resolve_model("gemini-2.5-pro"), DEFAULT_MODEL);
+    }
+
+    #[test]
+    fn test_convert_basic_conversation() {
+        let req = parse(json!({
+            "systemInstruction": {"parts": [{"text": "Be brief."}]},
+            "contents": [
+                {"role": "user", "parts": [{"text": "Hi"}]},
+                {"role": "model", "parts": [{"text": "thinking...", "thought": true}, {"text": "Hello"}]},
+                {"role": "user", "parts": [{"text": "Bye"}]}
+            ],
+            "generationConfig": {"maxOutputTokens": 100, "temperature": 1.5, "stopSequences": ["END"]}
+        }));
+        let converted = to_messages_request(&req, DEFAULT_MODEL, true);
+
+        assert!(converted.stream);
+        assert_eq!(converted.max_tokens, 100);
+        assert_eq!(converted.temperature, Some(1.0));
+        assert_eq!(converted.stop_sequences, Some(vec!["END".to_string()]));
+        assert_eq!(converted.system.unwrap()[0].text, "Be brief.");
+        assert_eq!(converted.messages.len(), 3);
+        assert_eq!(converted.messages[1].role, "assistant");
+        assert_eq!(
+            converted.messages[1].content,
+            json!([{"type": "text", "text": "Hello"}])
+        );
+    }
+
+    #[test]
+    fn test_convert_function_call_round_trip() {
+        let req = parse(json!({
+            "contents": [
+                {"role": "user", "parts": [{"text": "Weather?"}]},
+                {"role": "model", "parts": [{"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}]},
+                {"role": "user", "parts": [{"functionResponse": {"name": "get_weather", "response": {"output": "Sunny"}}}]}
+            ],
+            "tools": [{"functionDeclarations": [{
+                "name": "get_weather",
+                "description": "Get weather",
+                "parameters": {"type": "OBJECT", "properties": {"city": {"type": "STRING"}}}
+            }]}],
+            "toolConfig": {"functionCallingConfig": {"mode": "ANY", "allowedFunctionNames": ["get_weather"]}}
+        }));
+        let converted = to_messages_request(&req, DEFAULT_MODEL, false);
+
+        let call = &converted.messages[1].content[0];
+        let result = &converted.messages[2].content[0];
+        assert_eq!(call["type"], "tool_use");
+        assert_eq!(call["input"]["city"], "Paris");
+        assert_eq!(result["type"], "tool_result");
+        assert_eq!(result["tool_use_id"], call["id"]);
+        assert_eq!(result["content"], "Sunny");
+
+        let tool = &converted.tools.unwrap()[0];
+        assert_eq!(tool.input_schema["type"], "object");
+        assert_eq!(tool.input_schema["properties"]["city"]["type"], "string");
+        assert_eq!(
+            converted.tool_choice,
+            Some(json!({"type": "tool", "name": "get_weather"}))
+        );
+    }
+
+    #[test]
+    fn test_convert_thinking_and_inline_data() {
+        let req = parse(json!({
+            "contents": [{"role": "user", "parts": [
+                {"inlineData": {"mimeType": "image/png", "data": "aGk="}},
+                {"inlineData": {"mimeType": "audio/wav", "data": "aGk="}}
+            ]}],
+            "generationConfig": {"thinkingConfig": {"thinkingBudget": -1}}
+        }));
+        let converted = to_messages_request(&req, DEFAULT_MODEL, false);
+
+        let blocks = converted.messages[0].content.as_array().unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0]["source"]["media_type"], "image/png");
+        assert_eq!(
+            converted.thinking.unwrap().budget_tokens,
+            DYNAMIC_THINKING_BUDGET
+        );
+    }
+
+    #[test]
+    fn test_finish_reason() {
+        assert_eq!(finish_reason("end_turn"), "STOP");
+        assert_eq!(finish_reason("tool_use"), "STOP");
+        assert_eq!(finish_reason("max_tokens"), "MAX_TOKENS");
+    }
+}
@@ -0,0 +1,201 @@
+//! Gemini API Handler 函数
+
+use std::convert::Infallible;
+
+use axum::{
+    Json as JsonExtractor,
+    body::{Body, to_bytes},
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use bytes::Bytes;
+use futures::StreamExt;
+use serde_json::{Value, json};
+
+use crate::anthropic::handlers::post_messages;
+use crate::anthropic::middleware::AppState;
+use crate::common::api_keys::ClientKey;
+use crate::token;
+
+use super::converter::{self, resolve_model};
+use super::stream::{ChunkTranslator, SseParser, from_message, to_sse_data};
+use super::types::{CountTokensResponse, GenerateContentRequest};
+
+/// POST /v1beta/models/{model}:{action}
+///
+/// 按路径中的动作分发到 generateContent / streamGenerateContent / countTokens
+#[tracing::instrument(skip_all, fields(target = %target))]
+pub async fn model_action(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    Path(target): Path<String>,
+    JsonExtractor(body): JsonExtractor<Value>,
+) -> Response {
+    let Some((model, action)) = target.rsplit_once(':') else {
+        return gemini_error(StatusCode::NOT_FOUND, format!("Unknown method: {}", target));
+    };
+    tracing::info!(model = %model, action = %action, "Received Gemini request");
+
+    // countTokens 的请求体可以是 generateContent 请求本身，也可以包装在 generateContentRequest 中
+    let body = match (action, body.get("generateContentRequest")) {
+        ("countTokens", Some(inner)) => inner.clone(),
+        _ => body,
+    };
+    let request: GenerateContentRequest = match serde_json::from_value(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return gemini_error(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e));
+        }
+    };
+    let model = resolve_model(model);
+
+    match action {
+        "generateContent" => generate_content(state, client, headers, &request, &model).await,
+        "streamGenerateContent" => {
+            stream_generate_content(state, client, headers, &request, &model).await
+        }
+        "countTokens" => {
+            let request = converter::to_messages_request(&request, &model, false);
+            let total_tokens = token::count_all_tokens(
+                &request.model,
+                request.system.as_deref(),
+                &request.messages,
+                request.tools.as_deref(),
+            )
+            .await as i32;
+            Json(CountTokensResponse { total_tokens }).into_response()
+        }
+        _ => gemini_error(StatusCode::NOT_FOUND, format!("Unknown method: {}", action)),
+    }
+}
+
+/// 是否在响应中返回思考内容
+fn include_thoughts(request: &GenerateContentRequest) -> bool {
+    request
+        .generation_config
+        .as_ref()
+        .and_then(|c| c.thinking_config.as_ref())
+        .is_some_and(|c| c.include_thoughts)
+}
+
+/// 非流式生成
+async fn generate_content(
+    state: AppState,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    request: &GenerateContentRequest,
+    model: &str,
+) -> Response {
+    let payload = converter::to_messages_request(request, model, false);
+    let response = post_messages(State(state), client, headers, JsonExtractor(payload)).await;
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<Value>(&body).ok())
+        .unwrap_or(Value::Null);
+    if !status.is_success() {
+        return gemini_error(status, error_message(&body, status));
+    }
+    Json(from_message(&body, model, include_thoughts(request))).into_response()
+}
+
+/// 流式生成（SSE，每个事件为一个 generateContent 响应 chunk）
+async fn stream_generate_content(
+    state: AppState,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    request: &GenerateContentRequest,
+    model: &str,
+) -> Response {
+    let payload = converter::to_messages_request(request, model, true);
+    let response = post_messages(State(state), client, headers, JsonExtractor(payload)).await;
+    let status = response.status();
+    if !status.is_success() {
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .ok()
+            .and_then(|body| serde_json::from_slice::<Value>(&body).ok())
+            .unwrap_or(Value::Null);
+        return gemini_error(status, error_message(&body, status));
+    }
+
+    let mut parser = SseParser::default();
+    let mut translator = ChunkTranslator::new(model, include_thoughts(request));
+    let stream = response
+        .into_body()
+        .into_data_stream()
+        .map(move |chunk| {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    tracing::warn!("读取响应流失败: {}", e);
+                    return String::new();
+                }
+            };
+            parser
+                .feed(&chunk)
+                .iter()
+                .filter_map(|event| {
+                    if event["type"] == "error" {
+                        let message = event["error"]["message"].as_str().unwrap_or_default();
+                        return Some(to_sse_data(&error_body(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            message,
+                        )));
+                    }
+                    translator.translate(event).map(|chunk| to_sse_data(&chunk))
+                })
+                .collect()
+        })
+        .filter(|data: &String| futures::future::ready(!data.is_empty()))
+        .map(|data| Ok::<_, Infallible>(Bytes::from(data)));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::CONNECTION, "keep-alive")
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
+
+/// 提取 Anthropic 错误响应中的消息
+fn error_message(body: &Value, status: StatusCode) -> String {
+    body["error"]["message"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("Upstream request failed with status {}", status))
+}
+
+/// Google API 错误状态名
+fn error_status(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 | 413 => "INVALID_ARGUMENT",
+        401 => "UNAUTHENTICATED",
+        403 => "PERMISSION_DENIED",
+        404 => "NOT_FOUND",
+        409 => "ABORTED",
+        429 => "RESOURCE_EXHAUSTED",
+        503 => "UNAVAILABLE",
+        504 => "DEADLINE_EXCEEDED",
+        _ => "INTERNAL",
+    }
+}
+
+/// Google API 格式的错误响应体
+fn error_body(status: StatusCode, message: &str) -> Value {
+    json!({
+        "error": {
+            "code": status.as_u16(),
+            "message": message,
+            "status": error_status(status)
+        }
+    })
+}
+
+fn gemini_error(status: StatusCode, message: String) -> Response {
+    (status, Json(error_body(status, &message))).into_response()
+}
@@ -0,0 +1,16 @@
+//! Gemini API 兼容服务模块
+//!
+//! 提供与 Google Generative Language API 兼容的端点，供 Gemini CLI 等仅支持 Gemini 协议的工具使用。
+//! 请求先转换为 Anthropic Messages 格式，经 `/v1/messages` 的处理流程执行后再转换回 Gemini 格式。
+//!
+//! # 支持的端点
+//! - `POST /v1beta/models/{model}:generateContent` - 生成内容
+//! - `POST /v1beta/models/{model}:streamGenerateContent` - 流式生成内容（SSE）
+//! - `POST /v1beta/models/{model}:countTokens` - 计算 token 数量
+
+mod converter;
+mod handlers;
+mod stream;
+pub mod types;
+
+pub use handlers::model_action;
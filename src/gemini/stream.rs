@@ -0,0 +1,272 @@
+//! Anthropic → Gemini 响应转换
+//!
+//! 将 `/v1/messages` 的流式事件逐个转换为 generateContent 响应 chunk，
+//! 非流式响应直接由完整消息转换
+
+use serde_json::{Value, json};
+
+use super::converter::finish_reason;
+use super::types::{Candidate, CandidateContent, GenerateContentResponse, UsageMetadata};
+
+/// 按空行拆分 SSE 文本，提取每个事件的 data 并解析为 JSON
+///
+/// 未完整接收的事件保留在缓冲区中，注释行（保活、故障转移提示）被忽略
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: String,
+}
+
+impl SseParser {
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Value> {
+        self.buffer.push_str(&String::from_utf8_lossy(chunk));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find("\n\n") {
+            let raw: String = self.buffer.drain(..end + 2).collect();
+            let data: String = raw
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(str::trim_start)
+                .collect();
+            if let Ok(event) = serde_json::from_str(&data) {
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+/// 流式事件转换器
+pub struct ChunkTranslator {
+    model: String,
+    response_id: String,
+    include_thoughts: bool,
+    /// 正在接收参数的工具调用：(id, name, 参数 JSON 片段)
+    tool_call: Option<(String, String, String)>,
+    input_tokens: i32,
+    output_tokens: i32,
+}
+
+impl ChunkTranslator {
+    pub fn new(model: impl Into<String>, include_thoughts: bool) -> Self {
+        Self {
+            model: model.into(),
+            response_id: String::new(),
+            include_thoughts,
+            tool_call: None,
+            input_tokens: 0,
+            output_tokens: 0,
+        }
+    }
+
+    /// 转换单个 Anthropic 事件，不产生输出的事件返回 None
+    pub fn translate(&mut self, event: &Value) -> Option<GenerateContentResponse> {
+        match event["type"].as_str()? {
+            "message_start" => {
+                let message = &event["message"];
+                self.response_id = message["id"].as_str().unwrap_or_default().to_string();
+                self.input_tokens = usage_tokens(&message["usage"], "input_tokens");
+                None
+            }
+            "content_block_start" => {
+                let block = &event["content_block"];
+                if block["type"] == "tool_use" {
+                    self.tool_call = Some((
+                        block["id"].as_str().unwrap_or_default().to_string(),
+                        block["name"].as_str().unwrap_or_default().to_string(),
+                        String::new(),
+                    ));
+                }
+                None
+            }
+            "content_block_delta" => {
+                let delta = &event["delta"];
+                match delta["type"].as_str()? {
+                    "text_delta" => Some(self.chunk(vec![json!({"text": delta["text"]})], None)),
+                    "thinking_delta" if self.include_thoughts => Some(self.chunk(
+                        vec![json!({"text": delta["thinking"], "thought": true})],
+                        None,
+                    )),
+                    "input_json_delta" => {
+                        if let Some((_, _, input)) = &mut self.tool_call {
+                            input.push_str(delta["partial_json"].as_str().unwrap_or_default());
+                        }
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            "content_block_stop" => {
+                let (id, name, input) = self.tool_call.take()?;
+                let args: Value = serde_json::from_str(&input).unwrap_or_else(|_| json!({}));
+                Some(self.chunk(vec![function_call(&id, &name, args)], None))
+            }
+            "message_delta" => {
+                self.output_tokens = usage_tokens(&event["usage"], "output_tokens");
+                let reason =
+                    finish_reason(event["delta"]["stop_reason"].as_str().unwrap_or_default());
+                let mut chunk = self.chunk(Vec::new(), Some(reason));
+                chunk.usage_metadata =
+                    Some(UsageMetadata::new(self.input_tokens, self.output_tokens));
+                Some(chunk)
+            }
+            _ => None,
+        }
+    }
+
+    fn chunk(&self, parts: Vec<Value>, finish_reason: Option<&str>) -> GenerateContentResponse {
+        response(&self.model, &self.response_id, parts, finish_reason, None)
+    }
+}
+
+/// 将完整的 Anthropic 消息转换为 generateContent 响应
+pub fn from_message(
+    message: &Value,
+    model: &str,
+    include_thoughts: bool,
+) -> GenerateContentResponse {
+    let parts = message["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|block| match block["type"].as_str()? {
+            "text" => Some(json!({"text": block["text"]})),
+            "thinking" if include_thoughts => {
+                Some(json!({"text": block["thinking"], "thought": true}))
+            }
+            "tool_use" => Some(function_call(
+                block["id"].as_str().unwrap_or_default(),
+                block["name"].as_str().unwrap_or_default(),
+                block["input"].clone(),
+            )),
+            _ => None,
+        })
+        .collect();
+    let usage = UsageMetadata::new(
+        usage_tokens(&message["usage"], "input_tokens"),
+        usage_tokens(&message["usage"], "output_tokens"),
+    );
+    response(
+        model,
+        message["id"].as_str().unwrap_or_default(),
+        parts,
+        Some(finish_reason(
+            message["stop_reason"].as_str().unwrap_or_default(),
+        )),
+        Some(usage),
+    )
+}
+
+fn usage_tokens(usage: &Value, field: &str) -> i32 {
+    usage[field].as_i64().unwrap_or(0) as i32
+}
+
+fn function_call(id: &str, name: &str, args: Value) -> Value {
+    json!({"functionCall": {"id": id, "name": name, "args": args}})
+}
+
+fn response(
+    model: &str,
+    response_id: &str,
+    parts: Vec<Value>,
+    finish_reason: Option<&str>,
+    usage_metadata: Option<UsageMetadata>,
+) -> GenerateContentResponse {
+    GenerateContentResponse {
+        candidates: vec![Candidate {
+            content: CandidateContent {
+                role: "model",
+                parts,
+            },
+            finish_reason: finish_reason.map(str::to_string),
+            index: 0,
+        }],
+        usage_metadata,
+        model_version: model.to_string(),
+        response_id: response_id.to_string(),
+    }
+}
+
+/// 格式化为 SSE data 行
+pub fn to_sse_data(chunk: &impl serde::Serialize) -> String {
+    format!(
+        "data: {}\n\n",
+        serde_json::to_string(chunk).unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_handles_split_events_and_comments() {
+        let mut parser = SseParser::default();
+        assert!(
+            parser
+                .feed(b": ping\n\nevent: message_start\ndata: {\"type\":")
+                .is_empty()
+        );
+        let events =
+            parser.feed(b"\"message_start\"}\n\nevent: ping\ndata: {\"type\":\"ping\"}\n\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["type"], "message_start");
+        assert_eq!(events[1]["type"], "ping");
+    }
+
+    #[test]
+    fn test_translate_stream_chunks() {
+        let events = [
+            json!({"type": "message_start", "message": {"id": "msg_1", "usage": {"input_tokens": 12}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "thinking", "thinking": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "hmm"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "text_delta", "text": "Hi"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "content_block_start", "index": 2, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "ls", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 2, "delta": {"type": "input_json_delta", "partial_json": "{\"path\":"}}),
+            json!({"type": "content_block_delta", "index": 2, "delta": {"type": "input_json_delta", "partial_json": "\".\"}"}}),
+            json!({"type": "content_block_stop", "index": 2}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 7}}),
+            json!({"type": "message_stop"}),
+        ];
+        let mut translator = ChunkTranslator::new("claude-sonnet-4-5-20250929", false);
+        let chunks: Vec<Value> = events
+            .iter()
+            .filter_map(|e| translator.translate(e))
+            .map(|c| serde_json::to_value(c).unwrap())
+            .collect();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks[0]["candidates"][0]["content"]["parts"][0]["text"],
+            "Hi"
+        );
+        assert_eq!(chunks[0]["responseId"], "msg_1");
+        let call = &chunks[1]["candidates"][0]["content"]["parts"][0]["functionCall"];
+        assert_eq!(call["name"], "ls");
+        assert_eq!(call["args"]["path"], ".");
+        assert_eq!(chunks[2]["candidates"][0]["finishReason"], "STOP");
+        assert_eq!(chunks[2]["usageMetadata"]["totalTokenCount"], 19);
+    }
+
+    #[test]
+    fn test_from_message() {
+        let message = json!({
+            "id": "msg_1",
+            "content": [
+                {"type": "thinking", "thinking": "hmm"},
+                {"type": "text", "text": "Done"}
+            ],
+            "stop_reason": "max_tokens",
+            "usage": {"input_tokens": 3, "output_tokens": 4}
+        });
+        let value = serde_json::to_value(from_message(&message, "claude-haiku-4-5-20251001", true))
+            .unwrap();
+        let parts = &value["candidates"][0]["content"]["parts"];
+        assert_eq!(parts[0]["thought"], true);
+        assert_eq!(parts[1]["text"], "Done");
+        assert_eq!(value["candidates"][0]["finishReason"], "MAX_TOKENS");
+        assert_eq!(value["usageMetadata"]["promptTokenCount"], 3);
+    }
+}
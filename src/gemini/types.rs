@@ -0,0 +1,186 @@
+//! Gemini generateContent API 类型定义
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// === 请求类型 ===
+
+/// generateContent / streamGenerateContent / countTokens 请求体
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentRequest {
+    #[serde(default)]
+    pub contents: Vec<Content>,
+    pub system_instruction: Option<Content>,
+    #[serde(default)]
+    pub tools: Vec<GeminiTool>,
+    pub tool_config: Option<ToolConfig>,
+    pub generation_config: Option<GenerationConfig>,
+}
+
+/// 一轮对话内容
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Content {
+    /// user / model（systemInstruction 中可省略）
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+/// 内容片段
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    pub text: Option<String>,
+    /// 是否为思考内容
+    #[serde(default)]
+    pub thought: bool,
+    pub inline_data: Option<Blob>,
+    pub function_call: Option<FunctionCall>,
+    pub function_response: Option<FunctionResponse>,
+}
+
+/// 内联二进制数据（base64）
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    pub mime_type: String,
+    pub data: String,
+}
+
+/// 模型发起的函数调用
+#[derive(Debug, Clone, Deserialize)]
+pub struct FunctionCall {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+/// 函数调用结果
+#[derive(Debug, Clone, Deserialize)]
+pub struct FunctionResponse {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub response: Value,
+}
+
+/// 工具定义（仅支持函数声明）
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiTool {
+    #[serde(default)]
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+/// 函数声明
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionDeclaration {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// OpenAPI 风格的参数 schema（类型名为大写）
+    pub parameters: Option<Value>,
+    /// JSON Schema 格式的参数定义（优先于 parameters）
+    pub parameters_json_schema: Option<Value>,
+}
+
+/// 工具调用配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolConfig {
+    pub function_calling_config: Option<FunctionCallingConfig>,
+}
+
+/// 函数调用模式
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCallingConfig {
+    /// AUTO / ANY / NONE
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub allowed_function_names: Vec<String>,
+}
+
+/// 生成参数
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    pub max_output_tokens: Option<i32>,
+    /// 采样温度（0 ~ 2）
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<i32>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub thinking_config: Option<ThinkingConfig>,
+}
+
+/// 思考配置
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThinkingConfig {
+    /// 思考 token 预算（0 关闭，-1 由模型决定）
+    pub thinking_budget: Option<i32>,
+    /// 是否在响应中返回思考内容
+    #[serde(default)]
+    pub include_thoughts: bool,
+}
+
+// === 响应类型 ===
+
+/// generateContent 响应（流式响应的每个 chunk 格式相同）
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentResponse {
+    pub candidates: Vec<Candidate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_metadata: Option<UsageMetadata>,
+    pub model_version: String,
+    pub response_id: String,
+}
+
+/// 候选回答
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    pub content: CandidateContent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    pub index: u32,
+}
+
+/// 候选回答内容
+#[derive(Debug, Serialize)]
+pub struct CandidateContent {
+    pub role: &'static str,
+    /// text / thought / functionCall 片段
+    pub parts: Vec<Value>,
+}
+
+/// Token 用量
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    pub prompt_token_count: i32,
+    pub candidates_token_count: i32,
+    pub total_token_count: i32,
+}
+
+impl UsageMetadata {
+    pub fn new(prompt_token_count: i32, candidates_token_count: i32) -> Self {
+        Self {
+            prompt_token_count,
+            candidates_token_count,
+            total_token_count: prompt_token_count + candidates_token_count,
+        }
+    }
+}
+
+/// countTokens 响应
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CountTokensResponse {
+    pub total_tokens: i32,
+}
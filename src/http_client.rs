@@ -2,13 +2,13 @@
 //!
 //! 提供统一的 HTTP Client 构建功能，支持代理配置
 
-use reqwest::{Client, Proxy};
+use reqwest::{Client, ClientBuilder, Proxy};
 use std::time::Duration;
 
-use crate::model::config::TlsBackend;
+use crate::model::config::{Config, TlsBackend};
 
 /// 代理配置
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct ProxyConfig {
     /// 代理地址，支持 http/https/socks5
     pub url: String,
@@ -28,6 +28,17 @@ impl ProxyConfig {
         }
     }
 
+    /// 从应用配置读取代理设置（未配置 proxyUrl 时为 None）
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.proxy_url.as_ref().map(|url| {
+            let proxy = Self::new(url);
+            match (&config.proxy_username, &config.proxy_password) {
+                (Some(username), Some(password)) => proxy.with_auth(username, password),
+                _ => proxy,
+            }
+        })
+    }
+
     /// 设置认证信息
     pub fn with_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
         self.username = Some(username.into());
@@ -49,7 +60,32 @@ pub fn build_client(
     timeout_secs: u64,
     tls_backend: TlsBackend,
 ) -> anyhow::Result<Client> {
+    build_client_with_connect_timeout(proxy, timeout_secs, None, tls_backend)
+}
+
+/// 构建 HTTP Client，并限制建立连接（含 TLS 握手）的时间
+///
+/// `connect_timeout` 为 None 时不单独限制，仅受总超时约束
+pub fn build_client_with_connect_timeout(
+    proxy: Option<&ProxyConfig>,
+    timeout_secs: u64,
+    connect_timeout: Option<Duration>,
+    tls_backend: TlsBackend,
+) -> anyhow::Result<Client> {
+    Ok(client_builder(proxy, timeout_secs, connect_timeout, tls_backend)?.build()?)
+}
+
+/// 创建已配置超时、TLS 后端与代理的 ClientBuilder，供需要额外定制（DNS 解析、重定向策略等）的调用方使用
+pub fn client_builder(
+    proxy: Option<&ProxyConfig>,
+    timeout_secs: u64,
+    connect_timeout: Option<Duration>,
+    tls_backend: TlsBackend,
+) -> anyhow::Result<ClientBuilder> {
     let mut builder = Client::builder().timeout(Duration::from_secs(timeout_secs));
+    if let Some(connect_timeout) = connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
 
     if tls_backend == TlsBackend::Rustls {
         builder = builder.use_rustls_tls();
@@ -67,7 +103,7 @@ pub fn build_client(
         tracing::debug!("HTTP Client 使用代理: {}", proxy_config.url);
     }
 
-    Ok(builder.build()?)
+    Ok(builder)
 }
 
 #[cfg(test)]
@@ -0,0 +1,139 @@
+//! 凭据余额定时轮询
+//!
+//! 按 `balancePollIntervalSecs` 定期查询各启用凭据的额度并写入余额历史；
+//! 配置了 `balanceAlertThresholdPercent` 时，剩余额度跌破阈值的凭据会触发告警
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::common::alert;
+use crate::kiro::token_manager::MultiTokenManager;
+use crate::storage::balance_history::BalanceHistory;
+
+/// 启动后台轮询任务（间隔为 0 时不启动）
+///
+/// 启动后立即采样一次，之后每个间隔采样一次；单个凭据查询失败只记录日志
+pub fn spawn(token_manager: Arc<MultiTokenManager>, history: Arc<BalanceHistory>) {
+    let interval_secs = token_manager.config().balance_poll_interval_secs;
+    if interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // 已告警的凭据：余额恢复到阈值以上（如额度重置）前不重复告警
+        let mut alerted = HashSet::new();
+        loop {
+            interval.tick().await;
+            poll_once(&token_manager, &history, &mut alerted).await;
+        }
+    });
+    tracing::info!("凭据余额轮询已启用，间隔 {} 秒", interval_secs);
+}
+
+/// 对所有启用的凭据采样一次
+///
+/// 因额度用尽被禁用的凭据同样采样，以便获知重置时间并在额度恢复后自动重新启用
+async fn poll_once(
+    token_manager: &MultiTokenManager,
+    history: &BalanceHistory,
+    alerted: &mut HashSet<u64>,
+) {
+    let threshold = token_manager.config().balance_alert_threshold_percent;
+    let ids: Vec<u64> = token_manager
+        .snapshot()
+        .entries
+        .into_iter()
+        .filter(|e| !e.disabled || e.disabled_reason == Some("quota_exhausted"))
+        .map(|e| e.id)
+        .collect();
+
+    let mut recorded = 0;
+    for id in ids {
+        let usage = match token_manager.get_usage_limits_for(id).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                tracing::warn!("轮询凭据 #{} 余额失败: {}", id, e);
+                continue;
+            }
+        };
+        let (current_usage, usage_limit) = (usage.current_usage(), usage.usage_limit());
+        match history.record(id, current_usage, usage_limit) {
+            Ok(()) => recorded += 1,
+            Err(e) => tracing::warn!("记录凭据 #{} 余额历史失败: {}", id, e),
+        }
+        if let Some(threshold) = threshold {
+            check_threshold(id, current_usage, usage_limit, threshold, alerted);
+        }
+    }
+    tracing::debug!("凭据余额轮询完成，记录 {} 个凭据", recorded);
+}
+
+/// 剩余额度百分比（总额度为 0 时返回 None）
+fn remaining_percent(current_usage: f64, usage_limit: f64) -> Option<f64> {
+    (usage_limit > 0.0).then(|| ((usage_limit - current_usage) / usage_limit * 100.0).max(0.0))
+}
+
+/// 剩余额度跌破阈值时告警，恢复后清除告警状态
+///
+/// 返回本次是否触发告警
+fn check_threshold(
+    id: u64,
+    current_usage: f64,
+    usage_limit: f64,
+    threshold: f64,
+    alerted: &mut HashSet<u64>,
+) -> bool {
+    let Some(remaining) = remaining_percent(current_usage, usage_limit) else {
+        return false;
+    };
+    if remaining >= threshold {
+        alerted.remove(&id);
+        return false;
+    }
+    if !alerted.insert(id) {
+        return false;
+    }
+
+    let message = format!(
+        "凭据 #{} 剩余额度 {:.1}%，低于告警阈值 {}%（已用 {:.2} / {:.2}）",
+        id, remaining, threshold, current_usage, usage_limit
+    );
+    tracing::warn!("{}", message);
+    alert::notify(
+        "balance_low",
+        &format!("balance_low:{}", id),
+        message,
+        json!({
+            "credentialId": id,
+            "currentUsage": current_usage,
+            "usageLimit": usage_limit,
+            "remainingPercent": remaining,
+            "thresholdPercent": threshold,
+        }),
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_threshold_alerts_once_per_crossing() {
+        let mut alerted = HashSet::new();
+        assert!(!check_threshold(1, 50.0, 100.0, 20.0, &mut alerted));
+        assert!(check_threshold(1, 85.0, 100.0, 20.0, &mut alerted));
+        // 仍低于阈值时不重复告警
+        assert!(!check_threshold(1, 90.0, 100.0, 20.0, &mut alerted));
+        // 额度重置后再次跌破阈值重新告警
+        assert!(!check_threshold(1, 0.0, 100.0, 20.0, &mut alerted));
+        assert!(check_threshold(1, 95.0, 100.0, 20.0, &mut alerted));
+        // 总额度未知时不告警
+        assert!(!check_threshold(2, 0.0, 0.0, 20.0, &mut alerted));
+    }
+}
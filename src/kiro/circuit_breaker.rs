@@ -0,0 +1,161 @@
+//! 凭据熔断器
+//!
+//! 按凭据维护 closed / open / half-open 三态：
+//! - closed：正常参与调度，连续被拒绝（401/403/429）达到 `breakerThreshold` 次后打开
+//! - open：在 `breakerCooldownSecs` 内跳过该凭据
+//! - half-open：冷却结束后放行一个探测请求，成功则关闭，再次被拒绝则重新打开
+//!
+//! `breakerThreshold` 为 0 时熔断器不生效
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::model::config::ResilienceConfig;
+
+/// 熔断器状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// 单个凭据的熔断器
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreaker {
+    /// 连续被拒绝次数
+    rejections: u32,
+    /// 打开时间（None 表示 closed）
+    opened_at: Option<Instant>,
+    /// half-open 状态下探测请求的发出时间
+    probe_started_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// 当前状态
+    pub fn state(&self, config: &ResilienceConfig) -> BreakerState {
+        match self.opened_at {
+            None => BreakerState::Closed,
+            Some(at) if at.elapsed() < cooldown(config) => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    /// 是否允许向该凭据发送请求
+    ///
+    /// half-open 状态下同一时间只放行一个探测请求；探测请求在一个冷却时间内
+    /// 没有结果（如网络错误）时视为丢失，允许重新探测
+    pub fn allows_request(&self, config: &ResilienceConfig) -> bool {
+        match self.state(config) {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen => self
+                .probe_started_at
+                .is_none_or(|at| at.elapsed() >= cooldown(config)),
+        }
+    }
+
+    /// 凭据被选中时调用，half-open 状态下记录探测请求
+    pub fn on_acquire(&mut self, config: &ResilienceConfig) {
+        if self.state(config) == BreakerState::HalfOpen {
+            self.probe_started_at = Some(Instant::now());
+        }
+    }
+
+    /// 记录一次成功请求，返回熔断器是否由此关闭
+    pub fn on_success(&mut self) -> bool {
+        let was_open = self.opened_at.is_some();
+        *self = Self::default();
+        was_open
+    }
+
+    /// 记录一次被拒绝的请求，返回熔断器是否由此打开
+    pub fn on_rejection(&mut self, config: &ResilienceConfig) -> bool {
+        if config.breaker_threshold == 0 {
+            return false;
+        }
+        if self.state(config) == BreakerState::HalfOpen {
+            // 探测失败：重新打开并重新计算冷却时间
+            self.opened_at = Some(Instant::now());
+            self.probe_started_at = None;
+            return true;
+        }
+        self.rejections += 1;
+        if self.opened_at.is_none() && self.rejections >= config.breaker_threshold {
+            self.opened_at = Some(Instant::now());
+            return true;
+        }
+        false
+    }
+}
+
+fn cooldown(config: &ResilienceConfig) -> Duration {
+    Duration::from_secs(config.breaker_cooldown_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resilience(cooldown_secs: u64) -> ResilienceConfig {
+        ResilienceConfig {
+            breaker_threshold: 2,
+            breaker_cooldown_secs: cooldown_secs,
+            ..ResilienceConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_opens_after_threshold_and_closes_on_success() {
+        let config = resilience(60);
+        let mut breaker = CircuitBreaker::default();
+
+        assert!(!breaker.on_rejection(&config));
+        assert!(breaker.allows_request(&config));
+        assert!(breaker.on_rejection(&config));
+        assert_eq!(breaker.state(&config), BreakerState::Open);
+        assert!(!breaker.allows_request(&config));
+
+        assert!(breaker.on_success());
+        assert_eq!(breaker.state(&config), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_half_open_allows_single_probe() {
+        // 冷却时间为 0：打开后立即进入 half-open
+        let config = resilience(0);
+        let mut breaker = CircuitBreaker::default();
+        breaker.on_rejection(&config);
+        breaker.on_rejection(&config);
+        assert_eq!(breaker.state(&config), BreakerState::HalfOpen);
+        assert!(breaker.allows_request(&config));
+
+        // 探测失败后重新打开
+        breaker.on_acquire(&config);
+        assert!(breaker.on_rejection(&config));
+        assert!(breaker.opened_at.is_some());
+        assert!(breaker.probe_started_at.is_none());
+
+        // 探测进行中时不再放行
+        let config = resilience(60);
+        breaker.opened_at = Some(Instant::now() - Duration::from_secs(61));
+        breaker.on_acquire(&config);
+        assert_eq!(breaker.state(&config), BreakerState::HalfOpen);
+        assert!(!breaker.allows_request(&config));
+    }
+
+    #[test]
+    fn test_disabled_when_threshold_zero() {
+        let config = ResilienceConfig {
+            breaker_threshold: 0,
+            ..ResilienceConfig::default()
+        };
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..10 {
+            assert!(!breaker.on_rejection(&config));
+        }
+        assert!(breaker.allows_request(&config));
+    }
+}
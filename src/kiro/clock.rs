@@ -0,0 +1,86 @@
+//! 系统时钟偏差校正
+//!
+//! 容器等环境中本地时钟可能漂移，而 Token 的 `expiresAt` 以上游时间为准：
+//! 本地时钟偏慢时会过晚刷新 Token，导致上游返回 401 并被误计入凭据失败次数。
+//! 这里根据上游响应的 `Date` 头估算偏差，过期判断与过期时间计算均使用校正后的时间
+
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{DATE, HeaderMap};
+
+/// 低于该值的偏差视为 `Date` 头精度（秒级）与网络延迟带来的误差，忽略
+const MIN_SKEW_SECS: i64 = 2;
+
+/// 偏差超过该值时输出警告
+const WARN_SKEW_SECS: i64 = 30;
+
+/// 当前估算的偏差（上游时间 - 本地时间，秒）
+static SKEW_SECS: AtomicI64 = AtomicI64::new(0);
+
+/// 根据上游响应头更新时钟偏差
+pub fn observe(headers: &HeaderMap) {
+    let Some(skew) = headers
+        .get(DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|date| skew_from_date(date, Utc::now()))
+    else {
+        return;
+    };
+
+    let previous = SKEW_SECS.swap(skew, Ordering::Relaxed);
+    if skew.abs() >= WARN_SKEW_SECS && (skew - previous).abs() >= WARN_SKEW_SECS {
+        tracing::warn!(
+            "检测到系统时钟与上游相差 {} 秒，Token 过期判断将按上游时间校正",
+            skew
+        );
+    } else if previous.abs() >= WARN_SKEW_SECS && skew.abs() < WARN_SKEW_SECS {
+        tracing::info!("系统时钟偏差已恢复正常（{} 秒）", skew);
+    }
+}
+
+/// 当前估算的时钟偏差（秒）
+pub fn skew_secs() -> i64 {
+    SKEW_SECS.load(Ordering::Relaxed)
+}
+
+/// 按上游时间校正后的当前时间
+pub fn now() -> DateTime<Utc> {
+    Utc::now() + Duration::seconds(skew_secs())
+}
+
+/// 由 `Date` 头（RFC 2822 / IMF-fixdate）计算偏差，过小的偏差归零
+fn skew_from_date(date: &str, local: DateTime<Utc>) -> Option<i64> {
+    let remote = DateTime::parse_from_rfc2822(date).ok()?;
+    let skew = (remote.with_timezone(&Utc) - local).num_seconds();
+    Some(if skew.abs() < MIN_SKEW_SECS { 0 } else { skew })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skew_from_date() {
+        let local = DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        // 本地时钟慢了 5 分钟
+        assert_eq!(
+            skew_from_date("Wed, 01 Jan 2025 00:05:00 GMT", local),
+            Some(300)
+        );
+        // 本地时钟快了 2 分钟
+        assert_eq!(
+            skew_from_date("Tue, 31 Dec 2024 23:58:00 GMT", local),
+            Some(-120)
+        );
+        // 秒级误差忽略
+        assert_eq!(
+            skew_from_date("Wed, 01 Jan 2025 00:00:01 GMT", local),
+            Some(0)
+        );
+        assert_eq!(skew_from_date("not a date", local), None);
+    }
+}
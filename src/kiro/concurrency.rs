@@ -0,0 +1,145 @@
+//! 凭据自适应并发上限
+//!
+//! 采用 AIMD（加性增、乘性减）策略按凭据调整最大并发数：
+//! - 请求成功且首字节延迟正常：上限每轮（约等于当前上限个请求）增加 1
+//! - 被上游限流（429）或首字节延迟超过基线的 `latencyTolerance` 倍：上限乘以 `backoffRatio`
+//!
+//! 关闭 `concurrency.adaptive` 时上限固定为 `initialLimit`。
+//! `minLimit` 大于 `maxLimit` 的配置在加载时即被拒绝（见 [`ConcurrencyConfig::validate`]）
+
+use std::time::Duration;
+
+use crate::model::config::ConcurrencyConfig;
+
+/// 延迟基线（EWMA）的平滑系数
+const LATENCY_EWMA_ALPHA: f64 = 0.1;
+
+/// 单个凭据的并发上限状态
+#[derive(Debug, Clone)]
+pub struct AdaptiveLimit {
+    /// 当前上限（允许为小数，取整后生效）
+    limit: f64,
+    /// 首字节延迟基线（毫秒）
+    latency_baseline_ms: Option<f64>,
+}
+
+impl AdaptiveLimit {
+    pub fn new(config: &ConcurrencyConfig) -> Self {
+        Self {
+            limit: config
+                .initial_limit
+                .clamp(config.min_limit, config.max_limit) as f64,
+            latency_baseline_ms: None,
+        }
+    }
+
+    /// 当前生效的并发上限
+    pub fn limit(&self) -> usize {
+        self.limit.floor().max(1.0) as usize
+    }
+
+    /// 记录一次成功请求，返回上限是否发生变化
+    pub fn on_success(&mut self, latency: Duration, config: &ConcurrencyConfig) -> bool {
+        if !config.adaptive {
+            return false;
+        }
+
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        let baseline = self.latency_baseline_ms.unwrap_or(latency_ms);
+        self.latency_baseline_ms = Some(baseline + LATENCY_EWMA_ALPHA * (latency_ms - baseline));
+
+        if latency_ms > baseline * config.latency_tolerance {
+            return self.decrease(config);
+        }
+
+        let before = self.limit();
+        self.limit = (self.limit + 1.0 / self.limit).min(config.max_limit as f64);
+        self.limit() != before
+    }
+
+    /// 记录一次限流，返回上限是否发生变化
+    pub fn on_throttle(&mut self, config: &ConcurrencyConfig) -> bool {
+        if !config.adaptive {
+            return false;
+        }
+        self.decrease(config)
+    }
+
+    fn decrease(&mut self, config: &ConcurrencyConfig) -> bool {
+        let before = self.limit();
+        self.limit = (self.limit * config.backoff_ratio).max(config.min_limit as f64);
+        self.limit() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConcurrencyConfig {
+        ConcurrencyConfig {
+            initial_limit: 4,
+            max_limit: 6,
+            ..ConcurrencyConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_additive_increase_capped() {
+        let config = config();
+        let mut limit = AdaptiveLimit::new(&config);
+        assert_eq!(limit.limit(), 4);
+
+        // 约每轮（当前上限个请求）增加 1
+        for _ in 0..5 {
+            limit.on_success(Duration::from_millis(100), &config);
+        }
+        assert_eq!(limit.limit(), 5);
+
+        for _ in 0..100 {
+            limit.on_success(Duration::from_millis(100), &config);
+        }
+        assert_eq!(limit.limit(), 6);
+    }
+
+    #[test]
+    fn test_multiplicative_decrease() {
+        let config = config();
+        let mut limit = AdaptiveLimit::new(&config);
+
+        assert!(limit.on_throttle(&config));
+        assert_eq!(limit.limit(), 2);
+        limit.on_throttle(&config);
+        limit.on_throttle(&config);
+        assert_eq!(limit.limit(), config.min_limit as usize);
+
+        // 延迟突增同样触发收缩
+        let mut limit = AdaptiveLimit::new(&config);
+        limit.on_success(Duration::from_millis(100), &config);
+        assert!(limit.on_success(Duration::from_secs(5), &config));
+        assert_eq!(limit.limit(), 2);
+    }
+
+    #[test]
+    fn test_static_limit_when_disabled() {
+        let config = ConcurrencyConfig {
+            adaptive: false,
+            ..config()
+        };
+        let mut limit = AdaptiveLimit::new(&config);
+        assert!(!limit.on_throttle(&config));
+        assert!(!limit.on_success(Duration::from_millis(100), &config));
+        assert_eq!(limit.limit(), 4);
+    }
+
+    #[test]
+    fn test_inverted_bounds_rejected_at_load() {
+        assert!(config().validate().is_ok());
+
+        let path = std::env::temp_dir().join(format!("kiro-config-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{"concurrency": {"minLimit": 8, "maxLimit": 2}}"#).unwrap();
+        let error = crate::model::config::Config::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("minLimit"));
+    }
+}
@@ -0,0 +1,555 @@
+//! Kiro 上游错误类型
+//!
+//! 对需要特殊处理的上游响应进行识别和分类，
+//! 避免将其作为普通的 502 错误透传给客户端
+
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
+
+/// 上游可能返回的 request-id 响应头（按优先级排列）
+const REQUEST_ID_HEADERS: &[&str] = &[
+    "x-amzn-requestid",
+    "x-amzn-request-id",
+    "amzn-requestid",
+    "x-amz-request-id",
+    "x-request-id",
+    "request-id",
+];
+
+/// 上游不可用的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnavailableKind {
+    /// 上游处于维护模式
+    Maintenance,
+    /// 客户端版本过低，需要升级（通常需要调整 kiroVersion 配置）
+    UpgradeRequired,
+}
+
+impl UnavailableKind {
+    /// 稳定的字符串标识（用于日志、告警和 Admin API）
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnavailableKind::Maintenance => "maintenance",
+            UnavailableKind::UpgradeRequired => "upgrade_required",
+        }
+    }
+}
+
+/// 上游维护 / 版本过低错误
+///
+/// 通过 `anyhow::Error::downcast_ref` 在 handler 中识别
+#[derive(Debug, Clone)]
+pub struct UpstreamUnavailableError {
+    /// 不可用原因
+    pub kind: UnavailableKind,
+    /// 上游 HTTP 状态码
+    pub status: u16,
+    /// 上游响应体
+    pub body: String,
+    /// 上游 request-id（向上游反馈问题时需要提供）
+    pub request_id: Option<String>,
+}
+
+impl UpstreamUnavailableError {
+    /// 面向客户端的错误描述
+    pub fn client_message(&self) -> String {
+        let message = match self.kind {
+            UnavailableKind::Maintenance => {
+                "Upstream service is under maintenance, please retry later".to_string()
+            }
+            UnavailableKind::UpgradeRequired => {
+                "Upstream rejected the client version (upgrade required), please check kiroVersion in config".to_string()
+            }
+        };
+        with_request_id(message, self.request_id.as_deref())
+    }
+}
+
+impl fmt::Display for UpstreamUnavailableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            UnavailableKind::Maintenance => "上游维护中",
+            UnavailableKind::UpgradeRequired => "客户端版本过低",
+        };
+        write!(
+            f,
+            "API 请求失败（{}）: {} {}",
+            reason,
+            self.status,
+            with_request_id(self.body.clone(), self.request_id.as_deref())
+        )
+    }
+}
+
+impl std::error::Error for UpstreamUnavailableError {}
+
+/// Kiro API 返回的非成功响应
+///
+/// 由 provider 在重试结束后返回，handler 通过 `anyhow::Error::downcast_ref` 识别，
+/// 按上游状态码与错误码映射客户端响应
+#[derive(Debug, Clone)]
+pub struct KiroApiError {
+    /// 请求类型（用于日志，如 "流式 API"、"MCP"）
+    pub api: &'static str,
+    /// 上游 HTTP 状态码
+    pub status: StatusCode,
+    /// 上游错误码（响应体中的 `reason` / `__type` / `code`）
+    pub code: Option<String>,
+    /// 上游错误描述（响应体中的 `message`，无法解析时为原始响应体）
+    pub message: String,
+    /// 上游 request-id（向上游反馈问题时需要提供）
+    pub request_id: Option<String>,
+    /// 所有凭据均已用尽（额度耗尽或认证失败）
+    pub credentials_exhausted: bool,
+    /// 建议客户端的重试等待时间（上游 Retry-After，缺失时为重试退避时间）
+    pub retry_after: Option<Duration>,
+}
+
+/// token 超限时上游返回的错误码
+const CONTENT_LENGTH_EXCEEDED: &str = "CONTENT_LENGTH_EXCEEDS_THRESHOLD";
+
+/// 上游限流 / 容量不足的错误码
+const OVERLOADED_CODES: &[&str] = &[
+    "ThrottlingException",
+    "ServiceUnavailableException",
+    "INSUFFICIENT_MODEL_CAPACITY",
+];
+
+/// token 超限相关的错误描述关键字（小写匹配）
+const TOKEN_LIMIT_MARKERS: &[&str] = &["input is too long", "too long", "context limit"];
+
+impl KiroApiError {
+    /// 从上游响应解析错误
+    pub fn new(
+        api: &'static str,
+        status: StatusCode,
+        body: &str,
+        request_id: Option<String>,
+    ) -> Self {
+        let value = serde_json::from_str::<serde_json::Value>(body).ok();
+        let field = |pointers: &[&str]| {
+            value.as_ref().and_then(|value| {
+                pointers
+                    .iter()
+                    .find_map(|p| value.pointer(p).and_then(|v| v.as_str()))
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+            })
+        };
+        // AWS 风格的 __type 形如 "com.amazon.aws.codewhisperer#ThrottlingException"
+        let code = field(&[
+            "/reason",
+            "/error/reason",
+            "/__type",
+            "/code",
+            "/error/code",
+        ])
+        .map(|code| code.rsplit('#').next().unwrap_or_default().to_string());
+        let message =
+            field(&["/message", "/Message", "/error/message"]).unwrap_or_else(|| body.to_string());
+        Self {
+            api,
+            status,
+            code,
+            message,
+            request_id,
+            credentials_exhausted: false,
+            retry_after: None,
+        }
+    }
+
+    /// 设置建议的重试等待时间
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+
+    /// 标记所有凭据均已用尽
+    pub fn exhausted(mut self) -> Self {
+        self.credentials_exhausted = true;
+        self
+    }
+
+    /// 是否为输入超过上下文长度限制
+    pub fn is_token_limit(&self) -> bool {
+        if self.code.as_deref() == Some(CONTENT_LENGTH_EXCEEDED) {
+            return true;
+        }
+        let lower = self.message.to_ascii_lowercase();
+        self.status == StatusCode::BAD_REQUEST
+            && TOKEN_LIMIT_MARKERS.iter().any(|m| lower.contains(m))
+    }
+
+    /// 是否为上游限流 / 过载（503、529 或限流类错误码）
+    pub fn is_overloaded(&self) -> bool {
+        matches!(self.status.as_u16(), 503 | 529)
+            || self
+                .code
+                .as_deref()
+                .is_some_and(|code| OVERLOADED_CODES.contains(&code))
+    }
+
+    /// 映射为返回给客户端的状态码与错误类型
+    ///
+    /// 上游限流 / 过载映射为 Anthropic 的 529 overloaded_error，客户端 SDK 会按 Retry-After 退避重试
+    pub fn client_status(&self) -> (StatusCode, &'static str) {
+        if self.is_overloaded() {
+            return (overloaded_status(), "overloaded_error");
+        }
+        match self.status.as_u16() {
+            400 => (StatusCode::BAD_REQUEST, "invalid_request_error"),
+            429 => (StatusCode::TOO_MANY_REQUESTS, "rate_limit_error"),
+            401 | 403 => (StatusCode::UNAUTHORIZED, "authentication_error"),
+            _ => (StatusCode::BAD_GATEWAY, "api_error"),
+        }
+    }
+}
+
+/// Anthropic 的过载状态码（529，非标准状态码）
+pub fn overloaded_status() -> StatusCode {
+    StatusCode::from_u16(529).expect("529 是合法的状态码")
+}
+
+/// 解析 Retry-After 响应头（秒数或 HTTP 日期）
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+impl fmt::Display for KiroApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exhausted = if self.credentials_exhausted {
+            "（所有凭据已用尽）"
+        } else {
+            ""
+        };
+        let mut detail = self.status.to_string();
+        if let Some(code) = &self.code {
+            detail.push_str(&format!(" [{}]", code));
+        }
+        if !self.message.is_empty() {
+            detail.push(' ');
+            detail.push_str(&self.message);
+        }
+        write!(
+            f,
+            "{} 请求失败{}: {}",
+            self.api,
+            exhausted,
+            with_request_id(detail, self.request_id.as_deref())
+        )
+    }
+}
+
+impl std::error::Error for KiroApiError {}
+
+/// 没有可用于本次请求的凭据（全部禁用、分组内没有可用凭据或排队等待超时）
+///
+/// 通过 `anyhow::Error::downcast_ref` 识别，用于决定是否转发到备用后端
+#[derive(Debug, Clone)]
+pub struct CredentialsUnavailable(pub String);
+
+impl fmt::Display for CredentialsUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CredentialsUnavailable {}
+
+/// 上游流式响应超时
+///
+/// 通过 `anyhow::Error::downcast_ref` 在 handler 中识别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamTimeout {
+    /// 收到响应头后没有在限定时间内收到首个内容事件
+    FirstEvent(Duration),
+    /// 输出过程中超过限定时间没有收到上游数据
+    Idle(Duration),
+}
+
+impl StreamTimeout {
+    /// 面向客户端的错误描述
+    pub fn client_message(&self) -> String {
+        match self {
+            StreamTimeout::FirstEvent(limit) => format!(
+                "Upstream produced no output within {} seconds",
+                limit.as_secs()
+            ),
+            StreamTimeout::Idle(limit) => format!(
+                "Upstream stream stalled for more than {} seconds",
+                limit.as_secs()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for StreamTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamTimeout::FirstEvent(limit) => {
+                write!(f, "等待首个内容事件超时（{} 秒）", limit.as_secs())
+            }
+            StreamTimeout::Idle(limit) => {
+                write!(f, "上游流超过 {} 秒没有输出", limit.as_secs())
+            }
+        }
+    }
+}
+
+impl std::error::Error for StreamTimeout {}
+
+/// 从上游响应头中提取 request-id
+pub fn extract_request_id(headers: &HeaderMap) -> Option<String> {
+    REQUEST_ID_HEADERS.iter().find_map(|name| {
+        headers
+            .get(*name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    })
+}
+
+/// 在错误信息末尾附加上游 request-id（未知时原样返回）
+pub fn with_request_id(message: String, request_id: Option<&str>) -> String {
+    match request_id {
+        Some(id) => format!("{} (request-id: {})", message, id),
+        None => message,
+    }
+}
+
+/// 版本过低相关的关键字（小写匹配）
+const UPGRADE_REQUIRED_MARKERS: &[&str] = &[
+    "upgrade_required",
+    "upgraderequired",
+    "client version too old",
+    "client version is too old",
+    "unsupported client version",
+    "client version is no longer supported",
+    "please update your client",
+    "please upgrade",
+];
+
+/// 维护模式相关的关键字（小写匹配）
+const MAINTENANCE_MARKERS: &[&str] = &[
+    "maintenance_mode",
+    "under maintenance",
+    "scheduled maintenance",
+    "service is in maintenance",
+    "maintenanceexception",
+];
+
+/// 识别上游维护 / 版本过低响应
+///
+/// - 426 Upgrade Required 直接视为版本过低
+/// - 其他非成功状态码根据响应体关键字判断；402 的响应体说的是付费套餐（如 "please upgrade"
+///   your plan），不参与关键字判断
+pub fn detect_unavailable(status: u16, body: &str) -> Option<UnavailableKind> {
+    if status == 426 {
+        return Some(UnavailableKind::UpgradeRequired);
+    }
+    if (200..300).contains(&status) || status == 402 {
+        return None;
+    }
+
+    let lower = body.to_ascii_lowercase();
+    if UPGRADE_REQUIRED_MARKERS.iter().any(|m| lower.contains(m)) {
+        return Some(UnavailableKind::UpgradeRequired);
+    }
+    if MAINTENANCE_MARKERS.iter().any(|m| lower.contains(m)) {
+        return Some(UnavailableKind::Maintenance);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_upgrade_required_by_status() {
+        assert_eq!(
+            detect_unavailable(426, ""),
+            Some(UnavailableKind::UpgradeRequired)
+        );
+    }
+
+    #[test]
+    fn test_detect_upgrade_required_by_body() {
+        let body = r#"{"message":"Client version too old, please update your client"}"#;
+        assert_eq!(
+            detect_unavailable(400, body),
+            Some(UnavailableKind::UpgradeRequired)
+        );
+    }
+
+    #[test]
+    fn test_detect_ignores_payment_required_body() {
+        let body = r#"{"message":"Monthly limit reached, please upgrade your plan","reason":"MONTHLY_REQUEST_COUNT"}"#;
+        assert_eq!(detect_unavailable(402, body), None);
+        assert_eq!(
+            detect_unavailable(400, body),
+            Some(UnavailableKind::UpgradeRequired)
+        );
+    }
+
+    #[test]
+    fn test_detect_maintenance() {
+        let body = r#"{"message":"The service is under maintenance"}"#;
+        assert_eq!(
+            detect_unavailable(503, body),
+            Some(UnavailableKind::Maintenance)
+        );
+    }
+
+    #[test]
+    fn test_detect_none() {
+        assert_eq!(detect_unavailable(503, "high load"), None);
+        assert_eq!(detect_unavailable(200, "under maintenance"), None);
+    }
+
+    #[test]
+    fn test_downcast_from_anyhow() {
+        let err: anyhow::Error = UpstreamUnavailableError {
+            kind: UnavailableKind::Maintenance,
+            status: 503,
+            body: String::new(),
+            request_id: None,
+        }
+        .into();
+        assert!(err.downcast_ref::<UpstreamUnavailableError>().is_some());
+    }
+
+    #[test]
+    fn test_kiro_api_error_parses_body() {
+        let body = r#"{"__type":"com.amazon.aws.codewhisperer#ValidationException","message":"Too many requests"}"#;
+        let err = KiroApiError::new("流式 API", StatusCode::TOO_MANY_REQUESTS, body, None);
+        assert_eq!(err.code.as_deref(), Some("ValidationException"));
+        assert_eq!(err.message, "Too many requests");
+        assert_eq!(
+            err.client_status(),
+            (StatusCode::TOO_MANY_REQUESTS, "rate_limit_error")
+        );
+
+        let err = KiroApiError::new("MCP", StatusCode::BAD_GATEWAY, "upstream 429 body", None);
+        assert_eq!(err.code, None);
+        assert_eq!(err.message, "upstream 429 body");
+        assert_eq!(err.client_status(), (StatusCode::BAD_GATEWAY, "api_error"));
+
+        let err = KiroApiError::new("MCP", StatusCode::FORBIDDEN, "", Some("abc".to_string()))
+            .exhausted();
+        assert_eq!(
+            err.client_status(),
+            (StatusCode::UNAUTHORIZED, "authentication_error")
+        );
+        assert_eq!(
+            err.to_string(),
+            "MCP 请求失败（所有凭据已用尽）: 403 Forbidden (request-id: abc)"
+        );
+    }
+
+    #[test]
+    fn test_kiro_api_error_overloaded() {
+        let body = r#"{"message":"I am experiencing high traffic","reason":"INSUFFICIENT_MODEL_CAPACITY"}"#;
+        let err = KiroApiError::new("流式 API", StatusCode::TOO_MANY_REQUESTS, body, None);
+        assert_eq!(
+            err.client_status(),
+            (overloaded_status(), "overloaded_error")
+        );
+
+        let err = KiroApiError::new("流式 API", StatusCode::SERVICE_UNAVAILABLE, "busy", None);
+        assert_eq!(err.client_status().1, "overloaded_error");
+        assert_eq!(overloaded_status().as_u16(), 529);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert("retry-after", "7".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(7)));
+
+        let at = chrono::Utc::now() + chrono::Duration::seconds(30);
+        headers.insert("retry-after", at.to_rfc2822().parse().unwrap());
+        let wait = parse_retry_after(&headers).unwrap();
+        assert!(wait > Duration::from_secs(28) && wait <= Duration::from_secs(30));
+
+        headers.insert(
+            "retry-after",
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_kiro_api_error_token_limit() {
+        let body = r#"{"message":"Input is too long for requested model.","reason":"CONTENT_LENGTH_EXCEEDS_THRESHOLD"}"#;
+        let err = KiroApiError::new("流式 API", StatusCode::BAD_REQUEST, body, None);
+        assert!(err.is_token_limit());
+        assert!(
+            err.to_string()
+                .contains("[CONTENT_LENGTH_EXCEEDS_THRESHOLD]")
+        );
+
+        let body = r#"{"message":"Input is too long for requested model."}"#;
+        assert!(
+            KiroApiError::new("流式 API", StatusCode::BAD_REQUEST, body, None).is_token_limit()
+        );
+        // 非 400 的响应即使描述中出现关键字也不视为超限
+        assert!(
+            !KiroApiError::new(
+                "流式 API",
+                StatusCode::BAD_GATEWAY,
+                "response too long",
+                None
+            )
+            .is_token_limit()
+        );
+        let body = r#"{"message":"Improperly formed request.","reason":"INVALID_INPUT"}"#;
+        assert!(
+            !KiroApiError::new("流式 API", StatusCode::BAD_REQUEST, body, None).is_token_limit()
+        );
+    }
+
+    #[test]
+    fn test_extract_request_id() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(extract_request_id(&headers), None);
+
+        headers.insert("x-request-id", "fallback".parse().unwrap());
+        headers.insert("x-amzn-requestid", "abc-123".parse().unwrap());
+        assert_eq!(extract_request_id(&headers), Some("abc-123".to_string()));
+    }
+
+    #[test]
+    fn test_with_request_id() {
+        assert_eq!(
+            with_request_id("boom".to_string(), Some("abc")),
+            "boom (request-id: abc)"
+        );
+        assert_eq!(with_request_id("boom".to_string(), None), "boom");
+
+        let err = UpstreamUnavailableError {
+            kind: UnavailableKind::Maintenance,
+            status: 503,
+            body: String::new(),
+            request_id: Some("abc".to_string()),
+        };
+        assert!(err.client_message().ends_with("(request-id: abc)"));
+    }
+}
@@ -1,7 +1,15 @@
 //! Kiro API 客户端模块
 
+pub mod balance_poller;
+pub mod circuit_breaker;
+pub mod clock;
+pub mod concurrency;
+pub mod error;
 pub mod machine_id;
 pub mod model;
 pub mod parser;
 pub mod provider;
+pub mod stream_failover;
 pub mod token_manager;
+pub mod token_refresher;
+pub mod upstream_headers;
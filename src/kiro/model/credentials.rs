@@ -4,11 +4,12 @@
 //! 支持单凭据和多凭据配置格式
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
 /// Kiro OAuth 凭证
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct KiroCredentials {
     /// 凭据唯一标识符（自增 ID）
@@ -48,15 +49,87 @@ pub struct KiroCredentials {
     #[serde(skip_serializing_if = "is_zero")]
     pub priority: u32,
 
+    /// 调度权重（`schedulingStrategy` 为 `weighted` 时生效，未配置时为 1）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+
+    /// 最大并发连接数（硬上限，达到后不再分配新请求；未配置时只受自适应并发上限约束）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<u32>,
+
+    /// 分组标签（如 `work`、`personal`），请求可指定分组只使用带有该标签的凭据
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
     /// 凭据级 Region 配置（用于 OIDC token 刷新）
     /// 未配置时回退到 config.json 的全局 region
     #[serde(skip_serializing_if = "Option::is_none")]
     pub region: Option<String>,
 
+    /// 凭据级 Kiro API Region（可选）
+    /// 未配置时回退到 config.json 的全局 region
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_region: Option<String>,
+
+    /// 凭据级 Kiro API 地址（可选，如 `https://q.us-east-1.amazonaws.com`）
+    /// 配置后优先于 apiRegion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+
+    /// 备用 Kiro API Region（可选，也可以填写完整地址）
+    /// 主端点连接失败或返回 5xx 时按顺序改用这些端点重试
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failover_regions: Vec<String>,
+
     /// 凭据级 Machine ID 配置（可选）
     /// 未配置时回退到 config.json 的 machineId；都未配置时由 refreshToken 派生
     #[serde(skip_serializing_if = "Option::is_none")]
     pub machine_id: Option<String>,
+
+    /// 凭据级自定义上游请求头（与 config.json 的 `upstreamHeaders` 同名时覆盖全局配置）
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Kiro API 端点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiEndpoint {
+    /// 不含 API 路径的地址（如 `https://q.us-east-1.amazonaws.com`）
+    pub base: String,
+    /// Host 请求头
+    pub host: String,
+}
+
+impl ApiEndpoint {
+    /// 指定 region 的官方端点
+    pub fn for_region(region: &str) -> Self {
+        let host = format!("q.{}.amazonaws.com", region);
+        Self {
+            base: format!("https://{}", host),
+            host,
+        }
+    }
+
+    /// 自定义地址，无法解析时返回 None
+    pub fn from_url(url: &str) -> Option<Self> {
+        let parsed = reqwest::Url::parse(url).ok()?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return None;
+        }
+        let host = match parsed.port() {
+            Some(port) => format!("{}:{}", parsed.host_str()?, port),
+            None => parsed.host_str()?.to_string(),
+        };
+        Some(Self {
+            base: url.trim_end_matches('/').to_string(),
+            host,
+        })
+    }
+
+    /// 拼接 API 路径（如 `generateAssistantResponse`）
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base, path)
+    }
 }
 
 /// 判断是否为零（用于跳过序列化）
@@ -72,6 +145,18 @@ fn canonicalize_auth_method_value(value: &str) -> &str {
     }
 }
 
+/// 规范化分组标签：去除首尾空白、空标签与重复标签（保留原有顺序）
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized
+}
+
 /// 凭据配置（支持单对象或数组格式）
 ///
 /// 自动识别配置文件格式：
@@ -177,6 +262,46 @@ impl KiroCredentials {
         serde_json::to_string_pretty(self)
     }
 
+    /// 调用 Kiro API 使用的 region（未配置 apiRegion 时使用全局 region）
+    pub fn effective_api_region<'a>(&'a self, default_region: &'a str) -> &'a str {
+        self.api_region.as_deref().unwrap_or(default_region)
+    }
+
+    /// 调用 Kiro API 的端点列表：主端点在前，其后为备用端点（去除重复项）
+    ///
+    /// 主端点优先使用 endpoint，其次 apiRegion，最后回退到全局 region
+    pub fn api_endpoints(&self, default_region: &str) -> Vec<ApiEndpoint> {
+        let region = self.effective_api_region(default_region);
+        let primary = match self.endpoint.as_deref().map(str::trim) {
+            Some(url) if !url.is_empty() => ApiEndpoint::from_url(url).unwrap_or_else(|| {
+                tracing::warn!("凭据 endpoint 无效，改用 region {}: {}", region, url);
+                ApiEndpoint::for_region(region)
+            }),
+            _ => ApiEndpoint::for_region(region),
+        };
+        let mut endpoints = vec![primary];
+        for failover in self.failover_regions.iter().map(|r| r.trim()) {
+            let endpoint = if failover.contains("://") {
+                ApiEndpoint::from_url(failover)
+            } else if !failover.is_empty() {
+                Some(ApiEndpoint::for_region(failover))
+            } else {
+                continue;
+            };
+            match endpoint {
+                Some(endpoint) if !endpoints.contains(&endpoint) => endpoints.push(endpoint),
+                Some(_) => {}
+                None => tracing::warn!("忽略无效的备用端点: {}", failover),
+            }
+        }
+        endpoints
+    }
+
+    /// 是否带有指定分组标签
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
     pub fn canonicalize_auth_method(&mut self) {
         let auth_method = match &self.auth_method {
             Some(m) => m,
@@ -235,8 +360,15 @@ mod tests {
             client_id: None,
             client_secret: None,
             priority: 0,
+            weight: None,
+            max_concurrent: None,
+            tags: Vec::new(),
             region: None,
+            api_region: None,
+            endpoint: None,
+            failover_regions: Vec::new(),
             machine_id: None,
+            headers: BTreeMap::new(),
         };
 
         let json = creds.to_pretty_json().unwrap();
@@ -345,8 +477,15 @@ mod tests {
             client_id: None,
             client_secret: None,
             priority: 0,
+            weight: None,
+            max_concurrent: None,
+            tags: Vec::new(),
             region: Some("eu-west-1".to_string()),
+            api_region: None,
+            endpoint: None,
+            failover_regions: Vec::new(),
             machine_id: None,
+            headers: BTreeMap::new(),
         };
 
         let json = creds.to_pretty_json().unwrap();
@@ -367,8 +506,15 @@ mod tests {
             client_id: None,
             client_secret: None,
             priority: 0,
+            weight: None,
+            max_concurrent: None,
+            tags: Vec::new(),
             region: None,
+            api_region: None,
+            endpoint: None,
+            failover_regions: Vec::new(),
             machine_id: None,
+            headers: BTreeMap::new(),
         };
 
         let json = creds.to_pretty_json().unwrap();
@@ -471,8 +617,15 @@ mod tests {
             client_id: None,
             client_secret: None,
             priority: 3,
+            weight: Some(5),
+            max_concurrent: Some(2),
+            tags: vec!["work".to_string()],
             region: Some("us-west-2".to_string()),
+            api_region: None,
+            endpoint: None,
+            failover_regions: Vec::new(),
             machine_id: Some("c".repeat(64)),
+            headers: BTreeMap::from([("x-team".to_string(), "core".to_string())]),
         };
 
         let json = original.to_pretty_json().unwrap();
@@ -482,7 +635,43 @@ mod tests {
         assert_eq!(parsed.access_token, original.access_token);
         assert_eq!(parsed.refresh_token, original.refresh_token);
         assert_eq!(parsed.priority, original.priority);
+        assert_eq!(parsed.weight, original.weight);
+        assert_eq!(parsed.max_concurrent, original.max_concurrent);
+        assert_eq!(parsed.tags, original.tags);
         assert_eq!(parsed.region, original.region);
         assert_eq!(parsed.machine_id, original.machine_id);
+        assert_eq!(parsed.headers, original.headers);
+    }
+
+    #[test]
+    fn test_api_endpoints() {
+        let mut creds = KiroCredentials::default();
+        assert_eq!(
+            creds.api_endpoints("us-east-1"),
+            vec![ApiEndpoint::for_region("us-east-1")]
+        );
+
+        creds.api_region = Some("eu-central-1".to_string());
+        creds.failover_regions = vec![
+            "us-east-1".to_string(),
+            "eu-central-1".to_string(),
+            " ".to_string(),
+            "http://127.0.0.1:8990/kiro/".to_string(),
+        ];
+        let endpoints = creds.api_endpoints("us-east-1");
+        assert_eq!(endpoints.len(), 3);
+        assert_eq!(endpoints[0].host, "q.eu-central-1.amazonaws.com");
+        assert_eq!(endpoints[1].host, "q.us-east-1.amazonaws.com");
+        assert_eq!(endpoints[2].host, "127.0.0.1:8990");
+        assert_eq!(endpoints[2].url("mcp"), "http://127.0.0.1:8990/kiro/mcp");
+
+        // endpoint 优先于 apiRegion，无效时回退到 region
+        creds.endpoint = Some("https://kiro.example.com".to_string());
+        assert_eq!(creds.api_endpoints("us-east-1")[0].host, "kiro.example.com");
+        creds.endpoint = Some("not a url".to_string());
+        assert_eq!(
+            creds.api_endpoints("us-east-1")[0],
+            ApiEndpoint::for_region("eu-central-1")
+        );
     }
 }
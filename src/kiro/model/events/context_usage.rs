@@ -31,6 +31,11 @@ impl ContextUsageEvent {
     pub fn formatted_percentage(&self) -> String {
         format!("{:.2}%", self.context_usage_percentage)
     }
+
+    /// 按模型上下文窗口换算实际的输入 tokens（上游返回的是累积上下文使用百分比）
+    pub fn input_tokens(&self, context_window: i32) -> i32 {
+        (self.context_usage_percentage * context_window as f64 / 100.0) as i32
+    }
 }
 
 impl std::fmt::Display for ContextUsageEvent {
@@ -105,6 +105,9 @@ pub struct UserInputMessage {
     /// 消息来源（通常为 "AI_EDITOR"）
     #[serde(skip_serializing_if = "Option::is_none")]
     pub origin: Option<String>,
+    /// 推理参数（温度等采样设置）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inference_configuration: Option<InferenceConfiguration>,
 }
 
 impl UserInputMessage {
@@ -116,6 +119,7 @@ impl UserInputMessage {
             model_id: model_id.into(),
             images: Vec::new(),
             origin: Some("AI_EDITOR".to_string()),
+            inference_configuration: None,
         }
     }
 
@@ -136,6 +140,36 @@ impl UserInputMessage {
         self.origin = Some(origin.into());
         self
     }
+
+    /// 设置推理参数
+    pub fn with_inference_configuration(mut self, config: InferenceConfiguration) -> Self {
+        self.inference_configuration = Some(config);
+        self
+    }
+}
+
+/// 推理参数
+///
+/// 未设置的字段不会序列化，由上游使用默认值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceConfiguration {
+    /// 采样温度
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// 核采样概率阈值
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// 候选 token 数量上限
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i32>,
+}
+
+impl InferenceConfiguration {
+    /// 是否未设置任何参数
+    pub fn is_empty(&self) -> bool {
+        self.temperature.is_none() && self.top_p.is_none() && self.top_k.is_none()
+    }
 }
 
 /// 用户输入消息上下文
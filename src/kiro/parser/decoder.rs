@@ -32,7 +32,9 @@
 
 use super::error::{ParseError, ParseResult};
 use super::frame::{Frame, PRELUDE_SIZE, parse_frame};
+use crate::common::metrics;
 use bytes::{Buf, BytesMut};
+use serde::{Deserialize, Serialize};
 
 /// 默认最大缓冲区大小 (16 MB)
 pub const DEFAULT_MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
@@ -43,6 +45,17 @@ pub const DEFAULT_MAX_ERRORS: usize = 5;
 /// 默认初始缓冲区容量
 pub const DEFAULT_BUFFER_CAPACITY: usize = 8192;
 
+/// 缓冲区溢出策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// 丢弃超出上限的帧，继续解码后续帧
+    #[default]
+    Truncate,
+    /// 中止整个流
+    Abort,
+}
+
 /// 解码器状态
 ///
 /// 采用四态模型，参考 kiro-kt 的设计：
@@ -99,6 +112,10 @@ pub struct EventStreamDecoder {
     max_buffer_size: usize,
     /// 跳过的字节数（用于调试）
     bytes_skipped: usize,
+    /// 缓冲区溢出策略
+    overflow_policy: OverflowPolicy,
+    /// 被截断帧尚未到达的剩余字节数（到达后直接丢弃）
+    discard_remaining: usize,
 }
 
 impl Default for EventStreamDecoder {
@@ -123,6 +140,8 @@ impl EventStreamDecoder {
             max_errors: DEFAULT_MAX_ERRORS,
             max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
             bytes_skipped: 0,
+            overflow_policy: OverflowPolicy::default(),
+            discard_remaining: 0,
         }
     }
 
@@ -136,18 +155,58 @@ impl EventStreamDecoder {
             max_errors,
             max_buffer_size,
             bytes_skipped: 0,
+            overflow_policy: OverflowPolicy::default(),
+            discard_remaining: 0,
         }
     }
 
+    /// 创建具有指定缓冲区上限和溢出策略的解码器
+    pub fn with_limits(max_buffer_size: usize, overflow_policy: OverflowPolicy) -> Self {
+        let mut decoder =
+            Self::with_config(DEFAULT_BUFFER_CAPACITY, DEFAULT_MAX_ERRORS, max_buffer_size);
+        decoder.overflow_policy = overflow_policy;
+        decoder
+    }
+
     /// 向解码器提供数据
     ///
     /// # Returns
     /// - `Ok(())` - 数据已添加到缓冲区
-    /// - `Err(BufferOverflow)` - 缓冲区已满
+    /// - `Err(FrameTruncated)` - 超出上限的帧已被丢弃（截断策略，可继续解码）
+    /// - `Err(BufferOverflow)` - 缓冲区已满（中止策略，或无法确定帧边界）
     pub fn feed(&mut self, data: &[u8]) -> ParseResult<()> {
+        // 丢弃被截断帧的剩余字节
+        let mut data = data;
+        if self.discard_remaining > 0 {
+            let n = self.discard_remaining.min(data.len());
+            data = &data[n..];
+            self.discard_remaining -= n;
+            self.bytes_skipped += n;
+            if data.is_empty() {
+                return Ok(());
+            }
+        }
+
         // 检查缓冲区大小限制
         let new_size = self.buffer.len() + data.len();
         if new_size > self.max_buffer_size {
+            if self.overflow_policy == OverflowPolicy::Truncate
+                && let Some((frame_len, rest)) = self.truncate_pending_frame(data)
+            {
+                metrics::DECODER_FRAMES_TRUNCATED.inc_by(1);
+                tracing::warn!(
+                    "帧大小 {} 字节超出缓冲区上限 {}，已丢弃该帧",
+                    frame_len,
+                    self.max_buffer_size
+                );
+                // 帧之后的数据按正常流程继续处理
+                self.feed(rest)?;
+                return Err(ParseError::FrameTruncated {
+                    size: frame_len,
+                    max: self.max_buffer_size,
+                });
+            }
+            metrics::DECODER_BUFFER_OVERFLOWS.inc_by(1);
             return Err(ParseError::BufferOverflow {
                 size: new_size,
                 max: self.max_buffer_size,
@@ -164,6 +223,37 @@ impl EventStreamDecoder {
         Ok(())
     }
 
+    /// 丢弃缓冲区开头正在接收的帧（含本次数据中属于该帧的部分）
+    ///
+    /// 返回帧长度和本次数据中位于该帧之后的部分；无法确定帧长度或该帧未超出上限时返回 None
+    fn truncate_pending_frame<'a>(&mut self, data: &'a [u8]) -> Option<(usize, &'a [u8])> {
+        let buffered = self.buffer.len();
+        let mut prelude = [0u8; 4];
+        for (i, byte) in prelude.iter_mut().enumerate() {
+            *byte = if i < buffered {
+                self.buffer[i]
+            } else {
+                *data.get(i - buffered)?
+            };
+        }
+        let frame_len = u32::from_be_bytes(prelude) as usize;
+        // 仅丢弃自身超出上限的帧；正常帧导致的溢出无法通过截断恢复
+        if frame_len <= self.max_buffer_size {
+            return None;
+        }
+
+        let available = buffered + data.len();
+        self.buffer.clear();
+        if frame_len >= available {
+            self.discard_remaining = frame_len - available;
+            self.bytes_skipped += available;
+            Some((frame_len, &[]))
+        } else {
+            self.bytes_skipped += frame_len;
+            Some((frame_len, &data[frame_len - buffered..]))
+        }
+    }
+
     /// 尝试解码下一个帧
     ///
     /// # Returns
@@ -310,6 +400,7 @@ impl EventStreamDecoder {
     /// 清空缓冲区和所有计数器，恢复到 Ready 状态
     pub fn reset(&mut self) {
         self.buffer.clear();
+        self.discard_remaining = 0;
         self.state = DecoderState::Ready;
         self.frames_decoded = 0;
         self.error_count = 0;
@@ -419,6 +510,40 @@ mod tests {
         assert!(matches!(result, Err(ParseError::BufferOverflow { .. })));
     }
 
+    #[test]
+    fn test_decoder_truncate_oversized_frame() {
+        let mut decoder = EventStreamDecoder::with_limits(64, OverflowPolicy::Truncate);
+
+        // 声明长度为 200 字节的帧，超出 64 字节上限
+        let mut head = 200u32.to_be_bytes().to_vec();
+        head.extend_from_slice(&[0u8; 36]);
+        assert!(decoder.feed(&head).is_ok());
+
+        let result = decoder.feed(&[0u8; 40]);
+        assert!(matches!(
+            result,
+            Err(ParseError::FrameTruncated { size: 200, .. })
+        ));
+        assert_eq!(decoder.buffer_len(), 0);
+
+        // 帧剩余的 120 字节被丢弃，之后的数据正常进入缓冲区
+        assert!(decoder.feed(&[0u8; 100]).is_ok());
+        assert_eq!(decoder.buffer_len(), 0);
+        assert!(decoder.feed(&[1u8; 30]).is_ok());
+        assert_eq!(decoder.buffer_len(), 10);
+        assert_eq!(decoder.bytes_skipped(), 200);
+    }
+
+    #[test]
+    fn test_decoder_abort_on_overflow() {
+        let mut decoder = EventStreamDecoder::with_limits(64, OverflowPolicy::Abort);
+        let mut head = 200u32.to_be_bytes().to_vec();
+        head.extend_from_slice(&[0u8; 36]);
+        assert!(decoder.feed(&head).is_ok());
+        let result = decoder.feed(&[0u8; 40]);
+        assert!(matches!(result, Err(ParseError::BufferOverflow { .. })));
+    }
+
     #[test]
     fn test_decoder_insufficient_data() {
         let mut decoder = EventStreamDecoder::new();
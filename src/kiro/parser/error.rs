@@ -29,6 +29,8 @@ pub enum ParseError {
     TooManyErrors { count: usize, last_error: String },
     /// 缓冲区溢出
     BufferOverflow { size: usize, max: usize },
+    /// 超出缓冲区上限的帧已被丢弃（截断策略）
+    FrameTruncated { size: usize, max: usize },
 }
 
 impl std::error::Error for ParseError {}
@@ -74,6 +76,9 @@ impl fmt::Display for ParseError {
             Self::BufferOverflow { size, max } => {
                 write!(f, "缓冲区溢出: {} 字节 (最大 {})", size, max)
             }
+            Self::FrameTruncated { size, max } => {
+                write!(f, "帧超出缓冲区上限已丢弃: {} 字节 (最大 {})", size, max)
+            }
         }
     }
 }
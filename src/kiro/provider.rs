@@ -4,34 +4,88 @@
 //! 支持流式和非流式请求
 //! 支持多凭据故障转移和重试
 
+use futures::TryStreamExt;
+use parking_lot::RwLock;
 use reqwest::Client;
 use reqwest::header::{AUTHORIZATION, CONNECTION, CONTENT_TYPE, HOST, HeaderMap, HeaderValue};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 use uuid::Uuid;
 
-use crate::http_client::{ProxyConfig, build_client};
+use crate::common::alert;
+use crate::http_client::{ProxyConfig, build_client_with_connect_timeout};
+use crate::kiro::clock;
+use crate::kiro::error::{
+    KiroApiError, StreamTimeout, UnavailableKind, UpstreamUnavailableError, detect_unavailable,
+    extract_request_id, parse_retry_after, with_request_id,
+};
 use crate::kiro::machine_id;
-use crate::kiro::token_manager::{AcquiredContext, CallContext, ConnectionGuard, MultiTokenManager};
-
-/// 流式响应，包含 Response 和 ConnectionGuard
+use crate::kiro::model::credentials::ApiEndpoint;
+use crate::kiro::stream_failover::{self, BodyStream, Probe};
+use crate::kiro::token_manager::{
+    AcquiredContext, CallContext, ConnectionGuard, MultiTokenManager, Routing,
+};
+use crate::kiro::upstream_headers;
+
+/// 流式响应，包含响应体和 ConnectionGuard
 ///
 /// Guard 的生命周期决定了 active_connections 计数的生命周期
 /// 调用方需要持有此结构直到流完全消费完毕
 pub struct StreamResponse {
-    pub response: reqwest::Response,
+    /// 上游响应体（已包含探测首个内容事件时读取的数据）
+    pub body: BodyStream<anyhow::Error>,
     pub guard: ConnectionGuard,
+    /// 故障转移信息
+    pub failover: FailoverInfo,
 }
 
-#[cfg(test)]
-use crate::kiro::model::credentials::KiroCredentials;
+/// 故障转移信息
+///
+/// 记录请求过程中发生的凭据切换，便于客户端将质量/延迟异常与故障转移关联。
+/// 非流式响应通过 `reqwest::Response` 的 extensions 携带
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FailoverInfo {
+    /// 凭据切换次数
+    pub switches: u32,
+    /// 最终成功的凭据 ID
+    pub credential_id: u64,
+}
 
-/// 每个凭据的最大重试次数
-const MAX_RETRIES_PER_CREDENTIAL: usize = 3;
+impl FailoverInfo {
+    /// 记录一次尝试使用的凭据，与上次不同时计为一次切换（凭据 ID 从 1 开始分配）
+    fn observe(&mut self, id: u64) {
+        if self.credential_id != 0 && self.credential_id != id {
+            self.switches += 1;
+        }
+        self.credential_id = id;
+    }
+}
+
+/// 对话 API 路径
+const API_PATH: &str = "generateAssistantResponse";
+
+/// MCP API 路径
+const MCP_PATH: &str = "mcp";
 
-/// 总重试次数硬上限（避免无限重试）
-const MAX_TOTAL_RETRIES: usize = 9;
+/// 端点是否不可用（连接失败、超时或 5xx），可改用备用 region
+fn is_region_unavailable(result: &reqwest::Result<reqwest::Response>) -> bool {
+    match result {
+        Ok(resp) => resp.status().is_server_error(),
+        Err(e) => e.is_connect() || e.is_timeout(),
+    }
+}
+
+/// 取出非流式响应携带的连接守卫
+///
+/// `reqwest::Response` 的 `bytes()`/`text()` 在读取响应体前就会丢弃 extensions，
+/// 调用方需先取出并持有守卫，活跃连接数才能覆盖读取响应体的整个过程
+pub fn take_connection_guard(response: &mut reqwest::Response) -> Option<Arc<ConnectionGuard>> {
+    response.extensions_mut().remove::<Arc<ConnectionGuard>>()
+}
+
+#[cfg(test)]
+use crate::kiro::model::credentials::KiroCredentials;
 
 /// Kiro API Provider
 ///
@@ -39,7 +93,8 @@ const MAX_TOTAL_RETRIES: usize = 9;
 /// 支持多凭据故障转移和重试机制
 pub struct KiroProvider {
     token_manager: Arc<MultiTokenManager>,
-    client: Client,
+    /// 上游 HTTP 客户端（代理配置热重载时重建）
+    client: RwLock<Client>,
 }
 
 impl KiroProvider {
@@ -50,39 +105,145 @@ impl KiroProvider {
 
     /// 创建带代理配置的 KiroProvider 实例
     pub fn with_proxy(token_manager: Arc<MultiTokenManager>, proxy: Option<ProxyConfig>) -> Self {
-        let client = build_client(proxy.as_ref(), 720, token_manager.config().tls_backend)
-            .expect("创建 HTTP 客户端失败");
+        let client =
+            Self::build_client(&token_manager, proxy.as_ref()).expect("创建 HTTP 客户端失败");
 
         Self {
             token_manager,
-            client,
+            client: RwLock::new(client),
         }
     }
 
+    fn build_client(
+        token_manager: &MultiTokenManager,
+        proxy: Option<&ProxyConfig>,
+    ) -> anyhow::Result<Client> {
+        let config = token_manager.config();
+        build_client_with_connect_timeout(proxy, 720, config.timeouts.connect(), config.tls_backend)
+    }
+
+    /// 更换代理配置（重建上游 HTTP 客户端，进行中的请求继续使用旧客户端）
+    pub fn set_proxy(&self, proxy: Option<ProxyConfig>) -> anyhow::Result<()> {
+        let client = Self::build_client(&self.token_manager, proxy.as_ref())?;
+        *self.client.write() = client;
+        Ok(())
+    }
+
+    fn client(&self) -> Client {
+        self.client.read().clone()
+    }
+
     /// 获取 token_manager 的引用
     pub fn token_manager(&self) -> &MultiTokenManager {
         &self.token_manager
     }
 
-    /// 获取 API 基础 URL
-    pub fn base_url(&self) -> String {
-        format!(
-            "https://q.{}.amazonaws.com/generateAssistantResponse",
-            self.token_manager.config().region
-        )
+    /// 凭据的 Kiro API 端点列表（主端点在前，其后为备用 region）
+    fn endpoints(&self, ctx: &CallContext) -> Vec<ApiEndpoint> {
+        ctx.credentials
+            .api_endpoints(&self.token_manager.config().region)
     }
 
-    /// 获取 MCP API URL
-    pub fn mcp_url(&self) -> String {
-        format!(
-            "https://q.{}.amazonaws.com/mcp",
-            self.token_manager.config().region
-        )
+    /// 发送请求，主端点连接失败或返回 5xx 时依次改用凭据的备用 region
+    ///
+    /// 只在同一凭据的端点之间切换，不计入重试次数；
+    /// 所有端点都不可用时返回最后一个端点的结果，由调用方按原有策略重试
+    async fn send_with_region_failover(
+        &self,
+        ctx: &CallContext,
+        path: &str,
+        mut headers: HeaderMap,
+        request_body: &str,
+    ) -> reqwest::Result<reqwest::Response> {
+        let endpoints = self.endpoints(ctx);
+        let mut index = 0;
+        loop {
+            let endpoint = &endpoints[index];
+            if let Ok(host) = HeaderValue::from_str(&endpoint.host) {
+                headers.insert(HOST, host);
+            }
+            let result = self
+                .client()
+                .post(endpoint.url(path))
+                .headers(headers.clone())
+                .body(request_body.to_string())
+                .send()
+                .await;
+            index += 1;
+            if index == endpoints.len() || !is_region_unavailable(&result) {
+                return result;
+            }
+            let reason = match &result {
+                Ok(resp) => resp.status().to_string(),
+                Err(e) => e.to_string(),
+            };
+            tracing::warn!(
+                "凭据 #{} 的端点 {} 不可用（{}），改用 {}",
+                ctx.id,
+                endpoint.host,
+                reason,
+                endpoints[index].host
+            );
+        }
     }
 
-    /// 获取 API 基础域名
-    pub fn base_domain(&self) -> String {
-        format!("q.{}.amazonaws.com", self.token_manager.config().region)
+    /// 发送对话请求，启用对冲（`resilience.hedgeDelayMs` > 0）时：
+    /// 首个凭据在延迟内未返回响应头，则在另一个凭据上再发一次，采用先返回的一方；
+    /// 另一方的请求随 future 一同取消，其连接守卫随之释放。
+    /// 对冲请求发送失败或没有其他可用凭据时，继续等待首个请求
+    async fn send_hedged(
+        &self,
+        primary: AcquiredContext,
+        headers: HeaderMap,
+        request_body: &str,
+        routing: Routing<'_>,
+    ) -> (AcquiredContext, reqwest::Result<reqwest::Response>) {
+        let delay = self.token_manager.config().resilience.hedge_delay_ms;
+        if delay == 0 {
+            let result = self
+                .send_with_region_failover(&primary.ctx, API_PATH, headers, request_body)
+                .await;
+            return (primary, result);
+        }
+        let ctx = primary.ctx.clone();
+        let sent = self.send_with_region_failover(&ctx, API_PATH, headers, request_body);
+        tokio::pin!(sent);
+        tokio::select! {
+            result = &mut sent => return (primary, result),
+            _ = sleep(Duration::from_millis(delay)) => {}
+        }
+
+        let primary_id = primary.ctx.id;
+        let hedge = async {
+            // 对冲请求不参与会话绑定，并避开首个凭据
+            let avoid: Vec<u64> = routing.avoid.iter().copied().chain([primary_id]).collect();
+            let hedge = self
+                .token_manager
+                .acquire_context_for(Routing {
+                    session: None,
+                    avoid: &avoid,
+                    ..routing
+                })
+                .await
+                .ok()
+                .filter(|hedge| hedge.ctx.id != primary_id)?;
+            let headers = self.build_headers(&hedge.ctx).ok()?;
+            tracing::info!(
+                "凭据 #{} 在 {}ms 内未返回响应，对冲请求发往凭据 #{}",
+                primary_id,
+                delay,
+                hedge.ctx.id
+            );
+            let response = self
+                .send_with_region_failover(&hedge.ctx, API_PATH, headers, request_body)
+                .await
+                .ok()?;
+            Some((hedge, response))
+        };
+        tokio::select! {
+            result = &mut sent => (primary, result),
+            Some((hedge, response)) = hedge => (hedge, Ok(response)),
+        }
     }
 
     /// 构建请求头
@@ -92,7 +253,7 @@ impl KiroProvider {
     fn build_headers(&self, ctx: &CallContext) -> anyhow::Result<HeaderMap> {
         let config = self.token_manager.config();
 
-        let machine_id = machine_id::generate_from_credentials(&ctx.credentials, config)
+        let machine_id = machine_id::generate_from_credentials(&ctx.credentials, &config)
             .ok_or_else(|| anyhow::anyhow!("无法生成 machine_id，请检查凭证配置"))?;
 
         let kiro_version = &config.kiro_version;
@@ -122,10 +283,14 @@ impl KiroProvider {
             reqwest::header::USER_AGENT,
             HeaderValue::from_str(&user_agent).unwrap(),
         );
-        headers.insert(HOST, HeaderValue::from_str(&self.base_domain()).unwrap());
+        // apiRegion / endpoint 来自用户配置，无法作为请求头时不设置（请求随后因 URL 无效而失败）
+        if let Ok(host) = HeaderValue::from_str(&self.endpoints(ctx)[0].host) {
+            headers.insert(HOST, host);
+        }
+        let invocation_id = Uuid::new_v4().to_string();
         headers.insert(
             "amz-sdk-invocation-id",
-            HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap(),
+            HeaderValue::from_str(&invocation_id).unwrap(),
         );
         headers.insert(
             "amz-sdk-request",
@@ -136,6 +301,7 @@ impl KiroProvider {
             HeaderValue::from_str(&format!("Bearer {}", ctx.token)).unwrap(),
         );
         headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        self.apply_upstream_headers(&mut headers, ctx, &machine_id, &invocation_id);
 
         Ok(headers)
     }
@@ -144,7 +310,7 @@ impl KiroProvider {
     fn build_mcp_headers(&self, ctx: &CallContext) -> anyhow::Result<HeaderMap> {
         let config = self.token_manager.config();
 
-        let machine_id = machine_id::generate_from_credentials(&ctx.credentials, config)
+        let machine_id = machine_id::generate_from_credentials(&ctx.credentials, &config)
             .ok_or_else(|| anyhow::anyhow!("无法生成 machine_id，请检查凭证配置"))?;
 
         let kiro_version = &config.kiro_version;
@@ -167,10 +333,13 @@ impl KiroProvider {
             HeaderValue::from_str(&x_amz_user_agent).unwrap(),
         );
         headers.insert("user-agent", HeaderValue::from_str(&user_agent).unwrap());
-        headers.insert("host", HeaderValue::from_str(&self.base_domain()).unwrap());
+        if let Ok(host) = HeaderValue::from_str(&self.endpoints(ctx)[0].host) {
+            headers.insert("host", host);
+        }
+        let invocation_id = Uuid::new_v4().to_string();
         headers.insert(
             "amz-sdk-invocation-id",
-            HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap(),
+            HeaderValue::from_str(&invocation_id).unwrap(),
         );
         headers.insert(
             "amz-sdk-request",
@@ -181,10 +350,29 @@ impl KiroProvider {
             HeaderValue::from_str(&format!("Bearer {}", ctx.token)).unwrap(),
         );
         headers.insert("Connection", HeaderValue::from_static("close"));
+        self.apply_upstream_headers(&mut headers, ctx, &machine_id, &invocation_id);
 
         Ok(headers)
     }
 
+    /// 附加配置的自定义上游请求头
+    fn apply_upstream_headers(
+        &self,
+        headers: &mut HeaderMap,
+        ctx: &CallContext,
+        machine_id: &str,
+        invocation_id: &str,
+    ) {
+        let config = self.token_manager.config();
+        let vars = upstream_headers::TemplateVars {
+            credential_id: ctx.id,
+            machine_id,
+            region: ctx.credentials.effective_api_region(&config.region),
+            invocation_id,
+        };
+        upstream_headers::apply(headers, &config, &ctx.credentials, &vars);
+    }
+
     /// 发送非流式 API 请求
     ///
     /// 支持多凭据故障转移：
@@ -195,11 +383,17 @@ impl KiroProvider {
     ///
     /// # Arguments
     /// * `request_body` - JSON 格式的请求体字符串
+    /// * `routing` - 凭据选择约束（会话粘性绑定、分组）
     ///
     /// # Returns
     /// 返回原始的 HTTP Response，不做解析
-    pub async fn call_api(&self, request_body: &str) -> anyhow::Result<reqwest::Response> {
-        self.call_api_with_retry(request_body, false).await
+    #[tracing::instrument(name = "upstream", skip_all, fields(stream = false))]
+    pub async fn call_api(
+        &self,
+        request_body: &str,
+        routing: Routing<'_>,
+    ) -> anyhow::Result<reqwest::Response> {
+        self.call_api_with_retry(request_body, routing, false).await
     }
 
     /// 发送流式 API 请求
@@ -210,14 +404,81 @@ impl KiroProvider {
     /// - 402 MONTHLY_REQUEST_COUNT: 视为额度用尽，禁用凭据并切换
     /// - 429/5xx/网络等瞬态错误: 重试但不禁用或切换凭据（避免误把所有凭据锁死）
     ///
+    /// 响应流在输出任何内容前中断或超过 `timeouts.firstEventSecs` 没有内容时，换用其他
+    /// 凭据重新请求（最多 `streamRetries` 次）；重试次数用尽后按原样返回中断的响应流，
+    /// 或返回 [`StreamTimeout`] 错误。返回的响应体带有 `timeouts.idleSecs` 空闲超时
+    ///
     /// # Arguments
     /// * `request_body` - JSON 格式的请求体字符串
+    /// * `routing` - 凭据选择约束（会话粘性绑定、分组）
     ///
     /// # Returns
-    /// 返回 StreamResponse，包含 Response 和 ConnectionGuard
+    /// 返回 StreamResponse，包含响应体和 ConnectionGuard
     /// 调用方需要持有 guard 直到流完全消费完毕
-    pub async fn call_api_stream(&self, request_body: &str) -> anyhow::Result<StreamResponse> {
-        self.call_api_stream_with_retry(request_body).await
+    #[tracing::instrument(name = "upstream", skip_all, fields(stream = true))]
+    pub async fn call_api_stream(
+        &self,
+        request_body: &str,
+        routing: Routing<'_>,
+    ) -> anyhow::Result<StreamResponse> {
+        let config = self.token_manager.config();
+        let budget = config.resilience.stream_retries;
+        let idle = config.timeouts.idle();
+        let mut failed = Vec::new();
+        let mut failover = FailoverInfo::default();
+
+        loop {
+            let routing = Routing {
+                avoid: &failed,
+                ..routing
+            };
+            let response = self
+                .call_api_stream_with_retry(request_body, routing, failover)
+                .await?;
+            failover = response.failover;
+
+            let probe = stream_failover::read_until_content(response.body);
+            let probe = match config.timeouts.first_event() {
+                Some(limit) => tokio::time::timeout(limit, probe)
+                    .await
+                    .map_err(|_| StreamTimeout::FirstEvent(limit)),
+                None => Ok(probe.await),
+            };
+
+            let reason = match probe {
+                Ok(Probe::Started(body)) => {
+                    let body = stream_failover::with_idle_timeout(body, idle);
+                    return Ok(StreamResponse { body, ..response });
+                }
+                Ok(Probe::Died { body, reason }) if failed.len() >= budget => {
+                    tracing::warn!(
+                        "凭据 #{} 的流式响应在输出内容前中断，重试次数已用尽: {}",
+                        failover.credential_id,
+                        reason
+                    );
+                    let body = stream_failover::with_idle_timeout(body, idle);
+                    return Ok(StreamResponse { body, ..response });
+                }
+                Err(timeout) if failed.len() >= budget => {
+                    tracing::warn!(
+                        "凭据 #{} 的流式响应{}，重试次数已用尽",
+                        failover.credential_id,
+                        timeout
+                    );
+                    return Err(timeout.into());
+                }
+                Ok(Probe::Died { reason, .. }) => reason,
+                Err(timeout) => timeout.to_string(),
+            };
+            tracing::warn!(
+                "凭据 #{} 的流式响应未能开始输出（{}），换用其他凭据重试（{}/{}）",
+                failover.credential_id,
+                reason,
+                failed.len() + 1,
+                budget
+            );
+            failed.push(failover.credential_id);
+        }
     }
 
     /// 发送 MCP API 请求
@@ -226,22 +487,31 @@ impl KiroProvider {
     ///
     /// # Arguments
     /// * `request_body` - JSON 格式的 MCP 请求体字符串
+    /// * `routing` - 凭据选择约束（会话粘性绑定、分组）
     ///
     /// # Returns
     /// 返回原始的 HTTP Response
-    pub async fn call_mcp(&self, request_body: &str) -> anyhow::Result<reqwest::Response> {
-        self.call_mcp_with_retry(request_body).await
+    pub async fn call_mcp(
+        &self,
+        request_body: &str,
+        routing: Routing<'_>,
+    ) -> anyhow::Result<reqwest::Response> {
+        self.call_mcp_with_retry(request_body, routing).await
     }
 
     /// 内部方法：带重试逻辑的 MCP API 调用
-    async fn call_mcp_with_retry(&self, request_body: &str) -> anyhow::Result<reqwest::Response> {
+    async fn call_mcp_with_retry(
+        &self,
+        request_body: &str,
+        routing: Routing<'_>,
+    ) -> anyhow::Result<reqwest::Response> {
         let total_credentials = self.token_manager.total_count();
-        let max_retries = (total_credentials * MAX_RETRIES_PER_CREDENTIAL).min(MAX_TOTAL_RETRIES);
+        let max_retries = self.max_retries(total_credentials);
         let mut last_error: Option<anyhow::Error> = None;
 
         for attempt in 0..max_retries {
             // 获取调用上下文
-            let ctx = match self.token_manager.acquire_context().await {
+            let ctx = match self.token_manager.acquire_context_for(routing).await {
                 Ok(c) => c,
                 Err(e) => {
                     last_error = Some(e);
@@ -249,7 +519,6 @@ impl KiroProvider {
                 }
             };
 
-            let url = self.mcp_url();
             let headers = match self.build_mcp_headers(&ctx.ctx) {
                 Ok(h) => h,
                 Err(e) => {
@@ -259,12 +528,9 @@ impl KiroProvider {
             };
 
             // 发送请求
+            let started = Instant::now();
             let response = match self
-                .client
-                .post(&url)
-                .headers(headers)
-                .body(request_body.to_string())
-                .send()
+                .send_with_region_failover(&ctx.ctx, MCP_PATH, headers, request_body)
                 .await
             {
                 Ok(resp) => resp,
@@ -277,50 +543,88 @@ impl KiroProvider {
                     );
                     last_error = Some(e.into());
                     if attempt + 1 < max_retries {
-                        sleep(Self::retry_delay(attempt)).await;
+                        sleep(self.retry_delay(attempt)).await;
                     }
                     continue;
                 }
             };
+            clock::observe(response.headers());
 
             let status = response.status();
 
             // 成功响应
             if status.is_success() {
-                self.token_manager.report_success(ctx.ctx.id);
+                self.token_manager
+                    .report_success(ctx.ctx.id, started.elapsed());
+                // 与 call_api 相同：guard 随 Response 返回，读取完响应体前保持计数
+                let mut response = response;
+                response.extensions_mut().insert(Arc::new(ctx.guard));
                 return Ok(response);
             }
 
             // 失败响应
+            let request_id = extract_request_id(response.headers());
+            let retry_after =
+                parse_retry_after(response.headers()).unwrap_or_else(|| self.retry_delay(attempt));
             let body = response.text().await.unwrap_or_default();
 
+            // 402 额度用尽先于维护 / 版本过低识别：其响应体中的 "please upgrade" 指升级套餐
+            let quota_exhausted = status.as_u16() == 402 && Self::is_monthly_request_limit(&body);
+
+            // 上游维护 / 版本过低：标记凭据降级并告警，不作为普通 5xx 处理
+            if !quota_exhausted && let Some(kind) = detect_unavailable(status.as_u16(), &body) {
+                let (err, should_retry) = self.handle_upstream_unavailable(
+                    ctx.ctx.id,
+                    kind,
+                    status.as_u16(),
+                    body,
+                    request_id,
+                );
+                if !should_retry {
+                    return Err(err.into());
+                }
+                last_error = Some(err.into());
+                if attempt + 1 < max_retries {
+                    sleep(self.retry_delay(attempt)).await;
+                }
+                continue;
+            }
+
+            let error = KiroApiError::new("MCP", status, &body, request_id.clone())
+                .with_retry_after(retry_after);
+            // 附带上游 request-id，便于向上游反馈问题时定位
+            let body = with_request_id(body, request_id.as_deref());
+
             // 402 额度用尽
-            if status.as_u16() == 402 && Self::is_monthly_request_limit(&body) {
+            if quota_exhausted {
                 let has_available = self.token_manager.report_quota_exhausted(ctx.ctx.id);
                 if !has_available {
-                    anyhow::bail!("MCP 请求失败（所有凭据已用尽）: {} {}", status, body);
+                    return Err(error.exhausted().into());
                 }
-                last_error = Some(anyhow::anyhow!("MCP 请求失败: {} {}", status, body));
+                last_error = Some(error.into());
                 continue;
             }
 
             // 400 Bad Request
             if status.as_u16() == 400 {
-                anyhow::bail!("MCP 请求失败: {} {}", status, body);
+                return Err(error.into());
             }
 
             // 401/403 凭据问题
             if matches!(status.as_u16(), 401 | 403) {
                 let has_available = self.token_manager.report_failure(ctx.ctx.id);
                 if !has_available {
-                    anyhow::bail!("MCP 请求失败（所有凭据已用尽）: {} {}", status, body);
+                    return Err(error.exhausted().into());
                 }
-                last_error = Some(anyhow::anyhow!("MCP 请求失败: {} {}", status, body));
+                last_error = Some(error.into());
                 continue;
             }
 
             // 瞬态错误
             if matches!(status.as_u16(), 408 | 429) || status.is_server_error() {
+                if status.as_u16() == 429 {
+                    self.token_manager.report_throttled(ctx.ctx.id);
+                }
                 tracing::warn!(
                     "MCP 请求失败（上游瞬态错误，尝试 {}/{}）: {} {}",
                     attempt + 1,
@@ -328,22 +632,22 @@ impl KiroProvider {
                     status,
                     body
                 );
-                last_error = Some(anyhow::anyhow!("MCP 请求失败: {} {}", status, body));
+                last_error = Some(error.into());
                 if attempt + 1 < max_retries {
-                    sleep(Self::retry_delay(attempt)).await;
+                    sleep(self.retry_delay(attempt)).await;
                 }
                 continue;
             }
 
             // 其他 4xx
             if status.is_client_error() {
-                anyhow::bail!("MCP 请求失败: {} {}", status, body);
+                return Err(error.into());
             }
 
             // 兜底
-            last_error = Some(anyhow::anyhow!("MCP 请求失败: {} {}", status, body));
+            last_error = Some(error.into());
             if attempt + 1 < max_retries {
-                sleep(Self::retry_delay(attempt)).await;
+                sleep(self.retry_delay(attempt)).await;
             }
         }
 
@@ -355,22 +659,28 @@ impl KiroProvider {
     /// 内部方法：带重试逻辑的 API 调用
     ///
     /// 重试策略：
-    /// - 每个凭据最多重试 MAX_RETRIES_PER_CREDENTIAL 次
-    /// - 总重试次数 = min(凭据数量 × 每凭据重试次数, MAX_TOTAL_RETRIES)
+    /// - 每个凭据最多重试 `resilience.maxRetriesPerCredential` 次
+    /// - 总重试次数 = min(凭据数量 × 每凭据重试次数, `resilience.maxTotalRetries`)
     /// - 硬上限 9 次，避免无限重试
     async fn call_api_with_retry(
         &self,
         request_body: &str,
+        routing: Routing<'_>,
         is_stream: bool,
     ) -> anyhow::Result<reqwest::Response> {
         let total_credentials = self.token_manager.total_count();
-        let max_retries = (total_credentials * MAX_RETRIES_PER_CREDENTIAL).min(MAX_TOTAL_RETRIES);
+        let max_retries = self.max_retries(total_credentials);
         let mut last_error: Option<anyhow::Error> = None;
-        let api_type = if is_stream { "流式" } else { "非流式" };
+        let mut failover = FailoverInfo::default();
+        let api_type = if is_stream {
+            "流式 API"
+        } else {
+            "非流式 API"
+        };
 
         for attempt in 0..max_retries {
             // 获取调用上下文（绑定 id、credentials、token 和连接守卫）
-            let acquired = match self.token_manager.acquire_context().await {
+            let acquired = match self.token_manager.acquire_context_for(routing).await {
                 Ok(a) => a,
                 Err(e) => {
                     last_error = Some(e);
@@ -378,11 +688,9 @@ impl KiroProvider {
                 }
             };
 
-            let AcquiredContext { ctx, guard } = acquired;
-            let id = ctx.id;
+            failover.observe(acquired.ctx.id);
 
-            let url = self.base_url();
-            let headers = match self.build_headers(&ctx) {
+            let headers = match self.build_headers(&acquired.ctx) {
                 Ok(h) => h,
                 Err(e) => {
                     // guard 在这里 drop，活跃连接数 -1
@@ -392,14 +700,13 @@ impl KiroProvider {
             };
 
             // 发送请求
-            let response = match self
-                .client
-                .post(&url)
-                .headers(headers)
-                .body(request_body.to_string())
-                .send()
-                .await
-            {
+            let started = Instant::now();
+            let (AcquiredContext { ctx, guard }, response) = self
+                .send_hedged(acquired, headers, request_body, routing)
+                .await;
+            let id = ctx.id;
+            failover.observe(id);
+            let response = match response {
                 Ok(resp) => resp,
                 Err(e) => {
                     tracing::warn!(
@@ -413,17 +720,18 @@ impl KiroProvider {
                     // guard 在这里 drop，活跃连接数 -1
                     last_error = Some(e.into());
                     if attempt + 1 < max_retries {
-                        sleep(Self::retry_delay(attempt)).await;
+                        sleep(self.retry_delay(attempt)).await;
                     }
                     continue;
                 }
             };
+            clock::observe(response.headers());
 
             let status = response.status();
 
             // 成功响应
             if status.is_success() {
-                self.token_manager.report_success(id);
+                self.token_manager.report_success(id, started.elapsed());
                 // 保持 guard 存活：将其移入闭包，随 Response 一起返回
                 // 当 Response 被完全消费并 drop 时，guard 也会 drop
                 let guard = std::sync::Arc::new(guard);
@@ -431,16 +739,42 @@ impl KiroProvider {
                 // 使用 Response 的 extensions 来存储 guard
                 let mut response = response;
                 response.extensions_mut().insert(guard_clone);
+                response.extensions_mut().insert(failover);
                 // 原始 guard 在这里 drop，但 Arc 引用计数 > 0，不会真正释放
                 return Ok(response);
             }
 
             // 失败响应：读取 body 用于日志/错误信息
             // guard 会在各分支的 continue/bail! 时 drop，活跃连接数 -1
+            let request_id = extract_request_id(response.headers());
+            let retry_after =
+                parse_retry_after(response.headers()).unwrap_or_else(|| self.retry_delay(attempt));
             let body = response.text().await.unwrap_or_default();
 
+            // 402 额度用尽先于维护 / 版本过低识别：其响应体中的 "please upgrade" 指升级套餐
+            let quota_exhausted = status.as_u16() == 402 && Self::is_monthly_request_limit(&body);
+
+            // 上游维护 / 版本过低：标记凭据降级并告警，不作为普通 5xx 处理
+            if !quota_exhausted && let Some(kind) = detect_unavailable(status.as_u16(), &body) {
+                let (err, should_retry) =
+                    self.handle_upstream_unavailable(id, kind, status.as_u16(), body, request_id);
+                if !should_retry {
+                    return Err(err.into());
+                }
+                last_error = Some(err.into());
+                if attempt + 1 < max_retries {
+                    sleep(self.retry_delay(attempt)).await;
+                }
+                continue;
+            }
+
+            let error = KiroApiError::new(api_type, status, &body, request_id.clone())
+                .with_retry_after(retry_after);
+            // 附带上游 request-id，便于向上游反馈问题时定位
+            let body = with_request_id(body, request_id.as_deref());
+
             // 402 Payment Required 且额度用尽：禁用凭据并故障转移
-            if status.as_u16() == 402 && Self::is_monthly_request_limit(&body) {
+            if quota_exhausted {
                 tracing::warn!(
                     "API 请求失败（额度已用尽，禁用凭据并切换，尝试 {}/{}）: {} {}",
                     attempt + 1,
@@ -451,26 +785,16 @@ impl KiroProvider {
 
                 let has_available = self.token_manager.report_quota_exhausted(id);
                 if !has_available {
-                    anyhow::bail!(
-                        "{} API 请求失败（所有凭据已用尽）: {} {}",
-                        api_type,
-                        status,
-                        body
-                    );
+                    return Err(error.exhausted().into());
                 }
 
-                last_error = Some(anyhow::anyhow!(
-                    "{} API 请求失败: {} {}",
-                    api_type,
-                    status,
-                    body
-                ));
+                last_error = Some(error.into());
                 continue;
             }
 
             // 400 Bad Request - 请求问题，重试/切换凭据无意义
             if status.as_u16() == 400 {
-                anyhow::bail!("{} API 请求失败: {} {}", api_type, status, body);
+                return Err(error.into());
             }
 
             // 401/403 - 更可能是凭据/权限问题：计入失败并允许故障转移
@@ -485,26 +809,20 @@ impl KiroProvider {
 
                 let has_available = self.token_manager.report_failure(id);
                 if !has_available {
-                    anyhow::bail!(
-                        "{} API 请求失败（所有凭据已用尽）: {} {}",
-                        api_type,
-                        status,
-                        body
-                    );
+                    return Err(error.exhausted().into());
                 }
 
-                last_error = Some(anyhow::anyhow!(
-                    "{} API 请求失败: {} {}",
-                    api_type,
-                    status,
-                    body
-                ));
+                last_error = Some(error.into());
                 continue;
             }
 
             // 429/408/5xx - 瞬态上游错误：重试但不禁用或切换凭据
             // （避免 429 high traffic / 502 high load 等瞬态错误把所有凭据锁死）
+            // 429 会收缩该凭据的自适应并发上限
             if matches!(status.as_u16(), 408 | 429) || status.is_server_error() {
+                if status.as_u16() == 429 {
+                    self.token_manager.report_throttled(id);
+                }
                 tracing::warn!(
                     "API 请求失败（上游瞬态错误，尝试 {}/{}）: {} {}",
                     attempt + 1,
@@ -512,21 +830,16 @@ impl KiroProvider {
                     status,
                     body
                 );
-                last_error = Some(anyhow::anyhow!(
-                    "{} API 请求失败: {} {}",
-                    api_type,
-                    status,
-                    body
-                ));
+                last_error = Some(error.into());
                 if attempt + 1 < max_retries {
-                    sleep(Self::retry_delay(attempt)).await;
+                    sleep(self.retry_delay(attempt)).await;
                 }
                 continue;
             }
 
             // 其他 4xx - 通常为请求/配置问题：直接返回，不计入凭据失败
             if status.is_client_error() {
-                anyhow::bail!("{} API 请求失败: {} {}", api_type, status, body);
+                return Err(error.into());
             }
 
             // 兜底：当作可重试的瞬态错误处理（不切换凭据）
@@ -537,33 +850,37 @@ impl KiroProvider {
                 status,
                 body
             );
-            last_error = Some(anyhow::anyhow!(
-                "{} API 请求失败: {} {}",
-                api_type,
-                status,
-                body
-            ));
+            last_error = Some(error.into());
             if attempt + 1 < max_retries {
-                sleep(Self::retry_delay(attempt)).await;
+                sleep(self.retry_delay(attempt)).await;
             }
         }
 
         // 所有重试都失败
         Err(last_error.unwrap_or_else(|| {
             anyhow::anyhow!(
-                "{} API 请求失败：已达到最大重试次数（{}次）",
+                "{} 请求失败：已达到最大重试次数（{}次）",
                 api_type,
                 max_retries
             )
         }))
     }
 
-    fn retry_delay(attempt: usize) -> Duration {
+    /// 计算本次请求的最大重试次数
+    fn max_retries(&self, total_credentials: usize) -> usize {
+        let policy = &self.token_manager.config().resilience;
+        (total_credentials * policy.max_retries_per_credential)
+            .min(policy.max_total_retries)
+            .max(1)
+    }
+
+    fn retry_delay(&self, attempt: usize) -> Duration {
         // 指数退避 + 少量抖动，避免上游抖动时放大故障
-        const BASE_MS: u64 = 200;
-        const MAX_MS: u64 = 2_000;
-        let exp = BASE_MS.saturating_mul(2u64.saturating_pow(attempt.min(6) as u32));
-        let backoff = exp.min(MAX_MS);
+        let policy = &self.token_manager.config().resilience;
+        let exp = policy
+            .backoff_base_ms
+            .saturating_mul(2u64.saturating_pow(attempt.min(6) as u32));
+        let backoff = exp.min(policy.backoff_max_ms);
         let jitter_max = (backoff / 4).max(1);
         let jitter = fastrand::u64(0..=jitter_max);
         Duration::from_millis(backoff.saturating_add(jitter))
@@ -571,17 +888,20 @@ impl KiroProvider {
 
     /// 内部方法：带重试逻辑的流式 API 调用
     ///
-    /// 与 call_api_with_retry 类似，但返回 StreamResponse 以保持 guard 生命周期
+    /// 与 call_api_with_retry 类似，但返回 StreamResponse 以保持 guard 生命周期；
+    /// `failover` 为此前尝试累计的故障转移信息
     async fn call_api_stream_with_retry(
         &self,
         request_body: &str,
+        routing: Routing<'_>,
+        mut failover: FailoverInfo,
     ) -> anyhow::Result<StreamResponse> {
         let total_credentials = self.token_manager.total_count();
-        let max_retries = (total_credentials * MAX_RETRIES_PER_CREDENTIAL).min(MAX_TOTAL_RETRIES);
+        let max_retries = self.max_retries(total_credentials);
         let mut last_error: Option<anyhow::Error> = None;
 
         for attempt in 0..max_retries {
-            let acquired = match self.token_manager.acquire_context().await {
+            let acquired = match self.token_manager.acquire_context_for(routing).await {
                 Ok(a) => a,
                 Err(e) => {
                     last_error = Some(e);
@@ -589,11 +909,9 @@ impl KiroProvider {
                 }
             };
 
-            let AcquiredContext { ctx, guard } = acquired;
-            let id = ctx.id;
+            failover.observe(acquired.ctx.id);
 
-            let url = self.base_url();
-            let headers = match self.build_headers(&ctx) {
+            let headers = match self.build_headers(&acquired.ctx) {
                 Ok(h) => h,
                 Err(e) => {
                     last_error = Some(e);
@@ -601,14 +919,13 @@ impl KiroProvider {
                 }
             };
 
-            let response = match self
-                .client
-                .post(&url)
-                .headers(headers)
-                .body(request_body.to_string())
-                .send()
-                .await
-            {
+            let started = Instant::now();
+            let (AcquiredContext { ctx, guard }, response) = self
+                .send_hedged(acquired, headers, request_body, routing)
+                .await;
+            let id = ctx.id;
+            failover.observe(id);
+            let response = match response {
                 Ok(resp) => resp,
                 Err(e) => {
                     tracing::warn!(
@@ -619,24 +936,54 @@ impl KiroProvider {
                     );
                     last_error = Some(e.into());
                     if attempt + 1 < max_retries {
-                        sleep(Self::retry_delay(attempt)).await;
+                        sleep(self.retry_delay(attempt)).await;
                     }
                     continue;
                 }
             };
+            clock::observe(response.headers());
 
             let status = response.status();
 
             if status.is_success() {
-                self.token_manager.report_success(id);
+                self.token_manager.report_success(id, started.elapsed());
                 // 返回 StreamResponse，guard 由调用方持有
-                return Ok(StreamResponse { response, guard });
+                return Ok(StreamResponse {
+                    body: Box::pin(response.bytes_stream().map_err(anyhow::Error::from)),
+                    guard,
+                    failover,
+                });
             }
 
             // 失败响应处理（与 call_api_with_retry 相同）
+            let request_id = extract_request_id(response.headers());
+            let retry_after =
+                parse_retry_after(response.headers()).unwrap_or_else(|| self.retry_delay(attempt));
             let body = response.text().await.unwrap_or_default();
 
-            if status.as_u16() == 402 && Self::is_monthly_request_limit(&body) {
+            // 402 额度用尽先于维护 / 版本过低识别：其响应体中的 "please upgrade" 指升级套餐
+            let quota_exhausted = status.as_u16() == 402 && Self::is_monthly_request_limit(&body);
+
+            // 上游维护 / 版本过低：标记凭据降级并告警，不作为普通 5xx 处理
+            if !quota_exhausted && let Some(kind) = detect_unavailable(status.as_u16(), &body) {
+                let (err, should_retry) =
+                    self.handle_upstream_unavailable(id, kind, status.as_u16(), body, request_id);
+                if !should_retry {
+                    return Err(err.into());
+                }
+                last_error = Some(err.into());
+                if attempt + 1 < max_retries {
+                    sleep(self.retry_delay(attempt)).await;
+                }
+                continue;
+            }
+
+            let error = KiroApiError::new("流式 API", status, &body, request_id.clone())
+                .with_retry_after(retry_after);
+            // 附带上游 request-id，便于向上游反馈问题时定位
+            let body = with_request_id(body, request_id.as_deref());
+
+            if quota_exhausted {
                 tracing::warn!(
                     "流式 API 请求失败（额度已用尽，尝试 {}/{}）: {} {}",
                     attempt + 1,
@@ -646,14 +993,14 @@ impl KiroProvider {
                 );
                 let has_available = self.token_manager.report_quota_exhausted(id);
                 if !has_available {
-                    anyhow::bail!("流式 API 请求失败（所有凭据已用尽）: {} {}", status, body);
+                    return Err(error.exhausted().into());
                 }
-                last_error = Some(anyhow::anyhow!("流式 API 请求失败: {} {}", status, body));
+                last_error = Some(error.into());
                 continue;
             }
 
             if status.as_u16() == 400 {
-                anyhow::bail!("流式 API 请求失败: {} {}", status, body);
+                return Err(error.into());
             }
 
             if matches!(status.as_u16(), 401 | 403) {
@@ -666,13 +1013,16 @@ impl KiroProvider {
                 );
                 let has_available = self.token_manager.report_failure(id);
                 if !has_available {
-                    anyhow::bail!("流式 API 请求失败（所有凭据已用尽）: {} {}", status, body);
+                    return Err(error.exhausted().into());
                 }
-                last_error = Some(anyhow::anyhow!("流式 API 请求失败: {} {}", status, body));
+                last_error = Some(error.into());
                 continue;
             }
 
             if matches!(status.as_u16(), 408 | 429) || status.is_server_error() {
+                if status.as_u16() == 429 {
+                    self.token_manager.report_throttled(id);
+                }
                 tracing::warn!(
                     "流式 API 请求失败（上游瞬态错误，尝试 {}/{}）: {} {}",
                     attempt + 1,
@@ -680,15 +1030,15 @@ impl KiroProvider {
                     status,
                     body
                 );
-                last_error = Some(anyhow::anyhow!("流式 API 请求失败: {} {}", status, body));
+                last_error = Some(error.into());
                 if attempt + 1 < max_retries {
-                    sleep(Self::retry_delay(attempt)).await;
+                    sleep(self.retry_delay(attempt)).await;
                 }
                 continue;
             }
 
             if status.is_client_error() {
-                anyhow::bail!("流式 API 请求失败: {} {}", status, body);
+                return Err(error.into());
             }
 
             tracing::warn!(
@@ -698,9 +1048,9 @@ impl KiroProvider {
                 status,
                 body
             );
-            last_error = Some(anyhow::anyhow!("流式 API 请求失败: {} {}", status, body));
+            last_error = Some(error.into());
             if attempt + 1 < max_retries {
-                sleep(Self::retry_delay(attempt)).await;
+                sleep(self.retry_delay(attempt)).await;
             }
         }
 
@@ -709,6 +1059,45 @@ impl KiroProvider {
         }))
     }
 
+    /// 处理上游维护 / 版本过低响应
+    ///
+    /// 标记凭据降级并发送告警，返回 (错误, 是否继续重试)：
+    /// - 版本过低与凭据无关，切换凭据无意义，直接返回
+    /// - 维护中仅在仍有未降级凭据时继续重试
+    fn handle_upstream_unavailable(
+        &self,
+        id: u64,
+        kind: UnavailableKind,
+        status: u16,
+        body: String,
+        request_id: Option<String>,
+    ) -> (UpstreamUnavailableError, bool) {
+        let has_healthy = self.token_manager.report_degraded(id, kind.as_str());
+        let err = UpstreamUnavailableError {
+            kind,
+            status,
+            body,
+            request_id,
+        };
+        tracing::error!("凭据 #{} {}", id, err);
+
+        alert::notify(
+            &format!("upstream_{}", kind.as_str()),
+            &format!("upstream_{}:{}", kind.as_str(), id),
+            format!("凭据 #{} {}", id, err),
+            serde_json::json!({
+                "credentialId": id,
+                "region": self.token_manager.config().region,
+                "kiroVersion": self.token_manager.config().kiro_version,
+                "status": status,
+                "requestId": err.request_id,
+            }),
+        );
+
+        let should_retry = kind == UnavailableKind::Maintenance && has_healthy;
+        (err, should_retry)
+    }
+
     fn is_monthly_request_limit(body: &str) -> bool {
         if body.contains("MONTHLY_REQUEST_COUNT") {
             return true;
@@ -744,22 +1133,175 @@ mod tests {
         KiroProvider::new(Arc::new(tm))
     }
 
+    #[test]
+    fn test_failover_info_observe() {
+        let mut info = FailoverInfo::default();
+        info.observe(1);
+        info.observe(1);
+        assert_eq!(info.switches, 0);
+        info.observe(2);
+        info.observe(3);
+        assert_eq!(
+            info,
+            FailoverInfo {
+                switches: 2,
+                credential_id: 3
+            }
+        );
+    }
+
     #[test]
     fn test_base_url() {
         let config = Config::default();
         let credentials = KiroCredentials::default();
-        let provider = create_test_provider(config, credentials);
-        assert!(provider.base_url().contains("amazonaws.com"));
-        assert!(provider.base_url().contains("generateAssistantResponse"));
+        let provider = create_test_provider(config, credentials.clone());
+        let ctx = CallContext {
+            id: 1,
+            credentials,
+            token: "test_token".to_string(),
+        };
+        let url = provider.endpoints(&ctx)[0].url(API_PATH);
+        assert!(url.contains("amazonaws.com"));
+        assert!(url.contains("generateAssistantResponse"));
     }
 
     #[test]
     fn test_base_domain() {
         let mut config = Config::default();
         config.region = "us-east-1".to_string();
-        let credentials = KiroCredentials::default();
-        let provider = create_test_provider(config, credentials);
-        assert_eq!(provider.base_domain(), "q.us-east-1.amazonaws.com");
+        let credentials = KiroCredentials {
+            failover_regions: vec!["eu-central-1".to_string()],
+            ..Default::default()
+        };
+        let provider = create_test_provider(config, credentials.clone());
+        let mut ctx = CallContext {
+            id: 1,
+            credentials,
+            token: "test_token".to_string(),
+        };
+        let hosts: Vec<_> = provider
+            .endpoints(&ctx)
+            .into_iter()
+            .map(|e| e.host)
+            .collect();
+        assert_eq!(
+            hosts,
+            ["q.us-east-1.amazonaws.com", "q.eu-central-1.amazonaws.com"]
+        );
+
+        // 凭据级 apiRegion 优先于全局 region
+        ctx.credentials.api_region = Some("ap-southeast-1".to_string());
+        assert_eq!(
+            provider.endpoints(&ctx)[0].host,
+            "q.ap-southeast-1.amazonaws.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_with_region_failover() {
+        use axum::{Router, http::StatusCode, routing::post};
+
+        let app = Router::new()
+            .route(
+                "/busy/generateAssistantResponse",
+                post(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+            )
+            .route(
+                "/ok/generateAssistantResponse",
+                post(|headers: axum::http::HeaderMap| async move {
+                    headers[HOST].to_str().unwrap().to_string()
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let credentials = KiroCredentials {
+            refresh_token: Some("a".repeat(150)),
+            endpoint: Some("http://127.0.0.1:1".to_string()),
+            failover_regions: vec![
+                format!("http://{}/busy", addr),
+                format!("http://{}/ok", addr),
+            ],
+            ..Default::default()
+        };
+        let provider = create_test_provider(Config::default(), credentials.clone());
+        let ctx = CallContext {
+            id: 1,
+            credentials,
+            token: "test_token".to_string(),
+        };
+
+        let headers = provider.build_headers(&ctx).unwrap();
+        assert_eq!(headers[HOST], "127.0.0.1:1");
+        let response = provider
+            .send_with_region_failover(&ctx, API_PATH, headers.clone(), "{}")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), addr.to_string());
+
+        // 所有端点都不可用时返回最后一个端点的结果
+        let mut ctx = ctx;
+        ctx.credentials.failover_regions.pop();
+        let response = provider
+            .send_with_region_failover(&ctx, API_PATH, headers, "{}")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_send_hedged_uses_faster_credential() {
+        use axum::{Router, routing::post};
+
+        let app = Router::new()
+            .route(
+                "/slow/generateAssistantResponse",
+                post(|| async {
+                    sleep(Duration::from_secs(5)).await;
+                    "slow"
+                }),
+            )
+            .route("/fast/generateAssistantResponse", post(|| async { "fast" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let expires_at = Some((chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339());
+        let credentials = ["slow", "fast"]
+            .map(|path| KiroCredentials {
+                refresh_token: Some(path.repeat(50)),
+                access_token: Some("t".to_string()),
+                expires_at: expires_at.clone(),
+                endpoint: Some(format!("http://{}/{}", addr, path)),
+                ..Default::default()
+            })
+            .to_vec();
+        let mut config = Config::default();
+        config.resilience.hedge_delay_ms = 50;
+        let tm = MultiTokenManager::new(config, credentials, None, None, false).unwrap();
+        let provider = KiroProvider::new(Arc::new(tm));
+
+        let routing = Routing {
+            avoid: &[2],
+            ..Routing::default()
+        };
+        let primary = provider
+            .token_manager
+            .acquire_context_for(routing)
+            .await
+            .unwrap();
+        assert_eq!(primary.ctx.id, 1);
+        let headers = provider.build_headers(&primary.ctx).unwrap();
+        let (winner, response) = tokio::time::timeout(
+            Duration::from_secs(2),
+            provider.send_hedged(primary, headers, "{}", Routing::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(winner.ctx.id, 2);
+        assert_eq!(response.unwrap().text().await.unwrap(), "fast");
     }
 
     #[test]
@@ -794,6 +1336,26 @@ mod tests {
         assert_eq!(headers.get(CONNECTION).unwrap(), "close");
     }
 
+    #[test]
+    fn test_build_headers_with_invalid_region() {
+        let credentials = KiroCredentials {
+            refresh_token: Some("a".repeat(150)),
+            api_region: Some("us-east-1\nx".to_string()),
+            ..KiroCredentials::default()
+        };
+
+        let provider = create_test_provider(Config::default(), credentials.clone());
+        let ctx = CallContext {
+            id: 1,
+            credentials,
+            token: "test_token".to_string(),
+        };
+        let headers = provider.build_headers(&ctx).unwrap();
+        assert!(headers.get(HOST).is_none());
+        let headers = provider.build_mcp_headers(&ctx).unwrap();
+        assert!(headers.get(HOST).is_none());
+    }
+
     #[test]
     fn test_is_monthly_request_limit_detects_reason() {
         let body = r#"{"message":"You have reached the limit.","reason":"MONTHLY_REQUEST_COUNT"}"#;
@@ -0,0 +1,187 @@
+//! 流式响应的首个内容探测
+//!
+//! 上游流可能在连接建立后、输出任何内容前中断（连接被重置、空响应等）。此时尚未向
+//! 客户端发送任何数据，可以透明地换用其他凭据重试。[`read_until_content`] 读取并缓存
+//! 响应体，直到收到第一个内容事件（文本、工具调用、错误或异常）为止。
+//!
+//! 内容开始输出后，[`with_idle_timeout`] 负责发现中途停滞的上游流
+
+use std::fmt::Display;
+use std::pin::Pin;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt, stream};
+
+use crate::kiro::error::StreamTimeout;
+use crate::kiro::model::events::Event;
+use crate::kiro::parser::decoder::EventStreamDecoder;
+
+/// 上游响应体字节流
+pub type BodyStream<E> = Pin<Box<dyn Stream<Item = Result<Bytes, E>> + Send>>;
+
+/// 探测结果，两种情况都会返回包含已读取数据的完整响应体
+pub enum Probe<E> {
+    /// 已收到内容
+    Started(BodyStream<E>),
+    /// 输出内容前中断
+    Died { body: BodyStream<E>, reason: String },
+}
+
+/// 读取响应体直到收到第一个内容事件或流中断
+pub async fn read_until_content<E>(mut body: BodyStream<E>) -> Probe<E>
+where
+    E: Display + Send + 'static,
+{
+    let mut decoder = EventStreamDecoder::new();
+    let mut buffered = Vec::new();
+
+    let reason = loop {
+        match body.next().await {
+            Some(Ok(chunk)) => {
+                // 无法解码时不再探测，交给下游按原有逻辑处理
+                let started = decoder.feed(&chunk).is_err() || has_content(&mut decoder);
+                buffered.push(Ok(chunk));
+                if started {
+                    return Probe::Started(replay(buffered, body));
+                }
+            }
+            Some(Err(e)) => {
+                let reason = e.to_string();
+                buffered.push(Err(e));
+                break reason;
+            }
+            None => break "上游在输出内容前结束了响应流".to_string(),
+        }
+    };
+
+    Probe::Died {
+        body: replay(buffered, body),
+        reason,
+    }
+}
+
+/// 解码缓冲区中的帧，判断是否出现内容事件
+fn has_content(decoder: &mut EventStreamDecoder) -> bool {
+    decoder.decode_iter().flatten().any(|frame| {
+        matches!(
+            Event::from_frame(frame),
+            Ok(Event::AssistantResponse(_)
+                | Event::ToolUse(_)
+                | Event::Error { .. }
+                | Event::Exception { .. })
+        )
+    })
+}
+
+/// 为响应体附加空闲超时：超过 `idle` 没有收到数据时产生 [`StreamTimeout::Idle`] 错误并结束
+pub fn with_idle_timeout(
+    body: BodyStream<anyhow::Error>,
+    idle: Option<Duration>,
+) -> BodyStream<anyhow::Error> {
+    let Some(idle) = idle else {
+        return body;
+    };
+    Box::pin(stream::unfold(Some(body), move |body| async move {
+        let mut body = body?;
+        match tokio::time::timeout(idle, body.next()).await {
+            Ok(Some(item)) => Some((item, Some(body))),
+            Ok(None) => None,
+            Err(_) => Some((Err(StreamTimeout::Idle(idle).into()), None)),
+        }
+    }))
+}
+
+/// 先重放已读取的数据，再继续读取剩余响应体
+fn replay<E>(buffered: Vec<Result<Bytes, E>>, rest: BodyStream<E>) -> BodyStream<E>
+where
+    E: Send + 'static,
+{
+    Box::pin(stream::iter(buffered).chain(rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kiro::parser::crc::crc32;
+
+    /// 编码一个 event 类型的帧
+    fn event_frame(event_type: &str, payload: &str) -> Bytes {
+        let mut headers = Vec::new();
+        for (name, value) in [(":message-type", "event"), (":event-type", event_type)] {
+            headers.push(name.len() as u8);
+            headers.extend_from_slice(name.as_bytes());
+            headers.push(7);
+            headers.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            headers.extend_from_slice(value.as_bytes());
+        }
+
+        let total = 12 + headers.len() + payload.len() + 4;
+        let mut frame = (total as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&(headers.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&crc32(&frame).to_be_bytes());
+        frame.extend_from_slice(&headers);
+        frame.extend_from_slice(payload.as_bytes());
+        frame.extend_from_slice(&crc32(&frame).to_be_bytes());
+        Bytes::from(frame)
+    }
+
+    fn chunked(chunks: Vec<Result<Bytes, String>>) -> BodyStream<String> {
+        Box::pin(stream::iter(chunks))
+    }
+
+    async fn collect(body: BodyStream<String>) -> Vec<Result<Bytes, String>> {
+        body.collect().await
+    }
+
+    #[tokio::test]
+    async fn test_started_replays_buffered_chunks() {
+        let usage = event_frame("contextUsageEvent", r#"{"contextUsagePercentage":1.0}"#);
+        let content = event_frame("assistantResponseEvent", r#"{"content":"hi"}"#);
+        // 内容帧被拆成两段，探测需跨分片解码
+        let chunks = vec![
+            Ok(usage.clone()),
+            Ok(content.slice(..10)),
+            Ok(content.slice(10..)),
+            Ok(Bytes::from_static(b"tail")),
+        ];
+
+        let Probe::Started(body) = read_until_content(chunked(chunks.clone())).await else {
+            panic!("收到内容事件后应视为已开始");
+        };
+        assert_eq!(collect(body).await, chunks);
+    }
+
+    #[tokio::test]
+    async fn test_died_before_content() {
+        let usage = event_frame("meteringEvent", "{}");
+
+        let chunks = vec![Ok(usage.clone()), Err("connection reset".to_string())];
+        let Probe::Died { body, reason } = read_until_content(chunked(chunks.clone())).await else {
+            panic!("输出内容前出错应视为中断");
+        };
+        assert_eq!(reason, "connection reset");
+        assert_eq!(collect(body).await, chunks);
+
+        let chunks = vec![Ok(usage)];
+        assert!(matches!(
+            read_until_content(chunked(chunks)).await,
+            Probe::Died { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_ends_stalled_stream() {
+        let idle = Duration::from_millis(50);
+        let chunks = stream::iter([Ok(Bytes::from_static(b"data"))]).chain(stream::pending());
+        let mut body = with_idle_timeout(Box::pin(chunks), Some(idle));
+
+        assert!(body.next().await.unwrap().is_ok());
+        let error = body.next().await.unwrap().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<StreamTimeout>(),
+            Some(&StreamTimeout::Idle(idle))
+        );
+        assert!(body.next().await.is_none());
+    }
+}
@@ -5,22 +5,30 @@
 
 use anyhow::bail;
 use chrono::{DateTime, Duration, Utc};
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use serde::Serialize;
-use tokio::sync::Mutex as TokioMutex;
+use tokio::sync::{Mutex as TokioMutex, Notify};
 
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use crate::common::metrics;
 use crate::http_client::{ProxyConfig, build_client};
+use crate::kiro::circuit_breaker::{BreakerState, CircuitBreaker};
+use crate::kiro::clock;
+use crate::kiro::concurrency::AdaptiveLimit;
+use crate::kiro::error::CredentialsUnavailable;
 use crate::kiro::machine_id;
-use crate::kiro::model::credentials::KiroCredentials;
+use crate::kiro::model::credentials::{KiroCredentials, normalize_tags};
 use crate::kiro::model::token_refresh::{
     IdcRefreshRequest, IdcRefreshResponse, RefreshRequest, RefreshResponse,
 };
 use crate::kiro::model::usage_limits::UsageLimitsResponse;
-use crate::model::config::Config;
+use crate::model::config::{ClientPriority, Config, SchedulingStrategy};
+use crate::storage::MemoryStorage;
+use crate::storage::credential_stats::CredentialStats;
+use crate::storage::session_affinity::SessionAffinity;
 
 /// Token 管理器
 ///
@@ -80,26 +88,26 @@ impl TokenManager {
     }
 }
 
-/// 检查 Token 是否在指定时间内过期
+/// 检查 Token 是否在指定时间内过期（按上游时间校正本地时钟偏差）
 pub(crate) fn is_token_expiring_within(
     credentials: &KiroCredentials,
-    minutes: i64,
+    within: Duration,
 ) -> Option<bool> {
     credentials
         .expires_at
         .as_ref()
         .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
-        .map(|expires| expires <= Utc::now() + Duration::minutes(minutes))
+        .map(|expires| expires <= clock::now() + within)
 }
 
 /// 检查 Token 是否已过期（提前 5 分钟判断）
 pub(crate) fn is_token_expired(credentials: &KiroCredentials) -> bool {
-    is_token_expiring_within(credentials, 5).unwrap_or(true)
+    is_token_expiring_within(credentials, Duration::minutes(5)).unwrap_or(true)
 }
 
 /// 检查 Token 是否即将过期（10分钟内）
 pub(crate) fn is_token_expiring_soon(credentials: &KiroCredentials) -> bool {
-    is_token_expiring_within(credentials, 10).unwrap_or(false)
+    is_token_expiring_within(credentials, Duration::minutes(10)).unwrap_or(false)
 }
 
 /// 验证 refreshToken 的基本有效性
@@ -190,6 +198,7 @@ async fn refresh_social_token(
         .json(&body)
         .send()
         .await?;
+    clock::observe(response.headers());
 
     let status = response.status();
     if !status.is_success() {
@@ -218,7 +227,7 @@ async fn refresh_social_token(
     }
 
     if let Some(expires_in) = data.expires_in {
-        let expires_at = Utc::now() + Duration::seconds(expires_in);
+        let expires_at = clock::now() + Duration::seconds(expires_in);
         new_credentials.expires_at = Some(expires_at.to_rfc3339());
     }
 
@@ -272,6 +281,7 @@ async fn refresh_idc_token(
         .json(&body)
         .send()
         .await?;
+    clock::observe(response.headers());
 
     let status = response.status();
     if !status.is_success() {
@@ -296,7 +306,7 @@ async fn refresh_idc_token(
     }
 
     if let Some(expires_in) = data.expires_in {
-        let expires_at = Utc::now() + Duration::seconds(expires_in);
+        let expires_at = clock::now() + Duration::seconds(expires_in);
         new_credentials.expires_at = Some(expires_at.to_rfc3339());
     }
 
@@ -315,17 +325,13 @@ pub(crate) async fn get_usage_limits(
 ) -> anyhow::Result<UsageLimitsResponse> {
     tracing::debug!("正在获取使用额度信息...");
 
-    let region = &config.region;
-    let host = format!("q.{}.amazonaws.com", region);
+    let endpoint = credentials.api_endpoints(&config.region).remove(0);
     let machine_id = machine_id::generate_from_credentials(credentials, config)
         .ok_or_else(|| anyhow::anyhow!("无法生成 machineId"))?;
     let kiro_version = &config.kiro_version;
 
     // 构建 URL
-    let mut url = format!(
-        "https://{}/getUsageLimits?origin=AI_EDITOR&resourceType=AGENTIC_REQUEST",
-        host
-    );
+    let mut url = endpoint.url("getUsageLimits?origin=AI_EDITOR&resourceType=AGENTIC_REQUEST");
 
     // profileArn 是可选的
     if let Some(profile_arn) = &credentials.profile_arn {
@@ -349,13 +355,14 @@ pub(crate) async fn get_usage_limits(
         .get(&url)
         .header("x-amz-user-agent", &amz_user_agent)
         .header("User-Agent", &user_agent)
-        .header("host", &host)
+        .header("host", &endpoint.host)
         .header("amz-sdk-invocation-id", uuid::Uuid::new_v4().to_string())
         .header("amz-sdk-request", "attempt=1; max=1")
         .header("Authorization", format!("Bearer {}", token))
         .header("Connection", "close")
         .send()
         .await?;
+    clock::observe(response.headers());
 
     let status = response.status();
     if !status.is_success() {
@@ -390,8 +397,90 @@ struct CredentialEntry {
     disabled: bool,
     /// 当前活跃连接数（Least-Connections 负载均衡）
     active_connections: Arc<AtomicUsize>,
+    /// 自适应并发上限
+    concurrency: AdaptiveLimit,
     /// 禁用原因（用于区分手动禁用 vs 自动禁用，便于自愈）
     disabled_reason: Option<DisabledReason>,
+    /// 降级原因（上游维护/版本过低等），降级凭据仍可用但选择优先级最低
+    degraded_reason: Option<String>,
+    /// 因连续失败被熔断的时间（用于冷却后自动恢复）
+    tripped_at: Option<std::time::Instant>,
+    /// 熔断器（连续被拒绝时暂时跳过，冷却后探测恢复）
+    breaker: CircuitBreaker,
+    /// 额度用尽时的下次重置时间（到达后自动重新启用）
+    quota_reset_at: Option<DateTime<Utc>>,
+}
+
+impl CredentialEntry {
+    fn new(id: u64, credentials: KiroCredentials, config: &Config) -> Self {
+        Self {
+            id,
+            credentials,
+            failure_count: 0,
+            disabled: false,
+            active_connections: Arc::new(AtomicUsize::new(0)),
+            concurrency: AdaptiveLimit::new(&config.concurrency),
+            disabled_reason: None,
+            degraded_reason: None,
+            tripped_at: None,
+            breaker: CircuitBreaker::default(),
+            quota_reset_at: None,
+        }
+    }
+
+    /// 调度权重（未配置时为 1）
+    fn weight(&self) -> u32 {
+        self.credentials.weight.unwrap_or(1)
+    }
+
+    /// 生效的并发上限（自适应上限与 `maxConcurrent` 中较小者）
+    fn concurrency_limit(&self) -> usize {
+        let limit = self.concurrency.limit();
+        match self.credentials.max_concurrent {
+            Some(max) => limit.min(max.max(1) as usize),
+            None => limit,
+        }
+    }
+
+    /// 是否已达到 `maxConcurrent` 硬上限（达到后不再分配新请求）
+    fn at_hard_limit(&self) -> bool {
+        self.credentials.max_concurrent.is_some_and(|max| {
+            self.active_connections.load(Ordering::Acquire) >= max.max(1) as usize
+        })
+    }
+}
+
+/// 重新启用因额度用尽被禁用、且已到达重置时间的凭据
+fn recover_quota_reset(entries: &mut [CredentialEntry], now: DateTime<Utc>) {
+    for e in entries.iter_mut() {
+        if e.disabled_reason == Some(DisabledReason::QuotaExceeded)
+            && e.quota_reset_at.is_some_and(|at| at <= now)
+        {
+            enable_after_quota_reset(e);
+            tracing::info!("凭据 #{} 额度已重置，已自动重新启用", e.id);
+        }
+    }
+}
+
+fn enable_after_quota_reset(entry: &mut CredentialEntry) {
+    entry.disabled = false;
+    entry.disabled_reason = None;
+    entry.failure_count = 0;
+    entry.quota_reset_at = None;
+}
+
+/// 选择活跃连接数最少的凭据，连接数相同时随机选择
+fn least_connections(candidates: Vec<&CredentialEntry>) -> &CredentialEntry {
+    let min_connections = candidates
+        .iter()
+        .map(|e| e.active_connections.load(Ordering::Acquire))
+        .min()
+        .unwrap();
+    let best: Vec<_> = candidates
+        .into_iter()
+        .filter(|e| e.active_connections.load(Ordering::Acquire) == min_connections)
+        .collect();
+    best[fastrand::usize(..best.len())]
 }
 
 /// 禁用原因
@@ -405,6 +494,17 @@ enum DisabledReason {
     QuotaExceeded,
 }
 
+impl DisabledReason {
+    /// Admin API 中展示的名称
+    fn as_str(self) -> &'static str {
+        match self {
+            DisabledReason::Manual => "manual",
+            DisabledReason::TooManyFailures => "too_many_failures",
+            DisabledReason::QuotaExceeded => "quota_exhausted",
+        }
+    }
+}
+
 // ============================================================================
 // Admin API 公开结构
 // ============================================================================
@@ -417,8 +517,16 @@ pub struct CredentialEntrySnapshot {
     pub id: u64,
     /// 优先级
     pub priority: u32,
+    /// 调度权重
+    pub weight: u32,
+    /// 分组标签
+    pub tags: Vec<String>,
     /// 是否被禁用
     pub disabled: bool,
+    /// 禁用原因：`manual` / `too_many_failures` / `quota_exhausted`
+    pub disabled_reason: Option<&'static str>,
+    /// 额度用尽时的下次重置时间（RFC3339，到达后自动重新启用）
+    pub quota_reset_at: Option<String>,
     /// 连续失败次数
     pub failure_count: u32,
     /// 认证方式
@@ -429,8 +537,12 @@ pub struct CredentialEntrySnapshot {
     pub expires_at: Option<String>,
     /// 当前活跃连接数
     pub active_connections: u32,
-    /// 最大并发连接数
+    /// 最大并发连接数（自适应调整后的当前值）
     pub max_concurrent: u32,
+    /// 降级原因（为空表示健康）
+    pub degraded_reason: Option<String>,
+    /// 熔断器状态
+    pub breaker_state: BreakerState,
 }
 
 /// 凭据管理器状态快照
@@ -447,13 +559,42 @@ pub struct ManagerSnapshot {
     pub available: usize,
 }
 
+/// 凭据热重载结果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CredentialsReload {
+    /// 新增的凭据 ID
+    pub added: Vec<u64>,
+    /// 移除的凭据 ID
+    pub removed: Vec<u64>,
+    /// 内容有变化的凭据 ID
+    pub updated: Vec<u64>,
+}
+
+impl CredentialsReload {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+}
+
+/// 凭据导入结果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CredentialsImport {
+    /// 导入后分配的凭据 ID
+    pub imported: Vec<u64>,
+    /// 与已有凭据 refresh token 相同而跳过的数量
+    pub duplicates: usize,
+    /// refresh token 无效而跳过的数量
+    pub invalid: usize,
+}
+
 /// 多凭据 Token 管理器
 ///
-/// 支持多个凭据的管理，实现固定优先级 + 故障转移策略
+/// 支持多个凭据的管理，按调度策略分配请求并在故障时转移
 /// 故障统计基于 API 调用结果，而非 Token 刷新结果
 pub struct MultiTokenManager {
-    config: Config,
-    proxy: Option<ProxyConfig>,
+    /// 应用配置（热重载时整体替换）
+    config: RwLock<Arc<Config>>,
+    proxy: RwLock<Option<ProxyConfig>>,
     /// 凭据条目列表
     entries: Mutex<Vec<CredentialEntry>>,
     /// 当前活动凭据 ID
@@ -462,15 +603,30 @@ pub struct MultiTokenManager {
     refresh_lock: TokioMutex<()>,
     /// 凭据文件路径（用于回写）
     credentials_path: Option<PathBuf>,
-    /// 是否为多凭据格式（数组格式才回写）
-    is_multiple_format: bool,
+    /// 是否为多凭据格式（数组格式才回写；导入凭据时单凭据文件会转换为数组格式）
+    is_multiple_format: AtomicBool,
+    /// 会话 ID -> 绑定的凭据（`stickySessionTtlSecs` 内未使用则失效，默认仅保存在内存中）
+    sessions: SessionAffinity,
+    /// 凭据用量统计（可选，持久化到存储后端）
+    stats: Option<Arc<CredentialStats>>,
+    /// 连接释放通知（唤醒等待空闲连接的请求）
+    released: Arc<Notify>,
+    /// 各优先级（从高到低）正在等待空闲连接的请求数
+    waiting: [AtomicUsize; 3],
 }
 
-/// 每个凭据最大 API 调用失败次数
-const MAX_FAILURES_PER_CREDENTIAL: u32 = 3;
-
-/// 每个凭据最大并发连接数
-pub const MAX_CONCURRENT_PER_CREDENTIAL: u32 = 3;
+/// 单次请求的凭据选择约束
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Routing<'a> {
+    /// 会话 ID（用于凭据粘性绑定）
+    pub session: Option<&'a str>,
+    /// 凭据分组（只使用带有该标签的凭据）
+    pub group: Option<&'a str>,
+    /// 本次请求中已失败的凭据（仅在没有其他候选时使用）
+    pub avoid: &'a [u64],
+    /// 客户端优先级（等待空闲连接时高优先级先行，低优先级不超额分配）
+    pub priority: ClientPriority,
+}
 
 /// API 调用上下文
 ///
@@ -493,11 +649,13 @@ pub struct CallContext {
 pub struct ConnectionGuard {
     id: u64,
     active_connections: Arc<AtomicUsize>,
+    released: Arc<Notify>,
 }
 
 impl Drop for ConnectionGuard {
     fn drop(&mut self) {
         self.active_connections.fetch_sub(1, Ordering::AcqRel);
+        self.released.notify_waiters();
     }
 }
 
@@ -511,6 +669,26 @@ pub struct AcquiredContext {
     pub guard: ConnectionGuard,
 }
 
+/// 等待空闲连接的请求计数，释放时唤醒其余等待者重新检查（低优先级请求可能在让行）
+struct WaitingGuard<'a> {
+    waiting: &'a AtomicUsize,
+    released: &'a Notify,
+}
+
+impl<'a> WaitingGuard<'a> {
+    fn new(waiting: &'a AtomicUsize, released: &'a Notify) -> Self {
+        waiting.fetch_add(1, Ordering::AcqRel);
+        Self { waiting, released }
+    }
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::AcqRel);
+        self.released.notify_waiters();
+    }
+}
+
 impl MultiTokenManager {
     /// 创建多凭据 Token 管理器
     ///
@@ -553,14 +731,7 @@ impl MultiTokenManager {
                         has_new_machine_ids = true;
                     }
                 }
-                CredentialEntry {
-                    id,
-                    credentials: cred,
-                    failure_count: 0,
-                    disabled: false,
-                    active_connections: Arc::new(AtomicUsize::new(0)),
-                    disabled_reason: None,
-                }
+                CredentialEntry::new(id, cred, config_ref)
             })
             .collect();
 
@@ -584,13 +755,17 @@ impl MultiTokenManager {
             .unwrap_or(0);
 
         let manager = Self {
-            config,
-            proxy,
+            config: RwLock::new(Arc::new(config)),
+            proxy: RwLock::new(proxy),
             entries: Mutex::new(entries),
             current_id: Mutex::new(initial_id),
             refresh_lock: TokioMutex::new(()),
             credentials_path,
-            is_multiple_format,
+            is_multiple_format: AtomicBool::new(is_multiple_format),
+            sessions: SessionAffinity::new(Arc::new(MemoryStorage::new())),
+            released: Arc::new(Notify::new()),
+            waiting: Default::default(),
+            stats: None,
         };
 
         // 如果有新分配的 ID 或新生成的 machineId，立即持久化到配置文件
@@ -605,9 +780,43 @@ impl MultiTokenManager {
         Ok(manager)
     }
 
-    /// 获取配置的引用
-    pub fn config(&self) -> &Config {
-        &self.config
+    /// 设置会话绑定表（使用持久化存储时绑定在重启后保留，并可在多实例间共享）
+    pub fn with_session_affinity(mut self, sessions: SessionAffinity) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// 设置凭据用量统计
+    pub fn with_credential_stats(mut self, stats: Arc<CredentialStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// 记录凭据的一次失败到用量统计
+    fn record_stats_failure(&self, id: u64) {
+        if let Some(stats) = &self.stats {
+            stats.record_failure(id);
+        }
+    }
+
+    /// 获取当前配置
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    /// 替换配置（热重载），已选定凭据的进行中请求不受影响
+    pub fn set_config(&self, config: Config) {
+        *self.config.write() = Arc::new(config);
+    }
+
+    /// 获取当前代理配置
+    fn proxy(&self) -> Option<ProxyConfig> {
+        self.proxy.read().clone()
+    }
+
+    /// 替换代理配置（热重载，用于 Token 刷新与额度查询）
+    pub fn set_proxy(&self, proxy: Option<ProxyConfig>) {
+        *self.proxy.write() = proxy;
     }
 
     /// 获取当前活动凭据的克隆
@@ -631,26 +840,77 @@ impl MultiTokenManager {
         self.entries.lock().iter().filter(|e| !e.disabled).count()
     }
 
+    /// 所有凭据上的活跃连接总数（进行中的上游请求与流式响应）
+    pub fn active_connections(&self) -> usize {
+        self.entries
+            .lock()
+            .iter()
+            .map(|e| e.active_connections.load(Ordering::Acquire))
+            .sum()
+    }
+
     /// 获取 API 调用上下文
     ///
     /// 返回绑定了 id、credentials、token 和连接守卫的调用上下文
     /// 确保整个 API 调用过程中使用一致的凭据信息
     ///
-    /// 选择策略：Least-Connections（优先级 > 活跃连接数 > ID）
-    /// - 优先选择优先级高（数值小）的凭据
-    /// - 同优先级下选择活跃连接数最少的凭据
-    /// - 连接数相同时选择 ID 较小的凭据（稳定排序）
+    /// 选择策略由 `schedulingStrategy` 决定（默认 `least_connections`）：
+    /// - `priority`：优先级最高（数值小）的凭据中选择活跃连接数最少的
+    /// - `round_robin`：按凭据 ID 轮流使用
+    /// - `weighted`：按凭据 `weight` 加权随机
+    /// - `least_connections`：活跃连接数最少的凭据，相同时随机选择
+    ///
+    /// 超过并发上限与已降级的凭据仅在没有其他候选时才会被选中；
+    /// 达到 `maxConcurrent` 硬上限的凭据不会被选中，全部达到时排队等待连接释放
     ///
     /// 如果 Token 过期或即将过期，会自动刷新
     /// Token 刷新失败时会尝试下一个可用凭据（不计入失败次数）
     pub async fn acquire_context(&self) -> anyhow::Result<AcquiredContext> {
+        self.acquire_context_for(Routing::default()).await
+    }
+
+    /// 按请求约束获取 API 调用上下文
+    ///
+    /// - 指定分组时只从带有该标签的凭据中选择
+    /// - 会话已绑定凭据且该凭据仍可用（未禁用、未降级、属于该分组）时优先使用，
+    ///   使上游看到一致的会话；否则按 `acquire_context` 的策略选择并重新绑定
+    /// - `avoid` 中的凭据（本次请求已失败）仅在没有其他候选时使用
+    /// - 低优先级请求在所有凭据都超过并发上限时不超额分配，而是排队等待；
+    ///   有更高优先级的请求在等待时，低优先级请求让行
+    pub async fn acquire_context_for(
+        &self,
+        routing: Routing<'_>,
+    ) -> anyhow::Result<AcquiredContext> {
+        let Routing {
+            session,
+            group,
+            avoid,
+            priority,
+        } = routing;
         let mut tried_ids = std::collections::HashSet::<u64>::new();
+        let bound_id = match session {
+            Some(session) => self.bound_credential(session).await,
+            None => None,
+        };
+        let in_group = |e: &CredentialEntry| group.is_none_or(|group| e.credentials.has_tag(group));
+
+        let mut queue_deadline = None;
+        let mut waiting = None;
 
         loop {
-            let (id, credentials, guard) = {
+            // 在检查并发前注册释放通知，避免错过检查与等待之间释放的连接
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            let selected = 'select: {
                 let mut entries = self.entries.lock();
                 let total = entries.len();
 
+                // 熔断冷却结束、额度已重置的凭据自动恢复
+                self.recover_cooled_down(&mut entries);
+                recover_quota_reset(&mut entries, Utc::now());
+
                 // 检查是否需要自愈：所有凭据都因 TooManyFailures 被禁用
                 let available = entries.iter().filter(|e| !e.disabled).count();
                 if available == 0
@@ -666,65 +926,118 @@ impl MultiTokenManager {
                             e.disabled = false;
                             e.disabled_reason = None;
                             e.failure_count = 0;
+                            e.tripped_at = None;
                         }
                     }
                 }
 
                 let available = entries.iter().filter(|e| !e.disabled).count();
                 if available == 0 {
-                    anyhow::bail!("所有凭据均已禁用（{}/{}）", available, total);
+                    return Err(CredentialsUnavailable(format!(
+                        "所有凭据均已禁用（{}/{}）",
+                        available, total
+                    ))
+                    .into());
+                }
+                if let Some(group) = group
+                    && !entries.iter().any(|e| !e.disabled && in_group(e))
+                {
+                    return Err(
+                        CredentialsUnavailable(format!("分组 {} 中没有可用凭据", group)).into(),
+                    );
                 }
 
-                // 单凭证最大并发数
-                let max_concurrent = MAX_CONCURRENT_PER_CREDENTIAL as usize;
+                // 会话粘性：已绑定的凭据可用时直接使用（不受自适应并发上限限制，但受硬上限限制）
+                let sticky = bound_id.and_then(|bound| {
+                    entries.iter().find(|e| {
+                        e.id == bound
+                            && !e.disabled
+                            && !e.at_hard_limit()
+                            && e.degraded_reason.is_none()
+                            && !tried_ids.contains(&e.id)
+                            && !avoid.contains(&e.id)
+                            && in_group(e)
+                            && e.breaker.allows_request(&self.config().resilience)
+                    })
+                });
 
-                // Least-Connections 负载均衡：
-                // 1. 先筛选出可用且未超过并发限制的凭证
-                // 2. 找出连接数最少的凭证
-                // 3. 如果有多个连接数相同的，随机选一个
+                // 负载均衡：
+                // 1. 先筛选出可用且未超过各自并发上限（自适应调整）的凭证
+                // 2. 按 `schedulingStrategy` 从候选中选择
                 let candidates: Vec<_> = entries
                     .iter()
-                    .filter(|e| !e.disabled && !tried_ids.contains(&e.id))
-                    .filter(|e| e.active_connections.load(Ordering::Acquire) < max_concurrent)
+                    .filter(|e| !e.disabled && !tried_ids.contains(&e.id) && in_group(e))
+                    .filter(|e| {
+                        e.active_connections.load(Ordering::Acquire) < e.concurrency_limit()
+                    })
                     .collect();
 
-                // 如果所有凭证都超过并发限制，退化为选择连接数最少的
-                let candidates = if candidates.is_empty() {
+                // 如果所有凭证都超过并发限制，退化为选择连接数最少的（达到硬上限的除外）；
+                // 低优先级请求不超额分配，排队等待连接释放
+                let candidates = if candidates.is_empty() && priority != ClientPriority::Low {
                     entries
                         .iter()
-                        .filter(|e| !e.disabled && !tried_ids.contains(&e.id))
+                        .filter(|e| !e.disabled && !tried_ids.contains(&e.id) && in_group(e))
+                        .filter(|e| !e.at_hard_limit())
                         .collect::<Vec<_>>()
                 } else {
                     candidates
                 };
 
-                if candidates.is_empty() {
-                    anyhow::bail!(
-                        "所有凭据均无法获取有效 Token（可用: {}/{}）",
-                        available,
-                        total
-                    );
+                // 所有凭证都达到上限，或有更高优先级的请求在等待：排队等待连接释放
+                if (candidates.is_empty() || self.higher_priority_waiting(priority))
+                    && sticky.is_none()
+                    && entries
+                        .iter()
+                        .any(|e| !e.disabled && !tried_ids.contains(&e.id) && in_group(e))
+                {
+                    break 'select None;
                 }
 
-                // 找出最小的 active_connections
-                let min_connections = candidates
+                // 跳过熔断中的凭证，全部熔断时仍允许使用（与其直接失败不如尝试）
+                let candidates = if candidates
                     .iter()
-                    .map(|e| e.active_connections.load(Ordering::Acquire))
-                    .min()
-                    .unwrap();
+                    .any(|e| e.breaker.allows_request(&self.config().resilience))
+                {
+                    candidates
+                        .into_iter()
+                        .filter(|e| e.breaker.allows_request(&self.config().resilience))
+                        .collect::<Vec<_>>()
+                } else {
+                    candidates
+                };
 
-                // 筛选出所有具有相同最小连接数的凭证
-                let best_candidates: Vec<_> = candidates
-                    .into_iter()
-                    .filter(|e| e.active_connections.load(Ordering::Acquire) == min_connections)
-                    .collect();
+                // 避开本次请求中已失败的凭证，没有其他候选时仍允许使用
+                let candidates = if candidates.iter().any(|e| !avoid.contains(&e.id)) {
+                    candidates
+                        .into_iter()
+                        .filter(|e| !avoid.contains(&e.id))
+                        .collect::<Vec<_>>()
+                } else {
+                    candidates
+                };
 
-                // 从最佳候选中随机选择一个
-                let entry = if best_candidates.len() == 1 {
-                    best_candidates[0]
+                // 优先选择未降级的凭证，全部降级时仍允许使用（降级不等于禁用）
+                let candidates = if candidates.iter().any(|e| e.degraded_reason.is_none()) {
+                    candidates
+                        .into_iter()
+                        .filter(|e| e.degraded_reason.is_none())
+                        .collect::<Vec<_>>()
                 } else {
-                    let idx = fastrand::usize(..best_candidates.len());
-                    best_candidates[idx]
+                    candidates
+                };
+
+                if candidates.is_empty() {
+                    return Err(CredentialsUnavailable(format!(
+                        "所有凭据均无法获取有效 Token（可用: {}/{}）",
+                        available, total
+                    ))
+                    .into());
+                }
+
+                let entry = match sticky {
+                    Some(entry) => entry,
+                    None => self.schedule(candidates),
                 };
 
                 let id = entry.id;
@@ -737,9 +1050,32 @@ impl MultiTokenManager {
                 let guard = ConnectionGuard {
                     id,
                     active_connections: counter,
+                    released: Arc::clone(&self.released),
                 };
 
-                (id, credentials, guard)
+                // half-open 的凭据被选中即作为探测请求
+                if let Some(e) = entries.iter_mut().find(|e| e.id == id) {
+                    e.breaker.on_acquire(&self.config().resilience);
+                }
+
+                Some((id, credentials, guard))
+            };
+            let Some((id, credentials, guard)) = selected else {
+                waiting.get_or_insert_with(|| {
+                    WaitingGuard::new(&self.waiting[priority.rank()], &self.released)
+                });
+                let timeout = self.config().concurrency.queue_timeout_ms;
+                let deadline = *queue_deadline.get_or_insert_with(|| {
+                    tokio::time::Instant::now() + std::time::Duration::from_millis(timeout)
+                });
+                if tokio::time::timeout_at(deadline, released).await.is_err() {
+                    return Err(CredentialsUnavailable(format!(
+                        "所有凭据均已达到并发上限，排队等待 {} ms 后超时",
+                        timeout
+                    ))
+                    .into());
+                }
+                continue;
             };
 
             // 更新 current_id（记录最近分配的凭据）
@@ -751,6 +1087,9 @@ impl MultiTokenManager {
             // 尝试获取/刷新 Token
             match self.try_ensure_token(id, &credentials).await {
                 Ok(ctx) => {
+                    if let Some(session) = session {
+                        self.bind_session(session, id, bound_id);
+                    }
                     return Ok(AcquiredContext { ctx, guard });
                 }
                 Err(e) => {
@@ -763,6 +1102,107 @@ impl MultiTokenManager {
         }
     }
 
+    /// 是否有更高优先级的请求正在等待空闲连接
+    fn higher_priority_waiting(&self, priority: ClientPriority) -> bool {
+        self.waiting[..priority.rank()]
+            .iter()
+            .any(|w| w.load(Ordering::Acquire) > 0)
+    }
+
+    /// 按调度策略从候选凭据（非空）中选择一个
+    fn schedule<'a>(&self, candidates: Vec<&'a CredentialEntry>) -> &'a CredentialEntry {
+        match self.config().scheduling_strategy {
+            SchedulingStrategy::LeastConnections => least_connections(candidates),
+            SchedulingStrategy::Priority => {
+                // 优先级最高的凭据中选择连接数最少的
+                let best = candidates
+                    .iter()
+                    .map(|e| e.credentials.priority)
+                    .min()
+                    .unwrap();
+                least_connections(
+                    candidates
+                        .into_iter()
+                        .filter(|e| e.credentials.priority == best)
+                        .collect(),
+                )
+            }
+            SchedulingStrategy::RoundRobin => {
+                // 选择 ID 大于上次分配凭据的下一个凭据，到末尾后回到最小 ID
+                let last = *self.current_id.lock();
+                let next = candidates
+                    .iter()
+                    .filter(|e| e.id > last)
+                    .min_by_key(|e| e.id);
+                next.or_else(|| candidates.iter().min_by_key(|e| e.id))
+                    .copied()
+                    .unwrap()
+            }
+            SchedulingStrategy::Weighted => {
+                let total: u64 = candidates.iter().map(|e| u64::from(e.weight())).sum();
+                if total == 0 {
+                    return candidates[fastrand::usize(..candidates.len())];
+                }
+                let mut point = fastrand::u64(..total);
+                for entry in &candidates {
+                    let weight = u64::from(entry.weight());
+                    if point < weight {
+                        return entry;
+                    }
+                    point -= weight;
+                }
+                unreachable!("加权随机点超出权重总和")
+            }
+        }
+    }
+
+    /// 会话当前绑定的凭据 ID（未绑定、已过期或未启用粘性绑定时为 None）
+    async fn bound_credential(&self, session: &str) -> Option<u64> {
+        let ttl = self.config().sticky_session_ttl_secs;
+        if ttl == 0 {
+            return None;
+        }
+        self.sessions.get(session).await
+    }
+
+    /// 绑定会话到凭据并刷新有效期
+    fn bind_session(&self, session: &str, id: u64, previous: Option<u64>) {
+        let ttl = self.config().sticky_session_ttl_secs;
+        if ttl == 0 {
+            return;
+        }
+        if let Some(previous) = previous.filter(|&p| p != id) {
+            tracing::info!(
+                "会话 {} 的绑定凭据不可用，已从 #{} 切换到 #{}",
+                session,
+                previous,
+                id
+            );
+        }
+        self.sessions
+            .bind(session, id, std::time::Duration::from_secs(ttl));
+    }
+
+    /// 恢复熔断冷却时间已过的凭据（`resilience.failureCooldownSecs` 为 0 时不恢复）
+    fn recover_cooled_down(&self, entries: &mut [CredentialEntry]) {
+        let cooldown = self.config().resilience.failure_cooldown_secs;
+        if cooldown == 0 {
+            return;
+        }
+        let cooldown = std::time::Duration::from_secs(cooldown);
+        for e in entries.iter_mut() {
+            if e.disabled_reason == Some(DisabledReason::TooManyFailures)
+                && e.tripped_at.is_some_and(|at| at.elapsed() >= cooldown)
+            {
+                e.disabled = false;
+                e.disabled_reason = None;
+                e.failure_count = 0;
+                e.tripped_at = None;
+                tracing::info!("凭据 #{} 熔断冷却结束，已自动恢复", e.id);
+            }
+        }
+    }
+
     /// 切换到下一个优先级最高的可用凭据（内部方法）
     fn switch_to_next_by_priority(&self) {
         let entries = self.entries.lock();
@@ -841,7 +1281,7 @@ impl MultiTokenManager {
             if is_token_expired(&current_creds) || is_token_expiring_soon(&current_creds) {
                 // 确实需要刷新
                 let new_creds =
-                    refresh_token(&current_creds, &self.config, self.proxy.as_ref()).await?;
+                    refresh_token(&current_creds, &self.config(), self.proxy().as_ref()).await?;
 
                 if is_token_expired(&new_creds) {
                     anyhow::bail!("刷新后的 Token 仍然无效或已过期");
@@ -885,7 +1325,7 @@ impl MultiTokenManager {
     /// 将凭据列表回写到源文件
     ///
     /// 仅在以下条件满足时回写：
-    /// - 源文件是多凭据格式（数组）
+    /// - 源文件是多凭据格式（数组），或已在导入凭据时转换为数组格式
     /// - credentials_path 已设置
     ///
     /// # Returns
@@ -896,7 +1336,7 @@ impl MultiTokenManager {
         use anyhow::Context;
 
         // 仅多凭据格式才回写
-        if !self.is_multiple_format {
+        if !self.is_multiple_format.load(Ordering::Relaxed) {
             return Ok(false);
         }
 
@@ -905,18 +1345,7 @@ impl MultiTokenManager {
             None => return Ok(false),
         };
 
-        // 收集所有凭据
-        let credentials: Vec<KiroCredentials> = {
-            let entries = self.entries.lock();
-            entries
-                .iter()
-                .map(|e| {
-                    let mut cred = e.credentials.clone();
-                    cred.canonicalize_auth_method();
-                    cred
-                })
-                .collect()
-        };
+        let credentials = self.export_credentials();
 
         // 序列化为 pretty JSON
         let json = serde_json::to_string_pretty(&credentials).context("序列化凭据失败")?;
@@ -935,18 +1364,93 @@ impl MultiTokenManager {
 
     /// 报告指定凭据 API 调用成功
     ///
-    /// 重置该凭据的失败计数
+    /// 重置该凭据的失败计数，并根据首字节延迟调整并发上限
     ///
     /// # Arguments
     /// * `id` - 凭据 ID（来自 CallContext）
-    pub fn report_success(&self, id: u64) {
+    /// * `latency` - 发送请求到收到响应头的耗时
+    pub fn report_success(&self, id: u64, latency: std::time::Duration) {
+        if let Some(stats) = &self.stats {
+            stats.record_success(id);
+        }
         let mut entries = self.entries.lock();
         if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
             entry.failure_count = 0;
+            if entry
+                .concurrency
+                .on_success(latency, &self.config().concurrency)
+            {
+                tracing::debug!(
+                    "凭据 #{} 并发上限调整为 {}（首字节延迟 {}ms）",
+                    id,
+                    entry.concurrency.limit(),
+                    latency.as_millis()
+                );
+            }
+            if let Some(reason) = entry.degraded_reason.take() {
+                tracing::info!("凭据 #{} 调用成功，已解除降级状态（{}）", id, reason);
+            }
+            if entry.breaker.on_success() {
+                tracing::info!("凭据 #{} 探测成功，熔断器已关闭", id);
+            }
             tracing::debug!("凭据 #{} API 调用成功", id);
         }
     }
 
+    /// 报告指定凭据被上游限流（429）
+    ///
+    /// 不计入失败次数，收缩该凭据的并发上限并计入熔断器
+    pub fn report_throttled(&self, id: u64) {
+        metrics::CREDENTIAL_THROTTLES.inc_by(1);
+        self.record_stats_failure(id);
+        let mut entries = self.entries.lock();
+        let Some(entry) = entries.iter_mut().find(|e| e.id == id) else {
+            return;
+        };
+        if entry.concurrency.on_throttle(&self.config().concurrency) {
+            tracing::info!(
+                "凭据 #{} 被上游限流，并发上限收缩为 {}",
+                id,
+                entry.concurrency.limit()
+            );
+        }
+        self.record_rejection(entry);
+    }
+
+    /// 熔断器记录一次拒绝，打开时记录日志
+    fn record_rejection(&self, entry: &mut CredentialEntry) {
+        if entry.breaker.on_rejection(&self.config().resilience) {
+            tracing::warn!(
+                "凭据 #{} 连续被上游拒绝，熔断 {} 秒后探测恢复",
+                entry.id,
+                self.config().resilience.breaker_cooldown_secs
+            );
+        }
+    }
+
+    /// 报告指定凭据被上游标记为不可用（维护中/版本过低）
+    ///
+    /// 与 report_failure 不同：不计入失败次数、不禁用凭据，仅标记为降级，
+    /// 选择凭据时降级凭据排在健康凭据之后；调用成功后自动解除降级。
+    /// 返回是否还有未降级的可用凭据
+    ///
+    /// # Arguments
+    /// * `id` - 凭据 ID（来自 CallContext）
+    /// * `reason` - 降级原因
+    pub fn report_degraded(&self, id: u64, reason: &str) -> bool {
+        self.record_stats_failure(id);
+        let mut entries = self.entries.lock();
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            if entry.degraded_reason.as_deref() != Some(reason) {
+                tracing::warn!("凭据 #{} 已被标记为降级: {}", id, reason);
+            }
+            entry.degraded_reason = Some(reason.to_string());
+        }
+        entries
+            .iter()
+            .any(|e| !e.disabled && e.degraded_reason.is_none())
+    }
+
     /// 报告指定凭据 API 调用失败
     ///
     /// 增加失败计数，达到阈值时禁用凭据并切换到优先级最高的可用凭据
@@ -955,6 +1459,7 @@ impl MultiTokenManager {
     /// # Arguments
     /// * `id` - 凭据 ID（来自 CallContext）
     pub fn report_failure(&self, id: u64) -> bool {
+        self.record_stats_failure(id);
         let mut entries = self.entries.lock();
         let mut current_id = self.current_id.lock();
 
@@ -963,19 +1468,22 @@ impl MultiTokenManager {
             None => return entries.iter().any(|e| !e.disabled),
         };
 
+        self.record_rejection(entry);
         entry.failure_count += 1;
         let failure_count = entry.failure_count;
+        let threshold = self.config().resilience.failure_threshold;
 
         tracing::warn!(
             "凭据 #{} API 调用失败（{}/{}）",
             id,
             failure_count,
-            MAX_FAILURES_PER_CREDENTIAL
+            threshold
         );
 
-        if failure_count >= MAX_FAILURES_PER_CREDENTIAL {
+        if failure_count >= threshold {
             entry.disabled = true;
             entry.disabled_reason = Some(DisabledReason::TooManyFailures);
+            entry.tripped_at = Some(std::time::Instant::now());
             tracing::error!("凭据 #{} 已连续失败 {} 次，已被禁用", id, failure_count);
 
             // 切换到优先级最高的可用凭据
@@ -1007,6 +1515,7 @@ impl MultiTokenManager {
     /// - 切换到下一个可用凭据继续重试
     /// - 返回是否还有可用凭据
     pub fn report_quota_exhausted(&self, id: u64) -> bool {
+        self.record_stats_failure(id);
         let mut entries = self.entries.lock();
         let mut current_id = self.current_id.lock();
 
@@ -1022,9 +1531,11 @@ impl MultiTokenManager {
         entry.disabled = true;
         entry.disabled_reason = Some(DisabledReason::QuotaExceeded);
         // 设为阈值，便于在管理面板中直观看到该凭据已不可用
-        entry.failure_count = MAX_FAILURES_PER_CREDENTIAL;
+        entry.failure_count = self.config().resilience.failure_threshold;
+
+        entry.quota_reset_at = None;
 
-        tracing::error!("凭据 #{} 额度已用尽（MONTHLY_REQUEST_COUNT），已被禁用", id);
+        tracing::error!("凭据 #{} 额度已用尽，已被禁用", id);
 
         // 切换到优先级最高的可用凭据
         if let Some(next) = entries
@@ -1076,11 +1587,12 @@ impl MultiTokenManager {
         let AcquiredContext { ctx, guard: _guard } = self.acquire_context().await?;
         get_usage_limits(
             &ctx.credentials,
-            &self.config,
+            &self.config(),
             &ctx.token,
-            self.proxy.as_ref(),
+            self.proxy().as_ref(),
         )
         .await
+        .inspect(|usage| self.apply_usage_limits(ctx.id, usage))
     }
 
     // ========================================================================
@@ -1099,7 +1611,11 @@ impl MultiTokenManager {
                 .map(|e| CredentialEntrySnapshot {
                     id: e.id,
                     priority: e.credentials.priority,
+                    weight: e.weight(),
+                    tags: e.credentials.tags.clone(),
                     disabled: e.disabled,
+                    disabled_reason: e.disabled_reason.map(DisabledReason::as_str),
+                    quota_reset_at: e.quota_reset_at.map(|at| at.to_rfc3339()),
                     failure_count: e.failure_count,
                     auth_method: e.credentials.auth_method.as_deref().map(|m| {
                         if m.eq_ignore_ascii_case("builder-id") || m.eq_ignore_ascii_case("iam") {
@@ -1111,7 +1627,9 @@ impl MultiTokenManager {
                     has_profile_arn: e.credentials.profile_arn.is_some(),
                     expires_at: e.credentials.expires_at.clone(),
                     active_connections: e.active_connections.load(Ordering::Acquire) as u32,
-                    max_concurrent: MAX_CONCURRENT_PER_CREDENTIAL,
+                    max_concurrent: e.concurrency_limit() as u32,
+                    degraded_reason: e.degraded_reason.clone(),
+                    breaker_state: e.breaker.state(&self.config().resilience),
                 })
                 .collect(),
             current_id,
@@ -1162,6 +1680,29 @@ impl MultiTokenManager {
         Ok(())
     }
 
+    /// 设置凭据分组标签（Admin API）
+    pub fn set_tags(&self, id: u64, tags: Vec<String>) -> anyhow::Result<()> {
+        {
+            let mut entries = self.entries.lock();
+            let entry = entries
+                .iter_mut()
+                .find(|e| e.id == id)
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?;
+            entry.credentials.tags = normalize_tags(tags);
+        }
+        // 持久化更改
+        self.persist_credentials()?;
+        Ok(())
+    }
+
+    /// 是否存在带有指定分组标签的凭据（含已禁用）
+    pub fn has_group(&self, group: &str) -> bool {
+        self.entries
+            .lock()
+            .iter()
+            .any(|e| e.credentials.has_tag(group))
+    }
+
     /// 重置凭据失败计数并重新启用（Admin API）
     pub fn reset_and_enable(&self, id: u64) -> anyhow::Result<()> {
         {
@@ -1173,6 +1714,8 @@ impl MultiTokenManager {
             entry.failure_count = 0;
             entry.disabled = false;
             entry.disabled_reason = None;
+            entry.degraded_reason = None;
+            entry.breaker = CircuitBreaker::default();
         }
         // 持久化更改
         self.persist_credentials()?;
@@ -1196,7 +1739,7 @@ impl MultiTokenManager {
         let _guard = self.refresh_lock.lock().await;
 
         // 强制刷新 Token
-        let new_creds = refresh_token(&credentials, &self.config, self.proxy.as_ref()).await?;
+        let new_creds = refresh_token(&credentials, &self.config(), self.proxy().as_ref()).await?;
 
         // 更新凭据
         {
@@ -1213,6 +1756,71 @@ impl MultiTokenManager {
         Ok(())
     }
 
+    /// 主动刷新将在 `margin` 内过期的启用凭据（后台刷新任务使用）
+    ///
+    /// 刷新失败只记录日志，不计入失败次数；返回距离下一个凭据进入刷新窗口的时间，
+    /// 没有带过期时间的启用凭据时返回 None
+    pub async fn refresh_expiring(&self, margin: Duration) -> Option<std::time::Duration> {
+        let expiring: Vec<u64> = {
+            let entries = self.entries.lock();
+            entries
+                .iter()
+                .filter(|e| !e.disabled)
+                .filter(|e| is_token_expiring_within(&e.credentials, margin) == Some(true))
+                .map(|e| e.id)
+                .collect()
+        };
+
+        for id in expiring {
+            match self.refresh_if_expiring(id, margin).await {
+                Ok(true) => tracing::info!("凭据 #{} Token 已提前刷新", id),
+                Ok(false) => {}
+                Err(e) => tracing::warn!("凭据 #{} 提前刷新 Token 失败: {}", id, e),
+            }
+        }
+
+        let now = clock::now();
+        let entries = self.entries.lock();
+        entries
+            .iter()
+            .filter(|e| !e.disabled)
+            .filter_map(|e| e.credentials.expires_at.as_deref())
+            .filter_map(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+            .map(|expires| expires.to_utc() - margin - now)
+            .min()
+            .map(|wait| wait.to_std().unwrap_or_default())
+    }
+
+    /// 持有刷新锁后再次确认凭据仍将在 `margin` 内过期，是则刷新并回写
+    async fn refresh_if_expiring(&self, id: u64, margin: Duration) -> anyhow::Result<bool> {
+        let _guard = self.refresh_lock.lock().await;
+        let credentials = {
+            let entries = self.entries.lock();
+            entries
+                .iter()
+                .find(|e| e.id == id)
+                .map(|e| e.credentials.clone())
+                .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
+        };
+
+        // 等待刷新锁期间可能已被请求路径刷新
+        if is_token_expiring_within(&credentials, margin) != Some(true) {
+            return Ok(false);
+        }
+
+        let new_creds = refresh_token(&credentials, &self.config(), self.proxy().as_ref()).await?;
+        {
+            let mut entries = self.entries.lock();
+            if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                entry.credentials = new_creds;
+            }
+        }
+        if let Err(e) = self.persist_credentials() {
+            tracing::warn!("Token 刷新后持久化失败: {}", e);
+        }
+        Ok(true)
+    }
+
     /// 获取指定凭据的使用额度（Admin API）
     pub async fn get_usage_limits_for(&self, id: u64) -> anyhow::Result<UsageLimitsResponse> {
         let credentials = {
@@ -1240,7 +1848,7 @@ impl MultiTokenManager {
 
             if is_token_expired(&current_creds) || is_token_expiring_soon(&current_creds) {
                 let new_creds =
-                    refresh_token(&current_creds, &self.config, self.proxy.as_ref()).await?;
+                    refresh_token(&current_creds, &self.config(), self.proxy().as_ref()).await?;
                 {
                     let mut entries = self.entries.lock();
                     if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
@@ -1274,7 +1882,53 @@ impl MultiTokenManager {
                 .ok_or_else(|| anyhow::anyhow!("凭据不存在: {}", id))?
         };
 
-        get_usage_limits(&credentials, &self.config, &token, self.proxy.as_ref()).await
+        let usage =
+            get_usage_limits(&credentials, &self.config(), &token, self.proxy().as_ref()).await?;
+        self.apply_usage_limits(id, &usage);
+        Ok(usage)
+    }
+
+    /// 根据额度查询结果更新凭据状态
+    ///
+    /// - 额度已用尽：禁用凭据（原因 `quota_exhausted`）并记录下次重置时间，到达后自动重新启用
+    /// - 因额度用尽被禁用、但查询显示仍有额度（如已重置或升级订阅）：立即重新启用
+    fn apply_usage_limits(&self, id: u64, usage: &UsageLimitsResponse) {
+        let usage_limit = usage.usage_limit();
+        let exhausted = usage_limit > 0.0 && usage.current_usage() >= usage_limit;
+        let reset_at = usage
+            .next_date_reset
+            .and_then(|secs| DateTime::from_timestamp(secs as i64, 0));
+
+        if exhausted {
+            let already_disabled = self.entries.lock().iter().any(|e| e.id == id && e.disabled);
+            if !already_disabled {
+                self.report_quota_exhausted(id);
+            }
+            let mut entries = self.entries.lock();
+            if let Some(entry) = entries.iter_mut().find(|e| e.id == id)
+                && entry.disabled_reason == Some(DisabledReason::QuotaExceeded)
+            {
+                entry.quota_reset_at = reset_at;
+            }
+            return;
+        }
+
+        let recovered = {
+            let mut entries = self.entries.lock();
+            match entries.iter_mut().find(|e| e.id == id) {
+                Some(entry) if entry.disabled_reason == Some(DisabledReason::QuotaExceeded) => {
+                    enable_after_quota_reset(entry);
+                    true
+                }
+                _ => false,
+            }
+        };
+        if recovered {
+            tracing::info!("凭据 #{} 额度已恢复，已重新启用", id);
+            if *self.current_id.lock() == 0 {
+                self.select_highest_priority();
+            }
+        }
     }
 
     /// 添加新凭据（Admin API）
@@ -1295,7 +1949,7 @@ impl MultiTokenManager {
 
         // 2. 尝试刷新 Token 验证凭据有效性
         let mut validated_cred =
-            refresh_token(&new_cred, &self.config, self.proxy.as_ref()).await?;
+            refresh_token(&new_cred, &self.config(), self.proxy().as_ref()).await?;
 
         // 3. 分配新 ID
         let new_id = {
@@ -1316,18 +1970,14 @@ impl MultiTokenManager {
         validated_cred.client_id = new_cred.client_id;
         validated_cred.client_secret = new_cred.client_secret;
         validated_cred.region = new_cred.region;
+        validated_cred.api_region = new_cred.api_region;
+        validated_cred.endpoint = new_cred.endpoint;
+        validated_cred.failover_regions = new_cred.failover_regions;
         validated_cred.machine_id = new_cred.machine_id;
 
         {
             let mut entries = self.entries.lock();
-            entries.push(CredentialEntry {
-                id: new_id,
-                credentials: validated_cred,
-                failure_count: 0,
-                disabled: false,
-                active_connections: Arc::new(AtomicUsize::new(0)),
-                disabled_reason: None,
-            });
+            entries.push(CredentialEntry::new(new_id, validated_cred, &self.config()));
         }
 
         // 5. 持久化
@@ -1337,6 +1987,71 @@ impl MultiTokenManager {
         Ok(new_id)
     }
 
+    /// 导出全部凭据（含 refresh token，按 ID 顺序）
+    pub fn export_credentials(&self) -> Vec<KiroCredentials> {
+        let entries = self.entries.lock();
+        entries
+            .iter()
+            .map(|e| {
+                let mut cred = e.credentials.clone();
+                cred.canonicalize_auth_method();
+                cred
+            })
+            .collect()
+    }
+
+    /// 导入其他实例导出的凭据（Admin API）
+    ///
+    /// 与已有凭据 refresh token 相同的条目跳过；其余条目重新分配 ID 后加入，
+    /// 不在导入时刷新验证（已导出的 access token 过期后按正常流程刷新）
+    pub fn import_credentials(
+        &self,
+        credentials: Vec<KiroCredentials>,
+    ) -> anyhow::Result<CredentialsImport> {
+        let config = self.config();
+        let mut report = CredentialsImport::default();
+        {
+            let mut entries = self.entries.lock();
+            let mut next_id = entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+            for mut cred in credentials {
+                if validate_refresh_token(&cred).is_err() {
+                    report.invalid += 1;
+                    continue;
+                }
+                if entries
+                    .iter()
+                    .any(|e| e.credentials.refresh_token == cred.refresh_token)
+                {
+                    report.duplicates += 1;
+                    continue;
+                }
+                cred.canonicalize_auth_method();
+                cred.id = Some(next_id);
+                if cred.machine_id.is_none() {
+                    cred.machine_id = machine_id::generate_from_credentials(&cred, &config);
+                }
+                entries.push(CredentialEntry::new(next_id, cred, &config));
+                report.imported.push(next_id);
+                next_id += 1;
+            }
+        }
+
+        if !report.imported.is_empty() {
+            if *self.current_id.lock() == 0 {
+                self.select_highest_priority();
+            }
+            // 单凭据格式的文件无法容纳多个凭据，先转换为数组格式再回写，避免导入结果重启后丢失
+            if self.credentials_path.is_some()
+                && !self.is_multiple_format.swap(true, Ordering::Relaxed)
+            {
+                tracing::info!("凭据文件为单凭据格式，导入后转换为数组格式");
+            }
+            self.persist_credentials()?;
+            tracing::info!("已导入凭据: {:?}", report.imported);
+        }
+        Ok(report)
+    }
+
     /// 获取指定凭据的 refresh_token 指纹（前 64 字符）
     ///
     /// 用于批量导入时的重复检测，避免存储完整 token
@@ -1411,11 +2126,115 @@ impl MultiTokenManager {
         tracing::info!("已删除凭据 #{}", id);
         Ok(())
     }
+
+    /// 按凭据文件内容重新加载凭据（热重载）
+    ///
+    /// - 文件中新增的凭据加入调度，未设置 ID 时分配新 ID 并回写文件
+    /// - 文件中已删除的凭据移出调度，已在进行中的请求不受影响
+    /// - 内容有变化的凭据替换为新内容，refresh token 变化时清空失败计数并解除自动禁用
+    ///
+    /// 未设置 ID 的凭据按 refresh token 匹配已有凭据（兼容不回写的单凭据格式）；
+    /// refresh token 未变化时保留内存中已刷新的 access token
+    pub fn reload_credentials(&self, credentials: Vec<KiroCredentials>) -> CredentialsReload {
+        let config = self.config();
+        let mut report = CredentialsReload::default();
+        let mut has_new_ids = false;
+        let removed_current = {
+            let mut entries = self.entries.lock();
+            let mut next_id = entries
+                .iter()
+                .map(|e| e.id)
+                .chain(credentials.iter().filter_map(|c| c.id))
+                .max()
+                .unwrap_or(0)
+                + 1;
+            let mut seen = std::collections::HashSet::new();
+
+            for mut cred in credentials {
+                cred.canonicalize_auth_method();
+                let existing = match cred.id {
+                    Some(id) => entries.iter().position(|e| e.id == id),
+                    None => entries.iter().position(|e| {
+                        e.credentials.refresh_token.is_some()
+                            && e.credentials.refresh_token == cred.refresh_token
+                    }),
+                };
+                let Some(index) = existing else {
+                    let id = cred.id.unwrap_or_else(|| {
+                        let id = next_id;
+                        next_id += 1;
+                        has_new_ids = true;
+                        id
+                    });
+                    if !seen.insert(id) {
+                        tracing::warn!("凭据文件中存在重复的 ID #{}，已忽略", id);
+                        continue;
+                    }
+                    cred.id = Some(id);
+                    if cred.machine_id.is_none() {
+                        cred.machine_id = machine_id::generate_from_credentials(&cred, &config);
+                    }
+                    entries.push(CredentialEntry::new(id, cred, &config));
+                    report.added.push(id);
+                    continue;
+                };
+
+                let entry = &mut entries[index];
+                if !seen.insert(entry.id) {
+                    tracing::warn!("凭据文件中存在重复的 ID #{}，已忽略", entry.id);
+                    continue;
+                }
+                cred.id = Some(entry.id);
+                if cred.machine_id.is_none() {
+                    cred.machine_id = entry.credentials.machine_id.clone();
+                }
+                if cred.refresh_token == entry.credentials.refresh_token {
+                    cred.access_token = entry.credentials.access_token.clone();
+                    cred.expires_at = entry.credentials.expires_at.clone();
+                    if cred.profile_arn.is_none() {
+                        cred.profile_arn = entry.credentials.profile_arn.clone();
+                    }
+                } else {
+                    entry.failure_count = 0;
+                    entry.tripped_at = None;
+                    if entry.disabled && entry.disabled_reason != Some(DisabledReason::Manual) {
+                        entry.disabled = false;
+                        entry.disabled_reason = None;
+                    }
+                }
+                if entry.credentials != cred {
+                    entry.credentials = cred;
+                    report.updated.push(entry.id);
+                }
+            }
+
+            entries.retain(|e| {
+                let keep = seen.contains(&e.id);
+                if !keep {
+                    report.removed.push(e.id);
+                }
+                keep
+            });
+            report.removed.contains(&*self.current_id.lock())
+        };
+
+        if removed_current || !report.updated.is_empty() {
+            self.select_highest_priority();
+        }
+        if self.entries.lock().is_empty() {
+            *self.current_id.lock() = 0;
+        }
+        if has_new_ids && let Err(e) = self.persist_credentials() {
+            tracing::warn!("回写重新加载的凭据失败: {}", e);
+        }
+        report
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::Utc;
 
     #[test]
     fn test_token_manager_new() {
@@ -1568,7 +2387,7 @@ mod tests {
         manager.report_failure(1);
 
         // 成功后重置计数（使用 ID 1）
-        manager.report_success(1);
+        manager.report_success(1, std::time::Duration::from_millis(100));
 
         // 再失败两次不会禁用
         manager.report_failure(1);
@@ -1576,6 +2395,118 @@ mod tests {
         assert_eq!(manager.available_count(), 1);
     }
 
+    #[test]
+    fn test_multi_token_manager_import_credentials() {
+        let long_token = |c: char| c.to_string().repeat(120);
+        let cred1 = KiroCredentials {
+            refresh_token: Some(long_token('a')),
+            ..Default::default()
+        };
+        let manager =
+            MultiTokenManager::new(Config::default(), vec![cred1], None, None, false).unwrap();
+
+        let exported = manager.export_credentials();
+        assert_eq!(exported.len(), 1);
+
+        let fresh = KiroCredentials {
+            id: Some(1),
+            refresh_token: Some(long_token('b')),
+            ..Default::default()
+        };
+        let truncated = KiroCredentials {
+            refresh_token: Some("short".to_string()),
+            ..Default::default()
+        };
+        let report = manager
+            .import_credentials(vec![exported[0].clone(), fresh, truncated])
+            .unwrap();
+        assert_eq!(report.imported, vec![2]);
+        assert_eq!(report.duplicates, 1);
+        assert_eq!(report.invalid, 1);
+        assert_eq!(manager.total_count(), 2);
+        assert_eq!(manager.export_credentials()[1].id, Some(2));
+    }
+
+    #[test]
+    fn test_import_credentials_converts_single_format_file() {
+        let long_token = |c: char| c.to_string().repeat(120);
+        let cred = KiroCredentials {
+            id: Some(1),
+            refresh_token: Some(long_token('a')),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("kiro-creds-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, serde_json::to_string(&cred).unwrap()).unwrap();
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![cred],
+            None,
+            Some(path.clone()),
+            false,
+        )
+        .unwrap();
+
+        let imported = KiroCredentials {
+            refresh_token: Some(long_token('b')),
+            ..Default::default()
+        };
+        manager.import_credentials(vec![imported]).unwrap();
+
+        let saved: Vec<KiroCredentials> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[1].refresh_token, Some(long_token('b')));
+    }
+
+    #[test]
+    fn test_multi_token_manager_reload_credentials() {
+        let config = Config::default();
+        let mut cred1 = KiroCredentials::default();
+        cred1.refresh_token = Some("token1".to_string());
+        let mut cred2 = KiroCredentials::default();
+        cred2.refresh_token = Some("token2".to_string());
+
+        let manager =
+            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
+        for _ in 0..3 {
+            manager.report_failure(1);
+        }
+        assert_eq!(manager.available_count(), 1);
+
+        // 内容不变时不产生变更
+        let mut unchanged = KiroCredentials::default();
+        unchanged.id = Some(2);
+        unchanged.refresh_token = Some("token2".to_string());
+        let report = manager.reload_credentials(vec![unchanged.clone()]);
+        assert_eq!(report.removed, vec![1]);
+        assert!(report.added.is_empty() && report.updated.is_empty());
+
+        // 未设置 ID 的凭据按 refresh token 匹配，新 token 作为新凭据加入
+        let mut same = KiroCredentials::default();
+        same.refresh_token = Some("token2".to_string());
+        same.priority = 5;
+        let mut added = KiroCredentials::default();
+        added.refresh_token = Some("token3".to_string());
+        let report = manager.reload_credentials(vec![same, added]);
+        assert_eq!(report.updated, vec![2]);
+        assert_eq!(report.added, vec![3]);
+        assert_eq!(manager.total_count(), 2);
+        assert_eq!(manager.snapshot().entries[0].priority, 5);
+
+        // refresh token 变化时解除自动禁用
+        for _ in 0..3 {
+            manager.report_failure(3);
+        }
+        assert_eq!(manager.available_count(), 1);
+        let mut replaced = KiroCredentials::default();
+        replaced.id = Some(3);
+        replaced.refresh_token = Some("token4".to_string());
+        let report = manager.reload_credentials(vec![unchanged, replaced]);
+        assert_eq!(report.updated, vec![2, 3]);
+        assert_eq!(manager.available_count(), 2);
+    }
+
     #[test]
     fn test_multi_token_manager_switch_to_next() {
         let config = Config::default();
@@ -1615,10 +2546,11 @@ mod tests {
             MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
 
         // 凭据会自动分配 ID（从 1 开始）
-        for _ in 0..MAX_FAILURES_PER_CREDENTIAL {
+        let threshold = manager.config().resilience.failure_threshold;
+        for _ in 0..threshold {
             manager.report_failure(1);
         }
-        for _ in 0..MAX_FAILURES_PER_CREDENTIAL {
+        for _ in 0..threshold {
             manager.report_failure(2);
         }
 
@@ -1630,6 +2562,358 @@ mod tests {
         assert_eq!(manager.available_count(), 2);
     }
 
+    #[tokio::test]
+    async fn test_multi_token_manager_recovers_after_cooldown() {
+        let config = Config {
+            resilience: crate::model::config::ResilienceConfig {
+                failure_threshold: 1,
+                failure_cooldown_secs: 1,
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let cred1 = KiroCredentials {
+            access_token: Some("t1".to_string()),
+            expires_at: expires_at.clone(),
+            ..Default::default()
+        };
+        let cred2 = KiroCredentials {
+            access_token: Some("t2".to_string()),
+            expires_at,
+            ..Default::default()
+        };
+
+        let manager =
+            MultiTokenManager::new(config, vec![cred1, cred2], None, None, false).unwrap();
+
+        manager.report_failure(1);
+        assert_eq!(manager.available_count(), 1);
+
+        // 冷却期内不恢复
+        drop(manager.acquire_context().await.unwrap());
+        assert_eq!(manager.available_count(), 1);
+
+        // 模拟冷却时间已过
+        manager.entries.lock()[0].tripped_at =
+            Some(std::time::Instant::now() - std::time::Duration::from_secs(2));
+        drop(manager.acquire_context().await.unwrap());
+        assert_eq!(manager.available_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_scheduling_strategies() {
+        use crate::model::config::SchedulingStrategy;
+
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = [(2, 0), (1, 3), (1, 1)]
+            .map(|(priority, weight)| KiroCredentials {
+                access_token: Some("t".to_string()),
+                expires_at: expires_at.clone(),
+                priority,
+                weight: Some(weight),
+                ..Default::default()
+            })
+            .to_vec();
+        let manager_with = |strategy| {
+            let config = Config {
+                scheduling_strategy: strategy,
+                ..Config::default()
+            };
+            MultiTokenManager::new(config, creds.clone(), None, None, false).unwrap()
+        };
+
+        // 优先级：始终选择优先级最高（数值最小）的凭据
+        let manager = manager_with(SchedulingStrategy::Priority);
+        for _ in 0..3 {
+            let id = manager.acquire_context().await.unwrap().ctx.id;
+            assert!(id == 2 || id == 3);
+        }
+
+        // 轮询：按 ID 依次使用
+        let manager = manager_with(SchedulingStrategy::RoundRobin);
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(manager.acquire_context().await.unwrap().ctx.id);
+        }
+        let start = ids[0];
+        let expected: Vec<u64> = (0..4).map(|i| (start - 1 + i) % 3 + 1).collect();
+        assert_eq!(ids, expected);
+
+        // 加权：权重为 0 的凭据不会被选中
+        let manager = manager_with(SchedulingStrategy::Weighted);
+        for _ in 0..20 {
+            assert_ne!(manager.acquire_context().await.unwrap().ctx.id, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_snapshot_tracks_connections() {
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = vec![KiroCredentials {
+            access_token: Some("t".to_string()),
+            expires_at,
+            ..Default::default()
+        }];
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+        let active = |manager: &MultiTokenManager| manager.snapshot().entries[0].active_connections;
+
+        let first = manager.acquire_context().await.unwrap();
+        let second = manager.acquire_context().await.unwrap();
+        assert_eq!(active(&manager), 2);
+        assert_eq!(
+            manager.snapshot().entries[0].max_concurrent,
+            Config::default().concurrency.initial_limit
+        );
+
+        drop(first);
+        assert_eq!(active(&manager), 1);
+        drop(second);
+        assert_eq!(active(&manager), 0);
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_queues_at_max_concurrent() {
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = (0..2)
+            .map(|_| KiroCredentials {
+                access_token: Some("t".to_string()),
+                expires_at: expires_at.clone(),
+                max_concurrent: Some(1),
+                ..Default::default()
+            })
+            .collect();
+        let mut config = Config::default();
+        config.concurrency.queue_timeout_ms = 50;
+        let manager = Arc::new(MultiTokenManager::new(config, creds, None, None, false).unwrap());
+
+        // 每个凭据最多一个连接，不再退化为超额分配
+        let first = manager.acquire_context().await.unwrap();
+        let second = manager.acquire_context().await.unwrap();
+        assert_ne!(first.ctx.id, second.ctx.id);
+        assert_eq!(manager.snapshot().entries[0].max_concurrent, 1);
+
+        // 全部达到上限：排队超时后失败
+        let err = manager.acquire_context().await.err().unwrap().to_string();
+        assert!(err.contains("并发上限"), "实际: {}", err);
+
+        // 排队期间释放连接：等待的请求获得该凭据
+        let waiter = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move {
+                manager
+                    .acquire_context()
+                    .await
+                    .map(|acquired| acquired.ctx.id)
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let released_id = first.ctx.id;
+        drop(first);
+        assert_eq!(waiter.await.unwrap().unwrap(), released_id);
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_priority_scheduling() {
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = vec![KiroCredentials {
+            access_token: Some("t".to_string()),
+            expires_at,
+            ..Default::default()
+        }];
+        let mut config = Config::default();
+        config.concurrency.adaptive = false;
+        config.concurrency.initial_limit = 1;
+        config.concurrency.queue_timeout_ms = 1000;
+        let manager = Arc::new(MultiTokenManager::new(config, creds, None, None, false).unwrap());
+        let routing = |priority| Routing {
+            priority,
+            ..Routing::default()
+        };
+
+        // 超过并发上限时普通优先级仍可超额分配，低优先级排队等待
+        let first = manager.acquire_context().await.unwrap();
+        let second = manager.acquire_context().await.unwrap();
+        let low = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move {
+                manager
+                    .acquire_context_for(routing(ClientPriority::Low))
+                    .await
+                    .is_ok()
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!low.is_finished());
+
+        // 有高优先级请求等待时低优先级让行（直接登记一个等待中的高优先级请求）
+        manager.waiting[ClientPriority::High.rank()].fetch_add(1, Ordering::AcqRel);
+        drop(first);
+        drop(second);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!low.is_finished());
+
+        manager.waiting[ClientPriority::High.rank()].fetch_sub(1, Ordering::AcqRel);
+        manager.released.notify_waiters();
+        assert!(low.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_sticky_session() {
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = ["t1", "t2"]
+            .map(|token| KiroCredentials {
+                access_token: Some(token.to_string()),
+                expires_at: expires_at.clone(),
+                ..Default::default()
+            })
+            .to_vec();
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+        let session = Routing {
+            session: Some("s1"),
+            ..Routing::default()
+        };
+
+        // 持有首个连接：按最少连接数本应选择另一个凭据，但会话已绑定
+        let first = manager.acquire_context_for(session).await.unwrap();
+        let bound = first.ctx.id;
+        for _ in 0..3 {
+            let again = manager.acquire_context_for(session).await.unwrap();
+            assert_eq!(again.ctx.id, bound);
+        }
+
+        // 绑定的凭据不可用时切换并重新绑定
+        manager.set_disabled(bound, true).unwrap();
+        let other = manager.acquire_context_for(session).await.unwrap();
+        assert_ne!(other.ctx.id, bound);
+        manager.set_disabled(bound, false).unwrap();
+        let again = manager.acquire_context_for(session).await.unwrap();
+        assert_eq!(again.ctx.id, other.ctx.id);
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_breaker_skips_rejected_credential() {
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = ["t1", "t2"]
+            .map(|token| KiroCredentials {
+                access_token: Some(token.to_string()),
+                expires_at: expires_at.clone(),
+                ..Default::default()
+            })
+            .to_vec();
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+        let breaker_state =
+            |manager: &MultiTokenManager| manager.snapshot().entries[0].breaker_state;
+
+        for _ in 0..3 {
+            manager.report_throttled(1);
+        }
+        assert_eq!(breaker_state(&manager), BreakerState::Open);
+
+        // 熔断期间即使持有连接也不会选中凭据 #1
+        let mut held = Vec::new();
+        for _ in 0..3 {
+            let acquired = manager.acquire_context().await.unwrap();
+            assert_eq!(acquired.ctx.id, 2);
+            held.push(acquired);
+        }
+
+        manager.reset_and_enable(1).unwrap();
+        assert_eq!(breaker_state(&manager), BreakerState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_group_routing() {
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = [vec!["work"], vec![], vec!["work", "batch"]]
+            .map(|tags| KiroCredentials {
+                access_token: Some("t".to_string()),
+                expires_at: expires_at.clone(),
+                tags: tags.into_iter().map(String::from).collect(),
+                ..Default::default()
+            })
+            .to_vec();
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+        let work = Routing {
+            group: Some("work"),
+            ..Routing::default()
+        };
+
+        assert!(manager.has_group("batch"));
+        assert!(!manager.has_group("personal"));
+
+        // 持有连接时最少连接数会轮换，但始终不会选出分组外的凭据
+        let mut held = Vec::new();
+        for _ in 0..4 {
+            let guard = manager.acquire_context_for(work).await.unwrap();
+            assert_ne!(guard.ctx.id, 2);
+            held.push(guard);
+        }
+
+        // 分组内凭据全部禁用时不会回退到其他凭据
+        manager.set_disabled(1, true).unwrap();
+        manager.set_disabled(3, true).unwrap();
+        assert!(manager.acquire_context_for(work).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_avoids_failed_credentials() {
+        let expires_at = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+        let creds = (0..2)
+            .map(|_| KiroCredentials {
+                access_token: Some("t".to_string()),
+                expires_at: expires_at.clone(),
+                ..Default::default()
+            })
+            .collect();
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+
+        // 会话已绑定的凭据同样会被避开
+        let session = Routing {
+            session: Some("s1"),
+            ..Routing::default()
+        };
+        let bound = manager.acquire_context_for(session).await.unwrap().ctx.id;
+        let avoid = [bound];
+        let retry = Routing {
+            avoid: &avoid,
+            ..session
+        };
+        let retried = manager.acquire_context_for(retry).await.unwrap();
+        assert_ne!(retried.ctx.id, bound);
+
+        // 没有其他候选时仍使用已失败的凭据
+        let all = [1, 2];
+        let retry = Routing {
+            avoid: &all,
+            ..Routing::default()
+        };
+        assert!(manager.acquire_context_for(retry).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_multi_token_manager_refresh_expiring_schedules_next() {
+        let expiring_in = |minutes| KiroCredentials {
+            access_token: Some("t".to_string()),
+            expires_at: Some((Utc::now() + Duration::minutes(minutes)).to_rfc3339()),
+            ..Default::default()
+        };
+        let creds = vec![expiring_in(60), expiring_in(30), KiroCredentials::default()];
+        let manager = MultiTokenManager::new(Config::default(), creds, None, None, false).unwrap();
+        let margin = Duration::minutes(15);
+
+        // 均未进入刷新窗口：不发起刷新，等到最早过期的凭据进入窗口
+        let next = manager.refresh_expiring(margin).await.unwrap();
+        assert!(next > std::time::Duration::from_secs(14 * 60));
+        assert!(next <= std::time::Duration::from_secs(15 * 60));
+
+        // 禁用的凭据不参与调度
+        manager.set_disabled(2, true).unwrap();
+        let next = manager.refresh_expiring(margin).await.unwrap();
+        assert!(next > std::time::Duration::from_secs(44 * 60));
+    }
+
     #[test]
     fn test_multi_token_manager_report_quota_exhausted() {
         let config = Config::default();
@@ -1671,6 +2955,54 @@ mod tests {
         assert_eq!(manager.available_count(), 0);
     }
 
+    #[test]
+    fn test_multi_token_manager_quota_exhausted_recovers_after_reset() {
+        let manager = MultiTokenManager::new(
+            Config::default(),
+            vec![KiroCredentials::default(), KiroCredentials::default()],
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        let usage = |current: i64, reset_at: i64| -> UsageLimitsResponse {
+            serde_json::from_value(serde_json::json!({
+                "nextDateReset": reset_at as f64,
+                "usageBreakdownList": [{
+                    "currentUsageWithPrecision": current as f64,
+                    "usageLimitWithPrecision": 100.0
+                }]
+            }))
+            .unwrap()
+        };
+
+        // 额度用尽：禁用并记录重置时间
+        let reset_at = Utc::now() + Duration::days(3);
+        manager.apply_usage_limits(1, &usage(100, reset_at.timestamp()));
+        let entry = &manager.snapshot().entries[0];
+        assert!(entry.disabled);
+        assert_eq!(entry.disabled_reason, Some("quota_exhausted"));
+        assert!(entry.quota_reset_at.is_some());
+
+        // 未到重置时间不恢复，到达后恢复
+        {
+            let mut entries = manager.entries.lock();
+            recover_quota_reset(&mut entries, Utc::now());
+            assert!(entries[0].disabled);
+            recover_quota_reset(&mut entries, reset_at + Duration::seconds(1));
+            assert!(!entries[0].disabled);
+            assert_eq!(entries[0].disabled_reason, None);
+        }
+
+        // 再次用尽后查询显示已有余量：立即恢复；手动禁用的凭据不受影响
+        manager.apply_usage_limits(1, &usage(100, reset_at.timestamp()));
+        manager.apply_usage_limits(1, &usage(0, reset_at.timestamp()));
+        assert!(!manager.snapshot().entries[0].disabled);
+        manager.set_disabled(2, true).unwrap();
+        manager.apply_usage_limits(2, &usage(0, reset_at.timestamp()));
+        assert!(manager.snapshot().entries[1].disabled);
+    }
+
     // ============ 凭据级 Region 优先级测试 ============
 
     /// 辅助函数：获取 OIDC 刷新使用的 region（用于测试）
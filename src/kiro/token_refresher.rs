@@ -0,0 +1,33 @@
+//! 后台主动刷新 Token
+//!
+//! 按各凭据的 `expiresAt` 在过期前 `tokenRefreshMarginSecs` 秒刷新 Token，
+//! 空闲一段时间后的首个请求不必等待刷新
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::kiro::token_manager::MultiTokenManager;
+
+/// 两次检查之间的最短间隔，避免刷新持续失败时空转
+const MIN_WAIT: Duration = Duration::from_secs(30);
+
+/// 两次检查之间的最长间隔，保证运行时新增的凭据也能及时纳入
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+/// 启动后台刷新任务（提前量为 0 时不启动）
+pub fn spawn(token_manager: Arc<MultiTokenManager>) {
+    let margin_secs = token_manager.config().token_refresh_margin_secs;
+    if margin_secs == 0 {
+        return;
+    }
+
+    let margin = chrono::Duration::seconds(margin_secs as i64);
+    tokio::spawn(async move {
+        loop {
+            let next = token_manager.refresh_expiring(margin).await;
+            let wait = next.unwrap_or(MAX_WAIT).clamp(MIN_WAIT, MAX_WAIT);
+            tokio::time::sleep(wait).await;
+        }
+    });
+    tracing::info!("Token 后台刷新已启用，提前 {} 秒刷新", margin_secs);
+}
@@ -0,0 +1,105 @@
+//! 自定义上游请求头
+//!
+//! config.json 的 `upstreamHeaders`（全局）与凭据的 `headers`（凭据级，同名时覆盖全局）
+//! 会附加到每个发往 Kiro 的请求上，值中的占位符在发送时替换：
+//! `{credentialId}`、`{machineId}`、`{region}`、`{invocationId}`
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::kiro::model::credentials::KiroCredentials;
+use crate::model::config::Config;
+
+/// 由代理自行维护、不允许被覆盖的请求头
+const RESERVED: &[&str] = &[
+    "authorization",
+    "host",
+    "content-type",
+    "content-length",
+    "connection",
+];
+
+/// 模板占位符的取值
+pub struct TemplateVars<'a> {
+    pub credential_id: u64,
+    pub machine_id: &'a str,
+    pub region: &'a str,
+    /// 本次请求的 `amz-sdk-invocation-id`
+    pub invocation_id: &'a str,
+}
+
+/// 替换值模板中的占位符
+fn render(template: &str, vars: &TemplateVars<'_>) -> String {
+    template
+        .replace("{credentialId}", &vars.credential_id.to_string())
+        .replace("{machineId}", vars.machine_id)
+        .replace("{region}", vars.region)
+        .replace("{invocationId}", vars.invocation_id)
+}
+
+/// 将全局与凭据级自定义请求头写入 `headers`
+///
+/// 名称或值不合法、以及试图覆盖保留请求头的条目会被跳过并记录警告
+pub fn apply(
+    headers: &mut HeaderMap,
+    config: &Config,
+    credentials: &KiroCredentials,
+    vars: &TemplateVars<'_>,
+) {
+    for (name, template) in config.upstream_headers.iter().chain(&credentials.headers) {
+        let Ok(header_name) = HeaderName::from_bytes(name.as_bytes()) else {
+            tracing::warn!("忽略名称不合法的自定义上游请求头: {}", name);
+            continue;
+        };
+        if RESERVED.contains(&header_name.as_str()) {
+            tracing::warn!("自定义上游请求头不能覆盖 {}，已忽略", header_name);
+            continue;
+        }
+        match HeaderValue::from_str(&render(template, vars)) {
+            Ok(value) => {
+                headers.insert(header_name, value);
+            }
+            Err(_) => tracing::warn!("忽略值不合法的自定义上游请求头: {}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_renders_and_overrides() {
+        let headers_of = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        let config = Config {
+            upstream_headers: headers_of(&[
+                ("X-Route", "global"),
+                ("x-trace", "kiro-{credentialId}-{region}"),
+                ("Authorization", "Bearer evil"),
+            ]),
+            ..Default::default()
+        };
+        let credentials = KiroCredentials {
+            headers: headers_of(&[("x-route", "team-a")]),
+            ..Default::default()
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer real"));
+        let vars = TemplateVars {
+            credential_id: 3,
+            machine_id: "m",
+            region: "us-east-1",
+            invocation_id: "i",
+        };
+        apply(&mut headers, &config, &credentials, &vars);
+
+        assert_eq!(headers.get("x-route").unwrap(), "team-a");
+        assert_eq!(headers.get("x-trace").unwrap(), "kiro-3-us-east-1");
+        assert_eq!(headers.get("authorization").unwrap(), "Bearer real");
+    }
+}
@@ -0,0 +1,7 @@
+//! kiro-rs 库
+//!
+//! 代理服务本身以二进制形式提供；启用 `client` feature 后，本库导出调用代理 API 的
+//! 类型化异步客户端，便于在其他 Rust 项目中嵌入使用
+
+#[cfg(feature = "client")]
+pub mod client;
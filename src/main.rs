@@ -2,18 +2,26 @@ mod admin;
 mod admin_ui;
 mod anthropic;
 mod common;
+mod doctor;
+mod gemini;
 mod http_client;
 mod kiro;
 mod model;
+mod openai;
+mod ops;
+mod reload;
+mod storage;
+mod support_bundle;
 pub mod token;
 
 use std::sync::Arc;
 
 use clap::Parser;
+use futures::FutureExt;
 use kiro::model::credentials::{CredentialsConfig, KiroCredentials};
 use kiro::provider::KiroProvider;
 use kiro::token_manager::MultiTokenManager;
-use model::arg::Args;
+use model::arg::{Args, Command};
 use model::config::Config;
 
 #[tokio::main]
@@ -21,27 +29,46 @@ async fn main() {
     // 解析命令行参数
     let args = Args::parse();
 
-    // 初始化日志
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
+    // 初始化日志（链路追踪导出在加载配置后启用）
+    let mcp_stdio = matches!(args.command, Some(Command::Mcp));
+    let telemetry = common::telemetry::init(mcp_stdio);
 
-    // 加载配置
     let config_path = args
         .config
         .unwrap_or_else(|| Config::default_config_path().to_string());
+    let credentials_path = args
+        .credentials
+        .unwrap_or_else(|| KiroCredentials::default_credentials_path().to_string());
+
+    match &args.command {
+        // 自检子命令
+        Some(Command::Doctor) => {
+            std::process::exit(doctor::run(&config_path, &credentials_path).await);
+        }
+        // 诊断包子命令
+        Some(Command::SupportBundle { output, log }) => {
+            std::process::exit(support_bundle::run(
+                &config_path,
+                &credentials_path,
+                output.as_deref(),
+                log.as_deref(),
+            ));
+        }
+        Some(Command::Mcp) | None => {}
+    }
+
+    // 加载配置
     let config = Config::load(&config_path).unwrap_or_else(|e| {
         tracing::error!("加载配置失败: {}", e);
         std::process::exit(1);
     });
+    telemetry.enable_export(&config);
+    common::telemetry::set_format(config.logging.format);
+    if let Err(e) = common::telemetry::set_route_levels(&config.logging.routes) {
+        tracing::warn!("路由日志级别配置无效，已忽略: {}", e);
+    }
 
     // 加载凭证（支持单对象或数组格式）
-    let credentials_path = args
-        .credentials
-        .unwrap_or_else(|| KiroCredentials::default_credentials_path().to_string());
     let credentials_config = CredentialsConfig::load(&credentials_path).unwrap_or_else(|e| {
         tracing::error!("加载凭证失败: {}", e);
         std::process::exit(1);
@@ -58,40 +85,81 @@ async fn main() {
     let first_credentials = credentials_list.first().cloned().unwrap_or_default();
     tracing::debug!("主凭证: {:?}", first_credentials);
 
-    // 获取 API Key
-    let api_key = config.api_key.clone().unwrap_or_else(|| {
-        tracing::error!("配置文件中未设置 apiKey");
-        std::process::exit(1);
-    });
-
     // 构建代理配置
-    let proxy_config = config.proxy_url.as_ref().map(|url| {
-        let mut proxy = http_client::ProxyConfig::new(url);
-        if let (Some(username), Some(password)) = (&config.proxy_username, &config.proxy_password) {
-            proxy = proxy.with_auth(username, password);
-        }
-        proxy
-    });
+    let proxy_config = http_client::ProxyConfig::from_config(&config);
 
     if proxy_config.is_some() {
         tracing::info!("已配置 HTTP 代理: {}", config.proxy_url.as_ref().unwrap());
     }
 
+    // 打开持久化存储（用量账本、幂等缓存等共用）
+    let storage = storage::open(&config).unwrap_or_else(|e| {
+        tracing::error!("打开存储后端失败: {}", e);
+        std::process::exit(1);
+    });
+    tracing::info!("存储后端: {}", storage.backend());
+    let credential_stats = Arc::new(storage::credential_stats::CredentialStats::new(
+        storage.clone(),
+    ));
+
+    // 会话粘性绑定：memory 后端下定期写入快照文件，重启后恢复
+    let mut session_affinity = storage::session_affinity::SessionAffinity::new(storage.clone());
+    if config.storage_backend == storage::StorageBackend::Memory
+        && !config.sticky_session_path.is_empty()
+    {
+        session_affinity = session_affinity.with_snapshot(&config.sticky_session_path);
+        session_affinity.spawn_snapshot_writer();
+    }
+
     // 创建 MultiTokenManager 和 KiroProvider
     let token_manager = MultiTokenManager::new(
         config.clone(),
         credentials_list,
         proxy_config.clone(),
-        Some(credentials_path.into()),
+        Some(credentials_path.clone().into()),
         is_multiple_format,
     )
     .unwrap_or_else(|e| {
         tracing::error!("创建 Token 管理器失败: {}", e);
         std::process::exit(1);
-    });
+    })
+    .with_credential_stats(credential_stats.clone())
+    .with_session_affinity(session_affinity.clone());
     let token_manager = Arc::new(token_manager);
     let kiro_provider = KiroProvider::with_proxy(token_manager.clone(), proxy_config.clone());
 
+    // 初始化告警配置
+    common::alert::init_config(common::alert::AlertConfig {
+        webhook_url: config.alert_webhook_url.clone(),
+        proxy: proxy_config.clone(),
+        tls_backend: config.tls_backend,
+    });
+
+    // 加载 tokenizer（缺失或损坏时按配置重新下载）
+    let tokenizer_source = match (&config.tokenizer_url, &config.tokenizer_sha256) {
+        (Some(url), Some(sha256)) => Some(token::TokenizerSource {
+            url: url.clone(),
+            sha256: sha256.clone(),
+            proxy: proxy_config.clone(),
+            tls_backend: config.tls_backend,
+        }),
+        (Some(_), None) => {
+            tracing::warn!("已配置 tokenizerUrl 但未配置 tokenizerSha256，不会自动下载 tokenizer");
+            None
+        }
+        _ => None,
+    };
+    if let Err(e) = token::init_tokenizer(
+        tokenizer_source,
+        config.tokenizer_path.as_deref(),
+        &config.model_tokenizers,
+    )
+    .await
+    {
+        tracing::error!("加载 tokenizer 失败: {}", e);
+        std::process::exit(1);
+    }
+
     // 初始化 count_tokens 配置
     token::init_config(token::CountTokensConfig {
         api_url: config.count_tokens_api_url.clone(),
@@ -101,13 +169,85 @@ async fn main() {
         tls_backend: config.tls_backend,
     });
 
-    // 构建 Anthropic API 路由（从第一个凭据获取 profile_arn）
-    let anthropic_app = anthropic::create_router_with_provider(
-        &api_key,
+    // 凭据余额定时轮询（写入余额历史）
+    let balance_history = Arc::new(storage::balance_history::BalanceHistory::new(
+        storage.clone(),
+        config.balance_history_days,
+    ));
+    kiro::balance_poller::spawn(token_manager.clone(), balance_history.clone());
+    kiro::token_refresher::spawn(token_manager.clone());
+
+    let usage_ledger = Arc::new(
+        storage::ledger::UsageLedger::new(storage.clone())
+            .with_credential_stats(credential_stats.clone()),
+    );
+
+    // 客户端 API Key（主 apiKey、配置中的 apiKeys 与 Admin 创建的 Key）
+    let api_keys = Arc::new(common::api_keys::ApiKeyRegistry::new(
+        &config,
+        storage.clone(),
+    ));
+    if api_keys.is_empty() {
+        tracing::error!("配置文件中未设置 apiKey 或 apiKeys");
+        std::process::exit(1);
+    }
+
+    // Files API 本地文件存储（打开失败时仅禁用 Files API）
+    let file_store = match anthropic::FileStore::open(&config.files_dir) {
+        Ok(store) => Some(Arc::new(store)),
+        Err(e) => {
+            tracing::warn!(
+                "打开文件目录 {} 失败，Files API 已禁用: {}",
+                config.files_dir,
+                e
+            );
+            None
+        }
+    };
+
+    // 可清空缓存注册表（供 Admin API 使用）
+    let caches = Arc::new(common::cache::CacheRegistry::new());
+    caches.register(
+        common::cache::CacheKind::TokenCount,
+        token::token_count_cache(),
+    );
+
+    // 构建 Anthropic API 状态（从第一个凭据获取 profile_arn）
+    let mut anthropic_state = anthropic::create_app_state(
+        api_keys.clone(),
         Some(kiro_provider),
         first_credentials.profile_arn.clone(),
+        storage.clone(),
+        usage_ledger.clone(),
+        file_store,
+        &caches,
     );
 
+    // 监听配置与凭据文件变化并热重载
+    if let Some(provider) = anthropic_state.kiro_provider.clone() {
+        reload::spawn(config_path.into(), credentials_path.into(), provider);
+    }
+
+    // 连接服务端执行工具的 MCP 服务器
+    if config.mcp_client.enabled {
+        let pool = anthropic::McpToolPool::connect(&config.mcp_client).await;
+        if pool.is_empty() {
+            tracing::warn!("已启用 mcpClient，但没有可用的 MCP 工具");
+        }
+        anthropic_state = anthropic_state.with_mcp_tools(Arc::new(pool));
+    }
+
+    // MCP 子命令：通过 stdio 提供服务，不监听 HTTP 端口
+    if mcp_stdio {
+        if let Err(e) = anthropic::serve_mcp_stdio(anthropic_state).await {
+            tracing::error!("MCP 服务端异常退出: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let audit_log = anthropic_state.audit_log.clone();
+    let anthropic_app = anthropic::create_router(anthropic_state);
+
     // 构建 Admin API 路由（如果配置了非空的 admin_api_key）
     // 安全检查：空字符串被视为未配置，防止空 key 绕过认证
     let admin_key_valid = config
@@ -121,7 +261,15 @@ async fn main() {
             tracing::warn!("admin_api_key 配置为空，Admin API 未启用");
             anthropic_app
         } else {
-            let admin_service = admin::AdminService::new(token_manager.clone());
+            let mut admin_service = admin::AdminService::new(token_manager.clone())
+                .with_usage_ledger(usage_ledger.clone())
+                .with_credential_stats(credential_stats.clone())
+                .with_balance_history(balance_history.clone())
+                .with_cache_registry(caches.clone())
+                .with_api_keys(api_keys.clone());
+            if let Some(log) = audit_log {
+                admin_service = admin_service.with_audit_log(log);
+            }
             let admin_state = admin::AdminState::new(admin_key, admin_service);
             let admin_app = admin::create_admin_router(admin_state);
 
@@ -138,26 +286,114 @@ async fn main() {
         anthropic_app
     };
 
+    // 运维端点：配置 opsPort 时独立监听，否则挂载到 API 端口
+    let ops_app = ops::create_ops_router(token_manager.clone());
+    let app = match config.ops_port {
+        Some(ops_port) => {
+            let ops_host = config.ops_host.as_deref().unwrap_or(&config.host);
+            let ops_addr = format!("{}:{}", ops_host, ops_port);
+            let ops_listener = tokio::net::TcpListener::bind(&ops_addr)
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!("绑定运维端点 {} 失败: {}", ops_addr, e);
+                    std::process::exit(1);
+                });
+            tracing::info!(
+                "运维端点独立监听: {} (/metrics, /healthz, /readyz)",
+                ops_addr
+            );
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(ops_listener, ops_app).await {
+                    tracing::error!("运维端点服务异常退出: {}", e);
+                }
+            });
+            app
+        }
+        None => app.merge(ops_app),
+    };
+    let app = app.layer(axum::middleware::from_fn(
+        common::telemetry::route_log_middleware,
+    ));
+
     // 启动服务器
     let addr = format!("{}:{}", config.host, config.port);
     tracing::info!("启动 Anthropic API 端点: {}", addr);
-    tracing::info!("API Key: {}***", &api_key[..(api_key.len() / 2)]);
+    if let Some(api_key) = &config.api_key {
+        tracing::info!("API Key: {}***", &api_key[..(api_key.len() / 2)]);
+    }
+    tracing::info!("客户端 API Key: {} 个", api_keys.len());
     tracing::info!("可用 API:");
     tracing::info!("  GET  /v1/models");
+    tracing::info!("  GET  /v1/models/:id");
     tracing::info!("  POST /v1/messages");
     tracing::info!("  POST /v1/messages/count_tokens");
+    tracing::info!("  GET  /v1/messages/:id/events");
+    tracing::info!("  POST /v1/chat/completions");
+    tracing::info!("  POST /v1beta/models/:model:generateContent");
+    tracing::info!("  POST /v1beta/models/:model:streamGenerateContent");
+    tracing::info!("  POST /v1beta/models/:model:countTokens");
+    tracing::info!("  POST /v1/files");
+    tracing::info!("  GET  /v1/files");
+    tracing::info!("  GET  /v1/files/:id");
+    if config.mcp_server.enabled {
+        tracing::info!("  GET  /v1/mcp/sse");
+        tracing::info!("  POST /v1/mcp/messages");
+    }
+    if config.ops_port.is_none() {
+        tracing::info!("  GET  /metrics");
+        tracing::info!("  GET  /healthz");
+        tracing::info!("  GET  /readyz");
+    }
     if admin_key_valid {
         tracing::info!("Admin API:");
         tracing::info!("  GET  /api/admin/credentials");
+        tracing::info!("  GET  /api/admin/credentials/balances");
+        tracing::info!("  POST /api/admin/credentials/export");
+        tracing::info!("  POST /api/admin/credentials/import");
         tracing::info!("  POST /api/admin/credentials/:id/disabled");
         tracing::info!("  POST /api/admin/credentials/:id/priority");
         tracing::info!("  POST /api/admin/credentials/:id/reset");
         tracing::info!("  POST /api/admin/credentials/:id/refresh");
         tracing::info!("  GET  /api/admin/credentials/:id/balance");
+        tracing::info!("  GET  /api/admin/api-keys");
+        tracing::info!("  POST /api/admin/api-keys");
+        tracing::info!("  DELETE /api/admin/api-keys/:name");
+        tracing::info!("  POST /api/admin/api-keys/:name/disabled");
+        tracing::info!("  POST /api/admin/api-keys/:name/rate-limit");
+        tracing::info!("  POST /api/admin/api-keys/:name/quota");
+        tracing::info!("  GET  /api/admin/api-keys/:name/usage");
+        tracing::info!("  POST /api/admin/api-keys/:name/usage/reset");
+        tracing::info!("  GET  /api/admin/usage");
+        tracing::info!("  GET  /api/admin/requests");
+        tracing::info!("  POST /api/admin/cache/flush");
+        tracing::info!("  GET  /api/admin/config");
+        tracing::info!("  PUT  /api/admin/config/logging");
+        tracing::info!("  GET  /api/admin/status");
         tracing::info!("Admin UI:");
         tracing::info!("  GET  /admin");
     }
 
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // 收到关闭信号后停止接受新连接，等待进行中的响应结束，超过宽限期强制退出
+    let shutdown = common::shutdown::signal().boxed().shared();
+    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown.clone());
+    let grace = std::time::Duration::from_secs(config.shutdown_grace_period_secs);
+    let deadline = async {
+        shutdown.await;
+        common::shutdown::drain(token_manager, grace).await
+    };
+    tokio::select! {
+        result = server => {
+            result.unwrap();
+            tracing::info!("所有请求已完成，服务器已关闭");
+        }
+        remaining = deadline => {
+            tracing::warn!(
+                "优雅关闭宽限期（{} 秒）已过，强制中止 {} 个进行中的请求",
+                config.shutdown_grace_period_secs,
+                remaining
+            );
+        }
+    }
+    session_affinity.flush();
 }
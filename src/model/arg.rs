@@ -1,14 +1,37 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 /// Anthropic <-> Kiro API 客户端
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     /// 配置文件路径
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub config: Option<String>,
 
     /// 凭证文件路径
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub credentials: Option<String>,
+
+    /// 子命令（不指定时启动服务）
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// 子命令
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 自检：检查配置、tokenizer、凭据刷新、上游连通性、代理和时钟偏差
+    Doctor,
+    /// 生成脱敏的诊断包（配置差异、版本信息、凭据池摘要、最近的警告日志），便于附在 issue 中
+    SupportBundle {
+        /// 输出文件路径（默认 kiro-rs-support-<时间>.json）
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// 服务日志文件，用于提取最近的警告与错误
+        #[arg(long)]
+        log: Option<String>,
+    },
+    /// 以 MCP 服务端运行（stdio 传输），向支持 MCP 的编辑器提供 ask_claude 等工具
+    Mcp,
 }
@@ -1,7 +1,13 @@
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
 
+use crate::anthropic::converter::PROFESSIONAL_SYSTEM_PROMPT;
+use crate::kiro::parser::decoder::{DEFAULT_MAX_BUFFER_SIZE, OverflowPolicy};
+use crate::storage::StorageBackend;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum TlsBackend {
@@ -15,6 +21,47 @@ impl Default for TlsBackend {
     }
 }
 
+/// WebSearch 工具处理方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebSearchMode {
+    /// 拦截并通过 MCP 执行搜索
+    #[default]
+    Intercept,
+    /// 从请求中移除 web_search 工具后按普通请求转发
+    Strip,
+    /// 拒绝包含 web_search 工具的请求
+    Reject,
+}
+
+/// 请求 schema 校验方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RequestValidation {
+    /// 不校验
+    #[default]
+    Off,
+    /// 校验并记录警告日志，请求照常处理
+    Shadow,
+    /// 校验失败时返回 400 及字段级错误
+    Strict,
+}
+
+/// 凭据调度策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulingStrategy {
+    /// 始终使用优先级最高的凭据，不可用时才故障转移到下一优先级
+    Priority,
+    /// 按凭据 ID 轮流使用
+    RoundRobin,
+    /// 按凭据权重随机分配
+    Weighted,
+    /// 选择活跃连接数最少的凭据
+    #[default]
+    LeastConnections,
+}
+
 /// KNA 应用配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -25,6 +72,31 @@ pub struct Config {
     #[serde(default = "default_port")]
     pub port: u16,
 
+    /// 运维端点（/metrics、/healthz、/readyz）独立监听端口（可选，未配置时挂载到 API 端口）
+    #[serde(default)]
+    pub ops_port: Option<u16>,
+
+    /// 运维端点监听地址（可选，未配置时与 host 相同）
+    #[serde(default)]
+    pub ops_host: Option<String>,
+
+    /// `/readyz` 上游探测（默认仅在请求携带 `?probe=true` 时探测）
+    #[serde(default)]
+    pub readiness_probe: ReadinessProbeConfig,
+
+    /// 优雅关闭宽限期（秒）：收到 SIGTERM/Ctrl+C 后停止接受新请求，
+    /// 等待进行中的流式响应结束，超时后强制退出
+    #[serde(default = "default_shutdown_grace_period_secs")]
+    pub shutdown_grace_period_secs: u64,
+
+    /// OTLP/HTTP 链路追踪导出地址（可选，如 http://localhost:4318/v1/traces，需启用 otel 特性）
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+
+    /// 链路追踪上报的服务名
+    #[serde(default = "default_otel_service_name")]
+    pub otel_service_name: String,
+
     #[serde(default = "default_region")]
     pub region: String,
 
@@ -37,6 +109,10 @@ pub struct Config {
     #[serde(default)]
     pub api_key: Option<String>,
 
+    /// 额外的客户端 API Key（每个下游调用方一个，请求按 Key 名称归属）
+    #[serde(default)]
+    pub api_keys: Vec<ClientApiKey>,
+
     #[serde(default = "default_system_version")]
     pub system_version: String,
 
@@ -58,6 +134,22 @@ pub struct Config {
     #[serde(default = "default_count_tokens_auth_type")]
     pub count_tokens_auth_type: String,
 
+    /// tokenizer 下载地址（可选，本地文件缺失或损坏时下载，需同时配置 tokenizerSha256）
+    #[serde(default)]
+    pub tokenizer_url: Option<String>,
+
+    /// tokenizer 文件的 SHA-256（十六进制）
+    #[serde(default)]
+    pub tokenizer_sha256: Option<String>,
+
+    /// 默认 tokenizer 文件路径（可选，未配置时依次查找 tokenizers/claude-tokenizer.json 等内置路径）
+    #[serde(default)]
+    pub tokenizer_path: Option<String>,
+
+    /// 按模型使用的 tokenizer 文件（按配置顺序匹配，未匹配的模型使用默认 tokenizer）
+    #[serde(default)]
+    pub model_tokenizers: Vec<ModelTokenizer>,
+
     /// HTTP 代理地址（可选）
     /// 支持格式: http://host:port, https://host:port, socks5://host:port
     #[serde(default)]
@@ -74,6 +166,1139 @@ pub struct Config {
     /// Admin API 密钥（可选，启用 Admin API 功能）
     #[serde(default)]
     pub admin_api_key: Option<String>,
+
+    /// 告警 Webhook 地址（可选，上游维护/版本过低、余额不足等事件会推送到此地址）
+    #[serde(default)]
+    pub alert_webhook_url: Option<String>,
+
+    /// 持久化存储后端（memory / sqlite / redis）
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+
+    /// SQLite 数据库文件路径（storageBackend 为 sqlite 时使用）
+    #[serde(default = "default_storage_path")]
+    pub storage_path: String,
+
+    /// Files API 上传文件的保存目录
+    #[serde(default = "default_files_dir")]
+    pub files_dir: String,
+
+    /// Redis 连接地址（storageBackend 为 redis 时使用，如 redis://127.0.0.1/）
+    #[serde(default)]
+    pub storage_url: Option<String>,
+
+    /// 自定义提示词配置（名称 -> 提示词内容），可通过 x-kiro-inject 请求头选择
+    #[serde(default)]
+    pub prompt_profiles: HashMap<String, String>,
+
+    /// 是否允许客户端通过 x-kiro-inject 请求头覆盖提示词注入
+    #[serde(default = "default_allow_inject_header")]
+    pub allow_inject_header: bool,
+
+    /// 是否允许所有客户端通过 x-kiro-model-override 请求头指定 Kiro 模型 ID
+    /// （关闭时仅使用 adminApiKey 认证的请求可用）
+    #[serde(default)]
+    pub allow_model_override_header: bool,
+
+    /// 是否为 Opus 请求启用内置的专业助手提示词模板（默认关闭）
+    ///
+    /// 加载配置时作为 `systemPromptTemplates` 中的 `opus` 条目加入，已配置同名模板时以配置为准
+    #[serde(default)]
+    pub opus_prompt_injection: bool,
+
+    /// 自定义 Opus 提示词文件路径，替代内置的专业助手提示词
+    #[serde(default)]
+    pub opus_prompt_file: Option<String>,
+
+    /// 从 `opus_prompt_file` 加载的提示词内容
+    #[serde(skip)]
+    pub opus_prompt: Option<String>,
+
+    /// 预置对话轮次：插入到每个对话的系统消息之后、真实消息之前
+    #[serde(default)]
+    pub synthetic_history: Vec<SyntheticTurn>,
+
+    /// 重试、退避与熔断策略
+    #[serde(default)]
+    pub resilience: ResilienceConfig,
+
+    /// 按路由的日志级别（可通过 Admin API 运行时调整）
+    #[serde(default)]
+    pub logging: LoggingConfig,
+
+    /// 凭据并发上限策略
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+
+    /// 上游请求超时
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+
+    /// 非流式响应缓存
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+
+    /// Message Batches API
+    #[serde(default)]
+    pub batches: BatchConfig,
+
+    /// 请求审计日志（默认关闭）
+    #[serde(default)]
+    pub audit_log: AuditLogConfig,
+
+    /// 全局并发请求上限与排队（默认关闭）
+    #[serde(default)]
+    pub admission: AdmissionConfig,
+
+    /// 历史过长时由代理生成摘要替换较早的对话轮次（默认关闭）
+    #[serde(default)]
+    pub context_summary: ContextSummaryConfig,
+
+    /// 备用后端：所有 Kiro 凭据用尽或被限流时，/v1/messages 按顺序转发到这些后端
+    #[serde(default)]
+    pub fallback_backends: Vec<FallbackBackend>,
+
+    /// MCP 服务端（`/v1/mcp/sse`；`kiro-rs mcp` 子命令的 stdio 模式不受 enabled 影响）
+    #[serde(default)]
+    pub mcp_server: McpServerConfig,
+
+    /// 由服务端连接并代为执行工具调用的 MCP 服务器（默认关闭）
+    #[serde(default)]
+    pub mcp_client: McpClientConfig,
+
+    /// `GET /v1/models` 公布的模型列表
+    #[serde(default = "default_models")]
+    pub models: Vec<ModelEntry>,
+
+    /// `/v1/messages` 请求按 Anthropic Messages schema 校验的方式：`off` / `shadow` / `strict`
+    #[serde(default)]
+    pub request_validation: RequestValidation,
+
+    /// 凭据调度策略
+    #[serde(default)]
+    pub scheduling_strategy: SchedulingStrategy,
+
+    /// 会话粘性绑定有效期（秒，0 表示关闭）
+    ///
+    /// 从 `metadata.user_id` 提取到 conversationId 的请求，在有效期内固定使用同一凭据
+    #[serde(default = "default_sticky_session_ttl_secs")]
+    pub sticky_session_ttl_secs: u64,
+
+    /// 会话粘性绑定的快照文件（storageBackend 为 memory 时使用，空字符串表示不持久化）
+    ///
+    /// 绑定定期写入该文件，重启后恢复仍在有效期内的绑定
+    #[serde(default = "default_sticky_session_path")]
+    pub sticky_session_path: String,
+
+    /// 凭据余额轮询间隔（秒，0 表示关闭），每次轮询的结果写入余额历史
+    #[serde(default = "default_balance_poll_interval_secs")]
+    pub balance_poll_interval_secs: u64,
+
+    /// 余额历史保留天数
+    #[serde(default = "default_balance_history_days")]
+    pub balance_history_days: u32,
+
+    /// 余额告警阈值（剩余额度百分比，可选）
+    ///
+    /// 余额轮询发现凭据剩余额度低于该比例时记录警告日志，并推送到 `alertWebhookUrl`
+    #[serde(default)]
+    pub balance_alert_threshold_percent: Option<f64>,
+
+    /// 后台主动刷新 Token 的提前量（秒，0 表示关闭）
+    ///
+    /// 凭据在该时间内即将过期时由后台任务刷新，空闲后的首个请求无需等待刷新
+    #[serde(default = "default_token_refresh_margin_secs")]
+    pub token_refresh_margin_secs: u64,
+
+    /// 发生故障转移时是否在响应中暴露凭据 ID（仅建议在客户端可信时开启）
+    #[serde(default)]
+    pub expose_credential_ids: bool,
+
+    /// 流式响应重复片段抑制的最小重叠字节数（默认 0，即关闭）
+    ///
+    /// 上游重发的内容与已输出内容重叠达到该长度时丢弃重叠部分。正常输出中也可能出现
+    /// 与前文重叠的内容（如重复的代码行），因此需显式开启
+    #[serde(default)]
+    pub stream_dedup_min_overlap: usize,
+
+    /// 响应页脚（模型名或模型名片段 -> 追加的文本，`*` 匹配所有模型）
+    #[serde(default)]
+    pub response_footers: HashMap<String, String>,
+
+    /// 不注入响应页脚的 API Key 列表
+    #[serde(default)]
+    pub footer_opt_out_keys: Vec<String>,
+
+    /// 系统提示词模板（模型名或模型名片段 -> 模板，`*` 匹配所有模型）
+    #[serde(default)]
+    pub system_prompt_templates: HashMap<String, SystemPromptTemplate>,
+
+    /// 请求改写规则（按顺序匹配，命中的规则依次执行动作，仅作用于 `/v1/messages`）
+    #[serde(default)]
+    pub request_rules: Vec<RequestRule>,
+
+    /// 是否对对话中重复出现的图片去重（重复图片替换为指向首次出现位置的文本引用）
+    #[serde(default = "default_image_dedupe")]
+    pub image_dedupe: bool,
+
+    /// 转发前缩小尺寸或数据量超限的图片
+    #[serde(default)]
+    pub image_downscale: ImageDownscaleConfig,
+
+    /// 下载 URL 来源的图片（`source.type == "url"`）并转换为 base64
+    #[serde(default)]
+    pub image_fetch: ImageFetchConfig,
+
+    /// 请求体接近 Kiro API 上限（约 2MB）时自动丢弃最早的历史轮次（默认关闭）
+    #[serde(default)]
+    pub auto_compact_history: bool,
+
+    /// 客户端身份标记的哈希盐（可选，配置后在 agentContinuationId 中嵌入加盐哈希的 API Key 标记）
+    #[serde(default)]
+    pub client_identity_salt: Option<String>,
+
+    /// 上游事件流解码缓冲区上限（字节）
+    #[serde(default = "default_decoder_max_buffer_bytes")]
+    pub decoder_max_buffer_bytes: usize,
+
+    /// 解码缓冲区溢出策略：`truncate` 丢弃超限帧并继续，`abort` 中止流并返回错误
+    #[serde(default)]
+    pub decoder_overflow_policy: OverflowPolicy,
+
+    /// WebSearch 工具处理方式：`intercept` / `strip` / `reject`
+    #[serde(default)]
+    pub websearch_mode: WebSearchMode,
+
+    /// 按 API Key 覆盖 WebSearch 处理方式（API Key -> 处理方式）
+    #[serde(default)]
+    pub websearch_key_overrides: HashMap<String, WebSearchMode>,
+
+    /// 附加到上游 Kiro 请求的自定义请求头（名称 -> 值模板），凭据的 `headers` 同名时覆盖
+    #[serde(default)]
+    pub upstream_headers: BTreeMap<String, String>,
+}
+
+fn default_image_dedupe() -> bool {
+    true
+}
+
+fn default_sticky_session_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_balance_poll_interval_secs() -> u64 {
+    86400
+}
+
+fn default_balance_history_days() -> u32 {
+    90
+}
+
+fn default_token_refresh_margin_secs() -> u64 {
+    900
+}
+
+fn default_decoder_max_buffer_bytes() -> usize {
+    DEFAULT_MAX_BUFFER_SIZE
+}
+
+/// 日志配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggingConfig {
+    /// 路由路径前缀 → 日志级别（off / error / warn / info / debug / trace），按最长前缀匹配；
+    /// 仅作用于本服务自身的日志，未匹配的路由沿用 RUST_LOG
+    #[serde(default)]
+    pub routes: BTreeMap<String, String>,
+
+    /// 日志输出格式
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+/// 日志输出格式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// 人类可读的文本格式
+    #[default]
+    Text,
+    /// 每行一个 JSON 对象（请求相关字段平铺到顶层，便于 Loki/ELK 采集）
+    Json,
+}
+
+/// 重试、退避与熔断策略
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResilienceConfig {
+    /// 每个凭据的最大重试次数
+    #[serde(default = "default_max_retries_per_credential")]
+    pub max_retries_per_credential: usize,
+
+    /// 单次请求总重试次数硬上限（避免无限重试）
+    #[serde(default = "default_max_total_retries")]
+    pub max_total_retries: usize,
+
+    /// 指数退避基础延迟（毫秒）
+    #[serde(default = "default_backoff_base_ms")]
+    pub backoff_base_ms: u64,
+
+    /// 指数退避延迟上限（毫秒，不含抖动）
+    #[serde(default = "default_backoff_max_ms")]
+    pub backoff_max_ms: u64,
+
+    /// 凭据连续失败多少次后熔断（自动禁用）
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// 熔断后自动恢复的冷却时间（秒），0 表示不自动恢复（仍保留全部熔断时的自愈）
+    #[serde(default)]
+    pub failure_cooldown_secs: u64,
+
+    /// 凭据连续被拒绝（401/403/429）多少次后打开熔断器，0 表示关闭熔断器
+    #[serde(default = "default_breaker_threshold")]
+    pub breaker_threshold: u32,
+
+    /// 熔断器打开后跳过该凭据的时间（秒），之后放行一个探测请求
+    #[serde(default = "default_breaker_cooldown_secs")]
+    pub breaker_cooldown_secs: u64,
+
+    /// 流式响应在输出任何内容前中断时，换用其他凭据重试的次数，0 表示不重试
+    #[serde(default = "default_stream_retries")]
+    pub stream_retries: usize,
+
+    /// 对冲延迟（毫秒）：请求在该时间内未收到响应头时，在另一个凭据上再发一次，
+    /// 采用先返回的响应；0 表示关闭
+    #[serde(default)]
+    pub hedge_delay_ms: u64,
+}
+
+fn default_max_retries_per_credential() -> usize {
+    3
+}
+
+fn default_max_total_retries() -> usize {
+    9
+}
+
+fn default_backoff_base_ms() -> u64 {
+    200
+}
+
+fn default_backoff_max_ms() -> u64 {
+    2_000
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_breaker_threshold() -> u32 {
+    3
+}
+
+fn default_breaker_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_stream_retries() -> usize {
+    2
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        Self {
+            max_retries_per_credential: default_max_retries_per_credential(),
+            max_total_retries: default_max_total_retries(),
+            backoff_base_ms: default_backoff_base_ms(),
+            backoff_max_ms: default_backoff_max_ms(),
+            failure_threshold: default_failure_threshold(),
+            failure_cooldown_secs: 0,
+            breaker_threshold: default_breaker_threshold(),
+            breaker_cooldown_secs: default_breaker_cooldown_secs(),
+            stream_retries: default_stream_retries(),
+            hedge_delay_ms: 0,
+        }
+    }
+}
+
+/// 凭据并发上限策略
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcurrencyConfig {
+    /// 是否根据限流和延迟自动调整每个凭据的并发上限（AIMD）
+    #[serde(default = "default_concurrency_adaptive")]
+    pub adaptive: bool,
+
+    /// 初始并发上限（关闭自适应时即固定上限）
+    #[serde(default = "default_concurrency_initial_limit")]
+    pub initial_limit: u32,
+
+    /// 并发上限下限
+    #[serde(default = "default_concurrency_min_limit")]
+    pub min_limit: u32,
+
+    /// 并发上限上限
+    #[serde(default = "default_concurrency_max_limit")]
+    pub max_limit: u32,
+
+    /// 被限流或延迟突增时上限的收缩比例
+    #[serde(default = "default_concurrency_backoff_ratio")]
+    pub backoff_ratio: f64,
+
+    /// 首字节延迟超过基线多少倍时视为过载
+    #[serde(default = "default_concurrency_latency_tolerance")]
+    pub latency_tolerance: f64,
+
+    /// 所有凭据都达到 `maxConcurrent` 硬上限时，请求排队等待空闲连接的最长时间（毫秒，0 表示立即失败）
+    #[serde(default = "default_concurrency_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+fn default_concurrency_adaptive() -> bool {
+    true
+}
+
+fn default_concurrency_initial_limit() -> u32 {
+    3
+}
+
+fn default_concurrency_min_limit() -> u32 {
+    1
+}
+
+fn default_concurrency_max_limit() -> u32 {
+    16
+}
+
+fn default_concurrency_backoff_ratio() -> f64 {
+    0.5
+}
+
+fn default_concurrency_latency_tolerance() -> f64 {
+    3.0
+}
+
+fn default_concurrency_queue_timeout_ms() -> u64 {
+    30_000
+}
+
+impl ConcurrencyConfig {
+    /// 校验并发上限的取值范围（下限不能大于上限）
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.min_limit > self.max_limit {
+            anyhow::bail!(
+                "concurrency.minLimit ({}) 不能大于 concurrency.maxLimit ({})",
+                self.min_limit,
+                self.max_limit
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            adaptive: default_concurrency_adaptive(),
+            initial_limit: default_concurrency_initial_limit(),
+            min_limit: default_concurrency_min_limit(),
+            max_limit: default_concurrency_max_limit(),
+            backoff_ratio: default_concurrency_backoff_ratio(),
+            latency_tolerance: default_concurrency_latency_tolerance(),
+            queue_timeout_ms: default_concurrency_queue_timeout_ms(),
+        }
+    }
+}
+
+/// 上游请求超时（秒，0 表示不限制）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeoutConfig {
+    /// 建立连接（含 TLS 握手）的超时
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_secs: u64,
+
+    /// 流式请求收到响应头后，等待首个内容事件的超时
+    #[serde(default = "default_first_event_timeout_secs")]
+    pub first_event_secs: u64,
+
+    /// 流式响应输出过程中，两次收到上游数据之间的最长间隔
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_secs: u64,
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_first_event_timeout_secs() -> u64 {
+    300
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    300
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_secs: default_connect_timeout_secs(),
+            first_event_secs: default_first_event_timeout_secs(),
+            idle_secs: default_idle_timeout_secs(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// 将秒数转换为超时时长（0 表示不限制）
+    fn limit(secs: u64) -> Option<std::time::Duration> {
+        (secs > 0).then(|| std::time::Duration::from_secs(secs))
+    }
+
+    pub fn connect(&self) -> Option<std::time::Duration> {
+        Self::limit(self.connect_secs)
+    }
+
+    pub fn first_event(&self) -> Option<std::time::Duration> {
+        Self::limit(self.first_event_secs)
+    }
+
+    pub fn idle(&self) -> Option<std::time::Duration> {
+        Self::limit(self.idle_secs)
+    }
+}
+
+/// 按模型使用的 tokenizer 文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelTokenizer {
+    /// 模型名模式（不区分大小写，支持 `*` 通配符，如 `claude-3-*`）
+    pub model: String,
+    /// tokenizer 文件路径
+    pub path: String,
+}
+
+/// 非流式响应缓存（相同请求直接返回本地缓存的响应）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCacheConfig {
+    /// 是否启用
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 最多缓存的响应数，超出后淘汰最久未使用的条目
+    #[serde(default = "default_response_cache_max_entries")]
+    pub max_entries: usize,
+
+    /// 缓存有效期（秒）
+    #[serde(default = "default_response_cache_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+fn default_response_cache_max_entries() -> usize {
+    1000
+}
+
+fn default_response_cache_ttl_secs() -> u64 {
+    3600
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: default_response_cache_max_entries(),
+            ttl_secs: default_response_cache_ttl_secs(),
+        }
+    }
+}
+
+/// 请求审计日志：记录每次请求的时间、客户端 Key、模型、token 用量、耗时、状态码与所用凭据，
+/// 不保存请求与响应内容
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogConfig {
+    /// 是否启用
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 最多保留的记录数，超出后覆盖最旧的记录
+    #[serde(default = "default_audit_log_max_entries")]
+    pub max_entries: u64,
+}
+
+fn default_audit_log_max_entries() -> u64 {
+    10000
+}
+
+impl Default for AuditLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: default_audit_log_max_entries(),
+        }
+    }
+}
+
+/// 全局并发请求上限：超出上限的请求按到达顺序排队，队列已满或排队超时时返回 429
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionConfig {
+    /// 同时处理的最大请求数（含流式响应输出期间），0 表示不限制
+    #[serde(default)]
+    pub max_in_flight: usize,
+
+    /// 最多排队等待的请求数
+    #[serde(default = "default_admission_max_queue")]
+    pub max_queue: usize,
+
+    /// 排队等待的最长时间（毫秒）
+    #[serde(default = "default_admission_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+fn default_admission_max_queue() -> usize {
+    256
+}
+
+fn default_admission_queue_timeout_ms() -> u64 {
+    30_000
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 0,
+            max_queue: default_admission_max_queue(),
+            queue_timeout_ms: default_admission_queue_timeout_ms(),
+        }
+    }
+}
+
+/// URL 图片下载：限制单张图片的大小与下载时间，仅接受 JPEG / PNG / GIF / WebP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageFetchConfig {
+    /// 是否启用（关闭时 URL 图片以文本引用的形式转发）
+    #[serde(default = "default_image_fetch_enabled")]
+    pub enabled: bool,
+
+    /// 单张图片的最大字节数
+    #[serde(default = "default_image_fetch_max_bytes")]
+    pub max_bytes: usize,
+
+    /// 单张图片的下载超时（秒）
+    #[serde(default = "default_image_fetch_timeout_secs")]
+    pub timeout_secs: u64,
+
+    /// 单个请求最多下载的图片 URL 数（去重后）
+    #[serde(default = "default_image_fetch_max_urls")]
+    pub max_urls: usize,
+}
+
+fn default_image_fetch_enabled() -> bool {
+    true
+}
+
+fn default_image_fetch_max_bytes() -> usize {
+    5 * 1024 * 1024
+}
+
+fn default_image_fetch_timeout_secs() -> u64 {
+    10
+}
+
+fn default_image_fetch_max_urls() -> usize {
+    20
+}
+
+impl Default for ImageFetchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_image_fetch_enabled(),
+            max_bytes: default_image_fetch_max_bytes(),
+            timeout_secs: default_image_fetch_timeout_secs(),
+            max_urls: default_image_fetch_max_urls(),
+        }
+    }
+}
+
+/// 超限图片缩小：长边超过 `maxDimension` 或数据超过 `maxBytes` 的图片在转发前缩小并重新编码
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageDownscaleConfig {
+    /// 是否启用
+    #[serde(default = "default_image_downscale_enabled")]
+    pub enabled: bool,
+
+    /// 图片长边的最大像素数
+    #[serde(default = "default_image_downscale_max_dimension")]
+    pub max_dimension: u32,
+
+    /// 单张图片（解码后）的最大字节数
+    #[serde(default = "default_image_downscale_max_bytes")]
+    pub max_bytes: usize,
+}
+
+fn default_image_downscale_enabled() -> bool {
+    true
+}
+
+fn default_image_downscale_max_dimension() -> u32 {
+    1568
+}
+
+fn default_image_downscale_max_bytes() -> usize {
+    512 * 1024
+}
+
+impl Default for ImageDownscaleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_image_downscale_enabled(),
+            max_dimension: default_image_downscale_max_dimension(),
+            max_bytes: default_image_downscale_max_bytes(),
+        }
+    }
+}
+
+/// 备用后端（Anthropic API 或其他 kiro.rs 实例）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FallbackBackend {
+    /// 名称（用于日志与 `x-kiro-fallback` 响应头）
+    pub name: String,
+
+    /// API 地址，如 `https://api.anthropic.com`（请求发送到 `{baseUrl}/v1/messages`）
+    pub base_url: String,
+
+    /// API Key（以 `x-api-key` 头发送）
+    pub api_key: String,
+
+    /// 模型映射：请求中的模型 → 该后端使用的模型，`*` 匹配其他所有模型；未匹配时沿用原模型
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+}
+
+impl FallbackBackend {
+    /// 该后端使用的模型
+    pub fn map_model<'a>(&'a self, model: &'a str) -> &'a str {
+        self.models
+            .get(model)
+            .or_else(|| self.models.get("*"))
+            .map(String::as_str)
+            .unwrap_or(model)
+    }
+}
+
+/// 上下文摘要：历史超过 token 预算时，调用 Kiro 将较早的轮次压缩为一对摘要消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextSummaryConfig {
+    /// 是否启用
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 历史消息（不含系统提示词与预置对话）的 token 预算，超出后生成摘要
+    #[serde(default = "default_context_summary_max_history_tokens")]
+    pub max_history_tokens: u64,
+
+    /// 原样保留的最近对话轮数
+    #[serde(default = "default_context_summary_keep_recent_turns")]
+    pub keep_recent_turns: usize,
+}
+
+fn default_context_summary_max_history_tokens() -> u64 {
+    100_000
+}
+
+fn default_context_summary_keep_recent_turns() -> usize {
+    4
+}
+
+impl Default for ContextSummaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_history_tokens: default_context_summary_max_history_tokens(),
+            keep_recent_turns: default_context_summary_keep_recent_turns(),
+        }
+    }
+}
+
+/// Message Batches API（批处理任务在后台执行，结果以 JSONL 保存在本地目录）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchConfig {
+    /// 批处理任务元数据与结果的保存目录
+    #[serde(default = "default_batches_dir")]
+    pub dir: String,
+
+    /// 所有批处理任务合计同时执行的请求数上限
+    #[serde(default = "default_batch_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_batches_dir() -> String {
+    "batches".to_string()
+}
+
+fn default_batch_concurrency() -> usize {
+    4
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_batches_dir(),
+            concurrency: default_batch_concurrency(),
+        }
+    }
+}
+
+/// MCP（Model Context Protocol）服务端，向支持 MCP 的编辑器提供 `ask_claude` 等工具
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpServerConfig {
+    /// 是否启用 SSE 传输（`GET /v1/mcp/sse`）
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// `ask_claude` 未指定模型时使用的模型
+    #[serde(default = "default_mcp_model")]
+    pub default_model: String,
+}
+
+fn default_mcp_model() -> String {
+    "claude-sonnet-4-5-20250929".to_string()
+}
+
+impl Default for McpServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_model: default_mcp_model(),
+        }
+    }
+}
+
+/// 服务端执行的 MCP 工具：启动配置的 MCP 服务器，模型调用其工具时由 kiro-rs 执行并续写对话
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpClientConfig {
+    /// 是否启用
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 单个请求中工具执行与续写的最大轮数，超过后直接返回最后一轮的响应
+    #[serde(default = "default_mcp_max_rounds")]
+    pub max_rounds: u32,
+
+    /// 单次工具调用的超时（秒）
+    #[serde(default = "default_mcp_tool_timeout_secs")]
+    pub tool_timeout_secs: u64,
+
+    /// MCP 服务器列表（stdio 传输）
+    #[serde(default)]
+    pub servers: Vec<McpServerEntry>,
+}
+
+fn default_mcp_max_rounds() -> u32 {
+    8
+}
+
+fn default_mcp_tool_timeout_secs() -> u64 {
+    60
+}
+
+impl Default for McpClientConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_rounds: default_mcp_max_rounds(),
+            tool_timeout_secs: default_mcp_tool_timeout_secs(),
+            servers: Vec::new(),
+        }
+    }
+}
+
+/// 以子进程方式启动的 MCP 服务器
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpServerEntry {
+    /// 服务器名称（出现在 `mcp_tool_use` 内容块的 `server_name` 中）
+    pub name: String,
+
+    /// 可执行文件
+    pub command: String,
+
+    /// 命令行参数
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// 额外的环境变量
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// `/readyz` 上游探测：逐个凭据调用额度查询接口确认其当前可用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessProbeConfig {
+    /// 每次 `/readyz` 都进行探测（关闭时仅 `?probe=true` 触发）
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// 探测结果的缓存时间（秒），期间的 `/readyz` 直接返回缓存结果
+    #[serde(default = "default_readiness_probe_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// 单个凭据探测的超时（秒）
+    #[serde(default = "default_readiness_probe_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_shutdown_grace_period_secs() -> u64 {
+    30
+}
+
+fn default_readiness_probe_ttl_secs() -> u64 {
+    30
+}
+
+fn default_readiness_probe_timeout_secs() -> u64 {
+    10
+}
+
+impl Default for ReadinessProbeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_secs: default_readiness_probe_ttl_secs(),
+            timeout_secs: default_readiness_probe_timeout_secs(),
+        }
+    }
+}
+
+/// `GET /v1/models` 公布的模型
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelEntry {
+    /// 模型 ID（客户端请求时使用的名称）
+    pub id: String,
+
+    /// 显示名称（未配置时使用 ID）
+    #[serde(default)]
+    pub display_name: Option<String>,
+
+    /// 最大输出 tokens
+    #[serde(default = "default_model_max_tokens")]
+    pub max_tokens: i32,
+
+    /// 发布时间（Unix 秒）
+    #[serde(default)]
+    pub created: i64,
+}
+
+fn default_model_max_tokens() -> i32 {
+    32000
+}
+
+fn default_models() -> Vec<ModelEntry> {
+    [
+        (
+            "claude-sonnet-4-5-20250929",
+            "Claude Sonnet 4.5",
+            1727568000,
+        ),
+        ("claude-opus-4-5-20251101", "Claude Opus 4.5", 1730419200),
+        ("claude-haiku-4-5-20251001", "Claude Haiku 4.5", 1727740800),
+    ]
+    .into_iter()
+    .map(|(id, display_name, created)| ModelEntry {
+        id: id.to_string(),
+        display_name: Some(display_name.to_string()),
+        max_tokens: default_model_max_tokens(),
+        created,
+    })
+    .collect()
+}
+
+/// 预置的一轮 user / assistant 对话
+///
+/// 用于为依赖特定工具调用约定的客户端预热上下文
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntheticTurn {
+    pub user: String,
+    pub assistant: String,
+}
+
+/// 启用 `opusPromptInjection` 时内置的系统提示词模板键
+pub const OPUS_TEMPLATE_KEY: &str = "opus";
+
+/// 按模型匹配的系统提示词模板
+///
+/// 文本中可使用 `{date}`（当前 UTC 日期）和 `{model}`（请求的模型名）变量
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemPromptTemplate {
+    /// 插入到系统消息之前的文本
+    #[serde(default)]
+    pub prepend: Option<String>,
+    /// 追加到系统消息之后的文本
+    #[serde(default)]
+    pub append: Option<String>,
+}
+
+/// 请求改写规则
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestRule {
+    /// 规则名称（用于日志）
+    #[serde(default)]
+    pub name: String,
+    /// 匹配条件（未设置的条件视为满足）
+    #[serde(default, rename = "match")]
+    pub matcher: RuleMatch,
+    /// 命中后执行的动作
+    #[serde(default)]
+    pub actions: RuleActions,
+}
+
+/// 请求改写规则的匹配条件，所有已设置的条件都满足时命中
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleMatch {
+    /// 模型名片段（不区分大小写，`*` 匹配所有模型）
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 请求头（名称 -> 值，值不区分大小写，`*` 表示只要求存在该请求头）
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// 客户端 API Key 名称
+    #[serde(default)]
+    pub client_key: Option<String>,
+}
+
+/// 请求改写规则的动作
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleActions {
+    /// 插入到系统消息最前面的文本
+    #[serde(default)]
+    pub inject_system: Option<String>,
+    /// 移除的工具名称（`*` 移除所有工具）
+    #[serde(default)]
+    pub strip_tools: Vec<String>,
+    /// 强制 thinking 预算（`0` 关闭 thinking）
+    #[serde(default)]
+    pub thinking_budget: Option<i32>,
+    /// max_tokens 上限
+    #[serde(default)]
+    pub max_tokens: Option<i32>,
+}
+
+/// 客户端 API Key
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientApiKey {
+    /// 名称（唯一，用于日志与用量归属）
+    pub name: String,
+    pub key: String,
+    #[serde(default)]
+    pub disabled: bool,
+    /// 速率限制（未配置时不限制）
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// 凭据分组（配置后该 Key 的请求只使用带有该标签的凭据，忽略 `x-kiro-group` 请求头）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// 优先级：负载较高时优先处理高优先级 Key 的请求，低优先级请求更早被拒绝
+    #[serde(default, skip_serializing_if = "ClientPriority::is_normal")]
+    pub priority: ClientPriority,
+    /// 每日 / 每月 token 预算（未配置时不限制）
+    #[serde(default, skip_serializing_if = "TokenQuota::is_unlimited")]
+    pub quota: TokenQuota,
+    /// 是否允许使用服务端执行的 MCP 工具（`mcpClient`，默认不允许）
+    #[serde(default)]
+    pub mcp_tools: bool,
+    /// 是否允许使用 `x-kiro-model-override` 请求头（默认不允许，`allowModelOverrideHeader` 开启时对所有 Key 开放）
+    #[serde(default)]
+    pub model_override: bool,
+}
+
+/// 客户端 API Key 的 token 预算（输入 + 输出，按 UTC 自然日 / 自然月计算，未配置的周期不限制）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenQuota {
+    /// 每日 tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_tokens: Option<u64>,
+    /// 每月 tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_tokens: Option<u64>,
+}
+
+impl TokenQuota {
+    pub fn is_unlimited(&self) -> bool {
+        self.daily_tokens.is_none() && self.monthly_tokens.is_none()
+    }
+}
+
+/// 客户端 API Key 优先级
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientPriority {
+    /// 交互式使用等延迟敏感的调用方
+    High,
+    #[default]
+    Normal,
+    /// 批处理等可延后的调用方
+    Low,
+}
+
+impl ClientPriority {
+    /// 所有优先级，从高到低
+    pub const ALL: [ClientPriority; 3] = [Self::High, Self::Normal, Self::Low];
+
+    /// 在 [`Self::ALL`] 中的位置（0 为最高）
+    pub fn rank(self) -> usize {
+        self as usize
+    }
+
+    fn is_normal(&self) -> bool {
+        *self == Self::Normal
+    }
+}
+
+/// 客户端 API Key 速率限制（未配置的维度不限制）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitConfig {
+    /// 每分钟请求数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    /// 每分钟输入 + 输出 tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_minute: Option<u32>,
+}
+
+fn default_allow_inject_header() -> bool {
+    true
+}
+
+fn default_sticky_session_path() -> String {
+    "sticky_sessions.json".to_string()
+}
+
+fn default_storage_path() -> String {
+    "kiro-rs.db".to_string()
+}
+
+fn default_files_dir() -> String {
+    "files".to_string()
 }
 
 fn default_host() -> String {
@@ -92,9 +1317,11 @@ fn default_kiro_version() -> String {
     "0.8.0".to_string()
 }
 
+/// 未配置 `systemVersion` 时随机选用的系统版本
+pub const DEFAULT_SYSTEM_VERSIONS: &[&str] = &["darwin#24.6.0", "win32#10.0.22631"];
+
 fn default_system_version() -> String {
-    const SYSTEM_VERSIONS: &[&str] = &["darwin#24.6.0", "win32#10.0.22631"];
-    SYSTEM_VERSIONS[fastrand::usize(..SYSTEM_VERSIONS.len())].to_string()
+    DEFAULT_SYSTEM_VERSIONS[fastrand::usize(..DEFAULT_SYSTEM_VERSIONS.len())].to_string()
 }
 
 fn default_node_version() -> String {
@@ -105,6 +1332,10 @@ fn default_count_tokens_auth_type() -> String {
     "x-api-key".to_string()
 }
 
+fn default_otel_service_name() -> String {
+    "kiro-rs".to_string()
+}
+
 fn default_tls_backend() -> TlsBackend {
     TlsBackend::Rustls
 }
@@ -114,20 +1345,80 @@ impl Default for Config {
         Self {
             host: default_host(),
             port: default_port(),
+            ops_port: None,
+            ops_host: None,
+            readiness_probe: ReadinessProbeConfig::default(),
+            shutdown_grace_period_secs: default_shutdown_grace_period_secs(),
+            otlp_endpoint: None,
+            otel_service_name: default_otel_service_name(),
             region: default_region(),
             kiro_version: default_kiro_version(),
             machine_id: None,
             api_key: None,
+            api_keys: Vec::new(),
             system_version: default_system_version(),
             node_version: default_node_version(),
             tls_backend: default_tls_backend(),
             count_tokens_api_url: None,
             count_tokens_api_key: None,
             count_tokens_auth_type: default_count_tokens_auth_type(),
+            tokenizer_url: None,
+            tokenizer_sha256: None,
+            tokenizer_path: None,
+            model_tokenizers: Vec::new(),
             proxy_url: None,
             proxy_username: None,
             proxy_password: None,
             admin_api_key: None,
+            alert_webhook_url: None,
+            storage_backend: StorageBackend::default(),
+            storage_path: default_storage_path(),
+            files_dir: default_files_dir(),
+            storage_url: None,
+            prompt_profiles: HashMap::new(),
+            allow_inject_header: default_allow_inject_header(),
+            allow_model_override_header: false,
+            opus_prompt_injection: false,
+            opus_prompt_file: None,
+            opus_prompt: None,
+            synthetic_history: Vec::new(),
+            resilience: ResilienceConfig::default(),
+            logging: LoggingConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            response_cache: ResponseCacheConfig::default(),
+            batches: BatchConfig::default(),
+            audit_log: AuditLogConfig::default(),
+            admission: AdmissionConfig::default(),
+            context_summary: ContextSummaryConfig::default(),
+            fallback_backends: Vec::new(),
+            mcp_server: McpServerConfig::default(),
+            mcp_client: McpClientConfig::default(),
+            models: default_models(),
+            request_validation: RequestValidation::default(),
+            scheduling_strategy: SchedulingStrategy::default(),
+            sticky_session_ttl_secs: default_sticky_session_ttl_secs(),
+            sticky_session_path: default_sticky_session_path(),
+            balance_poll_interval_secs: default_balance_poll_interval_secs(),
+            balance_history_days: default_balance_history_days(),
+            balance_alert_threshold_percent: None,
+            token_refresh_margin_secs: default_token_refresh_margin_secs(),
+            expose_credential_ids: false,
+            stream_dedup_min_overlap: 0,
+            response_footers: HashMap::new(),
+            footer_opt_out_keys: Vec::new(),
+            system_prompt_templates: HashMap::new(),
+            request_rules: Vec::new(),
+            image_dedupe: default_image_dedupe(),
+            image_downscale: ImageDownscaleConfig::default(),
+            image_fetch: ImageFetchConfig::default(),
+            auto_compact_history: false,
+            client_identity_salt: None,
+            decoder_max_buffer_bytes: default_decoder_max_buffer_bytes(),
+            decoder_overflow_policy: OverflowPolicy::default(),
+            websearch_mode: WebSearchMode::default(),
+            websearch_key_overrides: HashMap::new(),
+            upstream_headers: BTreeMap::new(),
         }
     }
 }
@@ -138,6 +1429,23 @@ impl Config {
         "config.json"
     }
 
+    /// 将专业助手提示词加入为内置的 `opus` 系统提示词模板（不覆盖已配置的同名模板）
+    fn install_opus_template(&mut self) {
+        if !self.opus_prompt_injection {
+            return;
+        }
+        let prompt = self
+            .opus_prompt
+            .clone()
+            .unwrap_or_else(|| PROFESSIONAL_SYSTEM_PROMPT.to_string());
+        self.system_prompt_templates
+            .entry(OPUS_TEMPLATE_KEY.to_string())
+            .or_insert_with(|| SystemPromptTemplate {
+                prepend: Some(prompt),
+                append: None,
+            });
+    }
+
     /// 从文件加载配置
     pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
         let path = path.as_ref();
@@ -147,7 +1455,15 @@ impl Config {
         }
 
         let content = fs::read_to_string(path)?;
-        let config: Config = serde_json::from_str(&content)?;
+        let mut config: Config = serde_json::from_str(&content)?;
+        if let Some(prompt_file) = &config.opus_prompt_file {
+            let prompt = fs::read_to_string(prompt_file)
+                .with_context(|| format!("读取 Opus 提示词文件失败: {}", prompt_file))?;
+            config.opus_prompt = Some(prompt);
+        }
+        config.install_opus_template();
+        // 在构建任何凭据并发上限之前拒绝无效的取值范围（重新加载时保留当前配置）
+        config.concurrency.validate()?;
         Ok(config)
     }
 }
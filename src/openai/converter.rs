@@ -0,0 +1,308 @@
+//! OpenAI → Anthropic 请求转换
+//!
+//! 将 Chat Completions 请求转换为 Anthropic Messages 请求，
+//! 之后复用 `anthropic::converter` 生成 Kiro ConversationState
+
+use serde_json::{Value, json};
+
+use crate::anthropic::types::{Message, MessagesRequest, SystemMessage, Tool};
+
+use super::types::{ChatCompletionRequest, ChatMessage, ChatTool};
+
+/// 未指定输出上限时使用的默认值
+pub const DEFAULT_MAX_TOKENS: i32 = 8192;
+
+/// 转换 Chat Completions 请求
+pub fn to_messages_request(req: &ChatCompletionRequest) -> MessagesRequest {
+    let mut system = Vec::new();
+    let mut messages = Vec::new();
+
+    for msg in &req.messages {
+        match msg.role.as_str() {
+            "system" | "developer" => {
+                let text = content_text(msg.content.as_ref());
+                if !text.is_empty() {
+                    system.push(SystemMessage {
+                        text,
+                        cache_control: None,
+                    });
+                }
+            }
+            "assistant" => messages.push(convert_assistant_message(msg)),
+            "tool" => messages.push(convert_tool_message(msg)),
+            _ => messages.push(Message {
+                role: "user".to_string(),
+                content: convert_user_content(msg.content.as_ref()),
+            }),
+        }
+    }
+
+    MessagesRequest {
+        model: req.model.clone(),
+        max_tokens: req.output_limit().unwrap_or(DEFAULT_MAX_TOKENS),
+        messages,
+        stream: req.stream,
+        system: (!system.is_empty()).then_some(system),
+        tools: req
+            .tools
+            .as_ref()
+            .map(|tools| tools.iter().map(convert_tool).collect()),
+        tool_choice: req.tool_choice.as_ref().and_then(convert_tool_choice),
+        thinking: None,
+        metadata: None,
+        stop_sequences: req.stop_sequences(),
+        // OpenAI 温度取值范围为 0 ~ 2，Anthropic 为 0 ~ 1，超出部分按上限处理
+        temperature: req.temperature.map(|t| t.clamp(0.0, 1.0)),
+        top_p: req.top_p,
+        top_k: None,
+    }
+}
+
+/// 将 Anthropic stop_reason 映射为 OpenAI finish_reason
+pub fn finish_reason(stop_reason: &str) -> &'static str {
+    match stop_reason {
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        _ => "stop",
+    }
+}
+
+/// 提取纯文本内容（string 或 text 片段数组）
+fn content_text(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// 转换 user 消息内容（text / image_url 片段）
+fn convert_user_content(content: Option<&Value>) -> Value {
+    let Some(Value::Array(parts)) = content else {
+        return Value::String(content_text(content));
+    };
+
+    let blocks: Vec<Value> = parts
+        .iter()
+        .filter_map(|part| match part.get("type").and_then(Value::as_str) {
+            Some("text") => part
+                .get("text")
+                .and_then(Value::as_str)
+                .map(|text| json!({"type": "text", "text": text})),
+            Some("image_url") => {
+                let url = part
+                    .get("image_url")
+                    .and_then(|i| i.get("url"))
+                    .and_then(Value::as_str)?;
+                let block = parse_data_url(url).or_else(|| remote_image(url));
+                if block.is_none() {
+                    tracing::warn!("仅支持 data URL 或 http(s) 地址的图片，已忽略: {:.64}", url);
+                }
+                block
+            }
+            _ => None,
+        })
+        .collect();
+    Value::Array(blocks)
+}
+
+/// 解析 `data:<media_type>;base64,<data>` 格式的图片
+fn parse_data_url(url: &str) -> Option<Value> {
+    let rest = url.strip_prefix("data:")?;
+    let (meta, data) = rest.split_once(',')?;
+    let media_type = meta.strip_suffix(";base64")?;
+    Some(json!({
+        "type": "image",
+        "source": {"type": "base64", "media_type": media_type, "data": data}
+    }))
+}
+
+/// http(s) 地址的图片转换为 URL 来源，由请求转换前的图片下载处理
+fn remote_image(url: &str) -> Option<Value> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return None;
+    }
+    Some(json!({"type": "image", "source": {"type": "url", "url": url}}))
+}
+
+/// 转换 assistant 消息（文本 + tool_calls）
+fn convert_assistant_message(msg: &ChatMessage) -> Message {
+    let mut blocks = Vec::new();
+    let text = content_text(msg.content.as_ref());
+    if !text.is_empty() {
+        blocks.push(json!({"type": "text", "text": text}));
+    }
+    for call in msg.tool_calls.iter().flatten() {
+        let input: Value = serde_json::from_str(&call.function.arguments).unwrap_or_else(|e| {
+            tracing::warn!("工具调用参数 JSON 解析失败: {}, id: {}", e, call.id);
+            json!({})
+        });
+        blocks.push(json!({
+            "type": "tool_use",
+            "id": call.id,
+            "name": call.function.name,
+            "input": input
+        }));
+    }
+    Message {
+        role: "assistant".to_string(),
+        content: Value::Array(blocks),
+    }
+}
+
+/// 转换 tool 消息为携带 tool_result 的 user 消息
+fn convert_tool_message(msg: &ChatMessage) -> Message {
+    Message {
+        role: "user".to_string(),
+        content: json!([{
+            "type": "tool_result",
+            "tool_use_id": msg.tool_call_id.clone().unwrap_or_default(),
+            "content": content_text(msg.content.as_ref())
+        }]),
+    }
+}
+
+/// 转换工具定义
+fn convert_tool(tool: &ChatTool) -> Tool {
+    let input_schema = match &tool.function.parameters {
+        Some(Value::Object(map)) => map.clone().into_iter().collect(),
+        _ => [("type".to_string(), json!("object"))]
+            .into_iter()
+            .collect(),
+    };
+    Tool {
+        tool_type: None,
+        name: tool.function.name.clone(),
+        description: tool.function.description.clone(),
+        input_schema,
+        max_uses: None,
+        cache_control: None,
+    }
+}
+
+/// 转换 tool_choice（auto / none / required / 指定函数）
+fn convert_tool_choice(choice: &Value) -> Option<Value> {
+    match choice {
+        Value::String(s) => match s.as_str() {
+            "auto" => Some(json!({"type": "auto"})),
+            "none" => Some(json!({"type": "none"})),
+            "required" => Some(json!({"type": "any"})),
+            _ => None,
+        },
+        Value::Object(_) => choice
+            .get("function")
+            .and_then(|f| f.get("name"))
+            .and_then(Value::as_str)
+            .map(|name| json!({"type": "tool", "name": name})),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: Value) -> ChatCompletionRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_convert_basic_conversation() {
+        let req = parse(json!({
+            "model": "claude-sonnet-4-5",
+            "max_completion_tokens": 512,
+            "messages": [
+                {"role": "system", "content": "You are helpful."},
+                {"role": "user", "content": "Hi"}
+            ]
+        }));
+        let converted = to_messages_request(&req);
+        assert_eq!(converted.max_tokens, 512);
+        assert_eq!(converted.system.unwrap()[0].text, "You are helpful.");
+        assert_eq!(converted.messages.len(), 1);
+        assert_eq!(converted.messages[0].role, "user");
+        assert_eq!(converted.messages[0].content, json!("Hi"));
+    }
+
+    #[test]
+    fn test_convert_stop() {
+        let base = json!({"model": "claude-sonnet-4-5", "messages": []});
+
+        let mut value = base.clone();
+        value["stop"] = json!("END");
+        let converted = to_messages_request(&parse(value));
+        assert_eq!(converted.stop_sequences, Some(vec!["END".to_string()]));
+
+        let mut value = base.clone();
+        value["stop"] = json!(["a", "b"]);
+        let converted = to_messages_request(&parse(value));
+        assert_eq!(converted.stop_sequences.unwrap().len(), 2);
+
+        assert!(to_messages_request(&parse(base)).stop_sequences.is_none());
+    }
+
+    #[test]
+    fn test_convert_tool_round_trip() {
+        let req = parse(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "user", "content": "Read the file"},
+                {"role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "read", "arguments": "{\"path\":\"/a\"}"}
+                }]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "file content"}
+            ],
+            "tools": [{"type": "function", "function": {
+                "name": "read",
+                "description": "Read a file",
+                "parameters": {"type": "object", "properties": {"path": {"type": "string"}}}
+            }}],
+            "tool_choice": "required"
+        }));
+        let converted = to_messages_request(&req);
+        assert_eq!(converted.max_tokens, DEFAULT_MAX_TOKENS);
+
+        let assistant = &converted.messages[1].content[0];
+        assert_eq!(assistant["type"], "tool_use");
+        assert_eq!(assistant["input"]["path"], "/a");
+
+        let tool_result = &converted.messages[2].content[0];
+        assert_eq!(converted.messages[2].role, "user");
+        assert_eq!(tool_result["tool_use_id"], "call_1");
+
+        let tools = converted.tools.unwrap();
+        assert_eq!(tools[0].name, "read");
+        assert!(tools[0].input_schema.contains_key("properties"));
+        assert_eq!(converted.tool_choice, Some(json!({"type": "any"})));
+    }
+
+    #[test]
+    fn test_convert_image_data_url() {
+        let content = json!([
+            {"type": "text", "text": "What is this?"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+            {"type": "image_url", "image_url": {"url": "ftp://example.com/b.png"}}
+        ]);
+        let blocks = convert_user_content(Some(&content));
+        let blocks = blocks.as_array().unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[1]["source"]["media_type"], "image/png");
+        assert_eq!(blocks[1]["source"]["data"], "AAAA");
+        assert_eq!(blocks[2]["source"]["type"], "url");
+        assert_eq!(blocks[2]["source"]["url"], "https://example.com/a.png");
+    }
+
+    #[test]
+    fn test_finish_reason() {
+        assert_eq!(finish_reason("end_turn"), "stop");
+        assert_eq!(finish_reason("max_tokens"), "length");
+        assert_eq!(finish_reason("tool_use"), "tool_calls");
+    }
+}
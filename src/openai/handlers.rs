@@ -0,0 +1,629 @@
+//! OpenAI API Handler 函数
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json as JsonExtractor,
+    body::Body,
+    extract::{Extension, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use bytes::Bytes;
+use futures::{Stream, StreamExt, stream};
+use tokio::time::interval;
+
+use crate::anthropic::converter::{
+    AUTO_COMPACT_MAX_BYTES, ConversionError, compact_history, convert_request,
+};
+use crate::anthropic::handlers::{
+    apply_failover_headers, failover_sse_comment, feed_decoder, stream_timeout_response,
+    upstream_api_error_response, upstream_unavailable_response,
+};
+use crate::anthropic::middleware::AppState;
+use crate::anthropic::stream::{SseEvent, StreamContext};
+use crate::anthropic::types::ErrorResponse;
+use crate::anthropic::{
+    credential_group, footer, identity, image_dedupe, image_downscale, image_fetch, injection,
+    model_config, model_override, response_cache, system_prompt,
+};
+use crate::common::api_keys::ClientKey;
+use crate::common::audit;
+use crate::common::disconnect;
+use crate::common::metrics;
+use crate::common::rate_limit::KeyRateLimiter;
+use crate::common::telemetry;
+use crate::kiro::error::{StreamTimeout, UpstreamUnavailableError};
+use crate::kiro::model::events::Event;
+use crate::kiro::model::requests::kiro::KiroRequest;
+use crate::kiro::parser::decoder::{DEFAULT_BUFFER_CAPACITY, EventStreamDecoder};
+use crate::kiro::parser::error::ParseError;
+use crate::kiro::provider::{FailoverInfo, KiroProvider, StreamResponse, take_connection_guard};
+use crate::kiro::stream_failover::BodyStream;
+use crate::kiro::token_manager::{ConnectionGuard, Routing};
+use crate::storage::ledger::UsageLedger;
+use crate::token;
+
+use super::converter;
+use super::stream::{ChunkTranslator, SSE_DONE, to_sse_data};
+use super::types::ChatCompletionRequest;
+
+/// 流式保活注释的发送间隔（秒）
+const PING_INTERVAL_SECS: u64 = 25;
+
+/// POST /v1/chat/completions
+///
+/// OpenAI 兼容的对话接口
+#[tracing::instrument(
+    skip_all,
+    fields(model = %payload.model, stream = payload.stream, credential_id = tracing::field::Empty)
+)]
+pub async fn chat_completions(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    JsonExtractor(payload): JsonExtractor<ChatCompletionRequest>,
+) -> Response {
+    tracing::info!(
+        model = %payload.model,
+        stream = %payload.stream,
+        message_count = %payload.messages.len(),
+        "Received POST /v1/chat/completions request"
+    );
+    audit::note_request(&payload.model, payload.stream);
+    let provider = match &state.kiro_provider {
+        Some(p) => p.clone(),
+        None => {
+            tracing::error!("KiroProvider 未配置");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse::new(
+                    "service_unavailable",
+                    "Kiro API provider not configured",
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    let mut request = converter::to_messages_request(&payload);
+    let config = provider.token_manager().config();
+
+    // 下载 URL 来源的图片
+    if let Err(e) = image_fetch::resolve_image_urls(&config, &mut request).await {
+        tracing::warn!("下载 URL 图片失败: {}", e);
+        return invalid_request(e.to_string());
+    }
+
+    // 解析提示词注入选择（与 /v1/messages 规则一致）
+    let selection = if config.allow_inject_header {
+        injection::parse_header(&headers)
+    } else {
+        None
+    };
+    let injected_prompt = match injection::resolve_prompt(selection.as_ref(), &config) {
+        Ok(prompt) => prompt,
+        Err(name) => {
+            return invalid_request(format!(
+                "Unknown prompt profile in {}: {}",
+                injection::INJECT_HEADER,
+                name
+            ));
+        }
+    };
+
+    let group =
+        match credential_group::resolve(client.as_deref(), &headers, provider.token_manager()) {
+            Ok(group) => group,
+            Err(group) => {
+                tracing::warn!("未知的凭据分组: {}", group);
+                return invalid_request(format!(
+                    "Unknown credential group in {}: {}",
+                    credential_group::GROUP_HEADER,
+                    group
+                ));
+            }
+        };
+
+    let model_override = model_override::resolve(&config, client.as_deref(), &headers);
+    if let Some(model_id) = &model_override {
+        tracing::info!("模型覆盖请求头生效: {} -> {}", request.model, model_id);
+    }
+
+    let conversion_result = match convert_request(
+        &request,
+        model_override.as_deref(),
+        injected_prompt.as_deref(),
+        system_prompt::resolve(&config, &request.model, selection.as_ref()),
+        &config.synthetic_history,
+    ) {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::warn!("请求转换失败: {}", e);
+            return match e {
+                ConversionError::UnsupportedModel(model) => {
+                    invalid_request(format!("模型不支持: {}", model))
+                }
+                ConversionError::EmptyMessages => invalid_request("消息列表为空".to_string()),
+                ConversionError::UnknownToolChoice(_) | ConversionError::InvalidParameter(_) => {
+                    invalid_request(e.to_string())
+                }
+            };
+        }
+    };
+
+    let session_id = conversion_result.session_id;
+    let mut pinned_history = conversion_result.pinned_history;
+    let mut conversation_state = conversion_result.conversation_state;
+    let routing = Routing {
+        session: session_id.as_deref(),
+        group: group.as_deref(),
+        priority: client.as_ref().map(|c| c.priority).unwrap_or_default(),
+        ..Routing::default()
+    };
+    if let Some(tag) = identity::resolve(&config, &headers) {
+        conversation_state.agent_continuation_id = Some(identity::tagged_continuation_id(&tag));
+    }
+    if config.image_dedupe {
+        image_dedupe::dedupe_images(&mut conversation_state);
+    }
+    image_downscale::downscale_images(&mut conversation_state, &config.image_downscale).await;
+    if let Some(summarizer) = &state.summarizer {
+        let replaced = summarizer
+            .summarize(
+                &provider,
+                &mut conversation_state,
+                pinned_history,
+                &config.context_summary,
+                state.profile_arn.as_deref(),
+                routing,
+            )
+            .await;
+        if replaced > 0 {
+            // 摘要消息与系统提示词一样在自动压缩时保留
+            pinned_history += 2;
+        }
+    }
+    if config.auto_compact_history {
+        let dropped = compact_history(
+            &mut conversation_state,
+            pinned_history,
+            AUTO_COMPACT_MAX_BYTES,
+        );
+        if dropped > 0 {
+            tracing::info!("请求体接近上限，已自动丢弃 {} 条最早的历史消息", dropped);
+        }
+    }
+    let kiro_request = KiroRequest {
+        conversation_state,
+        profile_arn: state.profile_arn.clone(),
+    };
+    let request_body = match serde_json::to_string(&kiro_request) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("序列化请求失败: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(
+                    "internal_error",
+                    format!("序列化请求失败: {}", e),
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    let input_tokens = token::count_all_tokens(
+        &request.model,
+        request.system.as_deref(),
+        &request.messages,
+        request.tools.as_deref(),
+    )
+    .await as i32;
+
+    let footer = footer::resolve(&config, &request.model, &headers).map(str::to_string);
+    let params = CompletionParams {
+        model: &request.model,
+        routing,
+        input_tokens,
+        max_tokens: request.max_tokens,
+        stop_sequences: request.stop_sequences.clone().unwrap_or_default(),
+        footer: footer.as_deref(),
+        usage_ledger: state.usage_ledger_for(client.as_deref()),
+        rate_limiter: client.as_deref().map(|c| c.limiter.clone()),
+    };
+    if payload.stream {
+        return handle_stream_request(provider, &request_body, params, payload.include_usage())
+            .await;
+    }
+
+    // 相同请求命中响应缓存时不再调用上游
+    let cache_params = serde_json::json!({
+        "client": client.as_ref().map(|c| c.name.as_str()),
+        "model": request.model,
+        "maxTokens": request.max_tokens,
+        "stopSequences": params.stop_sequences,
+        "footer": params.footer,
+    });
+    let response_cache = response_cache::resolve(
+        state.response_cache.as_ref(),
+        &headers,
+        "chat/completions",
+        &kiro_request.conversation_state,
+        &cache_params,
+    );
+    if let Some((cache, key)) = &response_cache
+        && let Some(cached) = cache.lookup(key)
+    {
+        return cached;
+    }
+    let response = handle_non_stream_request(provider, &request_body, params).await;
+    match response_cache {
+        Some((cache, key)) => cache.store(&key, response).await,
+        None => response,
+    }
+}
+
+/// 单次补全请求的公共参数
+struct CompletionParams<'a> {
+    model: &'a str,
+    /// 凭据选择约束（会话粘性绑定、分组）
+    routing: Routing<'a>,
+    input_tokens: i32,
+    max_tokens: i32,
+    stop_sequences: Vec<String>,
+    footer: Option<&'a str>,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    /// 客户端 Key 的速率限制器（记录用量时扣减 tokens）
+    rate_limiter: Option<Arc<KeyRateLimiter>>,
+}
+
+impl CompletionParams<'_> {
+    /// 创建流处理上下文（复用 Anthropic 流式事件处理）
+    fn stream_context(&self, provider: &KiroProvider) -> StreamContext {
+        let config = provider.token_manager().config();
+        StreamContext::new_with_thinking(self.model, self.input_tokens, false)
+            .with_max_tokens(self.max_tokens)
+            .with_context_window(model_config::get_context_window_size(self.model))
+            .with_stop_sequences(self.stop_sequences.clone())
+            .with_overlap_dedup(config.stream_dedup_min_overlap)
+            .with_footer(self.footer)
+    }
+}
+
+/// 按配置创建上游事件流解码器
+fn event_decoder(provider: &KiroProvider) -> EventStreamDecoder {
+    let config = provider.token_manager().config();
+    EventStreamDecoder::with_limits(
+        config.decoder_max_buffer_bytes,
+        config.decoder_overflow_policy,
+    )
+}
+
+/// 生成 400 错误响应
+fn invalid_request(message: String) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse::new("invalid_request_error", message)),
+    )
+        .into_response()
+}
+
+/// 将上游调用失败转换为错误响应
+fn upstream_error_response(e: &anyhow::Error) -> Response {
+    if let Some(err) = e.downcast_ref::<UpstreamUnavailableError>() {
+        return upstream_unavailable_response(err);
+    }
+    if let Some(timeout) = e.downcast_ref::<StreamTimeout>() {
+        return stream_timeout_response(timeout);
+    }
+    upstream_api_error_response(e)
+}
+
+/// 解码上游字节并交给 StreamContext 处理（缓冲区溢出时返回错误）
+fn decode_events(
+    decoder: &mut EventStreamDecoder,
+    ctx: &mut StreamContext,
+    chunk: &[u8],
+) -> Result<Vec<SseEvent>, ParseError> {
+    feed_decoder(decoder, chunk)?;
+    let mut events = Vec::new();
+    for result in decoder.decode_iter() {
+        match result {
+            Ok(frame) => {
+                if let Ok(event) = Event::from_frame(frame) {
+                    events.extend(ctx.process_kiro_event(&event));
+                }
+            }
+            Err(e) => tracing::warn!("解码事件失败: {}", e),
+        }
+    }
+    Ok(events)
+}
+
+/// 记录用量（写入用量账本、向客户端 Key 的速率限制器扣减 tokens 并输出日志）
+fn record_usage(
+    usage_ledger: Option<&Arc<UsageLedger>>,
+    rate_limiter: Option<&Arc<KeyRateLimiter>>,
+    ctx: &StreamContext,
+) {
+    let input_tokens = ctx.final_input_tokens();
+    tracing::info!(input_tokens, output_tokens = ctx.output_tokens, "响应完成");
+    if let Some(ledger) = usage_ledger {
+        ledger.record(&ctx.model, input_tokens, ctx.output_tokens);
+    }
+    if let Some(limiter) = rate_limiter {
+        limiter.record_tokens(input_tokens.max(0) as i64 + ctx.output_tokens.max(0) as i64);
+    }
+}
+
+/// 处理流式请求
+async fn handle_stream_request(
+    provider: Arc<KiroProvider>,
+    request_body: &str,
+    params: CompletionParams<'_>,
+    include_usage: bool,
+) -> Response {
+    let StreamResponse {
+        body,
+        guard,
+        failover,
+    } = match provider.call_api_stream(request_body, params.routing).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::error!("Kiro API 调用失败: {}", e);
+            return upstream_error_response(&e);
+        }
+    };
+    tracing::Span::current().record("credential_id", failover.credential_id);
+    audit::note_credential(failover.credential_id);
+    let expose_ids = provider.token_manager().config().expose_credential_ids;
+    let usage_ledger = params
+        .usage_ledger
+        .as_ref()
+        .map(|ledger| ledger.for_credential(failover.credential_id));
+
+    let ctx = params.stream_context(&provider);
+    let translator = ChunkTranslator::new(params.model);
+    let failover_comment = failover_sse_comment(failover, expose_ids).map(Ok);
+    let stream = stream::iter(failover_comment).chain(telemetry::instrument_stream(
+        create_chat_stream(
+            body,
+            ChatStreamState {
+                ctx,
+                translator,
+                decoder: event_decoder(&provider),
+                guard: Some(guard),
+                usage_ledger,
+                rate_limiter: params.rate_limiter.clone(),
+                include_usage,
+                credential_id: failover.credential_id,
+                started: Instant::now(),
+            },
+        ),
+        tracing::info_span!("sse_stream"),
+    ));
+    let stream = disconnect::on_disconnect(
+        stream,
+        format!("chat completion（凭据 #{}）", failover.credential_id),
+    );
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::CONNECTION, "keep-alive")
+        .body(Body::from_stream(stream))
+        .unwrap();
+    apply_failover_headers(response.headers_mut(), failover, expose_ids);
+    response
+}
+
+/// 流式转换状态
+struct ChatStreamState {
+    ctx: StreamContext,
+    translator: ChunkTranslator,
+    decoder: EventStreamDecoder,
+    /// 随流一起存活，流结束时释放连接计数
+    guard: Option<ConnectionGuard>,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    rate_limiter: Option<Arc<KeyRateLimiter>>,
+    include_usage: bool,
+    /// 输出吞吐量统计归属的凭据
+    credential_id: u64,
+    /// 输出开始时间（上游响应已读到首个内容事件）
+    started: Instant,
+}
+
+impl ChatStreamState {
+    /// 将 Anthropic 事件转换为 OpenAI SSE 数据
+    fn translate(&mut self, events: Vec<SseEvent>) -> String {
+        events
+            .iter()
+            .filter_map(|e| self.translator.translate(e))
+            .map(|chunk| to_sse_data(&chunk))
+            .collect()
+    }
+
+    /// 记录正常结束的流的输出吞吐量
+    fn record_throughput(&self) {
+        metrics::OUTPUT_THROUGHPUT.record(
+            &self.ctx.model,
+            self.credential_id,
+            self.ctx.output_tokens,
+            self.started.elapsed(),
+        );
+    }
+
+    /// 生成错误数据并结束流（不再发送 finish_reason 和 [DONE]）
+    fn abort(&mut self, error_type: &str, message: String) -> String {
+        record_usage(
+            self.usage_ledger.as_ref(),
+            self.rate_limiter.as_ref(),
+            &self.ctx,
+        );
+        self.guard = None;
+        let body = ErrorResponse::new(error_type, message);
+        format!(
+            "data: {}\n\n",
+            serde_json::to_string(&body).unwrap_or_default()
+        )
+    }
+
+    /// 生成结束数据（finish_reason、可选的 usage chunk 和 [DONE]）
+    fn finish(&mut self) -> String {
+        let final_events = self.ctx.generate_final_events();
+        let mut out = self.translate(final_events);
+        record_usage(
+            self.usage_ledger.as_ref(),
+            self.rate_limiter.as_ref(),
+            &self.ctx,
+        );
+        if self.include_usage {
+            out.push_str(&to_sse_data(&self.translator.usage_chunk()));
+        }
+        out.push_str(SSE_DONE);
+        self.guard = None;
+        out
+    }
+}
+
+/// 创建 OpenAI 格式的 SSE 流
+fn create_chat_stream(
+    body_stream: BodyStream<anyhow::Error>,
+    state: ChatStreamState,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let ping_interval = interval(Duration::from_secs(PING_INTERVAL_SECS));
+
+    stream::unfold(
+        (body_stream, ping_interval, state, false),
+        |(mut body_stream, mut ping_interval, mut state, finished)| async move {
+            if finished {
+                return None;
+            }
+
+            tokio::select! {
+                chunk_result = body_stream.next() => {
+                    let (events, done) = match chunk_result {
+                        Some(Ok(chunk)) => {
+                            match decode_events(&mut state.decoder, &mut state.ctx, &chunk) {
+                                // 输出达到 max_tokens 上限或命中 stop 序列时立即结束
+                                Ok(events) => {
+                                    if state.ctx.output_finished {
+                                        state.record_throughput();
+                                    }
+                                    (events, state.ctx.output_finished)
+                                }
+                                Err(e) => {
+                                    let item = Some(Ok(Bytes::from(state.abort("api_error", e.to_string()))));
+                                    return Some((item, (body_stream, ping_interval, state, true)));
+                                }
+                            }
+                        }
+                        Some(Err(e)) => {
+                            tracing::error!("读取响应流失败: {}", e);
+                            // 上游停滞超时：发送错误数据并结束
+                            if let Some(timeout) = e.downcast_ref::<StreamTimeout>() {
+                                let data = state.abort("timeout_error", timeout.client_message());
+                                return Some((Some(Ok(Bytes::from(data))), (body_stream, ping_interval, state, true)));
+                            }
+                            (Vec::new(), true)
+                        }
+                        None => {
+                            state.record_throughput();
+                            (Vec::new(), true)
+                        }
+                    };
+                    let mut out = state.translate(events);
+                    if done {
+                        out.push_str(&state.finish());
+                    }
+                    let item = (!out.is_empty()).then(|| Ok(Bytes::from(out)));
+                    Some((item, (body_stream, ping_interval, state, done)))
+                }
+                // SSE 注释保活（客户端会忽略）
+                _ = ping_interval.tick() => {
+                    let item = Some(Ok(Bytes::from_static(b": ping\n\n")));
+                    Some((item, (body_stream, ping_interval, state, false)))
+                }
+            }
+        },
+    )
+    .filter_map(|item| async move { item })
+}
+
+/// 处理非流式请求
+async fn handle_non_stream_request(
+    provider: Arc<KiroProvider>,
+    request_body: &str,
+    params: CompletionParams<'_>,
+) -> Response {
+    let mut response = match provider.call_api(request_body, params.routing).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::error!("Kiro API 调用失败: {}", e);
+            return upstream_error_response(&e);
+        }
+    };
+    let failover = response
+        .extensions()
+        .get::<FailoverInfo>()
+        .copied()
+        .unwrap_or_default();
+    tracing::Span::current().record("credential_id", failover.credential_id);
+    audit::note_credential(failover.credential_id);
+    // 读取响应体期间仍计入该凭据的活跃连接数
+    let _guard = take_connection_guard(&mut response);
+
+    let body_bytes = match response.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("读取响应体失败: {}", e);
+            return (
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponse::new(
+                    "api_error",
+                    format!("读取响应失败: {}", e),
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    // 复用流式事件处理，再聚合为完整响应
+    let mut ctx = params.stream_context(&provider);
+    let mut events = ctx.generate_initial_events();
+    let mut decoder = event_decoder(&provider);
+    for chunk in body_bytes.chunks(DEFAULT_BUFFER_CAPACITY) {
+        match decode_events(&mut decoder, &mut ctx, chunk) {
+            Ok(decoded) => events.extend(decoded),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_GATEWAY,
+                    Json(ErrorResponse::new("api_error", e.to_string())),
+                )
+                    .into_response();
+            }
+        }
+    }
+    events.extend(ctx.generate_final_events());
+    let usage_ledger = params
+        .usage_ledger
+        .as_ref()
+        .map(|ledger| ledger.for_credential(failover.credential_id));
+    record_usage(usage_ledger.as_ref(), params.rate_limiter.as_ref(), &ctx);
+
+    let mut translator = ChunkTranslator::new(params.model);
+    let chunks = events
+        .iter()
+        .filter_map(|e| translator.translate(e))
+        .collect();
+    let completion = translator.aggregate(chunks);
+
+    let expose_ids = provider.token_manager().config().expose_credential_ids;
+    let mut response = Json(completion).into_response();
+    apply_failover_headers(response.headers_mut(), failover, expose_ids);
+    response
+}
@@ -0,0 +1,14 @@
+//! OpenAI API 兼容服务模块
+//!
+//! 提供与 OpenAI Chat Completions API 兼容的端点，供仅支持 OpenAI 协议的工具使用。
+//! 请求先转换为 Anthropic Messages 格式，复用 `anthropic` 模块的转换与流式处理逻辑。
+//!
+//! # 支持的端点
+//! - `POST /v1/chat/completions` - 创建对话补全（支持流式）
+
+mod converter;
+mod handlers;
+mod stream;
+pub mod types;
+
+pub use handlers::chat_completions;
@@ -0,0 +1,299 @@
+//! Anthropic SSE 事件 → OpenAI chunk 转换
+//!
+//! 上游事件先经 `anthropic::stream::StreamContext` 处理为 Anthropic 事件，
+//! 再在此转换为 OpenAI `chat.completion.chunk`；非流式响应由 chunk 聚合得到
+
+use std::collections::HashMap;
+
+use crate::anthropic::stream::SseEvent;
+
+use super::converter::finish_reason;
+use super::types::{
+    ChatCompletion, ChatCompletionChunk, Choice, ChunkChoice, Delta, FunctionCall,
+    FunctionCallDelta, ResponseMessage, ToolCall, ToolCallDelta, Usage,
+};
+
+/// 流式 chunk 转换器
+pub struct ChunkTranslator {
+    id: String,
+    created: i64,
+    model: String,
+    /// Anthropic 内容块索引 -> OpenAI tool_calls 索引
+    tool_indices: HashMap<i64, usize>,
+    /// message_delta 中携带的最终用量
+    usage: Option<Usage>,
+}
+
+impl ChunkTranslator {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            id: format!("chatcmpl-{}", uuid::Uuid::new_v4().simple()),
+            created: chrono::Utc::now().timestamp(),
+            model: model.into(),
+            tool_indices: HashMap::new(),
+            usage: None,
+        }
+    }
+
+    /// 转换单个 Anthropic 事件（不产生输出的事件返回 None）
+    pub fn translate(&mut self, event: &SseEvent) -> Option<ChatCompletionChunk> {
+        let data = &event.data;
+        match event.event.as_str() {
+            "message_start" => Some(self.chunk(
+                Delta {
+                    role: Some("assistant"),
+                    content: Some(String::new()),
+                    ..Default::default()
+                },
+                None,
+            )),
+            "content_block_start" => {
+                let block = &data["content_block"];
+                if block["type"] != "tool_use" {
+                    return None;
+                }
+                let index = self.tool_indices.len();
+                self.tool_indices
+                    .insert(data["index"].as_i64().unwrap_or_default(), index);
+                Some(self.chunk(
+                    Delta {
+                        tool_calls: vec![ToolCallDelta {
+                            index,
+                            id: block["id"].as_str().map(str::to_string),
+                            call_type: Some("function"),
+                            function: FunctionCallDelta {
+                                name: block["name"].as_str().map(str::to_string),
+                                arguments: Some(String::new()),
+                            },
+                        }],
+                        ..Default::default()
+                    },
+                    None,
+                ))
+            }
+            "content_block_delta" => {
+                let delta = &data["delta"];
+                let text = |key: &str| delta[key].as_str().map(str::to_string);
+                let delta = match delta["type"].as_str() {
+                    Some("text_delta") => Delta {
+                        content: text("text"),
+                        ..Default::default()
+                    },
+                    Some("thinking_delta") => Delta {
+                        reasoning_content: text("thinking").filter(|t| !t.is_empty()),
+                        ..Default::default()
+                    },
+                    Some("input_json_delta") => {
+                        let index = *self
+                            .tool_indices
+                            .get(&data["index"].as_i64().unwrap_or_default())?;
+                        Delta {
+                            tool_calls: vec![ToolCallDelta {
+                                index,
+                                id: None,
+                                call_type: None,
+                                function: FunctionCallDelta {
+                                    name: None,
+                                    arguments: text("partial_json"),
+                                },
+                            }],
+                            ..Default::default()
+                        }
+                    }
+                    _ => return None,
+                };
+                if delta.content.is_none()
+                    && delta.reasoning_content.is_none()
+                    && delta.tool_calls.is_empty()
+                {
+                    return None;
+                }
+                Some(self.chunk(delta, None))
+            }
+            "message_delta" => {
+                let usage = &data["usage"];
+                self.usage = Some(Usage::new(
+                    usage["input_tokens"].as_i64().unwrap_or_default() as i32,
+                    usage["output_tokens"].as_i64().unwrap_or_default() as i32,
+                ));
+                let stop_reason = data["delta"]["stop_reason"].as_str().unwrap_or("end_turn");
+                Some(self.chunk(
+                    Delta::default(),
+                    Some(finish_reason(stop_reason).to_string()),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// 生成仅包含用量的 chunk（stream_options.include_usage 时在结束前发送）
+    pub fn usage_chunk(&self) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: self.model.clone(),
+            choices: Vec::new(),
+            usage: Some(self.usage.unwrap_or_default()),
+        }
+    }
+
+    /// 将 chunk 聚合为非流式响应
+    pub fn aggregate(&self, chunks: Vec<ChatCompletionChunk>) -> ChatCompletion {
+        let mut content = String::new();
+        let mut reasoning = String::new();
+        let mut tool_calls: Vec<ToolCall> = Vec::new();
+        let mut finish = None;
+
+        for choice in chunks.into_iter().flat_map(|c| c.choices) {
+            let delta = choice.delta;
+            content.push_str(delta.content.as_deref().unwrap_or_default());
+            reasoning.push_str(delta.reasoning_content.as_deref().unwrap_or_default());
+            for call in delta.tool_calls {
+                if call.index >= tool_calls.len() {
+                    tool_calls.push(ToolCall {
+                        id: call.id.unwrap_or_default(),
+                        call_type: "function".to_string(),
+                        function: FunctionCall {
+                            name: call.function.name.unwrap_or_default(),
+                            arguments: String::new(),
+                        },
+                    });
+                }
+                if let (Some(existing), Some(arguments)) =
+                    (tool_calls.get_mut(call.index), call.function.arguments)
+                {
+                    existing.function.arguments.push_str(&arguments);
+                }
+            }
+            if choice.finish_reason.is_some() {
+                finish = choice.finish_reason;
+            }
+        }
+
+        ChatCompletion {
+            id: self.id.clone(),
+            object: "chat.completion",
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![Choice {
+                index: 0,
+                message: ResponseMessage {
+                    role: "assistant",
+                    content: (!content.is_empty() || tool_calls.is_empty()).then_some(content),
+                    reasoning_content: (!reasoning.is_empty()).then_some(reasoning),
+                    tool_calls,
+                },
+                finish_reason: finish,
+            }],
+            usage: self.usage.unwrap_or_default(),
+        }
+    }
+
+    fn chunk(&self, delta: Delta, finish_reason: Option<String>) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+            usage: None,
+        }
+    }
+}
+
+/// 格式化为 OpenAI SSE 数据行
+pub fn to_sse_data(chunk: &ChatCompletionChunk) -> String {
+    format!(
+        "data: {}\n\n",
+        serde_json::to_string(chunk).unwrap_or_default()
+    )
+}
+
+/// 流结束标记
+pub const SSE_DONE: &str = "data: [DONE]\n\n";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn events() -> Vec<SseEvent> {
+        vec![
+            SseEvent::new("message_start", json!({"type": "message_start"})),
+            SseEvent::new(
+                "content_block_start",
+                json!({"index": 0, "content_block": {"type": "text", "text": ""}}),
+            ),
+            SseEvent::new(
+                "content_block_delta",
+                json!({"index": 0, "delta": {"type": "text_delta", "text": "Hello"}}),
+            ),
+            SseEvent::new(
+                "content_block_start",
+                json!({"index": 1, "content_block": {"type": "tool_use", "id": "tool_1", "name": "read", "input": {}}}),
+            ),
+            SseEvent::new(
+                "content_block_delta",
+                json!({"index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"path\":"}}),
+            ),
+            SseEvent::new(
+                "content_block_delta",
+                json!({"index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"/a\"}"}}),
+            ),
+            SseEvent::new(
+                "message_delta",
+                json!({"delta": {"stop_reason": "tool_use"}, "usage": {"input_tokens": 10, "output_tokens": 5}}),
+            ),
+            SseEvent::new("message_stop", json!({"type": "message_stop"})),
+        ]
+    }
+
+    #[test]
+    fn test_translate_stream_chunks() {
+        let mut translator = ChunkTranslator::new("claude-sonnet-4-5");
+        let chunks: Vec<_> = events()
+            .iter()
+            .filter_map(|e| translator.translate(e))
+            .collect();
+        assert_eq!(chunks.len(), 6);
+
+        let first = serde_json::to_value(&chunks[0]).unwrap();
+        assert_eq!(first["object"], "chat.completion.chunk");
+        assert_eq!(first["choices"][0]["delta"]["role"], "assistant");
+
+        let tool_start = serde_json::to_value(&chunks[2]).unwrap();
+        let call = &tool_start["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(call["index"], 0);
+        assert_eq!(call["id"], "tool_1");
+        assert_eq!(call["function"]["name"], "read");
+
+        let last = serde_json::to_value(&chunks[5]).unwrap();
+        assert_eq!(last["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(translator.usage_chunk().usage.unwrap().total_tokens, 15);
+    }
+
+    #[test]
+    fn test_aggregate_completion() {
+        let mut translator = ChunkTranslator::new("claude-sonnet-4-5");
+        let chunks: Vec<_> = events()
+            .iter()
+            .filter_map(|e| translator.translate(e))
+            .collect();
+        let completion = serde_json::to_value(translator.aggregate(chunks)).unwrap();
+
+        assert_eq!(completion["object"], "chat.completion");
+        let message = &completion["choices"][0]["message"];
+        assert_eq!(message["content"], "Hello");
+        assert_eq!(
+            message["tool_calls"][0]["function"]["arguments"],
+            "{\"path\":\"/a\"}"
+        );
+        assert_eq!(completion["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(completion["usage"]["prompt_tokens"], 10);
+    }
+}
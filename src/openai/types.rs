@@ -0,0 +1,216 @@
+//! OpenAI Chat Completions API 类型定义
+
+use serde::{Deserialize, Serialize};
+
+// === 请求类型 ===
+
+/// Chat Completions 请求体
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub stream: bool,
+    /// 旧版输出上限字段
+    pub max_tokens: Option<i32>,
+    /// 新版输出上限字段（优先于 max_tokens）
+    pub max_completion_tokens: Option<i32>,
+    pub tools: Option<Vec<ChatTool>>,
+    pub tool_choice: Option<serde_json::Value>,
+    pub stream_options: Option<StreamOptions>,
+    /// 停止序列：字符串或字符串数组
+    pub stop: Option<serde_json::Value>,
+    /// 采样温度（0 ~ 2）
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+}
+
+impl ChatCompletionRequest {
+    /// 请求的输出 token 上限（未指定时为 None）
+    pub fn output_limit(&self) -> Option<i32> {
+        self.max_completion_tokens.or(self.max_tokens)
+    }
+
+    /// 停止序列（未指定时为 None）
+    pub fn stop_sequences(&self) -> Option<Vec<String>> {
+        match self.stop.as_ref()? {
+            serde_json::Value::String(s) => Some(vec![s.clone()]),
+            serde_json::Value::Array(items) => Some(
+                items
+                    .iter()
+                    .filter_map(|v| v.as_str().map(str::to_string))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// 流式响应结束前是否需要附带 usage chunk
+    pub fn include_usage(&self) -> bool {
+        self.stream_options
+            .as_ref()
+            .is_some_and(|o| o.include_usage)
+    }
+}
+
+/// 流式选项
+#[derive(Debug, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+/// 对话消息
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatMessage {
+    /// system / developer / user / assistant / tool
+    pub role: String,
+    /// 可以是 string、内容片段数组或 null（assistant 仅有 tool_calls 时）
+    #[serde(default)]
+    pub content: Option<serde_json::Value>,
+    /// assistant 消息中的工具调用
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// tool 消息对应的工具调用 ID
+    pub tool_call_id: Option<String>,
+}
+
+/// 工具调用
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub call_type: String,
+    pub function: FunctionCall,
+}
+
+/// 函数调用
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON 编码的参数
+    #[serde(default)]
+    pub arguments: String,
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
+/// 工具定义
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatTool {
+    pub function: FunctionDefinition,
+}
+
+/// 函数定义
+#[derive(Debug, Clone, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON Schema 格式的参数定义
+    pub parameters: Option<serde_json::Value>,
+}
+
+// === 响应类型 ===
+
+/// Token 用量
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub total_tokens: i32,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: i32, completion_tokens: i32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// 非流式响应
+#[derive(Debug, Serialize)]
+pub struct ChatCompletion {
+    pub id: String,
+    pub object: &'static str,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<Choice>,
+    pub usage: Usage,
+}
+
+/// 非流式响应选项
+#[derive(Debug, Serialize)]
+pub struct Choice {
+    pub index: u32,
+    pub message: ResponseMessage,
+    pub finish_reason: Option<String>,
+}
+
+/// 非流式响应消息
+#[derive(Debug, Serialize)]
+pub struct ResponseMessage {
+    pub role: &'static str,
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// 流式响应 chunk
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: &'static str,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// 流式响应选项
+#[derive(Debug, Serialize)]
+pub struct ChunkChoice {
+    pub index: u32,
+    pub delta: Delta,
+    pub finish_reason: Option<String>,
+}
+
+/// 流式增量
+#[derive(Debug, Default, Serialize)]
+pub struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallDelta>,
+}
+
+/// 工具调用增量
+#[derive(Debug, Serialize)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub call_type: Option<&'static str>,
+    pub function: FunctionCallDelta,
+}
+
+/// 函数调用增量
+#[derive(Debug, Serialize)]
+pub struct FunctionCallDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
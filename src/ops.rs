@@ -0,0 +1,195 @@
+//! 运维端点
+//!
+//! - `GET /metrics` - Prometheus 格式的运行时指标
+//! - `GET /healthz` - 存活探针（进程可响应即返回 200）
+//! - `GET /readyz` - 就绪探针（存在可用凭据时返回 200，否则 503；tokenizer 损坏时标记为 degraded；
+//!   优雅关闭期间返回 503）。
+//!   携带 `?probe=true` 或启用 `readinessProbe.enabled` 时逐个凭据探测上游，结果缓存 `ttlSecs` 秒
+//!
+//! 均无需认证。配置 `opsPort` 时在独立端口上提供，不再挂载到 API 监听端口
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use futures::future;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::common;
+use crate::kiro::token_manager::MultiTokenManager;
+use crate::token::{self, TokenizerStatus};
+
+/// 运维端点共享状态
+struct OpsState {
+    token_manager: Arc<MultiTokenManager>,
+    /// 最近一次上游探测的时间与结果
+    probe: tokio::sync::Mutex<Option<(Instant, ProbeReport)>>,
+}
+
+/// 一次上游探测的结果
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProbeReport {
+    probed_at: DateTime<Utc>,
+    credentials: Vec<CredentialProbe>,
+}
+
+impl ProbeReport {
+    fn usable_count(&self) -> usize {
+        self.credentials.iter().filter(|c| c.usable).count()
+    }
+}
+
+/// 单个凭据的探测结果
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CredentialProbe {
+    id: u64,
+    usable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// `/readyz` 查询参数
+#[derive(Debug, Default, Deserialize)]
+struct ReadyzQuery {
+    #[serde(default)]
+    probe: bool,
+}
+
+/// 创建运维端点路由
+pub fn create_ops_router(token_manager: Arc<MultiTokenManager>) -> Router {
+    let state = Arc::new(OpsState {
+        token_manager,
+        probe: tokio::sync::Mutex::new(None),
+    });
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
+        .with_state(state)
+}
+
+/// GET /metrics
+///
+/// 以 Prometheus 文本格式返回运行时指标
+async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::common::metrics::render(),
+    )
+}
+
+/// GET /healthz
+async fn get_healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// GET /readyz
+///
+/// 进入优雅关闭阶段后返回 503（status 为 draining），使负载均衡不再分配新请求；
+/// 所有凭据均被禁用（或探测时没有凭据通过探测）时无法处理请求，返回 503 以便负载均衡摘除实例；
+/// tokenizer 损坏仍可处理请求（token 计数回退到估算），仅标记为 degraded
+async fn get_readyz(
+    State(state): State<Arc<OpsState>>,
+    Query(query): Query<ReadyzQuery>,
+) -> impl IntoResponse {
+    let token_manager = &state.token_manager;
+    let available = token_manager.available_count();
+    let probe = if query.probe || token_manager.config().readiness_probe.enabled {
+        Some(probe(&state).await)
+    } else {
+        None
+    };
+    let usable = probe.as_ref().map_or(available, ProbeReport::usable_count);
+
+    let tokenizer = token::tokenizer_status();
+    let (status, text) = if common::shutdown::is_draining() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else if usable == 0 {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    } else if matches!(tokenizer, TokenizerStatus::Corrupted { .. }) {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "ready")
+    };
+    let mut body = json!({
+        "status": text,
+        "availableCredentials": available,
+        "totalCredentials": token_manager.total_count(),
+        "tokenizer": tokenizer,
+    });
+    if let Some(probe) = probe {
+        body["usableCredentials"] = json!(usable);
+        body["probe"] = json!(probe);
+    }
+    (status, Json(body))
+}
+
+/// 返回缓存的探测结果，过期时重新探测
+///
+/// 探测期间持有锁，并发的 `/readyz` 等待同一次探测结果而不是各自请求上游
+async fn probe(state: &OpsState) -> ProbeReport {
+    let config = &state.token_manager.config().readiness_probe;
+    let ttl = Duration::from_secs(config.ttl_secs);
+    let mut cached = state.probe.lock().await;
+    if let Some((at, report)) = cached.as_ref()
+        && at.elapsed() < ttl
+    {
+        return report.clone();
+    }
+
+    let report = probe_credentials(
+        &state.token_manager,
+        Duration::from_secs(config.timeout_secs),
+    )
+    .await;
+    tracing::info!(
+        "上游探测完成: {}/{} 个凭据可用",
+        report.usable_count(),
+        report.credentials.len()
+    );
+    *cached = Some((Instant::now(), report.clone()));
+    report
+}
+
+/// 对每个未禁用的凭据调用额度查询接口
+async fn probe_credentials(token_manager: &MultiTokenManager, timeout: Duration) -> ProbeReport {
+    let ids: Vec<u64> = token_manager
+        .snapshot()
+        .entries
+        .iter()
+        .filter(|e| !e.disabled)
+        .map(|e| e.id)
+        .collect();
+    let credentials = future::join_all(ids.into_iter().map(|id| async move {
+        let error =
+            match tokio::time::timeout(timeout, token_manager.get_usage_limits_for(id)).await {
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some("probe timed out".to_string()),
+            };
+        if let Some(error) = &error {
+            tracing::warn!("凭据 #{} 上游探测失败: {}", id, error);
+        }
+        CredentialProbe {
+            id,
+            usable: error.is_none(),
+            error,
+        }
+    }))
+    .await;
+
+    ProbeReport {
+        probed_at: Utc::now(),
+        credentials,
+    }
+}
@@ -0,0 +1,245 @@
+//! 配置与凭据文件热重载
+//!
+//! 监听 config.json 与凭据文件所在目录，文件变化后重新加载：
+//! - 凭据：新增、删除或修改的凭据立即生效（见 `MultiTokenManager::reload_credentials`）
+//! - 配置：替换运行时配置（模型列表、重试策略、调度策略等按请求读取的配置项），
+//!   代理变化时重建上游 HTTP 客户端，路由日志级别同步更新；
+//!   监听地址、存储后端等启动时使用的配置项仍需重启，重载时会给出提示
+//!
+//! 重载结果写入日志（Admin API 的 `GET /api/admin/logs/stream` 可实时查看）
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::common::telemetry;
+use crate::http_client::ProxyConfig;
+use crate::kiro::model::credentials::CredentialsConfig;
+use crate::kiro::provider::KiroProvider;
+use crate::model::config::Config;
+
+/// 合并连续文件事件的等待时间（编辑器保存时通常会产生多次写入/重命名）
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// 仅在启动时读取、修改后需重启才能生效的配置项
+const RESTART_REQUIRED: &[&str] = &[
+    "host",
+    "port",
+    "opsPort",
+    "opsHost",
+    "otlpEndpoint",
+    "otelServiceName",
+    "apiKey",
+    "apiKeys",
+    "adminApiKey",
+    "tlsBackend",
+    "tokenizerUrl",
+    "tokenizerSha256",
+    "tokenizerPath",
+    "modelTokenizers",
+    "countTokensApiUrl",
+    "countTokensApiKey",
+    "countTokensAuthType",
+    "alertWebhookUrl",
+    "storageBackend",
+    "storagePath",
+    "storageUrl",
+    "stickySessionPath",
+    "filesDir",
+    "responseCache",
+    "batches",
+    "auditLog",
+    "admission",
+    "mcpServer",
+    "mcpClient",
+    "balancePollIntervalSecs",
+    "balanceHistoryDays",
+    "tokenRefreshMarginSecs",
+    "shutdownGracePeriodSecs",
+];
+
+/// 发生变化的文件
+#[derive(Debug, Default, Clone, Copy)]
+struct Changed {
+    config: bool,
+    credentials: bool,
+}
+
+/// 启动文件监听任务（监听失败时仅记录警告，不影响服务）
+pub fn spawn(config_path: PathBuf, credentials_path: PathBuf, provider: Arc<KiroProvider>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+    let mut watcher =
+        match notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event
+                && !event.kind.is_access()
+            {
+                let _ = tx.send(event.paths);
+            }
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                tracing::warn!("创建文件监听失败，配置热重载未启用: {}", e);
+                return;
+            }
+        };
+
+    // 监听所在目录而非文件本身：编辑器常以“写临时文件 + 重命名”的方式保存，文件本身的监听会失效
+    let mut dirs = vec![watch_dir(&config_path), watch_dir(&credentials_path)];
+    dirs.dedup();
+    for dir in &dirs {
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            tracing::warn!("监听目录 {:?} 失败，配置热重载未启用: {}", dir, e);
+            return;
+        }
+    }
+
+    tracing::info!(
+        "配置热重载已启用: {:?}, {:?}",
+        config_path,
+        credentials_path
+    );
+    tokio::spawn(async move {
+        // watcher 被 drop 时停止监听，随任务一同存活
+        let _watcher = watcher;
+        while let Some(paths) = rx.recv().await {
+            let mut changed = Changed::default();
+            classify(&paths, &config_path, &credentials_path, &mut changed);
+            tokio::time::sleep(DEBOUNCE).await;
+            while let Ok(paths) = rx.try_recv() {
+                classify(&paths, &config_path, &credentials_path, &mut changed);
+            }
+
+            if changed.config {
+                reload_config(&config_path, &provider);
+            }
+            if changed.credentials {
+                reload_credentials(&credentials_path, &provider);
+            }
+        }
+    });
+}
+
+fn watch_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn classify(paths: &[PathBuf], config_path: &Path, credentials_path: &Path, changed: &mut Changed) {
+    for path in paths {
+        let name = path.file_name();
+        changed.config |= name.is_some() && name == config_path.file_name();
+        changed.credentials |= name.is_some() && name == credentials_path.file_name();
+    }
+}
+
+/// 重新加载配置文件（解析失败时保留当前配置）
+fn reload_config(path: &Path, provider: &KiroProvider) {
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::warn!("重新加载配置失败，继续使用当前配置: {}", e);
+            return;
+        }
+    };
+    let token_manager = provider.token_manager();
+    let current = token_manager.config();
+    let keys = changed_keys(&current, &config);
+    if keys.is_empty() {
+        return;
+    }
+
+    let proxy = ProxyConfig::from_config(&config);
+    if proxy != ProxyConfig::from_config(&current) {
+        if let Err(e) = provider.set_proxy(proxy.clone()) {
+            tracing::warn!("代理配置无效，继续使用当前配置: {}", e);
+            return;
+        }
+        token_manager.set_proxy(proxy);
+    }
+    if keys.iter().any(|key| key == "logging") {
+        telemetry::set_format(config.logging.format);
+        if let Err(e) = telemetry::set_route_levels(&config.logging.routes) {
+            tracing::warn!("路由日志级别配置无效，已忽略: {}", e);
+        }
+    }
+    token_manager.set_config(config);
+
+    tracing::info!("配置已重新加载，变更项: {}", keys.join(", "));
+    let restart: Vec<_> = keys
+        .iter()
+        .filter(|key| RESTART_REQUIRED.contains(&key.as_str()))
+        .map(String::as_str)
+        .collect();
+    if !restart.is_empty() {
+        tracing::warn!("以下配置项需重启后生效: {}", restart.join(", "));
+    }
+}
+
+/// 重新加载凭据文件（解析失败时保留当前凭据）
+fn reload_credentials(path: &Path, provider: &KiroProvider) {
+    let credentials = match CredentialsConfig::load(path) {
+        Ok(config) => config.into_sorted_credentials(),
+        Err(e) => {
+            tracing::warn!("重新加载凭据失败，继续使用当前凭据: {}", e);
+            return;
+        }
+    };
+    let report = provider.token_manager().reload_credentials(credentials);
+    if !report.is_empty() {
+        tracing::info!(
+            "凭据已重新加载: 新增 {:?}，移除 {:?}，更新 {:?}",
+            report.added,
+            report.removed,
+            report.updated
+        );
+    }
+}
+
+/// 比较两份配置，返回值不同的顶层配置项（camelCase）
+fn changed_keys(old: &Config, new: &Config) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    new.iter()
+        .filter(|(key, value)| old.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_keys() {
+        let old = Config::default();
+        let mut new = old.clone();
+        assert!(changed_keys(&old, &new).is_empty());
+
+        new.port = 9000;
+        new.proxy_url = Some("http://127.0.0.1:7890".to_string());
+        let mut keys = changed_keys(&old, &new);
+        keys.sort();
+        assert_eq!(keys, vec!["port", "proxyUrl"]);
+    }
+
+    #[test]
+    fn test_classify_by_file_name() {
+        let mut changed = Changed::default();
+        classify(
+            &[PathBuf::from("/etc/kiro/credentials.json")],
+            Path::new("config.json"),
+            Path::new("credentials.json"),
+            &mut changed,
+        );
+        assert!(!changed.config);
+        assert!(changed.credentials);
+    }
+}
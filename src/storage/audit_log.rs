@@ -0,0 +1,249 @@
+//! 请求审计日志
+//!
+//! 记录每次对话请求的元数据（时间、客户端 Key、模型、token 用量、耗时、状态码、所用凭据），
+//! 不保存请求与响应内容。记录以环形缓冲区的方式保存在所选存储后端中，超出上限后覆盖最旧的记录
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// 审计记录使用的存储命名空间
+const NAMESPACE: &str = "audit_log";
+
+/// 记录序号计数器使用的存储命名空间（与记录分开，清空记录时不重置序号）
+const SEQ_NAMESPACE: &str = "audit_log_seq";
+
+/// 单次请求的审计记录
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    /// 记录序号（单调递增）
+    pub id: u64,
+    /// 请求开始时间（RFC3339）
+    pub timestamp: String,
+    /// 请求路径
+    pub endpoint: String,
+    /// 客户端 API Key 名称
+    pub client_key: Option<String>,
+    /// 请求的模型
+    pub model: Option<String>,
+    /// 是否为流式请求
+    pub stream: bool,
+    /// HTTP 状态码
+    pub status: u16,
+    /// 耗时（毫秒，流式请求计算到响应体结束）
+    pub latency_ms: u64,
+    /// 输入 tokens
+    pub input_tokens: i64,
+    /// 输出 tokens
+    pub output_tokens: i64,
+    /// 使用的凭据 ID
+    pub credential_id: Option<u64>,
+}
+
+/// 审计记录查询条件（均为可选，同时指定时取交集）
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub client_key: Option<String>,
+    pub model: Option<String>,
+    pub status: Option<u16>,
+    pub credential_id: Option<u64>,
+    /// 起始时间（RFC3339，含）
+    pub since: Option<String>,
+    /// 结束时间（RFC3339，不含）
+    pub until: Option<String>,
+}
+
+impl AuditFilter {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        let time = |bound: &Option<String>| {
+            bound
+                .as_deref()
+                .map(|bound| compare_time(&record.timestamp, bound))
+        };
+        self.client_key
+            .as_ref()
+            .is_none_or(|key| record.client_key.as_ref() == Some(key))
+            && self
+                .model
+                .as_ref()
+                .is_none_or(|model| record.model.as_ref() == Some(model))
+            && self.status.is_none_or(|status| record.status == status)
+            && self
+                .credential_id
+                .is_none_or(|id| record.credential_id == Some(id))
+            && time(&self.since).is_none_or(|ord| ord.is_some_and(Ordering::is_ge))
+            && time(&self.until).is_none_or(|ord| ord.is_some_and(Ordering::is_lt))
+    }
+}
+
+/// 比较两个 RFC3339 时间，任一无法解析时返回 None
+fn compare_time(a: &str, b: &str) -> Option<Ordering> {
+    let a = chrono::DateTime::parse_from_rfc3339(a).ok()?;
+    let b = chrono::DateTime::parse_from_rfc3339(b).ok()?;
+    Some(a.cmp(&b))
+}
+
+/// 一页查询结果
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditPage {
+    /// 符合条件的记录总数
+    pub total: usize,
+    /// 本页记录（按序号倒序，即最新的在前）
+    pub records: Vec<AuditRecord>,
+}
+
+/// 请求审计日志
+///
+/// 序号由存储后端原子递增生成，记录键为 `序号 % 上限`（补零到 10 位），
+/// 写满后新记录覆盖同一槽位的最旧记录
+pub struct AuditLog {
+    storage: Arc<dyn Storage>,
+    max_entries: u64,
+}
+
+impl AuditLog {
+    /// 基于存储后端创建审计日志，`max_entries` 为最多保留的记录数
+    pub fn new(storage: Arc<dyn Storage>, max_entries: u64) -> Self {
+        Self {
+            storage,
+            max_entries: max_entries.max(1),
+        }
+    }
+
+    /// 追加一条记录（`id` 由此处分配）
+    ///
+    /// 存储失败只记录日志，不影响请求本身
+    pub fn append(&self, mut record: AuditRecord) {
+        let id = match self.storage.incr(SEQ_NAMESPACE, "seq", 1) {
+            Ok(id) => id.max(1) as u64,
+            Err(e) => {
+                tracing::warn!("写入审计日志失败: {}", e);
+                return;
+            }
+        };
+        record.id = id;
+        let key = format!("{:010}", (id - 1) % self.max_entries);
+        let result = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| self.storage.put(NAMESPACE, &key, &value, None));
+        if let Err(e) = result {
+            tracing::warn!("写入审计日志失败: {}", e);
+        }
+    }
+
+    /// 按条件分页查询，结果按序号倒序
+    pub fn query(
+        &self,
+        filter: &AuditFilter,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<AuditPage> {
+        let mut records: Vec<AuditRecord> = Vec::new();
+        for (key, value) in self.storage.scan(NAMESPACE, "")? {
+            match serde_json::from_str::<AuditRecord>(&value) {
+                Ok(record) if filter.matches(&record) => records.push(record),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("忽略无法解析的审计记录（{}）: {}", key, e),
+            }
+        }
+        records.sort_by_key(|record| std::cmp::Reverse(record.id));
+        let total = records.len();
+        let records = records.into_iter().skip(offset).take(limit).collect();
+        Ok(AuditPage { total, records })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn record(client_key: &str, model: &str, status: u16, timestamp: &str) -> AuditRecord {
+        AuditRecord {
+            timestamp: timestamp.to_string(),
+            endpoint: "/v1/messages".to_string(),
+            client_key: Some(client_key.to_string()),
+            model: Some(model.to_string()),
+            status,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer_overwrites_oldest() {
+        let log = AuditLog::new(Arc::new(MemoryStorage::new()), 3);
+        for i in 0..5 {
+            log.append(record("k", &format!("m{}", i), 200, "2026-01-01T00:00:00Z"));
+        }
+
+        let page = log.query(&AuditFilter::default(), 0, 10).unwrap();
+        assert_eq!(page.total, 3);
+        let ids: Vec<_> = page.records.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![5, 4, 3]);
+        assert_eq!(page.records[0].model.as_deref(), Some("m4"));
+    }
+
+    #[test]
+    fn test_query_filter_and_pagination() {
+        let log = AuditLog::new(Arc::new(MemoryStorage::new()), 100);
+        log.append(record(
+            "alice",
+            "claude-sonnet-4",
+            200,
+            "2026-01-01T00:00:00Z",
+        ));
+        log.append(record(
+            "bob",
+            "claude-sonnet-4",
+            429,
+            "2026-01-02T00:00:00Z",
+        ));
+        log.append(record(
+            "alice",
+            "claude-haiku-4",
+            200,
+            "2026-01-03T00:00:00Z",
+        ));
+        log.append(record(
+            "alice",
+            "claude-sonnet-4",
+            200,
+            "2026-01-04T00:00:00Z",
+        ));
+
+        let filter = AuditFilter {
+            client_key: Some("alice".to_string()),
+            model: Some("claude-sonnet-4".to_string()),
+            ..Default::default()
+        };
+        let page = log.query(&filter, 0, 10).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.records[0].id, 4);
+
+        let filter = AuditFilter {
+            since: Some("2026-01-02T00:00:00Z".to_string()),
+            until: Some("2026-01-04T00:00:00Z".to_string()),
+            ..Default::default()
+        };
+        let page = log.query(&filter, 1, 1).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.records.len(), 1);
+        assert_eq!(page.records[0].id, 2);
+
+        let filter = AuditFilter {
+            status: Some(429),
+            ..Default::default()
+        };
+        assert_eq!(
+            log.query(&filter, 0, 10).unwrap().records[0]
+                .client_key
+                .as_deref(),
+            Some("bob")
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! 凭据余额历史
+//!
+//! 后台轮询定期记录各凭据的已用额度与总额度，便于观察消耗速度，而不只是某一时刻的余额
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// 余额历史使用的存储命名空间
+const NAMESPACE: &str = "balance_history";
+
+/// 单次余额采样
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceSample {
+    /// 采样时间（RFC3339）
+    pub recorded_at: String,
+    /// 已用额度
+    pub current_usage: f64,
+    /// 总额度
+    pub usage_limit: f64,
+    /// 剩余额度
+    pub remaining: f64,
+}
+
+/// 余额历史
+///
+/// 键格式为 `{id}|{unix 秒（补零到 20 位）}`，按键排序即按时间排序；
+/// 每条记录写入时带保留期 TTL，过期后由存储后端清理
+pub struct BalanceHistory {
+    storage: Arc<dyn Storage>,
+    retention: Duration,
+}
+
+impl BalanceHistory {
+    /// 基于存储后端创建余额历史，`retention_days` 为保留天数
+    pub fn new(storage: Arc<dyn Storage>, retention_days: u32) -> Self {
+        Self {
+            storage,
+            retention: Duration::from_secs(u64::from(retention_days.max(1)) * 86400),
+        }
+    }
+
+    /// 记录一次采样
+    pub fn record(&self, id: u64, current_usage: f64, usage_limit: f64) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let sample = BalanceSample {
+            recorded_at: now.to_rfc3339(),
+            current_usage,
+            usage_limit,
+            remaining: (usage_limit - current_usage).max(0.0),
+        };
+        let key = format!("{}|{:020}", id, now.timestamp());
+        self.storage.put(
+            NAMESPACE,
+            &key,
+            &serde_json::to_string(&sample)?,
+            Some(self.retention),
+        )
+    }
+
+    /// 查询凭据的全部采样（按时间升序）
+    pub fn list(&self, id: u64) -> anyhow::Result<Vec<BalanceSample>> {
+        let mut samples = Vec::new();
+        for (key, value) in self.storage.scan(NAMESPACE, &format!("{}|", id))? {
+            match serde_json::from_str(&value) {
+                Ok(sample) => samples.push(sample),
+                Err(e) => tracing::warn!("忽略无法解析的余额记录（{}）: {}", key, e),
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// 估算每日消耗额度
+///
+/// 只统计最近一个额度周期内的采样（已用额度下降视为额度重置），不足两个采样或
+/// 时间跨度为 0 时返回 None
+pub fn burn_rate_per_day(samples: &[BalanceSample]) -> Option<f64> {
+    let start = samples
+        .windows(2)
+        .rposition(|pair| pair[1].current_usage < pair[0].current_usage)
+        .map_or(0, |i| i + 1);
+    let period = &samples[start..];
+    let (first, last) = (period.first()?, period.last()?);
+
+    let parse = |s: &BalanceSample| DateTime::parse_from_rfc3339(&s.recorded_at).ok();
+    let elapsed = (parse(last)? - parse(first)?).num_seconds();
+    if elapsed <= 0 {
+        return None;
+    }
+    Some((last.current_usage - first.current_usage) / elapsed as f64 * 86400.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn sample(recorded_at: &str, current_usage: f64) -> BalanceSample {
+        BalanceSample {
+            recorded_at: recorded_at.to_string(),
+            current_usage,
+            usage_limit: 100.0,
+            remaining: 100.0 - current_usage,
+        }
+    }
+
+    #[test]
+    fn test_record_and_list() {
+        let history = BalanceHistory::new(Arc::new(MemoryStorage::new()), 30);
+        history.record(1, 10.0, 50.0).unwrap();
+        history.record(11, 1.0, 50.0).unwrap();
+
+        let samples = history.list(1).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].remaining, 40.0);
+        assert!(history.list(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_burn_rate_uses_current_period() {
+        let samples = vec![
+            sample("2026-01-01T00:00:00Z", 80.0),
+            // 额度重置
+            sample("2026-01-02T00:00:00Z", 0.0),
+            sample("2026-01-03T00:00:00Z", 10.0),
+            sample("2026-01-05T00:00:00Z", 30.0),
+        ];
+        assert_eq!(burn_rate_per_day(&samples), Some(10.0));
+        assert_eq!(burn_rate_per_day(&samples[..1]), None);
+        assert_eq!(burn_rate_per_day(&[]), None);
+    }
+}
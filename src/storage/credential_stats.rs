@@ -0,0 +1,137 @@
+//! 凭据用量统计
+//!
+//! 按凭据累计请求数、失败次数、token 用量与最近使用时间，数据保存在所选存储后端中
+//! （`sqlite` / `redis` 后端重启后保留）
+
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::Serialize;
+
+use super::Storage;
+
+/// 凭据统计使用的存储命名空间
+const NAMESPACE: &str = "credential_stats";
+
+/// 单个凭据的累计统计
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialUsage {
+    /// 成功请求数
+    pub requests: i64,
+    /// 失败次数（鉴权失败、额度用尽、限流、上游不可用等）
+    pub failures: i64,
+    /// 输入 tokens
+    pub input_tokens: i64,
+    /// 输出 tokens
+    pub output_tokens: i64,
+    /// 最近一次成功使用时间（RFC3339）
+    pub last_used_at: Option<String>,
+    /// 最近一次失败时间（RFC3339）
+    pub last_failure_at: Option<String>,
+}
+
+/// 凭据统计
+///
+/// 键格式为 `{id}|{field}`：计数字段是独立计数器，时间字段直接覆盖写入
+#[derive(Clone)]
+pub struct CredentialStats {
+    storage: Arc<dyn Storage>,
+}
+
+impl CredentialStats {
+    /// 基于存储后端创建凭据统计
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// 记录一次成功请求
+    pub fn record_success(&self, id: u64) {
+        self.incr(id, "requests", 1);
+        self.touch(id, "last_used_at");
+    }
+
+    /// 记录一次失败
+    pub fn record_failure(&self, id: u64) {
+        self.incr(id, "failures", 1);
+        self.touch(id, "last_failure_at");
+    }
+
+    /// 记录请求消耗的 tokens
+    pub fn record_tokens(&self, id: u64, input_tokens: i32, output_tokens: i32) {
+        self.incr(id, "input_tokens", input_tokens.max(0) as i64);
+        self.incr(id, "output_tokens", output_tokens.max(0) as i64);
+    }
+
+    /// 查询凭据的累计统计
+    pub fn get(&self, id: u64) -> anyhow::Result<CredentialUsage> {
+        let mut usage = CredentialUsage::default();
+        for (key, value) in self.storage.scan(NAMESPACE, &format!("{}|", id))? {
+            let Some((_, field)) = key.split_once('|') else {
+                continue;
+            };
+            match field {
+                "requests" => usage.requests = value.parse().unwrap_or(0),
+                "failures" => usage.failures = value.parse().unwrap_or(0),
+                "input_tokens" => usage.input_tokens = value.parse().unwrap_or(0),
+                "output_tokens" => usage.output_tokens = value.parse().unwrap_or(0),
+                "last_used_at" => usage.last_used_at = Some(value),
+                "last_failure_at" => usage.last_failure_at = Some(value),
+                _ => {}
+            }
+        }
+        Ok(usage)
+    }
+
+    /// 在后台递增计数字段（存储失败只记录日志，不影响请求本身）
+    fn incr(&self, id: u64, field: &str, delta: i64) {
+        if delta == 0 {
+            return;
+        }
+        let key = format!("{}|{}", id, field);
+        super::spawn_write(&self.storage, move |storage| {
+            if let Err(e) = storage.incr(NAMESPACE, &key, delta) {
+                tracing::warn!("记录凭据统计失败（{}）: {}", key, e);
+            }
+        });
+    }
+
+    /// 在后台更新时间字段为当前时间
+    fn touch(&self, id: u64, field: &str) {
+        let key = format!("{}|{}", id, field);
+        let now = Utc::now().to_rfc3339();
+        super::spawn_write(&self.storage, move |storage| {
+            if let Err(e) = storage.put(NAMESPACE, &key, &now, None) {
+                tracing::warn!("记录凭据统计失败（{}）: {}", key, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_record_and_get() {
+        let stats = CredentialStats::new(Arc::new(MemoryStorage::new()));
+        stats.record_success(1);
+        stats.record_success(1);
+        stats.record_tokens(1, 100, 20);
+        stats.record_failure(1);
+        stats.record_success(12);
+
+        let usage = stats.get(1).unwrap();
+        assert_eq!(usage.requests, 2);
+        assert_eq!(usage.failures, 1);
+        assert_eq!(usage.input_tokens, 100);
+        assert_eq!(usage.output_tokens, 20);
+        assert!(usage.last_used_at.is_some());
+        assert!(usage.last_failure_at.is_some());
+
+        // 前缀 `1|` 不会匹配到凭据 #12
+        assert_eq!(stats.get(12).unwrap().requests, 1);
+        assert_eq!(stats.get(2).unwrap(), CredentialUsage::default());
+    }
+}
@@ -0,0 +1,245 @@
+//! 客户端 Key 的 token 用量
+//!
+//! 按 UTC 自然日与自然月累计每个客户端 Key 消耗的 tokens（输入 + 输出），用于执行 Key 的
+//! `quota` 预算。数据保存在所选存储后端中，多实例共享 `redis` 后端时跨实例累计
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Months, Utc};
+use serde::Serialize;
+
+use super::Storage;
+use crate::model::config::TokenQuota;
+
+/// Key 用量使用的存储命名空间
+const NAMESPACE: &str = "key_usage";
+
+/// 单个 Key 当前周期的用量
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyUsageTotals {
+    /// 当前日期（UTC，YYYY-MM-DD）
+    pub day: String,
+    /// 当日已用 tokens
+    pub daily_tokens: i64,
+    /// 当前月份（UTC，YYYY-MM）
+    pub month: String,
+    /// 当月已用 tokens
+    pub monthly_tokens: i64,
+}
+
+/// 预算周期
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => "daily",
+            QuotaPeriod::Monthly => "monthly",
+        }
+    }
+}
+
+/// 超出的预算
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub period: QuotaPeriod,
+    pub limit: u64,
+    pub used: i64,
+    /// 距离该周期重置的时间
+    pub resets_in: Duration,
+}
+
+/// 客户端 Key 的 token 用量
+///
+/// 键格式为 `{name}|day|{YYYY-MM-DD}` 与 `{name}|month|{YYYY-MM}`，每个周期是一个独立计数器
+#[derive(Clone)]
+pub struct KeyUsage {
+    storage: Arc<dyn Storage>,
+}
+
+impl KeyUsage {
+    /// 基于存储后端创建 Key 用量统计
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// 累计一次请求消耗的 tokens
+    ///
+    /// 在后台写入存储，失败只记录日志，不影响请求本身
+    pub fn record(&self, name: &str, tokens: i64) {
+        let (day, month) = period_keys(name, Utc::now());
+        super::spawn_write(&self.storage, move |storage| {
+            for key in [day, month] {
+                if let Err(e) = storage.incr(NAMESPACE, &key, tokens.max(0)) {
+                    tracing::warn!("记录 API Key 用量失败（{}）: {}", key, e);
+                    return;
+                }
+            }
+        });
+    }
+
+    /// 查询 Key 当前周期的用量
+    pub fn get(&self, name: &str) -> anyhow::Result<KeyUsageTotals> {
+        self.get_at(name, Utc::now())
+    }
+
+    fn get_at(&self, name: &str, now: DateTime<Utc>) -> anyhow::Result<KeyUsageTotals> {
+        let (day_key, month_key) = period_keys(name, now);
+        let read = |key: &str| -> anyhow::Result<i64> {
+            Ok(self
+                .storage
+                .get(NAMESPACE, key)?
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0))
+        };
+        Ok(KeyUsageTotals {
+            day: now.format("%Y-%m-%d").to_string(),
+            daily_tokens: read(&day_key)?,
+            month: now.format("%Y-%m").to_string(),
+            monthly_tokens: read(&month_key)?,
+        })
+    }
+
+    /// 检查 Key 是否已用完预算（先检查每日，再检查每月）
+    ///
+    /// 读取存储失败时放行，只记录日志。阻塞后端在阻塞线程池上读取
+    pub async fn check_async(&self, name: &str, quota: &TokenQuota) -> Result<(), QuotaExceeded> {
+        if quota.is_unlimited() || !self.storage.is_blocking() {
+            return self.check(name, quota);
+        }
+        let (usage, name, quota) = (self.clone(), name.to_string(), *quota);
+        tokio::task::spawn_blocking(move || usage.check(&name, &quota))
+            .await
+            .unwrap_or(Ok(()))
+    }
+
+    /// 检查 Key 是否已用完预算（同步读取存储）
+    pub fn check(&self, name: &str, quota: &TokenQuota) -> Result<(), QuotaExceeded> {
+        if quota.is_unlimited() {
+            return Ok(());
+        }
+        let now = Utc::now();
+        let usage = match self.get_at(name, now) {
+            Ok(usage) => usage,
+            Err(e) => {
+                tracing::warn!("读取 API Key {} 用量失败，跳过预算检查: {}", name, e);
+                return Ok(());
+            }
+        };
+        exceeded(quota, &usage, now).map_or(Ok(()), Err)
+    }
+
+    /// 清零 Key 当前周期的用量
+    pub fn reset(&self, name: &str) -> anyhow::Result<()> {
+        let (day, month) = period_keys(name, Utc::now());
+        for key in [day, month] {
+            self.storage.put(NAMESPACE, &key, "0", None)?;
+        }
+        Ok(())
+    }
+}
+
+/// 当日与当月计数器的键
+fn period_keys(name: &str, now: DateTime<Utc>) -> (String, String) {
+    (
+        format!("{}|day|{}", name, now.format("%Y-%m-%d")),
+        format!("{}|month|{}", name, now.format("%Y-%m")),
+    )
+}
+
+/// 按用量判断超出的预算
+fn exceeded(
+    quota: &TokenQuota,
+    usage: &KeyUsageTotals,
+    now: DateTime<Utc>,
+) -> Option<QuotaExceeded> {
+    let today = now.date_naive();
+    let daily_reset = today.succ_opt().unwrap_or(today);
+    let monthly_reset = today
+        .with_day(1)
+        .and_then(|first| first.checked_add_months(Months::new(1)))
+        .unwrap_or(today);
+    let until = |date: chrono::NaiveDate| {
+        (date.and_time(chrono::NaiveTime::MIN).and_utc() - now)
+            .to_std()
+            .unwrap_or_default()
+    };
+
+    [
+        (
+            QuotaPeriod::Daily,
+            quota.daily_tokens,
+            usage.daily_tokens,
+            daily_reset,
+        ),
+        (
+            QuotaPeriod::Monthly,
+            quota.monthly_tokens,
+            usage.monthly_tokens,
+            monthly_reset,
+        ),
+    ]
+    .into_iter()
+    .find_map(|(period, limit, used, reset)| {
+        let limit = limit?;
+        (used >= limit as i64).then(|| QuotaExceeded {
+            period,
+            limit,
+            used,
+            resets_in: until(reset),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_record_check_and_reset() {
+        let usage = KeyUsage::new(Arc::new(MemoryStorage::new()));
+        let quota = TokenQuota {
+            daily_tokens: Some(100),
+            monthly_tokens: Some(1000),
+        };
+        usage.record("team-a", 60);
+        usage.record("team-b", 500);
+        assert!(usage.check("team-a", &quota).is_ok());
+
+        usage.record("team-a", 40);
+        let exceeded = usage.check("team-a", &quota).unwrap_err();
+        assert_eq!(exceeded.period, QuotaPeriod::Daily);
+        assert_eq!((exceeded.limit, exceeded.used), (100, 100));
+        assert!(exceeded.resets_in <= Duration::from_secs(86400));
+
+        usage.reset("team-a").unwrap();
+        let totals = usage.get("team-a").unwrap();
+        assert_eq!((totals.daily_tokens, totals.monthly_tokens), (0, 0));
+        assert_eq!(usage.get("team-b").unwrap().monthly_tokens, 500);
+    }
+
+    #[test]
+    fn test_monthly_quota_resets_next_month() {
+        let now = DateTime::parse_from_rfc3339("2026-12-31T23:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let quota = TokenQuota {
+            daily_tokens: None,
+            monthly_tokens: Some(10),
+        };
+        let usage = KeyUsageTotals {
+            monthly_tokens: 10,
+            ..Default::default()
+        };
+        let exceeded = exceeded(&quota, &usage, now).unwrap();
+        assert_eq!(exceeded.period, QuotaPeriod::Monthly);
+        assert_eq!(exceeded.resets_in, Duration::from_secs(3600));
+    }
+}
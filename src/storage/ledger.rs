@@ -0,0 +1,189 @@
+//! 用量账本
+//!
+//! 按 UTC 日期和模型累计请求数与 token 用量，数据保存在所选存储后端中
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Serialize;
+
+use super::Storage;
+use super::credential_stats::CredentialStats;
+use super::key_usage::KeyUsage;
+use crate::common::api_keys::ClientKey;
+use crate::common::audit;
+
+/// 用量账本使用的存储命名空间
+const NAMESPACE: &str = "usage";
+
+/// 单日单模型的用量汇总
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    /// 日期（UTC，YYYY-MM-DD）
+    pub date: String,
+    /// 模型名称
+    pub model: String,
+    /// 请求数
+    pub requests: i64,
+    /// 输入 tokens
+    pub input_tokens: i64,
+    /// 输出 tokens
+    pub output_tokens: i64,
+}
+
+/// 用量账本
+///
+/// 键格式为 `{date}|{model}|{field}`，每个字段是一个独立计数器
+pub struct UsageLedger {
+    storage: Arc<dyn Storage>,
+    /// 客户端 Key 的 token 预算用量
+    key_usage: KeyUsage,
+    /// 本次请求的客户端 Key 名称（仅请求级视图）
+    client_name: Option<String>,
+    /// 凭据用量统计
+    credential_stats: Option<Arc<CredentialStats>>,
+    /// 本次请求使用的凭据（仅请求级视图）
+    credential_id: Option<u64>,
+}
+
+impl UsageLedger {
+    /// 基于存储后端创建用量账本
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            key_usage: KeyUsage::new(storage.clone()),
+            storage,
+            client_name: None,
+            credential_stats: None,
+            credential_id: None,
+        }
+    }
+
+    /// 设置凭据用量统计（请求级视图绑定凭据后，tokens 同时计入该凭据）
+    pub fn with_credential_stats(mut self, stats: Arc<CredentialStats>) -> Self {
+        self.credential_stats = Some(stats);
+        self
+    }
+
+    /// 创建请求级视图：记录用量时同时计入客户端 Key 的 token 预算
+    ///
+    /// 速率限制器的 tokens 由记录用量的处理函数显式扣减，不经由账本
+    pub fn for_client(&self, client: &ClientKey) -> Arc<Self> {
+        Arc::new(Self {
+            storage: self.storage.clone(),
+            key_usage: self.key_usage.clone(),
+            client_name: Some(client.name.clone()),
+            credential_stats: self.credential_stats.clone(),
+            credential_id: self.credential_id,
+        })
+    }
+
+    /// 创建绑定凭据的视图：记录用量时同时计入该凭据的 token 统计
+    pub fn for_credential(&self, id: u64) -> Arc<Self> {
+        Arc::new(Self {
+            storage: self.storage.clone(),
+            key_usage: self.key_usage.clone(),
+            client_name: self.client_name.clone(),
+            credential_stats: self.credential_stats.clone(),
+            credential_id: Some(id),
+        })
+    }
+
+    /// 记录一次成功请求的用量
+    ///
+    /// 在后台写入存储，失败只记录日志，不影响请求本身
+    pub fn record(&self, model: &str, input_tokens: i32, output_tokens: i32) {
+        audit::note_usage(input_tokens, output_tokens);
+        if let Some(name) = &self.client_name {
+            let tokens = input_tokens.max(0) as i64 + output_tokens.max(0) as i64;
+            self.key_usage.record(name, tokens);
+        }
+        if let (Some(stats), Some(id)) = (&self.credential_stats, self.credential_id) {
+            stats.record_tokens(id, input_tokens, output_tokens);
+        }
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let fields = [
+            ("requests", 1),
+            ("input_tokens", input_tokens.max(0) as i64),
+            ("output_tokens", output_tokens.max(0) as i64),
+        ];
+        let prefix = format!("{}|{}", date, model);
+        super::spawn_write(&self.storage, move |storage| {
+            for (field, delta) in fields {
+                let key = format!("{}|{}", prefix, field);
+                if let Err(e) = storage.incr(NAMESPACE, &key, delta) {
+                    tracing::warn!("记录用量失败（{}）: {}", key, e);
+                    return;
+                }
+            }
+        });
+    }
+
+    /// 查询最近 `days` 天（含今天）的用量，按日期倒序、模型名排序
+    pub fn recent_days(&self, days: u32) -> anyhow::Result<Vec<DailyUsage>> {
+        let today = Utc::now().date_naive();
+        let mut result = Vec::new();
+
+        for offset in 0..days.max(1) {
+            let date = (today - ChronoDuration::days(offset as i64))
+                .format("%Y-%m-%d")
+                .to_string();
+            let mut by_model: BTreeMap<String, DailyUsage> = BTreeMap::new();
+
+            for (key, value) in self.storage.scan(NAMESPACE, &format!("{}|", date))? {
+                let mut parts = key.splitn(3, '|');
+                let (Some(_), Some(model), Some(field)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    continue;
+                };
+                let value: i64 = value.parse().unwrap_or(0);
+                let usage = by_model
+                    .entry(model.to_string())
+                    .or_insert_with(|| DailyUsage {
+                        date: date.clone(),
+                        model: model.to_string(),
+                        ..Default::default()
+                    });
+                match field {
+                    "requests" => usage.requests = value,
+                    "input_tokens" => usage.input_tokens = value,
+                    "output_tokens" => usage.output_tokens = value,
+                    _ => {}
+                }
+            }
+
+            result.extend(by_model.into_values());
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_record_and_query() {
+        let ledger = UsageLedger::new(Arc::new(MemoryStorage::new()));
+        ledger.record("claude-sonnet-4", 100, 20);
+        ledger.record("claude-sonnet-4", 50, 10);
+        ledger.record("claude-haiku-4", 5, 1);
+
+        let usage = ledger.recent_days(1).unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].model, "claude-haiku-4");
+        assert_eq!(usage[1].requests, 2);
+        assert_eq!(usage[1].input_tokens, 150);
+        assert_eq!(usage[1].output_tokens, 30);
+    }
+
+    #[test]
+    fn test_recent_days_empty() {
+        let ledger = UsageLedger::new(Arc::new(MemoryStorage::new()));
+        assert!(ledger.recent_days(7).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! 内存存储后端
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use super::Storage;
+
+/// 存储条目
+struct Entry {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// 两次全量清理过期条目之间的最小间隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 进程内存储（默认后端）
+///
+/// 过期条目在读取或扫描时惰性清理；写入时若距上次全量清理超过 [`SWEEP_INTERVAL`]，
+/// 顺带清理所有过期条目，避免只写不读的 TTL 键无限增长
+pub struct MemoryStorage {
+    /// (namespace, key) -> 条目，BTreeMap 保证扫描结果有序
+    entries: Mutex<BTreeMap<(String, String), Entry>>,
+    /// 上次全量清理的时间
+    last_sweep: Mutex<Instant>,
+}
+
+impl MemoryStorage {
+    /// 创建空的内存存储
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(BTreeMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// 距上次全量清理超过间隔时清理所有过期条目
+    fn maybe_sweep(&self, entries: &mut BTreeMap<(String, String), Entry>, now: Instant) {
+        let mut last_sweep = self.last_sweep.lock();
+        if now.duration_since(*last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        *last_sweep = now;
+        entries.retain(|_, e| !e.is_expired(now));
+    }
+
+    /// 当前条目数（含尚未清理的过期条目）
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    fn is_blocking(&self) -> bool {
+        false
+    }
+
+    fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<String>> {
+        let mut entries = self.entries.lock();
+        let map_key = (namespace.to_string(), key.to_string());
+        match entries.get(&map_key) {
+            Some(entry) if entry.is_expired(Instant::now()) => {
+                entries.remove(&map_key);
+                Ok(None)
+            }
+            Some(entry) => Ok(Some(entry.value.clone())),
+            None => Ok(None),
+        }
+    }
+
+    fn put(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock();
+        let now = Instant::now();
+        self.maybe_sweep(&mut entries, now);
+        entries.insert(
+            (namespace.to_string(), key.to_string()),
+            Entry {
+                value: value.to_string(),
+                expires_at: ttl.map(|t| now + t),
+            },
+        );
+        Ok(())
+    }
+
+    fn incr(&self, namespace: &str, key: &str, delta: i64) -> anyhow::Result<i64> {
+        let mut entries = self.entries.lock();
+        let now = Instant::now();
+        let entry = entries
+            .entry((namespace.to_string(), key.to_string()))
+            .or_insert_with(|| Entry {
+                value: "0".to_string(),
+                expires_at: None,
+            });
+        if entry.is_expired(now) {
+            entry.value = "0".to_string();
+            entry.expires_at = None;
+        }
+        let current: i64 = entry
+            .value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("键 {}:{} 的值不是整数", namespace, key))?;
+        let next = current.saturating_add(delta);
+        entry.value = next.to_string();
+        Ok(next)
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let mut entries = self.entries.lock();
+        let now = Instant::now();
+        entries.retain(|_, e| !e.is_expired(now));
+        Ok(entries
+            .range((namespace.to_string(), prefix.to_string())..)
+            .take_while(|((ns, key), _)| ns == namespace && key.starts_with(prefix))
+            .map(|((_, key), e)| (key.clone(), e.value.clone()))
+            .collect())
+    }
+
+    fn clear(&self, namespace: &str) -> anyhow::Result<usize> {
+        let mut entries = self.entries.lock();
+        let before = entries.len();
+        entries.retain(|(ns, _), _| ns != namespace);
+        Ok(before - entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_storage() {
+        super::super::exercise_storage(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn test_memory_put_sweeps_expired_entries() {
+        let storage = MemoryStorage::new();
+        for i in 0..10 {
+            storage
+                .put(
+                    "ns",
+                    &format!("k{}", i),
+                    "v",
+                    Some(Duration::from_millis(1)),
+                )
+                .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        *storage.last_sweep.lock() -= SWEEP_INTERVAL;
+
+        storage.put("ns", "live", "v", None).unwrap();
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn test_memory_incr_rejects_non_integer() {
+        let storage = MemoryStorage::new();
+        storage.put("ns", "k", "abc", None).unwrap();
+        assert!(storage.incr("ns", "k", 1).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! 持久化存储抽象
+//!
+//! 为需要持久化的子系统（幂等缓存、用量账本等）提供统一的存储接口，
+//! 部署时通过 `storageBackend` 一次性选择持久性与简洁性的权衡：
+//! - `memory`：进程内存储（默认），重启后数据丢失
+//! - `sqlite`：本地 SQLite 文件（需启用 `sqlite` feature）
+//! - `redis`：Redis 服务（需启用 `redis` feature），适合多实例共享
+//!
+//! 存储接口为同步接口。SQLite / Redis 后端的操作是阻塞 IO，异步代码中应通过
+//! [`run_blocking`]（需要结果）或 [`spawn_write`]（无需等待）调用，避免阻塞 tokio 工作线程
+
+pub mod audit_log;
+pub mod balance_history;
+pub mod credential_stats;
+pub mod key_usage;
+pub mod ledger;
+mod memory;
+#[cfg(feature = "redis")]
+mod redis;
+pub mod session_affinity;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::config::Config;
+
+pub use memory::MemoryStorage;
+
+/// 存储后端类型
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+    /// 进程内存储
+    #[default]
+    Memory,
+    /// SQLite 文件存储
+    Sqlite,
+    /// Redis 存储
+    Redis,
+}
+
+/// 键值存储接口
+///
+/// 所有键都位于命名空间（namespace）下，不同子系统使用不同的命名空间互不干扰
+pub trait Storage: Send + Sync {
+    /// 后端名称（用于日志）
+    fn backend(&self) -> &'static str;
+
+    /// 操作是否为阻塞 IO（为 false 时异步代码可直接调用）
+    fn is_blocking(&self) -> bool {
+        true
+    }
+
+    /// 读取值，不存在或已过期时返回 None
+    fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<String>>;
+
+    /// 写入值，`ttl` 为 None 时永不过期
+    fn put(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<()>;
+
+    /// 原子递增整数计数器，返回递增后的值（不存在时从 0 开始）
+    fn incr(&self, namespace: &str, key: &str, delta: i64) -> anyhow::Result<i64>;
+
+    /// 列出命名空间下以 `prefix` 开头的所有键值对（按键排序）
+    fn scan(&self, namespace: &str, prefix: &str) -> anyhow::Result<Vec<(String, String)>>;
+
+    /// 删除命名空间下的所有键，返回删除的数量
+    fn clear(&self, namespace: &str) -> anyhow::Result<usize>;
+}
+
+/// 在阻塞线程池上执行存储操作并等待结果（非阻塞后端直接执行）
+pub async fn run_blocking<T, F>(storage: &Arc<dyn Storage>, op: F) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn Storage) -> anyhow::Result<T> + Send + 'static,
+{
+    if !storage.is_blocking() {
+        return op(storage.as_ref());
+    }
+    let storage = storage.clone();
+    tokio::task::spawn_blocking(move || op(storage.as_ref())).await?
+}
+
+/// 在后台执行无需等待结果的存储写入
+///
+/// 阻塞后端在 tokio 运行时中转到阻塞线程池执行；非阻塞后端或不在运行时中时直接执行
+pub fn spawn_write<F>(storage: &Arc<dyn Storage>, write: F)
+where
+    F: FnOnce(&dyn Storage) + Send + 'static,
+{
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if storage.is_blocking() => {
+            let storage = storage.clone();
+            handle.spawn_blocking(move || write(storage.as_ref()));
+        }
+        _ => write(storage.as_ref()),
+    }
+}
+
+/// 根据配置打开存储后端
+pub fn open(config: &Config) -> anyhow::Result<Arc<dyn Storage>> {
+    match config.storage_backend {
+        StorageBackend::Memory => Ok(Arc::new(MemoryStorage::new())),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => Ok(Arc::new(sqlite::SqliteStorage::open(&config.storage_path)?)),
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => {
+            anyhow::bail!("storageBackend 为 sqlite，但当前构建未启用 `sqlite` feature")
+        }
+        #[cfg(feature = "redis")]
+        StorageBackend::Redis => {
+            let url = config
+                .storage_url
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("storageBackend 为 redis 时必须配置 storageUrl"))?;
+            Ok(Arc::new(redis::RedisStorage::open(url)?))
+        }
+        #[cfg(not(feature = "redis"))]
+        StorageBackend::Redis => {
+            anyhow::bail!("storageBackend 为 redis，但当前构建未启用 `redis` feature")
+        }
+    }
+}
+
+/// 后端通用的行为测试
+#[cfg(test)]
+pub(crate) fn exercise_storage(storage: &dyn Storage) {
+    assert_eq!(storage.get("ns", "missing").unwrap(), None);
+
+    storage.put("ns", "a", "1", None).unwrap();
+    assert_eq!(storage.get("ns", "a").unwrap().as_deref(), Some("1"));
+    assert_eq!(storage.get("other", "a").unwrap(), None);
+
+    storage.put("ns", "a", "2", None).unwrap();
+    assert_eq!(storage.get("ns", "a").unwrap().as_deref(), Some("2"));
+
+    storage
+        .put("ns", "expired", "x", Some(Duration::from_millis(1)))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(storage.get("ns", "expired").unwrap(), None);
+
+    assert_eq!(storage.incr("counter", "c", 3).unwrap(), 3);
+    assert_eq!(storage.incr("counter", "c", 2).unwrap(), 5);
+
+    storage.put("scan", "p:b", "2", None).unwrap();
+    storage.put("scan", "p:a", "1", None).unwrap();
+    storage.put("scan", "q:a", "3", None).unwrap();
+    assert_eq!(
+        storage.scan("scan", "p:").unwrap(),
+        vec![
+            ("p:a".to_string(), "1".to_string()),
+            ("p:b".to_string(), "2".to_string())
+        ]
+    );
+
+    assert_eq!(storage.clear("scan").unwrap(), 3);
+    assert!(storage.scan("scan", "").unwrap().is_empty());
+    assert_eq!(storage.get("ns", "a").unwrap().as_deref(), Some("2"));
+}
@@ -0,0 +1,182 @@
+//! Redis 存储后端（需启用 `redis` feature）
+
+use std::time::Duration;
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use redis::Commands;
+
+use super::Storage;
+
+/// 所有键的公共前缀，避免与同一 Redis 中的其他数据冲突
+const KEY_PREFIX: &str = "kiro-rs";
+
+/// 每批 SCAN 返回的键数量提示
+const SCAN_COUNT: usize = 500;
+
+/// Redis 存储
+///
+/// 键格式为 `kiro-rs:{namespace}:{key}`，过期时间由 Redis 原生 TTL 管理
+pub struct RedisStorage {
+    client: redis::Client,
+    /// 复用的连接，出错时丢弃并在下次调用时重连
+    conn: Mutex<Option<redis::Connection>>,
+}
+
+impl RedisStorage {
+    /// 连接 Redis（启动时校验连通性）
+    pub fn open(url: &str) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url).context("解析 Redis 地址失败")?;
+        let conn = client.get_connection().context("连接 Redis 失败")?;
+        Ok(Self {
+            client,
+            conn: Mutex::new(Some(conn)),
+        })
+    }
+
+    fn full_key(namespace: &str, key: &str) -> String {
+        format!("{}:{}:{}", KEY_PREFIX, namespace, key)
+    }
+
+    /// 使用（必要时重建的）连接执行操作
+    fn with_conn<T>(
+        &self,
+        f: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T>,
+    ) -> anyhow::Result<T> {
+        let mut guard = self.conn.lock();
+        if guard.is_none() {
+            *guard = Some(self.client.get_connection().context("连接 Redis 失败")?);
+        }
+        let conn = guard.as_mut().expect("连接已建立");
+        match f(conn) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                if e.is_connection_dropped() || e.is_io_error() {
+                    *guard = None;
+                }
+                Err(anyhow::anyhow!("Redis 操作失败: {}", e))
+            }
+        }
+    }
+}
+
+impl Storage for RedisStorage {
+    fn backend(&self) -> &'static str {
+        "redis"
+    }
+
+    fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<String>> {
+        let full_key = Self::full_key(namespace, key);
+        self.with_conn(|conn| conn.get(&full_key))
+    }
+
+    fn put(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        let full_key = Self::full_key(namespace, key);
+        self.with_conn(|conn| match ttl {
+            Some(ttl) => conn.pset_ex(&full_key, value, (ttl.as_millis() as u64).max(1)),
+            None => conn.set(&full_key, value),
+        })
+    }
+
+    fn incr(&self, namespace: &str, key: &str, delta: i64) -> anyhow::Result<i64> {
+        let full_key = Self::full_key(namespace, key);
+        self.with_conn(|conn| conn.incr(&full_key, delta))
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let ns_prefix = format!("{}:{}:", KEY_PREFIX, namespace);
+        let pattern = scan_pattern(namespace, prefix);
+        self.with_conn(|conn| {
+            let keys = scan_keys(conn, &pattern)?;
+            if keys.is_empty() {
+                return Ok(Vec::new());
+            }
+            let values: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query(conn)?;
+            let mut pairs: Vec<(String, String)> = keys
+                .into_iter()
+                .zip(values)
+                .filter_map(|(k, v)| {
+                    let v = v?;
+                    Some((k.strip_prefix(&ns_prefix)?.to_string(), v))
+                })
+                .collect();
+            pairs.sort();
+            Ok(pairs)
+        })
+    }
+
+    fn clear(&self, namespace: &str) -> anyhow::Result<usize> {
+        let pattern = scan_pattern(namespace, "");
+        self.with_conn(|conn| {
+            let keys = scan_keys(conn, &pattern)?;
+            if keys.is_empty() {
+                return Ok(0);
+            }
+            redis::cmd("DEL").arg(&keys).query(conn)
+        })
+    }
+}
+
+/// 使用 SCAN 游标列出匹配模式的所有键
+fn scan_keys(conn: &mut redis::Connection, pattern: &str) -> redis::RedisResult<Vec<String>> {
+    let mut keys: Vec<String> = Vec::new();
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query(conn)?;
+        keys.extend(batch);
+        if next == 0 {
+            return Ok(keys);
+        }
+        cursor = next;
+    }
+}
+
+/// 匹配命名空间内指定前缀的所有键的 SCAN 模式（命名空间与前缀均转义）
+fn scan_pattern(namespace: &str, prefix: &str) -> String {
+    format!(
+        "{}:{}:{}*",
+        KEY_PREFIX,
+        escape_glob(namespace),
+        escape_glob(prefix)
+    )
+}
+
+/// 转义 Redis glob 模式中的特殊字符
+fn escape_glob(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_key() {
+        assert_eq!(RedisStorage::full_key("usage", "a:b"), "kiro-rs:usage:a:b");
+    }
+
+    #[test]
+    fn test_escape_glob() {
+        assert_eq!(escape_glob("a*b?[c]"), "a\\*b\\?\\[c\\]");
+        assert_eq!(scan_pattern("ns*", "p?"), "kiro-rs:ns\\*:p\\?*");
+    }
+}
@@ -0,0 +1,324 @@
+//! 会话与凭据的粘性绑定
+//!
+//! 绑定首先保存在进程内的绑定表中，选择凭据时优先查询该表。
+//! 绑定同时在后台写入所选存储后端：`sqlite` / `redis` 后端重启后保留，`redis` 后端可在多个实例间共享，
+//! 避免每次部署后会话被分配到其他凭据、上游上下文缓存全部失效。进程内未命中时从后端读取
+//! （在阻塞线程池上执行），读到的绑定在随后的 `bind` 中进入进程内绑定表；进程内的绑定距上次与后端核对
+//! 超过 `LOCAL_TTL_SECS` 时重新读取后端，从而看到其他实例的重新绑定。
+//! 绑定有效期每次使用都会刷新；同一绑定剩余有效期超过一半时不重复写入后端，写入为覆盖写，重复绑定是幂等的。
+//!
+//! `memory` 后端可额外启用快照文件（`stickySessionPath`）：绑定定期写入磁盘，
+//! 启动时恢复仍在有效期内的绑定，重启不会打乱进行中的长会话
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::{Storage, run_blocking, spawn_write};
+
+/// 会话绑定使用的存储命名空间
+const NAMESPACE: &str = "session_affinity";
+
+/// 快照文件的写入间隔
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 进程内绑定无需与存储后端核对的时长（秒）
+const LOCAL_TTL_SECS: i64 = 5;
+
+/// 绑定时清理过期绑定的最小间隔（秒）
+const SWEEP_INTERVAL_SECS: i64 = 60;
+
+/// 会话 ID -> 凭据 ID 的绑定表
+#[derive(Clone)]
+pub struct SessionAffinity {
+    storage: Arc<dyn Storage>,
+    /// 进程内绑定表（选择凭据时的主存储）
+    local: Arc<Mutex<LocalBindings>>,
+    snapshot: Option<Arc<Snapshot>>,
+}
+
+/// 进程内绑定表
+#[derive(Default)]
+struct LocalBindings {
+    bindings: HashMap<String, Binding>,
+    /// 上次清理过期绑定的时间（Unix 时间戳，秒）
+    last_sweep: i64,
+}
+
+/// 绑定表的磁盘快照
+struct Snapshot {
+    path: PathBuf,
+    /// 上次写入后是否有新的绑定
+    dirty: AtomicBool,
+}
+
+/// 单条绑定
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Binding {
+    credential_id: u64,
+    /// 过期时间（Unix 时间戳，秒）
+    expires_at: i64,
+    /// 存储后端中该绑定的过期时间（尚未写入后端时为 0）
+    #[serde(skip)]
+    stored_until: i64,
+    /// 上次与存储后端核对的时间（从快照恢复时为 0）
+    #[serde(skip)]
+    synced_at: i64,
+}
+
+impl SessionAffinity {
+    /// 基于存储后端创建绑定表
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage,
+            local: Arc::default(),
+            snapshot: None,
+        }
+    }
+
+    /// 启用快照文件，并将其中仍在有效期内的绑定恢复到进程内绑定表
+    ///
+    /// 文件不存在时从空表开始，读取或解析失败只记录日志
+    pub fn with_snapshot(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let now = Utc::now().timestamp();
+        let mut bindings = match load_snapshot(&path) {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                tracing::warn!("读取会话绑定快照 {} 失败: {}", path.display(), e);
+                HashMap::new()
+            }
+        };
+        bindings.retain(|_, b| b.expires_at > now);
+        if !bindings.is_empty() {
+            tracing::info!("已从 {} 恢复 {} 个会话绑定", path.display(), bindings.len());
+        }
+        self.local.lock().bindings.extend(bindings);
+        self.snapshot = Some(Arc::new(Snapshot {
+            path,
+            dirty: AtomicBool::new(false),
+        }));
+        self
+    }
+
+    /// 启动后台任务，定期将有变化的绑定写入快照文件（未启用快照时不启动）
+    pub fn spawn_snapshot_writer(&self) {
+        if self.snapshot.is_none() {
+            return;
+        }
+        let affinity = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let affinity = affinity.clone();
+                let _ = tokio::task::spawn_blocking(move || affinity.flush()).await;
+            }
+        });
+    }
+
+    /// 将有变化的绑定写入快照文件（丢弃已过期的绑定，写入失败只记录日志）
+    pub fn flush(&self) {
+        let Some(snapshot) = &self.snapshot else {
+            return;
+        };
+        if !snapshot.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let now = Utc::now().timestamp();
+        let json = {
+            let mut local = self.local.lock();
+            local.bindings.retain(|_, b| b.expires_at > now);
+            serde_json::to_vec(&local.bindings)
+        };
+        let result = json
+            .map_err(anyhow::Error::from)
+            .and_then(|json| save_snapshot(&snapshot.path, &json));
+        if let Err(e) = result {
+            snapshot.dirty.store(true, Ordering::Release);
+            tracing::warn!("写入会话绑定快照 {} 失败: {}", snapshot.path.display(), e);
+        }
+    }
+
+    /// 会话当前绑定的凭据 ID（未绑定、已过期或读取失败时为 None）
+    ///
+    /// 优先查询进程内绑定表，未命中或超过 `LOCAL_TTL_SECS` 未核对时从存储后端读取。
+    /// 后端中的绑定优先（可能已被其他实例重新绑定），后端没有该绑定或读取失败时沿用进程内绑定
+    pub async fn get(&self, session: &str) -> Option<u64> {
+        let now = Utc::now().timestamp();
+        let local = self
+            .local
+            .lock()
+            .bindings
+            .get(session)
+            .copied()
+            .filter(|b| b.expires_at > now);
+        if let Some(binding) = local
+            && now - binding.synced_at < LOCAL_TTL_SECS
+        {
+            return Some(binding.credential_id);
+        }
+        let key = session.to_string();
+        let stored =
+            match run_blocking(&self.storage, move |storage| storage.get(NAMESPACE, &key)).await {
+                Ok(value) => value.and_then(|v| v.parse().ok()),
+                Err(e) => {
+                    tracing::warn!("读取会话 {} 的凭据绑定失败: {}", session, e);
+                    None
+                }
+            };
+        let Some(binding) = local else {
+            return stored;
+        };
+        let id = stored.unwrap_or(binding.credential_id);
+        if let Some(binding) = self.local.lock().bindings.get_mut(session) {
+            binding.credential_id = id;
+            binding.synced_at = now;
+        }
+        Some(id)
+    }
+
+    /// 绑定会话到凭据，有效期从此刻起重新计算
+    ///
+    /// 只更新进程内绑定表；绑定变化或后端中的有效期不足一半时在后台写入存储后端（失败只记录日志）
+    pub fn bind(&self, session: &str, id: u64, ttl: Duration) {
+        let now = Utc::now().timestamp();
+        let ttl_secs = ttl.as_secs() as i64;
+        let store = {
+            let mut local = self.local.lock();
+            if now - local.last_sweep >= SWEEP_INTERVAL_SECS {
+                local.last_sweep = now;
+                local.bindings.retain(|_, b| b.expires_at > now);
+            }
+            let previous = local.bindings.get(session).copied();
+            let store = previous
+                .is_none_or(|b| b.credential_id != id || (b.stored_until - now) * 2 < ttl_secs);
+            let (stored_until, synced_at) = match previous {
+                Some(b) if !store => (b.stored_until, b.synced_at),
+                _ => (now + ttl_secs, now),
+            };
+            local.bindings.insert(
+                session.to_string(),
+                Binding {
+                    credential_id: id,
+                    expires_at: now + ttl_secs,
+                    stored_until,
+                    synced_at,
+                },
+            );
+            store
+        };
+        if store {
+            let session = session.to_string();
+            spawn_write(&self.storage, move |storage| {
+                if let Err(e) = storage.put(NAMESPACE, &session, &id.to_string(), Some(ttl)) {
+                    tracing::warn!("保存会话 {} 的凭据绑定失败: {}", session, e);
+                }
+            });
+        }
+        if let Some(snapshot) = &self.snapshot {
+            snapshot.dirty.store(true, Ordering::Release);
+        }
+    }
+}
+
+fn load_snapshot(path: &Path) -> anyhow::Result<HashMap<String, Binding>> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 先写临时文件再重命名，避免写入中断留下损坏的快照
+fn save_snapshot(path: &Path, json: &[u8]) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_bindings_shared_through_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let affinity = SessionAffinity::new(storage.clone());
+        assert_eq!(affinity.get("s1").await, None);
+
+        affinity.bind("s1", 3, Duration::from_secs(60));
+        affinity.bind("s1", 3, Duration::from_secs(60));
+        // 共享同一存储的实例（如重启后或其他节点）读取到相同的绑定
+        assert_eq!(SessionAffinity::new(storage).get("s1").await, Some(3));
+
+        affinity.bind("s2", 5, Duration::ZERO);
+        assert_eq!(affinity.get("s2").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_local_bindings_skip_redundant_backend_writes() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let affinity = SessionAffinity::new(storage.clone());
+
+        affinity.bind("s1", 3, Duration::from_secs(600));
+        // 后端中的绑定被清除后，有效期充足的重复绑定不再写入后端，读取仍命中进程内绑定表
+        storage.clear(NAMESPACE).unwrap();
+        affinity.bind("s1", 3, Duration::from_secs(600));
+        assert_eq!(storage.get(NAMESPACE, "s1").unwrap(), None);
+        assert_eq!(affinity.get("s1").await, Some(3));
+
+        // 绑定变化时重新写入后端
+        affinity.bind("s1", 4, Duration::from_secs(600));
+        assert_eq!(storage.get(NAMESPACE, "s1").unwrap().as_deref(), Some("4"));
+    }
+
+    #[tokio::test]
+    async fn test_rebind_on_other_instance_seen_after_local_ttl() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let a = SessionAffinity::new(storage.clone());
+        let b = SessionAffinity::new(storage);
+
+        a.bind("s1", 3, Duration::from_secs(600));
+        assert_eq!(b.get("s1").await, Some(3));
+        b.bind("s1", 3, Duration::from_secs(600));
+
+        // 实例 A 重新绑定；B 的进程内绑定在核对期限内仍然生效，之后读取到新的绑定
+        a.bind("s1", 4, Duration::from_secs(600));
+        assert_eq!(b.get("s1").await, Some(3));
+        b.local.lock().bindings.get_mut("s1").unwrap().synced_at -= LOCAL_TTL_SECS;
+        assert_eq!(b.get("s1").await, Some(4));
+        assert_eq!(b.local.lock().bindings["s1"].credential_id, 4);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restores_bindings_after_restart() {
+        let dir = std::env::temp_dir().join(format!("kiro-affinity-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("sticky_sessions.json");
+
+        let affinity = SessionAffinity::new(Arc::new(MemoryStorage::new())).with_snapshot(&path);
+        affinity.bind("s1", 3, Duration::from_secs(600));
+        affinity.bind("s2", 5, Duration::ZERO);
+        affinity.flush();
+
+        // 重启：从快照恢复未过期的绑定
+        let restored = SessionAffinity::new(Arc::new(MemoryStorage::new())).with_snapshot(&path);
+        assert_eq!(restored.get("s1").await, Some(3));
+        assert_eq!(restored.get("s2").await, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
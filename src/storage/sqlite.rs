@@ -0,0 +1,150 @@
+//! SQLite 存储后端（需启用 `sqlite` feature）
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::Storage;
+
+/// SQLite 文件存储
+///
+/// 所有命名空间共用一张 `kv` 表，过期时间以 Unix 毫秒时间戳存储
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+/// 当前 Unix 毫秒时间戳
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+impl SqliteStorage {
+    /// 打开（或创建）SQLite 数据库文件
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .with_context(|| format!("打开 SQLite 数据库失败: {:?}", path))?;
+        Self::init(conn)
+    }
+
+    /// 创建内存数据库（用于测试）
+    #[cfg(test)]
+    fn open_in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> anyhow::Result<Self> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS kv (
+                 namespace  TEXT    NOT NULL,
+                 key        TEXT    NOT NULL,
+                 value      TEXT    NOT NULL,
+                 expires_at INTEGER,
+                 PRIMARY KEY (namespace, key)
+             );",
+        )
+        .context("初始化 SQLite 表结构失败")?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<String>> {
+        let conn = self.conn.lock();
+        let value = conn
+            .query_row(
+                "SELECT value FROM kv
+                 WHERE namespace = ?1 AND key = ?2
+                   AND (expires_at IS NULL OR expires_at > ?3)",
+                params![namespace, key, now_millis()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value)
+    }
+
+    fn put(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        let expires_at = ttl.map(|t| now_millis() + t.as_millis() as i64);
+        self.conn.lock().execute(
+            "INSERT INTO kv (namespace, key, value, expires_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(namespace, key) DO UPDATE
+             SET value = excluded.value, expires_at = excluded.expires_at",
+            params![namespace, key, value, expires_at],
+        )?;
+        Ok(())
+    }
+
+    fn incr(&self, namespace: &str, key: &str, delta: i64) -> anyhow::Result<i64> {
+        let conn = self.conn.lock();
+        let now = now_millis();
+        // 已过期的计数器视为从 0 开始
+        let value: String = conn.query_row(
+            "INSERT INTO kv (namespace, key, value, expires_at) VALUES (?1, ?2, CAST(?3 AS TEXT), NULL)
+             ON CONFLICT(namespace, key) DO UPDATE
+             SET value = CAST(
+                     CASE WHEN kv.expires_at IS NOT NULL AND kv.expires_at <= ?4
+                          THEN ?3
+                          ELSE CAST(kv.value AS INTEGER) + ?3
+                     END AS TEXT),
+                 expires_at = CASE WHEN kv.expires_at IS NOT NULL AND kv.expires_at <= ?4
+                                   THEN NULL ELSE kv.expires_at END
+             RETURNING value",
+            params![namespace, key, delta, now],
+            |row| row.get(0),
+        )?;
+        value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("键 {}:{} 的值不是整数", namespace, key))
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(
+            "SELECT key, value FROM kv
+             WHERE namespace = ?1 AND substr(key, 1, length(?2)) = ?2
+               AND (expires_at IS NULL OR expires_at > ?3)
+             ORDER BY key",
+        )?;
+        let rows = stmt
+            .query_map(params![namespace, prefix, now_millis()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    fn clear(&self, namespace: &str) -> anyhow::Result<usize> {
+        let removed = self
+            .conn
+            .lock()
+            .execute("DELETE FROM kv WHERE namespace = ?1", params![namespace])?;
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_storage() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        super::super::exercise_storage(&storage);
+    }
+}
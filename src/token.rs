@@ -6,15 +6,54 @@
 //! - 优先使用 Hugging Face tokenizers（Claude 官方 tokenizer）
 //! - 如果 tokenizer 加载失败，回退到简单估算
 //! - 支持远程 API 调用（可选）
+//!
+//! tokenizer 文件存在但解析失败（损坏）时会在启动时报告，并在 `/readyz` 与 Admin 状态中体现；
+//! 配置 `tokenizerUrl` 与 `tokenizerSha256` 后会自动重新下载并校验；
+//! 启用 `bundled-tokenizer` feature 时 tokenizer 会嵌入二进制，本地文件缺失或损坏时使用嵌入的版本
+//!
+//! `tokenizerPath` 可替代内置的查找路径，`modelTokenizers` 可为匹配的模型指定单独的 tokenizer，
+//! 后者在启动时校验，文件无法加载时拒绝启动
+//!
+//! 本地计数按内容摘要缓存每条消息、系统提示与工具定义的 token 数，多轮对话每轮只需对新增内容分词
 
+use crate::anthropic::document;
 use crate::anthropic::types::{
     CountTokensRequest, CountTokensResponse, Message, SystemMessage, Tool,
 };
+use crate::common::cache::FlushableCache;
 use crate::http_client::{ProxyConfig, build_client};
-use crate::model::config::TlsBackend;
-use std::sync::OnceLock;
+use crate::model::config::{ModelTokenizer, TlsBackend};
+use parking_lot::Mutex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, OnceLock};
 use tokenizers::Tokenizer;
 
+/// tokenizer 文件的查找路径（按顺序）
+const TOKENIZER_PATHS: &[&str] = &[
+    "tokenizers/claude-tokenizer.json",
+    "./tokenizers/claude-tokenizer.json",
+    "../tokenizers/claude-tokenizer.json",
+];
+
+/// 嵌入的 tokenizer 在加载状态中显示的路径
+const BUNDLED_TOKENIZER_PATH: &str = "<bundled>";
+
+/// 编译时嵌入的 tokenizer（`bundled-tokenizer` feature）
+#[cfg(feature = "bundled-tokenizer")]
+const BUNDLED_TOKENIZER: Option<&[u8]> =
+    Some(include_bytes!("../tokenizers/claude-tokenizer.json"));
+#[cfg(not(feature = "bundled-tokenizer"))]
+const BUNDLED_TOKENIZER: Option<&[u8]> = None;
+
+/// token 计数缓存容量（条目数）
+const TOKEN_COUNT_CACHE_CAPACITY: usize = 100_000;
+
+/// tokenizer 下载超时（秒）
+const TOKENIZER_DOWNLOAD_TIMEOUT_SECS: u64 = 120;
+
 /// Count Tokens API 配置
 #[derive(Clone, Default)]
 pub struct CountTokensConfig {
@@ -33,8 +72,111 @@ pub struct CountTokensConfig {
 /// 全局配置存储
 static COUNT_TOKENS_CONFIG: OnceLock<CountTokensConfig> = OnceLock::new();
 
+/// tokenizer 下载源（固定版本，需校验 SHA-256）
+pub struct TokenizerSource {
+    pub url: String,
+    pub sha256: String,
+    pub proxy: Option<ProxyConfig>,
+    pub tls_backend: TlsBackend,
+}
+
+/// tokenizer 加载状态
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum TokenizerStatus {
+    /// 已加载（`downloaded` 表示本次启动重新下载）
+    Loaded { path: String, downloaded: bool },
+    /// 未找到 tokenizer 文件
+    Missing,
+    /// 文件存在但解析失败
+    Corrupted { path: String, error: String },
+}
+
+struct TokenizerState {
+    tokenizer: Option<Tokenizer>,
+    status: TokenizerStatus,
+}
+
 /// 全局 Claude tokenizer
-static CLAUDE_TOKENIZER: OnceLock<Option<Tokenizer>> = OnceLock::new();
+static CLAUDE_TOKENIZER: OnceLock<TokenizerState> = OnceLock::new();
+
+/// 按模型匹配的 tokenizer（模型名模式, tokenizer），按配置顺序匹配
+static MODEL_TOKENIZERS: OnceLock<Vec<(String, Tokenizer)>> = OnceLock::new();
+
+/// 全局 token 计数缓存
+static TOKEN_COUNT_CACHE: LazyLock<Arc<TokenCountCache>> =
+    LazyLock::new(|| Arc::new(TokenCountCache::new(TOKEN_COUNT_CACHE_CAPACITY)));
+
+/// 内容摘要 -> token 数
+type TokenCounts = HashMap<[u8; 32], u64>;
+
+/// 按内容摘要缓存的 token 计数
+///
+/// 采用两代淘汰：当前代写满一半容量后整体降为旧代（原旧代丢弃），旧代中命中的条目提升回当前代，
+/// 近似 LRU 且无需逐条维护访问顺序
+pub struct TokenCountCache {
+    /// （当前代, 旧代）
+    generations: Mutex<(TokenCounts, TokenCounts)>,
+    capacity: usize,
+}
+
+impl TokenCountCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            generations: Mutex::new((HashMap::new(), HashMap::new())),
+            capacity: capacity.max(2),
+        }
+    }
+
+    /// 返回缓存的计数，未命中时调用 `count` 计算并缓存
+    ///
+    /// `tokenizer` 标识使用的 tokenizer（不同 tokenizer 的计数互不共享），`content` 为被计数内容的序列化形式
+    fn get_or_count(&self, tokenizer: &str, content: &[u8], count: impl FnOnce() -> u64) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(tokenizer.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(content);
+        let key: [u8; 32] = hasher.finalize().into();
+
+        {
+            let mut generations = self.generations.lock();
+            let (current, previous) = &mut *generations;
+            if let Some(&tokens) = current.get(&key) {
+                return tokens;
+            }
+            if let Some(tokens) = previous.remove(&key) {
+                current.insert(key, tokens);
+                return tokens;
+            }
+        }
+
+        let tokens = count();
+        let mut generations = self.generations.lock();
+        if generations.0.len() >= self.capacity / 2 {
+            generations.1 = std::mem::take(&mut generations.0);
+        }
+        generations.0.insert(key, tokens);
+        tokens
+    }
+
+    fn len(&self) -> usize {
+        let generations = self.generations.lock();
+        generations.0.len() + generations.1.len()
+    }
+}
+
+impl FlushableCache for TokenCountCache {
+    fn flush(&self) -> anyhow::Result<usize> {
+        let flushed = self.len();
+        *self.generations.lock() = (HashMap::new(), HashMap::new());
+        Ok(flushed)
+    }
+}
+
+/// 全局 token 计数缓存（用于注册到缓存注册表）
+pub fn token_count_cache() -> Arc<TokenCountCache> {
+    TOKEN_COUNT_CACHE.clone()
+}
 
 /// 初始化 count_tokens 配置
 ///
@@ -43,38 +185,252 @@ pub fn init_config(config: CountTokensConfig) {
     let _ = COUNT_TOKENS_CONFIG.set(config);
 }
 
-/// 初始化 Claude tokenizer
+/// 启动时加载 Claude tokenizer
 ///
-/// 尝试从文件加载 tokenizer，如果失败则返回 None
-fn init_tokenizer() -> Option<Tokenizer> {
-    // 尝试从多个可能的路径加载 tokenizer
-    let paths = vec![
-        "tokenizers/claude-tokenizer.json",
-        "./tokenizers/claude-tokenizer.json",
-        "../tokenizers/claude-tokenizer.json",
-    ];
+/// `path` 为配置的默认 tokenizer 路径（None 时使用内置查找路径）；本地文件缺失或损坏且配置了下载源时，
+/// 下载并校验后写回本地。`models` 中的 tokenizer 文件无法加载时返回错误。应在应用启动时调用一次
+pub async fn init_tokenizer(
+    source: Option<TokenizerSource>,
+    path: Option<&str>,
+    models: &[ModelTokenizer],
+) -> anyhow::Result<()> {
+    let paths = match path {
+        Some(path) => vec![path],
+        None => TOKENIZER_PATHS.to_vec(),
+    };
+    let (mut tokenizer, mut status) = load_tokenizer(&paths);
+
+    if let (None, Some(source)) = (&tokenizer, source) {
+        let path = match &status {
+            TokenizerStatus::Corrupted { path, .. } if path != BUNDLED_TOKENIZER_PATH => {
+                path.clone()
+            }
+            _ => paths[0].to_string(),
+        };
+        match download_tokenizer(&source, &path).await {
+            Ok(downloaded) => {
+                tracing::info!("已重新下载并校验 Claude tokenizer: {}", path);
+                tokenizer = Some(downloaded);
+                status = TokenizerStatus::Loaded {
+                    path,
+                    downloaded: true,
+                };
+            }
+            Err(e) => tracing::error!("下载 Claude tokenizer 失败: {}", e),
+        }
+    }
+
+    log_status(&status);
+    let _ = CLAUDE_TOKENIZER.set(TokenizerState { tokenizer, status });
+
+    let _ = MODEL_TOKENIZERS.set(load_model_tokenizers(models)?);
+    Ok(())
+}
+
+/// 加载并校验按模型配置的 tokenizer
+fn load_model_tokenizers(models: &[ModelTokenizer]) -> anyhow::Result<Vec<(String, Tokenizer)>> {
+    models
+        .iter()
+        .map(|entry| {
+            if entry.model.trim().is_empty() {
+                anyhow::bail!("modelTokenizers 中存在空的模型名模式（{}）", entry.path);
+            }
+            let tokenizer = Tokenizer::from_file(&entry.path).map_err(|e| {
+                anyhow::anyhow!(
+                    "模型 {} 的 tokenizer 文件 {} 无法加载: {}",
+                    entry.model,
+                    entry.path,
+                    e
+                )
+            })?;
+            tracing::info!("已加载模型 {} 的 tokenizer: {}", entry.model, entry.path);
+            Ok((entry.model.to_lowercase(), tokenizer))
+        })
+        .collect()
+}
+
+/// 模型名是否匹配模式（不区分大小写，`*` 匹配任意字符串）
+fn model_matches(pattern: &str, model: &str) -> bool {
+    let model = model.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return model == first;
+    }
+    if !model.starts_with(first) || !model.ends_with(last) || model.len() < first.len() + last.len()
+    {
+        return false;
+    }
+    let mut rest = &model[first.len()..model.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// 从本地文件加载 tokenizer，失败时使用嵌入的版本（如有）
+fn load_tokenizer(paths: &[&str]) -> (Option<Tokenizer>, TokenizerStatus) {
+    let (tokenizer, status) = load_from_paths(paths);
+    if tokenizer.is_some() {
+        return (tokenizer, status);
+    }
+    match load_bundled(BUNDLED_TOKENIZER) {
+        Some(bundled) => {
+            if let TokenizerStatus::Corrupted { path, error } = &status {
+                tracing::warn!(
+                    "tokenizer 文件已损坏（{}）: {}，改用嵌入的 tokenizer",
+                    path,
+                    error
+                );
+            }
+            bundled
+        }
+        None => (tokenizer, status),
+    }
+}
+
+/// 加载嵌入的 tokenizer（未嵌入时返回 None）
+fn load_bundled(bytes: Option<&[u8]>) -> Option<(Option<Tokenizer>, TokenizerStatus)> {
+    let bytes = bytes?;
+    let loaded = match Tokenizer::from_bytes(bytes) {
+        Ok(tokenizer) => (
+            Some(tokenizer),
+            TokenizerStatus::Loaded {
+                path: BUNDLED_TOKENIZER_PATH.to_string(),
+                downloaded: false,
+            },
+        ),
+        Err(e) => (
+            None,
+            TokenizerStatus::Corrupted {
+                path: BUNDLED_TOKENIZER_PATH.to_string(),
+                error: e.to_string(),
+            },
+        ),
+    };
+    Some(loaded)
+}
 
+/// 依次尝试从路径加载 tokenizer，返回 tokenizer 与加载状态
+fn load_from_paths(paths: &[&str]) -> (Option<Tokenizer>, TokenizerStatus) {
+    let mut status = TokenizerStatus::Missing;
     for path in paths {
+        if !Path::new(path).exists() {
+            continue;
+        }
         match Tokenizer::from_file(path) {
             Ok(tokenizer) => {
-                tracing::info!("成功加载 Claude tokenizer: {}", path);
-                return Some(tokenizer);
+                let status = TokenizerStatus::Loaded {
+                    path: path.to_string(),
+                    downloaded: false,
+                };
+                return (Some(tokenizer), status);
             }
             Err(e) => {
                 tracing::debug!("无法从 {} 加载 tokenizer: {}", path, e);
+                // 记录第一个损坏的文件
+                if matches!(status, TokenizerStatus::Missing) {
+                    status = TokenizerStatus::Corrupted {
+                        path: path.to_string(),
+                        error: e.to_string(),
+                    };
+                }
             }
         }
     }
+    (None, status)
+}
+
+/// 下载 tokenizer，校验 SHA-256 并确认可解析后写入 `path`
+async fn download_tokenizer(source: &TokenizerSource, path: &str) -> anyhow::Result<Tokenizer> {
+    let client = build_client(
+        source.proxy.as_ref(),
+        TOKENIZER_DOWNLOAD_TIMEOUT_SECS,
+        source.tls_backend,
+    )?;
+    let bytes = client
+        .get(&source.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    verify_sha256(&bytes, &source.sha256)?;
+    let tokenizer = Tokenizer::from_bytes(&bytes)
+        .map_err(|e| anyhow::anyhow!("下载的 tokenizer 无法解析: {}", e))?;
 
-    tracing::warn!("无法加载 Claude tokenizer，将使用简单估算");
-    None
+    // 先写临时文件再重命名，避免写入中断留下损坏的文件
+    if let Some(dir) = Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = format!("{}.download", path);
+    std::fs::write(&tmp, &bytes)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(tokenizer)
+}
+
+/// 校验内容的 SHA-256（十六进制，忽略大小写）
+fn verify_sha256(bytes: &[u8], expected: &str) -> anyhow::Result<()> {
+    let actual = hex::encode(Sha256::digest(bytes));
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        anyhow::bail!(
+            "SHA-256 校验失败: 期望 {}，实际 {}",
+            expected.trim(),
+            actual
+        )
+    }
+}
+
+fn log_status(status: &TokenizerStatus) {
+    match status {
+        TokenizerStatus::Loaded { path, .. } => {
+            tracing::info!("成功加载 Claude tokenizer: {}", path)
+        }
+        TokenizerStatus::Missing => tracing::warn!("无法加载 Claude tokenizer，将使用简单估算"),
+        TokenizerStatus::Corrupted { path, error } => tracing::error!(
+            "Claude tokenizer 文件已损坏（{}）: {}，将使用简单估算，token 计数准确度下降",
+            path,
+            error
+        ),
+    }
+}
+
+/// 获取 tokenizer 状态（未在启动时初始化时从本地文件加载）
+fn get_state() -> &'static TokenizerState {
+    CLAUDE_TOKENIZER.get_or_init(|| {
+        let (tokenizer, status) = load_tokenizer(TOKENIZER_PATHS);
+        log_status(&status);
+        TokenizerState { tokenizer, status }
+    })
 }
 
 /// 获取 Claude tokenizer
 fn get_tokenizer() -> Option<&'static Tokenizer> {
-    CLAUDE_TOKENIZER
-        .get_or_init(init_tokenizer)
-        .as_ref()
+    get_state().tokenizer.as_ref()
+}
+
+/// 获取模型对应的 tokenizer 及其标识（未匹配 `modelTokenizers` 时使用默认 tokenizer，标识为空）
+fn get_model_tokenizer(model: &str) -> (&'static str, Option<&'static Tokenizer>) {
+    MODEL_TOKENIZERS
+        .get()
+        .and_then(|models| {
+            models
+                .iter()
+                .find(|(pattern, _)| model_matches(pattern, model))
+        })
+        .map(|(pattern, tokenizer)| (pattern.as_str(), Some(tokenizer)))
+        .unwrap_or(("", get_tokenizer()))
+}
+
+/// Claude tokenizer 加载状态
+pub fn tokenizer_status() -> TokenizerStatus {
+    get_state().status.clone()
 }
 
 /// 获取配置
@@ -86,8 +442,13 @@ fn get_config() -> Option<&'static CountTokensConfig> {
 ///
 /// 优先使用 Claude tokenizer，失败时回退到简单估算
 pub fn count_tokens(text: &str) -> u64 {
+    count_tokens_with(get_tokenizer(), text)
+}
+
+/// 使用指定的 tokenizer 计算 token 数量，未加载 tokenizer 时使用简单估算
+fn count_tokens_with(tokenizer: Option<&Tokenizer>, text: &str) -> u64 {
     // 尝试使用 Claude tokenizer
-    if let Some(tokenizer) = get_tokenizer() {
+    if let Some(tokenizer) = tokenizer {
         match tokenizer.encode(text, false) {
             Ok(encoding) => {
                 let count = encoding.get_ids().len() as u64;
@@ -131,21 +492,18 @@ fn count_tokens_fallback(text: &str) -> u64 {
 /// 估算请求的输入 tokens
 ///
 /// 优先级：远程 API > Claude tokenizer > 简单估算
-pub(crate) fn count_all_tokens(
-    model: String,
-    system: Option<Vec<SystemMessage>>,
-    messages: Vec<Message>,
-    tools: Option<Vec<Tool>>,
+pub(crate) async fn count_all_tokens(
+    model: &str,
+    system: Option<&[SystemMessage]>,
+    messages: &[Message],
+    tools: Option<&[Tool]>,
 ) -> u64 {
     // 检查是否配置了远程 API
     if let Some(config) = get_config() {
         if let Some(api_url) = &config.api_url {
             // 尝试调用远程 API
-            let result = tokio::task::block_in_place(|| {
-                tokio::runtime::Handle::current().block_on(call_remote_count_tokens(
-                    api_url, config, model, &system, &messages, &tools,
-                ))
-            });
+            let result =
+                call_remote_count_tokens(api_url, config, model, system, messages, tools).await;
 
             match result {
                 Ok(tokens) => {
@@ -160,26 +518,26 @@ pub(crate) fn count_all_tokens(
     }
 
     // 本地计算（使用 Claude tokenizer 或简单估算）
-    count_all_tokens_local(system, messages, tools)
+    count_all_tokens_local(model, system, messages, tools)
 }
 
 /// 调用远程 count_tokens API
 async fn call_remote_count_tokens(
     api_url: &str,
     config: &CountTokensConfig,
-    model: String,
-    system: &Option<Vec<SystemMessage>>,
-    messages: &Vec<Message>,
-    tools: &Option<Vec<Tool>>,
+    model: &str,
+    system: Option<&[SystemMessage]>,
+    messages: &[Message],
+    tools: Option<&[Tool]>,
 ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
     let client = build_client(config.proxy.as_ref(), 300, config.tls_backend)?;
 
     // 构建请求体
     let request = CountTokensRequest {
-        model,
-        messages: messages.clone(),
-        system: system.clone(),
-        tools: tools.clone(),
+        model: model.to_string(),
+        messages: messages.to_vec(),
+        system: system.map(<[_]>::to_vec),
+        tools: tools.map(<[_]>::to_vec),
     };
 
     // 构建请求
@@ -211,16 +569,23 @@ async fn call_remote_count_tokens(
 
 /// 本地计算请求的输入 tokens
 fn count_all_tokens_local(
-    system: Option<Vec<SystemMessage>>,
-    messages: Vec<Message>,
-    tools: Option<Vec<Tool>>,
+    model: &str,
+    system: Option<&[SystemMessage]>,
+    messages: &[Message],
+    tools: Option<&[Tool]>,
 ) -> u64 {
+    let (tokenizer_key, tokenizer) = get_model_tokenizer(model);
+    let count = |text: &str| count_tokens_with(tokenizer, text);
+    let cache = &*TOKEN_COUNT_CACHE;
+    let memoized = |tag: &str, content: &[u8], count: &dyn Fn() -> u64| {
+        cache.get_or_count(tokenizer_key, &[tag.as_bytes(), content].concat(), count)
+    };
     let mut total = 0;
 
     // 系统消息
-    if let Some(ref system) = system {
+    if let Some(system) = system {
         for msg in system {
-            let tokens = count_tokens(&msg.text);
+            let tokens = memoized("system", msg.text.as_bytes(), &|| count(&msg.text));
             total += tokens;
             tracing::debug!("系统消息 tokens: {}", tokens);
         }
@@ -234,19 +599,24 @@ fn count_all_tokens_local(
         // 每条消息的结构开销
         total += 4;
 
-        let msg_tokens = if let serde_json::Value::String(s) = &msg.content {
-            count_tokens(s)
-        } else if let serde_json::Value::Array(arr) = &msg.content {
-            let mut content_tokens = 0;
-            for item in arr {
-                if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
-                    content_tokens += count_tokens(text);
+        let content = serde_json::to_vec(&msg.content).unwrap_or_default();
+        let msg_tokens = memoized("message", &content, &|| {
+            if let serde_json::Value::String(s) = &msg.content {
+                count(s)
+            } else if let serde_json::Value::Array(arr) = &msg.content {
+                let mut content_tokens = 0;
+                for item in arr {
+                    if let Some(text) = item.get("text").and_then(|v| v.as_str()) {
+                        content_tokens += count(text);
+                    } else if item.get("type").and_then(|v| v.as_str()) == Some("document") {
+                        content_tokens += count(&document::document_text(item));
+                    }
                 }
+                content_tokens
+            } else {
+                0
             }
-            content_tokens
-        } else {
-            0
-        };
+        });
 
         total += msg_tokens;
 
@@ -258,12 +628,13 @@ fn count_all_tokens_local(
     }
 
     // 工具定义
-    if let Some(ref tools) = tools {
+    if let Some(tools) = tools {
         for tool in tools {
-            total += count_tokens(&tool.name);
-            total += count_tokens(&tool.description);
             let input_schema_json = serde_json::to_string(&tool.input_schema).unwrap_or_default();
-            total += count_tokens(&input_schema_json);
+            let content = [&tool.name, &tool.description, &input_schema_json].map(|s| s.as_bytes());
+            total += memoized("tool", &content.join(&0u8), &|| {
+                count(&tool.name) + count(&tool.description) + count(&input_schema_json)
+            });
             // 每个工具的结构开销
             total += 10;
         }
@@ -274,8 +645,8 @@ fn count_all_tokens_local(
         "Token 计数完成 - 总计: {} tokens (消息: {}, 系统: {}, 工具: {})",
         total,
         messages.len(),
-        system.as_ref().map(|s| s.len()).unwrap_or(0),
-        tools.as_ref().map(|t| t.len()).unwrap_or(0)
+        system.map(|s| s.len()).unwrap_or(0),
+        tools.map(|t| t.len()).unwrap_or(0)
     );
 
     total.max(1)
@@ -308,10 +679,165 @@ pub(crate) fn estimate_output_tokens(content: &[serde_json::Value]) -> i32 {
     total.max(1)
 }
 
+/// 截断文本，使其 token 数不超过 `budget`
+///
+/// 在字符边界上二分查找满足预算的最长前缀，`count` 为 token 计数函数
+pub(crate) fn truncate_to_budget(text: &str, budget: i64, count: impl Fn(&str) -> i64) -> &str {
+    if budget <= 0 {
+        return "";
+    }
+    if count(text) <= budget {
+        return text;
+    }
+
+    let boundaries: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect();
+    let (mut lo, mut hi) = (0, boundaries.len() - 1);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if count(&text[..boundaries[mid]]) <= budget {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    &text[..boundaries[lo]]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_truncate_to_budget() {
+        let by_chars = |s: &str| s.chars().count() as i64;
+        assert_eq!(
+            truncate_to_budget("hello world", 100, by_chars),
+            "hello world"
+        );
+        assert_eq!(truncate_to_budget("hello world", 5, by_chars), "hello");
+        assert_eq!(truncate_to_budget("你好世界", 3, by_chars), "你好世");
+        assert_eq!(truncate_to_budget("hello", 0, by_chars), "");
+    }
+
+    #[test]
+    fn test_load_reports_corrupted_file() {
+        let dir = std::env::temp_dir().join(format!("kiro-tokenizer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let corrupted = dir.join("claude-tokenizer.json");
+        std::fs::write(&corrupted, "{\"truncated\": ").unwrap();
+        let corrupted = corrupted.to_str().unwrap();
+        let missing = dir.join("missing.json");
+
+        let (tokenizer, status) = load_from_paths(&[missing.to_str().unwrap(), corrupted]);
+        assert!(tokenizer.is_none());
+        assert!(matches!(status, TokenizerStatus::Corrupted { path, .. } if path == corrupted));
+
+        let (_, status) = load_from_paths(&[missing.to_str().unwrap()]);
+        assert!(matches!(status, TokenizerStatus::Missing));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_count_all_tokens_on_current_thread_runtime() {
+        let messages: Vec<Message> = serde_json::from_value(serde_json::json!([
+            {"role": "user", "content": "Hello, world!"},
+            {"role": "assistant", "content": [{"type": "text", "text": "Hi there"}]}
+        ]))
+        .unwrap();
+        let tokens = count_all_tokens("claude-sonnet-4-5", None, &messages, None).await;
+        assert!(tokens > 8);
+    }
+
+    #[test]
+    fn test_token_count_cache() {
+        let cache = TokenCountCache::new(4);
+        let calls = std::cell::Cell::new(0);
+        let count = |tokens: u64| {
+            calls.set(calls.get() + 1);
+            tokens
+        };
+
+        assert_eq!(cache.get_or_count("", b"a", || count(1)), 1);
+        assert_eq!(cache.get_or_count("", b"a", || count(99)), 1);
+        // 不同 tokenizer 的计数互不共享
+        assert_eq!(cache.get_or_count("claude-3-*", b"a", || count(2)), 2);
+        assert_eq!(calls.get(), 2);
+
+        // 当前代写满一半容量后降为旧代，旧代命中时提升回当前代
+        cache.get_or_count("", b"b", || count(3));
+        assert_eq!(cache.get_or_count("", b"a", || count(99)), 1);
+        cache.get_or_count("", b"c", || count(4));
+        cache.get_or_count("", b"d", || count(5));
+        assert_eq!(cache.get_or_count("", b"a", || count(99)), 1);
+        // b 所在的代已被丢弃，需要重新计数
+        cache.get_or_count("", b"e", || count(6));
+        assert_eq!(cache.get_or_count("", b"b", || count(7)), 7);
+        assert_eq!(calls.get(), 7);
+
+        let len = cache.len();
+        assert_eq!(cache.flush().unwrap(), len);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_model_matches() {
+        assert!(model_matches("claude-3-*", "Claude-3-Haiku-20240307"));
+        assert!(model_matches("claude-*-opus-*", "claude-3-opus-20240229"));
+        assert!(model_matches("claude-sonnet-4-5", "claude-sonnet-4-5"));
+        assert!(!model_matches(
+            "claude-sonnet-4-5",
+            "claude-sonnet-4-5-20250929"
+        ));
+        assert!(!model_matches("claude-3-*", "claude-sonnet-4"));
+        assert!(!model_matches("*-opus-*-opus", "claude-opus"));
+    }
+
+    #[test]
+    fn test_load_model_tokenizers_validates_files() {
+        let entry = |model: &str, path: &str| ModelTokenizer {
+            model: model.to_string(),
+            path: path.to_string(),
+        };
+        let loaded =
+            load_model_tokenizers(&[entry("Claude-3-*", "tokenizers/claude-tokenizer.json")])
+                .unwrap();
+        assert_eq!(loaded[0].0, "claude-3-*");
+
+        let err = load_model_tokenizers(&[entry("claude-3-*", "missing-tokenizer.json")])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing-tokenizer.json"));
+        assert!(load_model_tokenizers(&[entry(" ", "tokenizers/claude-tokenizer.json")]).is_err());
+    }
+
+    #[test]
+    fn test_load_bundled() {
+        assert!(load_bundled(None).is_none());
+
+        let bytes = std::fs::read("tokenizers/claude-tokenizer.json").unwrap();
+        let (tokenizer, status) = load_bundled(Some(&bytes)).unwrap();
+        assert!(tokenizer.is_some());
+        assert!(
+            matches!(status, TokenizerStatus::Loaded { path, .. } if path == BUNDLED_TOKENIZER_PATH)
+        );
+
+        let (tokenizer, status) = load_bundled(Some(b"{}")).unwrap();
+        assert!(tokenizer.is_none());
+        assert!(matches!(status, TokenizerStatus::Corrupted { .. }));
+    }
+
+    #[test]
+    fn test_verify_sha256() {
+        let digest = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(verify_sha256(b"hello", digest).is_ok());
+        assert!(verify_sha256(b"hello", &digest.to_uppercase()).is_ok());
+        assert!(verify_sha256(b"hello!", digest).is_err());
+    }
+
     #[test]
     fn test_count_tokens_english() {
         let text = "Hello, world!";